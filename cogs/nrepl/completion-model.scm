@@ -7,23 +7,22 @@
 
 ;;; completion-model.scm - Completion Candidate Model
 ;;;
-;;; Pure transforms over parsed completion results (the output of
-;;; parse-ffi-sexp on the FFI's completions string). No helix requires, so it
-;;; loads under the bare steel CLI for headless tests.
+;;; Pure transforms over completion results (the FFI's completions list). No
+;;; helix requires, so it loads under the bare steel CLI for headless tests.
 
 (provide candidates->symbols+metadata
   poll-delay-for)
 
 ;;@doc
-;; Turn a parsed candidates list into (cons symbol-list metadata-hash).
+;; Turn a candidates list into (cons symbol-list metadata-hash).
 ;;
-;; Each candidate is a hash with '#:candidate, '#:ns and '#:type keys (the
-;; FFI emitter always writes all three; missing fields arrive as #f, e.g.
+;; Each candidate is a hash with "candidate", "ns" and "type" keys (the
+;; FFI always fills in all three; missing fields arrive as #f, e.g.
 ;; babashka sends no type). A bare string is accepted as a plain symbol with
 ;; no metadata. Anything else returns (cons '() (hash)).
 ;;
 ;; symbol-list preserves server order; metadata-hash maps candidate string ->
-;; (hash '#:ns ns '#:type type).
+;; (hash "ns" ns "type" type).
 (define (candidates->symbols+metadata candidates)
   (if (list? candidates)
     ;; Explicit cons/reverse loop: map with hash-constructing callbacks can
@@ -36,13 +35,13 @@
         (let ([item (car remaining)])
           (cond
             [(hash? item)
-              (let ([candidate (hash-ref item '#:candidate)])
+              (let ([candidate (hash-ref item "candidate")])
                 (loop (cdr remaining)
                   (cons candidate symbols)
                   (hash-insert
                     metadata
                     candidate
-                    (hash '#:ns (hash-ref item '#:ns) '#:type (hash-ref item '#:type)))))]
+                    (hash "ns" (hash-ref item "ns") "type" (hash-ref item "type")))))]
             [(string? item) ; plain-string fallback (old format)
               (loop (cdr remaining) (cons item symbols) metadata)]
             [else (loop (cdr remaining) symbols metadata)]))))
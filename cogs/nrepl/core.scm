@@ -13,7 +13,6 @@
 
 (require "adapter-interface.scm")
 (require "adapter-utils.scm")
-(require (only-in "string-utils.scm" parse-ffi-sexp))
 (require "helix/misc.scm")
 
 ;; Shared REPL machinery from repl-ui.hx: scratch-buffer management, rope
@@ -30,13 +29,22 @@
       eval-with-timeout
       load-file
       try-get-result
+      try-get-output
+      try-get-any-result
+      try-get-results
+      drain-results
       close
       stats
       interrupt
       stdin
       describe
+      resync
+      connection-state
+      server-flavor
+      debug-events
       ls-sessions
       attach-session
+      adopt-session
       session-id
       close-session-by-id)))
 
@@ -65,10 +73,19 @@
   nrepl:set-current-eval-request-id
   nrepl:interrupt
   nrepl:send-stdin
+  nrepl:drain-results
+  nrepl:try-get-any-result
+  nrepl:try-get-results
+  nrepl:try-get-output
   nrepl:stats
   nrepl:describe
+  nrepl:resync
+  nrepl:connection-state
+  nrepl:server-flavor
+  nrepl:debug-events
   nrepl:ls-sessions
   nrepl:attach-session
+  nrepl:adopt-session
   nrepl:clone-and-attach
   nrepl:kill-session
   nrepl:server-supports?
@@ -94,7 +111,7 @@
     spawned-process ; spawned-process struct or #f (for jack-in)
     current-eval-request-id ; request id of the in-flight eval, or #f (for interrupt)
     auto-load-on-save ; auto-run load-file on save when connected (default: #f)
-    server-capabilities) ; parsed describe hash (ops/versions/aux), or #f if unknown
+    server-capabilities) ; describe hash ("ops"/"versions"/"aux"/"flavor"), or #f if unknown
   #:transparent)
 
 ;;@doc
@@ -202,11 +219,17 @@
 ;;;; Result Processing ;;;;
 
 ;;@doc
-;; Parse the result string returned from FFI into a hashmap
-;; The string is a hash construction call like: (hash 'value "..." 'output (list) ...)
-;; Walked as data (never evaluated), so a hostile server cannot execute code here.
-(define (parse-eval-result result-str)
-  (parse-ffi-sexp result-str))
+;; Rekey an eval result from the FFI by symbol
+;; The FFI returns a hash keyed by strings, like (hash "value" "..." "output" (list) ...);
+;; adapters and the REPL buffer read 'value, 'output, 'error and 'ns. Only the
+;; top level is rekeyed: nested hashes ("stacktrace", "chunks") keep string keys.
+(define (eval-result->hash result)
+  (let loop ([keys (hash-keys->list result)]
+             [rekeyed (hash)])
+    (if (null? keys)
+      rekeyed
+      (loop (cdr keys)
+        (hash-insert rekeyed (string->symbol (car keys)) (hash-ref result (car keys)))))))
 
 ;; char-offset->line-col now lives in repl-ui.hx/coords.scm; it is required
 ;; above and re-exported from this module's provide list for existing callers.
@@ -428,7 +451,7 @@
               (string-append "eval req " (number->string req-id) " failed: ")
               on-error)
             (lambda (maybe-result resume)
-              (let ([result (parse-eval-result maybe-result)])
+              (let ([result (eval-result->hash maybe-result)])
                 (if (and (hash? result) (hash-contains? result 'need-input))
                   ;; Server is blocked on (read-line) etc. Hand control to
                   ;; the caller's prompt; send-input! feeds stdin and resumes.
@@ -507,7 +530,7 @@
                          (string-append "load-file req "
                            (number->string req-id)
                            " result ready"))]
-                     [result (parse-eval-result maybe-result)]
+                     [result (eval-result->hash maybe-result)]
                      [adapter (nrepl-state-adapter state)]
                      [formatted (adapter-format-result adapter file-contents result #t eval-number)]
                      [ns (hash-get result 'ns)]
@@ -517,6 +540,52 @@
                                  state)])
                 (on-success new-state formatted)))))))))
 
+;;@doc
+;; Take every eval and load-file result that arrived since the last call, on
+;; any connection. One call per tick services all outstanding requests, where
+;; poll-eval-result polls each request on its own timer.
+;;
+;; Returns a list (empty when nothing is new) of hashes with:
+;;   "conn-id"    - Connection the request was sent on
+;;   "request-id" - The id eval or load-file returned
+;;   "result"     - The result hash, as ffi.try-get-result returns it, or #f
+;;   "failed"     - Error message when the eval failed outright, or #f
+;;
+;; A result already taken with ffi.try-get-result is not repeated.
+(define (nrepl:drain-results)
+  (ffi.drain-results))
+
+;;@doc
+;; Take the oldest finished result on one connection, whichever request it
+;; answers.
+;;
+;; Returns (request-id . entry), where entry is a hash like those
+;; nrepl:drain-results lists, or #f when nothing has finished yet.
+(define (nrepl:try-get-any-result conn-id)
+  (let ([entry (ffi.try-get-any-result conn-id)])
+    (if entry
+        (cons (hash-get entry "request-id") entry)
+        #f)))
+
+;;@doc
+;; Poll several requests on one connection at once.
+;;
+;; Returns a list of hashes like those nrepl:drain-results lists, one for each
+;; id in request-ids that has finished, in the same order. Requests still
+;; running are left out; ask for them again later.
+(define (nrepl:try-get-results conn-id request-ids)
+  (ffi.try-get-results conn-id request-ids))
+
+;;@doc
+;; Take what an in-flight eval or load-file has printed since the last call,
+;; so a long evaluation can show its progress before it finishes.
+;;
+;; Returns a list of (kind text) pairs, oldest first, where kind is one of
+;; "stdout", "stderr", "warning" or "trace"; empty when nothing new was
+;; printed. The finished result still carries all of the output.
+(define (nrepl:try-get-output conn-id req-id)
+  (ffi.try-get-output conn-id req-id))
+
 ;;@doc
 ;; Set the evaluation timeout
 ;;
@@ -543,8 +612,9 @@
 ;; Get registry statistics for debugging
 ;;
 ;; Returns a hash with connection and session counts:
-;;   'connections - Number of active connections
-;;   'sessions - Number of active sessions
+;;   "total-connections" - Number of active connections
+;;   "total-sessions"    - Number of active sessions
+;;   "connections"       - Per-connection details and traffic metrics
 (define (nrepl:stats)
   (ffi.stats))
 
@@ -555,14 +625,67 @@
 ;;   conn-id - Connection ID
 ;;   verbose - When #t, the server includes full op documentation
 ;;
-;; Returns a parsed hash with:
-;;   'ops      - list of supported operation name strings
-;;   'versions - hash of implementation -> (hash of sub-key -> value)
-;;   'aux      - hash of auxiliary metadata
+;; Returns a hash with:
+;;   "ops"      - list of supported operation name strings
+;;   "versions" - hash of implementation -> (hash of sub-key -> value)
+;;   "aux"      - hash of auxiliary metadata
+;;   "flavor"   - the kind of server, as nrepl:server-flavor names it
 ;;
 ;; Throws if the connection is invalid or the server does not support describe.
 (define (nrepl:describe conn-id verbose)
-  (parse-eval-result (ffi.describe conn-id verbose)))
+  (ffi.describe conn-id verbose))
+
+;;@doc
+;; Flush and resynchronize a wedged connection: fail in-flight requests, drop
+;; stale results and unread socket bytes, then check the server with describe.
+;;
+;; Parameters:
+;;   conn-id - Connection ID
+;;
+;; Returns a hash with:
+;;   "bytes-discarded"   - bytes thrown away from the socket
+;;   "requests-failed"   - in-flight/queued requests that were failed
+;;   "responses-dropped" - unclaimed results that were dropped
+;;   "server-alive"      - #t if the server answered describe afterwards
+(define (nrepl:resync conn-id)
+  (ffi.resync conn-id))
+
+;;@doc
+;; Liveness of a connection, from the worker's background heartbeat. Does not
+;; touch the server, so it is cheap enough for a status line.
+;;
+;; Parameters:
+;;   conn-id - Connection ID
+;;
+;; Returns "connected", "degraded" (server not answering heartbeats) or
+;; "disconnected".
+(define (nrepl:connection-state conn-id)
+  (ffi.connection-state conn-id))
+
+;;@doc
+;; The kind of server on the other end, read from its describe reply on
+;; connect. Does not touch the server.
+;;
+;; Parameters:
+;;   conn-id - Connection ID
+;;
+;; Returns "clojure", "babashka", "nbb", "python" or "unknown".
+(define (nrepl:server-flavor conn-id)
+  (ffi.server-flavor conn-id))
+
+;;@doc
+;; Recent significant events on a connection (connects, disconnects, timeouts,
+;; limit hits), oldest first. Free of code and output, so safe for bug reports.
+;;
+;; Parameters:
+;;   conn-id - Connection ID
+;;
+;; Returns a list of hashes with:
+;;   "at"      - seconds since the Unix epoch
+;;   "kind"    - event kind string, e.g. "timeout"
+;;   "message" - detail (ids, sizes, durations)
+(define (nrepl:debug-events conn-id)
+  (ffi.debug-events conn-id))
 
 ;;@doc
 ;; Predicate: does the connected server advertise support for `op-name`?
@@ -579,7 +702,7 @@
   (let ([caps (nrepl-state-server-capabilities state)])
     (if (not caps)
       #t
-      (let ([ops (hash-get caps 'ops)])
+      (let ([ops (hash-get caps "ops")])
         (if (member op-name ops) #t #f)))))
 
 ;;;; Sessions ;;;;
@@ -589,7 +712,7 @@
 ;; strings. Requires the server to support the "ls-sessions" op (gate with
 ;; nrepl:server-supports?); raises on servers that don't.
 (define (nrepl:ls-sessions state)
-  (parse-eval-result (ffi.ls-sessions (nrepl-state-conn-id state))))
+  (ffi.ls-sessions (nrepl-state-conn-id state)))
 
 ;; Shared state update for attaching to a session: the previous session stays
 ;; alive on the server. Namespace resets to the default because it is
@@ -617,6 +740,16 @@
     (ffi.attach-session (nrepl-state-conn-id state) wire-id)
     wire-id))
 
+;;@doc
+;; Reattach to a session by a wire id saved earlier, e.g. by a previous editor
+;; instance, and return the new state. Unlike nrepl:attach-session this asks
+;; the server first (the "ls-sessions" op), and raises if it no longer has the
+;; session.
+(define (nrepl:adopt-session state wire-id)
+  (state-with-session state
+    (ffi.adopt-session (nrepl-state-conn-id state) wire-id)
+    wire-id))
+
 ;;@doc
 ;; Clone a fresh session on the server, attach to it, and return the new
 ;; state. The previous session stays alive.
@@ -25,21 +25,21 @@
 (define (format-symbol-documentation info max-width)
   "Format symbol info into displayable lines
    Returns: (list (line . style) ...)"
-  (let* ([has-name (hash-contains? info '#:name)]
-         [has-ns (hash-contains? info '#:ns)]
-         [has-arglists (hash-contains? info '#:arglists)]
-         [has-doc (hash-contains? info '#:doc)]
-         [wrapped (if has-doc (word-wrap (hash-ref info '#:doc) max-width) (list))]
+  (let* ([has-name (hash-contains? info "name")]
+         [has-ns (hash-contains? info "ns")]
+         [has-arglists (hash-contains? info "arglists")]
+         [has-doc (hash-contains? info "doc")]
+         [wrapped (if has-doc (word-wrap (hash-ref info "doc") max-width) (list))]
          [doc-lines (style-lines wrapped (style))])
     (append
       ;; Symbol name (bold)
       (if has-name
-        (list (cons (hash-ref info '#:name) (style-with-bold (style))))
+        (list (cons (hash-ref info "name") (style-with-bold (style))))
         (list))
 
       ;; Namespace (dimmed)
       (if has-ns
-        (list (cons (string-append "  " (hash-ref info '#:ns)) (style-fg (style) Color/Gray)))
+        (list (cons (string-append "  " (hash-ref info "ns")) (style-fg (style) Color/Gray)))
         (list))
 
       ;; Blank line after header
@@ -49,7 +49,7 @@
 
       ;; Arglists
       (if has-arglists
-        (style-lines (format-arglists (hash-ref info '#:arglists) max-width)
+        (style-lines (format-arglists (hash-ref info "arglists") max-width)
           (style-fg (style) Color/Cyan))
         (list))
 
@@ -63,12 +63,12 @@
       (if has-doc (list (cons "" (style))) (list))
 
       ;; File location (left-truncated to show filename)
-      (if (and (hash-contains? info '#:file) (hash-contains? info '#:line))
-        (let* ([line-val (hash-ref info '#:line)]
+      (if (and (hash-contains? info "file") (hash-contains? info "line"))
+        (let* ([line-val (hash-ref info "line")]
                [line-str (if (string? line-val)
                           line-val
                           (number->string line-val))]
-               [location (string-append (hash-ref info '#:file) ":" line-str)])
+               [location (string-append (hash-ref info "file") ":" line-str)])
           (list (cons (truncate-left location max-width) (style-fg (style) Color/Gray))))
         (list)))))
 
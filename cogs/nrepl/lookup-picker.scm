@@ -30,7 +30,7 @@
 ;; Load the steel-nrepl dylib for async completions and lookup
 (#%require-dylib "libsteel_nrepl"
   (prefix-in ffi.
-    (only-in submit-completions
+    (only-in submit-aliased-completions
       try-get-completions
       submit-lookup
       try-get-lookup)))
@@ -38,7 +38,6 @@
 (require "format-docs.scm")
 (require (only-in "ui-utils.hx/picker.scm" make-picker show-picker! picker-refilter!))
 (require (only-in "ui-utils.hx/picker-model.scm" picker-column make-string-filter))
-(require (only-in "string-utils.scm" parse-ffi-sexp))
 (require (only-in "completion-model.scm" candidates->symbols+metadata poll-delay-for))
 
 (provide show-lookup-picker)
@@ -108,7 +107,7 @@
     ;; user has not typed since), then re-run the picker's filter.
     (define (apply-completions maybe g)
       (if (= g (unbox gen))
-        (let ([parsed (candidates->symbols+metadata (parse-ffi-sexp maybe))])
+        (let ([parsed (candidates->symbols+metadata maybe)])
           (set-box! symbols (car parsed))
           (set-box! metadata (cdr parsed))
           (debug-fn (string-append "completions arrived: "
@@ -125,7 +124,7 @@
         (lambda (err)
           (debug-fn (string-append "completions submit error: " (to-string err)))
           #f)
-        (let ([req-id (ffi.submit-completions session text #f #f)])
+        (let ([req-id (ffi.submit-aliased-completions session text #f #f)])
           (debug-fn (string-append "completions fetch \"" text
                      "\" (request "
                      (to-string req-id)
@@ -161,11 +160,7 @@
           (poll-request
             (lambda () (ffi.try-get-lookup session req-id))
             (lambda (maybe)
-              (let ([parsed (parse-ffi-sexp maybe)])
-                ;; Cache an empty hash on parse failure: same render as a
-                ;; symbol the server has no info for.
-                (set-box! preview-cache
-                  (hash-insert (unbox preview-cache) symbol (if parsed parsed (hash)))))
+              (set-box! preview-cache (hash-insert (unbox preview-cache) symbol maybe))
               #t)
             (lambda ()
               ;; Negative-cache the timeout: stops the preview re-submitting
@@ -197,7 +192,7 @@
     ;; Alt-Enter action: insert namespace-qualified when a namespace is known.
     (define (insert-qualified symbol)
       (when symbol
-        (let ([ns (meta-field symbol '#:ns)])
+        (let ([ns (meta-field symbol "ns")])
           (helix.static.insert_string
             (if (and (string? ns) (not (string=? ns "")))
               (string-append ns "/" symbol)
@@ -222,9 +217,9 @@
                  (list
                    (picker-column "Symbol" 'flex (lambda (s) s))
                    (picker-column "Namespace" NS-COLUMN-WIDTH
-                     (lambda (s) (meta-field s '#:ns)))
+                     (lambda (s) (meta-field s "ns")))
                    (picker-column "Type" TYPE-COLUMN-WIDTH
-                     (lambda (s) (meta-field s '#:type))))
+                     (lambda (s) (meta-field s "type"))))
                  #:min-display-width
                  MIN-COLUMN-DISPLAY-WIDTH
                  #:column-spacing
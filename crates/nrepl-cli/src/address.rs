@@ -0,0 +1,105 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Finding the server to talk to
+//!
+//! An explicit `--address` or `--port` wins. Otherwise the port comes from
+//! a port file: the one named by `--port-file`, or the first of
+//! [`PORT_FILES`] found in the current directory or above it, which is
+//! where Leiningen, the Clojure CLI and shadow-cljs write them.
+
+use crate::error::CliError;
+use clap::Args;
+use std::path::{Path, PathBuf};
+
+/// Port files looked for, in order, in each directory searched.
+pub const PORT_FILES: [&str; 2] = [".nrepl-port", ".shadow-cljs/nrepl.port"];
+
+/// Where the server is, as given on the command line.
+#[derive(Debug, Clone, Default, Args)]
+pub struct Target {
+    /// Server address, as host:port.
+    #[arg(short, long, global = true, conflicts_with_all = ["port", "port_file"])]
+    pub address: Option<String>,
+
+    /// Server port on localhost.
+    #[arg(short, long, global = true, conflicts_with = "port_file")]
+    pub port: Option<u16>,
+
+    /// File holding the server's port, such as a .nrepl-port.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub port_file: Option<PathBuf>,
+}
+
+impl Target {
+    /// The `host:port` to connect to, searching up from `dir` for a port
+    /// file when none was given.
+    pub fn resolve(&self, dir: &Path) -> Result<String, CliError> {
+        if let Some(address) = &self.address {
+            return Ok(address.clone());
+        }
+        let port = match (self.port, &self.port_file) {
+            (Some(port), _) => port,
+            (None, Some(path)) => read_port_file(path)?,
+            (None, None) => {
+                let path = find_port_file(dir).ok_or(CliError::NoAddress)?;
+                read_port_file(&path)?
+            }
+        };
+        Ok(format!("localhost:{port}"))
+    }
+}
+
+/// The first of [`PORT_FILES`] in `dir` or its ancestors.
+pub fn find_port_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .flat_map(|d| PORT_FILES.iter().map(move |name| d.join(name)))
+        .find(|path| path.is_file())
+}
+
+/// The port written in the file at `path`.
+pub fn read_port_file(path: &Path) -> Result<u16, CliError> {
+    let text = std::fs::read_to_string(path).map_err(|e| CliError::io(path, e))?;
+    text.trim()
+        .parse()
+        .ok()
+        .filter(|&port| port != 0)
+        .ok_or_else(|| CliError::BadPortFile(path.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_file_is_found_above_and_read() {
+        let root = std::env::temp_dir().join(format!("nrepl-cli-port-{}", std::process::id()));
+        let nested = root.join("src/app");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(root.join(".nrepl-port"), "51234\n").unwrap();
+
+        let target = Target::default();
+        assert_eq!(target.resolve(&nested).unwrap(), "localhost:51234");
+        let explicit = Target {
+            port: Some(7888),
+            ..Target::default()
+        };
+        assert_eq!(explicit.resolve(&nested).unwrap(), "localhost:7888");
+
+        std::fs::write(root.join(".nrepl-port"), "port").unwrap();
+        assert!(matches!(
+            target.resolve(&nested),
+            Err(CliError::BadPortFile(_))
+        ));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,53 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Why a command could not run
+
+use nrepl_rs::NReplError;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// A failure of the client rather than of the code it ran. Printed to
+/// stderr, and the process exits with [`CLIENT_FAILURE`](crate::CLIENT_FAILURE).
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error(transparent)]
+    NRepl(#[from] NReplError),
+
+    /// Reading from the terminal failed.
+    #[error("Terminal error: {0}")]
+    Terminal(#[from] rustyline::error::ReadlineError),
+
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    /// Neither an address nor a port file was given, and none was found.
+    #[error("No server to connect to: pass --address or --port, or run under a .nrepl-port file")]
+    NoAddress,
+
+    /// A port file that does not hold a port number.
+    #[error("{}: not a port number", .0.display())]
+    BadPortFile(PathBuf),
+}
+
+impl CliError {
+    /// An I/O error on `path`.
+    pub fn io(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        Self::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
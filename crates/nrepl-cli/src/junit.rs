@@ -0,0 +1,171 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Test results as JUnit XML, the report format CI systems read
+//!
+//! Each namespace is a `<testsuite>` and each test var a `<testcase>` in
+//! it. A var with a failed assertion gets a `<failure>`, one that threw an
+//! `<error>`, each holding every such assertion of the var; `clojure.test`
+//! does not time tests, so there are no `time` attributes.
+
+use crate::output;
+use nrepl_rs::{TestAssertion, TestOutcome, TestResults};
+use std::fmt::Write;
+
+/// `results` as a JUnit XML document.
+pub fn report(results: &TestResults) -> String {
+    let summary = &results.summary;
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites tests=\"{}\" failures=\"{}\" errors=\"{}\">",
+        summary.tests, summary.fail, summary.error
+    );
+    for (ns, vars) in &results.results {
+        let count = |outcome| {
+            vars.values()
+                .filter(|assertions| worst(assertions) == Some(outcome))
+                .count()
+        };
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\">",
+            escape(ns),
+            vars.len(),
+            count(TestOutcome::Fail),
+            count(TestOutcome::Error),
+        );
+        for (var, assertions) in vars {
+            let _ = write!(
+                xml,
+                "    <testcase classname=\"{}\" name=\"{}\"",
+                escape(ns),
+                escape(var)
+            );
+            let Some(outcome) = worst(assertions).filter(|&o| o != TestOutcome::Pass) else {
+                xml.push_str("/>\n");
+                continue;
+            };
+            let (tag, kind) = match outcome {
+                TestOutcome::Error => ("error", "error"),
+                _ => ("failure", "fail"),
+            };
+            let failed: Vec<_> = assertions
+                .iter()
+                .filter(|a| a.outcome != TestOutcome::Pass)
+                .collect();
+            let message = failed
+                .iter()
+                .find_map(|a| a.message.as_deref().or(a.error.as_deref()))
+                .unwrap_or(kind);
+            let _ = writeln!(
+                xml,
+                ">\n      <{tag} message=\"{}\" type=\"{kind}\">",
+                escape(first_line(message))
+            );
+            for assertion in failed {
+                xml.push_str(&escape(&output::failure(assertion)));
+            }
+            let _ = writeln!(xml, "      </{tag}>\n    </testcase>");
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+/// The worst outcome among `assertions`: an error over a failure over a
+/// pass. `None` for a var that asserted nothing.
+fn worst(assertions: &[TestAssertion]) -> Option<TestOutcome> {
+    let rank = |outcome: &TestOutcome| match outcome {
+        TestOutcome::Pass => 0,
+        TestOutcome::Fail => 1,
+        TestOutcome::Error => 2,
+    };
+    assertions.iter().map(|a| a.outcome).max_by_key(rank)
+}
+
+fn first_line(text: &str) -> &str {
+    text.lines().next().unwrap_or_default()
+}
+
+/// `text` safe inside an attribute or element.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            // Control characters other than tab and newlines are not allowed
+            // in XML 1.0 at all.
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nrepl_rs::TestSummary;
+
+    fn assertion(var: &str, outcome: TestOutcome) -> TestAssertion {
+        TestAssertion {
+            outcome,
+            ns: "app.core-test".into(),
+            var: var.into(),
+            index: 0,
+            message: None,
+            expected: Some("(= 1 2)".into()),
+            actual: Some("(not (= 1 2))".into()),
+            diffs: Vec::new(),
+            file: Some("core_test.clj".into()),
+            line: Some(7),
+            context: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_report_marks_failing_vars() {
+        let mut results = TestResults {
+            summary: TestSummary {
+                tests: 2,
+                pass: 1,
+                fail: 1,
+                ..TestSummary::default()
+            },
+            ..TestResults::default()
+        };
+        let vars = results.results.entry("app.core-test".into()).or_default();
+        vars.insert("adds".into(), vec![assertion("adds", TestOutcome::Pass)]);
+        vars.insert(
+            "compares<".into(),
+            vec![
+                assertion("compares<", TestOutcome::Pass),
+                assertion("compares<", TestOutcome::Fail),
+            ],
+        );
+
+        let xml = report(&results);
+        assert!(xml.contains("<testsuites tests=\"2\" failures=\"1\" errors=\"0\">"));
+        assert!(xml.contains(
+            "<testsuite name=\"app.core-test\" tests=\"2\" failures=\"1\" errors=\"0\">"
+        ));
+        assert!(xml.contains("<testcase classname=\"app.core-test\" name=\"adds\"/>"));
+        assert!(xml.contains("name=\"compares&lt;\">\n      <failure message=\"fail\""));
+        assert!(xml.contains("FAIL in (compares&lt;) (core_test.clj:7)\nexpected: (= 1 2)"));
+    }
+}
@@ -0,0 +1,337 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! `nrepl-cli`: an nREPL client for the shell
+//!
+//! Each subcommand connects, does one thing and exits, so scripts and
+//! build steps can evaluate against a running REPL without a client
+//! program of their own:
+//!
+//! ```sh
+//! nrepl-cli eval '(+ 1 2)'
+//! nrepl-cli --port 7888 load-file src/app/core.clj
+//! echo '(run-all-tests)' | nrepl-cli --json eval
+//! nrepl-cli test --junit target/junit.xml app.core-test
+//! ```
+//!
+//! `nrepl-cli repl` instead stays connected and reads forms from the
+//! terminal; see [`repl`]. `nrepl-cli watch` stays connected to reload
+//! source files as they are saved; see [`watch`].
+//!
+//! The server is found as described in [`address`]. The exit status is 0
+//! on success, [`EVAL_FAILURE`] when the code threw or was interrupted or a
+//! test failed, and [`CLIENT_FAILURE`] when the client could not do what was
+//! asked.
+
+mod address;
+mod error;
+mod junit;
+mod output;
+mod repl;
+mod test_run;
+mod watch;
+
+use address::Target;
+use clap::{Parser, Subcommand};
+use error::CliError;
+use nrepl_rs::RefreshOptions;
+use nrepl_rs::blocking::NReplClient;
+use nrepl_rs::watch::{ReloadStrategy, WatchOptions};
+use nrepl_rs::worker::{EvalOptions, TestSelection, Worker};
+use output::Format;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// Exit status when the code ran but threw, or was interrupted, or a test
+/// failed.
+const EVAL_FAILURE: u8 = 1;
+
+/// Exit status when the client failed: no server, a lost connection, a
+/// file that could not be read. Also what `clap` exits with on bad usage.
+const CLIENT_FAILURE: u8 = 2;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "nrepl-cli",
+    version,
+    about = "Talk to an nREPL server from the shell"
+)]
+struct Cli {
+    #[command(flatten)]
+    target: Target,
+
+    /// Print results as JSON objects instead of plain text.
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Evaluate code and print its value.
+    Eval {
+        /// The code, or `-` (the default) to read it from stdin.
+        #[arg(default_value = "-")]
+        code: String,
+
+        /// Namespace to evaluate in.
+        #[arg(long)]
+        ns: Option<String>,
+
+        /// Seconds to wait for the eval to finish.
+        #[arg(long, value_name = "SECS")]
+        timeout: Option<u64>,
+    },
+    /// Load a source file, as an editor does on save.
+    LoadFile {
+        /// The file to load.
+        path: PathBuf,
+    },
+    /// Show the server's versions and the ops it supports.
+    Describe {
+        /// Include each op's documentation in the JSON output.
+        #[arg(long)]
+        verbose: bool,
+    },
+    /// List the sessions open on the server.
+    Sessions,
+    /// Run tests with cider-nrepl's test runner.
+    Test {
+        /// Namespaces (`app.core-test`) or vars (`app.core-test/adds`) to
+        /// test; every loaded test namespace when none are given.
+        #[arg(value_name = "NS[/VAR]")]
+        selectors: Vec<String>,
+
+        /// Load every project namespace first, so tests not yet loaded run
+        /// too.
+        #[arg(long, conflicts_with = "selectors")]
+        load_all: bool,
+
+        /// Rerun only what failed or errored in the last run.
+        #[arg(long, conflicts_with_all = ["selectors", "load_all"])]
+        failed: bool,
+
+        /// Also write the results as JUnit XML to this file.
+        #[arg(long, value_name = "PATH")]
+        junit: Option<PathBuf>,
+
+        /// Seconds to wait for each namespace's tests.
+        #[arg(long, value_name = "SECS", default_value_t = 600)]
+        timeout: u64,
+    },
+    /// Reload source files as they change, until Ctrl-C.
+    Watch {
+        /// Directories to watch, recursively.
+        #[arg(required = true, value_name = "PATH")]
+        paths: Vec<PathBuf>,
+
+        /// Reload with cider-nrepl's `refresh`, which also reloads the
+        /// namespaces that depend on a changed one, instead of loading each
+        /// changed file.
+        #[arg(long)]
+        refresh: bool,
+
+        /// Function to call before each refresh, such as a system's `stop`.
+        #[arg(long, value_name = "SYMBOL", requires = "refresh")]
+        before: Option<String>,
+
+        /// Function to call after each successful refresh, such as `start`.
+        #[arg(long, value_name = "SYMBOL", requires = "refresh")]
+        after: Option<String>,
+
+        /// Rerun the tests after each reload that succeeds: those given with
+        /// --test-selector, or every loaded test namespace.
+        #[arg(long)]
+        test: bool,
+
+        /// Namespace or var to rerun; may be repeated.
+        #[arg(long, value_name = "NS[/VAR]", requires = "test")]
+        test_selector: Vec<String>,
+
+        /// Milliseconds a file must go unchanged before it is reloaded.
+        #[arg(long, value_name = "MS", default_value_t = 200)]
+        debounce: u64,
+
+        /// Seconds to wait for each namespace's tests.
+        #[arg(long, value_name = "SECS", default_value_t = 600)]
+        timeout: u64,
+    },
+    /// Start an interactive REPL.
+    Repl {
+        /// File to keep input history in.
+        #[arg(long, value_name = "PATH", default_value_os_t = default_history())]
+        history: PathBuf,
+
+        /// Seconds each eval may run before it fails.
+        #[arg(long, value_name = "SECS", default_value_t = 3600)]
+        timeout: u64,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let format = if cli.json {
+        Format::Json
+    } else {
+        Format::Plain
+    };
+    match run(cli, format) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("nrepl-cli: {e}");
+            ExitCode::from(CLIENT_FAILURE)
+        }
+    }
+}
+
+fn run(cli: Cli, format: Format) -> Result<ExitCode, CliError> {
+    let cwd = std::env::current_dir().map_err(|e| CliError::io(".", e))?;
+    let address = cli.target.resolve(&cwd)?;
+    match cli.command {
+        Command::Eval { code, ns, timeout } => {
+            let code = if code == "-" {
+                let mut code = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut code)
+                    .map_err(|e| CliError::io("<stdin>", e))?;
+                code
+            } else {
+                code
+            };
+            let options = EvalOptions {
+                timeout: timeout.map(Duration::from_secs),
+                ns,
+                ..EvalOptions::default()
+            };
+            let mut client = NReplClient::connect(address)?;
+            let result = client.eval_with(code, options);
+            finish(client, format, result?)
+        }
+        Command::LoadFile { path } => {
+            let contents = std::fs::read_to_string(&path).map_err(|e| CliError::io(&path, e))?;
+            let mut client = NReplClient::connect(address)?;
+            let result = client.load_file(contents, Some(&path.to_string_lossy()));
+            finish(client, format, result?)
+        }
+        Command::Describe { verbose } => {
+            let worker = connect(address)?;
+            output::describe(format, &worker.describe(verbose)?);
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Sessions => {
+            let worker = connect(address)?;
+            output::sessions(format, &worker.ls_sessions()?);
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Test {
+            selectors,
+            load_all,
+            failed,
+            junit,
+            timeout,
+        } => {
+            let selections = if failed {
+                vec![TestSelection::Failed]
+            } else {
+                test_run::selections(&selectors, load_all)
+            };
+            let mut client = NReplClient::connect(address)?;
+            let results = test_run::run(&mut client, selections, Duration::from_secs(timeout));
+            // The tests have run or never will; either way the session is
+            // done with.
+            let _ = client.close();
+            let results = results?;
+            output::test_results(format, &results);
+            if let Some(path) = junit {
+                std::fs::write(&path, junit::report(&results))
+                    .map_err(|e| CliError::io(&path, e))?;
+            }
+            Ok(if results.passed() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::from(EVAL_FAILURE)
+            })
+        }
+        Command::Watch {
+            paths,
+            refresh,
+            before,
+            after,
+            test,
+            test_selector,
+            debounce,
+            timeout,
+        } => {
+            let strategy = if refresh {
+                ReloadStrategy::Refresh(RefreshOptions {
+                    before,
+                    after,
+                    ..RefreshOptions::default()
+                })
+            } else {
+                ReloadStrategy::LoadFile
+            };
+            let options = WatchOptions {
+                debounce: Duration::from_millis(debounce),
+                strategy,
+                ..WatchOptions::default()
+            };
+            let tests = test.then(|| watch::Rerun {
+                selections: test_run::selections(&test_selector, false),
+                timeout: Duration::from_secs(timeout),
+            });
+            let client = NReplClient::connect(address)?;
+            watch::run(client, &paths, options, tests, format)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Repl { history, timeout } => {
+            repl::Repl::connect(address, history, Duration::from_secs(timeout))?.run()?;
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+/// `~/.nrepl-cli-history`, or the current directory's without a home.
+fn default_history() -> PathBuf {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".nrepl-cli-history")
+}
+
+/// A worker connected to `address`, for the commands that need no session.
+fn connect(address: String) -> Result<Worker, CliError> {
+    let worker = Worker::new();
+    worker.connect_blocking(address)?;
+    Ok(worker)
+}
+
+/// Print `result`, close the client's session and pick the exit status.
+fn finish(
+    client: NReplClient,
+    format: Format,
+    result: nrepl_rs::EvalResult,
+) -> Result<ExitCode, CliError> {
+    output::eval_result(format, &result);
+    // The answer is out; a session the server will not close is its
+    // problem, not the caller's.
+    let _ = client.close();
+    Ok(if output::failed(&result) {
+        ExitCode::from(EVAL_FAILURE)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
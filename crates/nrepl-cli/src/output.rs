@@ -0,0 +1,177 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Printing what the server answered
+//!
+//! Plain output is for people: the eval's stdout on stdout, its stderr and
+//! any exception on stderr, then the value. JSON output is for scripts: one
+//! object per command on stdout, with the same fields whatever the outcome.
+
+use nrepl_rs::{EvalResult, Response, TestAssertion, TestOutcome, TestResults};
+use serde_json::{Value, json};
+use std::fmt::Write;
+
+/// How results are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Plain,
+    Json,
+}
+
+/// Whether an eval failed: it threw, or was interrupted.
+pub fn failed(result: &EvalResult) -> bool {
+    result.ex.is_some() || result.interrupted
+}
+
+/// Print an eval or load-file result.
+pub fn eval_result(format: Format, result: &EvalResult) {
+    match format {
+        Format::Plain => {
+            for out in &result.output {
+                print!("{out}");
+            }
+            for err in &result.error {
+                eprint!("{err}");
+            }
+            if let Some(ex) = &result.ex {
+                eprintln!("{ex}");
+            } else if result.interrupted {
+                eprintln!("Interrupted");
+            } else if let Some(value) = &result.value {
+                println!("{value}");
+            }
+        }
+        Format::Json => println!("{}", eval_json(result)),
+    }
+}
+
+/// `result` as the JSON object printed for it.
+pub fn eval_json(result: &EvalResult) -> Value {
+    json!({
+        "value": result.value,
+        "out": result.output.concat(),
+        "err": result.error.concat(),
+        "ns": result.ns,
+        "ex": result.ex,
+        "interrupted": result.interrupted,
+    })
+}
+
+/// Print a `describe` response: versions, then the ops the server supports.
+pub fn describe(format: Format, response: &Response) {
+    match format {
+        Format::Plain => {
+            for (name, version) in response.versions.iter().flatten() {
+                let version = version.get("version-string").map_or("?", String::as_str);
+                println!("{name} {version}");
+            }
+            let ops = response.ops.iter().flat_map(|ops| ops.keys());
+            println!("ops: {}", ops.cloned().collect::<Vec<_>>().join(" "));
+        }
+        Format::Json => println!("{}", response.to_json()),
+    }
+}
+
+/// Print session ids, one per line or as a JSON array.
+pub fn sessions(format: Format, sessions: &[String]) {
+    match format {
+        Format::Plain => {
+            for session in sessions {
+                println!("{session}");
+            }
+        }
+        Format::Json => println!("{}", json!(sessions)),
+    }
+}
+
+/// Print a test run: each failure, then the counts.
+pub fn test_results(format: Format, results: &TestResults) {
+    match format {
+        Format::Plain => {
+            for assertion in results.failures() {
+                print!("\n{}", failure(assertion));
+            }
+            let summary = &results.summary;
+            println!(
+                "\nRan {} tests containing {} assertions.\n{} failures, {} errors.",
+                summary.tests,
+                summary.pass + summary.fail + summary.error,
+                summary.fail,
+                summary.error
+            );
+        }
+        Format::Json => println!(
+            "{}",
+            json!({ "summary": results.summary, "results": results.results })
+        ),
+    }
+}
+
+/// A failed or erroring assertion as `clojure.test` prints it.
+pub fn failure(assertion: &TestAssertion) -> String {
+    let mut text = String::new();
+    let what = match assertion.outcome {
+        TestOutcome::Error => "ERROR",
+        _ => "FAIL",
+    };
+    let _ = write!(text, "{what} in ({})", assertion.var);
+    if let Some(file) = &assertion.file {
+        let _ = write!(text, " ({file}:{})", assertion.line.unwrap_or(0));
+    }
+    text.push('\n');
+    for line in [&assertion.context, &assertion.message]
+        .into_iter()
+        .flatten()
+    {
+        let _ = writeln!(text, "{line}");
+    }
+    if let Some(expected) = &assertion.expected {
+        let _ = writeln!(text, "expected: {expected}");
+    }
+    if let Some(actual) = assertion.actual.as_ref().or(assertion.error.as_ref()) {
+        let _ = writeln!(text, "  actual: {actual}");
+    }
+    for diff in &assertion.diffs {
+        let _ = writeln!(
+            text,
+            "    diff: - {}\n          + {}",
+            diff.removed, diff.added
+        );
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_json_joins_output() {
+        let mut result = EvalResult::new();
+        result.value = Some("3".into());
+        result.output = vec!["a\n".into(), "b".into()];
+        assert_eq!(
+            eval_json(&result),
+            json!({
+                "value": "3",
+                "out": "a\nb",
+                "err": "",
+                "ns": null,
+                "ex": null,
+                "interrupted": false,
+            })
+        );
+        assert!(!failed(&result));
+        result.ex = Some("class java.lang.ArithmeticException".into());
+        assert!(failed(&result));
+    }
+}
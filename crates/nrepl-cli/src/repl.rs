@@ -0,0 +1,262 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! The interactive REPL
+//!
+//! Input is read with line editing and history, and Enter on an unfinished
+//! form starts a new line rather than sending it. Output is printed as the
+//! server sends it, so a long job shows its progress; the value follows
+//! when the eval is done. Ctrl-C at the prompt clears the line, and during
+//! an eval interrupts it. An eval that reads stdin is answered a line at a
+//! time, Ctrl-D sending end of file.
+//!
+//! Lines starting with `:` are commands to the REPL itself: see [`HELP`].
+
+use crate::error::CliError;
+use nrepl_rs::blocking::NReplClient;
+use nrepl_rs::worker::{EvalOptions, EvalOutcome, RequestId, WorkerCommand, WorkerConfig};
+use nrepl_rs::{ChunkKind, EvalResult, NReplError, ServerOutput, forms_complete};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Editor, Helper};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+/// The REPL's own commands.
+pub const HELP: &str = "\
+:ns NAME   switch to namespace NAME, which must be loaded
+:help      show this
+:quit      leave (as does Ctrl-D)";
+
+/// Set by Ctrl-C while an eval runs; the terminal is out of raw mode then,
+/// so the key arrives as SIGINT.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// How often a running eval is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Namespace shown before the server has named one.
+const DEFAULT_NS: &str = "user";
+
+/// Holds a line back from the server until its forms are complete.
+struct FormValidator;
+
+impl Validator for FormValidator {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(
+            if ctx.input().starts_with(':') || forms_complete(ctx.input()) {
+                ValidationResult::Valid(None)
+            } else {
+                ValidationResult::Incomplete
+            },
+        )
+    }
+}
+
+impl Completer for FormValidator {
+    type Candidate = String;
+}
+
+impl Hinter for FormValidator {
+    type Hint = String;
+}
+
+impl Highlighter for FormValidator {}
+
+impl Helper for FormValidator {}
+
+/// A session, the terminal, and the namespace evals run in.
+pub struct Repl {
+    client: NReplClient,
+    editor: Editor<FormValidator, DefaultHistory>,
+    history: PathBuf,
+    /// The namespace the last eval left the session in.
+    ns: Option<String>,
+    timeout: Duration,
+}
+
+impl Repl {
+    /// Connect to `address`, reading and saving input history at
+    /// `history`. Each eval may run for `timeout`.
+    pub fn connect(address: String, history: PathBuf, timeout: Duration) -> Result<Self, CliError> {
+        let config = WorkerConfig::default()
+            .on_eval_output(print_output)
+            .large_fields_to_stderr();
+        let client = NReplClient::connect_with(address, config)?;
+        let mut editor = Editor::new()?;
+        editor.set_helper(Some(FormValidator));
+        // No history yet is the usual first run.
+        let _ = editor.load_history(&history);
+        // Without the handler Ctrl-C ends the process, which is all it could
+        // do before.
+        let _ = ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst));
+        Ok(Self {
+            client,
+            editor,
+            history,
+            ns: None,
+            timeout,
+        })
+    }
+
+    /// Read and evaluate until end of input or `:quit`.
+    pub fn run(mut self) -> Result<(), CliError> {
+        loop {
+            let prompt = format!("{}=> ", self.ns.as_deref().unwrap_or(DEFAULT_NS));
+            let input = match self.editor.readline(&prompt) {
+                Ok(input) => input,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(e) => return Err(e.into()),
+            };
+            let input = input.trim();
+            if input.is_empty() {
+                continue;
+            }
+            let _ = self.editor.add_history_entry(input);
+            match input.split_once(char::is_whitespace).unwrap_or((input, "")) {
+                (":quit" | ":q", _) => break,
+                (":help" | ":h", _) => println!("{HELP}"),
+                (":ns", name) if !name.trim().is_empty() => {
+                    self.eval("nil".to_string(), Some(name.trim().to_string()))?;
+                }
+                (command, _) if command.starts_with(':') => {
+                    eprintln!("Unknown command {command}; try :help");
+                }
+                _ => self.eval(input.to_string(), self.ns.clone())?,
+            }
+        }
+        let _ = self.editor.save_history(&self.history);
+        // Leaving, so a failed close has nobody to tell.
+        let _ = self.client.close();
+        Ok(())
+    }
+
+    /// Evaluate `code` in `ns` and print how it went. An eval that throws or
+    /// times out is reported and the REPL carries on; a lost connection
+    /// ends it.
+    fn eval(&mut self, code: String, ns: Option<String>) -> Result<(), CliError> {
+        let options = EvalOptions {
+            timeout: Some(self.timeout),
+            ns,
+            ..EvalOptions::default()
+        };
+        let session = self.client.session().clone();
+        let id = match self
+            .client
+            .worker()
+            .submit_eval_with(session, code, options)
+        {
+            Ok(id) => id,
+            Err(e) => {
+                eprintln!("{e}");
+                return Ok(());
+            }
+        };
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        let result = match self.wait(id) {
+            Ok(result) => result,
+            Err(CliError::NRepl(e))
+                if !matches!(e, NReplError::Connection(_) | NReplError::ConnectionLost(_)) =>
+            {
+                eprintln!("{e}");
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        // Output was printed as it came.
+        if let Some(ex) = &result.ex {
+            eprintln!("{ex}");
+        } else if result.interrupted {
+            eprintln!("Interrupted");
+        } else {
+            if let Some(ns) = &result.ns {
+                self.ns = Some(ns.clone());
+            }
+            if let Some(value) = &result.value {
+                println!("{value}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Poll for `id`'s result, passing on Ctrl-C and stdin.
+    fn wait(&mut self, id: RequestId) -> Result<EvalResult, CliError> {
+        loop {
+            if INTERRUPTED.swap(false, Ordering::SeqCst) {
+                self.interrupt(id);
+            }
+            match self
+                .client
+                .worker()
+                .try_recv_response(id)
+                .map(|r| r.outcome)
+            {
+                Some(EvalOutcome::Done(result)) => return Ok(result?),
+                Some(EvalOutcome::NeedInput { .. }) => match self.editor.readline("") {
+                    Ok(line) => self.stdin(format!("{line}\n")),
+                    Err(ReadlineError::Interrupted) => self.interrupt(id),
+                    // An empty `stdin` is end of file.
+                    Err(ReadlineError::Eof) => self.stdin(String::new()),
+                    Err(e) => return Err(e.into()),
+                },
+                None => std::thread::sleep(POLL_INTERVAL),
+            }
+        }
+    }
+
+    /// Interrupt `target` without waiting to hear how it went: its result
+    /// says.
+    fn interrupt(&mut self, target: RequestId) {
+        let session = self.client.session().clone();
+        let worker = self.client.worker();
+        let (reply, _) = channel();
+        let _ = worker.command_sender().send(WorkerCommand::Interrupt {
+            op_id: worker.next_id(),
+            session,
+            target,
+            reply,
+        });
+    }
+
+    /// Send `data` to the eval waiting on stdin.
+    fn stdin(&mut self, data: String) {
+        let session = self.client.session().clone();
+        let worker = self.client.worker();
+        let (reply, _) = channel();
+        let _ = worker.command_sender().send(WorkerCommand::Stdin {
+            op_id: worker.next_id(),
+            session,
+            data,
+            reply,
+        });
+    }
+}
+
+/// Print a chunk of eval output as it arrives: stdout to stdout, the rest
+/// to stderr.
+fn print_output(_: RequestId, chunk: &ServerOutput) {
+    if chunk.kind == ChunkKind::Stdout {
+        let mut out = std::io::stdout().lock();
+        let _ = out.write_all(chunk.text.as_bytes());
+        let _ = out.flush();
+    } else {
+        eprint!("{}", chunk.text);
+    }
+}
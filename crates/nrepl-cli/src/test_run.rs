@@ -0,0 +1,130 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Running tests with cider-nrepl's test ops
+//!
+//! Tests are picked by selectors: `app.core-test` runs a namespace's tests
+//! and `app.core-test/adds` one var. With none, every loaded test namespace
+//! runs. cider-nrepl takes one namespace per `test` op, so selectors
+//! naming several are run one namespace at a time and the reports merged.
+
+use crate::error::CliError;
+use nrepl_rs::blocking::NReplClient;
+use nrepl_rs::worker::{TestSelection, WorkerCommand};
+use nrepl_rs::{NReplError, TestResults};
+use std::collections::BTreeMap;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+/// The test ops to send for `selectors`, in namespace order.
+pub fn selections(selectors: &[String], load_all: bool) -> Vec<TestSelection> {
+    if selectors.is_empty() {
+        return vec![TestSelection::All { load_all }];
+    }
+    let mut namespaces: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for selector in selectors {
+        match selector.split_once('/') {
+            Some((ns, var)) => {
+                let vars = namespaces.entry(ns).or_default();
+                // A namespace also selected whole runs whole.
+                if !selectors.iter().any(|s| s == ns) {
+                    vars.push(var.to_string());
+                }
+            }
+            None => {
+                namespaces.insert(selector, Vec::new());
+            }
+        }
+    }
+    namespaces
+        .into_iter()
+        .map(|(ns, vars)| TestSelection::Namespace {
+            ns: ns.to_string(),
+            vars,
+        })
+        .collect()
+}
+
+/// Run each of `selections` in the client's session, one after another,
+/// giving each `timeout`.
+pub fn run(
+    client: &mut NReplClient,
+    selections: Vec<TestSelection>,
+    timeout: Duration,
+) -> Result<TestResults, CliError> {
+    let mut merged = TestResults::default();
+    for selection in selections {
+        let session = client.session().clone();
+        let worker = client.worker();
+        let (reply, replies) = channel();
+        worker
+            .command_sender()
+            .send(WorkerCommand::RunTests {
+                op_id: worker.next_id(),
+                session,
+                selection,
+                reply,
+            })
+            .map_err(|_| {
+                NReplError::Connection(std::io::Error::other("Worker thread disconnected"))
+            })?;
+        let results = replies
+            .recv_timeout(timeout)
+            .map_err(|_| NReplError::timeout("test", timeout))??;
+        merge(&mut merged, results);
+    }
+    Ok(merged)
+}
+
+/// Add `results` to `into`, counts and assertions both.
+fn merge(into: &mut TestResults, results: TestResults) {
+    let (sum, add) = (&mut into.summary, results.summary);
+    sum.namespaces += add.namespaces;
+    sum.vars += add.vars;
+    sum.tests += add.tests;
+    sum.pass += add.pass;
+    sum.fail += add.fail;
+    sum.error += add.error;
+    for (ns, vars) in results.results {
+        into.results.entry(ns).or_default().extend(vars);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selections_group_vars_by_namespace() {
+        let selectors = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            selections(&[], true),
+            vec![TestSelection::All { load_all: true }]
+        );
+        assert_eq!(
+            selections(
+                &selectors(&["b-test/one", "a-test", "b-test/two", "a-test/x"]),
+                false
+            ),
+            vec![
+                TestSelection::Namespace {
+                    ns: "a-test".into(),
+                    vars: vec![],
+                },
+                TestSelection::Namespace {
+                    ns: "b-test".into(),
+                    vars: vec!["one".into(), "two".into()],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,239 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Reloading files as they are saved, with no editor attached
+//!
+//! A [`ReloadWatcher`] does the watching and reloading; this loop polls it,
+//! reports each reload, and when asked reruns the tests once a batch of
+//! changes has loaded cleanly. A file that will not compile is reported as
+//! `file:line: message`, the form editors and terminals link to the source.
+//! Ctrl-C stops watching.
+
+use crate::error::CliError;
+use crate::output::{self, Format};
+use crate::test_run;
+use nrepl_rs::blocking::NReplClient;
+use nrepl_rs::watch::{ReloadEvent, ReloadWatcher, WatchOptions};
+use nrepl_rs::worker::TestSelection;
+use nrepl_rs::{EvalResult, NReplError, RefreshReport, StackTrace};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Set by Ctrl-C.
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// How often the watcher is polled.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What to run after a clean reload.
+pub struct Rerun {
+    pub selections: Vec<TestSelection>,
+    pub timeout: Duration,
+}
+
+/// Watch `paths` and reload what changes through `client` until Ctrl-C,
+/// running `tests` after each batch that loads without error. Ends early
+/// only if the connection is lost.
+pub fn run(
+    mut client: NReplClient,
+    paths: &[PathBuf],
+    options: WatchOptions,
+    tests: Option<Rerun>,
+    format: Format,
+) -> Result<(), CliError> {
+    let mut watcher = ReloadWatcher::new(paths, client.session().clone(), options)?;
+    let _ = ctrlc::set_handler(|| STOPPED.store(true, Ordering::SeqCst));
+    if format == Format::Plain {
+        let paths: Vec<_> = paths.iter().map(|p| p.display().to_string()).collect();
+        eprintln!("Watching {}", paths.join(", "));
+    }
+
+    // Whether a batch has been sent, and whether any of it failed.
+    let mut batch: Option<bool> = None;
+    let result = loop {
+        if STOPPED.load(Ordering::SeqCst) {
+            break Ok(());
+        }
+        let mut lost = None;
+        for event in watcher.poll(client.worker()) {
+            let ok = report(format, &event);
+            match event {
+                ReloadEvent::Reloading(_) => {
+                    batch.get_or_insert(false);
+                }
+                ReloadEvent::Failed {
+                    error: error @ (NReplError::Connection(_) | NReplError::ConnectionLost(_)),
+                    ..
+                } => lost = Some(error),
+                _ if !ok => batch = Some(true),
+                _ => {}
+            }
+        }
+        if let Some(error) = lost {
+            break Err(error.into());
+        }
+        if !watcher.is_busy()
+            && batch.take() == Some(false)
+            && let Some(tests) = &tests
+        {
+            let results = test_run::run(&mut client, tests.selections.clone(), tests.timeout);
+            match results {
+                Ok(results) => output::test_results(format, &results),
+                Err(CliError::NRepl(e))
+                    if !matches!(e, NReplError::Connection(_) | NReplError::ConnectionLost(_)) =>
+                {
+                    eprintln!("{e}");
+                }
+                Err(e) => break Err(e),
+            }
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    // Stopping, so a failed close has nobody to tell.
+    let _ = client.close();
+    result
+}
+
+/// Print `event`, returning whether it went well.
+fn report(format: Format, event: &ReloadEvent) -> bool {
+    let (ok, plain, json) = match event {
+        ReloadEvent::Reloading(paths) => {
+            let paths: Vec<_> = paths.iter().map(|p| p.display().to_string()).collect();
+            let plain = format!("Reloading {}", paths.join(" "));
+            (true, plain, json!({ "event": "reloading", "paths": paths }))
+        }
+        ReloadEvent::Loaded { path, result } => match compile_error(path, result) {
+            Some(error) => (
+                false,
+                error.clone(),
+                json!({ "event": "loaded", "path": path, "error": error }),
+            ),
+            None => (
+                true,
+                format!("Loaded {}", path.display()),
+                json!({ "event": "loaded", "path": path, "error": null }),
+            ),
+        },
+        ReloadEvent::Refreshed(report) => match refresh_error(report) {
+            Some(error) => (
+                false,
+                error.clone(),
+                json!({ "event": "refreshed", "namespaces": report.reloading, "error": error }),
+            ),
+            None => (
+                true,
+                format!("Reloaded {}", report.reloading.join(" ")),
+                json!({ "event": "refreshed", "namespaces": report.reloading, "error": null }),
+            ),
+        },
+        ReloadEvent::Failed { path, error } => {
+            let plain = match path {
+                Some(path) => format!("{}: {error}", path.display()),
+                None => format!("refresh: {error}"),
+            };
+            let json = json!({ "event": "failed", "path": path, "error": error.to_string() });
+            (false, plain, json)
+        }
+        ReloadEvent::WatchError(error) => (
+            false,
+            format!("watch: {error}"),
+            json!({ "event": "watch-error", "error": error }),
+        ),
+    };
+    match (format, ok) {
+        (Format::Json, _) => println!("{json}"),
+        (Format::Plain, true) => println!("{plain}"),
+        (Format::Plain, false) => eprintln!("{plain}"),
+    }
+    ok
+}
+
+/// `file:line: message` for a load that threw, placed where the error
+/// text says and falling back to the loaded file. `None` if it loaded.
+fn compile_error(path: &Path, result: &EvalResult) -> Option<String> {
+    if !output::failed(result) {
+        return None;
+    }
+    let err = result.error.concat();
+    let trace = StackTrace::parse(&err);
+    let message = match &trace {
+        Some(trace) if !trace.message.is_empty() => trace.message.clone(),
+        _ => result
+            .ex
+            .clone()
+            .unwrap_or_else(|| "Interrupted".to_string()),
+    };
+    Some(located(
+        path.display().to_string(),
+        trace.as_ref(),
+        &message,
+    ))
+}
+
+/// `file:line: message` for a refresh that stopped at a namespace that
+/// would not load. `None` if everything reloaded.
+fn refresh_error(report: &RefreshReport) -> Option<String> {
+    let error = report.error.as_ref()?;
+    let trace = error
+        .trace
+        .clone()
+        .or_else(|| StackTrace::parse(&error.err));
+    let message = trace
+        .as_ref()
+        .map(|t| t.root_cause().message.clone())
+        .filter(|m| !m.is_empty())
+        .unwrap_or_else(|| error.err.trim().to_string());
+    let fallback = error.ns.clone().unwrap_or_else(|| "refresh".to_string());
+    Some(located(fallback, trace.as_ref(), &message))
+}
+
+/// Prefix `message` with the first source location in `trace`, or with
+/// `fallback` when it has none.
+fn located(fallback: String, trace: Option<&StackTrace>, message: &str) -> String {
+    let location = trace
+        .into_iter()
+        .flat_map(|t| &t.frames)
+        .find_map(|f| Some((f.file.as_deref()?, f.line?)));
+    match location {
+        Some((file, line)) => format!("{file}:{line}: {message}"),
+        None => format!("{fallback}: {message}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile_error_names_file_and_line() {
+        let mut result = EvalResult::new();
+        result.ex = Some("class clojure.lang.Compiler$CompilerException".into());
+        result.error = vec![
+            "Syntax error compiling at (src/app/core.clj:3:1).\n".into(),
+            "Unable to resolve symbol: x in this context\n".into(),
+        ];
+        let path = Path::new("/work/src/app/core.clj");
+        assert_eq!(
+            compile_error(path, &result).as_deref(),
+            Some("src/app/core.clj:3: Unable to resolve symbol: x in this context")
+        );
+
+        result.error.clear();
+        assert_eq!(
+            compile_error(path, &result).as_deref(),
+            Some("/work/src/app/core.clj: class clojure.lang.Compiler$CompilerException")
+        );
+        assert_eq!(compile_error(path, &EvalResult::new()), None);
+    }
+}
@@ -0,0 +1,287 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! The `nrepl-cli` binary run against a mock server.
+
+use nrepl_rs::testing::{Frame, MockNReplServer, MockRequest, Reply, standard_replies};
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+/// Answers `(/ 1 0)` by throwing and anything else with its code's length,
+/// printing "hi" first. Loading a file that mentions `oops` fails to
+/// compile.
+fn script(request: &MockRequest) -> Vec<Reply> {
+    match (request.op(), request.get("code")) {
+        ("eval", Some("(/ 1 0)")) => vec![Reply::Frame(
+            Frame::reply_to(request)
+                .str("ex", "class java.lang.ArithmeticException")
+                .str("err", "Divide by zero\n")
+                .list("status", &["eval-error", "done"]),
+        )],
+        ("eval", Some(code)) => vec![
+            Reply::Frame(Frame::reply_to(request).str("out", "hi\n")),
+            Reply::Frame(
+                Frame::reply_to(request)
+                    .str("value", code.len().to_string())
+                    .str("ns", request.get("ns").unwrap_or("user"))
+                    .done(),
+            ),
+        ],
+        ("load-file", _) if request.get("file").is_some_and(|f| f.contains("oops")) => {
+            vec![Reply::Frame(
+                Frame::reply_to(request)
+                    .str("ex", "class clojure.lang.Compiler$CompilerException")
+                    .str(
+                        "err",
+                        "Syntax error compiling at (app/core.clj:2:1).\n\
+                         Unable to resolve symbol: oops in this context\n",
+                    )
+                    .list("status", &["eval-error", "done"]),
+            )]
+        }
+        ("load-file", _) => vec![Reply::Frame(
+            Frame::reply_to(request).str("value", "nil").done(),
+        )],
+        ("test" | "test-all", _) => vec![Reply::Raw(test_report(request))],
+        _ => standard_replies(request),
+    }
+}
+
+/// A cider-nrepl test report for the requested namespace: `adds` passes
+/// and `compares` fails.
+fn test_report(request: &MockRequest) -> Vec<u8> {
+    let ns = request.get("ns").unwrap_or("app.core-test");
+    let s = |s: &str| format!("{}:{s}", s.len());
+    let assertion = |var: &str, kind: &str| {
+        format!(
+            "d{}{}{}{}{}{}{}i0e{}i7e{}{}{}{}{}{}e",
+            s("actual"),
+            s("(not (= 1 2))"),
+            s("expected"),
+            s("(= 1 2)"),
+            s("file"),
+            s("core_test.clj"),
+            s("index"),
+            s("line"),
+            s("ns"),
+            s(ns),
+            s("type"),
+            s(kind),
+            s("var"),
+            s(var),
+        )
+    };
+    format!(
+        "d{}{}{}d{}d{}l{}e{}l{}eee{}{}{}l{}e{}d{}i0e{}i1e{}i1e{}i1e{}i2e{}i2eee",
+        s("id"),
+        s(request.id()),
+        s("results"),
+        s(ns),
+        s("adds"),
+        assertion("adds", "pass"),
+        s("compares"),
+        assertion("compares", "fail"),
+        s("session"),
+        s(request.session().unwrap_or_default()),
+        s("status"),
+        s("done"),
+        s("summary"),
+        s("error"),
+        s("fail"),
+        s("ns"),
+        s("pass"),
+        s("test"),
+        s("var"),
+    )
+    .into_bytes()
+}
+
+fn nrepl_cli(server: &MockNReplServer, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_nrepl-cli"))
+        .args(["--address", server.address()])
+        .args(args)
+        .output()
+        .expect("run nrepl-cli")
+}
+
+#[test]
+fn test_eval_prints_output_and_value() {
+    let server = MockNReplServer::start(script).expect("start mock server");
+    let out = nrepl_cli(&server, &["eval", "(+ 1 2)"]);
+    assert!(out.status.success(), "{out:?}");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "hi\n7\n");
+
+    let closed = server.received().iter().any(|r| r.op() == "close");
+    assert!(closed, "session left open");
+}
+
+#[test]
+fn test_eval_error_sets_exit_status() {
+    let server = MockNReplServer::start(script).expect("start mock server");
+    let out = nrepl_cli(&server, &["eval", "(/ 1 0)"]);
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Divide by zero"), "{stderr}");
+    assert!(stderr.contains("ArithmeticException"), "{stderr}");
+}
+
+#[test]
+fn test_json_eval_in_ns() {
+    let server = MockNReplServer::start(script).expect("start mock server");
+    let out = nrepl_cli(&server, &["--json", "eval", "--ns", "app.core", "x"]);
+    assert!(out.status.success(), "{out:?}");
+    let json: serde_json::Value = serde_json::from_slice(&out.stdout).expect("one JSON object");
+    assert_eq!(json["value"], "1");
+    assert_eq!(json["ns"], "app.core");
+    assert_eq!(json["out"], "hi\n");
+}
+
+#[test]
+fn test_repl_reads_forms_until_quit() {
+    let server = MockNReplServer::start(script).expect("start mock server");
+    let history = std::env::temp_dir().join(format!("nrepl-cli-history-{}", std::process::id()));
+    let mut repl = Command::new(env!("CARGO_BIN_EXE_nrepl-cli"))
+        .args(["--address", server.address(), "repl", "--history"])
+        .arg(&history)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("run nrepl-cli");
+    repl.stdin
+        .take()
+        .expect("stdin")
+        .write_all(b"(+ 1 2)\n:ns app.core\n(/ 1 0)\n:nope\n:quit\n")
+        .expect("write forms");
+    let out = repl.wait_with_output().expect("repl exits");
+    assert!(out.status.success(), "{out:?}");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("hi\n7\n"), "{stdout}");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("ArithmeticException"), "{stderr}");
+    assert!(stderr.contains("Unknown command :nope"), "{stderr}");
+
+    let received = server.received();
+    let evals: Vec<_> = received.iter().filter(|r| r.op() == "eval").collect();
+    assert_eq!(evals[1].get("ns"), Some("app.core"));
+    assert_eq!(evals[2].get("ns"), Some("app.core"));
+    assert!(received.iter().any(|r| r.op() == "close"));
+    let _ = std::fs::remove_file(&history);
+}
+
+#[test]
+fn test_failing_test_writes_junit_report() {
+    let server = MockNReplServer::start(script).expect("start mock server");
+    let junit = std::env::temp_dir().join(format!("nrepl-cli-junit-{}.xml", std::process::id()));
+    let out = Command::new(env!("CARGO_BIN_EXE_nrepl-cli"))
+        .args([
+            "--address",
+            server.address(),
+            "test",
+            "app.core-test",
+            "--junit",
+        ])
+        .arg(&junit)
+        .output()
+        .expect("run nrepl-cli");
+    assert_eq!(out.status.code(), Some(1), "{out:?}");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        stdout.contains("FAIL in (compares) (core_test.clj:7)\nexpected: (= 1 2)"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("Ran 2 tests containing 2 assertions.\n1 failures, 0 errors."));
+
+    let xml = std::fs::read_to_string(&junit).expect("JUnit report written");
+    let _ = std::fs::remove_file(&junit);
+    assert!(
+        xml.contains("<testcase classname=\"app.core-test\" name=\"adds\"/>"),
+        "{xml}"
+    );
+    assert!(xml.contains("name=\"compares\">\n      <failure"), "{xml}");
+
+    let received = server.received();
+    let test = received
+        .iter()
+        .find(|r| r.op() == "test")
+        .expect("test op sent");
+    assert_eq!(test.get("ns"), Some("app.core-test"));
+}
+
+#[test]
+fn test_watch_reports_compile_errors_and_reruns_tests() {
+    let server = MockNReplServer::start(script).expect("start mock server");
+    let dir = std::env::temp_dir().join(format!("nrepl-cli-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create watched dir");
+    let mut watch = Command::new(env!("CARGO_BIN_EXE_nrepl-cli"))
+        .args([
+            "--address",
+            server.address(),
+            "watch",
+            "--debounce",
+            "50",
+            "--test",
+        ])
+        .arg(&dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("run nrepl-cli");
+
+    // Saved until loaded, since the watch starts some time after the spawn.
+    let save = |contents: &str| {
+        let loaded = || {
+            server
+                .received()
+                .iter()
+                .any(|r| r.op() == "load-file" && r.get("file") == Some(contents))
+        };
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !loaded() && Instant::now() < deadline {
+            std::fs::write(dir.join("core.clj"), contents).expect("save file");
+            std::thread::sleep(Duration::from_millis(300));
+        }
+    };
+    save("(ns app.core)\n(oops)\n");
+    save("(ns app.core)\n");
+    std::thread::sleep(Duration::from_millis(500));
+    let _ = watch.kill();
+    let out = watch.wait_with_output().expect("watch exits");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("app/core.clj:2: Unable to resolve symbol: oops in this context"),
+        "{stderr}"
+    );
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("Loaded "), "{stdout}");
+    assert!(stdout.contains("Ran 2 tests"), "{stdout}");
+    // Only the clean reload reran the tests.
+    let tests = server
+        .received()
+        .iter()
+        .filter(|r| r.op() == "test-all")
+        .count();
+    assert_eq!(tests, 1);
+}
+
+#[test]
+fn test_no_server_is_a_client_failure() {
+    let out = Command::new(env!("CARGO_BIN_EXE_nrepl-cli"))
+        .args(["--port-file", "/nonexistent/.nrepl-port", "sessions"])
+        .output()
+        .expect("run nrepl-cli");
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).starts_with("nrepl-cli: /nonexistent"));
+}
@@ -0,0 +1,113 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! `nrepl-proxy`: see what a client and an nREPL server say to each other
+//!
+//! The proxy listens for clients (CIDER, Calva, this crate, anything) and
+//! connects each to the real server, passing bytes through untouched while
+//! printing every message both ways as a readable [`transcript`]. Point the
+//! client at the proxy's port instead of the server's:
+//!
+//! ```sh
+//! nrepl-proxy --upstream localhost:7888 --listen 127.0.0.1:7889
+//! nrepl-proxy --upstream localhost:7888 --json --output nrepl.jsonl
+//! ```
+//!
+//! It is meant for chasing middleware and codec incompatibilities: the
+//! transcript holds code, output and session ids, so keep it like a debug
+//! log.
+
+mod relay;
+mod transcript;
+
+use clap::Parser;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use transcript::Transcript;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "nrepl-proxy",
+    version,
+    about = "Relay an nREPL connection and print what passes through it"
+)]
+struct Cli {
+    /// The server to relay to, as host:port.
+    #[arg(long, short = 'u', value_name = "HOST:PORT")]
+    upstream: String,
+
+    /// Where to listen for clients; port 0 picks a free one.
+    #[arg(
+        long,
+        short = 'l',
+        value_name = "HOST:PORT",
+        default_value = "127.0.0.1:0"
+    )]
+    listen: String,
+
+    /// Write the transcript to this file instead of stdout.
+    #[arg(long, short = 'o', value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Write one JSON object per message, in full.
+    #[arg(long)]
+    json: bool,
+
+    /// Longest string shown in the plain transcript, in characters.
+    #[arg(long, value_name = "CHARS", default_value_t = 200)]
+    max_len: usize,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("nrepl-proxy: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), String> {
+    let out: Box<dyn std::io::Write + Send> = match &cli.output {
+        Some(path) => {
+            Box::new(std::fs::File::create(path).map_err(|e| format!("{}: {e}", path.display()))?)
+        }
+        None => Box::new(std::io::stdout()),
+    };
+    let transcript = Arc::new(Transcript::new(out, cli.json, cli.max_len));
+    let listener = TcpListener::bind(&cli.listen).map_err(|e| format!("{}: {e}", cli.listen))?;
+    let local = listener
+        .local_addr()
+        .map_err(|e| format!("{}: {e}", cli.listen))?;
+    // On stderr, so it stays out of the transcript, and first, so a script
+    // that asked for port 0 can read it.
+    eprintln!("Listening on {local}, relaying to {}", cli.upstream);
+
+    let mut conn = 0;
+    for client in listener.incoming() {
+        let client = match client {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("nrepl-proxy: accept: {e}");
+                continue;
+            }
+        };
+        conn += 1;
+        let (upstream, transcript) = (cli.upstream.clone(), Arc::clone(&transcript));
+        std::thread::spawn(move || relay::serve(client, &upstream, conn, transcript));
+    }
+    Ok(())
+}
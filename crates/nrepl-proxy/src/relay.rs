@@ -0,0 +1,156 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Carrying one client's connection to the server and back
+//!
+//! Bytes are forwarded as they arrive, before they are decoded, so the
+//! proxy never changes or delays what either side sees, even for traffic
+//! it cannot read. A copy is scanned for complete messages to go in the
+//! transcript. If a direction's bytes stop making sense as bencode, that
+//! is noted and the rest of them are forwarded without decoding.
+
+use crate::transcript::{Direction, Transcript};
+use nrepl_rs::BencodeValue;
+use nrepl_rs::codec::{FrameScanner, decode_value};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How much is read from a socket at once.
+const READ_SIZE: usize = 64 * 1024;
+
+/// A client connection and its server connection.
+struct Relay {
+    conn: u64,
+    transcript: Arc<Transcript>,
+    /// When each request still awaiting `done` was sent, by id.
+    sent: Mutex<HashMap<String, Instant>>,
+}
+
+/// Connect `client` to `upstream` and forward between them until either
+/// side hangs up.
+pub fn serve(client: TcpStream, upstream: &str, conn: u64, transcript: Arc<Transcript>) {
+    let peer = client
+        .peer_addr()
+        .map_or_else(|_| "unknown".to_string(), |a| a.to_string());
+    let server = match TcpStream::connect(upstream) {
+        Ok(server) => server,
+        Err(e) => {
+            transcript.note(conn, &format!("{peer} refused: {upstream}: {e}"));
+            return;
+        }
+    };
+    transcript.note(conn, &format!("{peer} connected to {upstream}"));
+    let relay = Arc::new(Relay {
+        conn,
+        transcript,
+        sent: Mutex::new(HashMap::new()),
+    });
+    let (Ok(client_reader), Ok(server_reader)) = (client.try_clone(), server.try_clone()) else {
+        relay.transcript.note(conn, "could not share the sockets");
+        return;
+    };
+
+    let requests = {
+        let relay = Arc::clone(&relay);
+        let (client, server) = (client_reader, server_reader);
+        std::thread::spawn(move || relay.pump(client, server, Direction::Request))
+    };
+    relay.pump(server, client, Direction::Reply);
+    let _ = requests.join();
+    relay.transcript.note(conn, "closed");
+}
+
+impl Relay {
+    /// Copy `from` to `to`, recording each message, until either fails or
+    /// `from` ends; then close both, which ends the other direction too.
+    fn pump(&self, mut from: TcpStream, mut to: TcpStream, direction: Direction) {
+        let mut chunk = vec![0; READ_SIZE];
+        let mut buffer = Vec::new();
+        let mut scanner = Some(FrameScanner::new());
+        loop {
+            let n = match from.read(&mut chunk) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            if to.write_all(&chunk[..n]).is_err() {
+                break;
+            }
+            let Some(frames) = &mut scanner else {
+                continue;
+            };
+            buffer.extend_from_slice(&chunk[..n]);
+            loop {
+                match frames.scan(&buffer) {
+                    Ok(Some(len)) => {
+                        let frame: Vec<u8> = buffer.drain(..len).collect();
+                        self.record(direction, &frame);
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        self.transcript
+                            .note(self.conn, &format!("{e}; forwarding the rest undecoded"));
+                        scanner = None;
+                        buffer = Vec::new();
+                        break;
+                    }
+                }
+            }
+        }
+        let _ = from.shutdown(Shutdown::Both);
+        let _ = to.shutdown(Shutdown::Both);
+    }
+
+    fn record(&self, direction: Direction, frame: &[u8]) {
+        let message = match decode_value(frame) {
+            Ok((message, _)) => message,
+            Err(e) => {
+                self.transcript.note(self.conn, &e.to_string());
+                return;
+            }
+        };
+        let latency = self.latency(direction, &message);
+        self.transcript
+            .message(self.conn, direction, frame.len(), &message, latency);
+    }
+
+    /// Note when a request went out; for a reply, how long ago its request
+    /// did. The request is forgotten once its `done` arrives.
+    fn latency(&self, direction: Direction, message: &BencodeValue) -> Option<Duration> {
+        let BencodeValue::Dict(fields) = message else {
+            return None;
+        };
+        let id = fields.get("id")?.as_str()?;
+        let mut sent = self.sent.lock().unwrap_or_else(PoisonError::into_inner);
+        match direction {
+            Direction::Request => {
+                sent.insert(id.to_string(), Instant::now());
+                None
+            }
+            Direction::Reply => {
+                let done = matches!(
+                    fields.get("status"),
+                    Some(BencodeValue::List(status))
+                        if status.iter().any(|s| s.as_str() == Some("done"))
+                );
+                let at = if done {
+                    sent.remove(id)
+                } else {
+                    sent.get(id).copied()
+                };
+                at.map(|at| at.elapsed())
+            }
+        }
+    }
+}
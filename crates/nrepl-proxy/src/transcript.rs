@@ -0,0 +1,199 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! The transcript: one line per message, from every connection
+//!
+//! A plain line reads
+//!
+//! ```text
+//!    1.204 #1 >>    61B op="eval" id="3" code="(+ 1 2)" session="9c1e..."
+//!    1.219 #1 <<    73B id="3" session="9c1e..." status=["done"] value="3" 15.1ms
+//! ```
+//!
+//! that is, seconds since the proxy started, the connection, the direction
+//! (`>>` client to server, `<<` back), the message's size on the wire and
+//! its fields, `op` and `id` first. A reply ends with the time since the
+//! request it answers was sent. Long strings are cut short; the bytes
+//! forwarded never are. With `--json` each line is instead an object with
+//! the message in full.
+
+use nrepl_rs::BencodeValue;
+use serde_json::json;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Which way a message went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Client to server.
+    Request,
+    /// Server to client.
+    Reply,
+}
+
+impl Direction {
+    fn arrow(self) -> &'static str {
+        match self {
+            Direction::Request => ">>",
+            Direction::Reply => "<<",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Direction::Request => "request",
+            Direction::Reply => "reply",
+        }
+    }
+}
+
+/// Where and how the transcript is written. Shared by every connection;
+/// each line is written whole.
+pub struct Transcript {
+    start: Instant,
+    json: bool,
+    /// Longest string shown in plain lines, in characters.
+    max_len: usize,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Transcript {
+    pub fn new(out: Box<dyn Write + Send>, json: bool, max_len: usize) -> Self {
+        Self {
+            start: Instant::now(),
+            json,
+            max_len,
+            out: Mutex::new(out),
+        }
+    }
+
+    /// Record a message of `size` bytes on connection `conn`. `latency` is
+    /// how long after its request a reply came.
+    pub fn message(
+        &self,
+        conn: u64,
+        direction: Direction,
+        size: usize,
+        message: &BencodeValue,
+        latency: Option<Duration>,
+    ) {
+        let line = if self.json {
+            json!({
+                "time": self.start.elapsed().as_secs_f64(),
+                "conn": conn,
+                "direction": direction.name(),
+                "bytes": size,
+                "latency_ms": latency.map(|l| l.as_secs_f64() * 1000.0),
+                "message": message.to_json(),
+            })
+            .to_string()
+        } else {
+            let mut line = format!(
+                "{} {:>5}B {}",
+                self.prefix(conn, direction.arrow()),
+                size,
+                render_fields(message, self.max_len)
+            );
+            if let Some(latency) = latency {
+                let _ = write!(line, " {:.1}ms", latency.as_secs_f64() * 1000.0);
+            }
+            line
+        };
+        self.write(&line);
+    }
+
+    /// Record something that is not a message: a connection opening or
+    /// closing, bytes that would not decode.
+    pub fn note(&self, conn: u64, text: &str) {
+        let line = if self.json {
+            json!({
+                "time": self.start.elapsed().as_secs_f64(),
+                "conn": conn,
+                "note": text,
+            })
+            .to_string()
+        } else {
+            format!("{} {text}", self.prefix(conn, "--"))
+        };
+        self.write(&line);
+    }
+
+    fn prefix(&self, conn: u64, marker: &str) -> String {
+        format!(
+            "{:>8.3} #{conn} {marker}",
+            self.start.elapsed().as_secs_f64()
+        )
+    }
+
+    fn write(&self, line: &str) {
+        let mut out = self.out.lock().unwrap_or_else(PoisonError::into_inner);
+        // A transcript that cannot be written must not stop the traffic.
+        let _ = writeln!(out, "{line}");
+        let _ = out.flush();
+    }
+}
+
+/// A message's fields as `key=value` pairs, `op` and `id` first. Anything
+/// other than a dict is shown as a value.
+fn render_fields(message: &BencodeValue, max_len: usize) -> String {
+    let BencodeValue::Dict(fields) = message else {
+        return render(message, max_len);
+    };
+    let first = ["op", "id"];
+    let ordered = first
+        .iter()
+        .filter_map(|key| fields.get_key_value(*key))
+        .chain(
+            fields
+                .iter()
+                .filter(|(key, _)| !first.contains(&key.as_str())),
+        );
+    ordered
+        .map(|(key, value)| format!("{key}={}", render(value, max_len)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn render(value: &BencodeValue, max_len: usize) -> String {
+    match value {
+        BencodeValue::String(s) => match s.char_indices().nth(max_len) {
+            Some((cut, _)) => format!("{:?}...(+{}B)", &s[..cut], s.len() - cut),
+            None => format!("{s:?}"),
+        },
+        BencodeValue::Bytes(b) => format!("<{} bytes>", b.len()),
+        BencodeValue::Int(i) => i.to_string(),
+        BencodeValue::List(items) => {
+            let items: Vec<_> = items.iter().map(|v| render(v, max_len)).collect();
+            format!("[{}]", items.join(" "))
+        }
+        BencodeValue::Dict(_) => format!("{{{}}}", render_fields(value, max_len)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nrepl_rs::codec::decode_value;
+
+    #[test]
+    fn test_fields_put_op_and_id_first_and_cut_long_strings() {
+        let (message, _) =
+            decode_value(b"d4:code11:(str \"abc\")2:id1:72:nsd1:ai1ee2:op4:eval6:statusl4:doneee")
+                .expect("valid bencode");
+        assert_eq!(
+            render_fields(&message, 5),
+            "op=\"eval\" id=\"7\" code=\"(str \"...(+6B) ns={a=1} status=[\"done\"]"
+        );
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! A client talking to a mock server through the `nrepl-proxy` binary.
+
+use nrepl_rs::blocking::NReplClient;
+use nrepl_rs::testing::{MockNReplServer, standard_replies};
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+#[test]
+fn test_traffic_passes_through_and_is_transcribed() {
+    let server = MockNReplServer::start(standard_replies).expect("start mock server");
+    let mut proxy = Command::new(env!("CARGO_BIN_EXE_nrepl-proxy"))
+        .args(["--upstream", server.address()])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("run nrepl-proxy");
+    let mut banner = String::new();
+    BufReader::new(proxy.stderr.take().expect("stderr"))
+        .read_line(&mut banner)
+        .expect("read banner");
+    let address = banner
+        .strip_prefix("Listening on ")
+        .and_then(|rest| rest.split(',').next())
+        .expect("listening address")
+        .to_string();
+
+    let mut client = NReplClient::connect(address).expect("connect through the proxy");
+    let result = client.eval("(+ 1 2)").expect("eval through the proxy");
+    assert!(result.ex.is_none());
+    client.close().expect("close session");
+    std::thread::sleep(Duration::from_millis(200));
+    let _ = proxy.kill();
+    let mut transcript = String::new();
+    proxy
+        .stdout
+        .take()
+        .expect("stdout")
+        .read_to_string(&mut transcript)
+        .expect("read transcript");
+    let _ = proxy.wait();
+
+    assert!(transcript.contains("#1 -- "), "{transcript}");
+    let eval = transcript
+        .lines()
+        .find(|l| l.contains(">>") && l.contains("op=\"eval\""))
+        .unwrap_or_else(|| panic!("no eval request in {transcript}"));
+    assert!(eval.contains("code=\"(+ 1 2)\""), "{eval}");
+    let done = transcript
+        .lines()
+        .find(|l| l.contains("<<") && l.contains("status=[\"done\"]") && l.ends_with("ms"));
+    assert!(done.is_some(), "no timed reply in {transcript}");
+}
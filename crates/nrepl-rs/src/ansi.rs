@@ -0,0 +1,304 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! ANSI escape sequences in eval output
+//!
+//! Libraries such as puget and kaocha color what they print with ANSI escape
+//! codes. An editor buffer shows those as `^[[31m` noise, while a terminal UI
+//! wants the colors. [`AnsiPolicy`] chooses, per worker (see
+//! [`WorkerConfig::ansi`](crate::worker::WorkerConfig::ansi)), whether `out`
+//! and `err` text keeps the codes, loses them, or loses them with the styling
+//! reported as [`StyleSpan`]s over the clean text.
+
+use std::ops::Range;
+
+/// What happens to ANSI escape sequences in `out` and `err` text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AnsiPolicy {
+    /// Pass text through as the server sent it.
+    #[default]
+    Keep,
+    /// Remove every escape sequence.
+    Strip,
+    /// Remove every escape sequence and report the colors and attributes
+    /// the text was printed with as [`StyleSpan`]s.
+    Parse,
+}
+
+/// A color set by an SGR escape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AnsiColor {
+    /// One of the 256 palette colors: 0-7 are the standard colors, 8-15
+    /// their bright variants.
+    Indexed(u8),
+    /// A 24-bit color.
+    Rgb(u8, u8, u8),
+}
+
+/// The styling in effect for a run of text. The default is plain text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct AnsiStyle {
+    pub foreground: Option<AnsiColor>,
+    pub background: Option<AnsiColor>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+}
+
+/// A styled run of text, as a byte range into the text with its escape
+/// sequences removed. Text outside every span is plain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StyleSpan {
+    pub range: Range<usize>,
+    pub style: AnsiStyle,
+}
+
+/// The styling of an eval's output under [`AnsiPolicy::Parse`]: one list of
+/// spans per entry of [`EvalResult::output`](crate::EvalResult::output) and
+/// of [`EvalResult::error`](crate::EvalResult::error), in the same order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputStyles {
+    pub output: Vec<Vec<StyleSpan>>,
+    pub error: Vec<Vec<StyleSpan>>,
+}
+
+/// An escape sequence longer than this is taken to be garbage rather than
+/// one still arriving: its `ESC` is dropped and the rest kept as text.
+const MAX_SEQUENCE: usize = 256;
+
+/// Applies an [`AnsiPolicy`] to the chunks of one request's stdout and
+/// stderr. The style in effect, and a sequence split across two chunks,
+/// carry over from one chunk of a stream to the next.
+#[derive(Debug, Default)]
+pub(crate) struct AnsiFilter {
+    policy: AnsiPolicy,
+    streams: [StreamState; 2],
+}
+
+#[derive(Debug, Default)]
+struct StreamState {
+    style: AnsiStyle,
+    /// The start of an escape sequence the last chunk ended in.
+    partial: String,
+}
+
+impl AnsiFilter {
+    pub(crate) fn new(policy: AnsiPolicy) -> Self {
+        Self {
+            policy,
+            streams: Default::default(),
+        }
+    }
+
+    /// `text` as the policy has it, with its style spans under
+    /// [`AnsiPolicy::Parse`].
+    pub(crate) fn apply(&mut self, stderr: bool, text: String) -> (String, Vec<StyleSpan>) {
+        if self.policy == AnsiPolicy::Keep {
+            return (text, Vec::new());
+        }
+        let parse = self.policy == AnsiPolicy::Parse;
+        let state = &mut self.streams[usize::from(stderr)];
+        if state.partial.is_empty() && !text.contains('\x1b') {
+            let spans = styled_run(state.style, 0..text.len(), parse);
+            return (text, spans.into_iter().collect());
+        }
+
+        let mut input = std::mem::take(&mut state.partial);
+        input.push_str(&text);
+        let mut clean = String::with_capacity(input.len());
+        let mut spans: Vec<StyleSpan> = Vec::new();
+        let mut push_run = |clean: &mut String, style: AnsiStyle, run: &str| {
+            let start = clean.len();
+            clean.push_str(run);
+            let Some(span) = styled_run(style, start..clean.len(), parse) else {
+                return;
+            };
+            match spans.last_mut() {
+                Some(last) if last.range.end == start && last.style == style => {
+                    last.range.end = span.range.end;
+                }
+                _ => spans.push(span),
+            }
+        };
+
+        let mut rest = input.as_str();
+        while let Some(esc) = rest.find('\x1b') {
+            push_run(&mut clean, state.style, &rest[..esc]);
+            let sequence = &rest[esc..];
+            match sequence_len(sequence) {
+                Some(len) => {
+                    if let Some(params) = sgr_params(&sequence[..len]) {
+                        state.style.apply_sgr(params);
+                    }
+                    rest = &sequence[len..];
+                }
+                None if sequence.len() > MAX_SEQUENCE => rest = &sequence[1..],
+                None => {
+                    state.partial = sequence.to_string();
+                    rest = "";
+                }
+            }
+        }
+        push_run(&mut clean, state.style, rest);
+        (clean, spans)
+    }
+}
+
+/// A span for a non-empty run printed in `style`, if it is styled at all.
+fn styled_run(style: AnsiStyle, range: Range<usize>, parse: bool) -> Option<StyleSpan> {
+    (parse && !range.is_empty() && style != AnsiStyle::default())
+        .then_some(StyleSpan { range, style })
+}
+
+/// The length of the escape sequence `sequence` starts with, or `None` if
+/// it has not all arrived yet.
+fn sequence_len(sequence: &str) -> Option<usize> {
+    let bytes = sequence.as_bytes();
+    match bytes.get(1)? {
+        // CSI: parameters, then one final byte.
+        b'[' => bytes[2..]
+            .iter()
+            .position(|b| (0x40..=0x7e).contains(b))
+            .map(|i| i + 3),
+        // OSC (a window title, a hyperlink): ends with BEL or ESC \.
+        b']' => bytes[2..].iter().enumerate().find_map(|(i, b)| match b {
+            0x07 => Some(i + 3),
+            b'\\' if bytes[i + 1] == 0x1b => Some(i + 3),
+            _ => None,
+        }),
+        _ => sequence[1..].chars().next().map(|c| 1 + c.len_utf8()),
+    }
+}
+
+/// The parameters of an SGR (`ESC [ ... m`) sequence.
+fn sgr_params(sequence: &str) -> Option<&str> {
+    sequence.strip_prefix("\x1b[")?.strip_suffix('m')
+}
+
+impl AnsiStyle {
+    /// Update the style by the SGR parameters `params`, such as `1;31`.
+    fn apply_sgr(&mut self, params: &str) {
+        let mut codes = params
+            .split([';', ':'])
+            .map(|p| p.parse::<u16>().unwrap_or(0));
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = Self::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.inverse = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.inverse = false,
+                30..=37 => self.foreground = Some(indexed(code - 30)),
+                38 => self.foreground = extended_color(&mut codes),
+                39 => self.foreground = None,
+                40..=47 => self.background = Some(indexed(code - 40)),
+                48 => self.background = extended_color(&mut codes),
+                49 => self.background = None,
+                90..=97 => self.foreground = Some(indexed(code - 90 + 8)),
+                100..=107 => self.background = Some(indexed(code - 100 + 8)),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn indexed(code: u16) -> AnsiColor {
+    AnsiColor::Indexed(u8::try_from(code).unwrap_or(u8::MAX))
+}
+
+/// The color after a 38 or 48: `5;n` from the palette or `2;r;g;b`.
+fn extended_color(codes: &mut impl Iterator<Item = u16>) -> Option<AnsiColor> {
+    let mut byte = || u8::try_from(codes.next()?).ok();
+    match byte()? {
+        5 => byte().map(AnsiColor::Indexed),
+        2 => Some(AnsiColor::Rgb(byte()?, byte()?, byte()?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_and_parse_agree_on_the_clean_text() {
+        let text = "\x1b[1;31mred\x1b[0m plain \x1b[38;5;208morange\x1b[39m\x1b]0;title\x07!";
+        let (kept, _) = AnsiFilter::new(AnsiPolicy::Keep).apply(false, text.to_string());
+        assert_eq!(kept, text);
+        let (stripped, spans) = AnsiFilter::new(AnsiPolicy::Strip).apply(false, text.to_string());
+        assert_eq!(stripped, "red plain orange!");
+        assert!(spans.is_empty());
+
+        let (parsed, spans) = AnsiFilter::new(AnsiPolicy::Parse).apply(false, text.to_string());
+        assert_eq!(parsed, stripped);
+        let red = AnsiStyle {
+            foreground: Some(AnsiColor::Indexed(1)),
+            bold: true,
+            ..AnsiStyle::default()
+        };
+        let orange = AnsiStyle {
+            foreground: Some(AnsiColor::Indexed(208)),
+            ..AnsiStyle::default()
+        };
+        assert_eq!(
+            spans,
+            [
+                StyleSpan {
+                    range: 0..3,
+                    style: red
+                },
+                StyleSpan {
+                    range: 10..16,
+                    style: orange
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_style_and_split_sequences_carry_across_chunks() {
+        let mut filter = AnsiFilter::new(AnsiPolicy::Parse);
+        let (first, _) = filter.apply(false, "a\x1b[3".to_string());
+        assert_eq!(first, "a");
+        let (second, spans) = filter.apply(false, "2mgreen".to_string());
+        assert_eq!(second, "green");
+        let green = AnsiStyle {
+            foreground: Some(AnsiColor::Indexed(2)),
+            ..AnsiStyle::default()
+        };
+        assert_eq!(
+            spans,
+            [StyleSpan {
+                range: 0..5,
+                style: green
+            }]
+        );
+        // Still green, and stderr has its own state.
+        let (_, spans) = filter.apply(false, "more".to_string());
+        assert_eq!(spans[0].style, green);
+        let (_, spans) = filter.apply(true, "err".to_string());
+        assert!(spans.is_empty());
+
+        let (rgb, spans) = filter.apply(false, "\x1b[0;48;2;1;2;3mx".to_string());
+        assert_eq!(rgb, "x");
+        assert_eq!(spans[0].style.background, Some(AnsiColor::Rgb(1, 2, 3)));
+        assert_eq!(spans[0].style.foreground, None);
+    }
+}
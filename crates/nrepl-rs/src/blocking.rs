@@ -0,0 +1,348 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! A client whose calls block until the server answers (feature `blocking`)
+//!
+//! [`NReplClient`] is for CLI tools and build scripts that want one answer
+//! at a time: connect, evaluate, read the value. It holds a [`Worker`],
+//! whose thread runs the Tokio runtime, and one session cloned on connect,
+//! so callers need neither a runtime of their own nor a polling loop.
+//!
+//! ```no_run
+//! use nrepl_rs::blocking::NReplClient;
+//!
+//! # fn main() -> Result<(), nrepl_rs::NReplError> {
+//! let mut client = NReplClient::connect("localhost:7888")?;
+//! let result = client.eval("(+ 1 2)")?;
+//! assert_eq!(result.value.as_deref(), Some("3"));
+//! client.close()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::error::NReplError;
+use crate::forms::Position;
+use crate::message::{Code, CompletionCandidate, EvalResult, Response};
+use crate::nrepl_ops::NReplOps;
+use crate::session::Session;
+use crate::worker::{
+    EvalOptions, EvalOutcome, RequestId, SubmitError, Worker, WorkerCommand, WorkerConfig,
+};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Timeout for [`NReplClient::eval`], as for an eval submitted without one.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_mins(1);
+
+/// How often a blocked eval checks for its result.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How long past its own timeout an eval is waited for: the worker's
+/// deadline only starts once the eval is sent, and its timeout error (with
+/// whatever output was gathered) is worth having over ours.
+const DEADLINE_GRACE: Duration = Duration::from_secs(1);
+
+/// A connection and a session on it, with every call blocking until done.
+pub struct NReplClient {
+    worker: Worker,
+    session: Session,
+}
+
+impl NReplClient {
+    /// Connect to `address` (`host:port`) and clone a session to work in.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection fails, or the server does not
+    /// clone a session within the `clone` [`Timeouts`](crate::Timeouts)
+    /// (30 seconds by default).
+    pub fn connect(address: impl Into<String>) -> Result<Self, NReplError> {
+        Self::connect_with(address, WorkerConfig::default())
+    }
+
+    /// [`connect`](Self::connect) with a worker built from `config`.
+    ///
+    /// # Errors
+    ///
+    /// As for [`connect`](Self::connect).
+    pub fn connect_with(
+        address: impl Into<String>,
+        config: WorkerConfig,
+    ) -> Result<Self, NReplError> {
+        let worker = Worker::with_config(config);
+        worker.connect_blocking(address.into())?;
+        let (reply, replies) = channel();
+        worker
+            .command_sender()
+            .send(WorkerCommand::CloneSession {
+                op_id: worker.next_id(),
+                reply,
+            })
+            .map_err(|_| disconnected())?;
+        let timeout = worker.timeouts().clone;
+        let session = replies
+            .recv_timeout(timeout)
+            .map_err(|_| NReplError::timeout("clone", timeout))??;
+        Ok(Self { worker, session })
+    }
+
+    /// The session every call runs in.
+    #[must_use]
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// The worker underneath, for the ops this client does not wrap.
+    pub fn worker(&mut self) -> &mut Worker {
+        &mut self.worker
+    }
+
+    /// Evaluate `code`, waiting up to [`DEFAULT_TIMEOUT`] for it to finish.
+    /// An eval that throws is an `Ok` carrying [`EvalResult::ex`].
+    ///
+    /// # Errors
+    ///
+    /// As for [`eval_with_timeout`](Self::eval_with_timeout).
+    pub fn eval(&mut self, code: impl Into<Code>) -> Result<EvalResult, NReplError> {
+        self.eval_with_timeout(code, DEFAULT_TIMEOUT)
+    }
+
+    /// Evaluate `code`, waiting up to `timeout` for it to finish. There is
+    /// nobody to answer a read from stdin, so an eval that asks for input
+    /// is interrupted.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NReplError::Timeout`] if the eval does not finish in time,
+    /// or a connection error if the worker has gone away.
+    pub fn eval_with_timeout(
+        &mut self,
+        code: impl Into<Code>,
+        timeout: Duration,
+    ) -> Result<EvalResult, NReplError> {
+        let id = self
+            .worker
+            .submit_eval(self.session.clone(), code, Some(timeout), None, None, None)
+            .map_err(|_| disconnected())?;
+        self.wait(id, "eval", timeout)
+    }
+
+    /// Evaluate `code` set up by `options`, as
+    /// [`Worker::submit_eval_with`], waiting up to its timeout
+    /// ([`DEFAULT_TIMEOUT`] if it has none).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NReplError::OperationFailed`] if `options.ns` is not a
+    /// namespace name, and otherwise as for
+    /// [`eval_with_timeout`](Self::eval_with_timeout).
+    pub fn eval_with(
+        &mut self,
+        code: impl Into<Code>,
+        mut options: EvalOptions,
+    ) -> Result<EvalResult, NReplError> {
+        let timeout = *options.timeout.get_or_insert(DEFAULT_TIMEOUT);
+        let id = self
+            .worker
+            .submit_eval_with(self.session.clone(), code, options)
+            .map_err(|e| match e {
+                SubmitError::InvalidNamespace(_) => NReplError::OperationFailed(e.to_string()),
+                _ => disconnected(),
+            })?;
+        self.wait(id, "eval", timeout)
+    }
+
+    /// Evaluate `code` as the text at `start` in `file`, so that the vars it
+    /// defines point there and stack traces through it name the file and
+    /// its real lines. Waits up to [`DEFAULT_TIMEOUT`].
+    ///
+    /// For a form cut from an editor buffer, `start` is where it begins in
+    /// the buffer: a [`FormSpan::start`](crate::FormSpan::start) from
+    /// [`forms_at`](crate::forms_at), or [`Position::at`] on the buffer's
+    /// text. [`Position::placed_at`] handles a piece cut from such a form.
+    ///
+    /// # Errors
+    ///
+    /// As for [`eval_with_timeout`](Self::eval_with_timeout).
+    pub fn eval_with_location(
+        &mut self,
+        code: impl Into<Code>,
+        file: Option<&str>,
+        start: Position,
+    ) -> Result<EvalResult, NReplError> {
+        let line = i64::try_from(start.line).ok();
+        let column = i64::try_from(start.column).ok();
+        let id = self
+            .worker
+            .submit_eval(
+                self.session.clone(),
+                code,
+                Some(DEFAULT_TIMEOUT),
+                file.map(str::to_string),
+                line,
+                column,
+            )
+            .map_err(|_| disconnected())?;
+        self.wait(id, "eval", DEFAULT_TIMEOUT)
+    }
+
+    /// Load `contents` as the file at `path` (`load-file`), waiting up to
+    /// [`DEFAULT_TIMEOUT`] for it to finish.
+    ///
+    /// # Errors
+    ///
+    /// As for [`eval_with_timeout`](Self::eval_with_timeout).
+    pub fn load_file(
+        &mut self,
+        contents: impl Into<Code>,
+        path: Option<&str>,
+    ) -> Result<EvalResult, NReplError> {
+        let name = path.and_then(|p| p.rsplit(['/', '\\']).next().map(str::to_string));
+        let id = self
+            .worker
+            .submit_load_file(
+                self.session.clone(),
+                contents,
+                path.map(str::to_string),
+                name,
+            )
+            .map_err(|_| disconnected())?;
+        self.wait(id, "load-file", DEFAULT_TIMEOUT)
+    }
+
+    /// Completions for `prefix` in `ns` (the session's namespace if `None`).
+    ///
+    /// # Errors
+    ///
+    /// As for [`Worker::completions`].
+    pub fn completions(
+        &self,
+        prefix: &str,
+        ns: Option<&str>,
+    ) -> Result<Vec<CompletionCandidate>, NReplError> {
+        self.worker.completions(&self.session, prefix, ns)
+    }
+
+    /// Information about `sym` as seen from `ns` (`lookup`).
+    ///
+    /// # Errors
+    ///
+    /// As for [`Worker::lookup`].
+    pub fn lookup(&self, sym: &str, ns: Option<&str>) -> Result<Response, NReplError> {
+        self.worker.lookup(&self.session, sym, ns)
+    }
+
+    /// The server's capabilities (`describe`).
+    ///
+    /// # Errors
+    ///
+    /// As for [`Worker::describe`].
+    pub fn describe(&self, verbose: bool) -> Result<Response, NReplError> {
+        self.worker.describe(verbose)
+    }
+
+    /// Close the session on the server and disconnect. Dropping the client
+    /// disconnects too, but leaves the session open.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server does not confirm the close within 30
+    /// seconds; the client is disconnected either way.
+    pub fn close(mut self) -> Result<(), NReplError> {
+        let (reply, replies) = channel();
+        self.worker
+            .command_sender()
+            .send(WorkerCommand::CloseSession {
+                op_id: self.worker.next_id(),
+                session: self.session.clone(),
+                reply,
+            })
+            .map_err(|_| disconnected())?;
+        let timeout = self.worker.timeouts().close;
+        let closed = replies
+            .recv_timeout(timeout)
+            .map_err(|_| NReplError::timeout("close", timeout))?;
+        self.worker.shutdown();
+        closed
+    }
+
+    /// Poll for `id`'s result, interrupting it if it asks for stdin.
+    pub(crate) fn wait(
+        &mut self,
+        id: RequestId,
+        operation: &str,
+        timeout: Duration,
+    ) -> Result<EvalResult, NReplError> {
+        let deadline = Instant::now() + timeout + DEADLINE_GRACE;
+        loop {
+            match self.worker.try_recv_response(id).map(|r| r.outcome) {
+                Some(EvalOutcome::Done(result)) => return result,
+                Some(EvalOutcome::NeedInput { .. }) => {
+                    let (reply, _) = channel();
+                    let _ = self.worker.command_sender().send(WorkerCommand::Interrupt {
+                        op_id: self.worker.next_id(),
+                        session: self.session.clone(),
+                        target: id,
+                        reply,
+                    });
+                }
+                None if Instant::now() >= deadline => {
+                    let _ = self
+                        .worker
+                        .command_sender()
+                        .send(WorkerCommand::Cancel { target: id });
+                    return Err(NReplError::timeout(operation, timeout));
+                }
+                None => thread::sleep(POLL_INTERVAL),
+            }
+        }
+    }
+}
+
+impl NReplOps for NReplClient {
+    fn eval(&mut self, code: Code) -> Result<EvalResult, NReplError> {
+        NReplClient::eval(self, code)
+    }
+
+    fn eval_with_timeout(
+        &mut self,
+        code: Code,
+        timeout: Duration,
+    ) -> Result<EvalResult, NReplError> {
+        NReplClient::eval_with_timeout(self, code, timeout)
+    }
+
+    fn load_file(&mut self, contents: Code, path: Option<&str>) -> Result<EvalResult, NReplError> {
+        NReplClient::load_file(self, contents, path)
+    }
+
+    fn completions(
+        &mut self,
+        prefix: &str,
+        ns: Option<&str>,
+    ) -> Result<Vec<CompletionCandidate>, NReplError> {
+        NReplClient::completions(self, prefix, ns)
+    }
+
+    fn lookup(&mut self, sym: &str, ns: Option<&str>) -> Result<Response, NReplError> {
+        NReplClient::lookup(self, sym, ns)
+    }
+
+    fn describe(&mut self, verbose: bool) -> Result<Response, NReplError> {
+        NReplClient::describe(self, verbose)
+    }
+}
+
+fn disconnected() -> NReplError {
+    NReplError::Connection(std::io::Error::other("Worker thread disconnected"))
+}
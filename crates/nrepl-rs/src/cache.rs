@@ -0,0 +1,300 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Opt-in caching of `completions` and `lookup` replies
+//!
+//! While the user types, an editor asks for the same completions and the
+//! same symbol's documentation over and over. A [`ResponseCache`] (see
+//! [`WorkerConfig::cache_responses`](crate::worker::WorkerConfig::cache_responses))
+//! answers a repeat from memory until its entry is older than the TTL or an
+//! eval in the same session could have changed what the server would say.
+//!
+//! Each session's entries carry a generation, bumped whenever the session is
+//! invalidated. A reply is only stored if its session's generation is the
+//! one it was requested under, so an eval sent while a `completions` was in
+//! flight cannot leave that reply behind as fresh.
+
+use crate::message::{CompletionCandidate, Response};
+use crate::session::Session;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Most entries kept per session. Past it, the session's expired entries
+/// go, and if that is not enough, all of them.
+const MAX_ENTRIES_PER_SESSION: usize = 1024;
+
+/// Shared handle to a connection's cache of `completions` and `lookup`
+/// replies, by session. Clones share the same entries.
+#[derive(Clone)]
+pub struct ResponseCache {
+    ttl: Duration,
+    inner: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    sessions: HashMap<String, SessionEntries>,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Default)]
+struct SessionEntries {
+    generation: u64,
+    entries: HashMap<CacheKey, (CachedReply, Instant)>,
+}
+
+/// What a reply answered.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum CacheKey {
+    Completions {
+        prefix: String,
+        ns: Option<String>,
+        complete_fn: Option<String>,
+        /// Whether an alias in the prefix was resolved first.
+        aliased: bool,
+    },
+    Lookup {
+        sym: String,
+        ns: Option<String>,
+        lookup_fn: Option<String>,
+    },
+}
+
+#[derive(Clone)]
+pub(crate) enum CachedReply {
+    Completions(Vec<CompletionCandidate>),
+    Lookup(Box<Response>),
+}
+
+/// Hit and miss counts since the cache was created or last cleared.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Requests answered from the cache.
+    pub hits: u64,
+    /// Requests sent to the server for want of a fresh entry.
+    pub misses: u64,
+    /// Entries held, fresh or not yet swept.
+    pub entries: usize,
+}
+
+/// Where a reply to a request that missed the cache is to be stored.
+pub(crate) struct CacheSlot {
+    cache: ResponseCache,
+    session: String,
+    /// Boxed to keep the slot small in the `Err` of a lookup.
+    key: Box<CacheKey>,
+    generation: u64,
+}
+
+impl CacheSlot {
+    /// Store `reply`, unless the session was invalidated since the request.
+    pub(crate) fn fill(self, reply: CachedReply) {
+        let mut state = self.cache.lock();
+        let Some(entries) = state.sessions.get_mut(&self.session) else {
+            return;
+        };
+        if entries.generation != self.generation {
+            return;
+        }
+        let now = Instant::now();
+        if entries.entries.len() >= MAX_ENTRIES_PER_SESSION {
+            let ttl = self.cache.ttl;
+            entries.entries.retain(|_, (_, at)| now - *at < ttl);
+            if entries.entries.len() >= MAX_ENTRIES_PER_SESSION {
+                entries.entries.clear();
+            }
+        }
+        entries.entries.insert(*self.key, (reply, now));
+    }
+}
+
+impl ResponseCache {
+    /// An empty cache whose entries stay fresh for `ttl`.
+    #[must_use]
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            inner: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// How long an entry stays fresh.
+    #[must_use]
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Hit and miss counts, and the entries held.
+    #[must_use]
+    pub fn stats(&self) -> CacheStats {
+        let state = self.lock();
+        CacheStats {
+            hits: state.hits,
+            misses: state.misses,
+            entries: state.sessions.values().map(|s| s.entries.len()).sum(),
+        }
+    }
+
+    /// Drop every entry for `session`. The worker does this itself for each
+    /// eval and load-file it sends.
+    pub fn invalidate(&self, session: &Session) {
+        self.invalidate_id(session.id());
+    }
+
+    /// [`invalidate`](Self::invalidate) by wire id.
+    pub(crate) fn invalidate_id(&self, session: &str) {
+        let mut state = self.lock();
+        let entries = state.sessions.entry(session.to_string()).or_default();
+        entries.generation += 1;
+        entries.entries.clear();
+    }
+
+    /// Forget a closed session altogether.
+    pub(crate) fn forget(&self, session: &str) {
+        self.lock().sessions.remove(session);
+    }
+
+    /// Drop every entry and reset the counts.
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        for entries in state.sessions.values_mut() {
+            entries.generation += 1;
+            entries.entries.clear();
+        }
+        state.hits = 0;
+        state.misses = 0;
+    }
+
+    /// Fresh completions for `key` in `session`, or, on a miss, the slot the
+    /// server's reply goes in.
+    pub(crate) fn completions(
+        &self,
+        session: &str,
+        key: CacheKey,
+    ) -> Result<Vec<CompletionCandidate>, CacheSlot> {
+        match self.get(session, key)? {
+            CachedReply::Completions(candidates) => Ok(candidates),
+            CachedReply::Lookup(_) => unreachable!("lookup reply under a completions key"),
+        }
+    }
+
+    /// A fresh `lookup` reply for `key` in `session`, or, on a miss, the slot
+    /// the server's reply goes in.
+    pub(crate) fn lookup(&self, session: &str, key: CacheKey) -> Result<Response, CacheSlot> {
+        match self.get(session, key)? {
+            CachedReply::Lookup(response) => Ok(*response),
+            CachedReply::Completions(_) => unreachable!("completions reply under a lookup key"),
+        }
+    }
+
+    fn get(&self, session: &str, key: CacheKey) -> Result<CachedReply, CacheSlot> {
+        let mut state = self.lock();
+        let entries = state.sessions.entry(session.to_string()).or_default();
+        let fresh = entries
+            .entries
+            .get(&key)
+            .filter(|(_, at)| at.elapsed() < self.ttl)
+            .map(|(reply, _)| reply.clone());
+        let generation = entries.generation;
+        if let Some(reply) = fresh {
+            state.hits += 1;
+            return Ok(reply);
+        }
+        state.misses += 1;
+        Err(CacheSlot {
+            cache: self.clone(),
+            session: session.to_string(),
+            key: Box::new(key),
+            generation,
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl std::fmt::Debug for ResponseCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResponseCache")
+            .field("ttl", &self.ttl)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(sym: &str) -> CacheKey {
+        CacheKey::Lookup {
+            sym: sym.to_string(),
+            ns: None,
+            lookup_fn: None,
+        }
+    }
+
+    fn reply(id: &str) -> CachedReply {
+        let frame = format!("d2:id{}:{id}e", id.len());
+        CachedReply::Lookup(Box::new(
+            crate::codec::decode_response(frame.as_bytes()).unwrap().0,
+        ))
+    }
+
+    #[test]
+    fn test_replies_are_served_until_invalidated_or_stale() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let slot = cache
+            .lookup("s1", lookup("map"))
+            .expect_err("first is a miss");
+        slot.fill(reply("map"));
+        assert_eq!(cache.lookup("s1", lookup("map")).ok().unwrap().id, "map");
+        // Other sessions and keys have their own entries.
+        assert!(cache.lookup("s2", lookup("map")).is_err());
+        assert!(cache.lookup("s1", lookup("filter")).is_err());
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 3,
+                entries: 1
+            }
+        );
+
+        cache.invalidate(&Session::new("s1"));
+        assert!(cache.lookup("s1", lookup("map")).is_err());
+
+        let stale = ResponseCache::new(Duration::ZERO);
+        stale
+            .lookup("s1", lookup("map"))
+            .unwrap_err()
+            .fill(reply("map"));
+        assert!(stale.lookup("s1", lookup("map")).is_err());
+    }
+
+    #[test]
+    fn test_reply_requested_before_an_invalidation_is_not_stored() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let slot = cache.lookup("s1", lookup("map")).unwrap_err();
+        // An eval went out while the lookup was in flight.
+        cache.invalidate_id("s1");
+        slot.fill(reply("map"));
+        assert!(cache.lookup("s1", lookup("map")).is_err());
+        assert_eq!(cache.stats().entries, 0);
+
+        cache.clear();
+        assert_eq!(cache.stats(), CacheStats::default());
+    }
+}
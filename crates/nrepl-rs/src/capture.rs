@@ -0,0 +1,239 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Wire capture of raw bencode frames
+//!
+//! When enabled with [`WorkerConfig::capture_frames`](crate::worker::WorkerConfig::capture_frames),
+//! every frame written or read is appended to a file, one line per frame:
+//!
+//! ```text
+//! 1735689600.125 send 45 d4:code7:(+ 1 2)2:id5:req-12:op4:evale
+//! 1735689600.131 recv 52 d2:id5:req-17:session36:...
+//! ```
+//!
+//! The fields are the time in seconds since the Unix epoch, the direction, the
+//! frame's length in bytes and the frame itself. Directions are `send`, `recv`
+//! (a decoded response), `skip` (a complete response that failed to decode and
+//! was skipped), `partial` (bytes still buffered when the reader gave up) and
+//! `drop` (bytes discarded by a resync). Bytes outside printable ASCII, and
+//! `\`, are written as `\xNN`, so the line is the frame verbatim apart from
+//! those escapes and a "Codec error at byte X" can be found by counting.
+//!
+//! Frames hold code, output and session ids, so treat a capture file like a
+//! debug log: never leave it on in production or share it unread.
+
+use crate::trace::event;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// Which way a captured frame went, and what became of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Send,
+    Recv,
+    Skip,
+    Partial,
+    Drop,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Send => "send",
+            Direction::Recv => "recv",
+            Direction::Skip => "skip",
+            Direction::Partial => "partial",
+            Direction::Drop => "drop",
+        }
+    }
+
+    #[cfg_attr(not(feature = "test-utils"), allow(dead_code))]
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "send" => Direction::Send,
+            "recv" => Direction::Recv,
+            "skip" => Direction::Skip,
+            "partial" => Direction::Partial,
+            "drop" => Direction::Drop,
+            _ => return None,
+        })
+    }
+}
+
+/// Shared handle to an open capture file. The writer and reader halves hold
+/// clones and append to the same file.
+#[derive(Clone)]
+pub(crate) struct FrameCapture {
+    inner: Arc<Mutex<Option<CaptureFile>>>,
+}
+
+struct CaptureFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    rotate_at: Option<u64>,
+}
+
+impl FrameCapture {
+    /// Open `path` for appending, rotating it once it would grow past
+    /// `rotate_at` bytes.
+    pub(crate) fn open(path: &Path, rotate_at: Option<u64>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Some(CaptureFile {
+                path: path.to_path_buf(),
+                file,
+                written,
+                rotate_at,
+            }))),
+        })
+    }
+
+    /// Append one frame. A failed write turns capture off for the rest of the
+    /// connection rather than disturbing it, and is logged as a trace event.
+    pub(crate) fn record(&self, direction: Direction, frame: &[u8]) {
+        let mut guard = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(capture) = guard.as_mut() else {
+            return;
+        };
+        if let Err(e) = capture.append(&format_line(SystemTime::now(), direction, frame)) {
+            event!(
+                WARN,
+                "frame capture stopped",
+                path = capture.path.display(),
+                error = e
+            );
+            *guard = None;
+        }
+    }
+}
+
+impl CaptureFile {
+    fn append(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64;
+        if let Some(limit) = self.rotate_at
+            && self.written > 0
+            && self.written + len > limit
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.written += len;
+        Ok(())
+    }
+
+    /// Move the current file to `<path>.1`, replacing any older one, and start
+    /// afresh.
+    fn rotate(&mut self) -> io::Result<()> {
+        std::fs::rename(&self.path, rotated_path(&self.path))?;
+        self.file = File::create(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+fn format_line(at: SystemTime, direction: Direction, frame: &[u8]) -> String {
+    let at = at
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut line = format!(
+        "{}.{:03} {} {} ",
+        at.as_secs(),
+        at.subsec_millis(),
+        direction.as_str(),
+        frame.len()
+    );
+    for &b in frame {
+        if (b.is_ascii_graphic() && b != b'\\') || b == b' ' {
+            line.push(char::from(b));
+        } else {
+            let _ = write!(line, "\\x{b:02x}");
+        }
+    }
+    line.push('\n');
+    line
+}
+
+/// Read a line written by [`format_line`] back into its direction and
+/// frame bytes, or `None` if it is not one.
+#[cfg_attr(not(feature = "test-utils"), allow(dead_code))]
+pub(crate) fn parse_line(line: &str) -> Option<(Direction, Vec<u8>)> {
+    let mut parts = line.splitn(4, ' ');
+    let _at = parts.next()?;
+    let direction = Direction::parse(parts.next()?)?;
+    let len: usize = parts.next()?.parse().ok()?;
+    let text = parts.next()?;
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    let mut frame = Vec::with_capacity(len);
+    let mut bytes = text.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'\\' {
+            if bytes.next()? != b'x' {
+                return None;
+            }
+            let hex = [bytes.next()?, bytes.next()?];
+            frame.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            frame.push(b);
+        }
+    }
+    (frame.len() == len).then_some((direction, frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_line_escapes_binary() {
+        let at = SystemTime::UNIX_EPOCH + Duration::from_millis(1_500);
+        let line = format_line(at, Direction::Recv, b"d3:out2:a\nq1:\\e");
+        assert_eq!(line, "1.500 recv 15 d3:out2:a\\x0aq1:\\x5ce\n");
+        assert_eq!(
+            parse_line(&line),
+            Some((Direction::Recv, b"d3:out2:a\nq1:\\e".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_capture_rotates_past_limit() {
+        let dir = std::env::temp_dir().join(format!("nrepl-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("frames.log");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(rotated_path(&path));
+
+        let capture = FrameCapture::open(&path, Some(100)).unwrap();
+        capture.record(Direction::Send, b"d2:op8:describee");
+        capture.record(Direction::Recv, b"d6:statusl4:doneee");
+        capture.record(Direction::Send, b"d2:op5:clonee");
+
+        let current = std::fs::read_to_string(&path).unwrap();
+        let rotated = std::fs::read_to_string(rotated_path(&path)).unwrap();
+        assert_eq!(current.lines().count(), 1);
+        assert!(current.contains(" send 13 d2:op5:clonee"));
+        assert_eq!(rotated.lines().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Finding library source on disk
+//!
+//! For a var defined in a library, `info` and `ns-path` name its file as a
+//! `jar:file:/…/clojure-1.12.0.jar!/clojure/core.clj` URL or, from older
+//! middleware, as the bare classpath-relative `clojure/core.clj`. Neither is
+//! something an editor can open. [`resolve_source`] turns either into a path:
+//! project files are returned where they are, and jar entries are extracted
+//! under the system temp directory, so go-to-definition reaches library code
+//! too. A bare relative path is looked up along the server's classpath, as
+//! returned by the `classpath` op.
+
+use crate::error::NReplError;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Directory under the system temp directory that jar entries extract to.
+const EXTRACT_DIR: &str = "nrepl-sources";
+
+/// Resolve a source `file`, as `info` or `ns-path` report it, to a path on
+/// disk. `classpath` is the server's, from the `classpath` op; only a bare
+/// relative `file` needs it.
+///
+/// A jar entry is extracted to `<temp>/nrepl-sources/<jar name>/<entry>`
+/// and reused until the jar changes. Unreadable classpath entries are
+/// skipped. A file found nowhere is an [`NReplError::OperationFailed`].
+pub fn resolve_source(file: &str, classpath: &[String]) -> Result<PathBuf, NReplError> {
+    resolve_source_in(file, classpath, &std::env::temp_dir().join(EXTRACT_DIR))
+}
+
+fn resolve_source_in(
+    file: &str,
+    classpath: &[String],
+    extract_root: &Path,
+) -> Result<PathBuf, NReplError> {
+    let not_found = || NReplError::OperationFailed(format!("source not found: {file}"));
+    if let Some(url) = file.strip_prefix("jar:") {
+        let (jar, entry) = url
+            .split_once("!/")
+            .ok_or_else(|| NReplError::OperationFailed(format!("malformed jar URL: {file}")))?;
+        let jar = file_url_path(jar).ok_or_else(not_found)?;
+        return extract_entry(&jar, &percent_decode(entry), extract_root)?.ok_or_else(not_found);
+    }
+    if let Some(path) = file_url_path(file) {
+        return path.is_file().then_some(path).ok_or_else(not_found);
+    }
+    let path = Path::new(file);
+    if path.is_absolute() {
+        return path
+            .is_file()
+            .then(|| path.to_path_buf())
+            .ok_or_else(not_found);
+    }
+    for root in classpath.iter().map(Path::new) {
+        if root.is_dir() {
+            let candidate = root.join(path);
+            if candidate.is_file() {
+                return Ok(candidate);
+            }
+        } else if is_jar(root)
+            && let Some(extracted) = extract_entry(root, file, extract_root)?
+        {
+            return Ok(extracted);
+        }
+    }
+    Err(not_found())
+}
+
+/// The path a `file:` URL names, or `None` if `url` is not one.
+fn file_url_path(url: &str) -> Option<PathBuf> {
+    let rest = url.strip_prefix("file:")?;
+    // `file:///x` and `file:/x` both name `/x`.
+    let rest = rest.strip_prefix("//").unwrap_or(rest);
+    Some(PathBuf::from(percent_decode(rest)))
+}
+
+/// Undo URL escaping (`%20` for a space). Malformed escapes are kept as is.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn is_jar(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("jar"))
+        && path.is_file()
+}
+
+/// Extract `entry` from `jar` under `extract_root`, returning where it went,
+/// or `None` if the jar cannot be read or has no such entry. An extraction
+/// newer than the jar is reused.
+fn extract_entry(
+    jar: &Path,
+    entry: &str,
+    extract_root: &Path,
+) -> Result<Option<PathBuf>, NReplError> {
+    let entry = entry.trim_start_matches('/');
+    let relative = Path::new(entry);
+    // Refuse anything that would land outside the jar's directory.
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Ok(None);
+    }
+    let Some(jar_name) = jar.file_name() else {
+        return Ok(None);
+    };
+    let target = extract_root.join(jar_name).join(relative);
+    if is_fresh(&target, jar) {
+        return Ok(Some(target));
+    }
+    let Ok(archive) = fs::File::open(jar).map(io::BufReader::new) else {
+        return Ok(None);
+    };
+    let Ok(mut archive) = zip::ZipArchive::new(archive) else {
+        return Ok(None);
+    };
+    let Ok(mut source) = archive.by_name(entry) else {
+        return Ok(None);
+    };
+    let failed = |e: io::Error| {
+        NReplError::OperationFailed(format!("extracting {entry} from {}: {e}", jar.display()))
+    };
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir).map_err(failed)?;
+    }
+    // Write beside the target and rename, so a concurrent reader never sees
+    // half a file.
+    let mut partial = target.clone().into_os_string();
+    partial.push(format!(".{}.partial", std::process::id()));
+    let written = fs::File::create(&partial)
+        .and_then(|mut out| io::copy(&mut source, &mut out))
+        .and_then(|_| fs::rename(&partial, &target));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(failed(e));
+    }
+    Ok(Some(target))
+}
+
+/// Whether `target` exists and is no older than `jar`.
+fn is_fresh(target: &Path, jar: &Path) -> bool {
+    let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+    match (modified(target), modified(jar)) {
+        (Some(extracted), Some(jar)) => extracted >= jar,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A fresh directory for one test, removed when dropped.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("nrepl-classpath-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Scratch(dir)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn write_jar(path: &Path, entries: &[(&str, &str)]) {
+        let mut jar = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        for (name, contents) in entries {
+            jar.start_file(*name, options).unwrap();
+            jar.write_all(contents.as_bytes()).unwrap();
+        }
+        jar.finish().unwrap();
+    }
+
+    #[test]
+    fn test_relative_file_found_in_classpath_jar() {
+        let scratch = Scratch::new("relative");
+        let src = scratch.0.join("src");
+        fs::create_dir_all(src.join("app")).unwrap();
+        fs::write(src.join("app/core.clj"), "(ns app.core)").unwrap();
+        let jar = scratch.0.join("clojure-1.12.0.jar");
+        write_jar(&jar, &[("clojure/core.clj", "(ns clojure.core)")]);
+        let classpath = [
+            src.display().to_string(),
+            scratch.0.join("missing.jar").display().to_string(),
+            jar.display().to_string(),
+        ];
+        let out = scratch.0.join("out");
+
+        let path = resolve_source_in("clojure/core.clj", &classpath, &out).unwrap();
+        assert_eq!(path, out.join("clojure-1.12.0.jar/clojure/core.clj"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "(ns clojure.core)");
+        // Extracted once, then reused.
+        assert_eq!(
+            resolve_source_in("clojure/core.clj", &classpath, &out).unwrap(),
+            path
+        );
+
+        // Directories on the classpath are searched in place.
+        assert_eq!(
+            resolve_source_in("app/core.clj", &classpath, &out).unwrap(),
+            src.join("app/core.clj")
+        );
+        assert!(matches!(
+            resolve_source_in("clojure/set.clj", &classpath, &out),
+            Err(NReplError::OperationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_jar_url_names_its_jar() {
+        let scratch = Scratch::new("jar-url");
+        let libs = scratch.0.join("my libs");
+        fs::create_dir_all(&libs).unwrap();
+        let jar = libs.join("lib.jar");
+        write_jar(&jar, &[("lib/core.clj", "(ns lib.core)")]);
+        let url = format!(
+            "jar:file:{}!/lib/core.clj",
+            jar.display().to_string().replace(' ', "%20")
+        );
+        let out = scratch.0.join("out");
+
+        let path = resolve_source_in(&url, &[], &out).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "(ns lib.core)");
+    }
+
+    #[test]
+    fn test_entry_outside_jar_dir_refused() {
+        let scratch = Scratch::new("escape");
+        let jar = scratch.0.join("evil.jar");
+        write_jar(&jar, &[("../escaped.clj", "boom")]);
+        let out = scratch.0.join("out");
+        let url = format!("jar:file:{}!/../escaped.clj", jar.display());
+        assert!(resolve_source_in(&url, &[], &out).is_err());
+        assert!(!scratch.0.join("escaped.clj").exists());
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b%2Fc"), "a b/c");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! ClojureScript REPLs with piggieback or shadow-cljs
+//!
+//! A ClojureScript REPL over nREPL is an ordinary Clojure session that has
+//! been upgraded by evaluating a form: piggieback's `cljs-repl` with a REPL
+//! environment, or shadow-cljs's `nrepl-select` with a build id. From then on
+//! the middleware hands that session's evals to the JavaScript runtime, until
+//! `:cljs/quit` hands it back.
+//!
+//! The JVM-side tooling does not follow the switch. nREPL's `completions`
+//! completes Clojure vars whatever the session, and alias lookups are Clojure
+//! evals. The worker therefore remembers which sessions it upgraded and sends
+//! their completions to cider-nrepl's `complete`, which knows both dialects.
+
+use crate::error::NReplError;
+
+/// Evaluated in a ClojureScript session to hand it back to Clojure.
+pub(crate) const CLJS_QUIT: &str = ":cljs/quit";
+
+/// How to turn a session into a ClojureScript REPL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CljsRepl {
+    /// `cider.piggieback/cljs-repl` with `repl_env`, a form building the REPL
+    /// environment, such as `(cljs.repl.node/repl-env)`.
+    Piggieback { repl_env: String },
+    /// `shadow.cljs.devtools.api/nrepl-select` for the shadow-cljs build
+    /// `build` (`app` for `:app`). The build must be running.
+    Shadow { build: String },
+}
+
+impl CljsRepl {
+    /// The form that upgrades the session. A shadow-cljs build id that is not
+    /// a plain keyword name is an [`NReplError::OperationFailed`].
+    pub(crate) fn upgrade_form(&self) -> Result<String, NReplError> {
+        match self {
+            CljsRepl::Piggieback { repl_env } => Ok(format!(
+                "(do (require 'cider.piggieback) \
+                 ((resolve 'cider.piggieback/cljs-repl) {repl_env}))"
+            )),
+            CljsRepl::Shadow { build } => {
+                let build = build.trim_start_matches(':');
+                let plain = !build.is_empty()
+                    && build
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || "-_.*+!?<>=/".contains(c));
+                if !plain {
+                    return Err(NReplError::OperationFailed(format!(
+                        "not a shadow-cljs build id: {build}"
+                    )));
+                }
+                Ok(format!(
+                    "(do (require 'shadow.cljs.devtools.api) \
+                     ((resolve 'shadow.cljs.devtools.api/nrepl-select) :{build}))"
+                ))
+            }
+        }
+    }
+
+    /// Check the printed value of a successful upgrade eval. shadow-cljs
+    /// answers `[:selected :app]`, or says why not (`[:no-worker :app]`)
+    /// without throwing.
+    pub(crate) fn check_upgrade(&self, value: Option<&str>) -> Result<(), NReplError> {
+        match (self, value) {
+            (CljsRepl::Shadow { build }, Some(value)) if value.starts_with("[:no-") => {
+                Err(NReplError::OperationFailed(format!(
+                    "shadow-cljs could not select {build}: {value}"
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upgrade_forms() {
+        let piggieback = CljsRepl::Piggieback {
+            repl_env: "(cljs.repl.node/repl-env)".to_string(),
+        };
+        assert!(
+            piggieback
+                .upgrade_form()
+                .unwrap()
+                .ends_with("((resolve 'cider.piggieback/cljs-repl) (cljs.repl.node/repl-env)))")
+        );
+
+        let shadow = CljsRepl::Shadow {
+            build: ":app".to_string(),
+        };
+        assert!(
+            shadow
+                .upgrade_form()
+                .unwrap()
+                .ends_with("nrepl-select) :app))")
+        );
+        assert!(
+            CljsRepl::Shadow {
+                build: "app) (System/exit 0".to_string()
+            }
+            .upgrade_form()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_shadow_refusal_detected() {
+        let shadow = CljsRepl::Shadow {
+            build: "app".to_string(),
+        };
+        assert!(shadow.check_upgrade(Some("[:selected :app]")).is_ok());
+        assert!(matches!(
+            shadow.check_upgrade(Some("[:no-worker :app]")),
+            Err(NReplError::OperationFailed(_))
+        ));
+    }
+}
@@ -20,7 +20,16 @@
 /// - Lists: `l<items>e` (e.g., "l4:spam4:eggse")
 /// - Dictionaries: `d<key><value>...e` (e.g., "d3:cow3:moo4:spam4:eggse")
 use crate::error::{NReplError, Result};
-use crate::message::{BencodeValue, Request, Response, response_from_bencode};
+use crate::message::{Request, Response, response_from_bencode};
+use bytes::Bytes;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+pub use crate::message::BencodeValue;
+/// Reads one bencode value as any [`serde::Deserialize`] type.
+pub use serde_bencode::de::Deserializer;
+/// Writes any [`serde::Serialize`] type as bencode.
+pub use serde_bencode::ser::Serializer;
 
 /// Maximum allowed length for a single bencode string (10MB)
 /// This prevents malicious servers from causing OOM by sending extremely large length values.
@@ -29,105 +38,262 @@ use crate::message::{BencodeValue, Request, Response, response_from_bencode};
 const MAX_STRING_LENGTH: usize = 10 * 1024 * 1024;
 
 pub fn encode_request(request: &Request) -> Result<Vec<u8>> {
-    serde_bencode::to_bytes(request).map_err(|e| NReplError::codec(e.to_string(), 0))
+    to_bytes(request)
 }
 
-/// Find the end position of a bencode message
-/// Returns the number of bytes consumed by one complete bencode value
-fn find_bencode_end(data: &[u8], start: usize) -> Result<usize> {
-    let mut pos = start;
+/// Encode `value` as bencode.
+///
+/// Maps become dicts with their keys sorted, `Option` fields that are
+/// `None` are left out, and `bool`s and floats are refused: bencode has
+/// neither.
+///
+/// # Errors
+///
+/// Returns [`NReplError::Codec`] if `value` holds something bencode cannot
+/// represent.
+pub fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut serializer = Serializer::new();
+    value
+        .serialize(&mut serializer)
+        .map_err(|e| NReplError::codec(e.to_string(), 0))?;
+    Ok(serializer.into_vec())
+}
+
+/// Decode the one complete bencode message in `data` as a `T`.
+///
+/// # Errors
+///
+/// Returns [`NReplError::Codec`] if `data` is not exactly one message, or
+/// the message does not have the shape of a `T`.
+pub fn from_bytes<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+    match FrameScanner::new().scan(data)? {
+        Some(len) if len == data.len() => {}
+        Some(len) => {
+            return Err(NReplError::codec_with_preview(
+                "Trailing bytes after bencode message",
+                len,
+                data,
+            ));
+        }
+        None => {
+            return Err(NReplError::codec_with_preview(
+                "Incomplete bencode message",
+                data.len(),
+                data,
+            ));
+        }
+    }
+    serde_bencode::from_bytes(data)
+        .map_err(|e| NReplError::codec_with_preview(e.to_string(), 0, data))
+}
 
-    if pos >= data.len() {
+/// Decode the bencode value at the head of `data`, whatever its shape.
+/// Returns the value and the number of bytes it took up.
+///
+/// Parses as the reader's salvage path does: a dict key left without a
+/// value ends the dict rather than failing the message.
+///
+/// # Errors
+///
+/// Returns [`NReplError::Codec`] if `data` does not start with a complete
+/// bencode value.
+pub fn decode_value(data: &[u8]) -> Result<(BencodeValue, usize)> {
+    let Some(len) = FrameScanner::new().scan(data)? else {
         return Err(NReplError::codec_with_preview(
             "Incomplete bencode message",
-            pos,
+            data.len(),
             data,
         ));
+    };
+    parse_value(&data[..len], 0)
+        .map(|(value, _)| (value, len))
+        .ok_or_else(|| NReplError::codec_with_preview("Malformed bencode value", 0, &data[..len]))
+}
+
+impl BencodeValue {
+    /// This value as bencode.
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
     }
 
-    match data[pos] {
-        b'i' => {
-            // Integer: i<number>e
-            pos += 1;
-            while pos < data.len() && data[pos] != b'e' {
-                pos += 1;
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            BencodeValue::String(s) => {
+                out.extend_from_slice(format!("{}:", s.len()).as_bytes());
+                out.extend_from_slice(s.as_bytes());
             }
-            if pos >= data.len() {
-                return Err(NReplError::codec_with_preview(
-                    "Incomplete integer",
-                    pos,
-                    data,
-                ));
+            BencodeValue::Bytes(b) => {
+                out.extend_from_slice(format!("{}:", b.len()).as_bytes());
+                out.extend_from_slice(b);
             }
-            pos += 1; // Skip 'e'
-            Ok(pos)
-        }
-        b'l' => {
-            // List: l<items>e
-            pos += 1;
-            while pos < data.len() && data[pos] != b'e' {
-                pos = find_bencode_end(data, pos)?;
+            BencodeValue::Int(i) => out.extend_from_slice(format!("i{i}e").as_bytes()),
+            BencodeValue::List(items) => {
+                out.push(b'l');
+                items.iter().for_each(|item| item.encode_into(out));
+                out.push(b'e');
             }
-            if pos >= data.len() {
-                return Err(NReplError::codec_with_preview("Incomplete list", pos, data));
+            BencodeValue::Dict(map) => {
+                out.push(b'd');
+                for (key, value) in map {
+                    out.extend_from_slice(format!("{}:", key.len()).as_bytes());
+                    out.extend_from_slice(key.as_bytes());
+                    value.encode_into(out);
+                }
+                out.push(b'e');
             }
-            pos += 1; // Skip 'e'
-            Ok(pos)
         }
-        b'd' => {
-            // Dict: d<key><value>...e
-            pos += 1;
-            while pos < data.len() && data[pos] != b'e' {
-                pos = find_bencode_end(data, pos)?; // key
-                // Tolerate a non-conforming server that emits a key with no
-                // value (guile-ares-rs does this for stack frames with no source
-                // location: `...6:sourceed...` - the `source` key is followed
-                // straight by the dict-terminating `e`). Strictly this is invalid
-                // bencode, but if we treated it as truncation we'd report the
-                // whole message `Incomplete` forever and wedge the reader. Closing
-                // the dict here keeps framing aligned so the message can be skipped
-                // (or salvaged) and later messages still decode.
-                if pos < data.len() && data[pos] == b'e' {
-                    break;
+    }
+}
+
+impl TryFrom<&Request> for BencodeValue {
+    type Error = NReplError;
+
+    /// The request as it would go on the wire: a dict of its set fields.
+    fn try_from(request: &Request) -> Result<Self> {
+        decode_value(&encode_request(request)?).map(|(value, _)| value)
+    }
+}
+
+impl TryFrom<BencodeValue> for Request {
+    type Error = NReplError;
+
+    /// A request from a dict of its fields, as a proxy or test server
+    /// would read one off the wire.
+    fn try_from(value: BencodeValue) -> Result<Self> {
+        from_bytes(&value.encode())
+    }
+}
+
+impl TryFrom<BencodeValue> for Response {
+    type Error = NReplError;
+
+    /// A response from a dict of its fields, with the reader's rules: a
+    /// field of an unexpected shape is salvaged rather than failing the
+    /// response, but there must be a string `id`.
+    fn try_from(value: BencodeValue) -> Result<Self> {
+        match decode_frame(&value.encode()) {
+            Decoded::Message { response, .. } => Ok(*response),
+            Decoded::Malformed { message, .. } => Err(NReplError::codec(message, 0)),
+            Decoded::Incomplete => Err(NReplError::codec("Incomplete bencode message", 0)),
+        }
+    }
+}
+
+/// Incremental framer for the bencode message at the head of a buffer.
+///
+/// Bencode is self-delimiting, so finding where a message ends needs only a
+/// count of open lists and dicts. The scanner keeps that count and how far
+/// it has walked between calls: when a read leaves the message incomplete,
+/// the next call resumes where this one stopped instead of walking the
+/// buffer again from the start. Without that, a response streamed in over
+/// many reads costs time quadratic in its size.
+///
+/// Each call must see the same buffer, grown only at the end. Call
+/// [`reset`](Self::reset) when the buffer is cleared or its head consumed
+/// some other way; [`scan`](Self::scan) resets itself when it finds a
+/// message's end.
+#[derive(Debug, Clone, Default)]
+pub struct FrameScanner {
+    /// Bytes of the head message walked so far.
+    pos: usize,
+    /// Lists and dicts opened and not yet closed.
+    depth: usize,
+}
+
+impl FrameScanner {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start over at the head of the buffer.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Walk on through `data` and return the length of the message at its
+    /// head once all of it is there, or `None` while bytes are still to come.
+    ///
+    /// A dict key followed straight by the dict's `e` is accepted:
+    /// guile-ares-rs sends `...6:sourcee` for stack frames with no source
+    /// location. Strictly that is invalid bencode, but treating it as
+    /// truncation would report the message incomplete forever and wedge the
+    /// reader; closing the dict keeps framing aligned so the message can be
+    /// skipped (or salvaged) and later ones still decode.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NReplError::Codec`] at a byte that cannot start a value or a
+    /// string length that is malformed or over the 10MB limit. The scanner
+    /// stays put, so calling again fails the same way.
+    pub fn scan(&mut self, data: &[u8]) -> Result<Option<usize>> {
+        loop {
+            let Some(&byte) = data.get(self.pos) else {
+                return Ok(None);
+            };
+            match byte {
+                b'l' | b'd' => {
+                    self.depth += 1;
+                    self.pos += 1;
+                    continue;
+                }
+                b'e' if self.depth > 0 => {
+                    self.depth -= 1;
+                    self.pos += 1;
+                }
+                b'i' => {
+                    // Integer: i<number>e
+                    let Some(end) = data[self.pos..].iter().position(|&b| b == b'e') else {
+                        return Ok(None);
+                    };
+                    self.pos += end + 1;
+                }
+                b'0'..=b'9' => match string_end(data, self.pos)? {
+                    Some(end) => self.pos = end,
+                    None => return Ok(None),
+                },
+                _ => {
+                    return Err(NReplError::codec(
+                        format!("Invalid bencode byte: 0x{byte:02x}"),
+                        self.pos,
+                    ));
                 }
-                pos = find_bencode_end(data, pos)?; // value
             }
-            if pos >= data.len() {
-                return Err(NReplError::codec_with_preview("Incomplete dict", pos, data));
+            if self.depth == 0 {
+                let len = self.pos;
+                self.reset();
+                return Ok(Some(len));
             }
-            pos += 1; // Skip 'e'
-            Ok(pos)
         }
-        b'0'..=b'9' => find_string_end(data, pos),
-        _ => Err(NReplError::codec_with_preview(
-            format!("Invalid bencode byte: 0x{:02x}", data[pos]),
-            pos,
-            data,
-        )),
     }
-}
 
-/// Find the end position of a bencode string (`<length>:<data>`) starting at `pos`.
-fn find_string_end(data: &[u8], start: usize) -> Result<usize> {
-    let mut pos = start;
-    let mut len_str = Vec::new();
-    while pos < data.len() && data[pos] != b':' {
-        len_str.push(data[pos]);
-        pos += 1;
-    }
-    if pos >= data.len() {
-        return Err(NReplError::codec_with_preview(
-            "Incomplete string length",
-            pos,
-            data,
-        ));
+    /// Decode the response at the head of `data` if all of it has arrived,
+    /// resuming the walk from the last call. See [`Decoded`].
+    pub fn decode(&mut self, data: &[u8]) -> Decoded {
+        match self.scan(data) {
+            Ok(Some(consumed)) => decode_frame(&data[..consumed]),
+            // A structural error means the buffered bytes don't yet form a
+            // complete message (or are not parseable as bencode framing);
+            // either way the reader's recourse is to read more.
+            Ok(None) | Err(_) => Decoded::Incomplete,
+        }
     }
-    pos += 1; // Skip ':'
+}
 
-    let len = std::str::from_utf8(&len_str)
-        .map_err(|_| NReplError::codec("Invalid string length encoding", pos))?
+/// The end of the bencode string (`<length>:<data>`) starting at `start`, or
+/// `None` if its length or data has not all arrived.
+fn string_end(data: &[u8], start: usize) -> Result<Option<usize>> {
+    let Some(colon) = data[start..].iter().position(|&b| b == b':') else {
+        return Ok(None);
+    };
+    let colon = start + colon;
+    let len = std::str::from_utf8(&data[start..colon])
+        .map_err(|_| NReplError::codec("Invalid string length encoding", colon + 1))?
         .parse::<usize>()
-        .map_err(|_| NReplError::codec("Invalid string length value", pos))?;
+        .map_err(|_| NReplError::codec("Invalid string length value", colon + 1))?;
 
     // Check maximum string length to prevent OOM from malicious servers
     if len > MAX_STRING_LENGTH {
@@ -138,48 +304,69 @@ fn find_string_end(data: &[u8], start: usize) -> Result<usize> {
                 MAX_STRING_LENGTH,
                 MAX_STRING_LENGTH / (1024 * 1024)
             ),
-            pos,
+            colon + 1,
         ));
     }
 
-    // Validate length before consuming bytes to prevent:
-    // 1. Integer overflow when adding len to pos
-    // 2. Out-of-bounds access attempts
-    let end_pos = pos.checked_add(len).ok_or_else(|| {
-        NReplError::codec(
-            format!("String length {len} would cause integer overflow at position {pos}"),
-            pos,
-        )
-    })?;
-
-    if end_pos > data.len() {
-        return Err(NReplError::codec_with_preview(
-            format!(
-                "Incomplete string data: claims length {} but only {} bytes available",
-                len,
-                data.len() - pos
-            ),
-            pos,
-            data,
-        ));
-    }
+    // Under MAX_STRING_LENGTH, so this cannot overflow.
+    let end = colon + 1 + len;
+    Ok((end <= data.len()).then_some(end))
+}
 
-    Ok(end_pos)
+/// The length of the complete bencode value at the head of `data`.
+fn value_len(data: &[u8]) -> Option<usize> {
+    FrameScanner::new().scan(data).ok().flatten()
 }
 
 /// Decode a response from bencode data
 /// Returns the response and the number of bytes consumed
+///
+/// # Errors
+///
+/// Returns [`NReplError::Codec`] if `data` does not start with a complete
+/// message or the message is not a valid response.
 pub fn decode_response(data: &[u8]) -> Result<(Response, usize)> {
-    // First find where the message ends
-    let msg_len = find_bencode_end(data, 0)?;
+    let mut scanner = FrameScanner::new();
+    let msg_len = match scanner.scan(data) {
+        Ok(Some(len)) => len,
+        Ok(None) => {
+            return Err(NReplError::codec_with_preview(
+                "Incomplete bencode message",
+                data.len(),
+                data,
+            ));
+        }
+        Err(NReplError::Codec {
+            message, position, ..
+        }) => return Err(NReplError::codec_with_preview(message, position, data)),
+        Err(e) => return Err(e),
+    };
 
     // Decode just that portion
-    let response: Response = serde_bencode::from_bytes(&data[..msg_len])
+    let mut response: Response = serde_bencode::from_bytes(&data[..msg_len])
         .map_err(|e| NReplError::codec_with_preview(e.to_string(), 0, &data[..msg_len]))?;
+    keep_binary(&mut response, &data[..msg_len]);
 
     Ok((response, msg_len))
 }
 
+/// Copy the top-level byte strings of `frame` that are not UTF-8 into
+/// `response.extra`, so the exact bytes of a field read as lossy text
+/// (a binary `body`, say) are not lost. Most frames are all UTF-8, and
+/// cost only the check.
+fn keep_binary(response: &mut Response, frame: &[u8]) {
+    if std::str::from_utf8(frame).is_ok() {
+        return;
+    }
+    if let Some((BencodeValue::Dict(fields), _)) = parse_value(frame, 0) {
+        response.extra.extend(
+            fields
+                .into_iter()
+                .filter(|(_, v)| matches!(v, BencodeValue::Bytes(_))),
+        );
+    }
+}
+
 /// Outcome of attempting to decode a single response from the head of `data`.
 ///
 /// This distinguishes the two failure modes that the streaming reader must treat
@@ -205,53 +392,278 @@ pub enum Decoded {
 
 /// Decode a single response from the head of `data`, classifying the result so
 /// the reader can skip undecodable-but-complete messages instead of looping on
-/// them. See [`Decoded`].
+/// them. See [`Decoded`]. Walks `data` from the start; a reader topping up a
+/// buffer should keep a [`FrameScanner`] instead.
 pub fn decode_one(data: &[u8]) -> Decoded {
-    match find_bencode_end(data, 0) {
-        Ok(consumed) => match serde_bencode::from_bytes::<Response>(&data[..consumed]) {
-            Ok(response) => Decoded::Message {
+    FrameScanner::new().decode(data)
+}
+
+/// Deserialize one complete message.
+fn decode_frame(frame: &[u8]) -> Decoded {
+    let consumed = frame.len();
+    match serde_bencode::from_bytes::<Response>(frame) {
+        Ok(mut response) => {
+            keep_binary(&mut response, frame);
+            Decoded::Message {
+                response: Box::new(response),
+                consumed,
+            }
+        }
+        // Strict decode failed on a *complete* frame - usually because a
+        // non-conforming server sent an unexpected value shape. Before giving
+        // up on the message, try to salvage it with a tolerant value-tree
+        // parse: if we can recover a routable response (one with an `id`), the
+        // op awaiting it completes with whatever the server actually sent
+        // instead of hanging until its timeout. Only when even the lenient
+        // parse can't produce a routable response do we treat it as Malformed
+        // and skip it.
+        Err(e) => match parse_value(frame, 0)
+            .map(|(value, _)| value)
+            .and_then(response_from_bencode)
+        {
+            Some(response) => Decoded::Message {
                 response: Box::new(response),
                 consumed,
             },
-            // Strict decode failed on a *complete* frame - usually because a
-            // non-conforming server sent an unexpected value shape. Before giving
-            // up on the message, try to salvage it with a tolerant value-tree
-            // parse: if we can recover a routable response (one with an `id`), the
-            // op awaiting it completes with whatever the server actually sent
-            // instead of hanging until its timeout. Only when even the lenient
-            // parse can't produce a routable response do we treat it as Malformed
-            // and skip it.
-            Err(e) => match parse_value(&data[..consumed], 0)
-                .map(|(value, _)| value)
-                .and_then(response_from_bencode)
-            {
-                Some(response) => Decoded::Message {
-                    response: Box::new(response),
-                    consumed,
-                },
-                None => Decoded::Malformed {
-                    consumed,
-                    message: e.to_string(),
-                },
+            None => Decoded::Malformed {
+                consumed,
+                message: e.to_string(),
             },
         },
-        // A structural error means the buffered bytes don't yet form a complete
-        // message (or are not parseable as bencode framing); either way the
-        // reader's recourse is to read more, so report Incomplete.
-        Err(_) => Decoded::Incomplete,
     }
 }
 
+/// A complete response frame with only its routing keys decoded.
+///
+/// `decode_one` deserializes every field of every message, but a reader
+/// mostly needs `id` and `status` to decide where a response goes, and many
+/// responses (stragglers for timed-out requests, heartbeat replies) go
+/// nowhere. [`LazyResponse::new`] reads `id`, `session` and `status` by
+/// walking the frame's top-level keys and skipping the other values
+/// unparsed; [`decode`](Self::decode) deserializes the rest, with the same
+/// strict-then-salvage rules as [`decode_one`], once a caller wants it.
+#[derive(Debug, Clone)]
+pub struct LazyResponse {
+    id: String,
+    session: Option<String>,
+    status: Vec<String>,
+    body: LazyBody,
+}
+
+#[derive(Debug, Clone)]
+enum LazyBody {
+    /// The frame, still to be deserialized.
+    Frame(Bytes),
+    /// Deserialized up front, because the routing keys could not be read
+    /// on their own.
+    Decoded(Box<Response>),
+}
+
+impl LazyResponse {
+    /// Read the routing keys of the complete message `frame`. A frame whose
+    /// `id` is not a top-level string is decoded in full straight away, so
+    /// the salvage path gets its chance to route it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NReplError::Codec`] if the frame cannot be routed at all:
+    /// the same frames [`decode_one`] reports as [`Decoded::Malformed`].
+    pub fn new(frame: Bytes) -> Result<Self> {
+        if let Some((id, session, status)) = routing_keys(&frame) {
+            return Ok(Self {
+                id,
+                session,
+                status,
+                body: LazyBody::Frame(frame),
+            });
+        }
+        let response = frame_to_response(&frame)?;
+        Ok(Self {
+            id: response.id.clone(),
+            session: (!response.session.is_empty()).then(|| response.session.clone()),
+            status: response.status.clone(),
+            body: LazyBody::Decoded(Box::new(response)),
+        })
+    }
+
+    /// The request id the response answers.
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The session the response belongs to, if it names one.
+    #[must_use]
+    pub fn session(&self) -> Option<&str> {
+        self.session.as_deref()
+    }
+
+    /// The response's status flags, empty for an intermediate message.
+    #[must_use]
+    pub fn status(&self) -> &[String] {
+        &self.status
+    }
+
+    /// The top-level string field `key`, read from the frame without
+    /// deserializing the rest. `None` if it is missing or not a string, or
+    /// the frame had to be decoded up front.
+    #[must_use]
+    pub fn field(&self, key: &str) -> Option<String> {
+        match &self.body {
+            LazyBody::Frame(frame) => string_field(frame, key.as_bytes()),
+            LazyBody::Decoded(_) => None,
+        }
+    }
+
+    /// Deserialize the whole response.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NReplError::Codec`] if the frame's routing keys read cleanly
+    /// but the rest of it cannot be made into a response.
+    pub fn decode(self) -> Result<Response> {
+        match self.body {
+            LazyBody::Frame(frame) => frame_to_response(&frame),
+            LazyBody::Decoded(response) => Ok(*response),
+        }
+    }
+}
+
+/// Deserialize one complete message, or say why it cannot be routed.
+fn frame_to_response(frame: &[u8]) -> Result<Response> {
+    match decode_frame(frame) {
+        Decoded::Message { response, .. } => Ok(*response),
+        Decoded::Malformed { message, .. } => Err(NReplError::codec(message, 0)),
+        Decoded::Incomplete => Err(NReplError::codec("Incomplete bencode message", frame.len())),
+    }
+}
+
+/// The `id`, `session` and `status` of a framed response dict, or `None` if
+/// it is not a dict that walks cleanly with a string `id`. Only those three
+/// values are parsed; every other one is stepped over by its framing.
+fn routing_keys(frame: &[u8]) -> Option<(String, Option<String>, Vec<String>)> {
+    if frame.first() != Some(&b'd') {
+        return None;
+    }
+    let (mut id, mut session, mut status) = (None, None, Vec::new());
+    let mut pos = 1;
+    while pos < frame.len() && frame[pos] != b'e' {
+        if !frame[pos].is_ascii_digit() {
+            return None;
+        }
+        let key_len = string_len(frame, pos)?;
+        let key_end = pos + value_len(&frame[pos..])?;
+        let key = &frame[key_end - key_len..key_end];
+        pos = key_end;
+        // A dangling key closes the dict (see FrameScanner::scan).
+        if pos >= frame.len() || frame[pos] == b'e' {
+            break;
+        }
+        let end = pos + value_len(&frame[pos..])?;
+        // Parsed the way `response_from_bencode` salvages them, so a frame
+        // routes the same whether or not strict decoding later accepts it.
+        match key {
+            b"id" => match parse_value(frame, pos)? {
+                (BencodeValue::String(s), _) => id = Some(s),
+                _ => return None,
+            },
+            b"session" => session = Some(parse_value(frame, pos)?.0.to_string_repr()),
+            b"status" => {
+                if let (BencodeValue::List(items), _) = parse_value(frame, pos)? {
+                    status = items.into_iter().map(|v| v.to_string_repr()).collect();
+                }
+            }
+            _ => {}
+        }
+        pos = end;
+    }
+    Some((id?, session.filter(|s| !s.is_empty()), status))
+}
+
+/// The top-level string `key` of a framed response dict. Every other value
+/// is stepped over by its framing.
+fn string_field(frame: &[u8], key: &[u8]) -> Option<String> {
+    if frame.first() != Some(&b'd') {
+        return None;
+    }
+    let mut pos = 1;
+    while pos < frame.len() && frame[pos] != b'e' {
+        if !frame[pos].is_ascii_digit() {
+            return None;
+        }
+        let key_len = string_len(frame, pos)?;
+        let key_end = pos + value_len(&frame[pos..])?;
+        let found = &frame[key_end - key_len..key_end] == key;
+        pos = key_end;
+        if pos >= frame.len() || frame[pos] == b'e' {
+            return None;
+        }
+        if found {
+            return match parse_value(frame, pos)? {
+                (BencodeValue::String(s), _) => Some(s),
+                _ => None,
+            };
+        }
+        pos += value_len(&frame[pos..])?;
+    }
+    None
+}
+
+/// Sizes of the top-level string fields in a framed response dict that exceed
+/// `threshold` bytes, as `(key, size)` pairs in wire order.
+///
+/// Reads only the length prefixes, never the string bodies, so it is cheap to
+/// run on every frame. Nested values (lists, dicts) are skipped: a response's
+/// bulk lives in top-level fields like `value`, `out` and `err`. Anything that
+/// does not walk cleanly returns what was found so far.
+pub(crate) fn large_string_fields(frame: &[u8], threshold: usize) -> Vec<(String, usize)> {
+    let mut found = Vec::new();
+    if frame.first() != Some(&b'd') {
+        return found;
+    }
+    let mut pos = 1;
+    while pos < frame.len() && frame[pos] != b'e' {
+        let (Some(key_len), Some(key_end)) = (
+            string_len(frame, pos),
+            value_len(&frame[pos..]).map(|len| pos + len),
+        ) else {
+            break;
+        };
+        let key = String::from_utf8_lossy(&frame[key_end - key_len..key_end]).into_owned();
+        pos = key_end;
+        if pos >= frame.len() || frame[pos] == b'e' {
+            break;
+        }
+        if frame[pos].is_ascii_digit()
+            && let Some(len) = string_len(frame, pos)
+            && len > threshold
+        {
+            found.push((key, len));
+        }
+        let Some(value_len) = value_len(&frame[pos..]) else {
+            break;
+        };
+        pos += value_len;
+    }
+    found
+}
+
+/// The declared length of the bencode string starting at `start`.
+fn string_len(data: &[u8], start: usize) -> Option<usize> {
+    let colon = start + data[start..].iter().position(|&b| b == b':')?;
+    std::str::from_utf8(&data[start..colon]).ok()?.parse().ok()
+}
+
 /// Tolerant recursive bencode parser producing a [`BencodeValue`] tree and the
 /// end offset of the parsed value.
 ///
 /// Unlike `serde_bencode`, this never rejects a message for a *type* reason: it
 /// is used as the salvage path in [`decode_one`] for frames that strict decoding
-/// refused. It mirrors the dangling-key tolerance of [`find_bencode_end`] so it
+/// refused. It mirrors the dangling-key tolerance of [`FrameScanner::scan`] so it
 /// can walk past the same non-conforming dicts. `None` means the bytes ran out
 /// mid-value (which should not happen on an already-framed slice, but is handled
 /// defensively rather than panicking).
-fn parse_value(data: &[u8], start: usize) -> Option<(BencodeValue, usize)> {
+pub(crate) fn parse_value(data: &[u8], start: usize) -> Option<(BencodeValue, usize)> {
     let first = *data.get(start)?;
     match first {
         b'i' => {
@@ -290,7 +702,7 @@ fn parse_value(data: &[u8], start: usize) -> Option<(BencodeValue, usize)> {
             while pos < data.len() && data[pos] != b'e' {
                 let (key, after_key) = parse_value(data, pos)?;
                 pos = after_key;
-                // Dangling key with no value (see find_bencode_end): close the dict.
+                // Dangling key with no value (see FrameScanner::scan): close the dict.
                 if pos >= data.len() || data[pos] == b'e' {
                     break;
                 }
@@ -324,8 +736,8 @@ fn parse_value(data: &[u8], start: usize) -> Option<(BencodeValue, usize)> {
             if data_end > data.len() {
                 return None;
             }
-            let s = String::from_utf8_lossy(&data[data_start..data_end]).into_owned();
-            Some((BencodeValue::String(s), data_end))
+            let bytes = data[data_start..data_end].to_vec();
+            Some((BencodeValue::from_bytes(bytes), data_end))
         }
         _ => None,
     }
@@ -334,6 +746,7 @@ fn parse_value(data: &[u8], start: usize) -> Option<(BencodeValue, usize)> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_encode_simple_request() {
@@ -358,6 +771,31 @@ mod tests {
             lookup_fn: None,
             middleware: None,
             extra_namespaces: None,
+            tests: None,
+            load: None,
+            var: None,
+            index: None,
+            query: None,
+            filter_regex: None,
+            spec_name: None,
+            docs: None,
+            privates: None,
+            edn: None,
+            print_right_margin: None,
+            inspect: None,
+            idx: None,
+            page_size: None,
+            dirs: None,
+            before: None,
+            after: None,
+            kind: None,
+            name: None,
+            content: None,
+            print_quota: None,
+            content_type: None,
+            key: None,
+            input: None,
+            tags: BTreeMap::new(),
         };
 
         let encoded = encode_request(&request).expect("encoding failed");
@@ -374,7 +812,7 @@ mod tests {
             op: "eval".to_string(),
             id: "msg-123".to_string(),
             session: Some("session-456".to_string()),
-            code: Some("(+ 1 2)".to_string()),
+            code: Some("(+ 1 2)".into()),
             line: None,
             column: None,
             file: None,
@@ -391,6 +829,31 @@ mod tests {
             lookup_fn: None,
             middleware: None,
             extra_namespaces: None,
+            tests: None,
+            load: None,
+            var: None,
+            index: None,
+            query: None,
+            filter_regex: None,
+            spec_name: None,
+            docs: None,
+            privates: None,
+            edn: None,
+            print_right_margin: None,
+            inspect: None,
+            idx: None,
+            page_size: None,
+            dirs: None,
+            before: None,
+            after: None,
+            kind: None,
+            name: None,
+            content: None,
+            print_quota: None,
+            content_type: None,
+            key: None,
+            input: None,
+            tags: BTreeMap::new(),
         };
 
         let encoded = encode_request(&request).expect("encoding failed");
@@ -435,7 +898,7 @@ mod tests {
             op: "eval".to_string(),
             id: "test-id".to_string(),
             session: Some("test-session".to_string()),
-            code: Some("(println \"hello\")".to_string()),
+            code: Some("(println \"hello\")".into()),
             line: None,
             column: None,
             file: None,
@@ -452,6 +915,31 @@ mod tests {
             lookup_fn: None,
             middleware: None,
             extra_namespaces: None,
+            tests: None,
+            load: None,
+            var: None,
+            index: None,
+            query: None,
+            filter_regex: None,
+            spec_name: None,
+            docs: None,
+            privates: None,
+            edn: None,
+            print_right_margin: None,
+            inspect: None,
+            idx: None,
+            page_size: None,
+            dirs: None,
+            before: None,
+            after: None,
+            kind: None,
+            name: None,
+            content: None,
+            print_quota: None,
+            content_type: None,
+            key: None,
+            input: None,
+            tags: BTreeMap::new(),
         };
 
         let encoded = encode_request(&request).expect("encoding failed");
@@ -496,7 +984,7 @@ mod tests {
             op: "eval".to_string(),
             id: "req-1".to_string(),
             session: Some("s1".to_string()),
-            code: Some("(+ 1 2)".to_string()),
+            code: Some("(+ 1 2)".into()),
             line: None,
             column: None,
             file: None,
@@ -513,6 +1001,31 @@ mod tests {
             lookup_fn: None,
             middleware: None,
             extra_namespaces: None,
+            tests: None,
+            load: None,
+            var: None,
+            index: None,
+            query: None,
+            filter_regex: None,
+            spec_name: None,
+            docs: None,
+            privates: None,
+            edn: None,
+            print_right_margin: None,
+            inspect: None,
+            idx: None,
+            page_size: None,
+            dirs: None,
+            before: None,
+            after: None,
+            kind: None,
+            name: None,
+            content: None,
+            print_quota: None,
+            content_type: None,
+            key: None,
+            input: None,
+            tags: BTreeMap::new(),
         };
 
         let encoded = encode_request(&request).expect("encoding failed");
@@ -568,6 +1081,99 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_frame_scanner_resumes_across_reads() {
+        // Two messages, the first with a nested list and a dangling key.
+        let first: &[u8] = b"d2:id5:msg-13:outl3:one3:twoe6:sourcee";
+        let stream = [first, b"d2:id5:msg-26:statusl4:doneee"].concat();
+
+        // Fed a byte at a time, the scanner only ever sees the new byte.
+        let mut scanner = FrameScanner::new();
+        let mut ends = Vec::new();
+        let mut head = 0;
+        for fed in 1..=stream.len() {
+            if let Some(len) = scanner.scan(&stream[head..fed]).unwrap() {
+                head += len;
+                ends.push(head);
+            }
+        }
+        assert_eq!(ends, [first.len(), stream.len()]);
+
+        match scanner.decode(&stream[first.len()..]) {
+            Decoded::Message { response, .. } => assert_eq!(response.id, "msg-2"),
+            _ => panic!("expected Message"),
+        }
+    }
+
+    #[test]
+    fn test_frame_scanner_rejects_bad_framing_and_survives_deep_nesting() {
+        let mut scanner = FrameScanner::new();
+        assert!(scanner.scan(b"d2:id").unwrap().is_none());
+        assert!(scanner.scan(b"d2:idx").is_err());
+        // Still stuck on the same byte.
+        assert!(scanner.scan(b"d2:idx").is_err());
+        scanner.reset();
+        assert!(scanner.scan(b"99999999999:x").is_err());
+
+        // Framing keeps a count, not a call stack.
+        let deep = [vec![b'l'; 100_000], vec![b'e'; 100_000]].concat();
+        assert_eq!(FrameScanner::new().scan(&deep).unwrap(), Some(deep.len()));
+    }
+
+    #[test]
+    fn test_lazy_response_reads_routing_keys_and_decodes_on_demand() {
+        let frame =
+            Bytes::from_static(b"d2:id5:req-17:session2:s16:statusl4:donee5:valued1:ai1eee");
+        let lazy = LazyResponse::new(frame).unwrap();
+        assert_eq!(lazy.id(), "req-1");
+        assert_eq!(lazy.session(), Some("s1"));
+        assert_eq!(lazy.status(), ["done"]);
+        // `value` is a dict, which strict decoding refuses; decoding salvages
+        // the message under the same id.
+        let response = lazy.decode().unwrap();
+        assert_eq!(response.id, "req-1");
+        assert_eq!(response.status, ["done"]);
+
+        // No session, no status: an intermediate message.
+        let lazy = LazyResponse::new(Bytes::from_static(b"d2:id1:73:out2:hie")).unwrap();
+        assert_eq!(lazy.session(), None);
+        assert!(lazy.status().is_empty());
+        assert_eq!(lazy.decode().unwrap().out.as_deref(), Some("hi"));
+
+        // Without a string id there is nothing to route.
+        assert!(LazyResponse::new(Bytes::from_static(b"d2:idi7e6:statusl4:doneee")).is_err());
+        assert!(LazyResponse::new(Bytes::from_static(b"l2:ide")).is_err());
+    }
+
+    #[test]
+    fn test_lazy_response_reads_one_field() {
+        let frame = Bytes::from_static(b"d2:id1:73:tagl1:xe4:undo2:u35:value1:3e");
+        let lazy = LazyResponse::new(frame).unwrap();
+        assert_eq!(lazy.field("undo").as_deref(), Some("u3"));
+        assert_eq!(lazy.field("value").as_deref(), Some("3"));
+        // Not a string, and not there.
+        assert_eq!(lazy.field("tag"), None);
+        assert_eq!(lazy.field("panel"), None);
+    }
+
+    #[test]
+    fn test_large_string_fields_reports_only_oversized_top_level_strings() {
+        let value = "x".repeat(64);
+        let frame = format!(
+            "d2:id5:req-12:nsl{}:{}e5:value{}:{}e",
+            value.len(),
+            value,
+            value.len(),
+            value
+        );
+
+        let found = large_string_fields(frame.as_bytes(), 32);
+        assert_eq!(found, vec![("value".to_string(), 64)]);
+
+        assert!(large_string_fields(frame.as_bytes(), 64).is_empty());
+        assert!(large_string_fields(b"i42e", 0).is_empty());
+    }
+
     #[test]
     fn test_decode_multiple_messages() {
         // Two messages concatenated
@@ -662,4 +1268,154 @@ mod tests {
             _ => panic!("expected Message for the ex/done frame"),
         }
     }
+
+    #[test]
+    fn test_serde_round_trip_through_bencode() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Job {
+            name: String,
+            tries: i64,
+            tags: Vec<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            owner: Option<String>,
+        }
+
+        let job = Job {
+            name: "build".to_string(),
+            tries: -3,
+            tags: vec!["a".to_string(), "b".to_string()],
+            owner: None,
+        };
+        let bytes = to_bytes(&job).unwrap();
+        assert_eq!(bytes, b"d4:name5:build4:tagsl1:a1:be5:triesi-3ee");
+        assert_eq!(from_bytes::<Job>(&bytes).unwrap(), job);
+
+        let mut trailing = bytes.clone();
+        trailing.extend_from_slice(b"i1e");
+        assert!(from_bytes::<Job>(&trailing).is_err());
+        assert!(from_bytes::<Job>(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_bencode_value_converts_requests_and_responses() {
+        let request = Request {
+            op: "eval".to_string(),
+            id: "7".to_string(),
+            code: Some("(+ 1 2)".into()),
+            line: Some(4),
+            ..Request::default()
+        };
+        let value = BencodeValue::try_from(&request).unwrap();
+        let BencodeValue::Dict(fields) = &value else {
+            panic!("request is not a dict: {value:?}");
+        };
+        assert_eq!(fields.get("line"), Some(&BencodeValue::Int(4)));
+        assert_eq!(value.encode(), encode_request(&request).unwrap());
+        let back = Request::try_from(value).unwrap();
+        assert_eq!((back.op.as_str(), back.id.as_str()), ("eval", "7"));
+
+        let (value, consumed) = decode_value(b"d2:id1:75:valuei3e6:statusl4:doneeeXX").unwrap();
+        assert_eq!(consumed, 35);
+        let response = Response::try_from(value).unwrap();
+        assert_eq!(response.value.as_deref(), Some("3"));
+        assert_eq!(response.status, ["done"]);
+
+        let no_id = BencodeValue::Dict(std::collections::BTreeMap::new());
+        assert!(Response::try_from(no_id).is_err());
+    }
+
+    #[test]
+    fn test_binary_byte_strings_survive_decoding() {
+        let png = b"\x89PNG\r\n\x1a\n\xff\x00";
+        let mut frame = b"d4:body10:".to_vec();
+        frame.extend_from_slice(png);
+        frame.extend_from_slice(b"12:content-typel9:image/pngdee2:id1:73:out2:\xc3(");
+        frame.extend_from_slice(b"4:rawsl2:ok1:\xfee6:statusl4:doneee");
+
+        match decode_one(&frame) {
+            Decoded::Message { response, consumed } => {
+                assert_eq!(consumed, frame.len());
+                assert_eq!(response.id, "7");
+                assert_eq!(response.status, ["done"]);
+                // Read as text, lossily; kept whole in `extra`.
+                assert_eq!(response.out.as_deref(), Some("\u{fffd}("));
+                assert!(response.body.as_deref().unwrap().starts_with('\u{fffd}'));
+                assert_eq!(
+                    response.extra.get("body").and_then(BencodeValue::as_bytes),
+                    Some(&png[..])
+                );
+                assert_eq!(
+                    response.extra.get("out"),
+                    Some(&BencodeValue::Bytes(b"\xc3(".to_vec()))
+                );
+                assert_eq!(
+                    response.extra.get("raws"),
+                    Some(&BencodeValue::List(vec![
+                        BencodeValue::String("ok".to_string()),
+                        BencodeValue::Bytes(vec![0xfe]),
+                    ]))
+                );
+            }
+            Decoded::Malformed { message, .. } => panic!("binary frame refused: {message}"),
+            Decoded::Incomplete => panic!("binary frame not framed"),
+        }
+
+        let (value, _) = decode_value(&frame).unwrap();
+        assert_eq!(value.encode(), frame);
+        let BencodeValue::Dict(fields) = &value else {
+            panic!("not a dict: {value:?}");
+        };
+        assert_eq!(
+            fields["out"].to_string_lossy().as_deref(),
+            Some("\u{fffd}(")
+        );
+        assert_eq!(fields["out"].as_str(), None);
+        assert_eq!(from_bytes::<BencodeValue>(&frame).unwrap(), value);
+    }
+
+    #[test]
+    fn test_request_integers_and_flags_round_trip() {
+        let request = Request {
+            op: "eval".to_string(),
+            id: "9".to_string(),
+            code: Some("(range)".into()),
+            line: Some(12),
+            column: Some(-1),
+            print_quota: Some(4096),
+            content_type: Some(true),
+            load: Some(false),
+            ..Request::default()
+        };
+        let encoded = encode_request(&request).unwrap();
+        let (value, _) = decode_value(&encoded).unwrap();
+        let BencodeValue::Dict(fields) = &value else {
+            panic!("request is not a dict: {value:?}");
+        };
+        assert_eq!(fields["line"], BencodeValue::Int(12));
+        assert_eq!(fields["column"], BencodeValue::Int(-1));
+        assert_eq!(
+            fields["nrepl.middleware.print/quota"],
+            BencodeValue::Int(4096)
+        );
+        assert_eq!(fields["content-type"], BencodeValue::Int(1));
+        // `false` would read as true on the server: it is left out.
+        assert!(!fields.contains_key("load?"));
+
+        let back = Request::try_from(value).unwrap();
+        assert_eq!((back.line, back.column), (Some(12), Some(-1)));
+        assert_eq!(back.print_quota, Some(4096));
+        assert_eq!((back.content_type, back.load), (Some(true), None));
+        assert_eq!(encode_request(&back).unwrap(), encoded);
+
+        let describe = crate::ops::describe_request("1", Some(true));
+        let encoded = String::from_utf8(encode_request(&describe).unwrap()).unwrap();
+        assert!(encoded.contains("8:verbose?i1e"), "{encoded}");
+        let quiet = crate::ops::describe_request("1", Some(false));
+        assert_eq!(encode_request(&quiet).unwrap(), b"d2:id1:12:op8:describee");
+
+        // Flags written as strings, as some clients send them.
+        let request: Request =
+            from_bytes(b"d2:id1:12:op7:apropos5:docs?4:true9:privates?5:falsee").unwrap();
+        assert_eq!((request.docs, request.privates), (Some(true), Some(false)));
+    }
 }
@@ -0,0 +1,395 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! A check of how a server answers the ops this client relies on
+//!
+//! [`NReplClient::run_conformance_check`] puts each core op to the server
+//! once and reports, per op, whether the answer had the shape the client
+//! expects and how long it took. The report prints as a short table, meant
+//! to be pasted into a bug report, or read before relying on a server
+//! setup.
+
+use crate::blocking::NReplClient;
+use crate::flavor::{ServerFlavor, ServerProfile};
+use crate::worker::WorkerCommand;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long each eval of the check may take.
+const EVAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the form the interrupt check stops would run if left alone.
+const SLEEP_SECS: u64 = 3;
+
+/// How the server did on one op.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckOutcome {
+    /// Answered as the client expects.
+    Passed,
+    /// `describe` does not list the op.
+    Unsupported,
+    /// Not tried, for the reason given.
+    Skipped(String),
+    /// Answered, but not as the client expects.
+    Deviation(String),
+    /// Did not answer: an error or a timeout.
+    Failed(String),
+}
+
+impl CheckOutcome {
+    fn label(&self) -> &'static str {
+        match self {
+            CheckOutcome::Passed => "passed",
+            CheckOutcome::Unsupported => "unsupported",
+            CheckOutcome::Skipped(_) => "skipped",
+            CheckOutcome::Deviation(_) => "deviation",
+            CheckOutcome::Failed(_) => "failed",
+        }
+    }
+
+    fn detail(&self) -> Option<&str> {
+        match self {
+            CheckOutcome::Passed | CheckOutcome::Unsupported => None,
+            CheckOutcome::Skipped(why)
+            | CheckOutcome::Deviation(why)
+            | CheckOutcome::Failed(why) => Some(why),
+        }
+    }
+}
+
+/// One op's result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceCheck {
+    /// What was checked: an op name, or `eval-output` and the like for a
+    /// particular side of one.
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+    /// Time from sending the request to its answer; zero if none was sent.
+    pub latency: Duration,
+}
+
+/// What [`NReplClient::run_conformance_check`] found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceReport {
+    pub flavor: ServerFlavor,
+    /// Each component `describe` named (`nrepl`, `clojure`, `babashka`...)
+    /// and its `version-string`.
+    pub versions: BTreeMap<String, String>,
+    /// In the order they ran.
+    pub checks: Vec<ConformanceCheck>,
+}
+
+impl ConformanceReport {
+    /// Whether no check failed or deviated. An op the server does not
+    /// offer does not count against it.
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| {
+            !matches!(
+                c.outcome,
+                CheckOutcome::Deviation(_) | CheckOutcome::Failed(_)
+            )
+        })
+    }
+
+    /// The check called `name`, if it ran.
+    #[must_use]
+    pub fn check(&self, name: &str) -> Option<&ConformanceCheck> {
+        self.checks.iter().find(|c| c.name == name)
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "server: {}", self.flavor.as_str())?;
+        let versions: Vec<String> = self
+            .versions
+            .iter()
+            .map(|(name, version)| format!("{name} {version}"))
+            .collect();
+        if !versions.is_empty() {
+            write!(f, " ({})", versions.join(", "))?;
+        }
+        writeln!(f)?;
+        for check in &self.checks {
+            write!(
+                f,
+                "{:<16}{:<13}{:>8}ms",
+                check.name,
+                check.outcome.label(),
+                check.latency.as_millis()
+            )?;
+            if let Some(detail) = check.outcome.detail() {
+                write!(f, "  {detail}")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl NReplClient {
+    /// Put each core op (`describe`, `clone`, `eval`, `interrupt`,
+    /// `lookup`, `completions`) to the server once, and report how each
+    /// answered and how long it took. Evals run in this client's session;
+    /// the clone check opens a session of its own and closes it again.
+    ///
+    /// The interrupt check evaluates a form that sleeps for a few seconds
+    /// and interrupts it, so the whole check takes a little while against
+    /// a server that cannot interrupt.
+    pub fn run_conformance_check(&mut self) -> ConformanceReport {
+        let mut checks = Vec::new();
+
+        let started = Instant::now();
+        let describe = self.describe(false);
+        let latency = started.elapsed();
+        let (profile, versions, outcome) = match describe {
+            Ok(response) => {
+                let profile = ServerProfile::from_describe(&response);
+                let versions: BTreeMap<String, String> = response
+                    .versions
+                    .iter()
+                    .flatten()
+                    .filter_map(|(name, fields)| {
+                        Some((name.clone(), fields.get("version-string")?.clone()))
+                    })
+                    .collect();
+                let outcome = if response.ops.as_ref().is_none_or(BTreeMap::is_empty) {
+                    CheckOutcome::Deviation("describe listed no ops".to_string())
+                } else {
+                    CheckOutcome::Passed
+                };
+                (Some(profile), versions, outcome)
+            }
+            Err(e) => (None, BTreeMap::new(), CheckOutcome::Failed(e.to_string())),
+        };
+        checks.push(ConformanceCheck {
+            name: "describe",
+            outcome,
+            latency,
+        });
+        let supports = |op: &str| profile.as_ref().is_none_or(|p| p.supports(op));
+        let flavor = profile.as_ref().map_or(ServerFlavor::Unknown, |p| p.flavor);
+
+        checks.push(self.check_clone());
+        checks.extend(self.check_eval());
+        checks.push(if supports("interrupt") {
+            self.check_interrupt(flavor)
+        } else {
+            unsupported("interrupt")
+        });
+        checks.push(if supports("lookup") {
+            self.check_lookup(flavor)
+        } else {
+            unsupported("lookup")
+        });
+        checks.push(if supports("completions") {
+            self.check_completions(flavor)
+        } else {
+            unsupported("completions")
+        });
+
+        ConformanceReport {
+            flavor,
+            versions,
+            checks,
+        }
+    }
+
+    /// Clone a session and close it again.
+    fn check_clone(&mut self) -> ConformanceCheck {
+        let worker = self.worker();
+        let (reply, replies) = channel();
+        let started = Instant::now();
+        let sent = worker.command_sender().send(WorkerCommand::CloneSession {
+            op_id: worker.next_id(),
+            reply,
+        });
+        let cloned = match sent {
+            Ok(()) => replies
+                .recv_timeout(EVAL_TIMEOUT)
+                .map_err(|_| "no answer".to_string())
+                .and_then(|r| r.map_err(|e| e.to_string())),
+            Err(_) => Err("worker gone".to_string()),
+        };
+        let latency = started.elapsed();
+        let outcome = match cloned {
+            Ok(session) if session.id().is_empty() => {
+                CheckOutcome::Deviation("clone gave no new-session".to_string())
+            }
+            Ok(session) => {
+                let (reply, _) = channel();
+                let _ = worker.command_sender().send(WorkerCommand::CloseSession {
+                    op_id: worker.next_id(),
+                    session,
+                    reply,
+                });
+                CheckOutcome::Passed
+            }
+            Err(e) => CheckOutcome::Failed(e),
+        };
+        ConformanceCheck {
+            name: "clone",
+            outcome,
+            latency,
+        }
+    }
+
+    /// An eval's value and namespace, then its output.
+    fn check_eval(&mut self) -> [ConformanceCheck; 2] {
+        let started = Instant::now();
+        let result = self.eval_with_timeout("(+ 1 2)", EVAL_TIMEOUT);
+        let latency = started.elapsed();
+        let outcome = match result {
+            Ok(r) if r.value.as_deref() != Some("3") => {
+                CheckOutcome::Deviation(format!("(+ 1 2) gave {:?}", r.value))
+            }
+            Ok(r) if r.ns.is_none() => {
+                CheckOutcome::Deviation("eval reply carried no ns".to_string())
+            }
+            Ok(_) => CheckOutcome::Passed,
+            Err(e) => CheckOutcome::Failed(e.to_string()),
+        };
+        let value = ConformanceCheck {
+            name: "eval",
+            outcome,
+            latency,
+        };
+
+        let started = Instant::now();
+        let result = self.eval_with_timeout("(print \"ok\")", EVAL_TIMEOUT);
+        let latency = started.elapsed();
+        let outcome = match result {
+            Ok(r) if r.output.concat() == "ok" => CheckOutcome::Passed,
+            Ok(r) => CheckOutcome::Deviation(format!("output was {:?}", r.output.concat())),
+            Err(e) => CheckOutcome::Failed(e.to_string()),
+        };
+        let output = ConformanceCheck {
+            name: "eval-output",
+            outcome,
+            latency,
+        };
+        [value, output]
+    }
+
+    /// Interrupt a sleeping eval: it should end `interrupted` well before
+    /// the sleep is up.
+    fn check_interrupt(&mut self, flavor: ServerFlavor) -> ConformanceCheck {
+        let code = match flavor {
+            ServerFlavor::Clojure | ServerFlavor::Babashka => {
+                format!("(Thread/sleep {})", SLEEP_SECS * 1000)
+            }
+            ServerFlavor::Python => format!("(do (import time) (time/sleep {SLEEP_SECS}))"),
+            ServerFlavor::Nbb | ServerFlavor::Unknown => {
+                return ConformanceCheck {
+                    name: "interrupt",
+                    outcome: CheckOutcome::Skipped(format!(
+                        "no blocking sleep known for {}",
+                        flavor.as_str()
+                    )),
+                    latency: Duration::ZERO,
+                };
+            }
+        };
+        let session = self.session().clone();
+        let worker = self.worker();
+        let Ok(target) = worker.submit_eval(session.clone(), code, None, None, None, None) else {
+            return failed("interrupt", "worker gone");
+        };
+        // Give the eval time to reach the server and start sleeping.
+        thread::sleep(Duration::from_millis(200));
+        let (reply, replies) = channel();
+        let started = Instant::now();
+        let _ = worker.command_sender().send(WorkerCommand::Interrupt {
+            op_id: worker.next_id(),
+            session,
+            target,
+            reply,
+        });
+        let acknowledged = replies
+            .recv_timeout(EVAL_TIMEOUT)
+            .map_err(|_| "no answer".to_string())
+            .and_then(|r| r.map_err(|e| e.to_string()));
+        let result = self
+            .wait(target, "interrupt", EVAL_TIMEOUT)
+            .map_err(|e| e.to_string());
+        let latency = started.elapsed();
+        let outcome = match (acknowledged, result) {
+            (Err(e), _) | (_, Err(e)) => CheckOutcome::Failed(e),
+            (Ok(()), Ok(r)) if r.interrupted => CheckOutcome::Passed,
+            (Ok(()), Ok(_)) => CheckOutcome::Deviation("the eval ran on to completion".to_string()),
+        };
+        ConformanceCheck {
+            name: "interrupt",
+            outcome,
+            latency,
+        }
+    }
+
+    /// Look up `map`, which every dialect has.
+    fn check_lookup(&mut self, flavor: ServerFlavor) -> ConformanceCheck {
+        let started = Instant::now();
+        let result = self.lookup("map", flavor.default_ns());
+        let latency = started.elapsed();
+        let outcome = match result {
+            Ok(r) if r.info.as_ref().is_some_and(|info| !info.is_empty()) => CheckOutcome::Passed,
+            Ok(_) => CheckOutcome::Deviation("no info for map".to_string()),
+            Err(e) => CheckOutcome::Failed(e.to_string()),
+        };
+        ConformanceCheck {
+            name: "lookup",
+            outcome,
+            latency,
+        }
+    }
+
+    /// Complete `ma`, which should offer `map`.
+    fn check_completions(&mut self, flavor: ServerFlavor) -> ConformanceCheck {
+        let started = Instant::now();
+        let result = self.completions("ma", flavor.default_ns());
+        let latency = started.elapsed();
+        let outcome = match result {
+            Ok(candidates) if candidates.iter().any(|c| c.candidate == "map") => {
+                CheckOutcome::Passed
+            }
+            Ok(candidates) => CheckOutcome::Deviation(format!(
+                "{} candidates for ma, map not among them",
+                candidates.len()
+            )),
+            Err(e) => CheckOutcome::Failed(e.to_string()),
+        };
+        ConformanceCheck {
+            name: "completions",
+            outcome,
+            latency,
+        }
+    }
+}
+
+fn unsupported(name: &'static str) -> ConformanceCheck {
+    ConformanceCheck {
+        name,
+        outcome: CheckOutcome::Unsupported,
+        latency: Duration::ZERO,
+    }
+}
+
+fn failed(name: &'static str, why: &str) -> ConformanceCheck {
+    ConformanceCheck {
+        name,
+        outcome: CheckOutcome::Failed(why.to_string()),
+        latency: Duration::ZERO,
+    }
+}
@@ -11,47 +11,78 @@
 // GNU Affero General Public License for more details.
 
 /// nREPL client connection and operations
-use crate::codec::{Decoded, decode_one, encode_request};
+use crate::ansi::{AnsiFilter, AnsiPolicy, StyleSpan};
+use crate::capture::{Direction, FrameCapture};
+use crate::codec::{FrameScanner, LazyResponse, encode_request, large_string_fields, to_bytes};
 use crate::error::{NReplError, Result};
 use crate::message::classify;
-use crate::message::{EvalResult, Request, Response};
-use std::sync::OnceLock;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
-use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use crate::message::{
+    BencodeValue, EvalResult, EvalTiming, Request, Response, ServerOutput, SpilledOutput,
+    TruncatedValue,
+};
+use crate::metrics::ClientMetrics;
+use crate::rich_content::RichContent;
+use crate::session::SessionTable;
+use crate::trace::{self, event};
+use bytes::BytesMut;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{IoSlice, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::time::Instant;
 
-/// Check if debug logging is enabled via `NREPL_DEBUG` environment variable
-///
-/// # Security Warning
-///
-/// Debug logging outputs sensitive information to stderr including:
-/// - Source code being evaluated (may contain secrets, credentials, API keys)
-/// - Evaluation results and output
-/// - Session IDs
-/// - Buffer contents in hexadecimal format
+/// The read half of whatever carries the connection's bytes: a TCP socket,
+/// or the local end of a bridge to another transport.
+type ReadHalf = Box<dyn AsyncRead + Send + Unpin>;
+/// The write half, as [`ReadHalf`].
+type WriteHalf = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Maximum size for a single nREPL response message (10MB)
+/// This prevents OOM attacks from malicious servers sending infinite data.
+/// It counts bytes, not reads, so it is the same whatever the read chunk.
+const MAX_RESPONSE_SIZE: usize = 10 * 1024 * 1024;
+
+/// Bytes taken from the socket per read unless configured otherwise
+/// ([`WorkerConfig::read_chunk_size`](crate::worker::WorkerConfig::read_chunk_size)).
+pub const DEFAULT_READ_CHUNK: usize = 4096;
+
+/// Bytes of requests [`FlushPolicy::Batched`] holds before writing them
+/// without waiting for the batch to end.
+pub const DEFAULT_BATCH_BYTES: usize = 64 * 1024;
+
+/// When a worker puts the requests it sends on the wire.
 ///
-/// **Never enable debug logging in production.** Only use during development/debugging,
-/// and ensure logs are not committed to version control or exposed to unauthorized users.
-fn debug_enabled() -> bool {
-    static DEBUG: OnceLock<bool> = OnceLock::new();
-    *DEBUG.get_or_init(|| std::env::var("NREPL_DEBUG").is_ok())
+/// Set with
+/// [`WorkerConfig::flush_policy`](crate::worker::WorkerConfig::flush_policy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Write and flush each request as it is sent: one system call per
+    /// request.
+    Immediate,
+    /// Hold requests sent back to back (a batch of evals, completions while
+    /// typing) until the worker has handled every command waiting for it,
+    /// then write them all in one vectored write. Held requests are written
+    /// sooner once they reach `max_bytes`.
+    Batched { max_bytes: usize },
 }
 
-macro_rules! debug_log {
-    ($($arg:tt)*) => {
-        if debug_enabled() {
-            eprintln!($($arg)*);
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        Self::Batched {
+            max_bytes: DEFAULT_BATCH_BYTES,
         }
-    };
+    }
 }
 
-/// Maximum size for a single nREPL response message (10MB)
-/// This prevents OOM attacks from malicious servers sending infinite data
-const MAX_RESPONSE_SIZE: usize = 10 * 1024 * 1024;
-
-/// Maximum number of incomplete read attempts before giving up (1000 reads)
-/// This prevents `DoS` attacks via incomplete messages that never complete
-const MAX_INCOMPLETE_READS: usize = 1000;
+/// Capacity an empty read buffer may keep for reuse. A buffer grown past
+/// this (and past a few read chunks) by one large response is released once
+/// it has drained, rather than held for the life of the connection.
+const IDLE_BUFFER_CAPACITY: usize = 64 * 1024;
 
 /// Maximum number of output entries that can be accumulated during an evaluation (10,000 entries)
 /// This prevents `DoS` attacks via excessive output flooding
@@ -61,6 +92,85 @@ const MAX_OUTPUT_ENTRIES: usize = 10_000;
 /// This prevents memory exhaustion from massive output
 const MAX_OUTPUT_TOTAL_SIZE: usize = 10 * 1024 * 1024;
 
+/// Default soft threshold above which a single response field is reported as a
+/// [`LargeField`] (1MB). Well under `MAX_RESPONSE_SIZE`: the point is to see a
+/// payload growing before it hits the hard limit.
+pub const DEFAULT_LARGE_FIELD_THRESHOLD: usize = 1024 * 1024;
+
+/// A single response field whose string value exceeded the large-field
+/// threshold. Reported before the response is handed on, so users can find the
+/// middleware or value producing huge payloads without `NREPL_DEBUG`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LargeField {
+    /// The response key carrying the value (`value`, `out`, `err`, ...).
+    pub key: String,
+    /// Size of the value in bytes.
+    pub size: usize,
+    /// Wire id of the request the response answers.
+    pub request_id: String,
+}
+
+/// Receiver for [`LargeField`] events, called on the worker thread.
+pub type LargeFieldHook = Arc<dyn Fn(&LargeField) + Send + Sync>;
+
+/// Where an [`EvalAccumulator`] reports each chunk of output it keeps.
+pub(crate) type OutputTap = Box<dyn Fn(&ServerOutput) + Send>;
+
+/// Where the reader sends [`LargeField`] events. With no hook installed an
+/// event goes to the debug log like any other diagnostic, never to a bare
+/// stderr that an embedding editor may be drawing on. Either way it carries
+/// only the key, size and id, never the payload itself.
+#[derive(Clone)]
+pub(crate) struct LargeFieldTelemetry {
+    pub(crate) threshold: usize,
+    pub(crate) hook: Option<LargeFieldHook>,
+}
+
+impl LargeFieldTelemetry {
+    /// Report every oversized top-level string in `frame`.
+    fn check(&self, frame: &[u8], request_id: &str) {
+        // A field cannot be larger than the frame it arrives in.
+        if frame.len() <= self.threshold {
+            return;
+        }
+        for (key, size) in large_string_fields(frame, self.threshold) {
+            let event = LargeField {
+                key,
+                size,
+                request_id: request_id.to_string(),
+            };
+            match &self.hook {
+                Some(hook) => hook(&event),
+                None => event!(
+                    DEBUG,
+                    "large response field",
+                    key = event.key,
+                    size = event.size,
+                    id = event.request_id,
+                ),
+            }
+        }
+    }
+}
+
+impl Default for LargeFieldTelemetry {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_LARGE_FIELD_THRESHOLD,
+            hook: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for LargeFieldTelemetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LargeFieldTelemetry")
+            .field("threshold", &self.threshold)
+            .field("hook", &self.hook.is_some())
+            .finish()
+    }
+}
+
 /// TCP connection establishment for nREPL.
 ///
 /// [`connect`](Self::connect) opens the socket; [`into_split`](Self::into_split)
@@ -71,9 +181,88 @@ const MAX_OUTPUT_TOTAL_SIZE: usize = 10 * 1024 * 1024;
 /// to cancel had already finished). The worker solves that by demultiplexing
 /// responses by request id, so control ops go out while an eval is in flight.
 pub struct NReplClient {
-    stream: TcpStream,
-    buffer: Vec<u8>, // Persistent buffer for handling multiple messages in one TCP read
-    incomplete_read_count: usize, // Counter to detect stuck/incomplete reads (DoS prevention)
+    read_half: ReadHalf,
+    write_half: WriteHalf,
+    buffer: ReadBuffer,
+}
+
+/// Bytes read but not yet decoded, with the framing state of the message at
+/// their head. One allocation serves the whole connection: reads land in it
+/// directly and decoded messages are split off the front, so a busy output
+/// stream reuses the same memory read after read.
+struct ReadBuffer {
+    bytes: BytesMut,
+    // How far the head message has been walked
+    scanner: FrameScanner,
+    // Reads the head message has taken so far, for the debug log
+    incomplete_reads: usize,
+    // Most bytes taken per read
+    chunk: usize,
+    // Longest wait for the rest of a response once part of it is in
+    read_timeout: Option<Duration>,
+    // When the last bytes came, while a response is part read
+    last_progress: Option<Instant>,
+}
+
+impl ReadBuffer {
+    fn new() -> Self {
+        Self {
+            bytes: BytesMut::new(),
+            scanner: FrameScanner::new(),
+            incomplete_reads: 0,
+            chunk: DEFAULT_READ_CHUNK,
+            read_timeout: None,
+            last_progress: None,
+        }
+    }
+
+    /// Forget everything buffered.
+    fn clear(&mut self) {
+        self.bytes.clear();
+        self.scanner.reset();
+        self.incomplete_reads = 0;
+        self.last_progress = None;
+    }
+
+    /// [`fill`](Self::fill), failing with `TimedOut` if a response is part
+    /// read and the read timeout passes with no more of it. The clock runs
+    /// from the last bytes to arrive, so a read cancelled and restarted
+    /// (the worker's loop does this whenever a command comes in) does not
+    /// reset it, while a large response arriving slowly never trips it.
+    async fn fill_in_time<R: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut R,
+    ) -> std::io::Result<usize> {
+        let Some(limit) = self.read_timeout.filter(|_| !self.bytes.is_empty()) else {
+            return self.fill(stream).await;
+        };
+        let since = *self.last_progress.get_or_insert_with(Instant::now);
+        let n = tokio::time::timeout_at(since + limit, self.fill(stream))
+            .await
+            .map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("read timed out: no bytes for {limit:?} in the middle of a response"),
+                )
+            })??;
+        self.last_progress = Some(Instant::now());
+        Ok(n)
+    }
+
+    /// Read up to one chunk from `stream` onto the end of the buffer,
+    /// returning how many bytes came. An empty buffer that grew large first
+    /// gives its memory back.
+    async fn fill<R: AsyncRead + Unpin>(&mut self, stream: &mut R) -> std::io::Result<usize> {
+        if self.bytes.is_empty() && self.bytes.capacity() > IDLE_BUFFER_CAPACITY.max(4 * self.chunk)
+        {
+            self.bytes = BytesMut::new();
+        }
+        self.bytes.reserve(self.chunk);
+        (&mut *stream)
+            .take(self.chunk as u64)
+            .read_buf(&mut self.bytes)
+            .await
+    }
 }
 
 impl NReplClient {
@@ -98,15 +287,94 @@ impl NReplClient {
     /// calls this and then [`into_split`](Self::into_split) on its own thread.
     pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
         let stream = TcpStream::connect(addr).await?;
-        Ok(Self {
-            stream,
-            buffer: Vec::new(),
-            incomplete_read_count: 0,
-        })
+        Ok(Self::from_tcp(stream))
+    }
+
+    fn from_tcp(stream: TcpStream) -> Self {
+        let (read_half, write_half) = stream.into_split();
+        Self {
+            read_half: Box::new(read_half),
+            write_half: Box::new(write_half),
+            buffer: ReadBuffer::new(),
+        }
+    }
+
+    /// A client speaking to a subprocess over its `stdin` and `stdout`, for
+    /// servers started in stdio mode (`bb nrepl-server --stdio` and the
+    /// like) so that no port is opened. Pass the child's pipes, or anything
+    /// else that reads and writes bencode. See [`crate::stdio`].
+    ///
+    /// Needs no connecting, so cannot fail: a process that has already
+    /// exited shows up as the connection closing on the first read.
+    pub fn connect_stdio<W, R>(stdin: W, stdout: R) -> Self
+    where
+        W: std::io::Write + Send + 'static,
+        R: std::io::Read + Send + 'static,
+    {
+        let (write_half, read_half) = crate::stdio::pipes(stdin, stdout);
+        Self {
+            read_half: Box::new(read_half),
+            write_half: Box::new(write_half),
+            buffer: ReadBuffer::new(),
+        }
+    }
+
+    /// A client over any byte stream that carries bencode both ways, such
+    /// as the local end of a transport bridge.
+    #[cfg(any(feature = "drawbridge", feature = "websocket"))]
+    fn from_stream<S>(stream: S) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (read_half, write_half) = tokio::io::split(stream);
+        Self {
+            read_half: Box::new(read_half),
+            write_half: Box::new(write_half),
+            buffer: ReadBuffer::new(),
+        }
+    }
+
+    /// Connect to `addr` (`host:port`) through `proxy`, which resolves the
+    /// host name itself.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NReplError::Connection` if the proxy cannot be reached or
+    /// refuses the tunnel.
+    #[cfg(feature = "proxy")]
+    pub async fn connect_via(addr: &str, proxy: &crate::proxy::Proxy) -> Result<Self> {
+        let stream = proxy.connect(addr).await?;
+        Ok(Self::from_tcp(stream))
+    }
+
+    /// Connect to a drawbridge endpoint at `url` (`http://host[:port]/path`),
+    /// for servers that expose nREPL only over HTTP. See [`crate::drawbridge`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `NReplError::Connection` for an `https://` or malformed URL,
+    /// or if the endpoint cannot be reached or does not answer with JSON.
+    #[cfg(feature = "drawbridge")]
+    pub async fn connect_http(url: &str) -> Result<Self> {
+        let stream = crate::drawbridge::connect(url).await?;
+        Ok(Self::from_stream(stream))
+    }
+
+    /// Connect through a WebSocket bridge at `url` (`ws://host[:port]/path`),
+    /// one bencode message per WebSocket message. See [`crate::websocket`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `NReplError::Connection` for a `wss://` or malformed URL, or
+    /// if the bridge cannot be reached or refuses the upgrade.
+    #[cfg(feature = "websocket")]
+    pub async fn connect_ws(url: &str) -> Result<Self> {
+        let stream = crate::websocket::connect(url).await?;
+        Ok(Self::from_stream(stream))
     }
 
     /// Split this client into an independent writer and reader over the same
-    /// TCP connection.
+    /// connection.
     ///
     /// This is the foundation of the demux model: an interrupt (or stdin) can be
     /// *written* through [`NReplWriter`] while [`NReplReader`] is parked
@@ -117,208 +385,543 @@ impl NReplClient {
     /// [`crate::ops::wire_id`]); [`crate::worker::Worker`] does both.
     pub fn into_split(self) -> (NReplWriter, NReplReader) {
         let NReplClient {
-            stream,
+            read_half,
+            write_half,
             buffer,
-            incomplete_read_count,
-            ..
         } = self;
 
-        let (read_half, write_half) = stream.into_split();
         (
-            NReplWriter { stream: write_half },
+            NReplWriter {
+                stream: write_half,
+                flush_policy: FlushPolicy::default(),
+                queued: Vec::new(),
+                queued_bytes: 0,
+                metrics: None,
+                capture: None,
+                sessions: SessionTable::default(),
+                auth: None,
+                write_timeout: None,
+                wedged: false,
+            },
             NReplReader {
                 stream: read_half,
                 buffer,
-                incomplete_read_count,
+                large_fields: LargeFieldTelemetry::default(),
+                metrics: None,
+                capture: None,
             },
         )
     }
 }
 
-/// Read a single bencode response from any async byte stream, using a
-/// persistent decode buffer to handle messages split across (or batched into)
-/// TCP reads.
-///
-/// Enforces the `MAX_RESPONSE_SIZE` and `MAX_INCOMPLETE_READS` protections.
+/// Frame the next bencode response from any async byte stream, decoding only
+/// its routing keys, using a persistent decode buffer to handle messages
+/// split across (or batched into) TCP reads. Reads land straight in the
+/// buffer, its scanner carries the framing walk over from one read to the
+/// next, and a complete message is split off the front without copying it
+/// or moving the bytes behind it.
 ///
-/// Note that for a single large streamed response `MAX_INCOMPLETE_READS`
-/// (1000 top-ups of 4KB) is reached at roughly 4MB, well before
-/// `MAX_RESPONSE_SIZE`, so it is the guard that actually fires.
-async fn read_one_response<R: AsyncRead + Unpin>(
+/// Enforces the `MAX_RESPONSE_SIZE` protection, on the bytes buffered
+/// however many reads brought them. A message that never completes is
+/// stopped by that and by the read timeout, if one is set.
+async fn read_one_frame<R: AsyncRead + Unpin>(
     stream: &mut R,
-    buffer: &mut Vec<u8>,
-    incomplete_read_count: &mut usize,
-) -> Result<Response> {
+    read: &mut ReadBuffer,
+    large_fields: &LargeFieldTelemetry,
+    metrics: Option<&ClientMetrics>,
+    capture: Option<&FrameCapture>,
+) -> Result<LazyResponse> {
     // Bencode messages are self-delimiting. We use a persistent buffer to handle
     // cases where multiple messages arrive in a single TCP read.
-
-    let mut temp_buf = [0u8; 4096];
-
     loop {
-        // First, try to decode from existing buffer data
-        if !buffer.is_empty() {
-            match decode_one(buffer) {
-                Decoded::Message { response, consumed } => {
-                    debug_log!(
-                        "[nREPL DEBUG] Successfully decoded response (consumed {} of {} bytes in buffer)",
-                        consumed,
-                        buffer.len()
-                    );
-                    // Remove the consumed bytes, keep the rest for next read
-                    buffer.drain(..consumed);
-                    debug_log!(
-                        "[nREPL DEBUG] Buffer now has {} bytes remaining",
-                        buffer.len()
-                    );
-                    // Reset incomplete read counter on success
-                    *incomplete_read_count = 0;
-                    return Ok(*response);
-                }
-                Decoded::Malformed { consumed, message } => {
-                    // A *complete* message we cannot deserialize (a non-conforming
-                    // server sent an unexpected value shape). Retrying would fail
-                    // identically forever and wedge the reader - every later
-                    // response queues up behind these bytes and never decodes.
-                    // Skip the bad message and carry on so the connection stays
-                    // usable; the op awaiting this id will simply time out.
-                    debug_log!(
-                        "[nREPL DEBUG] Skipping undecodable response ({} bytes): {}",
-                        consumed,
-                        message
-                    );
-                    buffer.drain(..consumed);
-                    *incomplete_read_count = 0;
-                    continue;
-                }
-                Decoded::Incomplete => {
-                    // Incomplete message, need to read more data
-                    *incomplete_read_count += 1;
-                    debug_log!(
-                        "[nREPL DEBUG] Incomplete message in buffer ({} bytes), reading more... (attempt {}/{})",
-                        buffer.len(),
-                        *incomplete_read_count,
-                        MAX_INCOMPLETE_READS
-                    );
-
-                    // Check if we've exceeded the maximum incomplete reads
-                    if *incomplete_read_count > MAX_INCOMPLETE_READS {
-                        return Err(NReplError::protocol(format!(
-                            "Too many incomplete reads ({} attempts), possible incomplete/malformed message",
-                            *incomplete_read_count
-                        )));
+        // First, try to frame a message from existing buffer data
+        if !read.bytes.is_empty() {
+            // A structural error means the buffered bytes don't yet form a
+            // complete message (or are not parseable as bencode framing);
+            // either way the recourse is to read more.
+            if let Ok(Some(consumed)) = read.scanner.scan(&read.bytes) {
+                // Split the message off, keep the rest for the next read
+                let frame = read.bytes.split_to(consumed).freeze();
+                read.incomplete_reads = 0;
+                read.last_progress = None;
+                match LazyResponse::new(frame.clone()) {
+                    Ok(response) => {
+                        event!(
+                            DEBUG,
+                            "framed response",
+                            id = response.id(),
+                            bytes = consumed,
+                            buffered = read.bytes.len()
+                        );
+                        large_fields.check(&frame, response.id());
+                        if let Some(capture) = capture {
+                            capture.record(Direction::Recv, &frame);
+                        }
+                        if let Some(metrics) = metrics {
+                            metrics.record_response(response.id(), response.status(), consumed);
+                        }
+                        return Ok(response);
                     }
-
-                    // Only format buffer contents if debug logging is enabled
-                    if debug_enabled() {
-                        // Show first 200 bytes as hex for debugging
-                        let preview_len = buffer.len().min(200);
-                        let hex: String = buffer[..preview_len]
-                            .iter()
-                            .map(|b| format!("{b:02x}"))
-                            .collect::<Vec<_>>()
-                            .join(" ");
-                        eprintln!("[nREPL DEBUG] Buffer hex (first {preview_len} bytes): {hex}");
-                        // Also show as string (replacing non-printable with .)
-                        let ascii: String = buffer[..preview_len]
-                            .iter()
-                            .map(|&b| {
-                                if (32..127).contains(&b) {
-                                    b as char
-                                } else {
-                                    '.'
-                                }
-                            })
-                            .collect();
-                        eprintln!(
-                            "[nREPL DEBUG] Buffer ASCII (first {preview_len} bytes): {ascii}"
+                    Err(e) => {
+                        // A *complete* message we cannot route (a non-conforming
+                        // server sent an unexpected value shape). Retrying would
+                        // fail identically forever and wedge the reader - every
+                        // later response queues up behind these bytes and never
+                        // decodes. Skip the bad message and carry on so the
+                        // connection stays usable; the op awaiting this id will
+                        // simply time out.
+                        event!(
+                            DEBUG,
+                            "skipping undecodable response",
+                            bytes = consumed,
+                            error = e.to_string()
                         );
+                        if let Some(capture) = capture {
+                            capture.record(Direction::Skip, &frame);
+                        }
+                        continue;
                     }
                 }
             }
+            // Incomplete message, need to read more data
+            read.incomplete_reads += 1;
+            event!(
+                DEBUG,
+                "incomplete message, reading more",
+                buffered = read.bytes.len(),
+                attempt = read.incomplete_reads
+            );
+
+            // Only format buffer contents if someone will see them
+            if trace::trace_enabled() {
+                // Show first 200 bytes as hex for debugging
+                let preview_len = read.bytes.len().min(200);
+                let hex: String = read.bytes[..preview_len]
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                // Also show as string (replacing non-printable with .)
+                let ascii: String = read.bytes[..preview_len]
+                    .iter()
+                    .map(|&b| {
+                        if (32..127).contains(&b) {
+                            b as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                event!(
+                    TRACE,
+                    "buffer preview",
+                    bytes = preview_len,
+                    hex = hex,
+                    ascii = ascii
+                );
+            }
         }
 
         // Read more data from the stream
-        debug_log!("[nREPL DEBUG] Waiting for data from stream...");
-        let n = stream.read(&mut temp_buf).await?;
-        debug_log!("[nREPL DEBUG] Read {} bytes from stream", n);
+        let n = read.fill_in_time(stream).await?;
+        event!(DEBUG, "read from stream", bytes = n);
 
         if n == 0 {
+            if let Some(capture) = capture
+                && !read.bytes.is_empty()
+            {
+                capture.record(Direction::Partial, &read.bytes);
+            }
             return Err(NReplError::Connection(std::io::Error::new(
                 std::io::ErrorKind::UnexpectedEof,
                 "connection closed",
             )));
         }
 
-        // Check buffer size BEFORE appending to prevent exceeding MAX_RESPONSE_SIZE
-        if buffer.len() + n > MAX_RESPONSE_SIZE {
+        // A read is at most one chunk, so the buffer overshoots
+        // MAX_RESPONSE_SIZE by no more than that before this fails it.
+        if read.bytes.len() > MAX_RESPONSE_SIZE {
+            let current = read.bytes.len() - n;
+            if let Some(capture) = capture {
+                capture.record(Direction::Partial, &read.bytes[..current]);
+            }
+            return Err(NReplError::protocol(format!(
+                "Response would exceed maximum size of {MAX_RESPONSE_SIZE} bytes (current: {current}, adding: {n})"
+            )));
+        }
+    }
+}
+
+/// A field added to every request, such as the token a guarding middleware
+/// checks before handling a message. The token is kept out of `Debug` and
+/// the frame capture, which record `<redacted>` in its place.
+#[derive(Clone)]
+pub(crate) struct AuthField {
+    key: String,
+    token: String,
+}
+
+impl AuthField {
+    /// The field `key` set to `token`, refused if `key` is one a request
+    /// sets itself (`op`, `id`, `session`, `code` and the rest): sent too,
+    /// it would replace or duplicate that field in every message.
+    pub(crate) fn new(key: String, token: String) -> Result<Self> {
+        if Request::wire_keys().contains(&key.as_str()) {
             return Err(NReplError::protocol(format!(
-                "Response would exceed maximum size of {} bytes (current: {}, adding: {})",
-                MAX_RESPONSE_SIZE,
-                buffer.len(),
-                n
+                "`{key}` is a request field and cannot carry an auth token"
             )));
         }
+        Ok(Self { key, token })
+    }
 
-        buffer.extend_from_slice(&temp_buf[..n]);
+    /// `request` encoded with the field set to `value`.
+    fn stamp(&self, request: &Request, value: &str) -> Result<Vec<u8>> {
+        encode_with(request, Some((&self.key, value)))
+    }
+}
+
+/// A request with one more field, encoded in the same pass as the rest.
+/// The key is never one of the request's own (see [`AuthField::new`]), so
+/// the dict stays free of duplicates.
+#[derive(serde::Serialize)]
+struct WithField<'a> {
+    #[serde(flatten)]
+    request: &'a Request,
+    #[serde(flatten)]
+    field: BTreeMap<&'a str, &'a str>,
+}
+
+/// `request` encoded with its tags and the field `extra`, if any, added.
+fn encode_with(request: &Request, extra: Option<(&str, &str)>) -> Result<Vec<u8>> {
+    if request.tags.is_empty() {
+        return match extra {
+            None => encode_request(request),
+            Some(field) => to_bytes(&WithField {
+                request,
+                field: BTreeMap::from([field]),
+            }),
+        };
+    }
+    // Tags give way to any field the request sets, which takes decoding it.
+    let mut fields = BencodeValue::try_from(request)?;
+    if let BencodeValue::Dict(map) = &mut fields {
+        for (key, value) in &request.tags {
+            map.entry(key.clone())
+                .or_insert_with(|| BencodeValue::String(value.clone()));
+        }
+        if let Some((key, value)) = extra {
+            map.insert(key.to_string(), BencodeValue::String(value.to_string()));
+        }
+    }
+    Ok(fields.encode())
+}
+
+impl std::fmt::Debug for AuthField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthField")
+            .field("key", &self.key)
+            .field("token", &"<redacted>")
+            .finish()
     }
 }
 
 /// Write half of a split nREPL connection.
 ///
-/// Holds the owned write half of the TCP stream so a control op (interrupt,
+/// Holds the write half of the connection so a control op (interrupt,
 /// stdin) can be written while the [`NReplReader`] is parked reading.
+/// Requests are encoded as they are sent and written by its
+/// [`FlushPolicy`]; whoever batches them must [`flush`](Self::flush).
 pub struct NReplWriter {
-    stream: OwnedWriteHalf,
+    stream: WriteHalf,
+    flush_policy: FlushPolicy,
+    /// Encoded requests not yet written, oldest first.
+    queued: Vec<Vec<u8>>,
+    queued_bytes: usize,
+    metrics: Option<ClientMetrics>,
+    capture: Option<FrameCapture>,
+    sessions: SessionTable,
+    auth: Option<AuthField>,
+    write_timeout: Option<Duration>,
+    /// Set when a write timed out part way, leaving half a request on the
+    /// wire: nothing written after it would be read as the server expects.
+    wedged: bool,
 }
 
 impl NReplWriter {
-    /// Encode and send a request, flushing the stream.
+    /// Encode a request and write it, or hold it for the next
+    /// [`flush`](Self::flush) if the flush policy batches requests.
     ///
     /// # Errors
     ///
-    /// Returns an error if encoding the request fails or the stream cannot be written.
+    /// Returns an error if encoding the request fails or the stream cannot be
+    /// written. A held request's write error comes from the flush instead.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            name = "nrepl.send",
+            skip_all,
+            fields(op = %request.op, id = %request.id)
+        )
+    )]
     pub async fn send(&mut self, request: &Request) -> Result<()> {
-        let encoded = encode_request(request)?;
-        debug_log!(
-            "[nREPL DEBUG] WROTE request op={} id={} ({} bytes)",
-            request.op,
-            request.id,
-            encoded.len()
+        let encoded = match &self.auth {
+            Some(auth) => auth.stamp(request, &auth.token)?,
+            None => encode_with(request, None)?,
+        };
+        if let Some(capture) = &self.capture {
+            match &self.auth {
+                Some(auth) => capture.record(Direction::Send, &auth.stamp(request, "<redacted>")?),
+                None => capture.record(Direction::Send, &encoded),
+            }
+        }
+        event!(
+            DEBUG,
+            "queued request",
+            op = request.op,
+            id = request.id,
+            bytes = encoded.len()
+        );
+        if let Some(metrics) = &self.metrics {
+            metrics.record_request(&request.op, &request.id, encoded.len());
+        }
+        if let Some(session) = &request.session {
+            self.sessions.record_op(session, &request.op);
+        }
+        self.queued_bytes += encoded.len();
+        self.queued.push(encoded);
+        match self.flush_policy {
+            FlushPolicy::Batched { max_bytes } if self.queued_bytes < max_bytes => Ok(()),
+            _ => self.flush().await,
+        }
+    }
+
+    /// Whether requests are held waiting for a [`flush`](Self::flush).
+    pub(crate) fn has_queued(&self) -> bool {
+        !self.queued.is_empty()
+    }
+
+    /// Write every held request, in one vectored write where the socket
+    /// takes it, and flush the stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stream cannot be written, or the write
+    /// timeout passes first. The held requests are dropped either way. After
+    /// a timeout every later write fails too.
+    pub async fn flush(&mut self) -> Result<()> {
+        if self.queued.is_empty() {
+            return Ok(());
+        }
+        let frames = std::mem::take(&mut self.queued);
+        let bytes = std::mem::take(&mut self.queued_bytes);
+        if self.wedged {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "an earlier write timed out part way; the connection is unusable",
+            )
+            .into());
+        }
+        let write = write_frames(&mut self.stream, &frames);
+        match self.write_timeout {
+            Some(limit) => {
+                if tokio::time::timeout(limit, write).await.is_err() {
+                    self.wedged = true;
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        format!("write timed out: the server took nothing for {limit:?}"),
+                    )
+                    .into());
+                }
+            }
+            None => write.await?,
+        }
+        event!(
+            DEBUG,
+            "wrote requests",
+            requests = frames.len(),
+            bytes = bytes
         );
-        self.stream.write_all(&encoded).await?;
-        self.stream.flush().await?;
-        debug_log!("[nREPL DEBUG] flushed request id={}", request.id);
         Ok(())
     }
+
+    /// Give up on a write that takes longer than `limit` from now on.
+    pub(crate) fn set_write_timeout(&mut self, limit: Duration) {
+        self.write_timeout = Some(limit);
+    }
+
+    /// Write requests by `policy` from now on.
+    pub(crate) fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+    }
+
+    /// Record every request written from now on into `metrics`.
+    pub(crate) fn set_metrics(&mut self, metrics: ClientMetrics) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Add `auth` to every request written from now on.
+    pub(crate) fn set_auth(&mut self, auth: AuthField) {
+        self.auth = Some(auth);
+    }
+
+    /// Append every frame written from now on to `capture`.
+    pub(crate) fn set_capture(&mut self, capture: FrameCapture) {
+        self.capture = Some(capture);
+    }
+
+    /// Track the sessions of every request written from now on in
+    /// `sessions`.
+    pub(crate) fn set_sessions(&mut self, sessions: SessionTable) {
+        self.sessions = sessions;
+    }
+
+    /// The sessions this writer has sent requests in.
+    pub(crate) fn sessions(&self) -> &SessionTable {
+        &self.sessions
+    }
+}
+
+/// Write `frames` in as few writes as the stream allows, and flush it.
+async fn write_frames(stream: &mut WriteHalf, frames: &[Vec<u8>]) -> Result<()> {
+    let mut slices: Vec<IoSlice<'_>> = frames.iter().map(|f| IoSlice::new(f)).collect();
+    let mut unwritten = &mut slices[..];
+    while !unwritten.is_empty() {
+        let n = stream.write_vectored(unwritten).await?;
+        if n == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::WriteZero).into());
+        }
+        IoSlice::advance_slices(&mut unwritten, n);
+    }
+    stream.flush().await?;
+    Ok(())
 }
 
 /// Read half of a split nREPL connection.
 ///
-/// Carries the in-progress decode buffer and incomplete-read counter so
-/// splitting a client mid-stream loses no buffered bytes.
+/// Carries the in-progress decode buffer and framing state so splitting a
+/// client mid-stream loses no buffered bytes.
 pub struct NReplReader {
-    stream: OwnedReadHalf,
-    buffer: Vec<u8>,
-    incomplete_read_count: usize,
+    stream: ReadHalf,
+    buffer: ReadBuffer,
+    large_fields: LargeFieldTelemetry,
+    metrics: Option<ClientMetrics>,
+    capture: Option<FrameCapture>,
 }
 
 impl NReplReader {
-    /// Read and decode the next bencode response from the connection.
+    /// Frame the next response from the connection, decoding only its
+    /// `id`, `session` and `status`. Call [`LazyResponse::decode`] for the
+    /// rest, or drop it unread when nobody is waiting on that id.
     ///
     /// # Errors
     ///
-    /// Returns an error if the connection is closed, a read times out, or the
-    /// response cannot be decoded.
-    pub async fn next_response(&mut self) -> Result<Response> {
-        read_one_response(
+    /// Returns an error if the connection is closed or a read fails.
+    pub async fn next_frame(&mut self) -> Result<LazyResponse> {
+        read_one_frame(
             &mut self.stream,
             &mut self.buffer,
-            &mut self.incomplete_read_count,
+            &self.large_fields,
+            self.metrics.as_ref(),
+            self.capture.as_ref(),
         )
         .await
     }
+
+    /// Throw away the decode buffer and whatever the socket delivers until it
+    /// has been quiet for `idle`, up to `limit` bytes. Returns how many bytes
+    /// were discarded.
+    ///
+    /// Used to resynchronize after a framing problem: once the server has
+    /// stopped sending, the next byte read starts a fresh message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection is closed or the read fails.
+    pub async fn drain(&mut self, idle: std::time::Duration, limit: usize) -> Result<usize> {
+        // Reads land in the decode buffer, which is emptied after each.
+        let mut discarded = 0;
+        loop {
+            discarded += self.buffer.bytes.len();
+            if let Some(capture) = &self.capture
+                && !self.buffer.bytes.is_empty()
+            {
+                capture.record(Direction::Drop, &self.buffer.bytes);
+            }
+            self.buffer.clear();
+            if discarded >= limit {
+                break;
+            }
+            match tokio::time::timeout(idle, self.buffer.fill(&mut self.stream)).await {
+                Ok(Ok(0)) => {
+                    return Err(NReplError::Connection(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "connection closed",
+                    )));
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => break,
+            }
+        }
+        event!(DEBUG, "drained stream", bytes = discarded);
+        Ok(discarded)
+    }
+
+    /// Give up on a response whose next bytes take longer than `limit` to
+    /// arrive once part of it has, from now on.
+    pub(crate) fn set_read_timeout(&mut self, limit: Duration) {
+        self.buffer.read_timeout = Some(limit);
+    }
+
+    /// Take at most `bytes` from the socket per read (at least 1). Only the
+    /// number of reads changes: the largest response is `MAX_RESPONSE_SIZE`
+    /// bytes whatever the chunk.
+    pub(crate) fn set_read_chunk(&mut self, bytes: usize) {
+        self.buffer.chunk = bytes.max(1);
+    }
+
+    /// Replace where oversized response fields are reported.
+    pub(crate) fn set_large_field_telemetry(&mut self, telemetry: LargeFieldTelemetry) {
+        self.large_fields = telemetry;
+    }
+
+    /// Record every response decoded from now on into `metrics`.
+    pub(crate) fn set_metrics(&mut self, metrics: ClientMetrics) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Append every frame read from now on to `capture`.
+    pub(crate) fn set_capture(&mut self, capture: FrameCapture) {
+        self.capture = Some(capture);
+    }
+}
+
+/// Where eval output goes once it passes the in-memory limits (10,000
+/// chunks of stdout or of stderr, or 10MB of the two together).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OutputSink {
+    /// Keep output in memory and fail the eval with
+    /// [`NReplError::Protocol`] once it passes the limits.
+    #[default]
+    Memory,
+    /// Write the overflow to a fresh file in the directory `path` (created
+    /// if missing) and let the eval run on. The file is reported in
+    /// [`EvalResult::spilled`](crate::EvalResult::spilled).
+    File(PathBuf),
+}
+
+impl OutputSink {
+    /// Spill to the system temporary directory.
+    #[must_use]
+    pub fn temp() -> Self {
+        Self::File(std::env::temp_dir())
+    }
 }
 
+/// Numbers spill files, so evals in one process never share one.
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
 /// Accumulates the responses of a single eval/load-file request into an
 /// [`EvalResult`], applying the same backpressure limits as the legacy path.
 ///
@@ -331,61 +934,133 @@ pub struct EvalAccumulator {
     // Combined size of stdout + stderr accumulated so far (MAX_OUTPUT_TOTAL_SIZE).
     total_output_size: usize,
     done: bool,
+    sink: OutputSink,
+    /// The spill file, once output has overflowed into it.
+    spill: Option<File>,
+    /// Merge chunks that continue an unfinished line of the same stream.
+    coalesce: bool,
+    /// Whether the last chunk kept was stderr.
+    last_stderr: Option<bool>,
+    ansi: AnsiFilter,
+    /// Longest value kept in [`EvalResult::value`].
+    max_value_bytes: Option<usize>,
+    /// When the request went out, and when the first response came back,
+    /// on both clocks.
+    sent: Option<(std::time::Instant, SystemTime)>,
+    first_response: Option<(std::time::Instant, SystemTime)>,
+    /// Keys of the tags the request carried, to look for in its responses.
+    tag_keys: Vec<String>,
+    /// Told of each chunk of output as it arrives.
+    tap: Option<OutputTap>,
 }
 
 impl EvalAccumulator {
     #[must_use]
     pub fn new() -> Self {
+        Self::with_sink(OutputSink::Memory)
+    }
+
+    /// An accumulator that sends output past the limits to `sink`.
+    #[must_use]
+    pub fn with_sink(sink: OutputSink) -> Self {
         Self {
             result: EvalResult::new(),
             total_output_size: 0,
             done: false,
+            sink,
+            spill: None,
+            coalesce: false,
+            last_stderr: None,
+            ansi: AnsiFilter::default(),
+            max_value_bytes: None,
+            sent: None,
+            first_response: None,
+            tag_keys: Vec::new(),
+            tap: None,
+        }
+    }
+
+    /// Look for the tags `keys` in each response, to be echoed in
+    /// [`EvalResult::tags`].
+    pub(crate) fn expect_tags(&mut self, keys: impl IntoIterator<Item = String>) {
+        self.tag_keys = keys.into_iter().collect();
+    }
+
+    /// Keep any expected tag `response` echoes. Called with the response
+    /// still undecoded, since [`Response`] has no room for unknown fields.
+    pub(crate) fn take_tags(&mut self, response: &LazyResponse) {
+        for key in &self.tag_keys {
+            if let Some(value) = response.field(key) {
+                self.result
+                    .tags
+                    .get_or_insert_default()
+                    .insert(key.clone(), value);
+            }
         }
     }
 
+    /// Start the clock for [`EvalResult::timing`]: the request is on its
+    /// way. Results from an accumulator never marked carry no timing.
+    pub(crate) fn mark_sent(&mut self) {
+        self.sent = Some((std::time::Instant::now(), SystemTime::now()));
+    }
+
+    /// Cut a value longer than `max_bytes` to that length when the result
+    /// is taken, recording what was elided in
+    /// [`EvalResult::truncated`].
+    #[must_use]
+    pub fn truncate_values(mut self, max_bytes: usize) -> Self {
+        self.max_value_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Put output through `policy` as it arrives. Under
+    /// [`AnsiPolicy::Parse`] the result carries
+    /// [`styles`](EvalResult::styles).
+    #[must_use]
+    pub fn ansi(mut self, policy: AnsiPolicy) -> Self {
+        self.ansi = AnsiFilter::new(policy);
+        self.result.styles = (policy == AnsiPolicy::Parse).then(Box::default);
+        self
+    }
+
+    /// Report each chunk of output to `tap` as it arrives, after the ANSI
+    /// policy and before any limit or coalescing.
+    #[must_use]
+    pub(crate) fn tap_output(mut self, tap: OutputTap) -> Self {
+        self.tap = Some(tap);
+        self
+    }
+
+    /// Merge each chunk that continues an unfinished line of the previous
+    /// chunk's stream into that chunk's entry, so a `println` the server
+    /// sent in pieces is one entry of [`EvalResult::output`] or
+    /// [`EvalResult::error`].
+    #[must_use]
+    pub fn coalesce_lines(mut self, coalesce: bool) -> Self {
+        self.coalesce = coalesce;
+        self
+    }
+
     /// Fold one response (already known to belong to this request) into the
     /// result. Returns an error if a backpressure limit is exceeded.
     ///
     /// # Errors
     ///
-    /// Returns an error if a backpressure limit (output size or message count) is exceeded.
+    /// Returns an error if a backpressure limit (output size or message count)
+    /// is exceeded with nowhere to spill to, or the spill file cannot be
+    /// written.
     pub fn push(&mut self, response: Response) -> Result<()> {
-        // Accumulate stdout output with backpressure limits
-        if let Some(out) = response.out {
-            if self.result.output.len() >= MAX_OUTPUT_ENTRIES {
-                return Err(NReplError::protocol(format!(
-                    "Output exceeded maximum entries limit ({MAX_OUTPUT_ENTRIES} entries)"
-                )));
-            }
-            let out_size = out.len();
-            if self.total_output_size + out_size > MAX_OUTPUT_TOTAL_SIZE {
-                return Err(NReplError::protocol(format!(
-                    "Output exceeded maximum total size of {} bytes ({} MB)",
-                    MAX_OUTPUT_TOTAL_SIZE,
-                    MAX_OUTPUT_TOTAL_SIZE / (1024 * 1024)
-                )));
-            }
-            self.total_output_size += out_size;
-            self.result.output.push(out);
+        if self.sent.is_some() && self.first_response.is_none() {
+            self.first_response = Some((std::time::Instant::now(), SystemTime::now()));
         }
 
-        // Accumulate stderr errors with backpressure limits
+        // Accumulate stdout and stderr with backpressure limits
+        if let Some(out) = response.out {
+            self.take_output(out, false)?;
+        }
         if let Some(err) = response.err {
-            if self.result.error.len() >= MAX_OUTPUT_ENTRIES {
-                return Err(NReplError::protocol(format!(
-                    "Error output exceeded maximum entries limit ({MAX_OUTPUT_ENTRIES} entries)"
-                )));
-            }
-            let err_size = err.len();
-            if self.total_output_size + err_size > MAX_OUTPUT_TOTAL_SIZE {
-                return Err(NReplError::protocol(format!(
-                    "Error output exceeded maximum total size of {} bytes ({} MB)",
-                    MAX_OUTPUT_TOTAL_SIZE,
-                    MAX_OUTPUT_TOTAL_SIZE / (1024 * 1024)
-                )));
-            }
-            self.total_output_size += err_size;
-            self.result.error.push(err);
+            self.take_output(err, true)?;
         }
 
         // Capture value (last one wins)
@@ -398,6 +1073,14 @@ impl EvalAccumulator {
             self.result.ns = Some(ns);
         }
 
+        // A value the content-type middleware presented as MIME content.
+        if let Some(content_type) = &response.content_type {
+            self.result.rich_content = Some(RichContent::from_parts(
+                content_type,
+                response.body.as_deref(),
+            ));
+        }
+
         // Capture explicit exception info (conformance #1). Prefer `ex`, fall
         // back to `root-ex` if only that is present.
         if let Some(ex) = response.ex {
@@ -418,9 +1101,132 @@ impl EvalAccumulator {
         Ok(())
     }
 
+    /// Keep `text` in memory while within the limits, otherwise spill it.
+    /// Once output has spilled all later output does, so the file keeps it
+    /// in order.
+    fn take_output(&mut self, text: String, stderr: bool) -> Result<()> {
+        let (text, spans) = self.ansi.apply(stderr, text);
+        if text.is_empty() {
+            // Nothing but escape sequences.
+            return Ok(());
+        }
+        if let Some(tap) = &self.tap {
+            tap(&ServerOutput::classified(
+                stderr,
+                text.clone(),
+                spans.clone(),
+            ));
+        }
+        if self.spill.is_none() {
+            let continues = self.coalesce && self.last_stderr == Some(stderr);
+            let (what, entries, styles) = if stderr {
+                (
+                    "Error output",
+                    &mut self.result.error,
+                    self.result.styles.as_mut().map(|s| &mut s.error),
+                )
+            } else {
+                (
+                    "Output",
+                    &mut self.result.output,
+                    self.result.styles.as_mut().map(|s| &mut s.output),
+                )
+            };
+            let merge = continues && entries.last().is_some_and(|last| !last.ends_with('\n'));
+            let limit = if !merge && entries.len() >= MAX_OUTPUT_ENTRIES {
+                format!("{what} exceeded maximum entries limit ({MAX_OUTPUT_ENTRIES} entries)")
+            } else if self.total_output_size + text.len() > MAX_OUTPUT_TOTAL_SIZE {
+                format!(
+                    "{what} exceeded maximum total size of {} bytes ({} MB)",
+                    MAX_OUTPUT_TOTAL_SIZE,
+                    MAX_OUTPUT_TOTAL_SIZE / (1024 * 1024)
+                )
+            } else {
+                self.total_output_size += text.len();
+                match entries.last_mut() {
+                    Some(last) if merge => {
+                        if let Some(last_spans) = styles.and_then(|s| s.last_mut()) {
+                            last_spans.extend(spans.into_iter().map(|span| StyleSpan {
+                                range: span.range.start + last.len()..span.range.end + last.len(),
+                                style: span.style,
+                            }));
+                        }
+                        last.push_str(&text);
+                    }
+                    _ => {
+                        entries.push(text);
+                        if let Some(styles) = styles {
+                            styles.push(spans);
+                        }
+                    }
+                }
+                self.last_stderr = Some(stderr);
+                return Ok(());
+            };
+            self.open_spill(limit)?;
+        }
+        self.spill_output(&text, stderr)
+    }
+
+    /// Open a spill file in the sink's directory, or fail with `limit` if
+    /// output is kept in memory.
+    fn open_spill(&mut self, limit: String) -> Result<()> {
+        let OutputSink::File(dir) = &self.sink else {
+            return Err(NReplError::protocol(limit));
+        };
+        let path = dir.join(format!(
+            "nrepl-output-{}-{}.log",
+            std::process::id(),
+            SPILL_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = std::fs::create_dir_all(dir)
+            .and_then(|()| File::create_new(&path))
+            .map_err(|e| spill_failed(&path, &e))?;
+        event!(
+            DEBUG,
+            "spilling eval output",
+            path = path.display().to_string()
+        );
+        self.spill = Some(file);
+        self.result.spilled = Some(SpilledOutput {
+            path,
+            stdout_bytes: 0,
+            stderr_bytes: 0,
+        });
+        Ok(())
+    }
+
+    /// Append `text` to the spill file and count it.
+    fn spill_output(&mut self, text: &str, stderr: bool) -> Result<()> {
+        let (Some(file), Some(spilled)) = (&mut self.spill, &mut self.result.spilled) else {
+            return Ok(());
+        };
+        file.write_all(text.as_bytes())
+            .map_err(|e| spill_failed(&spilled.path, &e))?;
+        let written = if stderr {
+            &mut spilled.stderr_bytes
+        } else {
+            &mut spilled.stdout_bytes
+        };
+        *written += text.len() as u64;
+        Ok(())
+    }
+
     /// Consume the accumulator, returning the assembled result.
     #[must_use]
-    pub fn finish(self) -> EvalResult {
+    pub fn finish(mut self) -> EvalResult {
+        if let (Some(max), Some(value)) = (self.max_value_bytes, &mut self.result.value) {
+            self.result.truncated = TruncatedValue::truncate(value, max);
+        }
+        if let Some((sent, sent_at)) = self.sent {
+            self.result.timing = Some(Box::new(EvalTiming {
+                sent_at,
+                first_response_at: self.first_response.map(|(_, at)| at),
+                done_at: SystemTime::now(),
+                duration: sent.elapsed(),
+                first_response_after: self.first_response.map(|(first, _)| first - sent),
+            }));
+        }
         self.result
     }
 
@@ -438,6 +1244,10 @@ impl EvalAccumulator {
     }
 }
 
+fn spill_failed(path: &std::path::Path, e: &std::io::Error) -> NReplError {
+    NReplError::OperationFailed(format!("spilling output to {}: {e}", path.display()))
+}
+
 impl Default for EvalAccumulator {
     fn default() -> Self {
         Self::new()
@@ -447,8 +1257,269 @@ impl Default for EvalAccumulator {
 impl std::fmt::Debug for NReplClient {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("NReplClient")
-            .field("buffer_size", &self.buffer.len())
-            .field("incomplete_read_count", &self.incomplete_read_count)
+            .field("buffer_size", &self.buffer.bytes.len())
+            .field("incomplete_read_count", &self.buffer.incomplete_reads)
             .finish_non_exhaustive()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(stream: &mut &[u8], read: &mut ReadBuffer) -> Vec<String> {
+        let telemetry = LargeFieldTelemetry::default();
+        let mut ids = Vec::new();
+        while let Ok(response) =
+            tokio_test::block_on(read_one_frame(stream, read, &telemetry, None, None))
+        {
+            ids.push(response.id().to_string());
+        }
+        ids
+    }
+
+    #[test]
+    fn test_responses_frame_across_tiny_read_chunks() {
+        let mut stream: &[u8] = b"d2:id5:req-15:value1:1ed2:id5:req-26:statusl4:doneee";
+        let mut read = ReadBuffer::new();
+        read.chunk = 3;
+        assert_eq!(read_all(&mut stream, &mut read), ["req-1", "req-2"]);
+    }
+
+    #[test]
+    fn test_small_read_chunks_do_not_limit_response_size() {
+        // 200KB in 64-byte reads is over 3000 reads for one message.
+        let value = "x".repeat(200 * 1024);
+        let message = format!("d2:id5:req-15:value{}:{value}e", value.len());
+        let mut stream = message.as_bytes();
+        let mut read = ReadBuffer::new();
+        read.chunk = 64;
+        assert_eq!(read_all(&mut stream, &mut read), ["req-1"]);
+    }
+
+    #[test]
+    fn test_batched_requests_wait_for_one_flush() {
+        use tokio::io::AsyncReadExt;
+
+        tokio_test::block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = NReplClient::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (mut server, _) = listener.accept().await.unwrap();
+            let (mut writer, _reader) = client.into_split();
+            let mut buf = [0u8; 1024];
+
+            for id in ["1", "2", "3"] {
+                writer
+                    .send(&crate::ops::describe_request(id, None))
+                    .await
+                    .unwrap();
+            }
+            assert!(writer.has_queued());
+            assert!(server.try_read(&mut buf).is_err(), "nothing written yet");
+            writer.flush().await.unwrap();
+            assert!(!writer.has_queued());
+            let expected = encoded_len(&["1", "2", "3"]);
+            let mut sent = 0;
+            while sent < expected {
+                sent += server.read(&mut buf).await.unwrap();
+            }
+            assert_eq!(sent, expected);
+
+            writer.set_flush_policy(FlushPolicy::Immediate);
+            writer
+                .send(&crate::ops::describe_request("4", None))
+                .await
+                .unwrap();
+            assert!(!writer.has_queued());
+            let n = server.read(&mut buf).await.unwrap();
+            assert_eq!(n, encoded_len(&["4"]));
+        });
+    }
+
+    #[test]
+    fn test_read_and_write_timeouts_catch_a_stalled_peer() {
+        use tokio::io::AsyncWriteExt;
+
+        tokio_test::block_on(async {
+            let (ours, mut theirs) = tokio::io::duplex(64);
+            let (read_half, write_half) = tokio::io::split(ours);
+            let client = NReplClient {
+                read_half: Box::new(read_half),
+                write_half: Box::new(write_half),
+                buffer: ReadBuffer::new(),
+            };
+            let (mut writer, mut reader) = client.into_split();
+            let limit = Duration::from_millis(50);
+            reader.set_read_timeout(limit);
+            writer.set_write_timeout(limit);
+            writer.set_flush_policy(FlushPolicy::Immediate);
+
+            // Half a response, then nothing.
+            theirs.write_all(b"d2:id1:15:val").await.unwrap();
+            let err = reader.next_frame().await.unwrap_err();
+            assert!(err.to_string().contains("read timed out"), "{err}");
+
+            // A request bigger than the pipe, which the peer never reads.
+            let big = crate::ops::eval_request_with_location(
+                "2",
+                "s",
+                "x".repeat(1024),
+                None,
+                None,
+                None,
+            );
+            let err = writer.send(&big).await.unwrap_err();
+            assert!(err.to_string().contains("write timed out"), "{err}");
+            let err = writer
+                .send(&crate::ops::describe_request("3", None))
+                .await
+                .unwrap_err();
+            assert!(err.is_connection_dead(), "{err}");
+        });
+    }
+
+    fn encoded_len(ids: &[&str]) -> usize {
+        ids.iter()
+            .map(|id| {
+                encode_request(&crate::ops::describe_request(*id, None))
+                    .unwrap()
+                    .len()
+            })
+            .sum()
+    }
+
+    fn out(text: &str) -> Response {
+        let frame = format!("d2:id1:13:out{}:{text}e", text.len());
+        crate::codec::decode_response(frame.as_bytes()).unwrap().0
+    }
+
+    #[test]
+    fn test_output_past_the_limit_spills_to_a_file() {
+        let mut kept = EvalAccumulator::new();
+        for _ in 0..MAX_OUTPUT_ENTRIES {
+            kept.push(out("x")).unwrap();
+        }
+        assert!(matches!(
+            kept.push(out("x")),
+            Err(NReplError::Protocol { .. })
+        ));
+
+        let dir = std::env::temp_dir().join(format!("nrepl-spill-test-{}", std::process::id()));
+        let mut spilling = EvalAccumulator::with_sink(OutputSink::File(dir.clone()));
+        for _ in 0..MAX_OUTPUT_ENTRIES {
+            spilling.push(out("x")).unwrap();
+        }
+        spilling.push(out("over\n")).unwrap();
+        // Under the limit again, but it follows spilled output, so it spills.
+        let mut err = out("boom\n");
+        err.err = err.out.take();
+        spilling.push(err).unwrap();
+
+        let result = spilling.finish();
+        assert_eq!(result.output.len(), MAX_OUTPUT_ENTRIES);
+        assert!(result.error.is_empty());
+        let spilled = result.spilled.expect("output spilled");
+        assert_eq!((spilled.stdout_bytes, spilled.stderr_bytes), (5, 5));
+        assert_eq!(
+            std::fs::read_to_string(&spilled.path).unwrap(),
+            "over\nboom\n"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_coalesced_chunks_join_their_unfinished_line() {
+        let mut acc = EvalAccumulator::new().coalesce_lines(true);
+        for chunk in ["hel", "lo", "\n", "next\n", "tail"] {
+            acc.push(out(chunk)).unwrap();
+        }
+        let mut err = out("warn");
+        err.err = err.out.take();
+        acc.push(err).unwrap();
+        // A different stream came between, so this starts its own entry.
+        acc.push(out(" more")).unwrap();
+
+        let result = acc.finish();
+        assert_eq!(result.output, ["hello\n", "next\n", "tail", " more"]);
+        assert_eq!(result.error, ["warn"]);
+
+        let mut plain = EvalAccumulator::new();
+        plain.push(out("hel")).unwrap();
+        plain.push(out("lo")).unwrap();
+        assert_eq!(plain.finish().output, ["hel", "lo"]);
+    }
+
+    #[test]
+    fn test_long_values_are_truncated_with_the_whole_kept() {
+        let value = |text: &str| {
+            let frame = format!("d2:id1:15:value{}:{text}e", text.len());
+            crate::codec::decode_response(frame.as_bytes()).unwrap().0
+        };
+        let mut acc = EvalAccumulator::new().truncate_values(3);
+        acc.push(value("(\"é…\")")).unwrap();
+        let result = acc.finish();
+        // Cut back to a character boundary rather than through the `é`.
+        assert_eq!(result.value.as_deref(), Some("(\""));
+        assert!(result.is_truncated());
+        assert_eq!(result.truncated.as_ref().unwrap().original_bytes, 9);
+        assert_eq!(result.fetch_full_value(), Some("(\"é…\")"));
+
+        let mut short = EvalAccumulator::new().truncate_values(4);
+        short.push(value("42")).unwrap();
+        let result = short.finish();
+        assert!(!result.is_truncated());
+        assert_eq!(result.fetch_full_value(), Some("42"));
+    }
+
+    #[test]
+    fn test_timing_runs_from_send_to_finish() {
+        let mut unsent = EvalAccumulator::new();
+        unsent.push(out("hi\n")).unwrap();
+        assert!(unsent.finish().timing.is_none());
+
+        let mut acc = EvalAccumulator::new();
+        acc.mark_sent();
+        std::thread::sleep(Duration::from_millis(5));
+        acc.push(out("hi\n")).unwrap();
+        acc.push(out("there\n")).unwrap();
+        let timing = acc.finish().timing.expect("timed");
+        let first = timing.first_response_after.expect("answered");
+        assert!(first >= Duration::from_millis(5));
+        assert!(timing.duration >= first);
+        assert!(timing.first_response_at.expect("answered") >= timing.sent_at);
+        assert!(timing.done_at >= timing.sent_at);
+    }
+
+    #[test]
+    fn test_parsed_styles_follow_coalesced_entries() {
+        let mut acc = EvalAccumulator::new()
+            .coalesce_lines(true)
+            .ansi(AnsiPolicy::Parse);
+        for chunk in ["ok \x1b[32m", "pass", "\x1b[0m\n", "\x1b[1mbold\x1b[0m\n"] {
+            acc.push(out(chunk)).unwrap();
+        }
+        let result = acc.finish();
+        assert_eq!(result.output, ["ok pass\n", "bold\n"]);
+        let styles = result.styles.expect("parsed");
+        let ranges: Vec<Vec<_>> = styles
+            .output
+            .iter()
+            .map(|spans| spans.iter().map(|s| (s.range.start, s.range.end)).collect())
+            .collect();
+        assert_eq!(ranges, [[(3, 7)], [(0, 4)]]);
+    }
+
+    #[test]
+    fn test_drained_buffer_gives_back_capacity_a_large_response_took() {
+        let value = "x".repeat(100 * 1024);
+        let frame = format!("d2:id5:req-15:value{}:{value}e", value.len());
+        let mut stream: &[u8] = frame.as_bytes();
+        let mut read = ReadBuffer::new();
+        assert_eq!(read_all(&mut stream, &mut read), ["req-1"]);
+        assert!(read.bytes.is_empty());
+        // The failed read at end of stream came after the release.
+        assert!(read.bytes.capacity() < IDLE_BUFFER_CAPACITY);
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Step debugging through cider-nrepl's debug middleware
+//!
+//! Code instrumented with `#dbg` or `#break` stops at each breakpoint it
+//! reaches. The server reports a stop not on the eval that hit it but on a
+//! long-lived `init-debugger` request, which never sends `done`: every stop is
+//! one more message with that request's id, carrying the value at the break,
+//! the locals, and a `key`. The eval then blocks until a `debug-input` with
+//! the same key says how to go on ([`DebugCommand`]).
+//!
+//! The worker keeps the `init-debugger` request open and hands each stop to
+//! the caller as a [`DebugBreak`]. While an eval is stopped its timeout is
+//! suspended, as it is while waiting on stdin.
+
+use crate::message::{BencodeValue, Response};
+use std::collections::BTreeMap;
+
+/// One stop at a breakpoint, waiting for a [`DebugCommand`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugBreak {
+    /// Names this stop; the `debug-input` answering it must carry it back.
+    pub key: String,
+    /// The value of the form the debugger stopped after, printed.
+    pub value: Option<String>,
+    /// Where that form sits in the instrumented top-level form: the child
+    /// index taken at each level, outermost first.
+    pub coor: Vec<u32>,
+    /// Source of the instrumented top-level form.
+    pub code: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    /// Locals in scope, as `(name, printed value)`.
+    pub locals: Vec<(String, String)>,
+    /// What the server accepts as the answer.
+    pub input_type: DebugInputType,
+    /// Text to show when asking for an expression.
+    pub prompt: Option<String>,
+    /// Wire id of the eval that hit the breakpoint.
+    pub original_id: Option<String>,
+    /// Namespace the instrumented code was evaluated in.
+    pub original_ns: Option<String>,
+}
+
+/// What a [`DebugBreak`] accepts as its answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub enum DebugInputType {
+    /// One of these commands, keyed by the shortcut an editor would bind
+    /// (`"n"` → `"next"`).
+    Commands(BTreeMap<String, String>),
+    /// A Clojure expression, sent as [`DebugCommand::Expression`].
+    Expression,
+}
+
+impl Default for DebugInputType {
+    fn default() -> Self {
+        DebugInputType::Commands(BTreeMap::new())
+    }
+}
+
+/// How to go on from a [`DebugBreak`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugCommand {
+    /// Step to the next form.
+    Next,
+    /// Step into the function called by the current form.
+    In,
+    /// Run to the end of the enclosing sexp.
+    Out,
+    /// Run to the form at point (the break's `coor`).
+    Here,
+    /// Run to the next breakpoint.
+    Continue,
+    /// Run to the end, skipping every remaining breakpoint.
+    ContinueAll,
+    /// Evaluate code with the break's locals in scope. The result comes back
+    /// as a new [`DebugBreak`] whose `value` is the result.
+    Eval(String),
+    /// Replace the value at the break with the result of evaluating code, and
+    /// continue.
+    Inject(String),
+    /// Answer a break whose `input_type` is [`DebugInputType::Expression`].
+    Expression(String),
+    /// Abort the eval.
+    Quit,
+}
+
+impl DebugCommand {
+    /// The `input` value cider-nrepl reads for this command.
+    pub(crate) fn input(&self) -> String {
+        match self {
+            DebugCommand::Next => ":next".to_string(),
+            DebugCommand::In => ":in".to_string(),
+            DebugCommand::Out => ":out".to_string(),
+            DebugCommand::Here => ":here".to_string(),
+            DebugCommand::Continue => ":continue".to_string(),
+            DebugCommand::ContinueAll => ":continue-all".to_string(),
+            DebugCommand::Eval(code) => {
+                format!("{{:response :eval, :code {}}}", clojure_string(code))
+            }
+            DebugCommand::Inject(code) => {
+                format!("{{:response :inject, :code {}}}", clojure_string(code))
+            }
+            DebugCommand::Expression(code) => code.clone(),
+            DebugCommand::Quit => ":quit".to_string(),
+        }
+    }
+}
+
+/// `s` as a Clojure string literal.
+pub(crate) fn clojure_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl DebugBreak {
+    /// Read a message from the `init-debugger` stream. `None` for anything
+    /// that is not a stop (no `key`).
+    pub(crate) fn from_response(r: &Response) -> Option<Self> {
+        let key = r.key.clone()?;
+        let to_u32 = |n: Option<i64>| n.and_then(|n| u32::try_from(n).ok());
+        Some(DebugBreak {
+            key,
+            value: r.debug_value.clone(),
+            coor: r
+                .coor
+                .iter()
+                .flatten()
+                .filter_map(|&n| u32::try_from(n).ok())
+                .collect(),
+            code: r.code.clone(),
+            file: r.file.clone(),
+            line: to_u32(r.line),
+            column: to_u32(r.column),
+            locals: r.locals.clone().unwrap_or_default(),
+            input_type: r.input_type.clone().unwrap_or_default(),
+            prompt: r.prompt.clone(),
+            original_id: r.original_id.clone(),
+            original_ns: r.original_ns.clone(),
+        })
+    }
+}
+
+/// Read `input-type`: a dict of shortcut to command, a plain list of
+/// commands (each its own shortcut), or `expression`.
+pub(crate) fn input_type_from_bencode(value: BencodeValue) -> Option<DebugInputType> {
+    match value {
+        BencodeValue::Dict(m) => Some(DebugInputType::Commands(
+            m.into_iter()
+                .map(|(k, v)| (k, v.to_string_repr()))
+                .collect(),
+        )),
+        BencodeValue::List(items) => Some(DebugInputType::Commands(
+            items
+                .iter()
+                .map(|v| (v.to_string_repr(), v.to_string_repr()))
+                .collect(),
+        )),
+        BencodeValue::String(s) if s == "expression" => Some(DebugInputType::Expression),
+        _ => None,
+    }
+}
+
+/// Read `locals`: a list of `[name value]` pairs. Malformed entries are
+/// skipped.
+pub(crate) fn locals_from_bencode(value: BencodeValue) -> Vec<(String, String)> {
+    let BencodeValue::List(items) = value else {
+        return Vec::new();
+    };
+    items
+        .into_iter()
+        .filter_map(|item| match item {
+            BencodeValue::List(pair) if pair.len() == 2 => {
+                Some((pair[0].to_string_repr(), pair[1].to_string_repr()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(frame: &str) -> Response {
+        crate::codec::decode_response(frame.as_bytes()).unwrap().0
+    }
+
+    #[test]
+    fn test_break_from_debug_message() {
+        let r = decode(concat!(
+            "d",
+            "4:code20:(defn f [x] (+ x 1))",
+            "4:coorli3ei2ee",
+            "11:debug-value1:2",
+            "2:id5:req-1",
+            "10:input-typed1:c8:continue1:n4:nexte",
+            "3:key4:k-42",
+            "4:linei7e",
+            "6:localsll1:x1:1ee",
+            "11:original-id5:req-9",
+            "6:statusl16:need-debug-inpute",
+            "e"
+        ));
+        let brk = DebugBreak::from_response(&r).expect("a stop");
+        assert_eq!(brk.key, "k-42");
+        assert_eq!(brk.value.as_deref(), Some("2"));
+        assert_eq!(brk.coor, vec![3, 2]);
+        assert_eq!(brk.line, Some(7));
+        assert_eq!(brk.locals, vec![("x".to_string(), "1".to_string())]);
+        assert_eq!(brk.original_id.as_deref(), Some("req-9"));
+        let DebugInputType::Commands(commands) = &brk.input_type else {
+            panic!("expected commands, got {:?}", brk.input_type);
+        };
+        assert_eq!(commands.get("n").map(String::as_str), Some("next"));
+
+        let prompt = decode("d2:id5:req-110:input-type10:expression3:key4:k-436:prompt6:Eval: e");
+        let brk = DebugBreak::from_response(&prompt).expect("a stop");
+        assert_eq!(brk.input_type, DebugInputType::Expression);
+        assert_eq!(brk.prompt.as_deref(), Some("Eval: "));
+
+        // Anything without a key is not a stop.
+        assert!(DebugBreak::from_response(&decode("d2:id5:req-1e")).is_none());
+    }
+
+    #[test]
+    fn test_command_input() {
+        assert_eq!(DebugCommand::Next.input(), ":next");
+        assert_eq!(DebugCommand::ContinueAll.input(), ":continue-all");
+        assert_eq!(
+            DebugCommand::Eval(r#"(str "a\b")"#.to_string()).input(),
+            r#"{:response :eval, :code "(str \"a\\b\")"}"#
+        );
+        assert_eq!(
+            DebugCommand::Expression("(inc x)".to_string()).input(),
+            "(inc x)"
+        );
+    }
+}
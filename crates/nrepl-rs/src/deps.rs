@@ -0,0 +1,138 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Loading libraries into a running REPL
+//!
+//! Clojure 1.12's `clojure.repl.deps/add-libs` resolves Maven coordinates
+//! and adds them, with their dependencies, to the REPL's classloader, so a
+//! library such as `criterium` can be used without a restart. Before 1.12
+//! the same function lived in the `add-lib3` branch of tools.deps, as
+//! `clojure.tools.deps.alpha.repl/add-libs`, which is tried when the first
+//! is missing. Both need the REPL to run from the Clojure CLI, which is
+//! what knows how to download.
+
+use crate::debugger::clojure_string;
+
+/// Where the functions that can load libraries live, in the order tried.
+const ADD_LIBS_FNS: &[&str] = &[
+    "clojure.repl.deps/add-libs",
+    "clojure.tools.deps.alpha.repl/add-libs",
+];
+
+/// The outcome of [`Worker::add_libs`](crate::worker::Worker::add_libs).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddLibsReport {
+    /// The function that loaded them, such as `clojure.repl.deps/add-libs`.
+    pub via: String,
+    /// Every library added to the classpath, requested or pulled in as a
+    /// dependency.
+    pub added: Vec<String>,
+    /// Requested libraries that were not added because the classpath had
+    /// them already.
+    pub already_present: Vec<String>,
+}
+
+impl AddLibsReport {
+    /// Whether `lib` was added by this call.
+    #[must_use]
+    pub fn was_added(&self, lib: &str) -> bool {
+        let lib = full_lib(lib);
+        self.added.iter().any(|a| full_lib(a) == lib)
+    }
+
+    /// Read the printed value of [`add_libs_form`]: the function used, then
+    /// the libraries it added, split by `;`. `libs` are the requested names.
+    pub(crate) fn parse(value: &str, libs: &[&str]) -> Option<Self> {
+        let body = value.strip_prefix('"')?.strip_suffix('"')?;
+        let (via, added) = body.split_once(';')?;
+        let mut report = Self {
+            via: via.to_string(),
+            added: added.split_whitespace().map(str::to_string).collect(),
+            already_present: Vec::new(),
+        };
+        report.already_present = libs
+            .iter()
+            .filter(|lib| !report.was_added(lib))
+            .map(|lib| (*lib).to_string())
+            .collect();
+        Some(report)
+    }
+}
+
+/// tools.deps reads an unqualified lib `foo` as `foo/foo`.
+fn full_lib(lib: &str) -> String {
+    if lib.contains('/') {
+        lib.to_string()
+    } else {
+        format!("{lib}/{lib}")
+    }
+}
+
+/// Whether `lib` can be spliced into a form as a quoted symbol.
+pub(crate) fn is_lib_name(lib: &str) -> bool {
+    let lib_char = |c: char| c.is_alphanumeric() || "-_.".contains(c);
+    lib.split('/').count() <= 2
+        && lib
+            .split('/')
+            .all(|part| !part.is_empty() && part.chars().all(lib_char))
+}
+
+/// Clojure form loading `coords` (lib, Maven version) with the first of
+/// [`ADD_LIBS_FNS`] that resolves, printing what it added in the format
+/// [`AddLibsReport::parse`] reads. Throws if none does.
+pub(crate) fn add_libs_form(coords: &[(&str, &str)]) -> String {
+    let coords: String = coords
+        .iter()
+        .map(|(lib, version)| format!("{lib} {{:mvn/version {}}}", clojure_string(version)))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let fns: String = ADD_LIBS_FNS.iter().map(|f| format!("{f} ")).collect();
+    format!(
+        "(let [f (some (fn [s] \
+                         (try (require (symbol (namespace s))) (some-> (resolve s) (vector s)) \
+                              (catch Throwable _ nil))) \
+                       '[{fns}])] \
+           (if-let [[add via] f] \
+             (str via \";\" (apply str (interpose \" \" (add '{{{coords}}})))) \
+             (throw (ex-info \"add-libs needs Clojure 1.12 or tools.deps add-lib3, run from the Clojure CLI\" {{}}))))",
+        fns = fns.trim_end()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_libs_form_and_report() {
+        let form = add_libs_form(&[("criterium/criterium", "0.4.6")]);
+        assert!(
+            form.contains("'{criterium/criterium {:mvn/version \"0.4.6\"}}"),
+            "{form}"
+        );
+        assert!(
+            form.contains("'[clojure.repl.deps/add-libs clojure.tools.deps.alpha.repl/add-libs]")
+        );
+        assert!(is_lib_name("org.clojure/data.json"));
+        assert!(!is_lib_name("evil) (System/exit 0"));
+
+        let report = AddLibsReport::parse(
+            r#""clojure.repl.deps/add-libs;criterium/criterium org.clojure/tools.reader""#,
+            &["criterium", "org.clojure/data.json"],
+        )
+        .unwrap();
+        assert_eq!(report.via, "clojure.repl.deps/add-libs");
+        assert!(report.was_added("criterium"));
+        assert_eq!(report.already_present, ["org.clojure/data.json"]);
+        assert_eq!(AddLibsReport::parse("nil", &[]), None);
+    }
+}
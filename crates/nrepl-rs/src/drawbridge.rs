@@ -0,0 +1,466 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! nREPL over HTTP, as served by drawbridge (feature `drawbridge`)
+//!
+//! drawbridge mounts nREPL as a Ring handler, for servers that are only
+//! reachable over HTTP (behind a load balancer, or on a platform that
+//! routes nothing else). A request is a POST whose form parameters are the
+//! message's fields; any request, POST or GET, answers with a JSON array of
+//! the responses queued for the client since the last one. The client is
+//! recognised by its Ring session cookie, and a GET with no parameters is
+//! how it polls.
+//!
+//! [`Worker::connect_http`](crate::worker::Worker::connect_http) bridges
+//! this onto the bencode stream the rest of the crate speaks: a
+//! task reads the frames the worker writes, POSTs each, and polls between
+//! them, writing every response it receives back as bencode. Polls ask the
+//! server to wait [`POLL_WAIT`] for responses, so a new request goes out
+//! within about that long. [`connect_blocking`](crate::worker::Worker::connect_blocking)
+//! connects this way too when given an `http://` address.
+//!
+//! Only plain HTTP is built in. For an `https://` endpoint, terminate TLS
+//! locally (`stunnel`, or an SSH tunnel to the load balancer) and connect
+//! to that.
+//!
+//! Form parameters are flat, so fields of a message that are dicts are not
+//! sent, and lists go as repeated parameters. drawbridge hands every field
+//! to nREPL as a string.
+
+use crate::codec::{FrameScanner, decode_value};
+use crate::error::{NReplError, Result};
+use crate::message::BencodeValue;
+use crate::trace::event;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+
+/// How long each poll asks the server to wait for a response, sent as
+/// drawbridge's `REPL-Response-Timeout` header.
+pub const POLL_WAIT: Duration = Duration::from_millis(100);
+
+/// How long to wait before polling again when a poll brought nothing back,
+/// for servers that answer at once whatever the header says. A request to
+/// send ends the wait early.
+const POLL_IDLE: Duration = Duration::from_millis(200);
+
+/// Buffer between the worker and the bridge, each way.
+const BRIDGE_BUFFER: usize = 64 * 1024;
+
+/// The longest HTTP response accepted, matching the bencode reader's limit.
+const MAX_HTTP_RESPONSE: usize = 10 * 1024 * 1024;
+
+/// Poll the endpoint at `url` once, to check it answers and to start the
+/// server-side client, then bridge it in a task on the current Tokio
+/// runtime. Returns the bridge's local end.
+pub(crate) async fn connect(url: &str) -> Result<DuplexStream> {
+    let mut endpoint = Endpoint::parse(url)?;
+    let responses = endpoint.exchange(None).await?;
+    let (local, remote) = tokio::io::duplex(BRIDGE_BUFFER);
+    let (from_worker, mut to_worker) = tokio::io::split(remote);
+    deliver(&mut to_worker, responses).await?;
+    tokio::spawn(bridge(endpoint, from_worker, to_worker));
+    Ok(local)
+}
+
+/// Where the endpoint is, and the cookies that tie requests to one
+/// server-side client.
+#[derive(Debug)]
+struct Endpoint {
+    /// The URL's host, and port if it has one, for the `Host` header.
+    host: String,
+    /// `host:port` to open connections to.
+    authority: String,
+    /// The path and query to request, starting with `/`.
+    target: String,
+    cookies: BTreeMap<String, String>,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Self> {
+        let invalid = |why: &str| {
+            NReplError::Connection(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("drawbridge URL {why}: {url}"),
+            ))
+        };
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err(if url.starts_with("https://") {
+                invalid("uses https, which needs TLS terminated locally")
+            } else {
+                invalid("must start with http://")
+            });
+        };
+        let (authority, target) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(invalid("has no host"));
+        }
+        let host = authority.to_string();
+        let authority = if authority.ends_with(']') || !authority.contains(':') {
+            format!("{authority}:80")
+        } else {
+            host.clone()
+        };
+        Ok(Self {
+            host,
+            authority,
+            target: target.to_string(),
+            cookies: BTreeMap::new(),
+        })
+    }
+
+    /// POST `message`, or poll when there is none, and return the
+    /// responses the server answered with.
+    async fn exchange(&mut self, message: Option<&BencodeValue>) -> Result<Vec<BencodeValue>> {
+        let mut request = match message {
+            Some(_) => format!("POST {} HTTP/1.1\r\n", self.target),
+            None => format!("GET {} HTTP/1.1\r\n", self.target),
+        };
+        let _ = write!(
+            request,
+            "Host: {}\r\nAccept: application/json\r\nConnection: close\r\n\
+             REPL-Response-Timeout: {}\r\n",
+            self.host,
+            POLL_WAIT.as_millis()
+        );
+        if !self.cookies.is_empty() {
+            let cookies: Vec<String> = self
+                .cookies
+                .iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect();
+            let _ = write!(request, "Cookie: {}\r\n", cookies.join("; "));
+        }
+        let body = message.map(form_encode).unwrap_or_default();
+        if message.is_some() {
+            let _ = write!(
+                request,
+                "Content-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n",
+                body.len()
+            );
+        }
+        request.push_str("\r\n");
+        request.push_str(&body);
+
+        let mut stream = TcpStream::connect(&self.authority).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut raw = Vec::new();
+        (&mut stream)
+            .take(MAX_HTTP_RESPONSE as u64 + 1)
+            .read_to_end(&mut raw)
+            .await?;
+        if raw.len() > MAX_HTTP_RESPONSE {
+            return Err(http_error("response over the 10MB limit"));
+        }
+        let body = self.read_response(&raw)?;
+        parse_responses(&body)
+    }
+
+    /// Check the status of `raw`, keep any cookies it sets, and return its
+    /// body.
+    fn read_response(&mut self, raw: &[u8]) -> Result<Vec<u8>> {
+        let head_end = raw
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or_else(|| http_error("response ended inside its head"))?;
+        let head = String::from_utf8_lossy(&raw[..head_end]);
+        let mut lines = head.lines();
+        let status_line = lines.next().unwrap_or_default();
+        if status_line.split_whitespace().nth(1) != Some("200") {
+            return Err(http_error(format!("server answered {status_line}")));
+        }
+        let mut chunked = false;
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("set-cookie") {
+                let pair = value.split(';').next().unwrap_or_default();
+                if let Some((name, value)) = pair.split_once('=') {
+                    self.cookies
+                        .insert(name.trim().to_string(), value.trim().to_string());
+                }
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            }
+        }
+        let body = &raw[head_end + 4..];
+        if chunked {
+            dechunk(body).ok_or_else(|| http_error("malformed chunked body"))
+        } else {
+            Ok(body.to_vec())
+        }
+    }
+}
+
+fn http_error(why: impl std::fmt::Display) -> NReplError {
+    NReplError::Connection(std::io::Error::other(format!("drawbridge: {why}")))
+}
+
+/// Join the chunks of a `Transfer-Encoding: chunked` body.
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = size.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(out);
+        }
+        out.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+/// A message's fields as `application/x-www-form-urlencoded`.
+fn form_encode(message: &BencodeValue) -> String {
+    let BencodeValue::Dict(fields) = message else {
+        return String::new();
+    };
+    let mut pairs = Vec::new();
+    for (key, value) in fields {
+        match value {
+            BencodeValue::List(items) => {
+                for item in items {
+                    if let Some(text) = form_text(item) {
+                        pairs.push(format!("{}={}", form_escape(key), form_escape(&text)));
+                    }
+                }
+            }
+            other => {
+                if let Some(text) = form_text(other) {
+                    pairs.push(format!("{}={}", form_escape(key), form_escape(&text)));
+                }
+            }
+        }
+    }
+    pairs.join("&")
+}
+
+fn form_text(value: &BencodeValue) -> Option<String> {
+    match value {
+        BencodeValue::Int(i) => Some(i.to_string()),
+        BencodeValue::List(_) | BencodeValue::Dict(_) => None,
+        other => other.to_string_lossy().map(String::from),
+    }
+}
+
+fn form_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'*' => {
+                out.push(char::from(byte));
+            }
+            b' ' => out.push('+'),
+            _ => {
+                let _ = write!(out, "%{byte:02X}");
+            }
+        }
+    }
+    out
+}
+
+/// Read drawbridge's JSON array of responses as bencode values.
+fn parse_responses(body: &[u8]) -> Result<Vec<BencodeValue>> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(Vec::new());
+    }
+    let responses: Vec<serde_json::Value> = serde_json::from_slice(body)
+        .map_err(|e| http_error(format!("response is not a JSON array: {e}")))?;
+    Ok(responses.into_iter().filter_map(from_json).collect())
+}
+
+fn from_json(value: serde_json::Value) -> Option<BencodeValue> {
+    use serde_json::Value;
+    Some(match value {
+        Value::Null => return None,
+        Value::Bool(b) => BencodeValue::String(b.to_string()),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => BencodeValue::Int(i),
+            None => BencodeValue::String(n.to_string()),
+        },
+        Value::String(s) => BencodeValue::String(s),
+        Value::Array(items) => {
+            BencodeValue::List(items.into_iter().filter_map(from_json).collect())
+        }
+        Value::Object(fields) => BencodeValue::Dict(
+            fields
+                .into_iter()
+                .filter_map(|(k, v)| Some((k, from_json(v)?)))
+                .collect(),
+        ),
+    })
+}
+
+/// Carry messages between the worker's end of the duplex and the endpoint
+/// until either side fails or goes away. Dropping `to_worker` on the way
+/// out is what tells the worker the connection closed.
+async fn bridge(
+    mut endpoint: Endpoint,
+    from_worker: ReadHalf<DuplexStream>,
+    mut to_worker: WriteHalf<DuplexStream>,
+) {
+    let (outgoing_tx, mut outgoing) = unbounded_channel();
+    let reader = tokio::spawn(read_requests(from_worker, outgoing_tx));
+    let result: Result<()> = async {
+        loop {
+            while let Ok(message) = outgoing.try_recv() {
+                deliver(&mut to_worker, endpoint.exchange(Some(&message)).await?).await?;
+            }
+            let polled = endpoint.exchange(None).await?;
+            if polled.is_empty() {
+                tokio::select! {
+                    message = outgoing.recv() => match message {
+                        Some(message) => {
+                            deliver(&mut to_worker, endpoint.exchange(Some(&message)).await?)
+                                .await?;
+                        }
+                        None => return Ok(()),
+                    },
+                    () = tokio::time::sleep(POLL_IDLE) => {}
+                }
+            } else {
+                deliver(&mut to_worker, polled).await?;
+            }
+        }
+    }
+    .await;
+    if let Err(e) = result {
+        event!(DEBUG, "drawbridge bridge closed", error = e);
+    }
+    reader.abort();
+}
+
+async fn deliver(
+    to_worker: &mut WriteHalf<DuplexStream>,
+    responses: Vec<BencodeValue>,
+) -> Result<()> {
+    for response in responses {
+        to_worker.write_all(&response.encode()).await?;
+    }
+    Ok(())
+}
+
+/// Split what the worker writes into messages. Ends when the worker closes
+/// its end, which closes `outgoing` and so ends the bridge.
+async fn read_requests(
+    mut from_worker: ReadHalf<DuplexStream>,
+    outgoing: UnboundedSender<BencodeValue>,
+) -> Result<()> {
+    let mut buffer = Vec::new();
+    let mut scanner = FrameScanner::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        while scanner.scan(&buffer)?.is_some() {
+            let (message, len) = decode_value(&buffer)?;
+            buffer.drain(..len);
+            if outgoing.send(message).is_err() {
+                return Ok(());
+            }
+        }
+        let n = from_worker.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::NReplClient;
+    use crate::ops;
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// A drawbridge endpoint that answers an eval with its value, and
+    /// records the bodies POSTed to it.
+    fn fake_drawbridge() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/repl", listener.local_addr().unwrap());
+        let posted = Arc::new(Mutex::new(Vec::new()));
+        let record = Arc::clone(&posted);
+        std::thread::spawn(move || {
+            let mut queued = String::new();
+            for conn in listener.incoming() {
+                let mut conn = conn.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = conn.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let request = String::from_utf8(request).unwrap();
+                let body = request.split("\r\n\r\n").nth(1).unwrap_or_default();
+                let mut answer = String::new();
+                if request.starts_with("POST") {
+                    assert!(request.contains("Cookie: ring-session=abc"), "{request}");
+                    record.lock().unwrap().push(body.to_string());
+                    queued = r#"{"id":"7","value":"3"},{"id":"7","status":["done"]}"#.into();
+                } else {
+                    answer = std::mem::take(&mut queued);
+                }
+                let reply = format!(
+                    "HTTP/1.1 200 OK\r\nSet-Cookie: ring-session=abc; Path=/\r\n\
+                     Content-Type: application/json\r\n\r\n[{answer}]"
+                );
+                conn.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+        (url, posted)
+    }
+
+    #[test]
+    fn test_eval_over_drawbridge() {
+        let (url, posted) = fake_drawbridge();
+        tokio_test::block_on(async {
+            let client = NReplClient::connect_http(&url).await.unwrap();
+            let (mut writer, mut reader) = client.into_split();
+            writer
+                .send(&ops::eval_request_with_location(
+                    "7", "sess", "(+ 1 2)", None, None, None,
+                ))
+                .await
+                .unwrap();
+            writer.flush().await.unwrap();
+            let value = reader.next_frame().await.unwrap().decode().unwrap();
+            assert_eq!(value.value.as_deref(), Some("3"));
+            let done = reader.next_frame().await.unwrap();
+            assert_eq!(done.status(), ["done"]);
+        });
+        let posted = posted.lock().unwrap();
+        assert!(posted[0].contains("code=%28%2B+1+2%29"), "{posted:?}");
+        assert!(posted[0].contains("op=eval"), "{posted:?}");
+    }
+
+    #[test]
+    fn test_url_and_chunked_body() {
+        let endpoint = Endpoint::parse("http://repl.example.com/repl").unwrap();
+        assert_eq!(endpoint.authority, "repl.example.com:80");
+        assert!(Endpoint::parse("https://repl.example.com/repl").is_err());
+        assert_eq!(
+            dechunk(b"4\r\n[{}]\r\n1\r\n \r\n0\r\n\r\n").unwrap(),
+            b"[{}] "
+        );
+    }
+}
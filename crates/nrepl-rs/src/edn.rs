@@ -0,0 +1,545 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! EDN parsing of printed eval values (feature `edn`)
+//!
+//! nREPL returns values as printed strings. [`parse`] (or
+//! [`EvalResult::value_edn`](crate::EvalResult::value_edn)) reads one back into
+//! an [`EdnValue`] so callers can walk maps and vectors instead of scraping
+//! text.
+//!
+//! Beyond plain EDN it accepts what Clojure's printer commonly produces: vars
+//! (`#'user/f`), namespaced maps (`#:user{:a 1}`, keys qualified on read),
+//! `##Inf`/`##NaN`, and reader objects such as `#object[...]`, which arrive as
+//! [`EdnValue::Tagged`]. Anything else unreadable, such as a regex literal, is
+//! a parse error.
+
+use crate::error::{NReplError, Result};
+
+/// Deepest nesting accepted, so a hostile value cannot overflow the stack.
+const MAX_DEPTH: usize = 512;
+
+/// A parsed EDN value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EdnValue {
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    /// A number kept as written because it has no exact Rust counterpart:
+    /// `1N`, `1.5M`, `1/3`, or an integer too big for `i64`.
+    BigNumber(String),
+    Char(char),
+    String(String),
+    Symbol(String),
+    /// A keyword, without its leading `:`. An auto-resolved keyword keeps
+    /// its second: `::auto` is `":auto"`.
+    Keyword(String),
+    List(Vec<EdnValue>),
+    Vector(Vec<EdnValue>),
+    Set(Vec<EdnValue>),
+    /// Entries in printed order.
+    Map(Vec<(EdnValue, EdnValue)>),
+    /// `#tag value`, e.g. `#inst "..."`, `#uuid "..."` or `#object[...]`.
+    Tagged(String, Box<EdnValue>),
+    /// `#'ns/name`, as Clojure prints a var (the value of a `def`).
+    Var(String),
+}
+
+impl EdnValue {
+    /// The value under `key` if this is a map.
+    #[must_use]
+    pub fn get(&self, key: &EdnValue) -> Option<&EdnValue> {
+        match self {
+            EdnValue::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// The value under keyword `:name` if this is a map.
+    #[must_use]
+    pub fn get_keyword(&self, name: &str) -> Option<&EdnValue> {
+        match self {
+            EdnValue::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| matches!(k, EdnValue::Keyword(kw) if kw == name))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// The elements of a list, vector or set.
+    #[must_use]
+    pub fn as_seq(&self) -> Option<&[EdnValue]> {
+        match self {
+            EdnValue::List(items) | EdnValue::Vector(items) | EdnValue::Set(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            EdnValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            EdnValue::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Integers are widened, so `1` and `1.0` both read as `1.0`.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            EdnValue::Integer(n) => Some(*n as f64),
+            EdnValue::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            EdnValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn is_nil(&self) -> bool {
+        matches!(self, EdnValue::Nil)
+    }
+}
+
+/// Parse a single EDN value. Surrounding whitespace and comments are allowed;
+/// anything else after the value is an error.
+///
+/// # Errors
+///
+/// Returns [`NReplError::Codec`] with the byte offset of the problem if `input`
+/// is not one readable value.
+pub fn parse(input: &str) -> Result<EdnValue> {
+    let mut parser = Parser { input, pos: 0 };
+    let value = parser.value(0)?;
+    parser.skip_ws();
+    if parser.pos < input.len() {
+        return Err(parser.error("unexpected trailing input"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ',' | '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';')
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> NReplError {
+        NReplError::codec(format!("invalid EDN: {message}"), self.pos)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Skip whitespace, commas and `;` comments.
+    fn skip_ws(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == ',' {
+                self.bump();
+            } else if c == ';' {
+                while let Some(c) = self.bump() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Everything up to the next delimiter.
+    fn token(&mut self) -> &str {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| !is_delimiter(c)) {
+            self.bump();
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn value(&mut self, depth: usize) -> Result<EdnValue> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+        self.skip_ws();
+        let Some(c) = self.peek() else {
+            return Err(self.error("unexpected end of input"));
+        };
+        match c {
+            '(' => {
+                self.bump();
+                Ok(EdnValue::List(self.seq(')', depth)?))
+            }
+            '[' => {
+                self.bump();
+                Ok(EdnValue::Vector(self.seq(']', depth)?))
+            }
+            '{' => {
+                self.bump();
+                self.map(depth)
+            }
+            '"' => {
+                self.bump();
+                self.string().map(EdnValue::String)
+            }
+            '\\' => {
+                self.bump();
+                self.character()
+            }
+            '#' => {
+                self.bump();
+                self.dispatch(depth)
+            }
+            ')' | ']' | '}' => Err(self.error("unexpected closing delimiter")),
+            _ => self.atom(),
+        }
+    }
+
+    fn seq(&mut self, close: char, depth: usize) -> Result<Vec<EdnValue>> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_ws();
+            match self.peek() {
+                Some(c) if c == close => {
+                    self.bump();
+                    return Ok(items);
+                }
+                // `#_` before a closing delimiter leaves nothing to return, so
+                // discard here rather than in `dispatch`.
+                Some('#') if self.input[self.pos..].starts_with("#_") => {
+                    self.pos += 2;
+                    self.value(depth + 1)?;
+                }
+                Some(_) => items.push(self.value(depth + 1)?),
+                None => return Err(self.error("unclosed collection")),
+            }
+        }
+    }
+
+    fn map(&mut self, depth: usize) -> Result<EdnValue> {
+        let items = self.seq('}', depth)?;
+        if items.len() % 2 != 0 {
+            return Err(self.error("map has an odd number of forms"));
+        }
+        let mut entries = Vec::with_capacity(items.len() / 2);
+        let mut items = items.into_iter();
+        while let (Some(k), Some(v)) = (items.next(), items.next()) {
+            entries.push((k, v));
+        }
+        Ok(EdnValue::Map(entries))
+    }
+
+    /// After `#`.
+    fn dispatch(&mut self, depth: usize) -> Result<EdnValue> {
+        match self.peek() {
+            Some('{') => {
+                self.bump();
+                Ok(EdnValue::Set(self.seq('}', depth)?))
+            }
+            Some('_') => {
+                self.bump();
+                self.value(depth + 1)?;
+                self.value(depth)
+            }
+            Some('\'') => {
+                self.bump();
+                let name = self.token();
+                if name.is_empty() {
+                    return Err(self.error("empty var name"));
+                }
+                Ok(EdnValue::Var(name.to_string()))
+            }
+            Some('#') => {
+                self.bump();
+                match self.token() {
+                    "Inf" => Ok(EdnValue::Float(f64::INFINITY)),
+                    "-Inf" => Ok(EdnValue::Float(f64::NEG_INFINITY)),
+                    "NaN" => Ok(EdnValue::Float(f64::NAN)),
+                    _ => Err(self.error("unknown symbolic value")),
+                }
+            }
+            Some(':') => {
+                self.bump();
+                let ns = self.token().to_string();
+                self.skip_ws();
+                if ns.is_empty() || self.bump() != Some('{') {
+                    return Err(self.error("malformed namespaced map"));
+                }
+                let EdnValue::Map(entries) = self.map(depth)? else {
+                    unreachable!("map() only returns maps");
+                };
+                let entries = entries
+                    .into_iter()
+                    .map(|(k, v)| (qualify(&ns, k), v))
+                    .collect();
+                Ok(EdnValue::Map(entries))
+            }
+            Some(c) if c.is_alphabetic() => {
+                let tag = self.token().to_string();
+                let value = self.value(depth + 1)?;
+                Ok(EdnValue::Tagged(tag, Box::new(value)))
+            }
+            _ => Err(self.error("unsupported dispatch")),
+        }
+    }
+
+    /// After the opening `"`.
+    fn string(&mut self) -> Result<String> {
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(out),
+                Some('\\') => out.push(match self.bump() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('u') => self.unicode_escape()?,
+                    Some(c @ ('"' | '\\')) => c,
+                    _ => return Err(self.error("invalid string escape")),
+                }),
+                Some(c) => out.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    /// After `\u`: four hex digits.
+    fn unicode_escape(&mut self) -> Result<char> {
+        let hex = self.input.get(self.pos..self.pos + 4).unwrap_or_default();
+        let c = u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(c)
+    }
+
+    /// After `\`. The first character is always part of the literal, so `\(`
+    /// and `\,` read as themselves.
+    fn character(&mut self) -> Result<EdnValue> {
+        let start = self.pos;
+        if self.bump().is_none() {
+            return Err(self.error("unexpected end of input"));
+        }
+        while self.peek().is_some_and(|c| !is_delimiter(c)) {
+            self.bump();
+        }
+        let name = &self.input[start..self.pos];
+        let mut chars = name.chars();
+        let c = match (chars.next(), chars.as_str()) {
+            (Some(c), "") => c,
+            _ => match name {
+                "newline" => '\n',
+                "space" => ' ',
+                "tab" => '\t',
+                "return" => '\r',
+                "backspace" => '\u{8}',
+                "formfeed" => '\u{c}',
+                _ if name.len() == 5 && name.starts_with('u') => {
+                    u32::from_str_radix(&name[1..], 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| self.error("invalid unicode character"))?
+                }
+                _ => return Err(self.error("unknown character name")),
+            },
+        };
+        Ok(EdnValue::Char(c))
+    }
+
+    /// A symbol, keyword, number, `nil`, `true` or `false`.
+    fn atom(&mut self) -> Result<EdnValue> {
+        let start = self.pos;
+        let token = self.token();
+        let value = match token {
+            "nil" => EdnValue::Nil,
+            "true" => EdnValue::Bool(true),
+            "false" => EdnValue::Bool(false),
+            _ if token.starts_with(':') => {
+                let name = &token[1..];
+                if name.trim_start_matches(':').is_empty() {
+                    self.pos = start;
+                    return Err(self.error("empty keyword"));
+                }
+                EdnValue::Keyword(name.to_string())
+            }
+            _ if starts_number(token) => match number(token) {
+                Some(n) => n,
+                None => {
+                    self.pos = start;
+                    return Err(self.error("invalid number"));
+                }
+            },
+            _ => EdnValue::Symbol(token.to_string()),
+        };
+        Ok(value)
+    }
+}
+
+fn starts_number(token: &str) -> bool {
+    let digits = token.strip_prefix(['+', '-']).unwrap_or(token);
+    digits.starts_with(|c: char| c.is_ascii_digit())
+}
+
+fn number(token: &str) -> Option<EdnValue> {
+    let body = token.strip_prefix('+').unwrap_or(token);
+    if let Some(digits) = body.strip_suffix('N') {
+        return is_integer(digits).then(|| EdnValue::BigNumber(token.to_string()));
+    }
+    if body.ends_with('M') || body.contains('/') {
+        return Some(EdnValue::BigNumber(token.to_string()));
+    }
+    // Hex, as in the identity hash of a printed `#object`.
+    let (negative, unsigned) = match body.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, body),
+    };
+    if let Some(hex) = unsigned.strip_prefix("0x").or(unsigned.strip_prefix("0X")) {
+        let n = i64::from_str_radix(hex, 16).ok()?;
+        return Some(EdnValue::Integer(if negative { -n } else { n }));
+    }
+    if body.contains(['.', 'e', 'E']) {
+        return body.parse().ok().map(EdnValue::Float);
+    }
+    if !is_integer(body) {
+        return None;
+    }
+    Some(body.parse().map_or_else(
+        |_| EdnValue::BigNumber(token.to_string()),
+        EdnValue::Integer,
+    ))
+}
+
+fn is_integer(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Apply a `#:ns{...}` prefix to one key: bare keywords and symbols gain the
+/// namespace, `:_/name` loses it, anything else (an auto-resolved `::name`
+/// included) is left alone.
+fn qualify(ns: &str, key: EdnValue) -> EdnValue {
+    match key {
+        EdnValue::Keyword(name) => EdnValue::Keyword(qualify_name(ns, name)),
+        EdnValue::Symbol(name) => EdnValue::Symbol(qualify_name(ns, name)),
+        other => other,
+    }
+}
+
+fn qualify_name(ns: &str, name: String) -> String {
+    if let Some(bare) = name.strip_prefix("_/") {
+        bare.to_string()
+    } else if name.contains('/') || name.starts_with(':') {
+        name
+    } else {
+        format!("{ns}/{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kw(name: &str) -> EdnValue {
+        EdnValue::Keyword(name.to_string())
+    }
+
+    #[test]
+    fn test_parse_scalars() {
+        assert_eq!(parse("nil").unwrap(), EdnValue::Nil);
+        assert_eq!(parse(" true ").unwrap(), EdnValue::Bool(true));
+        assert_eq!(parse("-42").unwrap(), EdnValue::Integer(-42));
+        assert_eq!(parse("2.5").unwrap(), EdnValue::Float(2.5));
+        assert_eq!(parse("0x1F").unwrap(), EdnValue::Integer(31));
+        assert_eq!(parse("1/3").unwrap(), EdnValue::BigNumber("1/3".into()));
+        assert_eq!(
+            parse("99999999999999999999").unwrap(),
+            EdnValue::BigNumber("99999999999999999999".into())
+        );
+        assert_eq!(parse("\\newline").unwrap(), EdnValue::Char('\n'));
+        assert_eq!(parse("\\(").unwrap(), EdnValue::Char('('));
+        assert_eq!(
+            parse(r#""a\"bé""#).unwrap(),
+            EdnValue::String("a\"bé".into())
+        );
+        assert_eq!(parse("::auto").unwrap(), kw(":auto"));
+        assert!(parse("::").is_err());
+        assert_eq!(parse("#'user/f").unwrap(), EdnValue::Var("user/f".into()));
+    }
+
+    #[test]
+    fn test_parse_collections() {
+        let value = parse("{:a 1, :b [2 3.0 \"x\"] :c #{:d} :e (nil)}").unwrap();
+        assert_eq!(value.get_keyword("a"), Some(&EdnValue::Integer(1)));
+        let b = value.get_keyword("b").and_then(EdnValue::as_seq).unwrap();
+        assert_eq!(b[1].as_f64(), Some(3.0));
+        assert_eq!(b[2].as_str(), Some("x"));
+        assert_eq!(value.get_keyword("c"), Some(&EdnValue::Set(vec![kw("d")])));
+        assert!(value.get_keyword("e").unwrap().as_seq().unwrap()[0].is_nil());
+    }
+
+    #[test]
+    fn test_parse_printer_extensions() {
+        let value = parse("#:user{:a 1 :other/b 2 :_/c 3 ::d 4}").unwrap();
+        let keys: Vec<&EdnValue> = match &value {
+            EdnValue::Map(entries) => entries.iter().map(|(k, _)| k).collect(),
+            _ => panic!("expected map"),
+        };
+        assert_eq!(keys, [&kw("user/a"), &kw("other/b"), &kw("c"), &kw(":d")]);
+
+        let object = parse("#object[java.lang.Object 0x1b2c \"java.lang.Object@1b2c\"]").unwrap();
+        assert!(matches!(object, EdnValue::Tagged(tag, _) if tag == "object"));
+        assert_eq!(parse("[1 #_ 2 3 #_4]").unwrap(), parse("[1 3]").unwrap());
+    }
+
+    #[test]
+    fn test_parse_errors_report_position() {
+        match parse("[1 2") {
+            Err(NReplError::Codec { position, .. }) => assert_eq!(position, 4),
+            other => panic!("expected codec error, got {other:?}"),
+        }
+        assert!(parse("{:a}").is_err());
+        assert!(parse("1 2").is_err());
+        assert!(parse("#\"re\"").is_err());
+        assert!(parse(&"[".repeat(MAX_DEPTH + 2)).is_err());
+    }
+}
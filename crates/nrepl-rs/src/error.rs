@@ -10,6 +10,7 @@
 // MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
 // GNU Affero General Public License for more details.
 
+use crate::message::EvalResult;
 use std::time::Duration;
 use thiserror::Error;
 
@@ -39,14 +40,154 @@ pub enum NReplError {
     #[error("Operation failed: {0}")]
     OperationFailed(String),
 
+    /// The request (by wire id) was abandoned through a
+    /// [`CancellationToken`](crate::worker::CancellationToken).
+    #[error("Request {0} was cancelled")]
+    Cancelled(String),
+
+    /// A control op went `waited` since its last response without a `done`
+    /// status: the server (often a middleware) answered it but never
+    /// terminated it. Distinct from [`Timeout`](Self::Timeout) so a buggy
+    /// middleware is not mistaken for a slow server.
+    #[error("Protocol violation: {op} request {id} went {waited:?} without a response or done")]
+    ProtocolViolation {
+        op: String,
+        id: String,
+        waited: Duration,
+    },
+
+    /// The connection stopped answering while it still looked open: a
+    /// heartbeat went unanswered past
+    /// [`half_open_after`](crate::worker::WorkerConfig::half_open_after), or
+    /// a read or write stalled past its limit. Every request in flight on it
+    /// fails with this rather than waiting out its own timeout.
+    #[error("Connection lost: {0}")]
+    ConnectionLost(String),
+
+    /// The worker has no connection to send on: it has not connected yet,
+    /// or is between reconnect attempts.
+    #[error("Not connected")]
+    NotConnected,
+
     #[error("Timeout after {duration:?} while {operation}")]
     Timeout {
         operation: String,
         duration: Duration,
+        /// What an eval had accumulated (stdout, stderr, last value) when its
+        /// deadline fired, so callers can still show what was printed. `None`
+        /// for non-eval timeouts. Boxed to keep `NReplError` small.
+        partial: Option<Box<EvalResult>>,
     },
 }
 
+/// The class of an [`NReplError`], for callers that branch on what went
+/// wrong instead of matching its message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// The connection could not be made or is gone: refused, reset, closed
+    /// by the server, or the worker not connected or shut down.
+    ConnectionLost,
+    /// A deadline passed, the client's or the socket's.
+    Timeout,
+    /// The session is unknown.
+    SessionInvalid,
+    /// The server refused or failed the request, an unsupported op included.
+    ServerRejected,
+    /// The server sent bytes that are not a valid message.
+    Decode,
+    /// The server's replies broke the protocol: a missing field, or a
+    /// control op never finished with `done`.
+    Protocol,
+    /// The request was cancelled on the client side.
+    Cancelled,
+}
+
+impl ErrorCode {
+    /// Stable lowercase name, for logs and the Steel side.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::ConnectionLost => "connection-lost",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::SessionInvalid => "session-invalid",
+            ErrorCode::ServerRejected => "server-rejected",
+            ErrorCode::Decode => "decode",
+            ErrorCode::Protocol => "protocol",
+            ErrorCode::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Socket errors that say the peer was slow, not gone.
+fn is_slow_io(kind: std::io::ErrorKind) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        kind,
+        ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+    )
+}
+
 impl NReplError {
+    /// The class of this error.
+    #[must_use]
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Connection(e) if is_slow_io(e.kind()) => ErrorCode::Timeout,
+            Self::Connection(_) | Self::ConnectionLost(_) | Self::NotConnected => {
+                ErrorCode::ConnectionLost
+            }
+            Self::Codec { .. } => ErrorCode::Decode,
+            Self::Protocol { .. } | Self::ProtocolViolation { .. } => ErrorCode::Protocol,
+            Self::SessionNotFound(_) => ErrorCode::SessionInvalid,
+            Self::OperationFailed(_) => ErrorCode::ServerRejected,
+            Self::Cancelled(_) => ErrorCode::Cancelled,
+            Self::Timeout { .. } => ErrorCode::Timeout,
+        }
+    }
+
+    /// Whether sending the same request again could succeed: it timed out,
+    /// lost its `done`, or hit a slow socket. Only worth acting on for
+    /// requests that are safe to repeat (see [`RetryPolicy`](crate::RetryPolicy)).
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Timeout { .. } | Self::ProtocolViolation { .. } => true,
+            Self::Connection(e) => is_slow_io(e.kind()),
+            _ => false,
+        }
+    }
+
+    /// Whether the connection this came from is unusable: every later
+    /// request on it will fail too, until a new worker connects.
+    #[must_use]
+    pub fn is_connection_dead(&self) -> bool {
+        self.code() == ErrorCode::ConnectionLost
+    }
+
+    /// Create a timeout error with no partial result
+    pub fn timeout(operation: impl Into<String>, duration: Duration) -> Self {
+        Self::Timeout {
+            operation: operation.into(),
+            duration,
+            partial: None,
+        }
+    }
+
+    /// The partial eval result carried by a [`Timeout`](Self::Timeout), if any
+    #[must_use]
+    pub fn partial_result(&self) -> Option<&EvalResult> {
+        match self {
+            Self::Timeout { partial, .. } => partial.as_deref(),
+            _ => None,
+        }
+    }
+
     /// Create a codec error with context
     pub fn codec(message: impl Into<String>, position: usize) -> Self {
         Self::Codec {
@@ -0,0 +1,161 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Per-connection ring buffer of significant events
+//!
+//! The worker records connects, disconnects, timeouts, limit hits and the like
+//! as they happen, always, so a bug report can include the recent history of a
+//! connection without `NREPL_DEBUG` having been set in advance. Events carry
+//! ids, sizes and durations only, never code or output, so a dump is safe to
+//! paste into an issue.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// Events kept per connection unless configured otherwise.
+pub const DEFAULT_EVENT_LOG_CAPACITY: usize = 128;
+
+/// What kind of thing happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugEventKind {
+    Connected,
+    ConnectFailed,
+    Disconnected,
+    /// An eval hit its deadline.
+    Timeout,
+    /// A control op never sent `done`.
+    ProtocolViolation,
+    /// An eval exceeded an output limit.
+    LimitExceeded,
+    /// A heartbeat went unanswered.
+    Degraded,
+    /// A heartbeat was answered after the connection was degraded.
+    Recovered,
+    Resync,
+    /// Unclaimed eval results were evicted from the worker's buffer.
+    ResponsesEvicted,
+    /// An idle session was closed (see
+    /// [`WorkerConfig::session_idle_ttl`](crate::worker::WorkerConfig::session_idle_ttl)).
+    SessionExpired,
+    /// A tracked session turned out to be gone from the server.
+    SessionInvalidated,
+    /// The connection stopped answering while it still looked open.
+    ConnectionLost,
+    /// A lost connection was made again (see
+    /// [`WorkerConfig::reconnect_on_loss`](crate::worker::WorkerConfig::reconnect_on_loss)).
+    Reconnected,
+}
+
+impl DebugEventKind {
+    /// Stable lowercase name, for logs and the Steel side.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DebugEventKind::Connected => "connected",
+            DebugEventKind::ConnectFailed => "connect-failed",
+            DebugEventKind::Disconnected => "disconnected",
+            DebugEventKind::Timeout => "timeout",
+            DebugEventKind::ProtocolViolation => "protocol-violation",
+            DebugEventKind::LimitExceeded => "limit-exceeded",
+            DebugEventKind::Degraded => "degraded",
+            DebugEventKind::Recovered => "recovered",
+            DebugEventKind::Resync => "resync",
+            DebugEventKind::ResponsesEvicted => "responses-evicted",
+            DebugEventKind::SessionExpired => "session-expired",
+            DebugEventKind::SessionInvalidated => "session-invalidated",
+            DebugEventKind::ConnectionLost => "connection-lost",
+            DebugEventKind::Reconnected => "reconnected",
+        }
+    }
+}
+
+/// One recorded event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugEvent {
+    pub at: SystemTime,
+    pub kind: DebugEventKind,
+    /// Human-readable detail: ids, sizes, durations.
+    pub message: String,
+}
+
+/// Bounded, shared event log. The oldest event is dropped to make room.
+#[derive(Clone, Debug)]
+pub(crate) struct EventLog {
+    inner: Arc<Mutex<VecDeque<DebugEvent>>>,
+    capacity: usize,
+}
+
+impl EventLog {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub(crate) fn record(&self, kind: DebugEventKind, message: impl Into<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut events = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(DebugEvent {
+            at: SystemTime::now(),
+            kind,
+            message: message.into(),
+        });
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Every retained event, oldest first.
+    pub(crate) fn snapshot(&self) -> Vec<DebugEvent> {
+        let events = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        events.iter().cloned().collect()
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_LOG_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_log_drops_oldest() {
+        let log = EventLog::new(2);
+        log.record(DebugEventKind::Connected, "a");
+        log.record(DebugEventKind::Timeout, "b");
+        log.record(DebugEventKind::Disconnected, "c");
+
+        let events = log.snapshot();
+        let messages: Vec<&str> = events.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, ["b", "c"]);
+        assert_eq!(events[1].kind, DebugEventKind::Disconnected);
+    }
+
+    #[test]
+    fn test_zero_capacity_records_nothing() {
+        let log = EventLog::new(0);
+        log.record(DebugEventKind::Connected, "a");
+        assert!(log.snapshot().is_empty());
+    }
+}
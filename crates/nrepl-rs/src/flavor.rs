@@ -0,0 +1,233 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Which nREPL server is on the other end
+//!
+//! babashka, nbb and Python servers speak the same protocol as the JVM
+//! reference server but implement less of it: babashka and nbb offer
+//! `complete` rather than `completions`, not every server can interrupt an
+//! eval, and some leave `ns` off eval replies. A [`ServerProfile`], read from
+//! `describe`, records the flavor and the ops on offer so the worker can
+//! steer around the differences (see
+//! [`WorkerConfig::detect_server`](crate::worker::WorkerConfig::detect_server)).
+
+use crate::message::Response;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// The server implementation, as its `describe` versions name it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerFlavor {
+    /// The reference server on the JVM.
+    Clojure,
+    Babashka,
+    Nbb,
+    /// basilisp or another Python server.
+    Python,
+    Unknown,
+}
+
+impl ServerFlavor {
+    /// Stable lowercase name, for logs and the Steel side.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ServerFlavor::Clojure => "clojure",
+            ServerFlavor::Babashka => "babashka",
+            ServerFlavor::Nbb => "nbb",
+            ServerFlavor::Python => "python",
+            ServerFlavor::Unknown => "unknown",
+        }
+    }
+
+    /// Eval timeout for submissions that do not set one. babashka and nbb
+    /// start in milliseconds and cannot always interrupt, so a stuck form is
+    /// given up on sooner than on the JVM, where the first require of a
+    /// large library can take most of a minute.
+    #[must_use]
+    pub fn default_eval_timeout(self) -> Duration {
+        match self {
+            ServerFlavor::Babashka | ServerFlavor::Nbb => Duration::from_secs(30),
+            ServerFlavor::Clojure | ServerFlavor::Python | ServerFlavor::Unknown => {
+                Duration::from_mins(1)
+            }
+        }
+    }
+
+    /// The namespace a fresh session starts in, if known.
+    #[must_use]
+    pub fn default_ns(self) -> Option<&'static str> {
+        match self {
+            ServerFlavor::Clojure | ServerFlavor::Babashka | ServerFlavor::Nbb => Some("user"),
+            ServerFlavor::Python => Some("basilisp.user"),
+            ServerFlavor::Unknown => None,
+        }
+    }
+
+    fn from_versions(versions: &[&str]) -> Self {
+        let has = |name: &str| versions.contains(&name);
+        if has("babashka") {
+            ServerFlavor::Babashka
+        } else if has("nbb") {
+            ServerFlavor::Nbb
+        } else if has("basilisp") || has("python") {
+            ServerFlavor::Python
+        } else if has("clojure") && has("java") {
+            ServerFlavor::Clojure
+        } else {
+            ServerFlavor::Unknown
+        }
+    }
+}
+
+/// A server's flavor and the ops it offers, from `describe`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerProfile {
+    pub flavor: ServerFlavor,
+    /// `None` when `describe` listed no ops, in which case every op is
+    /// assumed supported.
+    ops: Option<BTreeSet<String>>,
+}
+
+impl ServerProfile {
+    /// Read a `describe` reply.
+    #[must_use]
+    pub fn from_describe(response: &Response) -> Self {
+        let versions: Vec<&str> = response
+            .versions
+            .iter()
+            .flat_map(|v| v.keys())
+            .map(String::as_str)
+            .collect();
+        ServerProfile {
+            flavor: ServerFlavor::from_versions(&versions),
+            ops: response
+                .ops
+                .as_ref()
+                .map(|ops| ops.keys().cloned().collect()),
+        }
+    }
+
+    /// Whether the server offers `op`.
+    #[must_use]
+    pub fn supports(&self, op: &str) -> bool {
+        self.ops.as_ref().is_none_or(|ops| ops.contains(op))
+    }
+}
+
+/// What the worker has learned about its server, shared with the
+/// [`Worker`](crate::worker::Worker) handle.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ServerInfo {
+    inner: Arc<Mutex<Learned>>,
+}
+
+#[derive(Debug, Default)]
+struct Learned {
+    profile: Option<ServerProfile>,
+    /// Each session's namespace as of its latest eval reply.
+    session_ns: HashMap<String, String>,
+}
+
+impl ServerInfo {
+    fn learned(&self) -> std::sync::MutexGuard<'_, Learned> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn profile(&self) -> Option<ServerProfile> {
+        self.learned().profile.clone()
+    }
+
+    pub(crate) fn set_profile(&self, profile: ServerProfile) {
+        self.learned().profile = Some(profile);
+    }
+
+    /// Whether the server is known to lack `op`.
+    pub(crate) fn lacks(&self, op: &str) -> bool {
+        self.learned()
+            .profile
+            .as_ref()
+            .is_some_and(|p| !p.supports(op))
+    }
+
+    pub(crate) fn note_ns(&self, session: &str, ns: &str) {
+        let mut learned = self.learned();
+        if learned.session_ns.get(session).map(String::as_str) != Some(ns) {
+            learned
+                .session_ns
+                .insert(session.to_string(), ns.to_string());
+        }
+    }
+
+    /// The namespace to report for `session` when an eval reply left it
+    /// out: where the session last was, else where the server starts one.
+    pub(crate) fn fallback_ns(&self, session: &str) -> Option<String> {
+        let learned = self.learned();
+        learned.session_ns.get(session).cloned().or_else(|| {
+            learned
+                .profile
+                .as_ref()
+                .and_then(|p| p.flavor.default_ns())
+                .map(str::to_string)
+        })
+    }
+
+    pub(crate) fn forget_session(&self, session: &str) {
+        self.learned().session_ns.remove(session);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn describe(frame: &str) -> ServerProfile {
+        ServerProfile::from_describe(&crate::codec::decode_response(frame.as_bytes()).unwrap().0)
+    }
+
+    #[test]
+    fn test_flavor_from_versions() {
+        let bb = describe(concat!(
+            "d2:id5:req-13:opsd8:completede4:evaldee6:statusl4:donee",
+            "8:versionsd8:babashkad14:version-string6:1.12.0e5:nrepld14:version-string5:1.0.0eee"
+        ));
+        assert_eq!(bb.flavor, ServerFlavor::Babashka);
+        assert!(bb.supports("complete"));
+        assert!(!bb.supports("completions"));
+
+        let jvm = describe(concat!(
+            "d2:id5:req-1",
+            "8:versionsd7:clojured14:version-string6:1.12.0e4:javad14:version-string2:21eee"
+        ));
+        assert_eq!(jvm.flavor, ServerFlavor::Clojure);
+        // No ops listed: assume everything.
+        assert!(jvm.supports("completions"));
+
+        assert_eq!(describe("d2:id5:req-1e").flavor, ServerFlavor::Unknown);
+    }
+
+    #[test]
+    fn test_fallback_ns() {
+        let info = ServerInfo::default();
+        assert_eq!(info.fallback_ns("s1"), None);
+        info.set_profile(ServerProfile {
+            flavor: ServerFlavor::Nbb,
+            ops: None,
+        });
+        assert_eq!(info.fallback_ns("s1").as_deref(), Some("user"));
+        info.note_ns("s1", "app.core");
+        assert_eq!(info.fallback_ns("s1").as_deref(), Some("app.core"));
+        info.forget_session("s1");
+        assert_eq!(info.fallback_ns("s1").as_deref(), Some("user"));
+    }
+}
@@ -0,0 +1,271 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Connections to several servers under one roof
+//!
+//! A full-stack project often runs more than one REPL: a JVM backend, a
+//! shadow-cljs build, a babashka script runner. [`NReplFleet`] keeps a
+//! worker and a session for each, under a key the caller picks, routes
+//! operations to one of them by key, and can
+//! [`broadcast`](NReplFleet::broadcast) a form to all of them at once.
+
+use crate::error::{NReplError, Result};
+use crate::message::EvalResult;
+use crate::pool::worker_gone;
+use crate::session::Session;
+use crate::worker::{ConnectionState, EvalOutcome, Worker, WorkerCommand, WorkerConfig};
+use std::collections::BTreeMap;
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+
+/// One server: its worker and the session operations are routed to.
+struct Member {
+    worker: Worker,
+    session: Session,
+}
+
+/// Workers for several servers, each known by a key such as `"backend"` or
+/// `"shadow"`.
+///
+/// Dropping the fleet shuts every worker down. All methods block.
+///
+/// ```no_run
+/// use nrepl_rs::NReplFleet;
+/// use nrepl_rs::worker::WorkerConfig;
+/// use std::time::Duration;
+///
+/// let mut fleet = NReplFleet::new();
+/// fleet.connect("backend", "localhost:7888", WorkerConfig::default())?;
+/// fleet.connect("bb", "localhost:1667", WorkerConfig::default())?;
+///
+/// let version = fleet.eval("backend", "(clojure-version)", Duration::from_secs(5))?;
+/// for (key, result) in fleet.broadcast("(+ 1 2)", Duration::from_secs(5)) {
+///     println!("{key}: {:?}", result.map(|r| r.value));
+/// }
+/// # Ok::<(), nrepl_rs::NReplError>(())
+/// ```
+#[derive(Default)]
+pub struct NReplFleet {
+    members: BTreeMap<String, Member>,
+}
+
+impl NReplFleet {
+    /// An empty fleet.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connect to the server at `address`, clone a session on it and add it
+    /// as `key`, replacing (and shutting down) any worker already there.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from connecting or cloning the session; the fleet is
+    /// unchanged.
+    pub fn connect(
+        &mut self,
+        key: impl Into<String>,
+        address: impl Into<String>,
+        config: WorkerConfig,
+    ) -> Result<()> {
+        let worker = Worker::with_config(config);
+        worker.connect_blocking(address.into())?;
+        let (reply, replies) = channel();
+        worker
+            .command_sender()
+            .send(WorkerCommand::CloneSession {
+                op_id: worker.next_id(),
+                reply,
+            })
+            .map_err(|_| worker_gone())?;
+        let timeout = worker.timeouts().clone;
+        let session = replies
+            .recv_timeout(timeout)
+            .map_err(|_| NReplError::timeout("clone", timeout))??;
+        self.add(key, worker, session);
+        Ok(())
+    }
+
+    /// Add an already connected worker as `key`, with the session operations
+    /// on it should use. Returns the worker and session previously under
+    /// `key`, if any.
+    pub fn add(
+        &mut self,
+        key: impl Into<String>,
+        worker: Worker,
+        session: Session,
+    ) -> Option<(Worker, Session)> {
+        self.members
+            .insert(key.into(), Member { worker, session })
+            .map(|m| (m.worker, m.session))
+    }
+
+    /// Take `key` out of the fleet, handing back its worker and session.
+    pub fn remove(&mut self, key: &str) -> Option<(Worker, Session)> {
+        self.members.remove(key).map(|m| (m.worker, m.session))
+    }
+
+    /// The keys in the fleet, in order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.members.keys().map(String::as_str)
+    }
+
+    /// The worker for `key`, to send it any operation.
+    pub fn worker(&mut self, key: &str) -> Option<&mut Worker> {
+        self.members.get_mut(key).map(|m| &mut m.worker)
+    }
+
+    /// The session operations on `key` use.
+    #[must_use]
+    pub fn session(&self, key: &str) -> Option<&Session> {
+        self.members.get(key).map(|m| &m.session)
+    }
+
+    /// Evaluate `code` on the server `key` and block for the result.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NReplError::OperationFailed`] if there is no server `key`
+    /// or the eval stops to read stdin, and otherwise whatever the eval
+    /// failed with.
+    pub fn eval(&mut self, key: &str, code: &str, timeout: Duration) -> Result<EvalResult> {
+        let mut results = self.eval_on([key], code, timeout);
+        results
+            .remove(key)
+            .unwrap_or_else(|| Err(unknown_server(key)))
+    }
+
+    /// Evaluate `code` on every server at once, blocking until each has
+    /// answered or timed out, and return the results by key. A slow server
+    /// holds up only its own result, not the others' evaluation.
+    pub fn broadcast(
+        &mut self,
+        code: &str,
+        timeout: Duration,
+    ) -> BTreeMap<String, Result<EvalResult>> {
+        let keys: Vec<String> = self.members.keys().cloned().collect();
+        self.eval_on(keys.iter().map(String::as_str), code, timeout)
+    }
+
+    /// Submit `code` to each of `keys`, then collect the results as they
+    /// come in.
+    fn eval_on<'k>(
+        &mut self,
+        keys: impl IntoIterator<Item = &'k str>,
+        code: &str,
+        timeout: Duration,
+    ) -> BTreeMap<String, Result<EvalResult>> {
+        let mut results = BTreeMap::new();
+        let mut running = Vec::new();
+        for key in keys {
+            let Some(member) = self.members.get_mut(key) else {
+                results.insert(key.to_string(), Err(unknown_server(key)));
+                continue;
+            };
+            // An eval sent before the connection is up is dropped unanswered.
+            if member.worker.connection_state() == ConnectionState::Disconnected {
+                results.insert(key.to_string(), Err(NReplError::NotConnected));
+                continue;
+            }
+            match member.worker.submit_eval(
+                member.session.clone(),
+                code.to_string(),
+                Some(timeout),
+                None,
+                None,
+                None,
+            ) {
+                Ok(id) => running.push((key.to_string(), id)),
+                Err(_) => {
+                    results.insert(key.to_string(), Err(worker_gone()));
+                }
+            }
+        }
+
+        // The workers enforce `timeout`; this only guards against one that
+        // never answers at all.
+        let give_up = Instant::now() + timeout * 2;
+        while !running.is_empty() {
+            running.retain(|(key, id)| {
+                let member = self.members.get_mut(key).expect("running key is a member");
+                let Some(response) = member.worker.try_recv_response(*id) else {
+                    return true;
+                };
+                let result = match response.outcome {
+                    EvalOutcome::Done(result) => result,
+                    EvalOutcome::NeedInput { .. } => {
+                        let _ = member
+                            .worker
+                            .command_sender()
+                            .send(WorkerCommand::Interrupt {
+                                op_id: member.worker.next_id(),
+                                session: member.session.clone(),
+                                target: *id,
+                                reply: channel().0,
+                            });
+                        Err(NReplError::OperationFailed(
+                            "eval is waiting for stdin".to_string(),
+                        ))
+                    }
+                };
+                results.insert(key.clone(), result);
+                false
+            });
+            if Instant::now() > give_up {
+                for (key, _) in running.drain(..) {
+                    results.insert(key, Err(NReplError::timeout("eval", timeout)));
+                }
+            }
+            if running.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        results
+    }
+}
+
+fn unknown_server(key: &str) -> NReplError {
+    NReplError::OperationFailed(format!("No server named {key}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_and_unconnected_servers_fail_without_waiting() {
+        let mut fleet = NReplFleet::new();
+        assert!(
+            fleet
+                .add("idle", Worker::new(), Session::new("s1"))
+                .is_none()
+        );
+        assert_eq!(fleet.keys().collect::<Vec<_>>(), ["idle"]);
+
+        let started = Instant::now();
+        let results = fleet.broadcast("(+ 1 2)", Duration::from_secs(10));
+        assert!(matches!(
+            results.get("idle"),
+            Some(Err(NReplError::NotConnected))
+        ));
+        assert!(matches!(
+            fleet.eval("missing", "(+ 1 2)", Duration::from_secs(10)),
+            Err(NReplError::OperationFailed(m)) if m.contains("missing")
+        ));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        assert!(fleet.remove("idle").is_some());
+        assert_eq!(fleet.keys().count(), 0);
+    }
+}
@@ -0,0 +1,590 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Finding the form at a point in a buffer, for "eval form at point"
+//!
+//! [`forms_at`] reads the buffer's top-level forms up to the one holding the
+//! cursor and reports it, and the innermost form under the cursor, as byte
+//! ranges with the line and column nREPL's `eval` wants for locations.
+//!
+//! Unlike the reader behind [`parse_ns`](crate::parse_ns), this one keeps the
+//! buffer as written: a reader conditional is one form rather than the branch
+//! a dialect would read, metadata and quote prefixes belong to the form they
+//! prefix, and a `#_` discard is a form the cursor can be in. Strings,
+//! characters and comments are stepped over, so a paren inside them never
+//! counts.
+//!
+//! Forms inside a `(comment ...)` rich-comment block are written to be
+//! evaluated one at a time, so by default the top-level form at a cursor in
+//! one is the block's form rather than the whole block, as in CIDER.
+//! [`FormOptions::comment_blocks`] turns that off.
+
+use std::ops::Range;
+
+/// Deepest nesting read, so a pathological buffer cannot overflow the stack.
+const MAX_DEPTH: usize = 512;
+
+/// A place in a buffer, 1-based as nREPL's `line` and `column` are. The
+/// column counts characters, not bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// The position of byte `offset` in `text`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is past the end of `text` or not on a character
+    /// boundary.
+    #[must_use]
+    pub fn at(text: &str, offset: usize) -> Self {
+        position(text, offset)
+    }
+
+    /// This position, taken within a piece of text that starts at `origin`
+    /// in a larger one, as a position in the larger text. Only the first
+    /// line of the piece is shifted right; later lines start where the
+    /// larger text's do.
+    ///
+    /// Use it when the code sent to `eval` was cut from a form that was
+    /// itself cut from a file: the position of the code within the form,
+    /// placed at the form's position in the file, is where the code is in
+    /// the file.
+    #[must_use]
+    pub fn placed_at(self, origin: Position) -> Self {
+        if self.line == 1 {
+            Position {
+                line: origin.line,
+                column: origin.column + self.column - 1,
+            }
+        } else {
+            Position {
+                line: origin.line + self.line - 1,
+                column: self.column,
+            }
+        }
+    }
+
+    /// The inverse of [`placed_at`](Self::placed_at): this position in a
+    /// larger text as a position within the piece starting at `origin`,
+    /// or `None` if it comes before the piece. For reading a location the
+    /// server reports against the code that was sent.
+    #[must_use]
+    pub fn relative_to(self, origin: Position) -> Option<Self> {
+        match self.line.checked_sub(origin.line)? {
+            0 => Some(Position {
+                line: 1,
+                column: self.column.checked_sub(origin.column)? + 1,
+            }),
+            lines => Some(Position {
+                line: lines + 1,
+                column: self.column,
+            }),
+        }
+    }
+}
+
+/// Where a form is in a buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormSpan {
+    /// Byte range, prefixes and metadata included.
+    pub range: Range<usize>,
+    /// The form's first character.
+    pub start: Position,
+    /// Just past its last character.
+    pub end: Position,
+}
+
+impl FormSpan {
+    fn new(text: &str, range: Range<usize>) -> Self {
+        FormSpan {
+            start: position(text, range.start),
+            end: position(text, range.end),
+            range,
+        }
+    }
+
+    /// The form's source in `text`, the buffer it was found in.
+    #[must_use]
+    pub fn text<'a>(&self, text: &'a str) -> &'a str {
+        &text[self.range.clone()]
+    }
+}
+
+/// The forms at a cursor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormsAt {
+    /// The top-level form holding the cursor.
+    pub top_level: FormSpan,
+    /// The smallest form holding the cursor: a symbol or literal under it,
+    /// else the collection whose brackets or whitespace it is on.
+    pub innermost: FormSpan,
+}
+
+/// How [`forms_at_with`] reads a buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormOptions {
+    /// Treat the forms of a `(comment ...)` block as top-level forms. On by
+    /// default.
+    pub comment_blocks: bool,
+}
+
+impl Default for FormOptions {
+    fn default() -> Self {
+        FormOptions {
+            comment_blocks: true,
+        }
+    }
+}
+
+/// The forms at byte `offset` of `text`, with the default [`FormOptions`].
+#[must_use]
+pub fn forms_at(text: &str, offset: usize) -> Option<FormsAt> {
+    forms_at_with(text, offset, FormOptions::default())
+}
+
+/// The forms at byte `offset` of `text`.
+///
+/// A cursor on the whitespace just past a top-level form (the end of its
+/// last line, say) counts as in that form. `None` when the cursor is between
+/// top-level forms, or the buffer cannot be read as far as the cursor (an
+/// unclosed bracket before it). A stray closing bracket at top level is
+/// skipped.
+#[must_use]
+pub fn forms_at_with(text: &str, offset: usize, options: FormOptions) -> Option<FormsAt> {
+    let mut top = undiscard(top_level_at(text, offset)?)?;
+    if options.comment_blocks && is_comment_block(&top, text) {
+        // Past the `comment` symbol itself. Like the buffer's own top level,
+        // a cursor just past a form counts as in it.
+        let forms = &top.children[1..];
+        let at = forms
+            .iter()
+            .position(|f| f.span.contains(&offset))
+            .or_else(|| forms.iter().position(|f| f.span.end == offset));
+        if let Some(at) = at {
+            top = undiscard(top.children.swap_remove(at + 1))?;
+        }
+    }
+    let innermost = innermost(&top, offset);
+    Some(FormsAt {
+        top_level: FormSpan::new(text, top.span.clone()),
+        innermost: FormSpan::new(text, innermost),
+    })
+}
+
+/// Whether `text` reads as whole forms, with no bracket or string left
+/// open: a REPL's cue to send what was typed rather than wait for another
+/// line. Stray closing brackets do not make it incomplete; the reader on
+/// the server reports them.
+#[must_use]
+pub fn forms_complete(text: &str) -> bool {
+    let mut scanner = Scanner { src: text, pos: 0 };
+    loop {
+        scanner.skip_ws();
+        if scanner.pos >= text.len() {
+            return true;
+        }
+        if matches!(scanner.peek(), Some(')' | ']' | '}')) {
+            scanner.bump();
+            continue;
+        }
+        if scanner.form(0).is_none() {
+            return false;
+        }
+    }
+}
+
+/// `#_(form)` at top level: the form is what there is to evaluate.
+fn undiscard(form: Syntax) -> Option<Syntax> {
+    match form.kind {
+        Kind::Discard => form.children.into_iter().next(),
+        _ => Some(form),
+    }
+}
+
+/// Whether `form` is a `(comment ...)` block.
+fn is_comment_block(form: &Syntax, text: &str) -> bool {
+    form.kind == Kind::List
+        && form.children.first().is_some_and(|head| {
+            matches!(&text[head.span.clone()], "comment" | "clojure.core/comment")
+        })
+}
+
+/// Read top-level forms until the one holding `offset`.
+fn top_level_at(text: &str, offset: usize) -> Option<Syntax> {
+    let mut scanner = Scanner { src: text, pos: 0 };
+    let mut touching = None;
+    loop {
+        scanner.skip_ws();
+        if scanner.pos > offset || scanner.pos >= text.len() {
+            return touching;
+        }
+        if matches!(scanner.peek(), Some(')' | ']' | '}')) {
+            scanner.bump();
+            continue;
+        }
+        let form = scanner.form(0)?;
+        if form.span.contains(&offset) {
+            return Some(form);
+        }
+        if form.span.end == offset {
+            touching = Some(form);
+        }
+    }
+}
+
+/// The span of the smallest form in `node` strictly holding `offset`, or
+/// `node`'s own.
+fn innermost(node: &Syntax, offset: usize) -> Range<usize> {
+    node.children
+        .iter()
+        .find(|child| child.span.contains(&offset))
+        .map_or_else(|| node.span.clone(), |child| innermost(child, offset))
+}
+
+fn position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+/// A form as written: its extent and the forms inside it.
+#[derive(Debug)]
+pub(crate) struct Syntax {
+    pub(crate) span: Range<usize>,
+    pub(crate) kind: Kind,
+    /// Collection items, or for a prefixed form its metadata and target.
+    pub(crate) children: Vec<Syntax>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    /// `(...)`, which a comment block or a `def` is.
+    List,
+    /// Any other collection: vector, map, set, `#(...)`, `#?(...)`.
+    Collection,
+    /// `#_form`.
+    Discard,
+    /// Quoted, tagged or metadata-prefixed forms, and atoms.
+    Other,
+}
+
+struct Scanner<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ',' | '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';')
+}
+
+impl Scanner<'_> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Skip whitespace, commas, and `;` and `#!` comments.
+    fn skip_ws(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == ',' {
+                self.bump();
+            } else if c == ';' || self.src[self.pos..].starts_with("#!") {
+                while self.bump().is_some_and(|c| c != '\n') {}
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn token(&mut self) {
+        while self.peek().is_some_and(|c| !is_delimiter(c)) {
+            self.bump();
+        }
+    }
+
+    /// The next form after any whitespace.
+    fn next_form(&mut self, depth: usize) -> Option<Syntax> {
+        self.skip_ws();
+        self.form(depth + 1)
+    }
+
+    /// The form at the cursor.
+    fn form(&mut self, depth: usize) -> Option<Syntax> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let start = self.pos;
+        let (kind, children) = match self.bump()? {
+            '(' => (Kind::List, self.items(')', depth)?),
+            '[' => (Kind::Collection, self.items(']', depth)?),
+            '{' => (Kind::Collection, self.items('}', depth)?),
+            '"' => {
+                self.string()?;
+                (Kind::Other, Vec::new())
+            }
+            ')' | ']' | '}' => return None,
+            '\\' => {
+                // The character itself may be a delimiter: `\(`, `\ `.
+                self.bump()?;
+                self.token();
+                (Kind::Other, Vec::new())
+            }
+            '^' => {
+                let meta = self.next_form(depth)?;
+                let target = self.next_form(depth)?;
+                (Kind::Other, vec![meta, target])
+            }
+            '\'' | '`' | '@' => (Kind::Other, vec![self.next_form(depth)?]),
+            '~' => {
+                if self.peek() == Some('@') {
+                    self.bump();
+                }
+                (Kind::Other, vec![self.next_form(depth)?])
+            }
+            '#' => self.dispatch(depth)?,
+            _ => {
+                self.token();
+                (Kind::Other, Vec::new())
+            }
+        };
+        Some(Syntax {
+            span: start..self.pos,
+            kind,
+            children,
+        })
+    }
+
+    /// The form after a `#`.
+    fn dispatch(&mut self, depth: usize) -> Option<(Kind, Vec<Syntax>)> {
+        Some(match self.bump()? {
+            '{' => (Kind::Collection, self.items('}', depth)?),
+            '(' => (Kind::Collection, self.items(')', depth)?),
+            '"' => {
+                // A regex: backslashes escape as in a string.
+                self.string()?;
+                (Kind::Other, Vec::new())
+            }
+            '_' => (Kind::Discard, vec![self.next_form(depth)?]),
+            '?' => {
+                if self.peek() == Some('@') {
+                    self.bump();
+                }
+                self.skip_ws();
+                if self.bump()? != '(' {
+                    return None;
+                }
+                (Kind::Collection, self.items(')', depth)?)
+            }
+            '^' => {
+                let meta = self.next_form(depth)?;
+                let target = self.next_form(depth)?;
+                (Kind::Other, vec![meta, target])
+            }
+            ':' => {
+                // Namespaced map, `#:ns{...}` or `#::{...}`.
+                self.token();
+                if self.bump()? != '{' {
+                    return None;
+                }
+                (Kind::Collection, self.items('}', depth)?)
+            }
+            '#' => {
+                self.token();
+                (Kind::Other, Vec::new())
+            }
+            '\'' | '=' => (Kind::Other, vec![self.next_form(depth)?]),
+            _ => {
+                // A tagged literal: the tag, then the form.
+                self.token();
+                (Kind::Other, vec![self.next_form(depth)?])
+            }
+        })
+    }
+
+    fn items(&mut self, close: char, depth: usize) -> Option<Vec<Syntax>> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_ws();
+            if self.peek()? == close {
+                self.bump();
+                return Some(items);
+            }
+            items.push(self.form(depth + 1)?);
+        }
+    }
+
+    fn string(&mut self) -> Option<()> {
+        loop {
+            match self.bump()? {
+                '"' => return Some(()),
+                '\\' => {
+                    self.bump()?;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUFFER: &str = "(ns app.core)\n\n\
+        ^:private\n\
+        (defn f [x]\n  \
+          (str \"(not a form\" \\) x))\n\
+        ; (comment (foo))\n\
+        #_(g 1)\n\
+        (comment\n  \
+          (f 1)\n  \
+          #_(f 2))\n";
+
+    fn at(needle: &str) -> usize {
+        BUFFER.find(needle).unwrap()
+    }
+
+    #[test]
+    fn test_forms_at_cursor() {
+        let forms = forms_at(BUFFER, at("x))")).unwrap();
+        assert_eq!(
+            forms.top_level.text(BUFFER),
+            "^:private\n(defn f [x]\n  (str \"(not a form\" \\) x))"
+        );
+        assert_eq!(forms.top_level.start, Position { line: 3, column: 1 });
+        assert_eq!(forms.innermost.text(BUFFER), "x");
+        assert_eq!(
+            forms.innermost.start,
+            Position {
+                line: 5,
+                column: 25
+            }
+        );
+
+        // On the string: the string, though it holds a paren.
+        let forms = forms_at(BUFFER, at("not a form")).unwrap();
+        assert_eq!(forms.innermost.text(BUFFER), "\"(not a form\"");
+
+        // On a bracket: the collection.
+        let forms = forms_at(BUFFER, at("[x]")).unwrap();
+        assert_eq!(forms.innermost.text(BUFFER), "[x]");
+
+        // Just past the end of a line.
+        let forms = forms_at(BUFFER, at(")\n\n")).unwrap();
+        assert_eq!(forms.top_level.text(BUFFER), "(ns app.core)");
+        let forms = forms_at(BUFFER, at(")\n\n") + 1).unwrap();
+        assert_eq!(forms.top_level.text(BUFFER), "(ns app.core)");
+
+        // A discarded form is still the one to evaluate.
+        let forms = forms_at(BUFFER, at("g 1")).unwrap();
+        assert_eq!(forms.top_level.text(BUFFER), "(g 1)");
+    }
+
+    #[test]
+    fn test_forms_in_comment_block() {
+        let forms = forms_at(BUFFER, at("f 1")).unwrap();
+        assert_eq!(forms.top_level.text(BUFFER), "(f 1)");
+        assert_eq!(forms.top_level.start, Position { line: 9, column: 3 });
+        // Just past a form, and inside a discard.
+        let forms = forms_at(BUFFER, at("(f 1)") + 5).unwrap();
+        assert_eq!(forms.top_level.text(BUFFER), "(f 1)");
+        let forms = forms_at(BUFFER, at("f 2")).unwrap();
+        assert_eq!(forms.top_level.text(BUFFER), "(f 2)");
+        // On the `comment` symbol: the block.
+        let forms = forms_at(BUFFER, at("comment\n")).unwrap();
+        assert!(forms.top_level.text(BUFFER).starts_with("(comment"));
+
+        let off = FormOptions {
+            comment_blocks: false,
+        };
+        let forms = forms_at_with(BUFFER, at("f 1"), off).unwrap();
+        assert!(forms.top_level.text(BUFFER).starts_with("(comment"));
+        assert_eq!(forms.innermost.text(BUFFER), "f");
+    }
+
+    #[test]
+    fn test_forms_at_nothing() {
+        // A blank line between forms, and a comment.
+        assert_eq!(forms_at(BUFFER, at(")\n\n") + 2), None);
+        assert_eq!(forms_at(BUFFER, at("comment")), None);
+        // Unclosed before the cursor.
+        assert_eq!(forms_at("(defn f [x]\n(+ 1 2)", 14), None);
+        // Stray closer before it is skipped.
+        let forms = forms_at(")) (+ 1 2)", 5).unwrap();
+        assert_eq!(forms.top_level.range, 3..10);
+    }
+
+    #[test]
+    fn test_forms_complete() {
+        assert!(forms_complete("(+ 1 2) :a"));
+        assert!(forms_complete("  ; just a comment"));
+        assert!(forms_complete("(str \\( \"(\") ;; (\n"));
+        assert!(!forms_complete("(defn f [x]\n  (inc x)"));
+        assert!(!forms_complete("(println \"unterminated)"));
+        assert!(!forms_complete("#{1 2"));
+        assert!(forms_complete("(+ 1 2))"));
+    }
+
+    #[test]
+    fn test_positions_move_between_a_piece_and_its_buffer() {
+        let form = forms_at(BUFFER, at("(str")).unwrap().top_level;
+        let piece = form.text(BUFFER);
+        let offset = piece.find("(str").unwrap();
+        let inner = Position::at(piece, offset);
+        assert_eq!(inner, Position { line: 3, column: 3 });
+        let placed = inner.placed_at(form.start);
+        assert_eq!(placed, Position::at(BUFFER, at("(str")));
+        assert_eq!(placed.relative_to(form.start), Some(inner));
+
+        let first = Position { line: 1, column: 4 };
+        let origin = Position {
+            line: 7,
+            column: 10,
+        };
+        assert_eq!(
+            first.placed_at(origin),
+            Position {
+                line: 7,
+                column: 13
+            }
+        );
+        assert_eq!(
+            Position {
+                line: 7,
+                column: 13
+            }
+            .relative_to(origin),
+            Some(first)
+        );
+        assert_eq!(Position { line: 7, column: 2 }.relative_to(origin), None);
+        assert_eq!(
+            Position {
+                line: 6,
+                column: 20
+            }
+            .relative_to(origin),
+            None
+        );
+    }
+}
@@ -0,0 +1,546 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Symbol documentation, search and browsing from cider-nrepl's `info`,
+//! `eldoc`, `apropos` and namespace ops
+//!
+//! Unlike the built-in `lookup`, `info` and `eldoc` resolve Java interop
+//! members (`.toUpperCase`, `Math/abs`) and protocol methods, and `eldoc` sends
+//! arglists as parameter lists rather than one printed string, so an editor
+//! can highlight the argument under the cursor ([`Eldoc::highlight`]).
+//! `apropos` searches every loaded namespace by name, and optionally by
+//! docstring. `ns-list` and `ns-vars-with-meta` enumerate namespaces and their
+//! vars ([`NsVar`]) for a tree view. `fn-refs` and `fn-deps` cross-reference
+//! a function's callers and callees ([`XrefVar`]), for "find references".
+//! `clojuredocs-lookup` fetches a var's community examples and notes from
+//! ClojureDocs' export ([`ClojureDocs`]).
+
+use crate::message::{BencodeValue, Response};
+use crate::spec::SpecForm;
+use std::collections::BTreeMap;
+
+/// What cider-nrepl's `info` knows about a symbol. Which fields are set
+/// depends on what it resolved to: a var, a special form, or a Java class or
+/// member.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolInfo {
+    /// The var or member name, unqualified.
+    pub name: String,
+    pub ns: Option<String>,
+    /// Arglists, printed, one per line.
+    pub arglists: Option<String>,
+    pub doc: Option<String>,
+    /// Source location: a path, or a URL into a jar.
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    /// Related vars, fully qualified.
+    pub see_also: Vec<String>,
+    pub is_macro: bool,
+    pub special_form: bool,
+    /// For a protocol method, the protocol it belongs to.
+    pub protocol: Option<String>,
+    /// For Java interop, the class.
+    pub class: Option<String>,
+    /// For Java interop, the method or field.
+    pub member: Option<String>,
+    /// For Java interop, the member's modifiers, printed.
+    pub modifiers: Option<String>,
+    /// For Java interop, a link to the Javadoc.
+    pub javadoc: Option<String>,
+    /// The function's spec, if it has an `fdef`.
+    pub spec: Option<SpecForm>,
+}
+
+/// The signature summary cider-nrepl's `eldoc` sends for the function being
+/// called at point.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Eldoc {
+    pub name: Option<String>,
+    pub ns: Option<String>,
+    /// `function`, `macro`, `special-form`, `variable` and the like.
+    pub kind: Option<String>,
+    /// One entry per arity, each the parameter names in order. A variadic
+    /// arity has `&` before its rest parameter.
+    pub arglists: Vec<Vec<String>>,
+    pub docstring: Option<String>,
+}
+
+/// One var or special form found by cider-nrepl's `apropos`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct AproposMatch {
+    /// The defining namespace; `None` for special forms.
+    pub ns: Option<String>,
+    /// The name within `ns`.
+    pub name: String,
+    /// `function`, `macro`, `special-form`, `variable` and the like.
+    pub kind: String,
+    /// The docstring's first sentence, if it has one.
+    pub doc: Option<String>,
+}
+
+/// A var's ClojureDocs entry, from cider-nrepl's `clojuredocs-lookup`.
+///
+/// cider-nrepl reads ClojureDocs' export, cached on the server, so only
+/// vars from the libraries ClojureDocs covers (Clojure itself and a few
+/// others) have one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct ClojureDocs {
+    pub ns: String,
+    pub name: String,
+    pub doc: Option<String>,
+    /// Arglists, each printed, such as `[f coll]`.
+    pub arglists: Vec<String>,
+    /// Example code, one entry per example, comments and output included.
+    pub examples: Vec<String>,
+    /// Related vars, fully qualified.
+    pub see_also: Vec<String>,
+    /// Community notes, as Markdown.
+    pub notes: Vec<String>,
+    /// The entry's page on clojuredocs.org.
+    pub href: Option<String>,
+}
+
+/// A var from cider-nrepl's `fn-refs` (a function that calls the one asked
+/// about) or `fn-deps` (one it calls), with where it is defined.
+///
+/// cider-nrepl finds callers among the functions already loaded, by what
+/// their compiled code references, so a caller that has not been loaded
+/// is not listed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct XrefVar {
+    /// The defining namespace.
+    pub ns: Option<String>,
+    /// The name within `ns`.
+    pub name: String,
+    /// The docstring's first sentence, if it has one.
+    pub doc: Option<String>,
+    /// Source location: a path, as in the var's metadata.
+    pub file: Option<String>,
+    /// [`file`](Self::file) as a URL (`file:` or `jar:file:`), for opening.
+    pub file_url: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+/// A public var from cider-nrepl's `ns-vars-with-meta`, with its metadata.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct NsVar {
+    pub name: String,
+    /// Metadata values as the server printed them: `arglists` reads
+    /// `([x] [x y])` and `doc` is a quoted string literal.
+    pub meta: BTreeMap<String, String>,
+}
+
+impl NsVar {
+    /// The arglists, printed, if the var is a function or macro.
+    #[must_use]
+    pub fn arglists(&self) -> Option<&str> {
+        self.meta.get("arglists").map(String::as_str)
+    }
+
+    /// The docstring, read back from its printed form.
+    #[must_use]
+    pub fn doc(&self) -> Option<String> {
+        self.meta.get("doc").map(|doc| read_string_literal(doc))
+    }
+
+    #[must_use]
+    pub fn is_macro(&self) -> bool {
+        self.meta.get("macro").is_some_and(|v| v == "true")
+    }
+}
+
+/// Undo `pr-str` on a string: drop the quotes and the escapes. Text that is
+/// not a quoted literal comes back unchanged.
+fn read_string_literal(printed: &str) -> String {
+    let Some(inner) = printed
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    else {
+        return printed.to_string();
+    };
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Convert cider-nrepl's `ns-vars-with-meta` (`{var {key printed-value}}`);
+/// a var whose metadata is not a dict is kept with none.
+pub(crate) fn ns_vars_from_bencode(value: BencodeValue) -> Vec<NsVar> {
+    let BencodeValue::Dict(vars) = value else {
+        return Vec::new();
+    };
+    vars.into_iter()
+        .map(|(name, meta)| NsVar {
+            name,
+            meta: match meta {
+                BencodeValue::Dict(meta) => meta
+                    .into_iter()
+                    .map(|(k, v)| (k, v.to_string_repr()))
+                    .collect(),
+                _ => BTreeMap::new(),
+            },
+        })
+        .collect()
+}
+
+impl SymbolInfo {
+    /// Read an `info` exchange. `None` when the server found nothing
+    /// (`no-info`).
+    pub(crate) fn from_cider(responses: &[Response]) -> Option<Self> {
+        if has_status(responses, "no-info") {
+            return None;
+        }
+        let r = responses
+            .iter()
+            .find(|r| r.name.is_some() || r.member.is_some() || r.class.is_some())?;
+        let flag = |v: &Option<String>| v.as_deref().is_some_and(|v| v == "true");
+        Some(SymbolInfo {
+            name: r
+                .name
+                .clone()
+                .or_else(|| r.member.clone())
+                .unwrap_or_default(),
+            ns: r.ns.clone(),
+            arglists: r.arglists_str.clone(),
+            doc: r.doc.clone(),
+            file: r.file.clone(),
+            line: r.line.and_then(|n| u32::try_from(n).ok()),
+            column: r.column.and_then(|n| u32::try_from(n).ok()),
+            see_also: r.see_also.clone().unwrap_or_default(),
+            is_macro: flag(&r.is_macro),
+            special_form: flag(&r.special_form),
+            protocol: r.protocol.clone(),
+            class: r.class.clone(),
+            member: r.member.clone(),
+            modifiers: r.modifiers.clone(),
+            javadoc: r.javadoc.clone(),
+            spec: r.spec.clone(),
+        })
+    }
+}
+
+impl Eldoc {
+    /// Read an `eldoc` exchange. `None` when the server found nothing
+    /// (`no-eldoc`).
+    pub(crate) fn from_cider(responses: &[Response]) -> Option<Self> {
+        if has_status(responses, "no-eldoc") {
+            return None;
+        }
+        let r = responses
+            .iter()
+            .find(|r| r.eldoc.is_some() || r.name.is_some())?;
+        Some(Eldoc {
+            name: r.name.clone().or_else(|| r.member.clone()),
+            ns: r.ns.clone(),
+            kind: r.symbol_type.clone(),
+            arglists: r.eldoc.clone().unwrap_or_default(),
+            docstring: r.docstring.clone(),
+        })
+    }
+
+    /// Which parameter to highlight in each arglist when the cursor is on the
+    /// call's `arg`th argument (0-based): an index into that arglist, or
+    /// `None` for arities that cannot take that many arguments. Past the
+    /// fixed parameters of a variadic arity, the rest parameter is
+    /// highlighted.
+    #[must_use]
+    pub fn highlight(&self, arg: usize) -> Vec<Option<usize>> {
+        self.arglists
+            .iter()
+            .map(|params| match params.iter().position(|p| p == "&") {
+                Some(amp) if arg >= amp => (amp + 1 < params.len()).then_some(amp + 1),
+                _ => (arg < params.len()).then_some(arg),
+            })
+            .collect()
+    }
+}
+
+impl AproposMatch {
+    /// Convert one match dict. cider-nrepl names matches fully qualified
+    /// (`clojure.core/map`); `None` if the dict has no name.
+    pub(crate) fn from_cider(m: &BTreeMap<String, BencodeValue>) -> Option<Self> {
+        let text = |key: &str| match m.get(key) {
+            Some(BencodeValue::String(s)) if !s.is_empty() => Some(s.clone()),
+            _ => None,
+        };
+        let qualified = text("name")?;
+        // Split at the first slash so `clojure.core//` keeps `/` as the name.
+        let (ns, name) = match qualified.split_once('/') {
+            Some((ns, name)) if !ns.is_empty() && !name.is_empty() => {
+                (Some(ns.to_string()), name.to_string())
+            }
+            _ => (None, qualified),
+        };
+        Some(AproposMatch {
+            ns,
+            name,
+            kind: text("type").unwrap_or_default(),
+            doc: text("doc"),
+        })
+    }
+
+    /// `ns/name`, or just the name when there is no namespace.
+    #[must_use]
+    pub fn qualified_name(&self) -> String {
+        match &self.ns {
+            Some(ns) => format!("{ns}/{}", self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+impl ClojureDocs {
+    /// Convert the `clojuredocs` dict; `None` without a name.
+    pub(crate) fn from_cider(m: &BTreeMap<String, BencodeValue>) -> Option<Self> {
+        let text = |key: &str| match m.get(key) {
+            Some(BencodeValue::String(s)) if !s.is_empty() => Some(s.clone()),
+            _ => None,
+        };
+        let list = |key: &str| match m.get(key) {
+            Some(BencodeValue::List(items)) => {
+                items.iter().map(BencodeValue::to_string_repr).collect()
+            }
+            _ => Vec::new(),
+        };
+        Some(ClojureDocs {
+            ns: text("ns").unwrap_or_default(),
+            name: text("name")?,
+            doc: text("doc"),
+            arglists: list("arglists"),
+            examples: list("examples"),
+            see_also: list("see-alsos"),
+            notes: list("notes"),
+            href: text("href"),
+        })
+    }
+}
+
+impl XrefVar {
+    /// Convert one var dict; `None` if it has no name.
+    pub(crate) fn from_cider(m: &BTreeMap<String, BencodeValue>) -> Option<Self> {
+        let text = |key: &str| match m.get(key) {
+            Some(BencodeValue::String(s)) if !s.is_empty() => Some(s.clone()),
+            _ => None,
+        };
+        let number = |key: &str| match m.get(key) {
+            Some(BencodeValue::Int(n)) => u32::try_from(*n).ok(),
+            Some(BencodeValue::String(s)) => s.trim().parse().ok(),
+            _ => None,
+        };
+        let qualified = text("name")?;
+        let (ns, name) = match qualified.split_once('/') {
+            Some((ns, name)) if !ns.is_empty() && !name.is_empty() => {
+                (Some(ns.to_string()), name.to_string())
+            }
+            _ => (None, qualified),
+        };
+        Some(XrefVar {
+            ns,
+            name,
+            doc: text("doc"),
+            file: text("file"),
+            file_url: text("file-url"),
+            line: number("line"),
+            column: number("column"),
+        })
+    }
+
+    /// `ns/name`, or just the name when there is no namespace.
+    #[must_use]
+    pub fn qualified_name(&self) -> String {
+        match &self.ns {
+            Some(ns) => format!("{ns}/{}", self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+fn has_status(responses: &[Response], status: &str) -> bool {
+    responses
+        .iter()
+        .any(|r| r.status.iter().any(|s| s == status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::decode_response;
+
+    fn decode(frame: &str) -> Response {
+        decode_response(frame.as_bytes()).unwrap().0
+    }
+
+    #[test]
+    fn test_info_from_cider_var() {
+        let info = decode(concat!(
+            "d12:arglists-str10:([f coll])",
+            "3:doc9:Maps f...",
+            "4:file16:clojure/core.clj",
+            "2:id1:1",
+            "4:linei2776e",
+            "4:name3:map",
+            "2:ns12:clojure.core",
+            "8:see-alsol19:clojure.core/mapcate",
+            "6:statusl4:doneee"
+        ));
+        let info = SymbolInfo::from_cider(&[info]).unwrap();
+        assert_eq!(info.name, "map");
+        assert_eq!(info.ns.as_deref(), Some("clojure.core"));
+        assert_eq!(info.arglists.as_deref(), Some("([f coll])"));
+        assert_eq!(info.line, Some(2776));
+        assert_eq!(info.see_also, vec!["clojure.core/mapcat"]);
+        assert!(!info.is_macro);
+    }
+
+    #[test]
+    fn test_info_from_cider_java_member() {
+        let info = decode(concat!(
+            "d5:class16:java.lang.String2:id1:1",
+            "6:member11:toUpperCase9:modifiers9:#{public}",
+            "6:statusl4:doneee"
+        ));
+        let info = SymbolInfo::from_cider(&[info]).unwrap();
+        assert_eq!(info.name, "toUpperCase");
+        assert_eq!(info.class.as_deref(), Some("java.lang.String"));
+        assert_eq!(info.modifiers.as_deref(), Some("#{public}"));
+        assert_eq!(info.ns, None);
+    }
+
+    #[test]
+    fn test_info_from_cider_no_info() {
+        let done = decode("d2:id1:16:statusl4:done7:no-infoee");
+        assert_eq!(SymbolInfo::from_cider(&[done]), None);
+    }
+
+    #[test]
+    fn test_eldoc_from_cider() {
+        let eldoc = decode(concat!(
+            "d5:eldocll1:fel1:f4:collel1:f2:c12:c21:&5:collsee",
+            "2:id1:14:name3:map2:ns12:clojure.core",
+            "6:statusl4:donee4:type8:functione"
+        ));
+        let eldoc = Eldoc::from_cider(&[eldoc]).unwrap();
+        assert_eq!(eldoc.name.as_deref(), Some("map"));
+        assert_eq!(eldoc.kind.as_deref(), Some("function"));
+        assert_eq!(eldoc.arglists.len(), 3);
+        assert_eq!(eldoc.arglists[1], vec!["f", "coll"]);
+
+        assert_eq!(eldoc.highlight(0), vec![Some(0), Some(0), Some(0)]);
+        assert_eq!(eldoc.highlight(1), vec![None, Some(1), Some(1)]);
+        assert_eq!(eldoc.highlight(2), vec![None, None, Some(2)]);
+        // Everything past the fixed parameters lands on the rest parameter.
+        assert_eq!(eldoc.highlight(3), vec![None, None, Some(4)]);
+        assert_eq!(eldoc.highlight(9), vec![None, None, Some(4)]);
+    }
+
+    #[test]
+    fn test_eldoc_from_cider_no_eldoc() {
+        let done = decode("d2:id1:16:statusl4:done8:no-eldocee");
+        assert_eq!(Eldoc::from_cider(&[done]), None);
+    }
+
+    #[test]
+    fn test_apropos_matches_split_names() {
+        let response = decode(concat!(
+            "d15:apropos-matchesl",
+            "d3:doc23:Returns a lazy sequence4:name16:clojure.core/map4:type8:functione",
+            "d4:name14:clojure.core//4:type8:functione",
+            "d4:name2:if4:type12:special-forme",
+            "d3:doc7:no namee",
+            "e2:id1:16:statusl4:doneee"
+        ));
+        let matches = response.apropos_matches.unwrap();
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[0].ns.as_deref(), Some("clojure.core"));
+        assert_eq!(matches[0].name, "map");
+        assert_eq!(matches[0].doc.as_deref(), Some("Returns a lazy sequence"));
+        assert_eq!(matches[1].name, "/");
+        assert_eq!(matches[1].qualified_name(), "clojure.core//");
+        assert_eq!(matches[2].ns, None);
+        assert_eq!(matches[2].kind, "special-form");
+        assert_eq!(matches[2].qualified_name(), "if");
+    }
+
+    #[test]
+    fn test_ns_vars_from_cider() {
+        let response = decode(concat!(
+            "d2:id1:1",
+            "17:ns-vars-with-metad",
+            "3:addd8:arglists7:([x y])3:doc14:\"Adds \\\"two\\\"\"e",
+            "4:whend5:macro4:truee",
+            "e6:statusl4:doneee"
+        ));
+        let vars = response.ns_vars_with_meta.unwrap();
+        assert_eq!(vars.len(), 2);
+        assert_eq!(vars[0].name, "add");
+        assert_eq!(vars[0].arglists(), Some("([x y])"));
+        assert_eq!(vars[0].doc().as_deref(), Some("Adds \"two\""));
+        assert!(!vars[0].is_macro());
+        assert!(vars[1].is_macro());
+        assert_eq!(vars[1].doc(), None);
+    }
+
+    #[test]
+    fn test_fn_refs_carry_locations() {
+        let response = decode(concat!(
+            "d7:fn-refsl",
+            "d4:file12:demo/app.clj8:file-url22:file:/src/demo/app.clj",
+            "4:linei12e4:name12:demo.app/run",
+            "e",
+            "d4:name4:orphe",
+            "i7ee",
+            "2:id1:16:statusl4:doneee"
+        ));
+        let refs = response.fn_refs.unwrap();
+        assert_eq!(refs.len(), 2);
+        assert_eq!(refs[0].qualified_name(), "demo.app/run");
+        assert_eq!(refs[0].file.as_deref(), Some("demo/app.clj"));
+        assert_eq!(refs[0].line, Some(12));
+        assert_eq!(refs[0].column, None);
+        assert_eq!(refs[1].ns, None);
+    }
+
+    #[test]
+    fn test_clojuredocs_entry() {
+        let response = decode(concat!(
+            "d11:clojuredocsd",
+            "8:examplesl14:(inc 1)\n;;=> 2e",
+            "4:name3:inc2:ns12:clojure.core",
+            "9:see-alsosl11:clojure/dece",
+            "e2:id1:16:statusl4:doneee"
+        ));
+        let docs = response.clojuredocs.unwrap();
+        assert_eq!(docs.name, "inc");
+        assert_eq!(docs.examples, ["(inc 1)\n;;=> 2"]);
+        assert_eq!(docs.see_also, ["clojure/dec"]);
+        assert!(docs.notes.is_empty());
+    }
+}
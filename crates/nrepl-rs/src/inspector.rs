@@ -0,0 +1,321 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! cider-nrepl's value inspector
+//!
+//! The inspector lives server-side, one per session: evaluating with
+//! `inspect` set opens it on the result, and the `inspect-*` ops walk it
+//! (into a nested value, back out, through pages of a large collection).
+//! Every op answers with the whole view re-rendered, printed as a list of
+//! strings, `(:newline)` markers and `(:value "text" index)` references; the
+//! index is what `inspect-push` takes to descend. [`InspectorPage`] is that
+//! list read back into lines.
+
+use crate::error::NReplError;
+use crate::message::Response;
+
+/// One rendering of the inspector's current view.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InspectorPage {
+    /// The view, line by line.
+    pub lines: Vec<Vec<InspectorChunk>>,
+    /// How the current value was reached from the inspected root, printed
+    /// (for example `(nth 2) :body`).
+    pub path: Option<String>,
+    /// Paging of a large collection; `None` when it fits on one page.
+    pub page: Option<InspectorPaging>,
+}
+
+/// A piece of an inspector line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InspectorChunk {
+    Text(String),
+    /// A nested value, printed. Pass `index` to `inspect-push` to inspect it.
+    Value {
+        text: String,
+        index: u32,
+    },
+}
+
+/// Where the inspector is in a paged collection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InspectorPaging {
+    /// 1-based.
+    pub current: u32,
+    pub total: u32,
+    /// Items per page.
+    pub size: u32,
+}
+
+impl InspectorPage {
+    /// Read an inspector exchange. A failure (the eval threw, or there is
+    /// nothing to inspect) is an [`NReplError::OperationFailed`] with the
+    /// server's message.
+    pub(crate) fn from_cider(responses: &[Response]) -> Result<Self, NReplError> {
+        let path = responses
+            .iter()
+            .find_map(|r| r.path.clone())
+            .filter(|p| !p.is_empty());
+        let Some(rendered) = responses.iter().rev().find_map(|r| r.value.as_deref()) else {
+            let err: String = responses.iter().filter_map(|r| r.err.as_deref()).collect();
+            return Err(NReplError::OperationFailed(if err.is_empty() {
+                "inspector returned no view".to_string()
+            } else {
+                err.trim_end().to_string()
+            }));
+        };
+        let mut page = Self::parse(rendered).ok_or_else(|| {
+            NReplError::protocol(format!("unreadable inspector view: {rendered}"))
+        })?;
+        page.path = path;
+        Ok(page)
+    }
+
+    /// Read a printed rendering. `nil` (nothing inspected) is an empty page.
+    fn parse(rendered: &str) -> Option<Self> {
+        let items = match Reader::new(rendered).form()? {
+            Form::List(items) => items,
+            Form::Atom(a) if a == "nil" => Vec::new(),
+            _ => return None,
+        };
+        let mut lines = vec![Vec::new()];
+        for item in items {
+            match item {
+                Form::Str(s) => {
+                    // Text is not supposed to span lines, but split if it does.
+                    let mut parts = s.split('\n');
+                    if let Some(first) = parts.next().filter(|p| !p.is_empty()) {
+                        push_text(&mut lines, first);
+                    }
+                    for part in parts {
+                        lines.push(Vec::new());
+                        if !part.is_empty() {
+                            push_text(&mut lines, part);
+                        }
+                    }
+                }
+                Form::List(marker) => match marker.as_slice() {
+                    [Form::Atom(k)] if k == ":newline" => lines.push(Vec::new()),
+                    [Form::Atom(k), Form::Str(text), Form::Atom(index)] if k == ":value" => {
+                        let index = index.parse().ok()?;
+                        lines.last_mut()?.push(InspectorChunk::Value {
+                            text: text.clone(),
+                            index,
+                        });
+                    }
+                    _ => {}
+                },
+                Form::Atom(_) => {}
+            }
+        }
+        // The view ends with a newline; drop the empty line it leaves.
+        if lines.last().is_some_and(Vec::is_empty) {
+            lines.pop();
+        }
+        let mut page = InspectorPage {
+            lines,
+            path: None,
+            page: None,
+        };
+        page.page = page.paging();
+        Some(page)
+    }
+
+    /// The view as plain text, values shown as printed.
+    #[must_use]
+    pub fn text(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|chunk| match chunk {
+                        InspectorChunk::Text(t) | InspectorChunk::Value { text: t, .. } => {
+                            t.as_str()
+                        }
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The page info line cider-nrepl adds to a paged view:
+    /// `Page size: 32, showing page: 2 of 5`.
+    fn paging(&self) -> Option<InspectorPaging> {
+        let text = self.text();
+        let number_after = |label: &str| -> Option<u32> {
+            let start = text.find(label)? + label.len();
+            let digits: String = text[start..]
+                .trim_start()
+                .chars()
+                .take_while(char::is_ascii_digit)
+                .collect();
+            digits.parse().ok()
+        };
+        let current = number_after("showing page:")?;
+        let total = number_after(&format!("showing page: {current} of"))?;
+        (total > 1).then(|| InspectorPaging {
+            current,
+            total,
+            size: number_after("Page size:").unwrap_or(0),
+        })
+    }
+}
+
+fn push_text(lines: &mut [Vec<InspectorChunk>], text: &str) {
+    if let Some(line) = lines.last_mut() {
+        line.push(InspectorChunk::Text(text.to_string()));
+    }
+}
+
+/// The printed rendering, read just far enough to walk it.
+enum Form {
+    Str(String),
+    /// A keyword, number or symbol, as written.
+    Atom(String),
+    List(Vec<Form>),
+}
+
+struct Reader<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(s: &'a str) -> Self {
+        Reader {
+            chars: s.chars().peekable(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .chars
+            .peek()
+            .is_some_and(|c| c.is_whitespace() || *c == ',')
+        {
+            self.chars.next();
+        }
+    }
+
+    fn form(&mut self) -> Option<Form> {
+        self.skip_whitespace();
+        match *self.chars.peek()? {
+            '(' | '[' => {
+                self.chars.next();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if matches!(self.chars.peek()?, ')' | ']') {
+                        self.chars.next();
+                        return Some(Form::List(items));
+                    }
+                    items.push(self.form()?);
+                }
+            }
+            '"' => {
+                self.chars.next();
+                let mut s = String::new();
+                loop {
+                    match self.chars.next()? {
+                        '"' => return Some(Form::Str(s)),
+                        '\\' => match self.chars.next()? {
+                            'n' => s.push('\n'),
+                            't' => s.push('\t'),
+                            'r' => s.push('\r'),
+                            other => s.push(other),
+                        },
+                        c => s.push(c),
+                    }
+                }
+            }
+            _ => {
+                let mut atom = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if c.is_whitespace() || matches!(c, ',' | '(' | ')' | '[' | ']' | '"') {
+                        break;
+                    }
+                    atom.push(c);
+                    self.chars.next();
+                }
+                (!atom.is_empty()).then_some(Form::Atom(atom))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rendered_view() {
+        let page = InspectorPage::parse(concat!(
+            r#"("Class" ": " (:value "clojure.lang.PersistentArrayMap" 0) (:newline) "#,
+            r#"(:newline) "--- Contents:" (:newline) "  " (:value ":a" 1) " = " "#,
+            r#"(:value "\"x\"" 2) (:newline))"#
+        ))
+        .expect("a view");
+        assert_eq!(page.lines.len(), 4);
+        assert_eq!(
+            page.lines[3],
+            vec![
+                InspectorChunk::Text("  ".to_string()),
+                InspectorChunk::Value {
+                    text: ":a".to_string(),
+                    index: 1
+                },
+                InspectorChunk::Text(" = ".to_string()),
+                InspectorChunk::Value {
+                    text: "\"x\"".to_string(),
+                    index: 2
+                },
+            ]
+        );
+        assert_eq!(
+            page.text(),
+            "Class: clojure.lang.PersistentArrayMap\n\n--- Contents:\n  :a = \"x\""
+        );
+        assert_eq!(page.page, None);
+
+        assert_eq!(InspectorPage::parse("nil"), Some(InspectorPage::default()));
+        assert_eq!(InspectorPage::parse("(\"unterminated"), None);
+    }
+
+    #[test]
+    fn test_paging_from_page_info() {
+        let page = InspectorPage::parse(
+            r#"("--- Page Info:" (:newline) "  Page size: 32, showing page: 2 of 5" (:newline))"#,
+        )
+        .expect("a view");
+        assert_eq!(
+            page.page,
+            Some(InspectorPaging {
+                current: 2,
+                total: 5,
+                size: 32
+            })
+        );
+    }
+
+    #[test]
+    fn test_failure_carries_server_message() {
+        let decode = |frame: &str| crate::codec::decode_response(frame.as_bytes()).unwrap().0;
+        let responses = [
+            decode("d3:err14:Boom: divide!\n2:id5:req-1e"),
+            decode("d2:id5:req-16:statusl4:done10:eval-erroree"),
+        ];
+        match InspectorPage::from_cider(&responses) {
+            Err(NReplError::OperationFailed(msg)) => assert_eq!(msg, "Boom: divide!"),
+            other => panic!("expected OperationFailed, got {other:?}"),
+        }
+    }
+}
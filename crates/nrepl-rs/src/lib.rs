@@ -110,66 +110,263 @@
 //! - **REPL State**: Line numbers, *1/*2/*3 values, etc.
 //!
 //! A [`Session`] is just a wire id, and the server is the authority on which
-//! ids are live: the worker keeps no client-side session registry. Sessions
-//! must be explicitly closed to free server resources. Evaluating against a
-//! session the server has retired yields an empty result rather than an error,
-//! so track liveness with `close-session` or `ls-sessions` if you need it.
+//! ids are live: the worker keeps no client-side registry of live sessions.
+//! Sessions must be explicitly closed to free server resources. Evaluating
+//! against a session the server has retired yields an empty result rather
+//! than an error, so track liveness with `close-session` or `ls-sessions` if
+//! you need it. What the worker does remember is its own traffic:
+//! [`Worker::sessions`](worker::Worker::sessions) lists the sessions it has
+//! cloned or used as [`SessionInfo`]s, with a label set by
+//! [`label_session`](worker::Worker::label_session), when each was created
+//! and last used, and how many requests of each op went to it. With
+//! [`WorkerConfig::session_idle_ttl`](worker::WorkerConfig::session_idle_ttl)
+//! it also closes the ones left idle, so a long-lived editor does not pile up
+//! forgotten sessions on the server. After a server restart,
+//! [`reconcile_sessions`](worker::Worker::reconcile_sessions) checks the
+//! tracked sessions against `ls-sessions` and drops the stale ones. After a
+//! client restart, [`export_sessions`](worker::Worker::export_sessions) and
+//! [`import_sessions`](worker::Worker::import_sessions) carry them over as
+//! serializable [`SessionDescriptor`]s, re-registering only the ids the
+//! server still lists.
+//!
+//! [`SessionManager`] keeps a pool of warm sessions for callers that want a
+//! fresh one per task without a `clone` round trip each time. [`NReplFleet`]
+//! holds a worker and session for each of several servers (a JVM backend, a
+//! shadow-cljs build, babashka) and can broadcast a form to all of them.
+//!
+//! ### Server Flavors
+//!
+//! babashka, nbb and Python servers implement a subset of the reference
+//! server's ops. With
+//! [`WorkerConfig::detect_server`](worker::WorkerConfig::detect_server) the
+//! worker reads a [`ServerProfile`] from `describe` on connect and adapts to
+//! it: completions go to `complete` where `completions` is missing, timed-out
+//! evals are not interrupted on servers that cannot, the default eval timeout
+//! follows the [`ServerFlavor`], and eval results missing `ns` get the
+//! session's last known namespace.
 //!
 //! ### Error Handling
 //!
 //! The [`NReplError`] enum provides detailed error information:
 //! - **Connection errors**: Network failures, server disconnects
+//! - **Lost connections**: A half-open socket or a stalled read or write,
+//!   failed at once instead of by each op's timeout (see
+//!   [`WorkerConfig::half_open_after`](worker::WorkerConfig::half_open_after))
 //! - **Codec errors**: Malformed bencode messages (with position and buffer preview)
 //! - **Protocol errors**: Invalid responses, missing required fields
-//! - **Timeout errors**: Operations exceeding their timeout duration
+//! - **Timeout errors**: Operations exceeding their timeout duration (an eval
+//!   timeout carries the output gathered so far, see [`NReplError::partial_result`])
 //! - **Session errors**: Invalid or closed sessions
 //! - **Operation errors**: Server-reported failures
+//! - **Cancellation**: Requests abandoned through a
+//!   [`CancellationToken`](worker::CancellationToken) (the connection stays usable)
+//! - **Protocol violations**: A control op the server answered but never finished
+//!   with `done` (see [`WorkerConfig::done_timeout`](worker::WorkerConfig::done_timeout))
+//!
+//! [`NReplError::code`] sorts an error into an [`ErrorCode`] to branch on, and
+//! [`is_retryable`](NReplError::is_retryable) and
+//! [`is_connection_dead`](NReplError::is_connection_dead) answer the two
+//! questions an editor usually has: try again, or reconnect?
 //!
 //! A read error is terminal for the connection: the worker fails every pending
-//! op with a [`NReplError::Connection`] carrying the underlying message.
+//! op with a [`NReplError::Connection`] carrying the underlying message, or a
+//! [`NReplError::ConnectionLost`] when the socket stalled rather than closed.
+//! With [`reconnect_on_loss`](worker::WorkerConfig::reconnect_on_loss) the
+//! worker then connects to the same address again by itself.
+//!
+//! The worker's blocking `describe`, `completions`, `lookup` and `ls_sessions`
+//! helpers only read server state, so a timeout or a missing `done` there is
+//! retried by a [`RetryPolicy`] (three attempts by default) before it reaches
+//! the caller. Evals are never retried.
+//!
+//! Each blocking helper waits for its reply as long as the worker's
+//! [`Timeouts`] give its kind of op (30 seconds for all of them by default),
+//! set with [`WorkerConfig::timeouts`](worker::WorkerConfig::timeouts). For
+//! the retried helpers that is the wait for all attempts together.
 //!
 //! ## Supported Operations
 //!
-//! Evals are submitted with [`submit_eval`](worker::Worker::submit_eval) and
-//! [`submit_load_file`](worker::Worker::submit_load_file). Everything else is a
+//! Evals are submitted with [`submit_eval`](worker::Worker::submit_eval),
+//! [`eval_in_ns`](worker::Worker::eval_in_ns) and
+//! [`submit_load_file`](worker::Worker::submit_load_file), or as a blocking
+//! batch with [`eval_batch`](worker::Worker::eval_batch); the namespace each
+//! session ended up in is kept as replies arrive and read back with
+//! [`session_ns`](worker::Worker::session_ns).
+//! [`add_libs`](worker::Worker::add_libs) is a blocking eval too, loading
+//! Maven libraries into the running REPL with Clojure 1.12's `add-libs`.
+//! Everything else is a
 //! [`worker::WorkerCommand`] variant carrying a reply channel:
 //!
 //! - [`Interrupt`](worker::WorkerCommand::Interrupt) - Interrupt an ongoing evaluation
 //! - [`Stdin`](worker::WorkerCommand::Stdin) - Answer an eval's `need-input`
-//! - [`CloneSession`](worker::WorkerCommand::CloneSession) - Create a new session
+//! - [`CloneSession`](worker::WorkerCommand::CloneSession), [`CloneSessionFrom`](worker::WorkerCommand::CloneSessionFrom) - Create a new session, fresh or inheriting an existing one's bindings
 //! - [`CloseSession`](worker::WorkerCommand::CloseSession) - Close a session
 //! - [`Describe`](worker::WorkerCommand::Describe) - Query server capabilities
 //! - [`LsSessions`](worker::WorkerCommand::LsSessions) - List the server's sessions
-//! - [`Completions`](worker::WorkerCommand::Completions) - Request code completions
-//! - [`Lookup`](worker::WorkerCommand::Lookup) - Look up symbol information
+//! - [`UpgradeCljs`](worker::WorkerCommand::UpgradeCljs) - Turn a session into a ClojureScript REPL with piggieback or shadow-cljs, as a [`CljsRepl`]; its completions then go to cider-nrepl's `complete`
+//! - [`AddMiddleware`](worker::WorkerCommand::AddMiddleware) - Add middleware to a running server's handler, such as cider-nrepl's when the server started without it
+//! - [`LsMiddleware`](worker::WorkerCommand::LsMiddleware) - The server's middleware stack in order, as a [`MiddlewareStack`]
+//! - [`SwapMiddleware`](worker::WorkerCommand::SwapMiddleware) - Replace the server's whole stack; [`plan_swap_middleware`](worker::Worker::plan_swap_middleware) first says which ops the new one would drop, as a [`SwapPlan`]
+//! - [`Completions`](worker::WorkerCommand::Completions) - Request code completions (cached per session with [`cache_responses`](worker::WorkerConfig::cache_responses))
+//! - [`Lookup`](worker::WorkerCommand::Lookup) - Look up symbol information (likewise)
+//! - [`NsAliases`](worker::WorkerCommand::NsAliases) - A namespace's aliases and refers (cached)
+//! - [`AnalyzeStacktrace`](worker::WorkerCommand::AnalyzeStacktrace) - The last exception as a [`StackTrace`] (cider-nrepl)
+//! - [`Info`](worker::WorkerCommand::Info) - Symbol documentation and location as [`SymbolInfo`], Java members included (cider-nrepl)
+//! - [`ClojureDocs`](worker::WorkerCommand::ClojureDocs) - A var's community examples, see-alsos and notes from ClojureDocs, as a [`ClojureDocs`] (cider-nrepl)
+//! - [`Eldoc`](worker::WorkerCommand::Eldoc) - A function's arglists as an [`Eldoc`], for argument highlighting (cider-nrepl)
+//! - [`Apropos`](worker::WorkerCommand::Apropos) - Search loaded vars by name or docstring, as [`AproposMatch`]es (cider-nrepl)
+//! - [`FnRefs`](worker::WorkerCommand::FnRefs), [`FnDeps`](worker::WorkerCommand::FnDeps) - A function's callers and callees as [`XrefVar`]s with their locations, for find references (cider-nrepl)
+//! - [`NsList`](worker::WorkerCommand::NsList), [`NsVars`](worker::WorkerCommand::NsVars), [`NsPath`](worker::WorkerCommand::NsPath) - Loaded namespaces, their vars as [`NsVar`]s, and their source files (cider-nrepl)
+//! - [`Classpath`](worker::WorkerCommand::Classpath) - The server's classpath; with the `jar-sources` feature, `resolve_source` uses it to extract library source from jars (cider-nrepl)
+//! - [`SpecList`](worker::WorkerCommand::SpecList), [`SpecForm`](worker::WorkerCommand::SpecForm), [`SpecExample`](worker::WorkerCommand::SpecExample) - Browse clojure.spec: registered names, a spec's form as a [`SpecForm`], a generated example; [`SymbolInfo::spec`] has a function's `fdef` (cider-nrepl)
+//! - [`Undef`](worker::WorkerCommand::Undef), [`UndefAll`](worker::WorkerCommand::UndefAll) - Remove a stale var, or everything a namespace defines (cider-nrepl)
+//! - [`ToggleTraceVar`](worker::WorkerCommand::ToggleTraceVar), [`ToggleTraceNs`](worker::WorkerCommand::ToggleTraceNs) - Trace calls to a function, or a whole namespace, as a [`VarTrace`] or [`TraceState`]; trace lines arrive as the calling eval's output (cider-nrepl)
+//! - [`FormatCode`](worker::WorkerCommand::FormatCode), [`FormatEdn`](worker::WorkerCommand::FormatEdn) - Format Clojure with cljfmt, or pretty-print EDN, server-side (cider-nrepl)
+//! - [`RunTests`](worker::WorkerCommand::RunTests) - Run a namespace's tests, all tests, or the last failures, as [`TestResults`] (cider-nrepl)
+//! - [`TestStacktrace`](worker::WorkerCommand::TestStacktrace) - The stack trace of an erroring test (cider-nrepl)
+//! - [`Refresh`](worker::WorkerCommand::Refresh), [`RefreshClear`](worker::WorkerCommand::RefreshClear) - Reload changed namespaces, reporting the one that broke the reload in a [`RefreshReport`] (cider-nrepl)
+//! - [`Inspect`](worker::WorkerCommand::Inspect), [`Inspector`](worker::WorkerCommand::Inspector) - Browse a value in the server's inspector, a paged view rendered as an [`InspectorPage`] (cider-nrepl)
+//! - [`StartSideloader`](worker::WorkerCommand::StartSideloader) - Serve classes and resources the server asks for from a [`SideloadProvider`], such as [`directory_provider`]
+//! - [`OutSubscribe`](worker::WorkerCommand::OutSubscribe), [`OutUnsubscribe`](worker::WorkerCommand::OutUnsubscribe) - Receive output printed outside any eval, such as background threads' logging, as [`ServerOutput`] (cider-nrepl)
+//! - [`SubscribeTap`](worker::WorkerCommand::SubscribeTap) - Stream values sent with `tap>` on the server, apart from eval results
+//! - [`InitDebugger`](worker::WorkerCommand::InitDebugger), [`DebugInput`](worker::WorkerCommand::DebugInput) - Step through `#dbg`-instrumented code: breakpoint stops arrive as [`DebugBreak`]s, answered with a [`DebugCommand`] (cider-nrepl)
+//!
+//! ## Structured Values
+//!
+//! Values come back as printed strings. With the `edn` feature,
+//! `EvalResult::value_edn` reads one into an `EdnValue` tree (maps, vectors,
+//! keywords, numbers, strings), so tooling can inspect data without scraping
+//! text.
+//!
+//! With [`WorkerConfig::rich_content`](worker::WorkerConfig::rich_content),
+//! cider-nrepl's content-type middleware also sends values it can present
+//! (an image file, a rendered chart, a URL) as MIME content, read into
+//! [`EvalResult::rich_content`] as a [`RichContent`] for a frontend to show
+//! inline.
+//!
+//! With the `json` feature, `Response::to_json` turns a response into a
+//! JSON object keyed by its wire names, for logging pipelines and tools
+//! outside Rust.
+//!
+//! ## Blocking Client
+//!
+//! With the `blocking` feature,
+//! [`blocking::NReplClient`](blocking::NReplClient) wraps a worker and a
+//! session of its own in calls that wait for the server's answer, for CLI
+//! tools and build scripts that have no event loop to poll from.
+//!
+//! `NReplClient::run_conformance_check` puts each core op to the server
+//! once and reports what answered as expected, what deviated, and how long
+//! each took: a quick way to tell whether a server setup will work, and a
+//! table to paste into a bug report.
+//!
+//! Code that takes an [`NReplOps`] runs against this client, and in tests
+//! against a [`MockOps`], which answers from queued replies and records the
+//! calls it gets.
+//!
+//! With the `test-utils` feature, a
+//! [`testing::MockNReplServer`](testing::MockNReplServer) stands in for the
+//! server itself: it answers each request as a test scripts it, down to
+//! malformed frames, split packets and replies out of order.
+//!
+//! ## Reloading on Save
+//!
+//! With the `watch` feature, a [`ReloadWatcher`](watch::ReloadWatcher)
+//! watches project directories and reloads `.clj`, `.cljc` and `.cljs` files
+//! as they are saved, with `load-file` or cider-nrepl's `refresh`. It is
+//! polled alongside the worker and reports each reload as a
+//! [`ReloadEvent`](watch::ReloadEvent).
 //!
 //! ## Debug Logging
 //!
-//! Set the `NREPL_DEBUG` environment variable to enable detailed debug logging:
+//! Enable the `tracing` feature to have the client report through the
+//! [`tracing`](https://docs.rs/tracing) crate: an `nrepl.send` span per request
+//! written (fields `op`, `id`), an `nrepl.response` span per response routed
+//! (fields `id`, `status`), and `DEBUG` events for stream reads and decodes.
+//! Install any subscriber to collect them alongside your application's logs.
+//!
+//! ```toml
+//! nrepl-rs = { version = "0.5", features = ["tracing"] }
+//! ```
+//!
+//! Without the feature the same events go to stderr, but only when the
+//! `NREPL_DEBUG` environment variable is set:
 //!
 //! ```sh
 //! NREPL_DEBUG=1 cargo run 2> nrepl-debug.log
 //! ```
 //!
 //! Debug logs include:
-//! - Code being evaluated (with byte counts)
-//! - Request/response IDs for correlation
-//! - Response status messages
-//! - Buffer management operations
-//! - Stream read activity
+//! - Request/response IDs and ops for correlation
+//! - Byte counts for writes, reads and decoded frames
+//! - Skipped undecodable responses and reads of part-received responses
+//! - At `TRACE` (or with `NREPL_DEBUG`), a hex/ASCII preview of a stalled
+//!   decode buffer
 //!
 //! ### Security Warning
 //!
 //! **⚠️ Debug logs may contain sensitive information:**
-//! - Source code being evaluated (may include secrets, credentials, API keys)
-//! - Evaluation results and output
-//! - Session IDs
-//! - Buffer contents in hexadecimal format
+//! - Buffer previews can include source code being evaluated (may include
+//!   secrets, credentials, API keys), evaluation results and session IDs
 //!
 //! **Never enable debug logging in production environments.** Only use it during
 //! development and debugging, and ensure debug logs are not committed to version
 //! control or exposed to unauthorized users.
 //!
+//! ## Large Response Telemetry
+//!
+//! Independently of debug logging, the reader reports any single response
+//! field (usually `value` or `out`) larger than 1MB as a [`LargeField`] event:
+//! the key, its size and the request id, never the payload. Events go to
+//! stderr unless a hook is installed; both the threshold and the hook are set
+//! on [`worker::WorkerConfig`].
+//!
+//! ## Frame Capture
+//!
+//! For codec and protocol errors that need the exact bytes, have the worker
+//! append every raw frame to a file, optionally rotated by size:
+//!
+//! ```no_run
+//! use nrepl_rs::worker::{Worker, WorkerConfig};
+//!
+//! let worker = Worker::with_config(
+//!     WorkerConfig::default()
+//!         .capture_frames("/tmp/nrepl-frames.log")
+//!         .rotate_capture_at(16 * 1024 * 1024),
+//! );
+//! ```
+//!
+//! Each line is `<unix seconds.millis> <direction> <length> <frame>`, where
+//! direction is `send`, `recv`, `skip` (a complete response that failed to
+//! decode), `partial` (bytes buffered when the reader gave up) or `drop`
+//! (bytes discarded by a resync). Bytes outside printable ASCII, and `\`, are
+//! escaped as `\xNN`, so the byte offset in a "Codec error at byte X" can be
+//! counted off the line. Frames carry code, output and session ids: the same
+//! security warning applies as for debug logs. The one exception is the
+//! token set with [`auth_token`](worker::WorkerConfig::auth_token), which is
+//! sent on every request but captured as `<redacted>`.
+//!
+//! With the `test-utils` feature, a capture doubles as a test fixture:
+//! [`testing::Recording`](testing::Recording) reads it back and
+//! [`MockNReplServer::replay`](testing::MockNReplServer::replay) answers a
+//! client's requests with the responses the real server gave.
+//!
+//! ## Proxies and Other Transports
+//!
+//! With the `proxy` feature, [`WorkerConfig::proxy`](worker::WorkerConfig::proxy)
+//! routes the connection through a SOCKS5 or HTTP `CONNECT` proxy, for
+//! servers that are only reachable that way. See the `Proxy` type.
+//!
+//! With the `drawbridge` feature, a server that exposes nREPL only over
+//! HTTP can be reached through its drawbridge URL (`http://host/repl`) with
+//! `Worker::connect_http`; see the `drawbridge` module. With the
+//! `websocket` feature, `Worker::connect_ws` does the same for servers
+//! wrapped by a WebSocket bridge (`ws://host/nrepl`).
+//!
+//! A server run as a subprocess in stdio mode needs no port at all:
+//! [`Worker::connect_stdio`](worker::Worker::connect_stdio) takes the
+//! child's stdin and stdout.
+//!
 //! ## Troubleshooting
 //!
 //! ### Connection Errors
@@ -231,8 +428,10 @@
 //! **Problem**: High memory usage or OOM errors
 //!
 //! - **Large responses**: Results/output may exceed 10MB limits
-//! - **Session cleanup**: Remember to close sessions with `CloseSession`
+//! - **Session cleanup**: Remember to close sessions with `CloseSession`, or all
+//!   of them at once with [`close_all_sessions`](worker::Worker::close_all_sessions)
 //! - **Connection cleanup**: Call [`shutdown`](worker::Worker::shutdown) before dropping a worker
+//!   ([`abort`](worker::Worker::abort) if the server has stopped answering)
 //! - **Check output size**: Large print statements can consume significant memory
 //!
 //! ## Security Considerations
@@ -276,10 +475,57 @@
 //! This library is licensed under the GNU Affero General Public License v3.0 or later.
 //! See the LICENSE file for details.
 
+mod ansi;
+mod base64;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod cache;
+mod capture;
+#[cfg(feature = "jar-sources")]
+mod classpath;
+mod cljs;
+#[cfg(feature = "blocking")]
+mod conformance;
 mod connection;
+mod debugger;
+mod deps;
+#[cfg(feature = "drawbridge")]
+pub mod drawbridge;
+#[cfg(feature = "edn")]
+pub mod edn;
 mod error;
+mod events;
+mod flavor;
+mod fleet;
+mod forms;
+mod info;
+mod inspector;
 mod message;
+mod metrics;
+mod middleware;
+mod nrepl_ops;
+mod pool;
+#[cfg(feature = "proxy")]
+mod proxy;
+mod refresh;
+mod retry;
+mod rich_content;
 mod session;
+mod sideloader;
+mod source;
+mod spec;
+mod stacktrace;
+mod stdio;
+mod test_report;
+#[cfg(feature = "test-utils")]
+pub mod testing;
+mod timeouts;
+mod toggle_trace;
+mod trace;
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
 /// nREPL operation request builders, used by [`worker`] to construct requests
 /// with explicit ids.
@@ -290,19 +536,61 @@ pub(crate) mod ops;
 /// flight.
 pub mod worker;
 
-/// Bencode codec implementation (internal)
-///
-/// This module is public only to allow access from integration tests and benchmarks.
-/// It is hidden from documentation and should not be used by external code.
-/// The codec functionality is used internally for message serialization.
-///
-/// **Note**: This is not part of the public API and may change without notice.
-#[doc(hidden)]
+/// Bencode codec: framing, the tolerant [`BencodeValue`] parser the reader
+/// falls back on, and serde [`to_bytes`](codec::to_bytes)/
+/// [`from_bytes`](codec::from_bytes) for any type, so other tools can read
+/// and write bencode the way this client does.
 pub mod codec;
 
-pub use error::{NReplError, Result};
-pub use message::{CompletionCandidate, EvalResult, Response};
-pub use session::Session;
+pub use ansi::{AnsiColor, AnsiPolicy, AnsiStyle, OutputStyles, StyleSpan};
+pub use cache::{CacheStats, ResponseCache};
+#[cfg(feature = "jar-sources")]
+pub use classpath::resolve_source;
+pub use cljs::CljsRepl;
+#[cfg(feature = "blocking")]
+pub use conformance::{CheckOutcome, ConformanceCheck, ConformanceReport};
+pub use connection::{
+    DEFAULT_BATCH_BYTES, DEFAULT_LARGE_FIELD_THRESHOLD, DEFAULT_READ_CHUNK, FlushPolicy,
+    LargeField, LargeFieldHook, OutputSink,
+};
+pub use debugger::{DebugBreak, DebugCommand, DebugInputType};
+pub use deps::AddLibsReport;
+#[cfg(feature = "edn")]
+pub use edn::EdnValue;
+pub use error::{ErrorCode, NReplError, Result};
+pub use events::{DEFAULT_EVENT_LOG_CAPACITY, DebugEvent, DebugEventKind};
+pub use flavor::{ServerFlavor, ServerProfile};
+pub use fleet::NReplFleet;
+pub use forms::{
+    FormOptions, FormSpan, FormsAt, Position, forms_at, forms_at_with, forms_complete,
+};
+pub use info::{AproposMatch, ClojureDocs, Eldoc, NsVar, SymbolInfo, XrefVar};
+pub use inspector::{InspectorChunk, InspectorPage, InspectorPaging};
+pub use message::{
+    BencodeValue, ChunkKind, Code, CompletionCandidate, EvalResult, EvalTiming, NsAliases,
+    OutputChunk, Response, ServerOutput, SpilledOutput, TruncatedValue,
+};
+pub use metrics::{ClientMetrics, LatencyHistogram, MetricsSnapshot, OpMetrics};
+pub use middleware::{LostOp, MiddlewareDescriptor, MiddlewareLayer, MiddlewareStack, SwapPlan};
+pub use nrepl_ops::{MockOps, NReplOps, OpCall};
+pub use pool::SessionManager;
+#[cfg(feature = "proxy")]
+pub use proxy::Proxy;
+pub use refresh::{RefreshError, RefreshOptions, RefreshReport};
+pub use retry::RetryPolicy;
+pub use rich_content::{ContentType, RichContent};
+pub use session::{
+    Session, SessionDescriptor, SessionExpiredHook, SessionInfo, SessionReconciliation,
+};
+pub use sideloader::{SideloadKind, SideloadLookup, SideloadProvider, directory_provider};
+pub use source::{Dialect, NsForm, Require, parse_ns};
+pub use spec::SpecForm;
+pub use stacktrace::{Frame, StackTrace};
+pub use test_report::{
+    TestAssertion, TestDiff, TestOutcome, TestResults, TestSummary, TestsByNamespace,
+};
+pub use timeouts::{DEFAULT_CONTROL_TIMEOUT, Timeouts};
+pub use toggle_trace::{TraceState, VarTrace};
 
 #[cfg(test)]
 mod tests {
@@ -10,8 +10,21 @@
 // MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
 // GNU Affero General Public License for more details.
 
+use crate::ansi::{AnsiFilter, OutputStyles, StyleSpan};
+use crate::debugger::{DebugInputType, input_type_from_bencode, locals_from_bencode};
+use crate::info::{AproposMatch, ClojureDocs, NsVar, XrefVar, ns_vars_from_bencode};
+use crate::refresh::causes_from_bencode;
+use crate::rich_content::{ContentType, RichContent, content_type_from_bencode};
+use crate::spec::SpecForm;
+use crate::stacktrace::{Frame, StackTrace};
+use crate::test_report::{TestSummary, TestsByNamespace, results_from_bencode};
+use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer, Serialize};
+use std::borrow::Cow;
 use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant, SystemTime};
 
 /// Type alias for nested string maps (used in describe operation for ops/versions)
 type NestedStringMap = BTreeMap<String, BTreeMap<String, String>>;
@@ -26,7 +39,7 @@ pub struct Request {
 
     // eval operation
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) code: Option<String>,
+    pub(crate) code: Option<Code>,
 
     // eval operation - file location metadata
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -34,9 +47,9 @@ pub struct Request {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) column: Option<i64>,
 
-    // load-file operation
+    // load-file operation (the file's contents; an eval's source path)
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) file: Option<String>,
+    pub(crate) file: Option<Code>,
     #[serde(skip_serializing_if = "Option::is_none", rename = "file-path")]
     pub(crate) file_path: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none", rename = "file-name")]
@@ -51,7 +64,12 @@ pub struct Request {
     pub(crate) stdin: Option<String>,
 
     // describe operation
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        skip_serializing_if = "flag_unset",
+        deserialize_with = "deserialize_flag",
+        rename = "verbose?"
+    )]
     pub(crate) verbose: Option<bool>,
 
     // completions operation
@@ -75,19 +93,402 @@ pub struct Request {
     pub(crate) middleware: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none", rename = "extra-namespaces")]
     pub(crate) extra_namespaces: Option<Vec<String>>,
+
+    // cider-nrepl test operations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) tests: Option<Vec<String>>,
+    #[serde(
+        default,
+        skip_serializing_if = "flag_unset",
+        deserialize_with = "deserialize_flag",
+        rename = "load?"
+    )]
+    pub(crate) load: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) var: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) index: Option<i64>,
+
+    // cider-nrepl apropos operation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) query: Option<String>,
+
+    // cider-nrepl spec operations
+    #[serde(skip_serializing_if = "Option::is_none", rename = "filter-regex")]
+    pub(crate) filter_regex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", rename = "spec-name")]
+    pub(crate) spec_name: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "flag_unset",
+        deserialize_with = "deserialize_flag",
+        rename = "docs?"
+    )]
+    pub(crate) docs: Option<bool>,
+    #[serde(
+        default,
+        skip_serializing_if = "flag_unset",
+        deserialize_with = "deserialize_flag",
+        rename = "privates?"
+    )]
+    pub(crate) privates: Option<bool>,
+
+    // cider-nrepl format-edn operation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) edn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", rename = "print-right-margin")]
+    pub(crate) print_right_margin: Option<i64>,
+
+    // cider-nrepl inspector operations
+    #[serde(
+        default,
+        skip_serializing_if = "flag_unset",
+        deserialize_with = "deserialize_flag"
+    )]
+    pub(crate) inspect: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) idx: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none", rename = "page-size")]
+    pub(crate) page_size: Option<i64>,
+
+    // cider-nrepl refresh operations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) dirs: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) before: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) after: Option<String>,
+
+    // sideloader-provide operation
+    #[serde(skip_serializing_if = "Option::is_none", rename = "type")]
+    pub(crate) kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) content: Option<String>,
+
+    // nrepl.middleware.print, on eval: the most characters of a value to
+    // print before cutting it off
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        rename = "nrepl.middleware.print/quota"
+    )]
+    pub(crate) print_quota: Option<i64>,
+
+    // cider-nrepl content-type middleware, on eval
+    #[serde(
+        default,
+        skip_serializing_if = "flag_unset",
+        deserialize_with = "deserialize_flag",
+        rename = "content-type"
+    )]
+    pub(crate) content_type: Option<bool>,
+
+    // cider-nrepl debug-input operation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) input: Option<String>,
+
+    // Caller-chosen extra fields (EvalOptions::tags), added as the request
+    // is written, so they never displace a field set above.
+    #[serde(skip)]
+    pub(crate) tags: BTreeMap<String, String>,
+}
+
+impl Request {
+    /// Every key a request can put on the wire, as sent (`file-path`, not
+    /// `file_path`). Read from the names serde derives for the fields, so
+    /// it cannot fall behind them.
+    pub(crate) fn wire_keys() -> &'static [&'static str] {
+        static KEYS: LazyLock<&'static [&'static str]> = LazyLock::new(|| {
+            let mut names = FieldNames(&[]);
+            // Fails by design once the names are in hand.
+            let _ = Request::deserialize(&mut names);
+            names.0
+        });
+        *KEYS
+    }
+}
+
+/// A deserializer that only records the field names serde hands
+/// `deserialize_struct`, and produces nothing.
+struct FieldNames(&'static [&'static str]);
+
+impl<'de> Deserializer<'de> for &mut FieldNames {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("only field names are read"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0 = fields;
+        Err(de::Error::custom("only field names are read"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// A flag goes on the wire only when set. Bencode has no booleans, so a
+/// flag is the integer `1`; `false` is left out rather than sent as `0`,
+/// which Clojure would read as true.
+#[allow(clippy::ref_option)] // the signature serde's skip_serializing_if calls
+fn flag_unset(flag: &Option<bool>) -> bool {
+    *flag != Some(true)
+}
+
+/// Read a flag as clients send it: an integer (`0` is false), or a string
+/// (`"true"`/`"false"`, as some clients write them).
+fn deserialize_flag<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.map(|v| match v {
+        BencodeValue::Int(i) => i != 0,
+        BencodeValue::String(s) => !matches!(s.as_str(), "" | "false" | "0"),
+        _ => true,
+    }))
+}
+
+/// Source text for an eval or load-file request.
+///
+/// A `String` is moved in rather than copied, and an `Arc<str>` is shared:
+/// an editor holding a large buffer behind an `Arc` can send it as often as
+/// it likes without cloning the text. Derefs to `str`.
+#[derive(Clone)]
+pub struct Code(CodeText);
+
+#[derive(Clone)]
+enum CodeText {
+    Owned(String),
+    Shared(Arc<str>),
+}
+
+impl Code {
+    /// The source text.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            CodeText::Owned(s) => s,
+            CodeText::Shared(s) => s,
+        }
+    }
+}
+
+impl std::ops::Deref for Code {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl std::fmt::Debug for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl std::fmt::Display for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq for Code {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Code {}
+
+impl From<String> for Code {
+    fn from(code: String) -> Self {
+        Self(CodeText::Owned(code))
+    }
+}
+
+impl From<&str> for Code {
+    fn from(code: &str) -> Self {
+        Self(CodeText::Owned(code.to_string()))
+    }
+}
+
+impl From<Arc<str>> for Code {
+    fn from(code: Arc<str>) -> Self {
+        Self(CodeText::Shared(code))
+    }
+}
+
+impl From<&Arc<str>> for Code {
+    fn from(code: &Arc<str>) -> Self {
+        Self(CodeText::Shared(Arc::clone(code)))
+    }
+}
+
+impl Serialize for Code {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Code {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
 }
 
 /// Bencode value types that can appear in nREPL responses
 /// Standard nREPL uses strings, but nrepl-python sends structured data
-#[derive(Debug, Clone, Deserialize)]
-#[serde(untagged)]
-pub(crate) enum BencodeValue {
+///
+/// Serializes and deserializes as the bencode it stands for, so it can hold
+/// any message whose shape is not known up front; see [`crate::codec`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BencodeValue {
+    /// A byte string holding UTF-8 text, which is nearly all of them.
     String(String),
+    /// A byte string that is not UTF-8, kept as sent: binary content, or
+    /// text in another encoding. [`to_string_lossy`](Self::to_string_lossy)
+    /// reads it as text.
+    Bytes(Vec<u8>),
+    /// `i<n>e`.
     Int(i64),
+    /// `l<items>e`.
     List(Vec<BencodeValue>),
+    /// `d<key><value>...e`, keys sorted as bencode requires.
     Dict(BTreeMap<String, BencodeValue>),
 }
 
+impl BencodeValue {
+    /// A byte string: [`String`](Self::String) if `bytes` are UTF-8,
+    /// [`Bytes`](Self::Bytes) otherwise.
+    #[must_use]
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(s) => BencodeValue::String(s),
+            Err(e) => BencodeValue::Bytes(e.into_bytes()),
+        }
+    }
+
+    /// The text of a byte string, if it is UTF-8.
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            BencodeValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The bytes of a byte string, exactly as sent.
+    #[must_use]
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            BencodeValue::String(s) => Some(s.as_bytes()),
+            BencodeValue::Bytes(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// A byte string as text, with any bytes that are not UTF-8 replaced
+    /// by `U+FFFD`.
+    #[must_use]
+    pub fn to_string_lossy(&self) -> Option<Cow<'_, str>> {
+        match self {
+            BencodeValue::String(s) => Some(Cow::Borrowed(s)),
+            BencodeValue::Bytes(b) => Some(String::from_utf8_lossy(b)),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for BencodeValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            BencodeValue::String(s) => serializer.serialize_str(s),
+            BencodeValue::Bytes(b) => serializer.serialize_bytes(b),
+            BencodeValue::Int(i) => serializer.serialize_i64(*i),
+            BencodeValue::List(items) => serializer.collect_seq(items),
+            BencodeValue::Dict(map) => serializer.collect_map(map),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BencodeValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(BencodeValueVisitor)
+    }
+}
+
+/// Builds a [`BencodeValue`] from whatever shape the input has. Bencode
+/// byte strings arrive as bytes, and only become text if they are UTF-8.
+struct BencodeValueVisitor;
+
+impl<'de> Visitor<'de> for BencodeValueVisitor {
+    type Value = BencodeValue;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("a bencode value")
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(BencodeValue::Int(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        i64::try_from(v)
+            .map(BencodeValue::Int)
+            .map_err(|_| E::custom(format!("integer {v} out of range")))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(BencodeValue::String(v.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+        Ok(BencodeValue::String(v))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(BencodeValue::from_bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(BencodeValue::from_bytes(v))
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(BencodeValue::List(items))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut entries = BTreeMap::new();
+        while let Some((key, value)) = map.next_entry::<BencodeValue, BencodeValue>()? {
+            let key = match key {
+                BencodeValue::String(s) => s,
+                other => other.to_string_repr(),
+            };
+            entries.insert(key, value);
+        }
+        Ok(BencodeValue::Dict(entries))
+    }
+}
+
 impl BencodeValue {
     pub(crate) fn to_string_repr(&self) -> String {
         match self {
@@ -100,6 +501,7 @@ impl BencodeValue {
                 // `hello`. Display/quote handling is left to the adapter layer.
                 s.clone()
             }
+            BencodeValue::Bytes(b) => String::from_utf8_lossy(b).into_owned(),
             BencodeValue::Int(i) => i.to_string(),
             BencodeValue::List(list) => {
                 let items: Vec<String> = list.iter().map(BencodeValue::to_string_repr).collect();
@@ -114,6 +516,23 @@ impl BencodeValue {
             }
         }
     }
+
+    /// This value as JSON: strings, numbers, arrays and objects in the
+    /// same shape (feature `json`). Bytes that are not UTF-8 become an
+    /// array of numbers.
+    #[cfg(feature = "json")]
+    #[must_use]
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            BencodeValue::String(s) => serde_json::Value::String(s.clone()),
+            BencodeValue::Bytes(b) => b.iter().copied().collect(),
+            BencodeValue::Int(i) => serde_json::Value::from(*i),
+            BencodeValue::List(items) => items.iter().map(BencodeValue::to_json).collect(),
+            BencodeValue::Dict(map) => serde_json::Value::Object(
+                map.iter().map(|(k, v)| (k.clone(), v.to_json())).collect(),
+            ),
+        }
+    }
 }
 
 /// Convert any bencode value to a string representation
@@ -259,6 +678,234 @@ fn nested_map_from_bencode(value: BencodeValue) -> NestedStringMap {
     }
 }
 
+/// Convert cider-nrepl's `stacktrace` (a list of frame dicts) to [`Frame`]s,
+/// skipping any entry that is not a dict.
+fn deserialize_frames<'de, D>(deserializer: D) -> Result<Option<Vec<Frame>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.map(frames_from_bencode))
+}
+
+fn frames_from_bencode(value: BencodeValue) -> Vec<Frame> {
+    match value {
+        BencodeValue::List(items) => items
+            .iter()
+            .filter_map(|item| match item {
+                BencodeValue::Dict(frame) => Some(Frame::from_cider(frame)),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Convert cider-nrepl's test `results` (`{ns {var [result ...]}}`).
+fn deserialize_test_results<'de, D>(deserializer: D) -> Result<Option<TestsByNamespace>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.map(results_from_bencode))
+}
+
+/// Convert cider-nrepl's test `summary` dict; any other shape is dropped.
+fn deserialize_test_summary<'de, D>(deserializer: D) -> Result<Option<TestSummary>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(match value {
+        Some(BencodeValue::Dict(d)) => Some(TestSummary::from_cider(&d)),
+        _ => None,
+    })
+}
+
+/// Read an integer field, accepting one sent as a numeric string. Anything
+/// else is dropped.
+fn deserialize_number<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.and_then(number_from_bencode))
+}
+
+fn number_from_bencode(value: BencodeValue) -> Option<i64> {
+    match value {
+        BencodeValue::Int(n) => Some(n),
+        BencodeValue::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Read a list of strings, rendering any non-string entry. A lone string is
+/// taken as a one-element list.
+fn deserialize_string_list<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.map(string_list_from_bencode))
+}
+
+fn string_list_from_bencode(value: BencodeValue) -> Vec<String> {
+    match value {
+        BencodeValue::List(items) => items.iter().map(BencodeValue::to_string_repr).collect(),
+        other => vec![other.to_string_repr()],
+    }
+}
+
+/// Read eldoc's arglists: a list of parameter-name lists.
+fn deserialize_arglists<'de, D>(deserializer: D) -> Result<Option<Vec<Vec<String>>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.map(arglists_from_bencode))
+}
+
+fn arglists_from_bencode(value: BencodeValue) -> Vec<Vec<String>> {
+    match value {
+        BencodeValue::List(arities) => arities.into_iter().map(string_list_from_bencode).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Convert cider-nrepl's `apropos-matches` (a list of match dicts), skipping
+/// any entry that is not a dict.
+fn deserialize_apropos<'de, D>(deserializer: D) -> Result<Option<Vec<AproposMatch>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.map(apropos_from_bencode))
+}
+
+fn apropos_from_bencode(value: BencodeValue) -> Vec<AproposMatch> {
+    match value {
+        BencodeValue::List(items) => items
+            .iter()
+            .filter_map(|item| match item {
+                BencodeValue::Dict(m) => AproposMatch::from_cider(m),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Convert cider-nrepl's `clojuredocs` entry, if it is a dict.
+fn deserialize_clojuredocs<'de, D>(deserializer: D) -> Result<Option<ClojureDocs>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.and_then(clojuredocs_from_bencode))
+}
+
+fn clojuredocs_from_bencode(value: BencodeValue) -> Option<ClojureDocs> {
+    match value {
+        BencodeValue::Dict(m) => ClojureDocs::from_cider(&m),
+        _ => None,
+    }
+}
+
+/// Read a spec form (nested lists of printed symbols and keywords).
+fn deserialize_spec_form<'de, D>(deserializer: D) -> Result<Option<SpecForm>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.map(SpecForm::from_bencode))
+}
+
+/// Convert cider-nrepl's `fn-refs`/`fn-deps` (a list of var dicts), skipping
+/// any entry that is not a dict.
+fn deserialize_xref<'de, D>(deserializer: D) -> Result<Option<Vec<XrefVar>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.map(xref_from_bencode))
+}
+
+fn xref_from_bencode(value: BencodeValue) -> Vec<XrefVar> {
+    match value {
+        BencodeValue::List(items) => items
+            .iter()
+            .filter_map(|item| match item {
+                BencodeValue::Dict(m) => XrefVar::from_cider(m),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Convert cider-nrepl's `ns-vars-with-meta` (`{var {key printed-value}}`).
+fn deserialize_ns_vars<'de, D>(deserializer: D) -> Result<Option<Vec<NsVar>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.map(ns_vars_from_bencode))
+}
+
+/// Convert a refresh `error` (an analysed cause chain); any other shape is
+/// dropped.
+fn deserialize_causes<'de, D>(deserializer: D) -> Result<Option<StackTrace>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.and_then(causes_from_bencode))
+}
+
+/// Convert a `content-type`; an unreadable one is dropped.
+fn deserialize_content_type<'de, D>(deserializer: D) -> Result<Option<ContentType>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.and_then(content_type_from_bencode))
+}
+
+/// Read a list of integers, dropping entries that are not one.
+fn deserialize_number_list<'de, D>(deserializer: D) -> Result<Option<Vec<i64>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.map(number_list_from_bencode))
+}
+
+fn number_list_from_bencode(value: BencodeValue) -> Vec<i64> {
+    match value {
+        BencodeValue::List(items) => items.into_iter().filter_map(number_from_bencode).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Convert the debugger's `locals` (a list of `[name value]` pairs).
+fn deserialize_locals<'de, D>(deserializer: D) -> Result<Option<Vec<(String, String)>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.map(locals_from_bencode))
+}
+
+/// Convert the debugger's `input-type`; an unrecognised shape is dropped.
+fn deserialize_input_type<'de, D>(deserializer: D) -> Result<Option<DebugInputType>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<BencodeValue> = Option::deserialize(deserializer)?;
+    Ok(value.and_then(input_type_from_bencode))
+}
+
 /// Represents a single completion candidate returned by the completions operation
 ///
 /// The nREPL completions middleware returns structured data for each completion:
@@ -266,6 +913,7 @@ fn nested_map_from_bencode(value: BencodeValue) -> NestedStringMap {
 /// - `ns`: The namespace where the symbol is defined (e.g., "clojure.core")
 /// - `type`: The type of the symbol (e.g., "function", "macro", "var")
 #[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
 pub struct CompletionCandidate {
     pub candidate: String,
     #[serde(default)]
@@ -274,7 +922,42 @@ pub struct CompletionCandidate {
     pub candidate_type: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// A namespace's aliases and referred vars, as returned by
+/// [`WorkerCommand::NsAliases`](crate::worker::WorkerCommand::NsAliases).
+///
+/// Both maps are keyed by the short name used in the namespace's code and map
+/// to the full namespace name, e.g. `str` -> `clojure.string`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NsAliases {
+    /// `(:require [x :as alias])` aliases.
+    pub aliases: BTreeMap<String, String>,
+    /// Referred symbols and the namespace each comes from. The implicit
+    /// `clojure.core` refers are left out.
+    pub refers: BTreeMap<String, String>,
+}
+
+impl NsAliases {
+    /// Parse the printed value of the worker's alias query: a string of
+    /// space-separated `name ns` pairs, aliases then refers, split by `;`.
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let body = value.strip_prefix('"')?.strip_suffix('"')?;
+        let (aliases, refers) = body.split_once(';')?;
+        let pairs = |section: &str| {
+            let words: Vec<&str> = section.split_whitespace().collect();
+            words
+                .chunks_exact(2)
+                .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+                .collect()
+        };
+        Some(Self {
+            aliases: pairs(aliases),
+            refers: pairs(refers),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
 pub struct Response {
     pub id: String,
     #[serde(default)]
@@ -316,8 +999,238 @@ pub struct Response {
     #[serde(rename = "root-ex")]
     pub root_ex: Option<String>,
 
+    // cider-nrepl content-type middleware - an eval value as MIME content
+    #[serde(
+        default,
+        deserialize_with = "deserialize_content_type",
+        rename = "content-type"
+    )]
+    pub content_type: Option<ContentType>,
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub body: Option<String>,
+
     // middleware operations
     pub middleware: Option<Vec<String>>,
+    #[serde(rename = "unresolved-middleware")]
+    pub unresolved_middleware: Option<Vec<String>>,
+
+    // cider-nrepl analyze-last-stacktrace - one response per exception in the
+    // cause chain
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub class: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub message: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_frames")]
+    pub stacktrace: Option<Vec<Frame>>,
+
+    // cider-nrepl test operations
+    #[serde(default, deserialize_with = "deserialize_test_results")]
+    pub results: Option<TestsByNamespace>,
+    #[serde(default, deserialize_with = "deserialize_test_summary")]
+    pub summary: Option<TestSummary>,
+
+    // cider-nrepl info and eldoc - the symbol's metadata, flattened into the
+    // response
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub name: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_value",
+        rename = "arglists-str"
+    )]
+    pub arglists_str: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub doc: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub file: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_number")]
+    pub line: Option<i64>,
+    #[serde(default, deserialize_with = "deserialize_number")]
+    pub column: Option<i64>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_string_list",
+        rename = "see-also"
+    )]
+    pub see_also: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_value", rename = "macro")]
+    pub is_macro: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_value",
+        rename = "special-form"
+    )]
+    pub special_form: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub protocol: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub member: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub modifiers: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub javadoc: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_arglists")]
+    pub eldoc: Option<Vec<Vec<String>>>,
+    #[serde(default, deserialize_with = "deserialize_value", rename = "type")]
+    pub symbol_type: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub docstring: Option<String>,
+
+    // cider-nrepl apropos operation
+    #[serde(
+        default,
+        deserialize_with = "deserialize_apropos",
+        rename = "apropos-matches"
+    )]
+    pub apropos_matches: Option<Vec<AproposMatch>>,
+
+    // cider-nrepl spec operations; `info` sends `spec` for a function with
+    // an fdef
+    #[serde(
+        default,
+        deserialize_with = "deserialize_string_list",
+        rename = "spec-list"
+    )]
+    pub spec_list: Option<Vec<String>>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_spec_form",
+        rename = "spec-form"
+    )]
+    pub spec_form: Option<SpecForm>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_value",
+        rename = "spec-example"
+    )]
+    pub spec_example: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_spec_form")]
+    pub spec: Option<SpecForm>,
+
+    // cider-nrepl clojuredocs-lookup operation
+    #[serde(default, deserialize_with = "deserialize_clojuredocs")]
+    pub clojuredocs: Option<ClojureDocs>,
+
+    // cider-nrepl xref operations
+    #[serde(default, deserialize_with = "deserialize_xref", rename = "fn-refs")]
+    pub fn_refs: Option<Vec<XrefVar>>,
+    #[serde(default, deserialize_with = "deserialize_xref", rename = "fn-deps")]
+    pub fn_deps: Option<Vec<XrefVar>>,
+
+    // cider-nrepl namespace operations (ns-list, ns-vars-with-meta, ns-path)
+    #[serde(
+        default,
+        deserialize_with = "deserialize_string_list",
+        rename = "ns-list"
+    )]
+    pub ns_list: Option<Vec<String>>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_ns_vars",
+        rename = "ns-vars-with-meta"
+    )]
+    pub ns_vars_with_meta: Option<Vec<NsVar>>,
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub path: Option<String>,
+
+    // cider-nrepl classpath operation
+    #[serde(default, deserialize_with = "deserialize_string_list")]
+    pub classpath: Option<Vec<String>>,
+
+    // cider-nrepl format-code and format-edn operations
+    #[serde(
+        default,
+        deserialize_with = "deserialize_value",
+        rename = "formatted-code"
+    )]
+    pub formatted_code: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_value",
+        rename = "formatted-edn"
+    )]
+    pub formatted_edn: Option<String>,
+
+    // cider-nrepl refresh operations
+    #[serde(default, deserialize_with = "deserialize_string_list")]
+    pub reloading: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_causes", rename = "error")]
+    pub refresh_error: Option<StackTrace>,
+    #[serde(default, deserialize_with = "deserialize_value", rename = "error-ns")]
+    pub error_ns: Option<String>,
+
+    // cider-nrepl toggle-trace-var and toggle-trace-ns operations
+    #[serde(default, deserialize_with = "deserialize_value", rename = "var-name")]
+    pub var_name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_value", rename = "var-status")]
+    pub var_status: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_value", rename = "ns-status")]
+    pub ns_status: Option<String>,
+
+    // cider-nrepl debugger: a stop reported on the init-debugger request
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub key: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_value",
+        rename = "debug-value"
+    )]
+    pub debug_value: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_number_list")]
+    pub coor: Option<Vec<i64>>,
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub code: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_locals")]
+    pub locals: Option<Vec<(String, String)>>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_input_type",
+        rename = "input-type"
+    )]
+    pub input_type: Option<DebugInputType>,
+    #[serde(default, deserialize_with = "deserialize_value")]
+    pub prompt: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_value",
+        rename = "original-id"
+    )]
+    pub original_id: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_value",
+        rename = "original-ns"
+    )]
+    pub original_ns: Option<String>,
+
+    /// Every key the fields above do not model, with its value as sent:
+    /// what custom middleware adds to its responses. A modelled field whose
+    /// bytes are not UTF-8 holds them as lossy text, and is kept here too
+    /// as [`BencodeValue::Bytes`].
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, BencodeValue>,
+}
+
+#[cfg(feature = "json")]
+impl Response {
+    /// The response as a JSON object keyed by the wire names (`new-session`,
+    /// `root-ex`, ...), leaving out the fields the message did not carry
+    /// (feature `json`). Fields this crate decodes into structures, such as
+    /// `stacktrace` or `results`, appear in their decoded shape; the keys in
+    /// [`extra`](Self::extra) appear as sent.
+    #[must_use]
+    pub fn to_json(&self) -> serde_json::Value {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(mut fields)) => {
+                fields.retain(|_, v| !v.is_null());
+                serde_json::Value::Object(fields)
+            }
+            // Every field serializes: strings, integers, and maps with
+            // string keys.
+            Ok(other) => other,
+            Err(_) => serde_json::Value::Null,
+        }
+    }
 }
 
 /// Build a [`Response`] from an already-parsed bencode value, tolerating shapes
@@ -359,6 +1272,40 @@ pub(crate) fn response_from_bencode(value: BencodeValue) -> Option<Response> {
             _ => None,
         };
 
+    // A field read as text loses any bytes that are not UTF-8, so keep the
+    // originals in `extra` as well.
+    let binary: Vec<(String, BencodeValue)> = map
+        .iter()
+        .filter(|(_, v)| matches!(v, BencodeValue::Bytes(_)))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+
+    // A verbose `ls-middleware` sends each layer as a dict: name the layers
+    // in `middleware`, and keep the dicts for `MiddlewareStack`.
+    let verbose_middleware = match map.get("middleware") {
+        Some(BencodeValue::List(items))
+            if items.iter().any(|v| matches!(v, BencodeValue::Dict(_))) =>
+        {
+            map.remove("middleware")
+        }
+        _ => None,
+    };
+    let middleware = match &verbose_middleware {
+        Some(BencodeValue::List(items)) => Some(
+            items
+                .iter()
+                .map(|v| match v {
+                    BencodeValue::Dict(d) => d
+                        .get("name")
+                        .map(BencodeValue::to_string_repr)
+                        .unwrap_or_default(),
+                    other => other.to_string_repr(),
+                })
+                .collect(),
+        ),
+        _ => take_string_list(&mut map, "middleware"),
+    };
+
     let status: Vec<String> = take_string_list(&mut map, "status").unwrap_or_default();
     let ops = map.remove("ops").map(nested_map_from_bencode);
     let versions = map.remove("versions").map(nested_map_from_bencode);
@@ -391,6 +1338,7 @@ pub(crate) fn response_from_bencode(value: BencodeValue) -> Option<Response> {
         sessions: take_string_list(&mut map, "sessions"),
         // Structured completion candidates aren't salvaged here: completion
         // responses are well-formed in practice and never reach this path.
+        // If one does, its candidates stay in `extra`.
         completions: None,
         ops,
         versions,
@@ -398,7 +1346,70 @@ pub(crate) fn response_from_bencode(value: BencodeValue) -> Option<Response> {
         info,
         ex: take_string(&mut map, "ex"),
         root_ex: take_string(&mut map, "root-ex"),
-        middleware: take_string_list(&mut map, "middleware"),
+        content_type: map
+            .remove("content-type")
+            .and_then(content_type_from_bencode),
+        body: take_string(&mut map, "body"),
+        middleware,
+        unresolved_middleware: take_string_list(&mut map, "unresolved-middleware"),
+        class: take_string(&mut map, "class"),
+        message: take_string(&mut map, "message"),
+        stacktrace: map.remove("stacktrace").map(frames_from_bencode),
+        results: map.remove("results").map(results_from_bencode),
+        summary: match map.remove("summary") {
+            Some(BencodeValue::Dict(d)) => Some(TestSummary::from_cider(&d)),
+            _ => None,
+        },
+        name: take_string(&mut map, "name"),
+        arglists_str: take_string(&mut map, "arglists-str"),
+        doc: take_string(&mut map, "doc"),
+        file: take_string(&mut map, "file"),
+        line: map.remove("line").and_then(number_from_bencode),
+        column: map.remove("column").and_then(number_from_bencode),
+        see_also: map.remove("see-also").map(string_list_from_bencode),
+        is_macro: take_string(&mut map, "macro"),
+        special_form: take_string(&mut map, "special-form"),
+        protocol: take_string(&mut map, "protocol"),
+        member: take_string(&mut map, "member"),
+        modifiers: take_string(&mut map, "modifiers"),
+        javadoc: take_string(&mut map, "javadoc"),
+        eldoc: map.remove("eldoc").map(arglists_from_bencode),
+        symbol_type: take_string(&mut map, "type"),
+        docstring: take_string(&mut map, "docstring"),
+        apropos_matches: map.remove("apropos-matches").map(apropos_from_bencode),
+        spec_list: map.remove("spec-list").map(string_list_from_bencode),
+        spec_form: map.remove("spec-form").map(SpecForm::from_bencode),
+        spec_example: take_string(&mut map, "spec-example"),
+        spec: map.remove("spec").map(SpecForm::from_bencode),
+        clojuredocs: map.remove("clojuredocs").and_then(clojuredocs_from_bencode),
+        fn_refs: map.remove("fn-refs").map(xref_from_bencode),
+        fn_deps: map.remove("fn-deps").map(xref_from_bencode),
+        ns_list: map.remove("ns-list").map(string_list_from_bencode),
+        ns_vars_with_meta: map.remove("ns-vars-with-meta").map(ns_vars_from_bencode),
+        path: take_string(&mut map, "path"),
+        classpath: map.remove("classpath").map(string_list_from_bencode),
+        formatted_code: take_string(&mut map, "formatted-code"),
+        formatted_edn: take_string(&mut map, "formatted-edn"),
+        reloading: map.remove("reloading").map(string_list_from_bencode),
+        refresh_error: map.remove("error").and_then(causes_from_bencode),
+        error_ns: take_string(&mut map, "error-ns"),
+        var_name: take_string(&mut map, "var-name"),
+        var_status: take_string(&mut map, "var-status"),
+        ns_status: take_string(&mut map, "ns-status"),
+        key: take_string(&mut map, "key"),
+        debug_value: take_string(&mut map, "debug-value"),
+        coor: map.remove("coor").map(number_list_from_bencode),
+        code: take_string(&mut map, "code"),
+        locals: map.remove("locals").map(locals_from_bencode),
+        input_type: map.remove("input-type").and_then(input_type_from_bencode),
+        prompt: take_string(&mut map, "prompt"),
+        original_id: take_string(&mut map, "original-id"),
+        original_ns: take_string(&mut map, "original-ns"),
+        extra: {
+            map.extend(binary);
+            map.extend(verbose_middleware.map(|v| ("middleware".to_string(), v)));
+            map
+        },
     })
 }
 
@@ -456,6 +1467,90 @@ pub struct EvalResult {
     pub ex: Option<String>,
     /// True if the evaluation was interrupted (status included `interrupted`).
     pub interrupted: bool,
+    /// The value as MIME content (an image, HTML, a URL), when the eval asked
+    /// for content types and the server had a presentation for it.
+    pub rich_content: Option<RichContent>,
+    /// Output past the in-memory limit, written to disk under
+    /// [`OutputSink::File`](crate::OutputSink::File). `output` and `error`
+    /// then hold only what came before it.
+    pub spilled: Option<SpilledOutput>,
+    /// How `output` and `error` were styled, under
+    /// [`AnsiPolicy::Parse`](crate::AnsiPolicy::Parse).
+    pub styles: Option<Box<OutputStyles>>,
+    /// Set when `value` was cut short under
+    /// [`WorkerConfig::truncate_values`](crate::worker::WorkerConfig::truncate_values);
+    /// `value` is then a prefix of the printed value.
+    pub truncated: Option<Box<TruncatedValue>>,
+    /// When the eval was sent and answered. `None` for a result the worker
+    /// did not collect. Boxed to keep `EvalResult` small.
+    pub timing: Option<Box<EvalTiming>>,
+    /// The eval's [`tags`](crate::worker::EvalOptions::tags) that the server
+    /// echoed back on its responses, by key. `None` when none were sent or
+    /// the server drops fields it does not know. Boxed to keep `EvalResult`
+    /// small.
+    pub tags: Option<Box<BTreeMap<String, String>>>,
+}
+
+/// When an eval was sent and answered, by the client's clock.
+///
+/// A long wait for the first response is the server, or a middleware in
+/// front of the evaluation, being slow to start; the rest is the eval
+/// running and its output arriving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvalTiming {
+    /// When the request was handed to the connection.
+    pub sent_at: SystemTime,
+    /// When its first response arrived, if one did.
+    pub first_response_at: Option<SystemTime>,
+    /// When the result was complete: at `done`, or when the eval timed out.
+    pub done_at: SystemTime,
+    /// From sending to done. Measured on a monotonic clock, so it holds
+    /// even if the system clock was changed in between.
+    pub duration: Duration,
+    /// From sending to the first response, measured the same way.
+    pub first_response_after: Option<Duration>,
+}
+
+/// What was elided from a truncated [`EvalResult::value`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TruncatedValue {
+    /// Length in bytes of the value as the server printed it.
+    pub original_bytes: usize,
+    full: Arc<str>,
+}
+
+impl TruncatedValue {
+    /// Cut `value` to at most `max_bytes`, on a character boundary, keeping
+    /// the whole of it here. `None` if it already fits.
+    pub(crate) fn truncate(value: &mut String, max_bytes: usize) -> Option<Box<Self>> {
+        if value.len() <= max_bytes {
+            return None;
+        }
+        let full: Arc<str> = Arc::from(value.as_str());
+        let mut end = max_bytes;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        value.truncate(end);
+        Some(Box::new(Self {
+            original_bytes: full.len(),
+            full,
+        }))
+    }
+}
+
+/// Where an eval's overflow output went, and how much of it there was.
+///
+/// The file is the caller's to read and delete.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpilledOutput {
+    /// The file holding the overflow: stdout and stderr text in the order
+    /// it arrived.
+    pub path: PathBuf,
+    /// Bytes of stdout written to it.
+    pub stdout_bytes: u64,
+    /// Bytes of stderr written to it.
+    pub stderr_bytes: u64,
 }
 
 impl EvalResult {
@@ -468,7 +1563,98 @@ impl EvalResult {
             ns: None,
             ex: None,
             interrupted: false,
+            rich_content: None,
+            spilled: None,
+            styles: None,
+            truncated: None,
+            timing: None,
+            tags: None,
+        }
+    }
+
+    /// Whether [`value`](Self::value) was cut short for display.
+    #[must_use]
+    pub fn is_truncated(&self) -> bool {
+        self.truncated.is_some()
+    }
+
+    /// The whole printed value, even when [`value`](Self::value) holds only
+    /// its truncated prefix. Kept on the client, so no request is made.
+    #[must_use]
+    pub fn fetch_full_value(&self) -> Option<&str> {
+        match &self.truncated {
+            Some(truncated) => Some(&truncated.full),
+            None => self.value.as_deref(),
+        }
+    }
+
+    /// The result as classified chunks in display order: stdout, stderr,
+    /// exception, then value.
+    ///
+    /// Classification comes from the response field a chunk arrived in. The
+    /// only exception is stderr, which carries warnings and stack traces as
+    /// well as plain error text and has no field to tell them apart, so those
+    /// two are recognised by their leading text.
+    #[must_use]
+    pub fn chunks(&self) -> Vec<OutputChunk<'_>> {
+        let mut chunks: Vec<OutputChunk<'_>> = self
+            .output
+            .iter()
+            .map(|text| OutputChunk {
+                kind: ChunkKind::Stdout,
+                text,
+            })
+            .collect();
+        chunks.extend(self.error.iter().map(|text| OutputChunk {
+            kind: classify_stderr(text),
+            text,
+        }));
+        if let Some(ex) = &self.ex {
+            chunks.push(OutputChunk {
+                kind: ChunkKind::Exception,
+                text: ex,
+            });
+        }
+        if let Some(value) = &self.value {
+            chunks.push(OutputChunk {
+                kind: ChunkKind::Value,
+                text: value,
+            });
+        }
+        chunks
+    }
+
+    /// The exception this eval raised, read from its stderr text. `None` if
+    /// the eval did not raise or the server printed nothing recognisable; then
+    /// [`ex`](Self::ex) still names the class when the server sent one.
+    ///
+    /// Printed errors are usually the one-line REPL summary, which carries a
+    /// single frame. For the full trace ask cider-nrepl with
+    /// [`WorkerCommand::AnalyzeStacktrace`](crate::worker::WorkerCommand::AnalyzeStacktrace).
+    #[must_use]
+    pub fn stacktrace(&self) -> Option<StackTrace> {
+        if self.ex.is_none() && self.error.is_empty() {
+            return None;
+        }
+        let mut trace = StackTrace::parse(&self.error.concat())?;
+        if trace.class.is_empty()
+            && let Some(ex) = &self.ex
+        {
+            trace.class.clone_from(ex);
         }
+        Some(trace)
+    }
+
+    /// The value parsed as EDN, or `None` if there is no value.
+    ///
+    /// # Errors
+    ///
+    /// The inner result is an error if the printed value is not readable EDN
+    /// (see [`edn`](crate::edn) for what is accepted).
+    #[cfg(feature = "edn")]
+    #[must_use]
+    pub fn value_edn(&self) -> Option<crate::error::Result<crate::edn::EdnValue>> {
+        self.value.as_deref().map(crate::edn::parse)
     }
 }
 
@@ -478,10 +1664,291 @@ impl Default for EvalResult {
     }
 }
 
+/// Semantic class of a piece of eval output, so a client can style each kind
+/// consistently. The tag strings from [`as_str`](Self::as_str) are stable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChunkKind {
+    /// The printed result value.
+    Value,
+    /// Standard output (`out`).
+    Stdout,
+    /// Standard error (`err`) that is neither a warning nor a trace.
+    Stderr,
+    /// A compiler or runtime warning printed to stderr.
+    Warning,
+    /// The exception reported in `ex`/`root-ex`.
+    Exception,
+    /// Stack trace frames printed to stderr.
+    Trace,
+}
+
+impl ChunkKind {
+    /// The stable tag for this kind.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChunkKind::Value => "value",
+            ChunkKind::Stdout => "stdout",
+            ChunkKind::Stderr => "stderr",
+            ChunkKind::Warning => "warning",
+            ChunkKind::Exception => "exception",
+            ChunkKind::Trace => "trace",
+        }
+    }
+}
+
+/// One classified piece of an [`EvalResult`], borrowed from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputChunk<'a> {
+    pub kind: ChunkKind,
+    pub text: &'a str,
+}
+
+/// Output the server printed outside any of our evals, such as logging from
+/// a background thread, as delivered to an `out-subscribe` subscription; or
+/// an eval's output as it arrives, as delivered to
+/// [`WorkerConfig::on_eval_output`](crate::worker::WorkerConfig::on_eval_output).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerOutput {
+    /// [`ChunkKind::Stdout`], or how a stderr chunk classifies.
+    pub kind: ChunkKind,
+    pub text: String,
+    /// The styling of `text`, under
+    /// [`AnsiPolicy::Parse`](crate::AnsiPolicy::Parse).
+    pub spans: Vec<StyleSpan>,
+}
+
+impl ServerOutput {
+    /// A chunk of `out` (or of `err`, if `stderr`) text, put through `ansi`
+    /// before it is classified.
+    pub(crate) fn new(stderr: bool, text: String, ansi: &mut AnsiFilter) -> Self {
+        let (text, spans) = ansi.apply(stderr, text);
+        Self::classified(stderr, text, spans)
+    }
+
+    /// A chunk already put through the ANSI policy, classified.
+    pub(crate) fn classified(stderr: bool, text: String, spans: Vec<StyleSpan>) -> Self {
+        let kind = if stderr {
+            classify_stderr(&text)
+        } else {
+            ChunkKind::Stdout
+        };
+        Self { kind, text, spans }
+    }
+}
+
+/// Split an `err` chunk into warning, trace or plain stderr.
+fn classify_stderr(text: &str) -> ChunkKind {
+    let trimmed = text.trim_start();
+    // Clojure's reflection/boxed-math warnings and `WARNING:` lines from
+    // `ns` redefinitions all lead with the word.
+    if trimmed.starts_with("WARNING")
+        || trimmed.starts_with("Reflection warning")
+        || trimmed.starts_with("Boxed math warning")
+        || trimmed.starts_with("Performance warning")
+    {
+        return ChunkKind::Warning;
+    }
+    // A JVM trace chunk is a run of `at frame(File.java:1)` lines.
+    let mut lines = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .peekable();
+    if lines.peek().is_some() && lines.all(|l| l.starts_with("at ") || l.starts_with("...")) {
+        return ChunkKind::Trace;
+    }
+    ChunkKind::Stderr
+}
+
+/// Merges a subscription's consecutive `out` or `err` chunks into
+/// line-terminated pieces, each returned as whether it is stderr and its
+/// text.
+///
+/// A server often sends a `println` as several tiny chunks. Text is held
+/// until it ends a line, the other stream starts, or it has been held for
+/// `max_latency`, so a prompt without a newline still shows promptly.
+pub(crate) struct OutputCoalescer {
+    max_latency: Duration,
+    held: Option<HeldOutput>,
+}
+
+struct HeldOutput {
+    stderr: bool,
+    text: String,
+    since: Instant,
+}
+
+impl HeldOutput {
+    fn into_output(self) -> (bool, String) {
+        (self.stderr, self.text)
+    }
+}
+
+impl OutputCoalescer {
+    pub(crate) fn new(max_latency: Duration) -> Self {
+        Self {
+            max_latency,
+            held: None,
+        }
+    }
+
+    /// Take in one chunk received at `now`, returning the output that is
+    /// ready: held text of the other stream, then every complete line held
+    /// so far as one piece.
+    pub(crate) fn push(&mut self, stderr: bool, text: &str, now: Instant) -> Vec<(bool, String)> {
+        let mut ready = Vec::new();
+        if self.held.as_ref().is_some_and(|h| h.stderr != stderr)
+            && let Some(held) = self.held.take()
+        {
+            ready.push(held.into_output());
+        }
+        let held = self.held.get_or_insert_with(|| HeldOutput {
+            stderr,
+            text: String::new(),
+            since: now,
+        });
+        held.text.push_str(text);
+        if let Some(end) = held.text.rfind('\n') {
+            let rest = held.text.split_off(end + 1);
+            let lines = std::mem::replace(&mut held.text, rest);
+            held.since = now;
+            ready.push((stderr, lines));
+        }
+        if self.held.as_ref().is_some_and(|h| h.text.is_empty()) {
+            self.held = None;
+        }
+        ready.extend(self.poll(now));
+        ready
+    }
+
+    /// When the held text is due out, if any is held.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.held.as_ref().map(|h| h.since + self.max_latency)
+    }
+
+    /// The held text, if it has waited `max_latency` by `now`.
+    pub(crate) fn poll(&mut self, now: Instant) -> Option<(bool, String)> {
+        if self.deadline().is_some_and(|due| now >= due) {
+            self.flush()
+        } else {
+            None
+        }
+    }
+
+    /// The held text, however long it has waited.
+    pub(crate) fn flush(&mut self) -> Option<(bool, String)> {
+        self.held.take().map(HeldOutput::into_output)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_shared_code_is_sent_without_copying() {
+        let buffer: Arc<str> = Arc::from("(ns app.core)\n(defn f [] 1)");
+        let shared = crate::ops::load_file_request("req-1", "s1", &buffer, None, None);
+        assert_eq!(Arc::strong_count(&buffer), 2);
+
+        let owned = crate::ops::load_file_request("req-1", "s1", buffer.to_string(), None, None);
+        assert_eq!(
+            crate::codec::encode_request(&shared).unwrap(),
+            crate::codec::encode_request(&owned).unwrap()
+        );
+        assert_eq!(shared.file.as_deref(), Some(&*buffer));
+    }
+
+    #[test]
+    fn test_wire_keys_are_the_serialized_names() {
+        let keys = Request::wire_keys();
+        for key in ["op", "id", "session", "code", "file-path"] {
+            assert!(keys.contains(&key), "{key} missing");
+        }
+        assert!(!keys.contains(&"tags"));
+    }
+
+    #[test]
+    fn test_coalescer_sends_whole_lines_and_waits_out_partial_ones() {
+        let start = Instant::now();
+        let later = |ms| start + Duration::from_millis(ms);
+        let piece = |stderr, text: &str| (stderr, text.to_string());
+        let mut coalescer = OutputCoalescer::new(Duration::from_millis(50));
+
+        assert!(coalescer.push(false, "hel", start).is_empty());
+        assert_eq!(
+            coalescer.push(false, "lo\nwor", later(1)),
+            [piece(false, "hello\n")]
+        );
+        assert_eq!(coalescer.deadline(), Some(later(51)));
+        assert!(coalescer.poll(later(10)).is_none());
+
+        // The other stream starts: the held line goes out unfinished.
+        assert_eq!(
+            coalescer.push(true, "WARNING: x\n", later(20)),
+            [piece(false, "wor"), piece(true, "WARNING: x\n")]
+        );
+        assert!(coalescer.deadline().is_none());
+
+        assert!(coalescer.push(false, "prompt> ", later(30)).is_empty());
+        assert_eq!(coalescer.poll(later(80)), Some(piece(false, "prompt> ")));
+        assert!(coalescer.flush().is_none());
+    }
+
+    #[test]
+    fn test_ns_aliases_parse() {
+        let parsed =
+            NsAliases::parse(r#""str clojure.string s clojure.set;pprint clojure.pprint""#)
+                .expect("should parse");
+        assert_eq!(parsed.aliases.len(), 2);
+        assert_eq!(parsed.aliases["str"], "clojure.string");
+        assert_eq!(parsed.aliases["s"], "clojure.set");
+        assert_eq!(parsed.refers["pprint"], "clojure.pprint");
+
+        assert_eq!(NsAliases::parse(r#"";""#), Some(NsAliases::default()));
+        assert_eq!(NsAliases::parse("nil"), None);
+    }
+
+    #[test]
+    fn test_eval_result_chunks_classification() {
+        let result = EvalResult {
+            value: Some("nil".to_string()),
+            output: vec!["hi\n".to_string()],
+            error: vec![
+                "Reflection warning, user.clj:1:1 - call to method x can't be resolved.\n"
+                    .to_string(),
+                "\tat clojure.lang.Numbers.divide(Numbers.java:190)\n\tat user$eval1.invoke(NO_SOURCE_FILE:1)\n"
+                    .to_string(),
+                "Execution error (ArithmeticException)\n".to_string(),
+            ],
+            ns: Some("user".to_string()),
+            ex: Some("class java.lang.ArithmeticException".to_string()),
+            interrupted: false,
+            rich_content: None,
+            spilled: None,
+            styles: None,
+            truncated: None,
+            timing: None,
+            tags: None,
+        };
+
+        let kinds: Vec<ChunkKind> = result.chunks().iter().map(|c| c.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ChunkKind::Stdout,
+                ChunkKind::Warning,
+                ChunkKind::Trace,
+                ChunkKind::Stderr,
+                ChunkKind::Exception,
+                ChunkKind::Value,
+            ]
+        );
+        assert_eq!(result.chunks()[0].text, "hi\n");
+        assert!(EvalResult::new().chunks().is_empty());
+    }
+
     #[test]
     fn eval_result_is_send_sync() {
         fn assert_send<T: Send>() {}
@@ -573,4 +2040,50 @@ mod tests {
             "hello"
         );
     }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_response_to_json_uses_wire_names() {
+        let frame = b"d2:id1:711:new-session2:s27:root-ex3:Boo6:statusl4:donee3:tapi1ee";
+        let (response, _) = crate::codec::decode_response(frame).unwrap();
+        assert_eq!(
+            response.to_json(),
+            serde_json::json!({
+                "id": "7",
+                "session": "",
+                "new-session": "s2",
+                "root-ex": "Boo",
+                "status": ["done"],
+                "tap": 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_unmodelled_keys_are_kept_in_extra() {
+        let frame = b"d2:id1:76:statusl4:donee5:tracel1:ai2ee5:value1:3e";
+        let (response, _) = crate::codec::decode_response(frame).unwrap();
+        assert_eq!(response.value.as_deref(), Some("3"));
+        assert_eq!(
+            response.extra,
+            BTreeMap::from([(
+                "trace".to_string(),
+                BencodeValue::List(vec![
+                    BencodeValue::String("a".to_string()),
+                    BencodeValue::Int(2)
+                ])
+            )])
+        );
+
+        // The salvage path keeps them too. A `source` key with no value
+        // makes the frame invalid for strict decoding.
+        let frame = b"d2:id1:73:err4:boom4:spani7e6:sourcee";
+        match crate::codec::decode_one(frame) {
+            crate::codec::Decoded::Message { response, .. } => {
+                assert_eq!(response.err.as_deref(), Some("boom"));
+                assert_eq!(response.extra.get("span"), Some(&BencodeValue::Int(7)));
+            }
+            _ => panic!("expected the frame to be salvaged"),
+        }
+    }
 }
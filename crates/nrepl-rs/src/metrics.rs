@@ -0,0 +1,257 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Opt-in client-side traffic metrics
+//!
+//! A [`ClientMetrics`] collector is shared between a connection's writer and
+//! reader. The writer records each request (op, wire id, encoded size); the
+//! reader records each response frame and, when a response finishes its op,
+//! the latency since the request went out. Read it back as a
+//! [`MetricsSnapshot`].
+//!
+//! Latencies go into fixed buckets rather than being kept individually, so a
+//! long-lived connection costs the same memory as a fresh one.
+
+use crate::message::classify;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the latency buckets, in milliseconds. A final overflow
+/// bucket catches anything slower.
+const BUCKET_BOUNDS_MS: [u64; 12] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10_000];
+
+/// Most requests tracked for latency at once. Requests that never finish
+/// (their connection was resynchronized, say) would otherwise pile up.
+const MAX_IN_FLIGHT: usize = 10_000;
+
+/// Shared handle to a connection's metrics. Clones record into the same
+/// counters.
+#[derive(Clone, Default)]
+pub struct ClientMetrics {
+    inner: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    snapshot: MetricsSnapshot,
+    /// Wire id -> (op, sent at) for requests awaiting their final response.
+    in_flight: HashMap<String, (String, Instant)>,
+}
+
+/// Counters accumulated since the collector was created or last reset.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Encoded bytes written to the socket.
+    pub bytes_sent: u64,
+    /// Encoded bytes of decoded responses.
+    pub bytes_received: u64,
+    /// Requests written.
+    pub requests_sent: u64,
+    /// Response messages decoded (an eval usually produces several).
+    pub responses_received: u64,
+    /// Per-op breakdown, keyed by op name.
+    pub ops: BTreeMap<String, OpMetrics>,
+}
+
+/// Metrics for one op name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpMetrics {
+    /// Requests sent with this op.
+    pub sent: u64,
+    /// Response messages received for them.
+    pub responses: u64,
+    /// Time from sending each request to its final (`done`) response.
+    pub latency: LatencyHistogram,
+}
+
+/// Bucketed latency distribution.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: [u64; BUCKET_BOUNDS_MS.len() + 1],
+    total: Duration,
+    max: Duration,
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, latency: Duration) {
+        let millis = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+        let bucket = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|&bound| millis <= bound)
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        self.counts[bucket] += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    /// Number of completed requests recorded.
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Mean latency, or `None` if nothing has completed.
+    #[must_use]
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.count()).ok().filter(|&c| c > 0)?;
+        Some(self.total / count)
+    }
+
+    /// Slowest latency recorded.
+    #[must_use]
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Upper bound of the bucket holding the `p`th percentile (`0.0..=1.0`).
+    /// The overflow bucket reports [`max`](Self::max). `None` if empty.
+    #[must_use]
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        // Rank of the sample we want, 1-based; `p` is clamped so the cast is
+        // in range.
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let rank = ((p.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, &n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Some(
+                    BUCKET_BOUNDS_MS
+                        .get(bucket)
+                        .map_or(self.max, |&ms| Duration::from_millis(ms)),
+                );
+            }
+        }
+        Some(self.max)
+    }
+
+    /// `(upper bound, count)` for each bucket; the overflow bucket's bound is
+    /// `None`.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        self.counts.iter().enumerate().map(|(bucket, &n)| {
+            (
+                BUCKET_BOUNDS_MS
+                    .get(bucket)
+                    .map(|&ms| Duration::from_millis(ms)),
+                n,
+            )
+        })
+    }
+}
+
+impl ClientMetrics {
+    /// A fresh collector with all counters at zero.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copy of the counters as they stand.
+    #[must_use]
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.lock().snapshot.clone()
+    }
+
+    /// Zero every counter. Requests already in flight still have their
+    /// latency recorded when they finish.
+    pub fn reset(&self) {
+        self.lock().snapshot = MetricsSnapshot::default();
+    }
+
+    /// Record a request as it is written.
+    pub(crate) fn record_request(&self, op: &str, id: &str, bytes: usize) {
+        let mut state = self.lock();
+        let snapshot = &mut state.snapshot;
+        snapshot.bytes_sent += bytes as u64;
+        snapshot.requests_sent += 1;
+        snapshot.ops.entry(op.to_string()).or_default().sent += 1;
+        if state.in_flight.len() < MAX_IN_FLIGHT {
+            state
+                .in_flight
+                .insert(id.to_string(), (op.to_string(), Instant::now()));
+        }
+    }
+
+    /// Record a response frame of `bytes` bytes, answering `id`.
+    pub(crate) fn record_response(&self, id: &str, status: &[String], bytes: usize) {
+        let flags = classify(status);
+        let finished = flags.done || flags.error || flags.unknown_op;
+        let mut state = self.lock();
+        state.snapshot.bytes_received += bytes as u64;
+        state.snapshot.responses_received += 1;
+
+        let Some((op, sent_at)) = state.in_flight.get(id).cloned() else {
+            // Not ours to attribute (a straggler, or a request sent before
+            // metrics were on).
+            return;
+        };
+        let op_metrics = state.snapshot.ops.entry(op).or_default();
+        op_metrics.responses += 1;
+        if finished {
+            op_metrics.latency.record(sent_at.elapsed());
+            state.in_flight.remove(id);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // Counters stay meaningful after a panic elsewhere; keep going.
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl std::fmt::Debug for ClientMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientMetrics")
+            .field("snapshot", &self.snapshot())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_histogram_percentiles() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentile(0.5), None);
+        for ms in [2, 3, 4, 40, 20_000] {
+            histogram.record(Duration::from_millis(ms));
+        }
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.percentile(0.5), Some(Duration::from_millis(5)));
+        assert_eq!(histogram.percentile(0.8), Some(Duration::from_millis(50)));
+        // The overflow bucket has no bound, so it reports the slowest sample.
+        assert_eq!(histogram.percentile(1.0), Some(Duration::from_secs(20)));
+        assert_eq!(histogram.max(), Duration::from_secs(20));
+    }
+
+    #[test]
+    fn test_reset_zeroes_counters() {
+        let metrics = ClientMetrics::new();
+        metrics.record_request("eval", "req-1", 40);
+        assert_eq!(metrics.snapshot().requests_sent, 1);
+        assert_eq!(metrics.snapshot().ops["eval"].sent, 1);
+
+        metrics.reset();
+        assert_eq!(metrics.snapshot(), MetricsSnapshot::default());
+    }
+}
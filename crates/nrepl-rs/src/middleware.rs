@@ -0,0 +1,394 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! The server's middleware stack, as `ls-middleware` reports it
+//!
+//! nREPL lists the stack from the inside out: the handler that answers an
+//! op last comes first, the one that sees each message first comes last.
+//! [`MiddlewareStack`] keeps that order, so a caller planning a
+//! `swap-middleware` can tell where a layer sits relative to the ones it
+//! depends on. Names are the vars' qualified symbols, without the `#'` the
+//! server prints them with.
+//!
+//! Asked with `verbose?`, a server that supports it sends each layer as a
+//! dict carrying the var's descriptor (what it `requires`, what it
+//! `expects` to run before, and the ops it `handles`); plain nREPL sends
+//! names only, and [`MiddlewareLayer::descriptor`] is `None`.
+//!
+//! `swap-middleware` replaces the whole stack, and a stack missing a layer
+//! the client relies on can leave it unable to even clone a session.
+//! [`MiddlewareStack::plan_swap`] says which of the server's ops a proposed
+//! stack would drop before it is sent.
+
+use crate::message::{BencodeValue, Response};
+
+/// The ops nREPL's own middleware answers, for layers listed without a
+/// descriptor.
+const KNOWN_HANDLERS: &[(&str, &[&str])] = &[
+    (
+        "nrepl.middleware.session/session",
+        &["clone", "close", "interrupt", "ls-sessions"],
+    ),
+    ("nrepl.middleware.session/add-stdin", &["stdin"]),
+    (
+        "nrepl.middleware.interruptible-eval/interruptible-eval",
+        &["eval"],
+    ),
+    ("nrepl.middleware/wrap-describe", &["describe"]),
+    ("nrepl.middleware.load-file/wrap-load-file", &["load-file"]),
+    (
+        "nrepl.middleware.completion/wrap-completion",
+        &["completions"],
+    ),
+    ("nrepl.middleware.lookup/wrap-lookup", &["lookup"]),
+    (
+        "nrepl.middleware.sideloader/wrap-sideloader",
+        &["sideloader-start", "sideloader-provide"],
+    ),
+    (
+        "nrepl.middleware.dynamic-loader/wrap-dynamic-loader",
+        &["add-middleware", "swap-middleware", "ls-middleware"],
+    ),
+];
+
+/// A middleware var's descriptor, from its `:nrepl.middleware/descriptor`
+/// metadata. The lists are var names, except [`handles`](Self::handles),
+/// which is op names.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MiddlewareDescriptor {
+    /// Middleware that must sit outside this one.
+    pub requires: Vec<String>,
+    /// Middleware that must sit inside this one.
+    pub expects: Vec<String>,
+    /// The ops this middleware answers.
+    pub handles: Vec<String>,
+}
+
+/// One layer of a [`MiddlewareStack`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MiddlewareLayer {
+    /// The var's qualified name, such as `nrepl.middleware.session/session`.
+    pub name: String,
+    /// Set when the stack was listed verbosely by a server that sends it.
+    pub descriptor: Option<MiddlewareDescriptor>,
+}
+
+/// An op a proposed stack would drop, and the layer that answers it now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LostOp {
+    pub op: String,
+    pub middleware: String,
+}
+
+/// What a `swap-middleware` to a proposed stack would change, worked out
+/// before it is sent. See [`MiddlewareStack::plan_swap`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SwapPlan {
+    /// Layers in the proposed stack that are not in the current one.
+    pub added: Vec<String>,
+    /// Layers in the current stack that the proposed one leaves out.
+    pub removed: Vec<String>,
+    /// Ops the server offers now that nothing in the proposed stack would
+    /// answer.
+    pub lost_ops: Vec<LostOp>,
+    /// Removed layers whose ops are unknown: listed without a descriptor,
+    /// and not nREPL's own. They may take ops with them too.
+    pub unchecked: Vec<String>,
+}
+
+impl SwapPlan {
+    /// Whether the swap keeps every op the server offers now, as far as
+    /// can be told.
+    #[must_use]
+    pub fn is_safe(&self) -> bool {
+        self.lost_ops.is_empty() && self.unchecked.is_empty()
+    }
+
+    /// One line per problem, for showing before asking to go ahead.
+    #[must_use]
+    pub fn warnings(&self) -> Vec<String> {
+        self.lost_ops
+            .iter()
+            .map(|l| format!("{} would disappear (answered by {})", l.op, l.middleware))
+            .chain(
+                self.unchecked
+                    .iter()
+                    .map(|m| format!("removing {m}, whose ops are unknown")),
+            )
+            .collect()
+    }
+}
+
+/// The middleware a server's handler is built from, innermost first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MiddlewareStack {
+    layers: Vec<MiddlewareLayer>,
+}
+
+impl MiddlewareStack {
+    /// Read the stack from an `ls-middleware` exchange. Verbose entries are
+    /// taken from [`Response::extra`], where decoding keeps a `middleware`
+    /// list that is not plain names.
+    #[must_use]
+    pub fn from_responses(responses: &[Response]) -> Self {
+        let layers = responses
+            .iter()
+            .find_map(|r| {
+                if let Some(BencodeValue::List(items)) = r.extra.get("middleware") {
+                    Some(items.iter().filter_map(layer_from_bencode).collect())
+                } else {
+                    r.middleware.as_ref().map(|names| {
+                        names
+                            .iter()
+                            .map(|name| MiddlewareLayer {
+                                name: var_name(name),
+                                descriptor: None,
+                            })
+                            .collect()
+                    })
+                }
+            })
+            .unwrap_or_default();
+        Self { layers }
+    }
+
+    /// The layers, innermost first.
+    #[must_use]
+    pub fn layers(&self) -> &[MiddlewareLayer] {
+        &self.layers
+    }
+
+    /// The layers' names, innermost first: the list `swap-middleware`
+    /// takes.
+    #[must_use]
+    pub fn names(&self) -> Vec<String> {
+        self.layers.iter().map(|l| l.name.clone()).collect()
+    }
+
+    /// Where `name` sits, counting from the innermost layer.
+    #[must_use]
+    pub fn position(&self, name: &str) -> Option<usize> {
+        let name = name.strip_prefix("#'").unwrap_or(name);
+        self.layers.iter().position(|l| l.name == name)
+    }
+
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    /// The layer named `name`.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&MiddlewareLayer> {
+        self.position(name).map(|i| &self.layers[i])
+    }
+
+    /// The layer that answers `op`, if the descriptors say.
+    #[must_use]
+    pub fn handler_of(&self, op: &str) -> Option<&MiddlewareLayer> {
+        self.layers.iter().find(|l| {
+            l.descriptor
+                .as_ref()
+                .is_some_and(|d| d.handles.iter().any(|h| h == op))
+        })
+    }
+
+    /// Compare `proposed` (var names, innermost first, as `swap-middleware`
+    /// takes them) with this stack, given the `ops` the server offers now
+    /// (the keys of a `describe`'s [`Response::ops`]).
+    ///
+    /// An op is lost if a layer that answers it now is removed and no
+    /// layer left answers it. Which layer answers what comes from the
+    /// descriptors of a verbose listing, or for nREPL's own middleware from
+    /// what it is known to handle.
+    #[must_use]
+    pub fn plan_swap(&self, proposed: &[String], ops: &[String]) -> SwapPlan {
+        let proposed: Vec<String> = proposed.iter().map(|n| var_name(n)).collect();
+        let mut plan = SwapPlan {
+            added: proposed
+                .iter()
+                .filter(|n| !self.contains(n))
+                .cloned()
+                .collect(),
+            ..SwapPlan::default()
+        };
+        let kept = |op: &str| {
+            proposed.iter().any(|n| {
+                self.get(n)
+                    .and_then(MiddlewareLayer::handles)
+                    .or_else(|| known_handles(n))
+                    .is_some_and(|handles| handles.iter().any(|h| h == op))
+            })
+        };
+        for layer in &self.layers {
+            if proposed.contains(&layer.name) {
+                continue;
+            }
+            plan.removed.push(layer.name.clone());
+            let Some(handles) = layer.handles() else {
+                plan.unchecked.push(layer.name.clone());
+                continue;
+            };
+            plan.lost_ops.extend(
+                handles
+                    .into_iter()
+                    .filter(|op| ops.contains(op) && !kept(op))
+                    .map(|op| LostOp {
+                        op,
+                        middleware: layer.name.clone(),
+                    }),
+            );
+        }
+        plan
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+}
+
+impl MiddlewareLayer {
+    /// The ops this layer answers, from its descriptor or, for nREPL's own
+    /// middleware, from what it is known to handle.
+    fn handles(&self) -> Option<Vec<String>> {
+        self.descriptor
+            .as_ref()
+            .map(|d| d.handles.clone())
+            .or_else(|| known_handles(&self.name))
+    }
+}
+
+fn known_handles(name: &str) -> Option<Vec<String>> {
+    KNOWN_HANDLERS
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, ops)| ops.iter().map(|op| (*op).to_string()).collect())
+}
+
+/// A var as printed (`#'ns/name`) or as a symbol, to the symbol.
+fn var_name(printed: &str) -> String {
+    printed.strip_prefix("#'").unwrap_or(printed).to_string()
+}
+
+/// A verbose entry, `{"name" ..., "requires" [...], ...}`, or a plain name.
+fn layer_from_bencode(value: &BencodeValue) -> Option<MiddlewareLayer> {
+    match value {
+        BencodeValue::Dict(d) => {
+            let names = |key: &str| match d.get(key) {
+                Some(BencodeValue::List(items)) => items
+                    .iter()
+                    .map(|v| var_name(&v.to_string_repr()))
+                    .collect(),
+                // Descriptors map each handled op to its documentation.
+                Some(BencodeValue::Dict(ops)) => ops.keys().cloned().collect(),
+                _ => Vec::new(),
+            };
+            Some(MiddlewareLayer {
+                name: var_name(&d.get("name")?.to_string_repr()),
+                descriptor: Some(MiddlewareDescriptor {
+                    requires: names("requires"),
+                    expects: names("expects"),
+                    handles: names("handles"),
+                }),
+            })
+        }
+        other => Some(MiddlewareLayer {
+            name: var_name(other.as_str()?),
+            descriptor: None,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::decode_value;
+
+    /// Decode `frame` the way the worker does, salvage path included.
+    fn response(frame: &[u8]) -> Response {
+        Response::try_from(decode_value(frame).unwrap().0).unwrap()
+    }
+
+    #[test]
+    fn test_stack_keeps_order_and_descriptors() {
+        let plain = response(
+            b"d2:id1:110:middlewarel34:#'nrepl.middleware.session/session27:#'cider.nrepl/wrap-complete\
+              e6:statusl4:doneee",
+        );
+        let stack = MiddlewareStack::from_responses(&[plain]);
+        assert_eq!(
+            stack.names(),
+            [
+                "nrepl.middleware.session/session",
+                "cider.nrepl/wrap-complete"
+            ]
+        );
+        assert_eq!(stack.position("#'cider.nrepl/wrap-complete"), Some(1));
+        assert!(stack.layers().iter().all(|l| l.descriptor.is_none()));
+
+        let verbose = response(
+            b"d2:id1:210:middlewareld7:handlesd8:completed3:doc4:Listee\
+              4:name25:cider.nrepl/wrap-complete8:requiresl34:#'nrepl.middleware.session/sessionee\
+              e6:statusl4:doneee",
+        );
+        let stack = MiddlewareStack::from_responses(&[verbose]);
+        let layer = stack.handler_of("complete").unwrap();
+        assert_eq!(layer.name, "cider.nrepl/wrap-complete");
+        assert_eq!(
+            layer.descriptor.as_ref().unwrap().requires,
+            ["nrepl.middleware.session/session"]
+        );
+    }
+
+    #[test]
+    fn test_plan_swap_reports_lost_ops() {
+        let current = response(
+            b"d2:id1:110:middlewarel\
+              56:#'nrepl.middleware.interruptible-eval/interruptible-eval\
+              34:#'nrepl.middleware.session/session\
+              24:#'my.middleware/wrap-fooe\
+              6:statusl4:doneee",
+        );
+        let stack = MiddlewareStack::from_responses(&[current]);
+        let ops: Vec<String> = ["eval", "clone", "close", "interrupt", "foo"]
+            .map(str::to_string)
+            .to_vec();
+
+        let keep_all = stack.plan_swap(&stack.names(), &ops);
+        assert!(keep_all.is_safe(), "{keep_all:?}");
+
+        let proposed = [
+            "nrepl.middleware.interruptible-eval/interruptible-eval".to_string(),
+            "my.middleware/wrap-bar".to_string(),
+        ];
+        let plan = stack.plan_swap(&proposed, &ops);
+        assert!(!plan.is_safe());
+        assert_eq!(plan.added, ["my.middleware/wrap-bar"]);
+        assert_eq!(
+            plan.removed,
+            ["nrepl.middleware.session/session", "my.middleware/wrap-foo"]
+        );
+        let lost: Vec<&str> = plan.lost_ops.iter().map(|l| l.op.as_str()).collect();
+        assert_eq!(lost, ["clone", "close", "interrupt"]);
+        assert_eq!(plan.unchecked, ["my.middleware/wrap-foo"]);
+        assert_eq!(
+            plan.warnings()[0],
+            "clone would disappear (answered by nrepl.middleware.session/session)"
+        );
+    }
+}
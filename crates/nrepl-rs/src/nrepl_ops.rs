@@ -0,0 +1,282 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! The client operations as a trait, so tests can stand in for a server
+//!
+//! Code written against [`NReplOps`] runs on a real connection through
+//! [`blocking::NReplClient`](crate::blocking::NReplClient) (feature
+//! `blocking`), and in tests on a [`MockOps`], which answers from replies
+//! queued up front and records every call it gets.
+//!
+//! The trait is synchronous, like the rest of the crate's public API: the
+//! worker thread owns the runtime, so callers never need one.
+
+use crate::error::NReplError;
+use crate::message::{Code, CompletionCandidate, EvalResult, Response};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// The operations a program drives an nREPL session with.
+pub trait NReplOps {
+    /// Evaluate `code` with the client's default timeout.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the eval did not finish: a timeout or a lost
+    /// connection. An eval that throws is an `Ok` carrying
+    /// [`EvalResult::ex`].
+    fn eval(&mut self, code: Code) -> Result<EvalResult, NReplError>;
+
+    /// Evaluate `code`, waiting up to `timeout` for it to finish.
+    ///
+    /// # Errors
+    ///
+    /// As for [`eval`](Self::eval).
+    fn eval_with_timeout(
+        &mut self,
+        code: Code,
+        timeout: Duration,
+    ) -> Result<EvalResult, NReplError>;
+
+    /// Load `contents` as the file at `path` (`load-file`).
+    ///
+    /// # Errors
+    ///
+    /// As for [`eval`](Self::eval).
+    fn load_file(&mut self, contents: Code, path: Option<&str>) -> Result<EvalResult, NReplError>;
+
+    /// Completions for `prefix` in `ns` (the session's namespace if `None`).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server failed or did not answer the request.
+    fn completions(
+        &mut self,
+        prefix: &str,
+        ns: Option<&str>,
+    ) -> Result<Vec<CompletionCandidate>, NReplError>;
+
+    /// Information about `sym` as seen from `ns` (`lookup`).
+    ///
+    /// # Errors
+    ///
+    /// As for [`completions`](Self::completions).
+    fn lookup(&mut self, sym: &str, ns: Option<&str>) -> Result<Response, NReplError>;
+
+    /// The server's capabilities (`describe`).
+    ///
+    /// # Errors
+    ///
+    /// As for [`completions`](Self::completions).
+    fn describe(&mut self, verbose: bool) -> Result<Response, NReplError>;
+}
+
+/// A call made on a [`MockOps`], with its arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpCall {
+    /// [`NReplOps::eval`] (`timeout` is `None`) or
+    /// [`NReplOps::eval_with_timeout`].
+    Eval {
+        code: String,
+        timeout: Option<Duration>,
+    },
+    LoadFile {
+        contents: String,
+        path: Option<String>,
+    },
+    Completions {
+        prefix: String,
+        ns: Option<String>,
+    },
+    Lookup {
+        sym: String,
+        ns: Option<String>,
+    },
+    Describe {
+        verbose: bool,
+    },
+}
+
+/// An [`NReplOps`] that answers from queued replies and records each call.
+///
+/// Evals and load-files take the next reply queued with
+/// [`reply_eval`](Self::reply_eval), in order; the other ops have a queue
+/// each. A call whose queue is empty fails with
+/// [`NReplError::OperationFailed`] naming the op, so a test that did not
+/// expect it finds out.
+#[derive(Debug, Default)]
+pub struct MockOps {
+    calls: Vec<OpCall>,
+    evals: VecDeque<Result<EvalResult, NReplError>>,
+    completions: VecDeque<Result<Vec<CompletionCandidate>, NReplError>>,
+    lookups: VecDeque<Result<Response, NReplError>>,
+    describes: VecDeque<Result<Response, NReplError>>,
+}
+
+impl MockOps {
+    /// A mock with nothing queued.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue the outcome of the next eval or load-file.
+    pub fn reply_eval(&mut self, result: Result<EvalResult, NReplError>) -> &mut Self {
+        self.evals.push_back(result);
+        self
+    }
+
+    /// Queue an eval that prints `value`, the usual case.
+    pub fn reply_value(&mut self, value: impl Into<String>) -> &mut Self {
+        let mut result = EvalResult::new();
+        result.value = Some(value.into());
+        self.reply_eval(Ok(result))
+    }
+
+    /// Queue the answer to the next `completions`.
+    pub fn reply_completions(
+        &mut self,
+        result: Result<Vec<CompletionCandidate>, NReplError>,
+    ) -> &mut Self {
+        self.completions.push_back(result);
+        self
+    }
+
+    /// Queue the answer to the next `lookup`.
+    pub fn reply_lookup(&mut self, result: Result<Response, NReplError>) -> &mut Self {
+        self.lookups.push_back(result);
+        self
+    }
+
+    /// Queue the answer to the next `describe`.
+    pub fn reply_describe(&mut self, result: Result<Response, NReplError>) -> &mut Self {
+        self.describes.push_back(result);
+        self
+    }
+
+    /// Every call made so far, oldest first.
+    #[must_use]
+    pub fn calls(&self) -> &[OpCall] {
+        &self.calls
+    }
+
+    /// Whether every queued reply has been used.
+    #[must_use]
+    pub fn is_drained(&self) -> bool {
+        self.evals.is_empty()
+            && self.completions.is_empty()
+            && self.lookups.is_empty()
+            && self.describes.is_empty()
+    }
+}
+
+/// The next reply in `queue`, or an error saying none was queued for `op`.
+fn next<T>(queue: &mut VecDeque<Result<T, NReplError>>, op: &str) -> Result<T, NReplError> {
+    queue
+        .pop_front()
+        .unwrap_or_else(|| Err(NReplError::OperationFailed(format!("no {op} reply queued"))))
+}
+
+impl NReplOps for MockOps {
+    fn eval(&mut self, code: Code) -> Result<EvalResult, NReplError> {
+        self.calls.push(OpCall::Eval {
+            code: code.to_string(),
+            timeout: None,
+        });
+        next(&mut self.evals, "eval")
+    }
+
+    fn eval_with_timeout(
+        &mut self,
+        code: Code,
+        timeout: Duration,
+    ) -> Result<EvalResult, NReplError> {
+        self.calls.push(OpCall::Eval {
+            code: code.to_string(),
+            timeout: Some(timeout),
+        });
+        next(&mut self.evals, "eval")
+    }
+
+    fn load_file(&mut self, contents: Code, path: Option<&str>) -> Result<EvalResult, NReplError> {
+        self.calls.push(OpCall::LoadFile {
+            contents: contents.to_string(),
+            path: path.map(str::to_string),
+        });
+        next(&mut self.evals, "load-file")
+    }
+
+    fn completions(
+        &mut self,
+        prefix: &str,
+        ns: Option<&str>,
+    ) -> Result<Vec<CompletionCandidate>, NReplError> {
+        self.calls.push(OpCall::Completions {
+            prefix: prefix.to_string(),
+            ns: ns.map(str::to_string),
+        });
+        next(&mut self.completions, "completions")
+    }
+
+    fn lookup(&mut self, sym: &str, ns: Option<&str>) -> Result<Response, NReplError> {
+        self.calls.push(OpCall::Lookup {
+            sym: sym.to_string(),
+            ns: ns.map(str::to_string),
+        });
+        next(&mut self.lookups, "lookup")
+    }
+
+    fn describe(&mut self, verbose: bool) -> Result<Response, NReplError> {
+        self.calls.push(OpCall::Describe { verbose });
+        next(&mut self.describes, "describe")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Code that only knows the trait, as a downstream application would.
+    fn add(ops: &mut dyn NReplOps) -> Result<Option<String>, NReplError> {
+        Ok(ops.eval(Code::from("(+ 1 2)"))?.value)
+    }
+
+    #[test]
+    fn test_mock_answers_in_order_and_records_calls() {
+        let mut mock = MockOps::new();
+        mock.reply_value("3")
+            .reply_eval(Err(NReplError::timeout("eval", Duration::from_secs(1))));
+        assert_eq!(add(&mut mock).unwrap().as_deref(), Some("3"));
+        assert!(matches!(add(&mut mock), Err(NReplError::Timeout { .. })));
+        assert!(mock.is_drained());
+
+        let err = mock.lookup("map", Some("user")).unwrap_err();
+        assert!(err.to_string().contains("no lookup reply queued"), "{err}");
+        assert_eq!(
+            mock.calls(),
+            [
+                OpCall::Eval {
+                    code: "(+ 1 2)".to_string(),
+                    timeout: None
+                },
+                OpCall::Eval {
+                    code: "(+ 1 2)".to_string(),
+                    timeout: None
+                },
+                OpCall::Lookup {
+                    sym: "map".to_string(),
+                    ns: Some("user".to_string())
+                },
+            ]
+        );
+    }
+}
@@ -11,7 +11,8 @@
 // GNU Affero General Public License for more details.
 
 /// nREPL operation builders
-use crate::message::Request;
+use crate::message::{Code, Request};
+use crate::refresh::RefreshOptions;
 
 /// Format a numeric request id into its on-the-wire form (`req-{n}`).
 ///
@@ -37,6 +38,15 @@ pub fn clone_request(id: impl Into<String>) -> Request {
     base_request("clone", id)
 }
 
+/// Build a clone request for a copy of `session`, inheriting its dynamic
+/// bindings
+pub fn clone_from_request(id: impl Into<String>, session: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ..base_request("clone", id)
+    }
+}
+
 /// Build an eval request with optional file location metadata
 ///
 /// This allows the nREPL server to preserve source file metadata in compiled functions,
@@ -56,7 +66,7 @@ pub fn clone_request(id: impl Into<String>) -> Request {
 pub fn eval_request_with_location(
     id: impl Into<String>,
     session: &str,
-    code: impl Into<String>,
+    code: impl Into<Code>,
     file: Option<String>,
     line: Option<i64>,
     column: Option<i64>,
@@ -64,13 +74,34 @@ pub fn eval_request_with_location(
     Request {
         session: Some(session.to_string()),
         code: Some(code.into()),
-        file,
+        file: file.map(Code::from),
         line,
         column,
         ..base_request("eval", id)
     }
 }
 
+/// Build an eval request that names no session
+///
+/// nREPL runs a session-less eval in a throwaway session, so it neither waits
+/// behind the caller's session evals nor touches `*1`/`*e`. Suited to quick
+/// introspection on behalf of tooling.
+///
+/// # Arguments
+/// * `code` - Code to evaluate
+/// * `ns` - Optional namespace to evaluate in
+pub fn sessionless_eval_request(
+    id: impl Into<String>,
+    code: impl Into<Code>,
+    ns: Option<String>,
+) -> Request {
+    Request {
+        code: Some(code.into()),
+        ns,
+        ..base_request("eval", id)
+    }
+}
+
 /// Build a load-file request
 ///
 /// # Arguments
@@ -81,7 +112,7 @@ pub fn eval_request_with_location(
 pub fn load_file_request(
     id: impl Into<String>,
     session: &str,
-    file_contents: impl Into<String>,
+    file_contents: impl Into<Code>,
     file_path: Option<String>,
     file_name: Option<String>,
 ) -> Request {
@@ -130,6 +161,47 @@ pub fn describe_request(id: impl Into<String>, verbose: Option<bool>) -> Request
     }
 }
 
+/// Build an `add-middleware` request, adding `middleware` (fully qualified
+/// var names) to the server's running stack, after requiring
+/// `extra_namespaces`
+pub fn add_middleware_request(
+    id: impl Into<String>,
+    session: &str,
+    middleware: &[String],
+    extra_namespaces: &[String],
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        middleware: Some(middleware.to_vec()),
+        extra_namespaces: (!extra_namespaces.is_empty()).then(|| extra_namespaces.to_vec()),
+        ..base_request("add-middleware", id)
+    }
+}
+
+/// Build an `ls-middleware` request, listing the server's middleware stack.
+/// `verbose` asks for each layer's descriptor as well.
+pub fn ls_middleware_request(id: impl Into<String>, verbose: bool) -> Request {
+    Request {
+        verbose: verbose.then_some(true),
+        ..base_request("ls-middleware", id)
+    }
+}
+
+/// Build a `swap-middleware` request, replacing the server's stack with
+/// `middleware` (fully qualified var names), after requiring
+/// `extra_namespaces`
+pub fn swap_middleware_request(
+    id: impl Into<String>,
+    session: &str,
+    middleware: &[String],
+    extra_namespaces: &[String],
+) -> Request {
+    Request {
+        op: "swap-middleware".to_string(),
+        ..add_middleware_request(id, session, middleware, extra_namespaces)
+    }
+}
+
 /// Build an ls-sessions request to list active sessions
 pub fn ls_sessions_request(id: impl Into<String>) -> Request {
     base_request("ls-sessions", id)
@@ -175,6 +247,22 @@ pub fn completions_request(
     }
 }
 
+/// Build a cider-nrepl `complete` request, which completes ClojureScript
+/// as well as Clojure
+pub fn complete_request(
+    id: impl Into<String>,
+    session: &str,
+    prefix: impl Into<String>,
+    ns: Option<String>,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        prefix: Some(prefix.into()),
+        ns,
+        ..base_request("complete", id)
+    }
+}
+
 /// Build a lookup request to get information about a symbol
 ///
 /// # Arguments
@@ -198,6 +286,450 @@ pub fn lookup_request(
     }
 }
 
+/// Build a cider-nrepl `analyze-last-stacktrace` request for the session's
+/// most recent exception (`*e`)
+pub fn analyze_last_stacktrace_request(id: impl Into<String>, session: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ..base_request("analyze-last-stacktrace", id)
+    }
+}
+
+/// Build a cider-nrepl `info` request for `sym`, resolved in `ns` when given
+pub fn info_request(
+    id: impl Into<String>,
+    session: &str,
+    sym: impl Into<String>,
+    ns: Option<String>,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        sym: Some(sym.into()),
+        ns,
+        ..base_request("info", id)
+    }
+}
+
+/// Build a cider-nrepl `eldoc` request for `sym`, resolved in `ns` when given
+pub fn eldoc_request(
+    id: impl Into<String>,
+    session: &str,
+    sym: impl Into<String>,
+    ns: Option<String>,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        sym: Some(sym.into()),
+        ns,
+        ..base_request("eldoc", id)
+    }
+}
+
+/// Build a cider-nrepl `apropos` request for vars whose name matches the
+/// regex `query`, in `search_ns` only when given. `docs` also matches
+/// docstrings; `privates` includes private vars.
+pub fn apropos_request(
+    id: impl Into<String>,
+    session: &str,
+    query: impl Into<String>,
+    search_ns: Option<String>,
+    docs: bool,
+    privates: bool,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        query: Some(query.into()),
+        ns: search_ns,
+        // Sent only when set, like `load?`
+        docs: docs.then_some(true),
+        privates: privates.then_some(true),
+        ..base_request("apropos", id)
+    }
+}
+
+/// Build a cider-nrepl `clojuredocs-lookup` request for `sym` in `ns`
+pub fn clojuredocs_lookup_request(
+    id: impl Into<String>,
+    session: &str,
+    ns: impl Into<String>,
+    sym: impl Into<String>,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ns: Some(ns.into()),
+        sym: Some(sym.into()),
+        ..base_request("clojuredocs-lookup", id)
+    }
+}
+
+/// Build a cider-nrepl `fn-refs` request for the functions that call `sym`
+/// in `ns`
+pub fn fn_refs_request(
+    id: impl Into<String>,
+    session: &str,
+    ns: impl Into<String>,
+    sym: impl Into<String>,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ns: Some(ns.into()),
+        sym: Some(sym.into()),
+        ..base_request("fn-refs", id)
+    }
+}
+
+/// Build a cider-nrepl `fn-deps` request for the functions `sym` in `ns`
+/// calls
+pub fn fn_deps_request(
+    id: impl Into<String>,
+    session: &str,
+    ns: impl Into<String>,
+    sym: impl Into<String>,
+) -> Request {
+    Request {
+        op: "fn-deps".to_string(),
+        ..fn_refs_request(id, session, ns, sym)
+    }
+}
+
+/// Build a cider-nrepl `ns-list` request for every loaded namespace
+pub fn ns_list_request(id: impl Into<String>, session: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ..base_request("ns-list", id)
+    }
+}
+
+/// Build a cider-nrepl `ns-vars-with-meta` request for the public vars of `ns`
+pub fn ns_vars_request(id: impl Into<String>, session: &str, ns: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ns: Some(ns.to_string()),
+        ..base_request("ns-vars-with-meta", id)
+    }
+}
+
+/// Build a cider-nrepl `ns-path` request for the source file of `ns`
+pub fn ns_path_request(id: impl Into<String>, session: &str, ns: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ns: Some(ns.to_string()),
+        ..base_request("ns-path", id)
+    }
+}
+
+/// Build a `sideloader-start` request, giving `session` a classloader that
+/// looks up missing classes and resources from the client
+pub fn sideloader_start_request(id: impl Into<String>, session: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ..base_request("sideloader-start", id)
+    }
+}
+
+/// Build a `sideloader-provide` request answering the lookup of `name`.
+/// `content` is the bytes base64-encoded, or empty if the client has none.
+pub fn sideloader_provide_request(
+    id: impl Into<String>,
+    session: &str,
+    kind: &str,
+    name: &str,
+    content: String,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        kind: Some(kind.to_string()),
+        name: Some(name.to_string()),
+        content: Some(content),
+        ..base_request("sideloader-provide", id)
+    }
+}
+
+/// Build a cider-nrepl `classpath` request for the server's classpath
+pub fn classpath_request(id: impl Into<String>, session: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ..base_request("classpath", id)
+    }
+}
+
+/// Build a cider-nrepl `spec-list` request for the registered specs, only
+/// those whose name matches the regex `filter_regex` when given
+pub fn spec_list_request(
+    id: impl Into<String>,
+    session: &str,
+    filter_regex: Option<String>,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        filter_regex,
+        ..base_request("spec-list", id)
+    }
+}
+
+/// Build a cider-nrepl `spec-form` request for the form of `spec_name`
+/// (`:ns/name` or a function's qualified symbol)
+pub fn spec_form_request(
+    id: impl Into<String>,
+    session: &str,
+    spec_name: impl Into<String>,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        spec_name: Some(spec_name.into()),
+        ..base_request("spec-form", id)
+    }
+}
+
+/// Build a cider-nrepl `spec-example` request for a generated value
+/// conforming to `spec_name`
+pub fn spec_example_request(
+    id: impl Into<String>,
+    session: &str,
+    spec_name: impl Into<String>,
+) -> Request {
+    Request {
+        op: "spec-example".to_string(),
+        ..spec_form_request(id, session, spec_name)
+    }
+}
+
+/// Build a cider-nrepl `undef` request removing `sym` as resolved in `ns`
+pub fn undef_request(id: impl Into<String>, session: &str, ns: &str, sym: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ns: Some(ns.to_string()),
+        sym: Some(sym.to_string()),
+        ..base_request("undef", id)
+    }
+}
+
+/// Build a cider-nrepl `undef-all` request removing every var, alias and
+/// refer in `ns`
+pub fn undef_all_request(id: impl Into<String>, session: &str, ns: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ns: Some(ns.to_string()),
+        ..base_request("undef-all", id)
+    }
+}
+
+/// Build a cider-nrepl `toggle-trace-var` request tracing or untracing `sym`
+/// as resolved in `ns`
+pub fn toggle_trace_var_request(
+    id: impl Into<String>,
+    session: &str,
+    ns: &str,
+    sym: &str,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ns: Some(ns.to_string()),
+        sym: Some(sym.to_string()),
+        ..base_request("toggle-trace-var", id)
+    }
+}
+
+/// Build a cider-nrepl `toggle-trace-ns` request tracing or untracing every
+/// function in `ns`
+pub fn toggle_trace_ns_request(id: impl Into<String>, session: &str, ns: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ns: Some(ns.to_string()),
+        ..base_request("toggle-trace-ns", id)
+    }
+}
+
+/// Build a cider-nrepl `out-subscribe` request, asking for output printed
+/// outside any eval to be forwarded to `session`
+pub fn out_subscribe_request(id: impl Into<String>, session: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ..base_request("out-subscribe", id)
+    }
+}
+
+/// Build a cider-nrepl `out-unsubscribe` request
+pub fn out_unsubscribe_request(id: impl Into<String>, session: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ..base_request("out-unsubscribe", id)
+    }
+}
+
+/// Build a cider-nrepl `format-code` request, formatting `code` with cljfmt
+pub fn format_code_request(id: impl Into<String>, session: &str, code: impl Into<Code>) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        code: Some(code.into()),
+        ..base_request("format-code", id)
+    }
+}
+
+/// Build a cider-nrepl `format-edn` request, pretty-printing `edn` within
+/// `right_margin` columns when given
+pub fn format_edn_request(
+    id: impl Into<String>,
+    session: &str,
+    edn: impl Into<String>,
+    right_margin: Option<u32>,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        edn: Some(edn.into()),
+        print_right_margin: right_margin.map(i64::from),
+        ..base_request("format-edn", id)
+    }
+}
+
+/// Build an eval request that opens cider-nrepl's inspector on the result of
+/// `code`, evaluated in `ns` when given
+pub fn inspect_eval_request(
+    id: impl Into<String>,
+    session: &str,
+    code: impl Into<Code>,
+    ns: Option<String>,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        code: Some(code.into()),
+        ns,
+        inspect: Some(true),
+        ..base_request("eval", id)
+    }
+}
+
+/// Build a cider-nrepl `inspect-*` request for `op` that takes no arguments
+/// (`inspect-refresh`, `inspect-pop`, `inspect-next-page`, `inspect-prev-page`)
+pub fn inspect_request(id: impl Into<String>, session: &str, op: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ..base_request(op, id)
+    }
+}
+
+/// Build a cider-nrepl `inspect-push` request descending into the value
+/// rendered with index `idx`
+pub fn inspect_push_request(id: impl Into<String>, session: &str, idx: u32) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        idx: Some(i64::from(idx)),
+        ..base_request("inspect-push", id)
+    }
+}
+
+/// Build a cider-nrepl `inspect-set-page-size` request
+pub fn inspect_set_page_size_request(id: impl Into<String>, session: &str, size: u32) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        page_size: Some(i64::from(size)),
+        ..base_request("inspect-set-page-size", id)
+    }
+}
+
+/// Build a cider-nrepl `refresh` request, or `refresh-all` with `all`
+pub fn refresh_request(
+    id: impl Into<String>,
+    session: &str,
+    all: bool,
+    options: RefreshOptions,
+) -> Request {
+    let RefreshOptions {
+        dirs,
+        before,
+        after,
+    } = options;
+    Request {
+        session: Some(session.to_string()),
+        dirs: (!dirs.is_empty()).then_some(dirs),
+        before,
+        after,
+        ..base_request(if all { "refresh-all" } else { "refresh" }, id)
+    }
+}
+
+/// Build a cider-nrepl `refresh-clear` request
+pub fn refresh_clear_request(id: impl Into<String>, session: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ..base_request("refresh-clear", id)
+    }
+}
+
+/// Build a cider-nrepl `init-debugger` request. The server never finishes it:
+/// each breakpoint hit in the session is reported as a response to it
+pub fn init_debugger_request(id: impl Into<String>, session: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ..base_request("init-debugger", id)
+    }
+}
+
+/// Build a cider-nrepl `debug-input` request answering the breakpoint stop
+/// named `key` with `input`
+pub fn debug_input_request(
+    id: impl Into<String>,
+    session: &str,
+    key: impl Into<String>,
+    input: impl Into<String>,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        key: Some(key.into()),
+        input: Some(input.into()),
+        ..base_request("debug-input", id)
+    }
+}
+
+/// Build a cider-nrepl `test` request running `tests` (var names, unqualified)
+/// in namespace `ns`, or every test in it when `tests` is empty
+pub fn test_request(id: impl Into<String>, session: &str, ns: &str, tests: Vec<String>) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ns: Some(ns.to_string()),
+        tests: Some(tests),
+        ..base_request("test", id)
+    }
+}
+
+/// Build a cider-nrepl `test-all` request. With `load_all`, every project
+/// namespace is loaded first so tests not yet required are found.
+pub fn test_all_request(id: impl Into<String>, session: &str, load_all: bool) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        // Sent only when set: the server reads any value, even 0, as true
+        load: load_all.then_some(true),
+        ..base_request("test-all", id)
+    }
+}
+
+/// Build a cider-nrepl `retest` request, re-running the tests that failed or
+/// errored in the session's last run
+pub fn retest_request(id: impl Into<String>, session: &str) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ..base_request("retest", id)
+    }
+}
+
+/// Build a cider-nrepl `test-stacktrace` request for the erroring assertion
+/// at `index` in `ns/var` from the session's last test run
+pub fn test_stacktrace_request(
+    id: impl Into<String>,
+    session: &str,
+    ns: &str,
+    var: &str,
+    index: u32,
+) -> Request {
+    Request {
+        session: Some(session.to_string()),
+        ns: Some(ns.to_string()),
+        var: Some(var.to_string()),
+        index: Some(i64::from(index)),
+        ..base_request("test-stacktrace", id)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -222,8 +754,8 @@ mod tests {
         assert_eq!(req.id, "req-7");
         assert_eq!(req.op, "eval");
         assert_eq!(req.session, Some("session-1".to_string()));
-        assert_eq!(req.code, Some("(+ 1 2)".to_string()));
-        assert_eq!(req.file, Some("/path/to/file.clj".to_string()));
+        assert_eq!(req.code.as_deref(), Some("(+ 1 2)"));
+        assert_eq!(req.file.as_deref(), Some("/path/to/file.clj"));
         assert_eq!(req.line, Some(42));
         assert_eq!(req.column, Some(10));
     }
@@ -234,7 +766,7 @@ mod tests {
 
         assert_eq!(req.op, "eval");
         assert_eq!(req.session, Some("session-1".to_string()));
-        assert_eq!(req.code, Some("(+ 1 2)".to_string()));
+        assert_eq!(req.code.as_deref(), Some("(+ 1 2)"));
         assert_eq!(req.file, None);
         assert_eq!(req.line, None);
         assert_eq!(req.column, None);
@@ -251,8 +783,40 @@ mod tests {
             None, // No column
         );
 
-        assert_eq!(req.file, Some("src/core.clj".to_string()));
+        assert_eq!(req.file.as_deref(), Some("src/core.clj"));
         assert_eq!(req.line, Some(10));
         assert_eq!(req.column, None);
     }
+
+    #[test]
+    fn test_test_all_request_sends_load_only_when_set() {
+        let encode = |req| String::from_utf8(crate::codec::encode_request(&req).unwrap()).unwrap();
+
+        let plain = encode(test_all_request(wire_id(3), "session-1", false));
+        assert!(!plain.contains("load?"));
+
+        let loading = encode(test_all_request(wire_id(4), "session-1", true));
+        assert!(loading.contains("5:load?i1e"));
+    }
+
+    #[test]
+    fn test_clone_from_request_names_source_session() {
+        assert_eq!(clone_request(wire_id(1)).session, None);
+        let req = clone_from_request(wire_id(2), "session-1");
+        assert_eq!(req.op, "clone");
+        assert_eq!(req.session.as_deref(), Some("session-1"));
+    }
+
+    #[test]
+    fn test_test_request_lists_vars() {
+        let req = test_request(
+            wire_id(5),
+            "session-1",
+            "my.tests",
+            vec!["adding".to_string()],
+        );
+        let encoded = String::from_utf8(crate::codec::encode_request(&req).unwrap()).unwrap();
+        assert!(encoded.contains("2:ns8:my.tests"));
+        assert!(encoded.contains("5:testsl6:addinge"));
+    }
 }
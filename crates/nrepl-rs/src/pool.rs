@@ -0,0 +1,238 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Warm session pool for isolated evals
+//!
+//! [`SessionManager`] keeps a set of pre-cloned sessions on one connection and
+//! hands them out with checkout/checkin semantics, so a caller that wants a
+//! session per task (a test runner, say) does not pay a `clone` round trip for
+//! each one.
+//!
+//! Sessions isolate dynamic state: `*ns*`, `*1`/`*2`/`*3`, `*e` and any
+//! `set!` bindings. They do not isolate global state such as `def`s, which
+//! live in namespaces shared by every session on the server.
+
+use crate::error::{NReplError, Result};
+use crate::message::EvalResult;
+use crate::session::Session;
+use crate::worker::{EvalOutcome, RequestId, Worker, WorkerCommand};
+use std::collections::VecDeque;
+use std::sync::mpsc::{Sender, channel};
+use std::time::{Duration, Instant};
+
+/// How long a checkin waits for its namespace reset eval.
+const RESET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A pool of warm sessions on one worker.
+///
+/// Owns the [`Worker`]; reach it through [`worker`](Self::worker) to evaluate
+/// in a checked-out session. All methods block.
+///
+/// ```no_run
+/// use nrepl_rs::SessionManager;
+/// use nrepl_rs::worker::Worker;
+///
+/// let worker = Worker::new();
+/// worker.connect_blocking("localhost:7888".to_string())?;
+/// let mut pool = SessionManager::new(worker, 4)?.reset_ns("user");
+///
+/// let session = pool.checkout()?;
+/// let id = pool.worker().submit_eval(session.clone(), "(+ 1 2)", None, None, None, None);
+/// // ... poll pool.worker().try_recv_response(id) ...
+/// pool.checkin(session)?;
+/// # Ok::<(), nrepl_rs::NReplError>(())
+/// ```
+pub struct SessionManager {
+    worker: Worker,
+    idle: VecDeque<Session>,
+    /// Number of warm sessions kept; extras are closed on checkin.
+    size: usize,
+    /// Namespace to switch a session back to on checkin.
+    reset_ns: Option<String>,
+}
+
+impl SessionManager {
+    /// Wrap `worker` (already connected) and clone `size` warm sessions.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error from cloning a session; sessions cloned before
+    /// it are closed.
+    pub fn new(worker: Worker, size: usize) -> Result<Self> {
+        let mut pool = Self {
+            worker,
+            idle: VecDeque::with_capacity(size),
+            size,
+            reset_ns: None,
+        };
+        for _ in 0..size {
+            match pool.clone_session() {
+                Ok(session) => pool.idle.push_back(session),
+                Err(e) => {
+                    let _ = pool.close_idle();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(pool)
+    }
+
+    /// Switch each session to `ns` when it is checked back in, so the next
+    /// borrower does not inherit an `in-ns` from the last.
+    #[must_use]
+    pub fn reset_ns(mut self, ns: impl Into<String>) -> Self {
+        self.reset_ns = Some(ns.into());
+        self
+    }
+
+    /// The worker the pooled sessions live on.
+    pub fn worker(&mut self) -> &mut Worker {
+        &mut self.worker
+    }
+
+    /// Number of sessions ready to hand out.
+    #[must_use]
+    pub fn idle(&self) -> usize {
+        self.idle.len()
+    }
+
+    /// Take a session for exclusive use. When the pool is empty a fresh one is
+    /// cloned, so checkout never waits on another borrower.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the pool is empty and cloning a session fails.
+    pub fn checkout(&mut self) -> Result<Session> {
+        match self.idle.pop_front() {
+            Some(session) => Ok(session),
+            None => self.clone_session(),
+        }
+    }
+
+    /// Return a session to the pool, resetting its namespace if configured.
+    /// A session beyond the pool's size, or one whose reset fails, is closed
+    /// instead of being handed out again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if closing a surplus or failed session fails.
+    pub fn checkin(&mut self, session: Session) -> Result<()> {
+        let reset_ok = match self.reset_ns.clone() {
+            Some(ns) => self
+                .eval(&session, format!("(in-ns '{ns})"))
+                .is_ok_and(|r| r.ex.is_none()),
+            None => true,
+        };
+        if reset_ok && self.idle.len() < self.size {
+            self.idle.push_back(session);
+            Ok(())
+        } else {
+            self.close_session(session)
+        }
+    }
+
+    /// Close every idle session. Checked-out sessions are the borrower's to
+    /// close (or check in first).
+    ///
+    /// # Errors
+    ///
+    /// Returns the last error from closing a session; every session is still
+    /// attempted.
+    pub fn close_idle(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        while let Some(session) = self.idle.pop_front() {
+            if let Err(e) = self.close_session(session) {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    fn clone_session(&self) -> Result<Session> {
+        self.control("clone-session", |op_id, reply| {
+            WorkerCommand::CloneSession { op_id, reply }
+        })
+    }
+
+    fn close_session(&self, session: Session) -> Result<()> {
+        self.control("close-session", |op_id, reply| {
+            WorkerCommand::CloseSession {
+                op_id,
+                session,
+                reply,
+            }
+        })
+    }
+
+    /// Send a control op and block for its reply.
+    fn control<T>(
+        &self,
+        operation: &str,
+        build: impl FnOnce(RequestId, Sender<Result<T>>) -> WorkerCommand,
+    ) -> Result<T> {
+        let (reply_tx, reply_rx) = channel();
+        self.worker
+            .command_sender()
+            .send(build(self.worker.next_id(), reply_tx))
+            .map_err(|_| worker_gone())?;
+        let timeout = self.worker.timeouts().for_op(operation);
+        reply_rx
+            .recv_timeout(timeout)
+            .map_err(|_| NReplError::timeout(operation, timeout))?
+    }
+
+    /// Evaluate `code` in `session` and block for the result.
+    fn eval(&mut self, session: &Session, code: String) -> Result<EvalResult> {
+        let id = self
+            .worker
+            .submit_eval(session.clone(), code, Some(RESET_TIMEOUT), None, None, None)
+            .map_err(|_| worker_gone())?;
+        // The worker enforces RESET_TIMEOUT; this only guards against a
+        // worker that never answers at all.
+        let give_up = Instant::now() + RESET_TIMEOUT * 2;
+        loop {
+            if let Some(response) = self.worker.try_recv_response(id) {
+                return match response.outcome {
+                    EvalOutcome::Done(result) => result,
+                    EvalOutcome::NeedInput { .. } => Err(NReplError::OperationFailed(
+                        "session reset is waiting for stdin".to_string(),
+                    )),
+                };
+            }
+            if Instant::now() > give_up {
+                return Err(NReplError::timeout("eval", RESET_TIMEOUT));
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
+
+impl Drop for SessionManager {
+    /// Closes idle sessions without waiting for the replies, so dropping a
+    /// pool on a wedged connection cannot hang.
+    fn drop(&mut self) {
+        for session in self.idle.drain(..) {
+            let _ = self
+                .worker
+                .command_sender()
+                .send(WorkerCommand::CloseSession {
+                    op_id: self.worker.next_id(),
+                    session,
+                    reply: channel().0,
+                });
+        }
+    }
+}
+
+pub(crate) fn worker_gone() -> NReplError {
+    NReplError::Connection(std::io::Error::other("Worker thread disconnected"))
+}
@@ -0,0 +1,158 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Reloading changed namespaces with cider-nrepl's `refresh` ops
+//!
+//! `refresh` reloads the namespaces whose files changed since the last
+//! refresh, plus everything that depends on them, in dependency order
+//! (tools.namespace's reloaded workflow); `refresh-all` reloads every
+//! namespace on the source paths; `refresh-clear` forgets the tracker's
+//! state after a failed reload. A reload stops at the first namespace that
+//! fails to load, and [`RefreshReport`] says which one and why.
+
+use crate::message::{BencodeValue, Response};
+use crate::stacktrace::StackTrace;
+
+/// Which directories to scan and what to run around a reload. The default
+/// scans the whole classpath and runs no hooks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefreshOptions {
+    /// Source directories to scan; empty for every classpath directory.
+    pub dirs: Vec<String>,
+    /// Fully qualified symbol of a function to call before reloading, such
+    /// as a system's `stop`.
+    pub before: Option<String>,
+    /// Function to call after a successful reload, such as `start`.
+    pub after: Option<String>,
+}
+
+/// What a `refresh` or `refresh-all` did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefreshReport {
+    /// The namespaces the server set out to reload, in load order.
+    pub reloading: Vec<String>,
+    /// Why the reload stopped, if it did.
+    pub error: Option<RefreshError>,
+}
+
+/// A reload that failed partway.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefreshError {
+    /// The namespace that would not load; `None` when the failure came from
+    /// a `before`/`after` hook or the refresh machinery itself.
+    pub ns: Option<String>,
+    /// The exception, when the server sent one.
+    pub trace: Option<StackTrace>,
+    /// Error text the server printed alongside.
+    pub err: String,
+}
+
+impl RefreshReport {
+    /// Whether every namespace reloaded.
+    #[must_use]
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Read a refresh exchange. The server reports a failure with an `error`
+    /// status, carrying the analysed exception in `error` and the failing
+    /// namespace in `error-ns`.
+    pub(crate) fn from_cider(responses: &[Response]) -> Self {
+        let mut report = RefreshReport::default();
+        let mut err = String::new();
+        let mut failed = false;
+        for r in responses {
+            if let Some(namespaces) = &r.reloading {
+                report.reloading.extend(namespaces.iter().cloned());
+            }
+            if let Some(e) = &r.err {
+                err.push_str(e);
+            }
+            failed |= r.status.iter().any(|s| s == "error")
+                || r.refresh_error.is_some()
+                || r.error_ns.is_some();
+        }
+        if failed {
+            report.error = Some(RefreshError {
+                ns: responses.iter().find_map(|r| r.error_ns.clone()),
+                trace: responses.iter().find_map(|r| r.refresh_error.clone()),
+                err,
+            });
+        }
+        report
+    }
+}
+
+/// Read a refresh `error`: cider-nrepl's analysed cause chain, a list of
+/// `{class message stacktrace}` dicts, outermost first.
+pub(crate) fn causes_from_bencode(value: BencodeValue) -> Option<StackTrace> {
+    let BencodeValue::List(causes) = value else {
+        return None;
+    };
+    causes
+        .into_iter()
+        .filter_map(|cause| match cause {
+            BencodeValue::Dict(m) => StackTrace::from_cause(&m),
+            _ => None,
+        })
+        .rev()
+        .reduce(|cause, mut outer| {
+            outer.cause = Some(Box::new(cause));
+            outer
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(frame: &str) -> Response {
+        crate::codec::decode_response(frame.as_bytes()).unwrap().0
+    }
+
+    #[test]
+    fn test_report_lists_reloaded_namespaces() {
+        let responses = [
+            decode("d2:id5:req-19:reloadingl8:app.core13:app.core-testee"),
+            decode("d2:id5:req-16:statusl2:okee"),
+            decode("d2:id5:req-16:statusl4:doneee"),
+        ];
+        let report = RefreshReport::from_cider(&responses);
+        assert_eq!(report.reloading, ["app.core", "app.core-test"]);
+        assert!(report.succeeded());
+    }
+
+    #[test]
+    fn test_report_names_failing_namespace() {
+        let responses = [
+            decode("d2:id5:req-19:reloadingl8:app.util8:app.coreee"),
+            decode(concat!(
+                "d5:errorl",
+                "d5:class32:clojure.lang.Compiler$CompilerEx7:message9:bad thing",
+                "10:stacktraceld4:name16:app.core$eval123ee",
+                "e",
+                "d5:class26:java.lang.RuntimeException7:message22:Unable to resolve: foo",
+                "e",
+                "e",
+                "8:error-ns8:app.core2:id5:req-16:statusl5:erroree"
+            )),
+        ];
+        let report = RefreshReport::from_cider(&responses);
+        assert!(!report.succeeded());
+        let error = report.error.expect("an error");
+        assert_eq!(error.ns.as_deref(), Some("app.core"));
+        let trace = error.trace.expect("a trace");
+        assert_eq!(trace.class, "clojure.lang.Compiler$CompilerEx");
+        assert_eq!(trace.frames.len(), 1);
+        assert_eq!(trace.root_cause().message, "Unable to resolve: foo");
+    }
+}
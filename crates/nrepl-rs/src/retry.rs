@@ -0,0 +1,215 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Retrying idempotent requests
+//!
+//! `describe`, `completions`, `lookup` and `ls-sessions` only read server
+//! state, so sending one again after a timeout or a dropped `done` is
+//! harmless. [`RetryPolicy`] says how often and how patiently; the worker
+//! applies its policy (see
+//! [`WorkerConfig::retry_policy`](crate::worker::WorkerConfig::retry_policy))
+//! in its blocking helpers for those ops. Evals are never retried: running
+//! one twice is not harmless.
+
+use crate::error::{NReplError, Result};
+use std::time::{Duration, Instant};
+
+/// How an idempotent request is retried after a transient failure.
+///
+/// Attempt `n` (from 1) that fails with an error `retryable` accepts is
+/// followed, after `backoff(n)`, by attempt `n + 1`, up to `max_attempts` in
+/// all. The last error is returned.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Attempts in all, the first included. 1 (or 0) means no retries.
+    pub max_attempts: u32,
+    /// Wait before the first retry; each later one waits twice as long.
+    pub initial_backoff: Duration,
+    /// Longest wait between attempts.
+    pub max_backoff: Duration,
+    /// Whether an error is worth another attempt.
+    pub retryable: fn(&NReplError) -> bool,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, 100ms then 200ms apart, retrying the errors
+    /// [`NReplError::is_retryable`] accepts.
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            retryable: NReplError::is_retryable,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes one attempt and returns its error.
+    #[must_use]
+    pub fn never() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// The wait after failed attempt `attempt` (from 1).
+    #[must_use]
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(1).min(31);
+        self.initial_backoff
+            .saturating_mul(1 << doublings)
+            .min(self.max_backoff)
+    }
+
+    /// Run `attempt` until it succeeds, fails with an error the policy does
+    /// not retry, or runs out of attempts, sleeping between attempts.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from the last attempt made.
+    pub fn run<T>(&self, mut attempt: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut made = 1;
+        loop {
+            match attempt() {
+                Err(e) if made < self.max_attempts && (self.retryable)(&e) => {
+                    std::thread::sleep(self.backoff(made));
+                    made += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Run `attempt` as [`run`](Self::run) does, but within `budget` in
+    /// all: each attempt is handed the time that remains to wait in, and no
+    /// retry is made once its backoff would use up the rest. A caller that
+    /// promises to wait no longer than an op's timeout keeps the promise,
+    /// however many attempts the policy allows.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from the last attempt made.
+    pub fn run_within<T>(
+        &self,
+        budget: Duration,
+        mut attempt: impl FnMut(Duration) -> Result<T>,
+    ) -> Result<T> {
+        let deadline = Instant::now() + budget;
+        let remaining = || deadline.saturating_duration_since(Instant::now());
+        let mut made = 1;
+        loop {
+            match attempt(remaining()) {
+                Err(e)
+                    if made < self.max_attempts
+                        && (self.retryable)(&e)
+                        && self.backoff(made) < remaining() =>
+                {
+                    std::thread::sleep(self.backoff(made));
+                    made += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn fast() -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(10), Duration::from_secs(2));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_transient_errors_are_retried_until_success() {
+        let calls = Cell::new(0);
+        let result = fast().run(|| {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 {
+                Err(NReplError::timeout("describe", Duration::from_secs(1)))
+            } else {
+                Ok(calls.get())
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_attempts_are_capped() {
+        let calls = Cell::new(0);
+        let result: Result<()> = fast().run(|| {
+            calls.set(calls.get() + 1);
+            Err(NReplError::timeout("lookup", Duration::from_secs(1)))
+        });
+        assert!(matches!(result, Err(NReplError::Timeout { .. })));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn test_attempts_share_one_budget() {
+        // Each attempt waits out all the time it is given, as a blocking
+        // op on a silent server does.
+        let budget = Duration::from_millis(100);
+        let calls = Cell::new(0);
+        let started = Instant::now();
+        let result: Result<()> = fast().run_within(budget, |wait| {
+            calls.set(calls.get() + 1);
+            std::thread::sleep(wait);
+            Err(NReplError::timeout("describe", wait))
+        });
+        assert!(matches!(result, Err(NReplError::Timeout { .. })));
+        assert!(started.elapsed() < budget + Duration::from_millis(50));
+        assert_eq!(calls.get(), 1);
+
+        // Quick failures still retry while the budget lasts.
+        calls.set(0);
+        let _: Result<()> = fast().run_within(budget, |_| {
+            calls.set(calls.get() + 1);
+            Err(NReplError::timeout("lookup", Duration::ZERO))
+        });
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn test_permanent_errors_are_not_retried() {
+        let calls = Cell::new(0);
+        let result: Result<()> = fast().run(|| {
+            calls.set(calls.get() + 1);
+            Err(NReplError::OperationFailed("unknown op".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+
+        calls.set(0);
+        let _: Result<()> = RetryPolicy::never().run(|| {
+            calls.set(calls.get() + 1);
+            Err(NReplError::timeout("describe", Duration::from_secs(1)))
+        });
+        assert_eq!(calls.get(), 1);
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Rich eval results from cider-nrepl's content-type middleware
+//!
+//! An eval sent with `content-type` set asks the server to describe results
+//! it knows how to present (an image file, a `java.awt.Image`, a URL) as
+//! MIME content rather than only as printed text: the response gains a
+//! `content-type` of `[mime {params}]` and a `body`, base64 for binary types.
+//! [`RichContent`] is that pair, read back so a frontend can render a plot
+//! inline. The printed `value` still arrives alongside.
+
+use crate::base64;
+use crate::message::BencodeValue;
+use std::collections::BTreeMap;
+
+/// A response's `content-type`: the MIME type and its parameters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct ContentType {
+    pub mime: String,
+    pub params: BTreeMap<String, String>,
+}
+
+/// An eval result the server sent as MIME content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RichContent {
+    /// Image bytes, decoded from the base64 body.
+    Image { mime: String, data: Vec<u8> },
+    /// An HTML document or fragment.
+    Html(String),
+    /// A resource to fetch instead of a body (`message/external-body`).
+    Url(String),
+    /// Any other type, with its body as sent.
+    Other { mime: String, body: String },
+}
+
+impl RichContent {
+    /// Read a `content-type` and its `body`. An image whose body is not valid
+    /// base64 is kept as [`Other`](Self::Other).
+    pub(crate) fn from_parts(content_type: &ContentType, body: Option<&str>) -> Self {
+        let mime = content_type.mime.as_str();
+        let body = body.unwrap_or_default();
+        if mime == "message/external-body"
+            && let Some(url) = content_type
+                .params
+                .get("URL")
+                .or_else(|| content_type.params.get("url"))
+        {
+            return RichContent::Url(url.clone());
+        }
+        if mime.starts_with("image/")
+            && let Some(data) = base64::decode(body)
+        {
+            return RichContent::Image {
+                mime: mime.to_string(),
+                data,
+            };
+        }
+        if mime == "text/html" {
+            return RichContent::Html(body.to_string());
+        }
+        RichContent::Other {
+            mime: mime.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    /// The MIME type.
+    #[must_use]
+    pub fn mime(&self) -> &str {
+        match self {
+            RichContent::Image { mime, .. } | RichContent::Other { mime, .. } => mime,
+            RichContent::Html(_) => "text/html",
+            RichContent::Url(_) => "message/external-body",
+        }
+    }
+}
+
+/// Read `content-type`: `[mime {params}]`, or a bare MIME string.
+pub(crate) fn content_type_from_bencode(value: BencodeValue) -> Option<ContentType> {
+    match value {
+        BencodeValue::String(mime) => Some(ContentType {
+            mime,
+            params: BTreeMap::new(),
+        }),
+        BencodeValue::List(items) => {
+            let mut items = items.into_iter();
+            let BencodeValue::String(mime) = items.next()? else {
+                return None;
+            };
+            let params = match items.next() {
+                Some(BencodeValue::Dict(m)) => m
+                    .into_iter()
+                    .map(|(k, v)| (k, v.to_string_repr()))
+                    .collect(),
+                _ => BTreeMap::new(),
+            };
+            Some(ContentType { mime, params })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Response;
+
+    fn decode(frame: &str) -> Response {
+        crate::codec::decode_response(frame.as_bytes()).unwrap().0
+    }
+
+    #[test]
+    fn test_image_body_decoded() {
+        let r = decode("d4:body8:iVBORw==12:content-typel9:image/pngdee2:id5:req-1e");
+        let content_type = r.content_type.expect("a content type");
+        let rich = RichContent::from_parts(&content_type, r.body.as_deref());
+        assert_eq!(
+            rich,
+            RichContent::Image {
+                mime: "image/png".to_string(),
+                data: vec![0x89, b'P', b'N', b'G'],
+            }
+        );
+        assert_eq!(rich.mime(), "image/png");
+    }
+
+    #[test]
+    fn test_external_body_is_url() {
+        let r = decode(concat!(
+            "d4:body0:12:content-typel21:message/external-body",
+            "d11:access-type3:URL3:URL19:https://clojure.orgee2:id5:req-1e"
+        ));
+        let rich = RichContent::from_parts(&r.content_type.unwrap(), r.body.as_deref());
+        assert_eq!(rich, RichContent::Url("https://clojure.org".to_string()));
+    }
+}
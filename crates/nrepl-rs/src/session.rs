@@ -10,6 +10,10 @@
 // MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
 // GNU Affero General Public License for more details.
 
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
 /// Represents an nREPL session
 ///
 /// # Security Note
@@ -53,6 +57,201 @@ impl Session {
     }
 }
 
+/// What a worker knows about one of its sessions, from
+/// [`Worker::sessions`](crate::worker::Worker::sessions).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// The session's id.
+    pub session: Session,
+    /// A name for the session's role ("user REPL", "test runner"), set with
+    /// [`Worker::label_session`](crate::worker::Worker::label_session).
+    pub label: Option<String>,
+    /// When the worker cloned the session, or first sent a request in it if
+    /// the session came from elsewhere.
+    pub created: SystemTime,
+    /// When the worker last sent a request in the session.
+    pub last_used: SystemTime,
+    /// Requests sent in the session, by op.
+    pub ops: BTreeMap<String, u64>,
+}
+
+impl SessionInfo {
+    fn new(session: Session) -> Self {
+        let now = SystemTime::now();
+        Self {
+            session,
+            label: None,
+            created: now,
+            last_used: now,
+            ops: BTreeMap::new(),
+        }
+    }
+
+    /// Requests sent in the session, of any op.
+    #[must_use]
+    pub fn requests(&self) -> u64 {
+        self.ops.values().sum()
+    }
+}
+
+/// A session as saved across client restarts, from
+/// [`Worker::export_sessions`](crate::worker::Worker::export_sessions).
+///
+/// Unlike [`Session`] this deserializes, since it is meant to be read back
+/// from disk. That is safe because it is not a session: only
+/// [`Worker::import_sessions`](crate::worker::Worker::import_sessions) turns
+/// it into one, and only for ids the server lists as live.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SessionDescriptor {
+    /// The session's wire id.
+    pub id: String,
+    /// The session's label, if it had one.
+    pub label: Option<String>,
+    /// When the session was first tracked.
+    pub created: SystemTime,
+}
+
+impl From<&SessionInfo> for SessionDescriptor {
+    fn from(info: &SessionInfo) -> Self {
+        Self {
+            id: info.session.id().to_string(),
+            label: info.label.clone(),
+            created: info.created,
+        }
+    }
+}
+
+/// What [`Worker::reconcile_sessions`](crate::worker::Worker::reconcile_sessions)
+/// changed in the worker's sessions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionReconciliation {
+    /// Tracked sessions the server no longer has, now dropped. Requests in
+    /// them would come back empty.
+    pub invalidated: Vec<SessionInfo>,
+    /// Server sessions the worker was not tracking, now tracked. Empty
+    /// unless orphans were adopted.
+    pub adopted: Vec<Session>,
+}
+
+/// Called with each session the worker closes for being idle.
+pub type SessionExpiredHook = Arc<dyn Fn(&SessionInfo) + Send + Sync>;
+
+/// When the worker closes idle sessions, and whom it tells.
+#[derive(Clone, Default)]
+pub(crate) struct SessionExpiry {
+    pub(crate) ttl: Option<Duration>,
+    pub(crate) hook: Option<SessionExpiredHook>,
+}
+
+impl std::fmt::Debug for SessionExpiry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionExpiry")
+            .field("ttl", &self.ttl)
+            .field("hook", &self.hook.is_some())
+            .finish()
+    }
+}
+
+/// The sessions a worker has cloned or used, shared between the worker
+/// thread (which records them) and the [`Worker`](crate::worker::Worker)
+/// handle (which reads and labels them). The server remains the authority on
+/// which sessions are live; this only remembers what went over the wire.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SessionTable {
+    inner: Arc<Mutex<HashMap<String, SessionInfo>>>,
+}
+
+impl SessionTable {
+    fn table(&self) -> std::sync::MutexGuard<'_, HashMap<String, SessionInfo>> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Start tracking a session the server has just cloned.
+    pub(crate) fn opened(&self, id: &str) {
+        self.table()
+            .entry(id.to_string())
+            .or_insert_with(|| SessionInfo::new(Session::new(id)));
+    }
+
+    /// Count a request of `op` sent in session `id`.
+    pub(crate) fn record_op(&self, id: &str, op: &str) {
+        let mut table = self.table();
+        let info = table
+            .entry(id.to_string())
+            .or_insert_with(|| SessionInfo::new(Session::new(id)));
+        info.last_used = SystemTime::now();
+        *info.ops.entry(op.to_string()).or_default() += 1;
+    }
+
+    pub(crate) fn set_label(&self, id: &str, label: Option<String>) {
+        self.table()
+            .entry(id.to_string())
+            .or_insert_with(|| SessionInfo::new(Session::new(id)))
+            .label = label;
+    }
+
+    /// Track `id` with the label and creation time it was saved with.
+    pub(crate) fn restore(&self, id: &str, saved: &SessionDescriptor) {
+        let mut table = self.table();
+        let info = table
+            .entry(id.to_string())
+            .or_insert_with(|| SessionInfo::new(Session::new(id)));
+        info.label.clone_from(&saved.label);
+        info.created = saved.created;
+    }
+
+    pub(crate) fn forget(&self, id: &str) {
+        self.table().remove(id);
+    }
+
+    /// The sessions that have gone `ttl` without a request, other than
+    /// those in `busy`.
+    pub(crate) fn idle(&self, ttl: Duration, busy: &HashSet<&str>) -> Vec<SessionInfo> {
+        self.table()
+            .values()
+            .filter(|info| !busy.contains(info.session.id()))
+            .filter(|info| info.last_used.elapsed().is_ok_and(|idle| idle >= ttl))
+            .cloned()
+            .collect()
+    }
+
+    /// Bring the table in line with `live`, the server's sessions: drop the
+    /// tracked ones it lacks and, with `adopt`, track the ones it has that
+    /// are missing here.
+    pub(crate) fn reconcile(&self, live: &[String], adopt: bool) -> SessionReconciliation {
+        let mut table = self.table();
+        let mut invalidated: Vec<SessionInfo> = Vec::new();
+        table.retain(|id, info| {
+            let alive = live.iter().any(|l| l == id);
+            if !alive {
+                invalidated.push(info.clone());
+            }
+            alive
+        });
+        invalidated.sort_by_key(|info| info.created);
+        let mut adopted = Vec::new();
+        if adopt {
+            for id in live {
+                if !table.contains_key(id) {
+                    table.insert(id.clone(), SessionInfo::new(Session::new(id.as_str())));
+                    adopted.push(Session::new(id.as_str()));
+                }
+            }
+        }
+        SessionReconciliation {
+            invalidated,
+            adopted,
+        }
+    }
+
+    /// Every tracked session, oldest first.
+    pub(crate) fn snapshot(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> = self.table().values().cloned().collect();
+        sessions.sort_by(|a, b| a.created.cmp(&b.created).then(a.session.cmp(&b.session)));
+        sessions
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -88,4 +287,63 @@ mod tests {
         // Note: Deserialize is intentionally NOT implemented for security reasons
         // (prevents session hijacking via untrusted data deserialization)
     }
+
+    #[test]
+    fn test_session_descriptor_round_trips() {
+        let table = SessionTable::default();
+        table.set_label("s1", Some("user REPL".to_string()));
+        let saved: Vec<SessionDescriptor> = table
+            .snapshot()
+            .iter()
+            .map(SessionDescriptor::from)
+            .collect();
+        let json = serde_json::to_string(&saved).expect("serialize");
+        let read: Vec<SessionDescriptor> = serde_json::from_str(&json).expect("deserialize");
+        assert_eq!(read, saved);
+
+        let restored = SessionTable::default();
+        restored.restore("s1", &read[0]);
+        let info = &restored.snapshot()[0];
+        assert_eq!(info.label.as_deref(), Some("user REPL"));
+        assert_eq!(info.created, saved[0].created);
+    }
+
+    #[test]
+    fn test_session_table_counts_ops() {
+        let table = SessionTable::default();
+        table.opened("s1");
+        table.record_op("s1", "eval");
+        table.record_op("s1", "eval");
+        table.record_op("s1", "complete");
+        table.set_label("s1", Some("user REPL".to_string()));
+        // A session cloned elsewhere is tracked from its first request.
+        table.record_op("s2", "eval");
+
+        let sessions = table.snapshot();
+        assert_eq!(sessions.len(), 2);
+        let s1 = &sessions[0];
+        assert_eq!(s1.session.id(), "s1");
+        assert_eq!(s1.label.as_deref(), Some("user REPL"));
+        assert_eq!(s1.ops.get("eval"), Some(&2));
+        assert_eq!(s1.requests(), 3);
+        assert!(s1.last_used >= s1.created);
+
+        let busy = HashSet::from(["s2"]);
+        assert!(table.idle(Duration::from_secs(60), &busy).is_empty());
+        let idle = table.idle(Duration::ZERO, &busy);
+        assert_eq!(idle.len(), 1);
+        assert_eq!(idle[0].session.id(), "s1");
+
+        table.forget("s1");
+        assert_eq!(table.snapshot()[0].session.id(), "s2");
+
+        let live = ["s3".to_string()];
+        let changes = table.reconcile(&live, false);
+        assert_eq!(changes.invalidated[0].session.id(), "s2");
+        assert!(changes.adopted.is_empty());
+        assert!(table.snapshot().is_empty());
+        let changes = table.reconcile(&live, true);
+        assert_eq!(changes.adopted, [Session::new("s3")]);
+        assert_eq!(table.snapshot()[0].session.id(), "s3");
+    }
 }
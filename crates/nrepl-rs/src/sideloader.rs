@@ -0,0 +1,119 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Serving classes and resources to the server with nREPL's sideloader
+//!
+//! `sideloader-start` gives a session a classloader that, when the server
+//! cannot find a class or resource on its own classpath, asks the client for
+//! it. Each ask is a message on the start request's id with status
+//! `sideloader-lookup`, a `type` (`class` or `resource`) and a `name`; the
+//! lookup blocks server-side until a `sideloader-provide` answers it with the
+//! bytes, base64, or an empty `content` for "not here". This is how tooling
+//! code can be loaded into a server started without it on its classpath.
+//!
+//! The worker answers lookups itself from a [`SideloadProvider`], so no
+//! round trip through the caller is needed while server code waits.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// What a lookup asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SideloadKind {
+    /// A class by binary name (`cider.nrepl.Foo`); the answer is its
+    /// `.class` file.
+    Class,
+    /// A classpath resource by path (`cider/nrepl.clj`).
+    Resource,
+}
+
+impl SideloadKind {
+    /// The wire `type`.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SideloadKind::Class => "class",
+            SideloadKind::Resource => "resource",
+        }
+    }
+
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        match s {
+            "class" => Some(SideloadKind::Class),
+            "resource" => Some(SideloadKind::Resource),
+            _ => None,
+        }
+    }
+}
+
+/// One lookup the worker answered, reported for diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SideloadLookup {
+    pub kind: SideloadKind,
+    pub name: String,
+    /// Whether the provider had it.
+    pub found: bool,
+}
+
+/// Answers lookups: the bytes of the class or resource `name`, or `None` if
+/// it is not available. Called on the worker thread while the server waits,
+/// so it should be quick; reading a local file is fine.
+pub type SideloadProvider = Arc<dyn Fn(SideloadKind, &str) -> Option<Vec<u8>> + Send + Sync>;
+
+/// A [`SideloadProvider`] serving from local directories laid out like a
+/// classpath, searched in order: class `a.b.C` is read from `a/b/C.class`, a
+/// resource from its own path.
+#[must_use]
+pub fn directory_provider(roots: Vec<PathBuf>) -> SideloadProvider {
+    Arc::new(move |kind, name| {
+        let relative = match kind {
+            SideloadKind::Class => format!("{}.class", name.replace('.', "/")),
+            SideloadKind::Resource => name.to_string(),
+        };
+        // A resource name is a classpath path; keep it inside the roots.
+        if relative
+            .split('/')
+            .any(|part| part == ".." || part.is_empty())
+        {
+            return None;
+        }
+        roots
+            .iter()
+            .find_map(|root| std::fs::read(root.join(&relative)).ok())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directory_provider_maps_names_to_paths() {
+        let root = std::env::temp_dir().join(format!("nrepl-sideload-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("tools/impl")).unwrap();
+        std::fs::write(root.join("tools/impl/Helper.class"), b"\xca\xfe").unwrap();
+        std::fs::write(root.join("tools/core.clj"), "(ns tools.core)").unwrap();
+        let provide = directory_provider(vec![std::env::temp_dir().join("missing"), root.clone()]);
+
+        assert_eq!(
+            provide(SideloadKind::Class, "tools.impl.Helper").as_deref(),
+            Some(&b"\xca\xfe"[..])
+        );
+        assert_eq!(
+            provide(SideloadKind::Resource, "tools/core.clj").as_deref(),
+            Some(&b"(ns tools.core)"[..])
+        );
+        assert_eq!(provide(SideloadKind::Resource, "tools/other.clj"), None);
+        assert_eq!(provide(SideloadKind::Resource, "../etc/passwd"), None);
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
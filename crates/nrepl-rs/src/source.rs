@@ -0,0 +1,653 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Reading the `ns` form of a Clojure or ClojureScript source file
+//!
+//! Loading a buffer or evaluating a form from it needs the namespace the file
+//! declares, and completion and navigation want its aliases. [`parse_ns`]
+//! finds the `(ns ...)` form and reads its name, docstring, metadata and
+//! requires, so an editor can drive [`Worker::eval_in_ns`] and `load-file`
+//! without a Clojure reader of its own.
+//!
+//! The reader underneath knows enough Clojure syntax to skip everything that
+//! is not the `ns` form: strings, characters, comments, `#_` discards,
+//! metadata, reader conditionals (resolved for a [`Dialect`]) and the other
+//! dispatch macros. It does not read values; numbers, regexes and tagged
+//! literals are only stepped over.
+//!
+//! [`Worker::eval_in_ns`]: crate::worker::Worker::eval_in_ns
+
+use std::ops::Range;
+use std::path::Path;
+
+/// Deepest nesting read, so a pathological file cannot overflow the stack.
+const MAX_DEPTH: usize = 512;
+
+/// Which branch of a reader conditional (`#?(:clj ... :cljs ...)`) is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Clojure,
+    ClojureScript,
+}
+
+impl Dialect {
+    /// The dialect a file is read as: ClojureScript for `.cljs`, Clojure for
+    /// anything else, `.cljc` included.
+    #[must_use]
+    pub fn from_path(path: &Path) -> Self {
+        if path.extension().is_some_and(|ext| ext == "cljs") {
+            Dialect::ClojureScript
+        } else {
+            Dialect::Clojure
+        }
+    }
+
+    fn feature(self) -> &'static str {
+        match self {
+            Dialect::Clojure => "clj",
+            Dialect::ClojureScript => "cljs",
+        }
+    }
+}
+
+/// A file's `ns` declaration.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NsForm {
+    pub name: String,
+    /// The docstring, or the `:doc` of the name's metadata.
+    pub doc: Option<String>,
+    /// Metadata on the name and the attribute map, in order, as keyword name
+    /// (no `:`) and the value's source text: `^:no-doc` reads as
+    /// `("no-doc", "true")`.
+    pub meta: Vec<(String, String)>,
+    /// From `:require`, `:require-macros` and `:use`, in order.
+    pub requires: Vec<Require>,
+}
+
+impl NsForm {
+    /// The namespace `alias` names in this file.
+    #[must_use]
+    pub fn resolve_alias(&self, alias: &str) -> Option<&str> {
+        self.requires
+            .iter()
+            .find(|r| r.alias.as_deref() == Some(alias))
+            .map(|r| r.ns.as_str())
+    }
+}
+
+/// One required namespace.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Require {
+    /// The namespace, or for a ClojureScript string require (`["react" :as
+    /// r]`) the JavaScript module.
+    pub ns: String,
+    /// `:as` or `:as-alias`.
+    pub alias: Option<String>,
+    /// Names from `:refer` (or `:only` under `:use`).
+    pub refer: Vec<String>,
+    /// `:refer :all`, or a `:use` without `:only`.
+    pub refer_all: bool,
+    /// From `:require-macros`, or a ClojureScript require with
+    /// `:include-macros true`.
+    pub macros: bool,
+}
+
+/// Read the `ns` form of `source`. `None` if the file has none, or cannot be
+/// read as far as its `ns` form (an unbalanced bracket before it, say).
+#[must_use]
+pub fn parse_ns(source: &str, dialect: Dialect) -> Option<NsForm> {
+    let mut reader = Reader::new(source, dialect);
+    let mut forms = Vec::new();
+    loop {
+        forms.clear();
+        if !reader.read_top(&mut forms)? {
+            return None;
+        }
+        if let Some(ns) = forms.iter().find_map(|node| ns_form(node, source)) {
+            return Some(ns);
+        }
+    }
+}
+
+/// A form read from source, with where it was.
+#[derive(Debug)]
+pub(crate) struct Node<'a> {
+    pub(crate) form: Form<'a>,
+    /// Byte range of the form itself, metadata excluded.
+    pub(crate) span: Range<usize>,
+    /// `^...` metadata forms, outermost first.
+    pub(crate) meta: Vec<Node<'a>>,
+}
+
+#[derive(Debug)]
+pub(crate) enum Form<'a> {
+    List(Vec<Node<'a>>),
+    Vector(Vec<Node<'a>>),
+    Map(Vec<Node<'a>>),
+    Symbol(&'a str),
+    /// Without its leading colons.
+    Keyword(&'a str),
+    /// Escapes resolved.
+    Str(String),
+    /// Anything else: numbers, characters, sets, regexes, quoted and tagged
+    /// forms.
+    Other,
+}
+
+/// Reads forms one at a time, resolving reader conditionals for a dialect.
+pub(crate) struct Reader<'a> {
+    src: &'a str,
+    pos: usize,
+    dialect: Dialect,
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ',' | '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';')
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(src: &'a str, dialect: Dialect) -> Self {
+        Reader {
+            src,
+            pos: 0,
+            dialect,
+        }
+    }
+
+    /// Read the next top-level form into `out`: none, one, or several for a
+    /// splicing conditional. `Some(false)` at the end of input, `None` if the
+    /// form cannot be read.
+    pub(crate) fn read_top(&mut self, out: &mut Vec<Node<'a>>) -> Option<bool> {
+        self.skip_ignored(0)?;
+        if self.pos >= self.src.len() {
+            return Some(false);
+        }
+        self.read_into(out, 0)?;
+        Some(true)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.src.get(self.pos + offset..)?.chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.bump() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    /// Skip whitespace, commas, `;` and `#!` comments and `#_` discards.
+    fn skip_ignored(&mut self, depth: usize) -> Option<()> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() || c == ',' => {
+                    self.bump();
+                }
+                Some(';') => self.skip_line(),
+                Some('#') if self.peek_at(1) == Some('!') => self.skip_line(),
+                Some('#') if self.peek_at(1) == Some('_') => {
+                    self.pos += 2;
+                    self.read_one(depth)?;
+                }
+                _ => return Some(()),
+            }
+        }
+    }
+
+    /// Everything up to the next delimiter.
+    fn token(&mut self) -> &'a str {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| !is_delimiter(c)) {
+            self.bump();
+        }
+        &self.src[start..self.pos]
+    }
+
+    /// Read exactly one form, skipping conditionals that read as nothing.
+    fn read_one(&mut self, depth: usize) -> Option<Node<'a>> {
+        let mut out = Vec::new();
+        while out.is_empty() {
+            self.skip_ignored(depth)?;
+            self.read_into(&mut out, depth)?;
+        }
+        out.into_iter().next()
+    }
+
+    /// Read the form at the cursor into `out`.
+    fn read_into(&mut self, out: &mut Vec<Node<'a>>, depth: usize) -> Option<()> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let start = self.pos;
+        let form = match self.bump()? {
+            '(' => Form::List(self.seq(')', depth)?),
+            '[' => Form::Vector(self.seq(']', depth)?),
+            '{' => Form::Map(self.seq('}', depth)?),
+            '"' => Form::Str(self.string()?),
+            ')' | ']' | '}' => return None,
+            '\\' => {
+                // The character itself may be a delimiter: `\(`, `\ `.
+                self.bump()?;
+                self.token();
+                Form::Other
+            }
+            '^' => {
+                let meta = self.read_one(depth + 1)?;
+                let mut target = self.read_one(depth + 1)?;
+                target.meta.insert(0, meta);
+                out.push(target);
+                return Some(());
+            }
+            '\'' | '`' | '@' => {
+                self.read_one(depth + 1)?;
+                Form::Other
+            }
+            '~' => {
+                if self.peek() == Some('@') {
+                    self.bump();
+                }
+                self.read_one(depth + 1)?;
+                Form::Other
+            }
+            '#' => match self.dispatch(out, depth)? {
+                Some(form) => form,
+                None => return Some(()),
+            },
+            _ => {
+                self.pos = start;
+                let token = self.token();
+                if let Some(keyword) = token.strip_prefix(':') {
+                    Form::Keyword(keyword.trim_start_matches(':'))
+                } else if token.starts_with(|c: char| c.is_ascii_digit())
+                    || (token.len() > 1
+                        && token.starts_with(['+', '-'])
+                        && token[1..].starts_with(|c: char| c.is_ascii_digit()))
+                {
+                    Form::Other
+                } else {
+                    Form::Symbol(token)
+                }
+            }
+        };
+        out.push(Node {
+            form,
+            span: start..self.pos,
+            meta: Vec::new(),
+        });
+        Some(())
+    }
+
+    /// The form after a `#`. `Ok(None)` when it has already been pushed to
+    /// `out` (a reader conditional, which may read as zero or several forms).
+    fn dispatch(&mut self, out: &mut Vec<Node<'a>>, depth: usize) -> Option<Option<Form<'a>>> {
+        let form = match self.bump()? {
+            '{' => {
+                self.seq('}', depth)?;
+                Form::Other
+            }
+            '(' => Form::List(self.seq(')', depth)?),
+            '"' => {
+                self.regex()?;
+                Form::Other
+            }
+            '#' => {
+                self.token();
+                Form::Other
+            }
+            '^' => {
+                // Old-style metadata: read it the same way.
+                self.pos -= 1;
+                self.read_into(out, depth)?;
+                return Some(None);
+            }
+            ':' => {
+                // Namespaced map, `#:ns{...}` or `#::{...}`.
+                self.token();
+                if self.bump()? != '{' {
+                    return None;
+                }
+                Form::Map(self.seq('}', depth)?)
+            }
+            '?' => {
+                let splicing = self.peek() == Some('@');
+                if splicing {
+                    self.bump();
+                }
+                self.skip_ignored(depth)?;
+                if self.bump()? != '(' {
+                    return None;
+                }
+                let branches = self.seq(')', depth)?;
+                self.conditional(branches, splicing, out);
+                return Some(None);
+            }
+            '\'' | '=' => {
+                // Var quote or eval reader.
+                self.read_one(depth + 1)?;
+                Form::Other
+            }
+            _ => {
+                // A tagged literal: the tag, then the form.
+                self.pos -= 1;
+                self.token();
+                self.read_one(depth + 1)?;
+                Form::Other
+            }
+        };
+        Some(Some(form))
+    }
+
+    /// Push the branch of `#?(feature form ...)` this dialect reads.
+    fn conditional(&self, branches: Vec<Node<'a>>, splicing: bool, out: &mut Vec<Node<'a>>) {
+        let mut branches = branches.into_iter();
+        while let (Some(feature), Some(form)) = (branches.next(), branches.next()) {
+            let Form::Keyword(feature) = feature.form else {
+                continue;
+            };
+            if feature == self.dialect.feature() || feature == "default" {
+                match form.form {
+                    Form::List(items) | Form::Vector(items) if splicing => out.extend(items),
+                    _ => out.push(form),
+                }
+                return;
+            }
+        }
+    }
+
+    fn seq(&mut self, close: char, depth: usize) -> Option<Vec<Node<'a>>> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_ignored(depth + 1)?;
+            if self.peek()? == close {
+                self.bump();
+                return Some(items);
+            }
+            self.read_into(&mut items, depth + 1)?;
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let mut s = String::new();
+        loop {
+            match self.bump()? {
+                '"' => return Some(s),
+                '\\' => match self.bump()? {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    c => s.push(c),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    /// Step over a regex body, whose backslashes escape nothing but `"`.
+    fn regex(&mut self) -> Option<()> {
+        loop {
+            match self.bump()? {
+                '"' => return Some(()),
+                '\\' => {
+                    self.bump()?;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Read `node` as an `ns` declaration if it is one.
+fn ns_form(node: &Node<'_>, src: &str) -> Option<NsForm> {
+    let Form::List(items) = &node.form else {
+        return None;
+    };
+    let mut items = items.iter();
+    if !matches!(items.next()?.form, Form::Symbol("ns" | "clojure.core/ns")) {
+        return None;
+    }
+    let name_node = items.next()?;
+    let Form::Symbol(name) = name_node.form else {
+        return None;
+    };
+    let mut ns = NsForm {
+        name: name.to_string(),
+        ..NsForm::default()
+    };
+    for meta in &name_node.meta {
+        read_meta(meta, src, &mut ns.meta);
+    }
+    let mut rest = items.peekable();
+    if let Some(Node {
+        form: Form::Str(doc),
+        ..
+    }) = rest.peek()
+    {
+        ns.doc = Some(doc.clone());
+        rest.next();
+    }
+    if let Some(attrs) = rest.next_if(|n| matches!(n.form, Form::Map(_))) {
+        read_meta(attrs, src, &mut ns.meta);
+    }
+    if ns.doc.is_none() {
+        ns.doc = ns
+            .meta
+            .iter()
+            .find(|(k, _)| k == "doc")
+            .and_then(|(_, v)| v.strip_prefix('"')?.strip_suffix('"'))
+            .map(str::to_string);
+    }
+    for clause in rest {
+        let Form::List(clause) = &clause.form else {
+            continue;
+        };
+        let Some((head, specs)) = clause.split_first() else {
+            continue;
+        };
+        let (macros, using) = match head.form {
+            Form::Keyword("require") => (false, false),
+            Form::Keyword("require-macros") => (true, false),
+            Form::Keyword("use") => (false, true),
+            Form::Keyword("use-macros") => (true, true),
+            _ => continue,
+        };
+        for spec in specs {
+            libspec(spec, None, macros, using, &mut ns.requires);
+        }
+    }
+    Some(ns)
+}
+
+/// Add `^:flag`, `^Tag` or `^{...}` metadata, or an attribute map, to `meta`.
+fn read_meta(node: &Node<'_>, src: &str, meta: &mut Vec<(String, String)>) {
+    match &node.form {
+        Form::Keyword(k) => meta.push(((*k).to_string(), "true".to_string())),
+        Form::Symbol(tag) => meta.push(("tag".to_string(), (*tag).to_string())),
+        Form::Str(tag) => meta.push(("tag".to_string(), tag.clone())),
+        Form::Map(entries) => {
+            for pair in entries.chunks_exact(2) {
+                if let Form::Keyword(k) = pair[0].form {
+                    meta.push((k.to_string(), src[pair[1].span.clone()].to_string()));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Read one libspec, or a prefix list of them, into `out`.
+fn libspec(
+    spec: &Node<'_>,
+    prefix: Option<&str>,
+    macros: bool,
+    using: bool,
+    out: &mut Vec<Require>,
+) {
+    let qualify = |name: &str| match prefix {
+        Some(prefix) => format!("{prefix}.{name}"),
+        None => name.to_string(),
+    };
+    match &spec.form {
+        Form::Symbol(name) => out.push(Require {
+            ns: qualify(name),
+            refer_all: using,
+            macros,
+            ..Require::default()
+        }),
+        Form::Str(module) => out.push(Require {
+            ns: module.clone(),
+            macros,
+            ..Require::default()
+        }),
+        Form::List(items) | Form::Vector(items) => {
+            let Some((head, options)) = items.split_first() else {
+                return;
+            };
+            let name = match &head.form {
+                Form::Symbol(name) => qualify(name),
+                Form::Str(module) => module.clone(),
+                _ => return,
+            };
+            // `(prefix lib ...)`, or `[prefix [lib ...] ...]`: a vector is a
+            // libspec only when options, all keyword-led, follow the name.
+            let is_prefix_list = matches!(spec.form, Form::List(_))
+                || options
+                    .first()
+                    .is_some_and(|o| !matches!(o.form, Form::Keyword(_)));
+            if is_prefix_list {
+                for inner in options {
+                    libspec(inner, Some(&name), macros, using, out);
+                }
+                return;
+            }
+            let mut require = Require {
+                ns: name,
+                refer_all: using,
+                macros,
+                ..Require::default()
+            };
+            for pair in options.chunks_exact(2) {
+                let Form::Keyword(key) = pair[0].form else {
+                    continue;
+                };
+                match (key, &pair[1].form) {
+                    ("as" | "as-alias", Form::Symbol(alias)) => {
+                        require.alias = Some((*alias).to_string());
+                    }
+                    ("refer" | "only", Form::Keyword("all")) => require.refer_all = true,
+                    ("refer" | "only", Form::Vector(names) | Form::List(names)) => {
+                        require.refer_all = false;
+                        require
+                            .refer
+                            .extend(names.iter().filter_map(|n| match n.form {
+                                Form::Symbol(s) => Some(s.to_string()),
+                                _ => None,
+                            }));
+                    }
+                    ("include-macros", Form::Symbol("true")) => require.macros = true,
+                    _ => {}
+                }
+            }
+            out.push(require);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ns_reads_requires() {
+        let src = r#"#!/usr/bin/env bb
+;; A (commented) preamble
+#_(ns not.this)
+(ns ^:no-doc app.core
+  "Entry point."
+  {:author "someone"}
+  (:require [clojure.string :as str :refer [join]]
+            [clojure [set :as set] walk]
+            #?(:clj [clojure.java.io :as io] :cljs [cljs.reader :as io])
+            #?@(:cljs [["react" :as react]])
+            app.util)
+  (:use [app.legacy :only [helper]])
+  (:import (java.io File)))
+
+(defn -main [] (println "\(" \) "}"))
+"#;
+        let ns = parse_ns(src, Dialect::Clojure).unwrap();
+        assert_eq!(ns.name, "app.core");
+        assert_eq!(ns.doc.as_deref(), Some("Entry point."));
+        assert_eq!(
+            ns.meta,
+            vec![
+                ("no-doc".to_string(), "true".to_string()),
+                ("author".to_string(), "\"someone\"".to_string()),
+            ]
+        );
+        let names: Vec<(&str, Option<&str>)> = ns
+            .requires
+            .iter()
+            .map(|r| (r.ns.as_str(), r.alias.as_deref()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("clojure.string", Some("str")),
+                ("clojure.set", Some("set")),
+                ("clojure.walk", None),
+                ("clojure.java.io", Some("io")),
+                ("app.util", None),
+                ("app.legacy", None),
+            ]
+        );
+        assert_eq!(ns.requires[0].refer, vec!["join"]);
+        assert_eq!(ns.requires[5].refer, vec!["helper"]);
+        assert!(!ns.requires[5].refer_all);
+        assert_eq!(ns.resolve_alias("io"), Some("clojure.java.io"));
+
+        let cljs = parse_ns(src, Dialect::ClojureScript).unwrap();
+        assert_eq!(cljs.resolve_alias("io"), Some("cljs.reader"));
+        assert_eq!(cljs.resolve_alias("react"), Some("react"));
+    }
+
+    #[test]
+    fn test_parse_ns_without_ns_form() {
+        assert_eq!(parse_ns("(println 1)\n(def x 2)", Dialect::Clojure), None);
+        // Unreadable before the ns form.
+        assert_eq!(parse_ns("(foo]\n(ns a.b)", Dialect::Clojure), None);
+        // A broken buffer after it does not matter.
+        let ns = parse_ns(
+            "(ns ^{:doc \"Doc.\"} a.b (:require-macros [a.m :as m]))\n(defn f [",
+            Dialect::ClojureScript,
+        )
+        .unwrap();
+        assert_eq!(ns.doc.as_deref(), Some("Doc."));
+        assert!(ns.requires[0].macros);
+        assert_eq!(
+            Dialect::from_path(Path::new("src/a/b.cljs")),
+            Dialect::ClojureScript
+        );
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Browsing clojure.spec registries with cider-nrepl's spec ops
+//!
+//! `spec-list` names the registered specs, `spec-form` describes one, and
+//! `spec-example` generates a value that conforms to it. cider-nrepl sends a
+//! spec's form as nested lists whose leaves are the printed symbols and
+//! keywords, read here into a [`SpecForm`]. `info` sends the same shape
+//! for a function with an `fdef`, as [`SymbolInfo::spec`](crate::SymbolInfo::spec).
+
+use crate::message::BencodeValue;
+use std::fmt;
+
+/// A spec's form, such as `(clojure.spec.alpha/cat :x clojure.core/int?)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub enum SpecForm {
+    /// A symbol, keyword or literal, as printed.
+    Atom(String),
+    /// A form made of others: a call such as `(s/keys ...)`, or a vector.
+    List(Vec<SpecForm>),
+}
+
+impl SpecForm {
+    /// The operator of a list form (`clojure.spec.alpha/keys`), or the atom
+    /// itself.
+    #[must_use]
+    pub fn head(&self) -> Option<&str> {
+        match self {
+            SpecForm::Atom(atom) => Some(atom),
+            SpecForm::List(items) => match items.first() {
+                Some(SpecForm::Atom(head)) => Some(head),
+                _ => None,
+            },
+        }
+    }
+
+    /// The spec names (`:ns/name` keywords) the form refers to, in order,
+    /// for following a spec to the ones it is built from.
+    #[must_use]
+    pub fn referenced_specs(&self) -> Vec<&str> {
+        let mut found = Vec::new();
+        self.collect_specs(&mut found);
+        found
+    }
+
+    fn collect_specs<'a>(&'a self, found: &mut Vec<&'a str>) {
+        match self {
+            SpecForm::Atom(atom) if atom.starts_with(':') && atom.contains('/') => {
+                found.push(atom);
+            }
+            SpecForm::Atom(_) => {}
+            SpecForm::List(items) => items.iter().for_each(|item| item.collect_specs(found)),
+        }
+    }
+
+    pub(crate) fn from_bencode(value: BencodeValue) -> Self {
+        match value {
+            BencodeValue::List(items) => {
+                SpecForm::List(items.into_iter().map(Self::from_bencode).collect())
+            }
+            // A map in a form, flattened to its keys and values.
+            BencodeValue::Dict(entries) => SpecForm::List(
+                entries
+                    .into_iter()
+                    .flat_map(|(k, v)| [SpecForm::Atom(k), Self::from_bencode(v)])
+                    .collect(),
+            ),
+            other => SpecForm::Atom(other.to_string_repr()),
+        }
+    }
+}
+
+impl fmt::Display for SpecForm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpecForm::Atom(atom) => f.write_str(atom),
+            SpecForm::List(items) => {
+                f.write_str("(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::decode_response;
+
+    #[test]
+    fn test_spec_form_reads_nested_lists() {
+        let response = decode_response(
+            b"d2:id1:19:spec-forml23:clojure.spec.alpha/keys4::reql7::user/a7::user/beee\
+              6:statusl4:doneee",
+        )
+        .unwrap()
+        .0;
+        let form = response.spec_form.unwrap();
+        assert_eq!(form.head(), Some("clojure.spec.alpha/keys"));
+        assert_eq!(form.referenced_specs(), [":user/a", ":user/b"]);
+        assert_eq!(
+            form.to_string(),
+            "(clojure.spec.alpha/keys :req (:user/a :user/b))"
+        );
+    }
+}
@@ -0,0 +1,449 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Structured stack traces
+//!
+//! Two sources produce a [`StackTrace`]:
+//!
+//! - the text a Clojure server prints for an eval error, read back by
+//!   [`StackTrace::parse`] (and [`EvalResult::stacktrace`](crate::EvalResult::stacktrace)).
+//!   Both the one-line REPL summary (`Execution error (ArithmeticException) at
+//!   user/eval2 (REPL:1).`) and a JVM `printStackTrace` dump are understood;
+//! - cider-nrepl's `analyze-last-stacktrace` op
+//!   ([`WorkerCommand::AnalyzeStacktrace`](crate::worker::WorkerCommand::AnalyzeStacktrace)),
+//!   which already sends frames as structured data.
+//!
+//! Compiled Clojure frames such as `my_app.core$foo_bar.invoke` are demunged
+//! to `ns` `my-app.core` and `function` `foo-bar`, so an editor can jump to the
+//! definition rather than a JVM class.
+
+use crate::message::{BencodeValue, Response};
+use std::collections::BTreeMap;
+
+/// An exception: its class, message and frames, innermost call first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct StackTrace {
+    /// Exception class, fully qualified when the source gave it that way.
+    pub class: String,
+    pub message: String,
+    pub frames: Vec<Frame>,
+    /// The exception this one wraps (`Caused by:`), if known.
+    pub cause: Option<Box<StackTrace>>,
+}
+
+/// One stack frame. Only `name` is always present.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct Frame {
+    /// The frame as the source named it, e.g. `clojure.lang.Numbers.divide`
+    /// or `user/eval2`.
+    pub name: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// Clojure namespace, for frames compiled from Clojure.
+    pub ns: Option<String>,
+    /// Clojure function name within `ns`.
+    pub function: Option<String>,
+}
+
+impl StackTrace {
+    /// Read an exception out of printed error text. `None` if the text holds
+    /// neither a REPL error summary nor a JVM stack trace.
+    #[must_use]
+    pub fn parse(text: &str) -> Option<Self> {
+        let lines: Vec<&str> = text.lines().collect();
+        let start = lines.iter().position(|l| is_header(l))?;
+        let lines = &lines[start..];
+        if let Some(summary) = parse_summary(lines) {
+            return Some(summary);
+        }
+        parse_jvm(lines)
+    }
+
+    /// Build a trace from an `analyze-last-stacktrace` exchange: one response
+    /// per exception in the cause chain, outermost first. `None` if none of the
+    /// responses describes an exception (there was no last error).
+    pub(crate) fn from_cider(responses: &[Response]) -> Option<Self> {
+        responses
+            .iter()
+            .filter_map(|r| {
+                let class = r.class.clone()?;
+                Some(StackTrace {
+                    class,
+                    message: r.message.clone().unwrap_or_default(),
+                    frames: r.stacktrace.clone().unwrap_or_default(),
+                    cause: None,
+                })
+            })
+            .rev()
+            .reduce(|cause, mut outer| {
+                outer.cause = Some(Box::new(cause));
+                outer
+            })
+    }
+
+    /// Convert one cause dict from an analysed exception (`class`, `message`,
+    /// `stacktrace`), as cider-nrepl embeds them in other ops' replies.
+    /// `None` without a class.
+    pub(crate) fn from_cause(cause: &BTreeMap<String, BencodeValue>) -> Option<Self> {
+        let text = |key: &str| match cause.get(key) {
+            Some(BencodeValue::String(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let frames = match cause.get("stacktrace") {
+            Some(BencodeValue::List(frames)) => frames
+                .iter()
+                .filter_map(|f| match f {
+                    BencodeValue::Dict(f) => Some(Frame::from_cider(f)),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Some(StackTrace {
+            class: text("class")?,
+            message: text("message").unwrap_or_default(),
+            frames,
+            cause: None,
+        })
+    }
+
+    /// The innermost exception in the cause chain: usually the real problem.
+    #[must_use]
+    pub fn root_cause(&self) -> &StackTrace {
+        let mut trace = self;
+        while let Some(cause) = &trace.cause {
+            trace = cause;
+        }
+        trace
+    }
+}
+
+impl Frame {
+    /// Convert one cider-nrepl frame dict (`name`, `file`, `line`, `ns`, `fn`,
+    /// and more we ignore).
+    pub(crate) fn from_cider(frame: &BTreeMap<String, BencodeValue>) -> Self {
+        let text = |key: &str| match frame.get(key) {
+            Some(BencodeValue::String(s)) if !s.is_empty() => Some(s.clone()),
+            _ => None,
+        };
+        let line = match frame.get("line") {
+            Some(BencodeValue::Int(n)) => u32::try_from(*n).ok(),
+            _ => None,
+        };
+        Frame {
+            name: text("name").unwrap_or_default(),
+            file: text("file"),
+            line,
+            ns: text("ns"),
+            function: text("fn"),
+        }
+    }
+}
+
+/// Does this line start an exception report?
+fn is_header(line: &str) -> bool {
+    summary_kind(line).is_some() || jvm_header(line).is_some()
+}
+
+/// `Execution error`, `Syntax error` and friends: the rest of the line after
+/// `error `.
+fn summary_kind(line: &str) -> Option<&str> {
+    ["Execution error", "Syntax error", "Unexpected error"]
+        .iter()
+        .find_map(|prefix| line.strip_prefix(prefix))
+        .map(str::trim_start)
+}
+
+/// Clojure 1.10+ REPL summary:
+///
+/// ```text
+/// Execution error (ArithmeticException) at user/eval2 (REPL:1).
+/// Divide by zero
+/// ```
+fn parse_summary(lines: &[&str]) -> Option<StackTrace> {
+    let rest = summary_kind(lines[0])?;
+    let rest = rest.strip_suffix('.').unwrap_or(rest);
+
+    let (class, rest) = match rest.strip_prefix('(') {
+        Some(inner) => {
+            let (class, rest) = inner.split_once(')')?;
+            (class.to_string(), rest.trim_start())
+        }
+        None => (String::new(), rest),
+    };
+
+    // What remains is `at user/eval2 (REPL:1)` or `compiling at (REPL:1:1)`.
+    let (before, location) = match rest.rfind(" (").or_else(|| rest.find('(')) {
+        Some(open) if rest.ends_with(')') => (&rest[..open], &rest[open..]),
+        _ => (rest, ""),
+    };
+    let location = location
+        .trim_start_matches([' ', '('])
+        .trim_end_matches(')');
+    let var = before.trim().rsplit(' ').next().filter(|v| v.contains('/'));
+
+    let mut frames = Vec::new();
+    if var.is_some() || !location.is_empty() {
+        let (file, line) = split_location(location);
+        let (ns, function) = match var.and_then(|v| v.split_once('/')) {
+            Some((ns, function)) => (Some(ns.to_string()), Some(function.to_string())),
+            None => (None, None),
+        };
+        frames.push(Frame {
+            name: var.unwrap_or_default().to_string(),
+            file,
+            line,
+            ns,
+            function,
+        });
+    }
+
+    let message: Vec<&str> = lines[1..]
+        .iter()
+        .map(|l| l.trim_end())
+        .take_while(|l| !is_header(l) && !l.trim_start().starts_with("at "))
+        .collect();
+    Some(StackTrace {
+        class,
+        message: message.join("\n").trim().to_string(),
+        frames,
+        cause: None,
+    })
+}
+
+/// `java.lang.ArithmeticException: Divide by zero` -> class and message.
+fn jvm_header(line: &str) -> Option<(&str, &str)> {
+    let line = line
+        .trim()
+        .strip_prefix("Caused by: ")
+        .unwrap_or(line.trim());
+    let (class, message) = line.split_once(": ").unwrap_or((line, ""));
+    let simple = class.rsplit('.').next()?;
+    let looks_like_class = class.contains('.')
+        && !class.contains(char::is_whitespace)
+        && (simple.ends_with("Exception") || simple.ends_with("Error") || simple.ends_with("Info"));
+    looks_like_class.then_some((class, message))
+}
+
+/// A `printStackTrace` dump, following `Caused by:` sections into the chain.
+fn parse_jvm(lines: &[&str]) -> Option<StackTrace> {
+    let (class, message) = jvm_header(lines[0])?;
+    let mut trace = StackTrace {
+        class: class.to_string(),
+        message: message.to_string(),
+        ..StackTrace::default()
+    };
+    for (i, line) in lines.iter().enumerate().skip(1) {
+        let line = line.trim();
+        if let Some(frame) = line.strip_prefix("at ") {
+            trace.frames.push(parse_jvm_frame(frame));
+        } else if line.starts_with("Caused by: ") {
+            trace.cause = parse_jvm(&lines[i..]).map(Box::new);
+            break;
+        }
+    }
+    Some(trace)
+}
+
+/// `clojure.lang.Numbers.divide(Numbers.java:190)`
+fn parse_jvm_frame(text: &str) -> Frame {
+    let (name, location) = match text.split_once('(') {
+        Some((name, location)) => (name, location.trim_end_matches(')')),
+        None => (text, ""),
+    };
+    let (file, line) = split_location(location);
+    let (ns, function) = clojure_names(name);
+    Frame {
+        name: name.to_string(),
+        file,
+        line,
+        ns,
+        function,
+    }
+}
+
+/// `core.clj:10` or `REPL:1:5` -> file and line. Placeholders such as
+/// `Native Method` give neither.
+fn split_location(location: &str) -> (Option<String>, Option<u32>) {
+    let mut parts = location.split(':');
+    let file = parts.next().filter(|f| !f.is_empty() && !f.contains(' '));
+    let line = parts.next().and_then(|l| l.parse().ok());
+    (file.map(str::to_string), line)
+}
+
+/// `my_app.core$foo_bar$fn__123.invoke` -> `my-app.core`, `foo-bar`.
+fn clojure_names(name: &str) -> (Option<String>, Option<String>) {
+    // Drop the method; the class is what Clojure munged.
+    let class = name.rsplit_once('.').map_or(name, |(class, _)| class);
+    let Some((ns, rest)) = class.split_once('$') else {
+        return (None, None);
+    };
+    let function = rest.split('$').next().unwrap_or(rest);
+    (Some(demunge(ns)), Some(demunge(function)))
+}
+
+/// Undo Clojure's name munging (`_QMARK_` -> `?`, `_` -> `-`, ...).
+fn demunge(name: &str) -> String {
+    const TOKENS: [(&str, char); 24] = [
+        ("COLON", ':'),
+        ("PLUS", '+'),
+        ("GT", '>'),
+        ("LT", '<'),
+        ("EQ", '='),
+        ("TILDE", '~'),
+        ("BANG", '!'),
+        ("CIRCA", '@'),
+        ("SHARP", '#'),
+        ("SINGLEQUOTE", '\''),
+        ("DOUBLEQUOTE", '"'),
+        ("PERCENT", '%'),
+        ("CARET", '^'),
+        ("AMPERSAND", '&'),
+        ("STAR", '*'),
+        ("BAR", '|'),
+        ("LBRACE", '{'),
+        ("RBRACE", '}'),
+        ("LBRACK", '['),
+        ("RBRACK", ']'),
+        ("SLASH", '/'),
+        ("BSLASH", '\\'),
+        ("QMARK", '?'),
+        ("DOT", '.'),
+    ];
+    let mut out = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(underscore) = rest.find('_') {
+        out.push_str(&rest[..underscore]);
+        rest = &rest[underscore..];
+        let token = TOKENS.iter().find(|(token, _)| {
+            rest[1..]
+                .strip_prefix(token)
+                .is_some_and(|after| after.starts_with('_'))
+        });
+        match token {
+            Some((token, c)) => {
+                out.push(*c);
+                rest = &rest[token.len() + 2..];
+            }
+            None => {
+                out.push('-');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_repl_summary() {
+        let trace = StackTrace::parse(
+            "Execution error (ArithmeticException) at user/eval2 (REPL:1).\nDivide by zero\n",
+        )
+        .unwrap();
+        assert_eq!(trace.class, "ArithmeticException");
+        assert_eq!(trace.message, "Divide by zero");
+        assert_eq!(
+            trace.frames,
+            [Frame {
+                name: "user/eval2".into(),
+                file: Some("REPL".into()),
+                line: Some(1),
+                ns: Some("user".into()),
+                function: Some("eval2".into()),
+            }]
+        );
+
+        let syntax = StackTrace::parse(
+            "Syntax error compiling at (src/app/core.clj:3:1).\nUnable to resolve symbol: x in this context\n",
+        )
+        .unwrap();
+        assert_eq!(syntax.class, "");
+        assert_eq!(syntax.frames[0].file.as_deref(), Some("src/app/core.clj"));
+        assert_eq!(syntax.frames[0].line, Some(3));
+        assert!(syntax.message.starts_with("Unable to resolve symbol"));
+    }
+
+    #[test]
+    fn test_parse_jvm_trace_with_cause() {
+        let text = "\
+clojure.lang.ExceptionInfo: boom {:a 1}
+\tat my_app.core$valid_QMARK_$fn__12.invoke(core.clj:10)
+\tat clojure.lang.AFn.run(AFn.java:22)
+\tat java.lang.Thread.run(Native Method)
+Caused by: java.lang.ArithmeticException: Divide by zero
+\tat clojure.lang.Numbers.divide(Numbers.java:190)
+\t... 3 more
+";
+        let trace = StackTrace::parse(text).unwrap();
+        assert_eq!(trace.class, "clojure.lang.ExceptionInfo");
+        assert_eq!(trace.message, "boom {:a 1}");
+        assert_eq!(trace.frames.len(), 3);
+        assert_eq!(trace.frames[0].ns.as_deref(), Some("my-app.core"));
+        assert_eq!(trace.frames[0].function.as_deref(), Some("valid?"));
+        assert_eq!(trace.frames[0].line, Some(10));
+        assert_eq!(trace.frames[2].file, None);
+
+        let root = trace.root_cause();
+        assert_eq!(root.class, "java.lang.ArithmeticException");
+        assert_eq!(root.frames[0].name, "clojure.lang.Numbers.divide");
+        assert_eq!(root.frames[0].ns, None);
+    }
+
+    #[test]
+    fn test_parse_rejects_plain_text() {
+        assert_eq!(
+            StackTrace::parse("WARNING: foo already refers to bar\n"),
+            None
+        );
+        assert_eq!(StackTrace::parse(""), None);
+    }
+
+    #[test]
+    fn test_from_cider_chains_causes() {
+        let decode = |bytes: &[u8]| crate::codec::decode_response(bytes).unwrap().0;
+        let outer = decode(
+            b"d5:class26:clojure.lang.ExceptionInfo2:id5:req-17:message4:boom10:stacktraceld\
+              4:file8:core.clj2:fn3:foo4:linei10e4:name15:app.core$foo.do2:ns8:app.coreeee",
+        );
+        let inner = decode(b"d5:class19:ArithmeticException2:id5:req-17:message14:Divide by zeroe");
+        let done = decode(b"d2:id5:req-16:statusl4:doneee");
+
+        let trace = StackTrace::from_cider(&[outer, inner, done]).unwrap();
+        assert_eq!(trace.class, "clojure.lang.ExceptionInfo");
+        assert_eq!(
+            trace.frames,
+            [Frame {
+                name: "app.core$foo.do".into(),
+                file: Some("core.clj".into()),
+                line: Some(10),
+                ns: Some("app.core".into()),
+                function: Some("foo".into()),
+            }]
+        );
+        assert_eq!(trace.root_cause().message, "Divide by zero");
+        assert_eq!(StackTrace::from_cider(&[]), None);
+    }
+
+    #[test]
+    fn test_demunge() {
+        assert_eq!(demunge("swap_BANG__STAR_"), "swap!*");
+        assert_eq!(demunge("my_app.core"), "my-app.core");
+        assert_eq!(demunge("__x_"), "--x-");
+    }
+}
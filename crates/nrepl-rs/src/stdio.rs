@@ -0,0 +1,180 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! nREPL over a subprocess's stdin and stdout
+//!
+//! A server started with a `--stdio` style flag speaks bencode on its
+//! standard streams instead of opening a port. The pipes to it are
+//! blocking, so a thread each way moves the bytes between them and the
+//! async reader and writer the worker runs on; when the process exits, its
+//! stdout closes and the worker sees the connection close.
+
+use std::io::{Read, Write};
+use std::pin::Pin;
+use std::sync::mpsc as std_mpsc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+
+/// Chunks read from stdout that may wait for the reader before the pump
+/// thread blocks.
+const READ_AHEAD: usize = 16;
+
+/// Start the threads pumping `stdin` and `stdout`, and return the async
+/// ends of each.
+pub(crate) fn pipes<W, R>(stdin: W, stdout: R) -> (PipeWriter, PipeReader)
+where
+    W: Write + Send + 'static,
+    R: Read + Send + 'static,
+{
+    let (to_stdin, from_writer) = std_mpsc::channel::<Vec<u8>>();
+    std::thread::spawn(move || {
+        let mut stdin = stdin;
+        for chunk in from_writer {
+            if stdin
+                .write_all(&chunk)
+                .and_then(|()| stdin.flush())
+                .is_err()
+            {
+                return;
+            }
+        }
+    });
+
+    let (to_reader, from_stdout) = mpsc::channel::<Vec<u8>>(READ_AHEAD);
+    std::thread::spawn(move || {
+        let mut stdout = stdout;
+        let mut chunk = vec![0u8; 8192];
+        loop {
+            match stdout.read(&mut chunk) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => {
+                    if to_reader.blocking_send(chunk[..n].to_vec()).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+
+    (
+        PipeWriter { to_stdin },
+        PipeReader {
+            from_stdout,
+            pending: Vec::new(),
+            pos: 0,
+        },
+    )
+}
+
+/// The async end of the thread writing to the subprocess's stdin.
+pub(crate) struct PipeWriter {
+    to_stdin: std_mpsc::Sender<Vec<u8>>,
+}
+
+impl AsyncWrite for PipeWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.to_stdin.send(buf.to_vec()) {
+            Ok(()) => Poll::Ready(Ok(buf.len())),
+            Err(_) => Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    // The thread flushes after every chunk it writes.
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The async end of the thread reading the subprocess's stdout.
+pub(crate) struct PipeReader {
+    from_stdout: mpsc::Receiver<Vec<u8>>,
+    /// The chunk being handed out, and how much of it has been.
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+impl AsyncRead for PipeReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.pos == self.pending.len() {
+            match self.from_stdout.poll_recv(cx) {
+                Poll::Ready(Some(chunk)) => {
+                    self.pending = chunk;
+                    self.pos = 0;
+                }
+                // Stdout closed: end of stream.
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let n = buf.remaining().min(self.pending.len() - self.pos);
+        let start = self.pos;
+        buf.put_slice(&self.pending[start..start + n]);
+        self.pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::NReplClient;
+    use crate::ops;
+
+    #[test]
+    fn test_eval_over_pipes() {
+        let (server_stdin, client_stdin) = std::io::pipe().unwrap();
+        let (client_stdout, mut server_stdout) = std::io::pipe().unwrap();
+        let server = std::thread::spawn(move || {
+            use std::io::{Read, Write};
+            let mut server_stdin = server_stdin;
+            let mut request = Vec::new();
+            let mut byte = [0u8; 1];
+            while !request.ends_with(b"4:code7:(+ 1 2)") {
+                server_stdin.read_exact(&mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            server_stdout
+                .write_all(b"d2:id1:75:value1:3ed2:id1:76:statusl4:doneee")
+                .unwrap();
+            // Exiting closes stdout.
+        });
+
+        tokio_test::block_on(async {
+            let client = NReplClient::connect_stdio(client_stdin, client_stdout);
+            let (mut writer, mut reader) = client.into_split();
+            writer
+                .send(&ops::eval_request_with_location(
+                    "7", "sess", "(+ 1 2)", None, None, None,
+                ))
+                .await
+                .unwrap();
+            writer.flush().await.unwrap();
+            let value = reader.next_frame().await.unwrap().decode().unwrap();
+            assert_eq!(value.value.as_deref(), Some("3"));
+            assert_eq!(reader.next_frame().await.unwrap().status(), ["done"]);
+            server.join().unwrap();
+            assert!(reader.next_frame().await.is_err());
+        });
+    }
+}
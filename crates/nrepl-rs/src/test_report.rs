@@ -0,0 +1,380 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Typed results of cider-nrepl's test runner
+//!
+//! The `test`, `test-all` and `retest` ops
+//! ([`WorkerCommand::RunTests`](crate::worker::WorkerCommand::RunTests)) report
+//! every `clojure.test` assertion they ran, grouped by namespace and var, plus
+//! a summary of the counts. [`TestResults`] holds that report; a failing
+//! assertion's `index` is what
+//! [`WorkerCommand::TestStacktrace`](crate::worker::WorkerCommand::TestStacktrace)
+//! needs to fetch the stack trace of an erroring test.
+
+use crate::error::NReplError;
+use crate::message::{BencodeValue, Response};
+use std::collections::BTreeMap;
+
+/// Assertions by namespace, then var, each var's in the order they ran.
+pub type TestsByNamespace = BTreeMap<String, BTreeMap<String, Vec<TestAssertion>>>;
+
+/// A test run: the counts, and each assertion by namespace, then var.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TestResults {
+    pub summary: TestSummary,
+    pub results: TestsByNamespace,
+}
+
+/// Counts for a test run, as `clojure.test` reports them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct TestSummary {
+    pub namespaces: u32,
+    pub vars: u32,
+    /// Test functions run.
+    pub tests: u32,
+    /// Assertions that passed.
+    pub pass: u32,
+    /// Assertions that failed.
+    pub fail: u32,
+    /// Tests that threw outside an assertion, or assertions that threw.
+    pub error: u32,
+}
+
+/// How an assertion came out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub enum TestOutcome {
+    Pass,
+    Fail,
+    /// The test threw. Any type the server reports that is not `pass` or
+    /// `fail` lands here too, so it is never mistaken for a pass.
+    Error,
+}
+
+/// One `is` assertion, or the exception that stopped a test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct TestAssertion {
+    pub outcome: TestOutcome,
+    pub ns: String,
+    pub var: String,
+    /// Position of the assertion within its var's results, for
+    /// [`WorkerCommand::TestStacktrace`](crate::worker::WorkerCommand::TestStacktrace).
+    pub index: u32,
+    /// The assertion's message argument, if it had one.
+    pub message: Option<String>,
+    /// The expected form or value, printed.
+    pub expected: Option<String>,
+    /// The actual value, printed.
+    pub actual: Option<String>,
+    /// For a failed `(is (= expected actual ...))`, how each actual value
+    /// differs from the expected one.
+    pub diffs: Vec<TestDiff>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// The enclosing `testing` contexts, joined.
+    pub context: Option<String>,
+    /// For an error, the exception printed.
+    pub error: Option<String>,
+}
+
+/// One actual value of a failed equality and what separates it from the
+/// expected value, all printed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct TestDiff {
+    pub actual: String,
+    /// What the expected value has that the actual one lacks.
+    pub removed: String,
+    /// What the actual value has that the expected one lacks.
+    pub added: String,
+}
+
+impl TestResults {
+    /// Whether nothing failed or errored. An empty run counts as passed.
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.summary.fail == 0 && self.summary.error == 0
+    }
+
+    /// Every assertion, in namespace, var, then run order.
+    pub fn assertions(&self) -> impl Iterator<Item = &TestAssertion> {
+        self.results.values().flat_map(BTreeMap::values).flatten()
+    }
+
+    /// The assertions that failed or errored.
+    pub fn failures(&self) -> impl Iterator<Item = &TestAssertion> {
+        self.assertions().filter(|a| a.outcome != TestOutcome::Pass)
+    }
+
+    /// Gather a test op's responses. The report normally arrives in one
+    /// response, but results are merged and the last summary kept in case a
+    /// server splits it.
+    pub(crate) fn from_cider(responses: &[Response]) -> Result<Self, NReplError> {
+        if responses
+            .iter()
+            .any(|r| r.status.iter().any(|s| s == "namespace-not-found"))
+        {
+            return Err(NReplError::OperationFailed(
+                "test namespace not found".to_string(),
+            ));
+        }
+
+        let mut report = TestResults::default();
+        let mut summarised = false;
+        for response in responses {
+            if let Some(summary) = response.summary {
+                report.summary = summary;
+                summarised = true;
+            }
+            for (ns, vars) in response.results.iter().flatten() {
+                let entry = report.results.entry(ns.clone()).or_default();
+                for (var, assertions) in vars {
+                    entry
+                        .entry(var.clone())
+                        .or_default()
+                        .extend(assertions.iter().cloned());
+                }
+            }
+        }
+
+        // No report at all means the runner itself failed (a namespace that
+        // would not load, say), which it reports like an eval error.
+        if !summarised && report.results.is_empty() {
+            let err: String = responses.iter().filter_map(|r| r.err.as_deref()).collect();
+            if !err.is_empty() {
+                return Err(NReplError::OperationFailed(err.trim_end().to_string()));
+            }
+            if let Some(ex) = responses.iter().find_map(|r| r.ex.as_deref()) {
+                return Err(NReplError::OperationFailed(ex.to_string()));
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl TestSummary {
+    /// Read cider-nrepl's summary dict (`ns`, `var`, `test`, `pass`, `fail`,
+    /// `error`); missing counts are 0.
+    pub(crate) fn from_cider(summary: &BTreeMap<String, BencodeValue>) -> Self {
+        let count = |key: &str| match summary.get(key) {
+            Some(BencodeValue::Int(n)) => u32::try_from(*n).unwrap_or(0),
+            _ => 0,
+        };
+        TestSummary {
+            namespaces: count("ns"),
+            vars: count("var"),
+            tests: count("test"),
+            pass: count("pass"),
+            fail: count("fail"),
+            error: count("error"),
+        }
+    }
+}
+
+impl TestAssertion {
+    /// Convert one result dict. `ns` and `var` fall back to the keys it was
+    /// filed under.
+    fn from_cider(result: &BTreeMap<String, BencodeValue>, ns: &str, var: &str) -> Self {
+        let text = |key: &str| match result.get(key) {
+            Some(BencodeValue::String(s)) if !s.is_empty() => Some(s.clone()),
+            _ => None,
+        };
+        let number = |key: &str| match result.get(key) {
+            Some(BencodeValue::Int(n)) => u32::try_from(*n).ok(),
+            _ => None,
+        };
+        let outcome = match result.get("type") {
+            Some(BencodeValue::String(t)) if t == "pass" => TestOutcome::Pass,
+            Some(BencodeValue::String(t)) if t == "fail" => TestOutcome::Fail,
+            _ => TestOutcome::Error,
+        };
+        let diffs = match result.get("diffs") {
+            Some(BencodeValue::List(diffs)) => {
+                diffs.iter().filter_map(TestDiff::from_cider).collect()
+            }
+            _ => Vec::new(),
+        };
+        TestAssertion {
+            outcome,
+            ns: text("ns").unwrap_or_else(|| ns.to_string()),
+            var: text("var").unwrap_or_else(|| var.to_string()),
+            index: number("index").unwrap_or(0),
+            message: text("message"),
+            expected: text("expected"),
+            actual: text("actual"),
+            diffs,
+            file: text("file"),
+            line: number("line"),
+            context: text("context"),
+            error: text("error"),
+        }
+    }
+}
+
+impl TestDiff {
+    /// cider-nrepl sends each diff as `[actual [removed added]]`.
+    fn from_cider(diff: &BencodeValue) -> Option<Self> {
+        let BencodeValue::List(pair) = diff else {
+            return None;
+        };
+        let [actual, BencodeValue::List(changes)] = pair.as_slice() else {
+            return None;
+        };
+        let [removed, added] = changes.as_slice() else {
+            return None;
+        };
+        Some(TestDiff {
+            actual: actual.to_string_repr(),
+            removed: removed.to_string_repr(),
+            added: added.to_string_repr(),
+        })
+    }
+}
+
+/// Convert cider-nrepl's `results` (`{ns {var [result ...]}}`), skipping
+/// entries of the wrong shape.
+pub(crate) fn results_from_bencode(value: BencodeValue) -> TestsByNamespace {
+    let BencodeValue::Dict(namespaces) = value else {
+        return TestsByNamespace::new();
+    };
+    namespaces
+        .into_iter()
+        .filter_map(|(ns, vars)| match vars {
+            BencodeValue::Dict(vars) => Some((ns, vars)),
+            _ => None,
+        })
+        .map(|(ns, vars)| {
+            let vars = vars
+                .into_iter()
+                .map(|(var, results)| {
+                    let assertions = match results {
+                        BencodeValue::List(items) => items
+                            .iter()
+                            .filter_map(|item| match item {
+                                BencodeValue::Dict(result) => {
+                                    Some(TestAssertion::from_cider(result, &ns, &var))
+                                }
+                                _ => None,
+                            })
+                            .collect(),
+                        _ => Vec::new(),
+                    };
+                    (var, assertions)
+                })
+                .collect();
+            (ns, vars)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::decode_response;
+
+    fn decode(frame: &str) -> Response {
+        decode_response(frame.as_bytes()).unwrap().0
+    }
+
+    #[test]
+    fn test_from_cider_reads_results_and_summary() {
+        let report = decode(concat!(
+            "d2:id1:1",
+            "7:resultsd8:my.testsd",
+            "6:addingl",
+            "d5:indexi0e2:ns8:my.tests4:type4:pass3:var6:addinge",
+            "d6:actual3:[5]5:diffsll1:5l1:41:5eee5:indexi1e4:linei12e",
+            "7:message4:sums8:expected1:44:type4:fail3:var6:addinge",
+            "e",
+            "8:blows-upl",
+            "d5:error24:java.lang.Exception: boo5:indexi0e4:type5:errore",
+            "e",
+            "ee",
+            "7:summaryd5:errori1e4:faili1e2:nsi1e4:passi1e4:testi2e3:vari2ee",
+            "e"
+        ));
+        let done = decode("d2:id1:16:statusl4:doneee");
+
+        let results = TestResults::from_cider(&[report, done]).unwrap();
+        assert_eq!(
+            results.summary,
+            TestSummary {
+                namespaces: 1,
+                vars: 2,
+                tests: 2,
+                pass: 1,
+                fail: 1,
+                error: 1,
+            }
+        );
+        assert!(!results.passed());
+        assert_eq!(results.assertions().count(), 3);
+
+        let failures: Vec<_> = results.failures().collect();
+        assert_eq!(failures.len(), 2);
+
+        let fail = failures[0];
+        assert_eq!(fail.outcome, TestOutcome::Fail);
+        assert_eq!(fail.ns, "my.tests");
+        assert_eq!(fail.var, "adding");
+        assert_eq!(fail.index, 1);
+        assert_eq!(fail.line, Some(12));
+        assert_eq!(fail.message.as_deref(), Some("sums"));
+        assert_eq!(fail.expected.as_deref(), Some("4"));
+        assert_eq!(fail.actual.as_deref(), Some("[5]"));
+        assert_eq!(
+            fail.diffs,
+            vec![TestDiff {
+                actual: "5".to_string(),
+                removed: "4".to_string(),
+                added: "5".to_string(),
+            }]
+        );
+
+        // The error result carries no ns or var of its own: they come from
+        // where it was filed.
+        let error = failures[1];
+        assert_eq!(error.outcome, TestOutcome::Error);
+        assert_eq!(error.ns, "my.tests");
+        assert_eq!(error.var, "blows-up");
+        assert_eq!(error.error.as_deref(), Some("java.lang.Exception: boo"));
+    }
+
+    #[test]
+    fn test_from_cider_namespace_not_found() {
+        let done = decode("d2:id1:16:statusl19:namespace-not-found4:doneee");
+        assert!(matches!(
+            TestResults::from_cider(&[done]),
+            Err(NReplError::OperationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_cider_runner_error_surfaces_err() {
+        let err = decode("d3:err22:Syntax error compiling2:id1:1e");
+        let done = decode("d2:id1:16:statusl4:doneee");
+        match TestResults::from_cider(&[err, done]) {
+            Err(NReplError::OperationFailed(msg)) => assert_eq!(msg, "Syntax error compiling"),
+            other => panic!("expected OperationFailed, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_from_cider_empty_run_passes() {
+        let report = decode("d2:id1:17:resultsde7:summaryd2:nsi0e4:testi0eee");
+        let results = TestResults::from_cider(&[report]).unwrap();
+        assert!(results.passed());
+        assert_eq!(results.assertions().count(), 0);
+    }
+}
@@ -0,0 +1,846 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! An in-process nREPL server for tests (feature `test-utils`)
+//!
+//! [`MockNReplServer`] listens on a local port and answers each request the
+//! way a script says, so a test can drive a [`Worker`](crate::worker::Worker)
+//! without a JVM. The script is a closure from [`MockRequest`] to the
+//! [`Reply`]s to send, and can send what a real server would not: malformed
+//! frames, frames split across writes, replies out of request order, or
+//! output far past the client's limits. Whatever the script does not
+//! handle can go to [`standard_replies`].
+//!
+//! ```no_run
+//! use nrepl_rs::testing::{Frame, MockNReplServer, Reply, standard_replies};
+//!
+//! let server = MockNReplServer::start(|request| match request.op() {
+//!     "eval" => vec![
+//!         Reply::Frame(Frame::reply_to(request).str("out", "x".repeat(1 << 20))),
+//!         Reply::Frame(Frame::reply_to(request).str("value", "nil").done()),
+//!     ],
+//!     _ => standard_replies(request),
+//! })
+//! .expect("start mock server");
+//! // Connect a worker to `server.address()`...
+//! ```
+//!
+//! [`ChaosTransport`] sits between a client and a server, real or mock, and
+//! breaks the server's frames on a seeded [`ChaosSchedule`]: hanging up
+//! mid-frame, holding frames back, changing bytes, or sending them twice.
+
+use crate::capture::{Direction, parse_line};
+use crate::codec::{FrameScanner, parse_value};
+use crate::message::BencodeValue;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+/// A request as the mock server decoded it.
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    fields: BTreeMap<String, BencodeValue>,
+}
+
+impl MockRequest {
+    /// The request's `op`, or `""` if it had none.
+    #[must_use]
+    pub fn op(&self) -> &str {
+        self.get("op").unwrap_or_default()
+    }
+
+    /// The request's `id`, or `""` if it had none.
+    #[must_use]
+    pub fn id(&self) -> &str {
+        self.get("id").unwrap_or_default()
+    }
+
+    /// The request's `session`, if it named one.
+    #[must_use]
+    pub fn session(&self) -> Option<&str> {
+        self.get("session")
+    }
+
+    /// The string field `key`, if the request had one.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        match self.fields.get(key)? {
+            BencodeValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The integer field `key`, if the request had one.
+    #[must_use]
+    pub fn int(&self, key: &str) -> Option<i64> {
+        match self.fields.get(key)? {
+            BencodeValue::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// The string items of the list field `key`, if the request had one.
+    #[must_use]
+    pub fn list(&self, key: &str) -> Option<Vec<&str>> {
+        match self.fields.get(key)? {
+            BencodeValue::List(items) => Some(
+                items
+                    .iter()
+                    .filter_map(|item| match item {
+                        BencodeValue::String(s) => Some(s.as_str()),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// A response frame, built field by field and bencoded when sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Frame {
+    fields: BTreeMap<String, Field>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Field {
+    Str(String),
+    Int(i64),
+    List(Vec<String>),
+}
+
+impl Frame {
+    /// A frame answering `request`: its `id`, and its `session` if it named
+    /// one.
+    #[must_use]
+    pub fn reply_to(request: &MockRequest) -> Self {
+        let frame = Self::for_id(request.id());
+        match request.session() {
+            Some(session) => frame.str("session", session),
+            None => frame,
+        }
+    }
+
+    /// A frame for request `id`, which need not be the request being
+    /// answered: replying to an earlier or later id is how a script sends
+    /// responses out of order.
+    #[must_use]
+    pub fn for_id(id: &str) -> Self {
+        Self::default().str("id", id)
+    }
+
+    /// Set the string field `key`.
+    #[must_use]
+    pub fn str(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.fields
+            .insert(key.to_string(), Field::Str(value.into()));
+        self
+    }
+
+    /// Set the integer field `key`.
+    #[must_use]
+    pub fn int(mut self, key: &str, value: i64) -> Self {
+        self.fields.insert(key.to_string(), Field::Int(value));
+        self
+    }
+
+    /// Set the list field `key` to `items`.
+    #[must_use]
+    pub fn list(mut self, key: &str, items: &[&str]) -> Self {
+        let items = items.iter().map(|s| (*s).to_string()).collect();
+        self.fields.insert(key.to_string(), Field::List(items));
+        self
+    }
+
+    /// Set `status` to `["done"]`.
+    #[must_use]
+    pub fn done(self) -> Self {
+        self.list("status", &["done"])
+    }
+
+    /// The frame as bencode, keys in order.
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![b'd'];
+        for (key, value) in &self.fields {
+            put_str(&mut out, key);
+            match value {
+                Field::Str(s) => put_str(&mut out, s),
+                Field::Int(i) => out.extend_from_slice(format!("i{i}e").as_bytes()),
+                Field::List(items) => {
+                    out.push(b'l');
+                    for item in items {
+                        put_str(&mut out, item);
+                    }
+                    out.push(b'e');
+                }
+            }
+        }
+        out.push(b'e');
+        out
+    }
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(format!("{}:", s.len()).as_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Something the mock server writes in answer to a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// A well-formed response frame.
+    Frame(Frame),
+    /// Bytes written as given: a malformed or truncated frame, say.
+    Raw(Vec<u8>),
+    /// `bytes` written `piece` bytes at a time, flushed and `pause`d after
+    /// each, so the client sees a frame arrive over several reads.
+    Split {
+        bytes: Vec<u8>,
+        piece: usize,
+        pause: Duration,
+    },
+    /// Write nothing for this long, then carry on with the next reply.
+    Pause(Duration),
+    /// Close the connection without another byte.
+    Hangup,
+}
+
+/// Answers a real server would give: `clone` gets a fresh `new-session`
+/// (`mock-1`, `mock-2`, ...), `ls-sessions` no sessions, `eval` and
+/// `load-file` the value `nil`, and everything else a bare `done`.
+#[must_use]
+pub fn standard_replies(request: &MockRequest) -> Vec<Reply> {
+    static SESSIONS: AtomicUsize = AtomicUsize::new(1);
+    let frame = Frame::reply_to(request);
+    let frame = match request.op() {
+        "clone" => {
+            let n = SESSIONS.fetch_add(1, Ordering::Relaxed);
+            frame.str("new-session", format!("mock-{n}"))
+        }
+        "ls-sessions" => frame.list("sessions", &[]),
+        "eval" | "load-file" => frame.str("value", "nil").str("ns", "user"),
+        _ => frame,
+    };
+    vec![Reply::Frame(frame.done())]
+}
+
+/// Traffic read back from a
+/// [`capture_frames`](crate::worker::WorkerConfig::capture_frames) file, for
+/// [`MockNReplServer::replay`] to answer from.
+///
+/// Each recorded request keeps the responses that carried its id, in the
+/// order they arrived, undecodable (`skip`) ones included.
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    exchanges: Vec<Exchange>,
+}
+
+#[derive(Debug, Clone)]
+struct Exchange {
+    op: String,
+    id: String,
+    responses: Vec<Vec<u8>>,
+}
+
+impl Recording {
+    /// Read the capture file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read.
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    /// Read capture lines. Lines that are not frames, and `partial` and
+    /// `drop` bytes, are passed over.
+    #[must_use]
+    pub fn parse(capture: &str) -> Self {
+        let mut exchanges: Vec<Exchange> = Vec::new();
+        for (direction, frame) in capture.lines().filter_map(parse_line) {
+            let request = MockRequest {
+                fields: match parse_value(&frame, 0) {
+                    Some((BencodeValue::Dict(fields), _)) => fields,
+                    _ => BTreeMap::new(),
+                },
+            };
+            match direction {
+                Direction::Send => exchanges.push(Exchange {
+                    op: request.op().to_string(),
+                    id: request.id().to_string(),
+                    responses: Vec::new(),
+                }),
+                Direction::Recv | Direction::Skip => {
+                    if let Some(exchange) =
+                        exchanges.iter_mut().rev().find(|e| e.id == request.id())
+                    {
+                        exchange.responses.push(frame);
+                    }
+                }
+                Direction::Partial | Direction::Drop => {}
+            }
+        }
+        Self { exchanges }
+    }
+
+    /// How many requests were recorded.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.exchanges.len()
+    }
+
+    /// Whether no request was recorded.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.exchanges.is_empty()
+    }
+}
+
+/// `frame` with its `id` entry changed from `from` to `to`.
+fn with_id(frame: &[u8], from: &str, to: &str) -> Vec<u8> {
+    let old = format!("2:id{}:{from}", from.len()).into_bytes();
+    let Some(at) = frame.windows(old.len()).position(|w| w == old) else {
+        return frame.to_vec();
+    };
+    let mut out = frame[..at].to_vec();
+    out.extend_from_slice(format!("2:id{}:{to}", to.len()).as_bytes());
+    out.extend_from_slice(&frame[at + old.len()..]);
+    out
+}
+
+type Script = Box<dyn FnMut(&MockRequest) -> Vec<Reply> + Send>;
+
+/// A local nREPL server that answers by a script, for tests.
+///
+/// Connections are served one at a time, in the order they arrive, so a
+/// test can reconnect to the same server. Stops listening when dropped.
+pub struct MockNReplServer {
+    address: String,
+    received: Arc<Mutex<Vec<MockRequest>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl MockNReplServer {
+    /// Listen on a free local port and answer every request with `script`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no local port can be bound.
+    pub fn start(
+        script: impl FnMut(&MockRequest) -> Vec<Reply> + Send + 'static,
+    ) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?.to_string();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        {
+            let received = Arc::clone(&received);
+            let stop = Arc::clone(&stop);
+            let mut script: Script = Box::new(script);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    if let Ok(stream) = stream {
+                        serve(stream, &mut script, &received);
+                    }
+                }
+            });
+        }
+        Ok(Self {
+            address,
+            received,
+            stop,
+        })
+    }
+
+    /// A server that answers each request with the responses recorded for
+    /// the next unanswered request of the same op in `recording`, under the
+    /// new request's id. A client making the same calls as the recorded one
+    /// gets the same answers, byte for byte. A request with nothing left to
+    /// answer it fails with an `error` status naming its op.
+    ///
+    /// # Errors
+    ///
+    /// As for [`start`](Self::start).
+    pub fn replay(recording: Recording) -> std::io::Result<Self> {
+        let mut unanswered: Vec<Option<Exchange>> =
+            recording.exchanges.into_iter().map(Some).collect();
+        Self::start(move |request| {
+            let next = unanswered
+                .iter_mut()
+                .find(|e| e.as_ref().is_some_and(|e| e.op == request.op()))
+                .and_then(Option::take);
+            match next {
+                Some(exchange) => exchange
+                    .responses
+                    .iter()
+                    .map(|frame| Reply::Raw(with_id(frame, &exchange.id, request.id())))
+                    .collect(),
+                None => vec![Reply::Frame(
+                    Frame::reply_to(request)
+                        .str(
+                            "err",
+                            format!("no recorded {} left to replay", request.op()),
+                        )
+                        .list("status", &["error", "done"]),
+                )],
+            }
+        })
+    }
+
+    /// A server that only gives [`standard_replies`].
+    ///
+    /// # Errors
+    ///
+    /// As for [`start`](Self::start).
+    pub fn standard() -> std::io::Result<Self> {
+        Self::start(standard_replies)
+    }
+
+    /// The `host:port` to connect to.
+    #[must_use]
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Every request received so far, oldest first.
+    #[must_use]
+    pub fn received(&self) -> Vec<MockRequest> {
+        self.received
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl Drop for MockNReplServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Wake the accept loop so it sees the flag. If a connection is still
+        // being served, the thread ends when the client hangs up.
+        let _ = TcpStream::connect(&self.address);
+    }
+}
+
+/// Answer requests on `stream` until the client hangs up or a script sends
+/// [`Reply::Hangup`].
+fn serve(mut stream: TcpStream, script: &mut Script, received: &Mutex<Vec<MockRequest>>) {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 8192];
+    let mut scanner = FrameScanner::new();
+    loop {
+        let n = match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+        buf.extend_from_slice(&chunk[..n]);
+        loop {
+            let len = match scanner.scan(&buf) {
+                Ok(Some(len)) => len,
+                Ok(None) => break,
+                // The client sent something that is not bencode: give up on
+                // this connection, as a real server would.
+                Err(_) => return,
+            };
+            let fields = match parse_value(&buf[..len], 0) {
+                Some((BencodeValue::Dict(fields), _)) => fields,
+                _ => BTreeMap::new(),
+            };
+            buf.drain(..len);
+            let request = MockRequest { fields };
+            received
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(request.clone());
+            for reply in script(&request) {
+                if !send(&mut stream, reply) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Write one reply; `false` once the connection is done with.
+fn send(stream: &mut TcpStream, reply: Reply) -> bool {
+    let written = match reply {
+        Reply::Frame(frame) => stream.write_all(&frame.encode()),
+        Reply::Raw(bytes) => stream.write_all(&bytes),
+        Reply::Split {
+            bytes,
+            piece,
+            pause,
+        } => bytes.chunks(piece.max(1)).try_for_each(|part| {
+            stream.write_all(part)?;
+            stream.flush()?;
+            thread::sleep(pause);
+            Ok(())
+        }),
+        Reply::Pause(pause) => {
+            thread::sleep(pause);
+            Ok(())
+        }
+        Reply::Hangup => return false,
+    };
+    written.is_ok()
+}
+
+/// A fault [`ChaosTransport`] injects into a frame from the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Forward the first half of the frame, then close the connection.
+    Hangup,
+    /// Hold the frame back this long before forwarding it.
+    Delay(Duration),
+    /// Forward the frame with one byte changed.
+    Corrupt,
+    /// Forward the frame twice.
+    Duplicate,
+}
+
+/// When [`ChaosTransport`] injects which [`Fault`].
+///
+/// Every frame from the server is numbered, from 0 and across connections.
+/// A frame given a fault with [`at`](Self::at) gets that one; any other
+/// draws from a generator seeded by [`seeded`](Self::seeded) against the
+/// odds set below, so a failing run is repeated by reusing its seed.
+#[derive(Debug, Clone)]
+pub struct ChaosSchedule {
+    state: u64,
+    fixed: BTreeMap<usize, Fault>,
+    hangup: f64,
+    delay: f64,
+    max_delay: Duration,
+    corrupt: f64,
+    duplicate: f64,
+}
+
+impl ChaosSchedule {
+    /// A schedule that injects nothing until told to, drawing from `seed`.
+    #[must_use]
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            // Xorshift has a fixed point at zero.
+            state: seed | 1,
+            fixed: BTreeMap::new(),
+            hangup: 0.0,
+            delay: 0.0,
+            max_delay: Duration::ZERO,
+            corrupt: 0.0,
+            duplicate: 0.0,
+        }
+    }
+
+    /// Inject `fault` into frame `frame`, whatever the odds say.
+    #[must_use]
+    pub fn at(mut self, frame: usize, fault: Fault) -> Self {
+        self.fixed.insert(frame, fault);
+        self
+    }
+
+    /// Close the connection mid-frame with probability `odds` per frame.
+    #[must_use]
+    pub fn hangup(mut self, odds: f64) -> Self {
+        self.hangup = odds;
+        self
+    }
+
+    /// Delay a frame, by up to `max`, with probability `odds` per frame.
+    #[must_use]
+    pub fn delay(mut self, odds: f64, max: Duration) -> Self {
+        self.delay = odds;
+        self.max_delay = max;
+        self
+    }
+
+    /// Change a byte of a frame with probability `odds` per frame.
+    #[must_use]
+    pub fn corrupt(mut self, odds: f64) -> Self {
+        self.corrupt = odds;
+        self
+    }
+
+    /// Send a frame twice with probability `odds` per frame.
+    #[must_use]
+    pub fn duplicate(mut self, odds: f64) -> Self {
+        self.duplicate = odds;
+        self
+    }
+
+    /// The next number from the generator (xorshift64).
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A number in `[0, 1)`.
+    #[allow(clippy::cast_precision_loss)]
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// The fault, if any, for frame `frame`. Always draws, so a fixed fault
+    /// does not shift what the later frames get.
+    fn fault_for(&mut self, frame: usize) -> Option<Fault> {
+        let roll = self.unit();
+        let wait = self.next();
+        if let Some(fault) = self.fixed.get(&frame) {
+            return Some(*fault);
+        }
+        let mut edge = self.hangup;
+        if roll < edge {
+            return Some(Fault::Hangup);
+        }
+        edge += self.delay;
+        if roll < edge {
+            let max = u64::try_from(self.max_delay.as_micros()).unwrap_or(u64::MAX);
+            return Some(Fault::Delay(Duration::from_micros(
+                wait % max.saturating_add(1),
+            )));
+        }
+        edge += self.corrupt;
+        if roll < edge {
+            return Some(Fault::Corrupt);
+        }
+        edge += self.duplicate;
+        (roll < edge).then_some(Fault::Duplicate)
+    }
+}
+
+/// A local relay between a client and a server that breaks what the server
+/// sends, for tests of the decoder and of recovery from a lost connection.
+///
+/// Requests go through untouched. Responses are cut into frames and each is
+/// given the [`Fault`] its [`ChaosSchedule`] picks, if any. Every connection
+/// is relayed on its own thread to a fresh connection upstream, so a client
+/// that reconnects after a [`Fault::Hangup`] gets through. Stops listening
+/// when dropped.
+pub struct ChaosTransport {
+    address: String,
+    injected: Arc<Mutex<Vec<(usize, Fault)>>>,
+    stop: Arc<AtomicBool>,
+}
+
+/// What every relay thread of one [`ChaosTransport`] shares.
+struct Chaos {
+    schedule: Mutex<ChaosSchedule>,
+    frames: AtomicUsize,
+    injected: Arc<Mutex<Vec<(usize, Fault)>>>,
+}
+
+impl ChaosTransport {
+    /// Listen on a free local port, relaying to `upstream` (`host:port`)
+    /// with faults from `schedule`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no local port can be bound.
+    pub fn start(upstream: impl Into<String>, schedule: ChaosSchedule) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?.to_string();
+        let upstream = upstream.into();
+        let injected = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let chaos = Arc::new(Chaos {
+            schedule: Mutex::new(schedule),
+            frames: AtomicUsize::new(0),
+            injected: Arc::clone(&injected),
+        });
+        {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                for client in listener.incoming() {
+                    if stop.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    let Ok(client) = client else { continue };
+                    let Ok(server) = TcpStream::connect(&upstream) else {
+                        continue;
+                    };
+                    let chaos = Arc::clone(&chaos);
+                    thread::spawn(move || relay(client, server, &chaos));
+                }
+            });
+        }
+        Ok(Self {
+            address,
+            injected,
+            stop,
+        })
+    }
+
+    /// The `host:port` to connect to.
+    #[must_use]
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Every fault injected so far, with the number of its frame.
+    #[must_use]
+    pub fn injected(&self) -> Vec<(usize, Fault)> {
+        self.injected
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl Drop for ChaosTransport {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = TcpStream::connect(&self.address);
+    }
+}
+
+/// Relay one connection until either side hangs up or a fault does.
+fn relay(mut client: TcpStream, mut server: TcpStream, chaos: &Chaos) {
+    let (Ok(mut to_server), Ok(mut from_client)) = (server.try_clone(), client.try_clone()) else {
+        return;
+    };
+    thread::spawn(move || {
+        let _ = std::io::copy(&mut from_client, &mut to_server);
+        let _ = to_server.shutdown(Shutdown::Write);
+    });
+
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 8192];
+    let mut scanner = FrameScanner::new();
+    'read: loop {
+        let n = match server.read(&mut chunk) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        buf.extend_from_slice(&chunk[..n]);
+        loop {
+            let len = match scanner.scan(&buf) {
+                Ok(Some(len)) => len,
+                Ok(None) => break,
+                // Not bencode: pass it on as it is, the client's problem.
+                Err(_) => buf.len(),
+            };
+            let frame: Vec<u8> = buf.drain(..len).collect();
+            if !forward(&mut client, frame, chaos) {
+                break 'read;
+            }
+        }
+    }
+    let _ = client.shutdown(Shutdown::Both);
+    let _ = server.shutdown(Shutdown::Both);
+}
+
+/// Forward one frame with its fault; `false` once the connection is cut.
+fn forward(client: &mut TcpStream, mut frame: Vec<u8>, chaos: &Chaos) -> bool {
+    let number = chaos.frames.fetch_add(1, Ordering::Relaxed);
+    let (fault, pick) = {
+        let mut schedule = chaos
+            .schedule
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        (schedule.fault_for(number), schedule.next())
+    };
+    if let Some(fault) = fault {
+        chaos
+            .injected
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((number, fault));
+    }
+    let written = match fault {
+        None => client.write_all(&frame),
+        Some(Fault::Hangup) => {
+            let _ = client.write_all(&frame[..frame.len() / 2]);
+            let _ = client.flush();
+            return false;
+        }
+        Some(Fault::Delay(wait)) => {
+            thread::sleep(wait);
+            client.write_all(&frame)
+        }
+        Some(Fault::Corrupt) => {
+            let at = usize::try_from(pick).unwrap_or(usize::MAX) % frame.len();
+            frame[at] ^= u8::try_from(pick >> 56).unwrap_or(0).max(1);
+            client.write_all(&frame)
+        }
+        Some(Fault::Duplicate) => client
+            .write_all(&frame)
+            .and_then(|()| client.write_all(&frame)),
+    };
+    written.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_encodes_fields_in_key_order() {
+        let frame = Frame::for_id("req-1").str("value", "3").done();
+        assert_eq!(
+            String::from_utf8(frame.encode()).unwrap(),
+            "d2:id5:req-16:statusl4:donee5:value1:3e"
+        );
+        let (response, _) = crate::codec::decode_response(&frame.encode()).unwrap();
+        assert_eq!(response.value.as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn test_recording_pairs_responses_with_their_request() {
+        let recording = Recording::parse(concat!(
+            "1.000 send 27 d2:id5:req-12:op8:describee\n",
+            "1.001 send 24 d2:id5:req-22:op5:clonee\n",
+            "1.002 recv 31 d2:id5:req-211:new-session2:s1e\n",
+            "1.003 recv 29 d2:id5:req-16:statusl4:doneee\n",
+            "not a capture line\n",
+        ));
+        assert_eq!(recording.len(), 2);
+        let clone = &recording.exchanges[1];
+        assert_eq!((clone.op.as_str(), clone.responses.len()), ("clone", 1));
+        assert_eq!(
+            with_id(&clone.responses[0], "req-2", "req-10"),
+            b"d2:id6:req-1011:new-session2:s1e"
+        );
+    }
+
+    #[test]
+    fn test_chaos_schedule_repeats_for_a_seed() {
+        let schedule = || {
+            ChaosSchedule::seeded(42)
+                .hangup(0.1)
+                .delay(0.2, Duration::from_millis(5))
+                .corrupt(0.2)
+                .duplicate(0.2)
+                .at(3, Fault::Corrupt)
+        };
+        let faults = |mut s: ChaosSchedule| (0..200).map(|n| s.fault_for(n)).collect::<Vec<_>>();
+        let first = faults(schedule());
+        assert_eq!(first, faults(schedule()));
+        assert_eq!(first[3], Some(Fault::Corrupt));
+        assert!(first.contains(&Some(Fault::Hangup)));
+        assert!(first.contains(&None));
+        assert!(first.iter().all(|f| match f {
+            Some(Fault::Delay(wait)) => *wait <= Duration::from_millis(5),
+            _ => true,
+        }));
+        assert_ne!(first, faults(ChaosSchedule::seeded(43).hangup(0.5)));
+    }
+}
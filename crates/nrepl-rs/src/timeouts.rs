@@ -0,0 +1,96 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! How long blocking callers wait for each kind of op
+//!
+//! The worker's blocking helpers, and the pool, fleet and client built on
+//! it, wait a fixed time for a reply before giving up with
+//! [`NReplError::Timeout`](crate::NReplError::Timeout). [`Timeouts`] holds
+//! those waits by kind of op, set with
+//! [`WorkerConfig::timeouts`](crate::worker::WorkerConfig::timeouts) for a
+//! slow remote server or a loaded CI machine, and read back through
+//! [`Worker::timeouts`](crate::worker::Worker::timeouts). Evals are not
+//! covered: each carries its own timeout.
+
+use std::time::Duration;
+
+/// What blocking callers wait when nothing else is configured.
+pub const DEFAULT_CONTROL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a blocking caller waits for each kind of op to be answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Opening the connection.
+    pub connect: Duration,
+    /// `clone`, for a new session.
+    pub clone: Duration,
+    /// `close`, one session's or all of them together.
+    pub close: Duration,
+    /// `interrupt`.
+    pub interrupt: Duration,
+    /// Every other op that is not an eval: `describe`, `completions`,
+    /// `lookup` and the rest.
+    pub control: Duration,
+}
+
+impl Default for Timeouts {
+    /// [`DEFAULT_CONTROL_TIMEOUT`] for everything.
+    fn default() -> Self {
+        Self::uniform(DEFAULT_CONTROL_TIMEOUT)
+    }
+}
+
+impl Timeouts {
+    /// The same wait for every kind of op.
+    #[must_use]
+    pub fn uniform(wait: Duration) -> Self {
+        Self {
+            connect: wait,
+            clone: wait,
+            close: wait,
+            interrupt: wait,
+            control: wait,
+        }
+    }
+
+    /// The wait for `op`, by its nREPL name (`clone`, `close`, `interrupt`)
+    /// or `connect`; `clone-session` and `close-session` count as `clone`
+    /// and `close`. Any other name gets [`control`](Self::control).
+    #[must_use]
+    pub fn for_op(&self, op: &str) -> Duration {
+        match op {
+            "connect" => self.connect,
+            "clone" | "clone-session" => self.clone,
+            "close" | "close-session" => self.close,
+            "interrupt" => self.interrupt,
+            _ => self.control,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_op_picks_the_kind() {
+        let timeouts = Timeouts {
+            clone: Duration::from_secs(60),
+            interrupt: Duration::from_secs(5),
+            ..Timeouts::default()
+        };
+        assert_eq!(timeouts.for_op("clone-session"), Duration::from_secs(60));
+        assert_eq!(timeouts.for_op("interrupt"), Duration::from_secs(5));
+        assert_eq!(timeouts.for_op("close"), DEFAULT_CONTROL_TIMEOUT);
+        assert_eq!(timeouts.for_op("completions"), DEFAULT_CONTROL_TIMEOUT);
+    }
+}
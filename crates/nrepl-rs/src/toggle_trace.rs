@@ -0,0 +1,124 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Function call tracing with cider-nrepl's `toggle-trace-var` and
+//! `toggle-trace-ns`
+//!
+//! Tracing wraps a var (or every function in a namespace) with tools.trace,
+//! so each call prints its arguments and return value. Nothing comes back on
+//! the toggle itself: the trace lines are printed by whichever eval calls the
+//! traced function, and arrive as that eval's `out` like any other output.
+//! Toggling again restores the original definition.
+
+use crate::error::NReplError;
+use crate::message::Response;
+
+/// Whether a var or namespace is traced after a toggle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceState {
+    Traced,
+    Untraced,
+}
+
+/// The outcome of `toggle-trace-var`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarTrace {
+    /// The var toggled, printed (`#'app.core/handler`).
+    pub var: String,
+    pub state: TraceState,
+}
+
+impl TraceState {
+    fn parse(status: &str) -> Option<Self> {
+        match status {
+            "traced" => Some(TraceState::Traced),
+            "untraced" => Some(TraceState::Untraced),
+            _ => None,
+        }
+    }
+}
+
+impl VarTrace {
+    /// Read a `toggle-trace-var` exchange. A symbol that does not resolve to
+    /// a function (`not-traceable`) is an [`NReplError::OperationFailed`].
+    pub(crate) fn from_cider(responses: &[Response], sym: &str) -> Result<Self, NReplError> {
+        let status = responses.iter().find_map(|r| r.var_status.as_deref());
+        let var = responses
+            .iter()
+            .find_map(|r| r.var_name.clone())
+            .unwrap_or_else(|| sym.to_string());
+        match status.and_then(TraceState::parse) {
+            Some(state) => Ok(VarTrace { var, state }),
+            None => Err(NReplError::OperationFailed(format!(
+                "{var} is not traceable"
+            ))),
+        }
+    }
+}
+
+/// Read a `toggle-trace-ns` exchange. An unknown namespace (`not-found`) is an
+/// [`NReplError::OperationFailed`].
+pub(crate) fn ns_trace_state(responses: &[Response], ns: &str) -> Result<TraceState, NReplError> {
+    responses
+        .iter()
+        .find_map(|r| r.ns_status.as_deref())
+        .and_then(TraceState::parse)
+        .ok_or_else(|| NReplError::OperationFailed(format!("namespace not found: {ns}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(frame: &str) -> Response {
+        crate::codec::decode_response(frame.as_bytes()).unwrap().0
+    }
+
+    #[test]
+    fn test_var_trace_state() {
+        let responses = [
+            decode("d2:id5:req-18:var-name16:#'app.core/hello10:var-status6:tracede"),
+            decode("d2:id5:req-16:statusl4:doneee"),
+        ];
+        assert_eq!(
+            VarTrace::from_cider(&responses, "hello").unwrap(),
+            VarTrace {
+                var: "#'app.core/hello".to_string(),
+                state: TraceState::Traced
+            }
+        );
+
+        let refused = [decode(
+            "d2:id5:req-18:var-name12:#'app.core/x10:var-status13:not-traceablee",
+        )];
+        match VarTrace::from_cider(&refused, "x") {
+            Err(NReplError::OperationFailed(msg)) => {
+                assert_eq!(msg, "#'app.core/x is not traceable");
+            }
+            other => panic!("expected OperationFailed, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_ns_trace_state() {
+        let untraced = [decode("d2:id5:req-19:ns-status8:untracede")];
+        assert_eq!(
+            ns_trace_state(&untraced, "app.core").unwrap(),
+            TraceState::Untraced
+        );
+        let missing = [decode("d2:id5:req-19:ns-status9:not-founde")];
+        assert!(matches!(
+            ns_trace_state(&missing, "no.such"),
+            Err(NReplError::OperationFailed(_))
+        ));
+    }
+}
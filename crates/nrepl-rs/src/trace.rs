@@ -0,0 +1,70 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Diagnostic logging
+//!
+//! With the `tracing` feature, [`event!`] emits a structured `tracing` event
+//! and the writer and worker open spans per request and response, so an
+//! embedding application sees the client's activity in its own subscriber.
+//! Without it, events fall back to plain lines on stderr, printed only when
+//! `NREPL_DEBUG` is set, and the crate takes no extra dependency.
+//!
+//! # Security Warning
+//!
+//! Trace-level events include raw buffer contents in hexadecimal, which may
+//! contain source code being evaluated (secrets, credentials, API keys),
+//! evaluation results and session ids. **Never enable them in production**, and
+//! keep such logs out of version control.
+
+/// Emit a diagnostic event at `DEBUG` or `TRACE` with `key = value` fields,
+/// each formatted with `Display`.
+///
+/// ```text
+/// event!(DEBUG, "wrote request", op = request.op, bytes = encoded.len());
+/// ```
+macro_rules! event {
+    ($level:ident, $msg:literal $(, $field:ident = $value:expr)* $(,)?) => {{
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($field = %$value,)* $msg);
+        #[cfg(not(feature = "tracing"))]
+        if $crate::trace::enabled() {
+            eprintln!(
+                concat!("[nREPL DEBUG] ", $msg $(, " ", stringify!($field), "={}")*),
+                $($value),*
+            );
+        }
+    }};
+}
+
+pub(crate) use event;
+
+/// Whether trace-level events would be recorded, so expensive fields (buffer
+/// dumps) are only built when someone will see them.
+#[cfg(feature = "tracing")]
+pub(crate) fn trace_enabled() -> bool {
+    tracing::enabled!(tracing::Level::TRACE)
+}
+
+/// Whether trace-level events would be recorded, so expensive fields (buffer
+/// dumps) are only built when someone will see them.
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn trace_enabled() -> bool {
+    enabled()
+}
+
+/// Whether stderr logging was switched on via the `NREPL_DEBUG` environment
+/// variable. Read once.
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn enabled() -> bool {
+    static DEBUG: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *DEBUG.get_or_init(|| std::env::var("NREPL_DEBUG").is_ok())
+}
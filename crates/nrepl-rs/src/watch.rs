@@ -0,0 +1,356 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Reloading source files as they change (feature `watch`)
+//!
+//! [`ReloadWatcher`] watches project directories for saved `.clj`, `.cljc`
+//! and `.cljs` files and reloads them through a [`Worker`], without an
+//! editor in the loop: each changed file with `load-file`, or all of them at
+//! once with cider-nrepl's `refresh`, which also reloads their dependents.
+//!
+//! Like the worker, it is polled: call [`poll`](ReloadWatcher::poll) from an
+//! idle loop or a timer. Each call sends the reloads that are due and returns
+//! what happened since the last one as [`ReloadEvent`]s.
+
+use crate::error::NReplError;
+use crate::message::EvalResult;
+use crate::refresh::{RefreshOptions, RefreshReport};
+use crate::session::Session;
+use crate::worker::{EvalOutcome, RequestId, SubmitError, Worker, WorkerCommand};
+use notify::event::ModifyKind;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError, channel};
+use std::time::{Duration, Instant};
+
+/// How long a file must go unchanged before it is reloaded, so an editor's
+/// save (often a write, a rename and a chmod) reloads it once.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// How changed files are reloaded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ReloadStrategy {
+    /// Send each changed file's contents with `load-file`. Works on any
+    /// server, but namespaces that depend on a changed one keep the old
+    /// definitions until they are reloaded too.
+    #[default]
+    LoadFile,
+    /// Run cider-nrepl's `refresh`, which reloads the changed namespaces
+    /// and their dependents in order.
+    Refresh(RefreshOptions),
+}
+
+/// What a [`ReloadWatcher`] watches for and how it reloads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchOptions {
+    /// File extensions, without the dot, that count as source.
+    pub extensions: Vec<String>,
+    /// Quiet period after the last change before reloading.
+    pub debounce: Duration,
+    /// How changed files are reloaded.
+    pub strategy: ReloadStrategy,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            extensions: vec!["clj".into(), "cljc".into(), "cljs".into()],
+            debounce: DEFAULT_DEBOUNCE,
+            strategy: ReloadStrategy::LoadFile,
+        }
+    }
+}
+
+/// Something a [`ReloadWatcher`] did or heard.
+#[derive(Debug)]
+pub enum ReloadEvent {
+    /// These files changed and their reload has been sent.
+    Reloading(Vec<PathBuf>),
+    /// A file's `load-file` finished. It may still have thrown: check
+    /// [`EvalResult::ex`].
+    Loaded { path: PathBuf, result: EvalResult },
+    /// A `refresh` finished; its report says whether every namespace
+    /// reloaded.
+    Refreshed(RefreshReport),
+    /// A reload could not be read, sent or completed. `path` is `None` for a
+    /// `refresh`.
+    Failed {
+        path: Option<PathBuf>,
+        error: NReplError,
+    },
+    /// The filesystem watcher reported a problem, such as a watched
+    /// directory going away.
+    WatchError(String),
+}
+
+/// Watches directories and reloads changed source files through a worker.
+/// Stops watching when dropped.
+pub struct ReloadWatcher {
+    // Held for its lifetime: dropping it stops the watch.
+    _watcher: RecommendedWatcher,
+    changes: Receiver<notify::Result<notify::Event>>,
+    session: Session,
+    options: WatchOptions,
+    changed: BTreeSet<PathBuf>,
+    last_change: Option<Instant>,
+    loading: Vec<(RequestId, PathBuf)>,
+    refreshing: Option<Receiver<Result<RefreshReport, NReplError>>>,
+}
+
+impl ReloadWatcher {
+    /// Watch every directory in `roots`, recursively, reloading changed
+    /// files in `session`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NReplError::OperationFailed`] if the platform watcher cannot
+    /// be started or a root cannot be watched (it does not exist, say).
+    pub fn new<P: AsRef<Path>>(
+        roots: impl IntoIterator<Item = P>,
+        session: Session,
+        options: WatchOptions,
+    ) -> Result<Self, NReplError> {
+        let (tx, changes) = channel();
+        let mut watcher = notify::recommended_watcher(tx)
+            .map_err(|e| NReplError::OperationFailed(format!("starting file watcher: {e}")))?;
+        for root in roots {
+            let root = root.as_ref();
+            watcher.watch(root, RecursiveMode::Recursive).map_err(|e| {
+                NReplError::OperationFailed(format!("watching {}: {e}", root.display()))
+            })?;
+        }
+        Ok(Self {
+            _watcher: watcher,
+            changes,
+            session,
+            options,
+            changed: BTreeSet::new(),
+            last_change: None,
+            loading: Vec::new(),
+            refreshing: None,
+        })
+    }
+
+    /// Whether a reload is waiting out the debounce or still running.
+    #[must_use]
+    pub fn is_busy(&self) -> bool {
+        !self.changed.is_empty() || !self.loading.is_empty() || self.refreshing.is_some()
+    }
+
+    /// Take in file changes, collect finished reloads and send the reloads
+    /// that are due (non-blocking). A `refresh` is not sent while another is
+    /// running; changes made meanwhile wait for the next one.
+    pub fn poll(&mut self, worker: &mut Worker) -> Vec<ReloadEvent> {
+        let mut events = Vec::new();
+        self.take_changes(&mut events);
+        self.collect_finished(worker, &mut events);
+        let settled = self
+            .last_change
+            .is_some_and(|at| at.elapsed() >= self.options.debounce);
+        if settled && !self.changed.is_empty() && self.refreshing.is_none() {
+            self.reload(worker, &mut events);
+        }
+        events
+    }
+
+    fn take_changes(&mut self, events: &mut Vec<ReloadEvent>) {
+        loop {
+            match self.changes.try_recv() {
+                Ok(Ok(event)) => {
+                    if !is_content_change(event.kind) {
+                        continue;
+                    }
+                    for path in event.paths {
+                        if self.is_source(&path) {
+                            self.changed.insert(path);
+                            self.last_change = Some(Instant::now());
+                        }
+                    }
+                }
+                Ok(Err(e)) => events.push(ReloadEvent::WatchError(e.to_string())),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+            }
+        }
+    }
+
+    fn is_source(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| self.options.extensions.iter().any(|e| e == ext))
+    }
+
+    fn collect_finished(&mut self, worker: &mut Worker, events: &mut Vec<ReloadEvent>) {
+        let session = &self.session;
+        self.loading.retain(|(id, path)| {
+            match worker.try_recv_response(*id).map(|r| r.outcome) {
+                Some(EvalOutcome::Done(Ok(result))) => {
+                    events.push(ReloadEvent::Loaded {
+                        path: path.clone(),
+                        result,
+                    });
+                    false
+                }
+                Some(EvalOutcome::Done(Err(error))) => {
+                    events.push(ReloadEvent::Failed {
+                        path: Some(path.clone()),
+                        error,
+                    });
+                    false
+                }
+                // Nobody is there to type: interrupt, and the load finishes
+                // as interrupted.
+                Some(EvalOutcome::NeedInput { .. }) => {
+                    let (reply, _) = channel();
+                    let _ = worker.command_sender().send(WorkerCommand::Interrupt {
+                        op_id: worker.next_id(),
+                        session: session.clone(),
+                        target: *id,
+                        reply,
+                    });
+                    true
+                }
+                None => true,
+            }
+        });
+
+        if let Some(reply) = &self.refreshing {
+            let finished = match reply.try_recv() {
+                Ok(Ok(report)) => Some(ReloadEvent::Refreshed(report)),
+                Ok(Err(error)) => Some(ReloadEvent::Failed { path: None, error }),
+                Err(TryRecvError::Disconnected) => Some(ReloadEvent::Failed {
+                    path: None,
+                    error: disconnected(),
+                }),
+                Err(TryRecvError::Empty) => None,
+            };
+            if let Some(event) = finished {
+                events.push(event);
+                self.refreshing = None;
+            }
+        }
+    }
+
+    fn reload(&mut self, worker: &mut Worker, events: &mut Vec<ReloadEvent>) {
+        let paths: Vec<PathBuf> = std::mem::take(&mut self.changed).into_iter().collect();
+        self.last_change = None;
+        match &self.options.strategy {
+            ReloadStrategy::LoadFile => self.load_files(paths, worker, events),
+            ReloadStrategy::Refresh(options) => {
+                let (reply, rx) = channel();
+                let sent = worker.command_sender().send(WorkerCommand::Refresh {
+                    op_id: worker.next_id(),
+                    session: self.session.clone(),
+                    all: false,
+                    options: options.clone(),
+                    reply,
+                });
+                if sent.is_ok() {
+                    self.refreshing = Some(rx);
+                    events.push(ReloadEvent::Reloading(paths));
+                } else {
+                    events.push(ReloadEvent::Failed {
+                        path: None,
+                        error: disconnected(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn load_files(
+        &mut self,
+        paths: Vec<PathBuf>,
+        worker: &mut Worker,
+        events: &mut Vec<ReloadEvent>,
+    ) {
+        let mut sent = Vec::new();
+        let mut failed = Vec::new();
+        for path in paths {
+            let contents = match std::fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                // Deleted, or renamed away mid-save: the new name has its own
+                // event.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    failed.push(ReloadEvent::Failed {
+                        path: Some(path),
+                        error: e.into(),
+                    });
+                    continue;
+                }
+            };
+            let file_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned());
+            match worker.submit_load_file(
+                self.session.clone(),
+                contents,
+                Some(path.to_string_lossy().into_owned()),
+                file_name,
+            ) {
+                Ok(id) => {
+                    self.loading.push((id, path.clone()));
+                    sent.push(path);
+                }
+                Err(e) => failed.push(ReloadEvent::Failed {
+                    path: Some(path),
+                    error: NReplError::OperationFailed(e.to_string()),
+                }),
+            }
+        }
+        if !sent.is_empty() {
+            events.push(ReloadEvent::Reloading(sent));
+        }
+        events.extend(failed);
+    }
+}
+
+/// Writes and creations; not reads, deletions or attribute changes.
+fn is_content_change(kind: EventKind) -> bool {
+    match kind {
+        EventKind::Create(_) => true,
+        EventKind::Modify(modify) => !matches!(modify, ModifyKind::Metadata(_)),
+        _ => false,
+    }
+}
+
+fn disconnected() -> NReplError {
+    NReplError::OperationFailed(SubmitError::WorkerDisconnected.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, DataChange, MetadataKind, RemoveKind};
+
+    #[test]
+    fn test_only_content_changes_trigger_reloads() {
+        assert!(is_content_change(EventKind::Create(CreateKind::File)));
+        assert!(is_content_change(EventKind::Modify(ModifyKind::Data(
+            DataChange::Content
+        ))));
+        assert!(!is_content_change(EventKind::Modify(ModifyKind::Metadata(
+            MetadataKind::Permissions
+        ))));
+        assert!(!is_content_change(EventKind::Access(AccessKind::Read)));
+        assert!(!is_content_change(EventKind::Remove(RemoveKind::File)));
+    }
+
+    #[test]
+    fn test_missing_root_is_an_error() {
+        let session = Session::new("s1");
+        let missing = std::env::temp_dir().join("nrepl-watch-does-not-exist");
+        let result = ReloadWatcher::new([missing], session, WatchOptions::default());
+        assert!(matches!(result, Err(NReplError::OperationFailed(_))));
+    }
+}
@@ -0,0 +1,447 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! nREPL over a WebSocket (feature `websocket`)
+//!
+//! Some servers are wrapped by a bridge that relays a WebSocket to the
+//! nREPL port, so they can be reached through proxies and firewalls that
+//! pass only HTTP, or from a browser-hosted tool. Over such a bridge each
+//! request goes as one binary message holding its bencode; what comes back
+//! is read as one bencode stream, so the bridge may split or join
+//! responses across messages, and send them as text or binary.
+//!
+//! [`Worker::connect_ws`](crate::worker::Worker::connect_ws) opens the
+//! WebSocket (RFC 6455) and bridges it onto the bencode stream the rest of
+//! the crate speaks, answering pings as it goes.
+//!
+//! Only `ws://` is built in. For a `wss://` endpoint, terminate TLS
+//! locally and connect to that.
+
+use crate::base64;
+use crate::codec::FrameScanner;
+use crate::error::{NReplError, Result};
+use crate::trace::event;
+use std::hash::{BuildHasher, Hasher};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+/// Appended to the client's key before hashing, per RFC 6455.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Buffer between the worker and the bridge, each way.
+const BRIDGE_BUFFER: usize = 64 * 1024;
+
+/// The longest message payload accepted, matching the bencode reader's
+/// limit.
+const MAX_PAYLOAD: u64 = 10 * 1024 * 1024;
+
+/// The longest handshake response head read.
+const MAX_HANDSHAKE: usize = 8 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// Open a WebSocket to `url` and bridge it in a task on the current Tokio
+/// runtime. Returns the bridge's local end.
+pub(crate) async fn connect(url: &str) -> Result<DuplexStream> {
+    let (host, authority, target) = parse_url(url)?;
+    let mut stream = TcpStream::connect(&authority).await?;
+    handshake(&mut stream, &host, &target).await?;
+    let (local, remote) = tokio::io::duplex(BRIDGE_BUFFER);
+    let (from_worker, to_worker) = tokio::io::split(remote);
+    let (socket_read, socket_write) = stream.into_split();
+    tokio::spawn(async move {
+        if let Err(e) = bridge(from_worker, to_worker, socket_read, socket_write).await {
+            event!(DEBUG, "websocket bridge closed", error = e);
+        }
+    });
+    Ok(local)
+}
+
+/// `ws://host[:port]/path` to the `Host` header value, the address to
+/// connect to, and the request target.
+fn parse_url(url: &str) -> Result<(String, String, String)> {
+    let invalid = |why: &str| {
+        NReplError::Connection(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("WebSocket URL {why}: {url}"),
+        ))
+    };
+    let Some(rest) = url.strip_prefix("ws://") else {
+        return Err(if url.starts_with("wss://") {
+            invalid("uses wss, which needs TLS terminated locally")
+        } else {
+            invalid("must start with ws://")
+        });
+    };
+    let (host, target) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return Err(invalid("has no host"));
+    }
+    let authority = if host.ends_with(']') || !host.contains(':') {
+        format!("{host}:80")
+    } else {
+        host.to_string()
+    };
+    Ok((host.to_string(), authority, target.to_string()))
+}
+
+/// Ask for the upgrade and check the server agreed to it.
+async fn handshake(stream: &mut TcpStream, host: &str, target: &str) -> Result<()> {
+    let key = base64::encode(&random_bytes::<16>());
+    let request = format!(
+        "GET {target} HTTP/1.1\r\nHost: {host}\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade\r\nSec-WebSocket-Key: {key}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read the head a byte at a time: frames may follow straight after.
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HANDSHAKE {
+            return Err(ws_error("handshake response head too long"));
+        }
+        head.push(stream.read_u8().await?);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.lines();
+    let status_line = lines.next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("101") {
+        return Err(ws_error(format!(
+            "server refused the upgrade: {status_line}"
+        )));
+    }
+    let expected = accept_key(&key);
+    let accepted = lines
+        .filter_map(|line| line.split_once(':'))
+        .any(|(name, value)| {
+            name.eq_ignore_ascii_case("sec-websocket-accept") && value.trim() == expected
+        });
+    if accepted {
+        Ok(())
+    } else {
+        Err(ws_error(
+            "server's Sec-WebSocket-Accept does not match the key",
+        ))
+    }
+}
+
+fn ws_error(why: impl std::fmt::Display) -> NReplError {
+    NReplError::Connection(std::io::Error::other(format!("websocket: {why}")))
+}
+
+/// The `Sec-WebSocket-Accept` a server must answer `key` with.
+fn accept_key(key: &str) -> String {
+    base64::encode(&sha1(format!("{key}{ACCEPT_GUID}").as_bytes()))
+}
+
+/// Bytes for the handshake key and frame masks. RFC 6455 asks only that
+/// they not be predictable to whoever controls the page or the bridge;
+/// std's per-process hash keys, mixed with the time, are enough for that.
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut out = [0u8; N];
+    for (i, chunk) in out.chunks_mut(8).enumerate() {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_usize(i);
+        if let Ok(now) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+            hasher.write_u128(now.as_nanos());
+        }
+        let bytes = hasher.finish().to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+    out
+}
+
+/// Carry bytes between the worker's end of the duplex and the socket until
+/// either side closes. Dropping `to_worker` on the way out is what tells
+/// the worker the connection closed.
+async fn bridge(
+    mut from_worker: ReadHalf<DuplexStream>,
+    mut to_worker: WriteHalf<DuplexStream>,
+    mut socket_read: OwnedReadHalf,
+    mut socket_write: OwnedWriteHalf,
+) -> Result<()> {
+    let mut outgoing = Vec::new();
+    let mut scanner = FrameScanner::new();
+    let mut incoming = Vec::new();
+    let mut worker_chunk = [0u8; 4096];
+    let mut socket_chunk = [0u8; 4096];
+    loop {
+        tokio::select! {
+            n = from_worker.read(&mut worker_chunk) => {
+                let n = n?;
+                if n == 0 {
+                    write_frame(&mut socket_write, OP_CLOSE, &[]).await?;
+                    return Ok(());
+                }
+                outgoing.extend_from_slice(&worker_chunk[..n]);
+                // One message per request.
+                while let Some(len) = scanner.scan(&outgoing)? {
+                    write_frame(&mut socket_write, OP_BINARY, &outgoing[..len]).await?;
+                    outgoing.drain(..len);
+                }
+            }
+            n = socket_read.read(&mut socket_chunk) => {
+                let n = n?;
+                if n == 0 {
+                    return Ok(());
+                }
+                incoming.extend_from_slice(&socket_chunk[..n]);
+                while let Some((opcode, payload, len)) = read_frame(&incoming)? {
+                    incoming.drain(..len);
+                    match opcode {
+                        OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+                            to_worker.write_all(&payload).await?;
+                        }
+                        OP_PING => write_frame(&mut socket_write, OP_PONG, &payload).await?,
+                        OP_CLOSE => {
+                            write_frame(&mut socket_write, OP_CLOSE, &payload).await?;
+                            return Ok(());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Write one masked, final frame, as a client must.
+async fn write_frame(socket: &mut OwnedWriteHalf, opcode: u8, payload: &[u8]) -> Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(0x80 | len as u8),
+        len @ 126..=0xffff => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    let mask = random_bytes::<4>();
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+    socket.write_all(&frame).await?;
+    Ok(())
+}
+
+/// The frame at the head of `data`, as its opcode, unmasked payload and
+/// length on the wire, or `None` until all of it has arrived.
+fn read_frame(data: &[u8]) -> Result<Option<(u8, Vec<u8>, usize)>> {
+    let [first, second, ..] = *data else {
+        return Ok(None);
+    };
+    let opcode = first & 0x0f;
+    let masked = second & 0x80 != 0;
+    let (len, mut pos) = match second & 0x7f {
+        126 => match data.get(2..4) {
+            Some(ext) => (u64::from(u16::from_be_bytes([ext[0], ext[1]])), 4),
+            None => return Ok(None),
+        },
+        127 => match data
+            .get(2..10)
+            .and_then(|ext| <[u8; 8]>::try_from(ext).ok())
+        {
+            Some(ext) => (u64::from_be_bytes(ext), 10),
+            None => return Ok(None),
+        },
+        len => (u64::from(len), 2),
+    };
+    if len > MAX_PAYLOAD {
+        return Err(ws_error(format!("{len}-byte message over the 10MB limit")));
+    }
+    let mask = if masked {
+        let Some(mask) = data.get(pos..pos + 4) else {
+            return Ok(None);
+        };
+        pos += 4;
+        Some([mask[0], mask[1], mask[2], mask[3]])
+    } else {
+        None
+    };
+    let end = pos + usize::try_from(len).unwrap_or(usize::MAX);
+    let Some(payload) = data.get(pos..end) else {
+        return Ok(None);
+    };
+    let payload = match mask {
+        Some(mask) => payload
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(b, m)| b ^ m)
+            .collect(),
+        None => payload.to_vec(),
+    };
+    Ok(Some((opcode, payload, end)))
+}
+
+/// SHA-1, which the handshake needs and nothing else does.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [
+        0x6745_2301,
+        0xefcd_ab89,
+        0x98ba_dcfe,
+        0x1032_5476,
+        0xc3d2_e1f0,
+    ];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+    let mut out = [0u8; 20];
+    for (chunk, word) in out.chunks_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::NReplClient;
+    use crate::ops;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_accept_key_and_frames() {
+        // The example from RFC 6455, section 1.3.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert!(parse_url("wss://repl.example.com/").is_err());
+        assert_eq!(
+            parse_url("ws://repl.example.com/nrepl").unwrap().1,
+            "repl.example.com:80"
+        );
+        // Masked "Hello", from RFC 6455, section 5.7.
+        let frame = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        assert_eq!(
+            read_frame(&frame).unwrap(),
+            Some((OP_TEXT, b"Hello".to_vec(), frame.len()))
+        );
+        assert_eq!(read_frame(&frame[..6]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_eval_over_websocket() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/nrepl", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut head = Vec::new();
+            let mut byte = [0u8; 1];
+            while !head.ends_with(b"\r\n\r\n") {
+                conn.read_exact(&mut byte).unwrap();
+                head.push(byte[0]);
+            }
+            let head = String::from_utf8(head).unwrap();
+            let key = head
+                .lines()
+                .find_map(|line| line.strip_prefix("Sec-WebSocket-Key: "))
+                .unwrap();
+            let reply = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(key)
+            );
+            conn.write_all(reply.as_bytes()).unwrap();
+
+            let mut received = Vec::new();
+            let mut buf = [0u8; 4096];
+            let request = loop {
+                let n = conn.read(&mut buf).unwrap();
+                received.extend_from_slice(&buf[..n]);
+                if let Some((opcode, payload, _)) = read_frame(&received).unwrap() {
+                    assert_eq!(opcode, OP_BINARY);
+                    break payload;
+                }
+            };
+            // A ping first, then the responses split across two text
+            // messages mid-frame.
+            let responses = b"d2:id1:75:value1:3ed2:id1:76:statusl4:doneee";
+            conn.write_all(&[0x89, 0x00]).unwrap();
+            for part in [&responses[..10], &responses[10..]] {
+                conn.write_all(&[0x81, part.len() as u8]).unwrap();
+                conn.write_all(part).unwrap();
+            }
+            let mut pong = [0u8; 6];
+            conn.read_exact(&mut pong).unwrap();
+            assert_eq!(pong[0], 0x80 | OP_PONG);
+            request
+        });
+
+        tokio_test::block_on(async {
+            let client = NReplClient::connect_ws(&url).await.unwrap();
+            let (mut writer, mut reader) = client.into_split();
+            writer
+                .send(&ops::eval_request_with_location(
+                    "7", "sess", "(+ 1 2)", None, None, None,
+                ))
+                .await
+                .unwrap();
+            writer.flush().await.unwrap();
+            let value = reader.next_frame().await.unwrap().decode().unwrap();
+            assert_eq!(value.value.as_deref(), Some("3"));
+            assert_eq!(reader.next_frame().await.unwrap().status(), ["done"]);
+        });
+        let request = String::from_utf8(server.join().unwrap()).unwrap();
+        assert!(request.contains("4:code7:(+ 1 2)"), "{request}");
+    }
+}
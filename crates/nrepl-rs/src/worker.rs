@@ -27,21 +27,83 @@
 //! serialized through a single `active_eval` + queue; control ops bypass the
 //! queue and are written immediately, so completions/lookup can run during a
 //! long eval. This is what makes `interrupt` actually work.
-
-use crate::connection::{EvalAccumulator, NReplClient, NReplReader, NReplWriter};
+//!
+//! When the deadline fires the eval is failed locally with a timeout. If the
+//! worker was built with
+//! [`interrupt_on_timeout`](crate::worker::WorkerConfig::interrupt_on_timeout),
+//! an `interrupt` for it is also written so the server stops running the
+//! abandoned form.
+//!
+//! With [`heartbeat`](crate::worker::WorkerConfig::heartbeat) the loop also
+//! sends a `describe` probe on a fixed interval. A probe left unanswered for a
+//! whole interval marks the connection
+//! [`Degraded`](crate::worker::ConnectionState::Degraded); its late reply marks
+//! it `Connected` again. Probes are control ops, so they never queue behind an
+//! eval. Watch the state with
+//! [`connection_state`](crate::worker::Worker::connection_state) and
+//! [`on_disconnect`](crate::worker::Worker::on_disconnect).
+//!
+//! A probe still unanswered after
+//! [`half_open_after`](crate::worker::WorkerConfig::half_open_after) means
+//! the socket is half-open: writes go out but nothing comes back. The loop
+//! then fails everything in flight with
+//! [`NReplError::ConnectionLost`] and stops, as it does when a read or write
+//! stalls past its limit. With
+//! [`reconnect_on_loss`](crate::worker::WorkerConfig::reconnect_on_loss) the
+//! worker goes on to connect to the same address again, backing off between
+//! attempts.
+
+use crate::ansi::AnsiPolicy;
+use crate::cache::ResponseCache;
+use crate::cljs::CljsRepl;
+use crate::connection::{
+    AuthField, EvalAccumulator, FlushPolicy, LargeField, LargeFieldTelemetry, NReplClient,
+    OutputSink,
+};
+use crate::debugger::{DebugBreak, DebugCommand};
+use crate::deps::{AddLibsReport, add_libs_form, is_lib_name};
 use crate::error::NReplError;
-use crate::message::{CompletionCandidate, EvalResult, Response, StatusFlags, classify};
+use crate::events::{DebugEvent, DebugEventKind, EventLog};
+use crate::flavor::{ServerInfo, ServerProfile};
+use crate::info::{AproposMatch, ClojureDocs, Eldoc, NsVar, SymbolInfo, XrefVar};
+use crate::inspector::InspectorPage;
+use crate::message::{Code, CompletionCandidate, EvalResult, NsAliases, Response, ServerOutput};
+use crate::metrics::ClientMetrics;
+use crate::middleware::{MiddlewareStack, SwapPlan};
 use crate::ops;
-use crate::session::Session;
-use std::collections::{HashMap, VecDeque};
-use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::refresh::{RefreshOptions, RefreshReport};
+use crate::retry::RetryPolicy;
+use crate::session::{
+    Session, SessionDescriptor, SessionExpiry, SessionInfo, SessionReconciliation, SessionTable,
+};
+use crate::sideloader::{SideloadLookup, SideloadProvider};
+use crate::spec::SpecForm;
+use crate::stacktrace::StackTrace;
+use crate::test_report::TestResults;
+use crate::timeouts::Timeouts;
+use crate::toggle_trace::{TraceState, VarTrace};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
 use std::thread;
 use std::time::Duration;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+use tokio::sync::watch;
 use tokio::time::Instant;
 
+mod completion;
+mod evals;
+mod event_loop;
+mod pending;
+mod sessions;
+mod streams;
+mod tooling;
+
+use completion::is_plain_symbol;
+use event_loop::worker_main;
+
 /// Newtype wrapper for request IDs to prevent mixing with other ID types
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct RequestId(usize);
@@ -72,11 +134,614 @@ const MAX_PENDING_RESPONSES: usize = 1000;
 /// Default eval timeout when a submission does not specify one (60 seconds).
 const DEFAULT_EVAL_TIMEOUT: Duration = Duration::from_mins(1);
 
+/// Per-connection worker behaviour, fixed when the worker is built.
+///
+/// Built with chained setters from [`WorkerConfig::default`], then handed to
+/// [`Worker::with_config`].
+#[derive(Debug, Clone, Default)]
+pub struct WorkerConfig {
+    interrupt_on_timeout: bool,
+    large_fields: LargeFieldTelemetry,
+    heartbeat: Option<Duration>,
+    half_open_after: Option<Duration>,
+    reconnect: bool,
+    metrics: Option<ClientMetrics>,
+    done_timeout: Option<Duration>,
+    events: EventLog,
+    capture_path: Option<PathBuf>,
+    capture_rotate_at: Option<u64>,
+    rich_content: bool,
+    detect_server: bool,
+    in_ns_fallback: bool,
+    server: ServerInfo,
+    sessions: SessionTable,
+    session_expiry: SessionExpiry,
+    retry: RetryPolicy,
+    timeouts: Timeouts,
+    read_chunk: Option<usize>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    flush_policy: FlushPolicy,
+    output: OutputOptions,
+    response_hook: ResponseHookSlot,
+    cache: Option<ResponseCache>,
+    auth: Option<AuthField>,
+    #[cfg(feature = "proxy")]
+    proxy: Option<crate::proxy::Proxy>,
+}
+
+impl WorkerConfig {
+    /// Send an `interrupt` for an eval whose deadline expires.
+    ///
+    /// A timeout only abandons the eval on the client side; without this the
+    /// server keeps running the form (and holding the session) until it
+    /// finishes on its own. Off by default.
+    #[must_use]
+    pub fn interrupt_on_timeout(mut self, enabled: bool) -> Self {
+        self.interrupt_on_timeout = enabled;
+        self
+    }
+
+    /// Report any single response field larger than `bytes` as a
+    /// [`LargeField`] event. Defaults to
+    /// [`DEFAULT_LARGE_FIELD_THRESHOLD`](crate::DEFAULT_LARGE_FIELD_THRESHOLD).
+    #[must_use]
+    pub fn large_field_threshold(mut self, bytes: usize) -> Self {
+        self.large_fields.threshold = bytes;
+        self
+    }
+
+    /// Deliver [`LargeField`] events to `hook` instead of the debug log.
+    /// Runs on the worker thread, so it should be quick.
+    #[must_use]
+    pub fn on_large_field(mut self, hook: impl Fn(&LargeField) + Send + Sync + 'static) -> Self {
+        self.large_fields.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Print [`LargeField`] events to stderr, one line each, whether or not
+    /// debug logging is on. For command-line tools; an embedding that owns
+    /// the terminal (an editor) should use [`on_large_field`](Self::on_large_field).
+    #[must_use]
+    pub fn large_fields_to_stderr(self) -> Self {
+        self.on_large_field(|field| {
+            eprintln!(
+                "[nREPL] large response field: key={} size={} id={}",
+                field.key, field.size, field.request_id
+            );
+        })
+    }
+
+    /// Probe the server with `describe` every `interval` to track
+    /// [`ConnectionState`]. Off by default, in which case the state only
+    /// moves between `Connected` and `Disconnected`.
+    #[must_use]
+    pub fn heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = Some(interval);
+        self
+    }
+
+    /// Declare the connection lost when a heartbeat probe goes `limit`
+    /// without an answer, as on a half-open socket that still takes writes
+    /// but never delivers a reply. Everything in flight then fails with
+    /// [`NReplError::ConnectionLost`] at once instead of each waiting out
+    /// its own timeout. Sends a heartbeat every third of `limit` unless
+    /// [`heartbeat`](Self::heartbeat) sets one. Off by default.
+    #[must_use]
+    pub fn half_open_after(mut self, limit: Duration) -> Self {
+        self.half_open_after = Some(limit);
+        self
+    }
+
+    /// Connect to the same address again when the connection is lost,
+    /// rather than leaving the worker disconnected: closed by the server,
+    /// failed, or found half-open. Attempts back off from one second to
+    /// thirty and go on until one succeeds or the worker shuts down. In
+    /// between, the state is `Disconnected` and requests fail as not
+    /// connected. Requests in flight when it was lost still fail, and
+    /// sessions are gone if the server restarted. Off by default; a stdio
+    /// connection is never remade.
+    #[must_use]
+    pub fn reconnect_on_loss(mut self, enabled: bool) -> Self {
+        self.reconnect = enabled;
+        self
+    }
+
+    /// Count traffic and op latencies, readable through
+    /// [`Worker::metrics`]. Off by default.
+    #[must_use]
+    pub fn collect_metrics(mut self, enabled: bool) -> Self {
+        self.metrics = enabled.then(ClientMetrics::new);
+        self
+    }
+
+    /// Fail a control op (anything but eval/load-file) with
+    /// [`NReplError::ProtocolViolation`] if the server goes this long without
+    /// answering it and it is still not `done`. Counted from the op's last
+    /// response, so an op that keeps streaming replies is never cut off.
+    /// Defaults to [`DEFAULT_DONE_TIMEOUT`].
+    ///
+    /// Evals are left to their own timeout: a multi-form eval legitimately
+    /// sends values long before its `done`.
+    #[must_use]
+    pub fn done_timeout(mut self, limit: Duration) -> Self {
+        self.done_timeout = Some(limit);
+        self
+    }
+
+    /// Keep the last `events` entries in the [`Worker::debug_events`] log.
+    /// Defaults to
+    /// [`DEFAULT_EVENT_LOG_CAPACITY`](crate::DEFAULT_EVENT_LOG_CAPACITY); 0
+    /// turns the log off.
+    #[must_use]
+    pub fn event_log_capacity(mut self, events: usize) -> Self {
+        self.events = EventLog::new(events);
+        self
+    }
+
+    /// Append every raw frame sent and received to the file at `path`, with a
+    /// timestamp and direction, for diagnosing codec and protocol errors. The
+    /// file is opened when the worker connects; if it cannot be, the connect
+    /// fails. Off by default. The line format is described in the crate docs
+    /// under "Frame Capture".
+    ///
+    /// Frames include evaluated code and its output: treat the file as
+    /// sensitive.
+    #[must_use]
+    pub fn capture_frames(mut self, path: impl Into<PathBuf>) -> Self {
+        self.capture_path = Some(path.into());
+        self
+    }
+
+    /// Rotate the [`capture_frames`](Self::capture_frames) file once it would
+    /// grow past `bytes`: it is renamed to `<path>.1`, replacing any earlier
+    /// one, and a fresh file started. Unbounded by default.
+    #[must_use]
+    pub fn rotate_capture_at(mut self, bytes: u64) -> Self {
+        self.capture_rotate_at = Some(bytes);
+        self
+    }
+
+    /// Ask cider-nrepl's content-type middleware to present eval values it
+    /// can (images, URLs) as MIME content, delivered in
+    /// [`EvalResult::rich_content`]. Off by default; servers without the
+    /// middleware ignore it.
+    #[must_use]
+    pub fn rich_content(mut self, enabled: bool) -> Self {
+        self.rich_content = enabled;
+        self
+    }
+
+    /// Send a `describe` as soon as the connection is up and adapt to the
+    /// server it reveals (see [`ServerProfile`]): completions go to
+    /// `complete` on servers without `completions`, a timed-out eval is only
+    /// interrupted where `interrupt` exists, evals without a timeout get
+    /// the flavor's default, and eval results missing `ns` have it filled
+    /// in. Any `describe` sent later refreshes the profile. Off by default.
+    #[must_use]
+    pub fn detect_server(mut self, enabled: bool) -> Self {
+        self.detect_server = enabled;
+        self
+    }
+
+    /// Close any session that goes `ttl` without a request, on the server
+    /// and in [`Worker::sessions`]. A session with an eval running or
+    /// queued, or a subscription open (output, taps, the debugger, the
+    /// sideloader), is never idle. Off by default.
+    #[must_use]
+    pub fn session_idle_ttl(mut self, ttl: Duration) -> Self {
+        self.session_expiry.ttl = Some(ttl);
+        self
+    }
+
+    /// Call `hook` with each session closed by
+    /// [`session_idle_ttl`](Self::session_idle_ttl), after its `close` is
+    /// sent. Runs on the worker thread, so it should be quick.
+    #[must_use]
+    pub fn on_session_expired(
+        mut self,
+        hook: impl Fn(&SessionInfo) + Send + Sync + 'static,
+    ) -> Self {
+        self.session_expiry.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Have [`Worker::eval_in_ns`] also switch namespace in the code itself,
+    /// with an `in-ns` ahead of the form, for servers that ignore the
+    /// request's `ns`. The switch then outlasts the eval, as an `in-ns`
+    /// typed at the REPL would. Off by default.
+    #[must_use]
+    pub fn in_ns_fallback(mut self, enabled: bool) -> Self {
+        self.in_ns_fallback = enabled;
+        self
+    }
+
+    /// Retry the worker's blocking `describe`, `completions`, `lookup` and
+    /// `ls-sessions` helpers by `policy` when they fail transiently.
+    /// Defaults to [`RetryPolicy::default`]; [`RetryPolicy::never`] turns
+    /// retries off.
+    #[must_use]
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Wait for replies as long as `timeouts` gives each kind of op, in the
+    /// worker's blocking helpers and in the pool, fleet and client built on
+    /// it. Defaults to [`Timeouts::default`], 30 seconds for everything.
+    #[must_use]
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Read up to `bytes` from the socket at a time. Defaults to
+    /// [`DEFAULT_READ_CHUNK`](crate::DEFAULT_READ_CHUNK); a server streaming
+    /// a lot of output is read in fewer system calls with a larger chunk.
+    /// The chunk does not change how large a response may be: that limit
+    /// counts bytes, however many reads they take.
+    #[must_use]
+    pub fn read_chunk_size(mut self, bytes: usize) -> Self {
+        self.read_chunk = Some(bytes);
+        self
+    }
+
+    /// Drop the connection when a response stops arriving part way: once
+    /// some of it is in, each further read must bring bytes within `limit`.
+    /// Quiet between responses, as while an eval runs without printing, is
+    /// not a stall; that is left to the eval's own timeout. Off by default.
+    #[must_use]
+    pub fn read_timeout(mut self, limit: Duration) -> Self {
+        self.read_timeout = Some(limit);
+        self
+    }
+
+    /// Fail a write the server does not take within `limit`, as when its
+    /// receive window stays shut because it stopped reading. The request
+    /// may be half written, so the connection is unusable afterwards and
+    /// every later request fails at once. Off by default, when a stuck
+    /// write waits as long as the op's own timeout.
+    #[must_use]
+    pub fn write_timeout(mut self, limit: Duration) -> Self {
+        self.write_timeout = Some(limit);
+        self
+    }
+
+    /// Write requests by `policy`. Defaults to [`FlushPolicy::Batched`]
+    /// with [`DEFAULT_BATCH_BYTES`](crate::DEFAULT_BATCH_BYTES): requests
+    /// submitted back to back share one write, and a lone request is written
+    /// as soon as the worker picks it up. [`FlushPolicy::Immediate`] writes
+    /// each one on its own.
+    #[must_use]
+    pub fn flush_policy(mut self, policy: FlushPolicy) -> Self {
+        self.flush_policy = policy;
+        self
+    }
+
+    /// Send eval and load-file output past the in-memory limits to `sink`.
+    /// Defaults to [`OutputSink::Memory`], which fails such an eval;
+    /// [`OutputSink::File`] lets a job with verbose logging run to the end.
+    #[must_use]
+    pub fn output_sink(mut self, sink: OutputSink) -> Self {
+        self.output.sink = sink;
+        self
+    }
+
+    /// Merge consecutive output chunks of the same stream into
+    /// line-terminated entries, for an editor that pays per chunk it
+    /// renders. An eval's chunks that continue an unfinished line join that
+    /// line's entry of [`EvalResult::output`] or [`EvalResult::error`]. An
+    /// [`OutSubscribe`](WorkerCommand::OutSubscribe) stream holds text until
+    /// it ends a line, the other stream starts, or it has waited
+    /// `max_latency`. Off by default: each chunk is delivered as the server
+    /// sent it.
+    #[must_use]
+    pub fn coalesce_output(mut self, max_latency: Duration) -> Self {
+        self.output.coalesce = Some(max_latency);
+        self
+    }
+
+    /// Put `out` and `err` text through `policy` before it reaches
+    /// [`EvalResult`] or an [`OutSubscribe`](WorkerCommand::OutSubscribe)
+    /// stream. Defaults to [`AnsiPolicy::Keep`]; an editor buffer that
+    /// cannot show color wants [`AnsiPolicy::Strip`], a terminal UI
+    /// [`AnsiPolicy::Parse`].
+    #[must_use]
+    pub fn ansi(mut self, policy: AnsiPolicy) -> Self {
+        self.output.ansi = policy;
+        self
+    }
+
+    /// Cut eval values longer than `max_bytes` down to that length, so a UI
+    /// is never handed a 5MB printed lazy seq to render. The cut is marked
+    /// in [`EvalResult::truncated`] with the original length, and
+    /// [`EvalResult::fetch_full_value`] still returns the whole value.
+    #[must_use]
+    pub fn truncate_values(mut self, max_bytes: usize) -> Self {
+        self.output.max_value_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Ask the server to stop printing an eval's value after `chars`
+    /// characters (`nrepl.middleware.print/quota`), so a value that would
+    /// print forever, like an infinite lazy seq, still comes back. The
+    /// server marks a cut value with `nrepl.middleware.print/truncated` in
+    /// its status. Unlike [`truncate_values`](Self::truncate_values), what
+    /// is cut is never sent, and cannot be fetched later.
+    #[must_use]
+    pub fn print_quota(mut self, chars: u32) -> Self {
+        self.output.print_quota = Some(i64::from(chars));
+        self
+    }
+
+    /// Call `hook` with each chunk of an eval's or load-file's output as it
+    /// arrives, with the request it belongs to, so a terminal can show a
+    /// long job's progress. The chunks still reach [`EvalResult::output`]
+    /// and [`EvalResult::error`]. Runs on the worker thread, so it should
+    /// be quick.
+    #[must_use]
+    pub fn on_eval_output(
+        mut self,
+        hook: impl Fn(RequestId, &ServerOutput) + Send + Sync + 'static,
+    ) -> Self {
+        self.output.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` with a request's id as soon as its eval or load-file
+    /// response is ready for [`Worker::try_recv_response`], including a
+    /// [`NeedInput`](EvalOutcome::NeedInput) pause. An editor can wake its
+    /// event loop for the request, or fetch just the results that arrived,
+    /// rather than poll every outstanding request on a timer. Runs on the
+    /// worker thread, so it should be quick.
+    #[must_use]
+    pub fn on_response(mut self, hook: impl Fn(RequestId) + Send + Sync + 'static) -> Self {
+        self.response_hook = ResponseHookSlot(Some(Arc::new(hook)));
+        self
+    }
+
+    /// Answer a repeated `completions` or `lookup` in a session from memory
+    /// for up to `ttl`, rather than asking the server again on every
+    /// keystroke. Any eval or load-file in the session drops its entries.
+    /// Read hit counts and invalidate by hand through
+    /// [`Worker::response_cache`]. Off by default.
+    #[must_use]
+    pub fn cache_responses(mut self, ttl: Duration) -> Self {
+        self.cache = Some(ResponseCache::new(ttl));
+        self
+    }
+
+    /// Add `key` with the value `token` to every request, for servers whose
+    /// middleware turns away messages without it (an `auth` key, say).
+    /// The token is never logged: the frame capture records `<redacted>`
+    /// in its place, and the config's `Debug` output hides it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NReplError::Protocol`] if `key` is a field requests set
+    /// themselves, such as `op`, `id` or `session`.
+    pub fn auth_token(
+        mut self,
+        key: impl Into<String>,
+        token: impl Into<String>,
+    ) -> Result<Self, NReplError> {
+        self.auth = Some(AuthField::new(key.into(), token.into())?);
+        Ok(self)
+    }
+
+    /// Connect through `proxy` rather than directly. The server address
+    /// given to [`Worker::connect_blocking`] is passed to the proxy unresolved.
+    #[cfg(feature = "proxy")]
+    #[must_use]
+    pub fn proxy(mut self, proxy: crate::proxy::Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+}
+
+/// Receiver for eval output as it arrives, called on the worker thread; see
+/// [`WorkerConfig::on_eval_output`].
+pub type EvalOutputHook = Arc<dyn Fn(RequestId, &ServerOutput) + Send + Sync>;
+
+/// Receiver for the ids of finished requests, called on the worker thread;
+/// see [`WorkerConfig::on_response`].
+pub type ResponseHook = Arc<dyn Fn(RequestId) + Send + Sync>;
+
+/// The [`WorkerConfig::on_response`] hook, if one is set.
+#[derive(Clone, Default)]
+struct ResponseHookSlot(Option<ResponseHook>);
+
+impl std::fmt::Debug for ResponseHookSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ResponseHookSlot")
+            .field(&self.0.is_some())
+            .finish()
+    }
+}
+
+/// How eval and subscription output is treated, as configured on
+/// [`WorkerConfig`].
+#[derive(Clone, Default)]
+struct OutputOptions {
+    sink: OutputSink,
+    coalesce: Option<Duration>,
+    ansi: AnsiPolicy,
+    max_value_bytes: Option<usize>,
+    print_quota: Option<i64>,
+    hook: Option<EvalOutputHook>,
+}
+
+impl OutputOptions {
+    /// A fresh accumulator for the eval or load-file `request_id`.
+    fn accumulator(&self, request_id: RequestId) -> EvalAccumulator {
+        let mut acc = EvalAccumulator::with_sink(self.sink.clone())
+            .coalesce_lines(self.coalesce.is_some())
+            .ansi(self.ansi);
+        if let Some(hook) = &self.hook {
+            let hook = Arc::clone(hook);
+            acc = acc.tap_output(Box::new(move |chunk| hook(request_id, chunk)));
+        }
+        match self.max_value_bytes {
+            Some(max) => acc.truncate_values(max),
+            None => acc,
+        }
+    }
+}
+
+impl std::fmt::Debug for OutputOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OutputOptions")
+            .field("sink", &self.sink)
+            .field("coalesce", &self.coalesce)
+            .field("ansi", &self.ansi)
+            .field("max_value_bytes", &self.max_value_bytes)
+            .field("print_quota", &self.print_quota)
+            .field("hook", &self.hook.is_some())
+            .finish()
+    }
+}
+
+/// How long a control op may go without a response, and without `done`,
+/// before it is failed as a protocol violation. Below the 30s blocking
+/// callers wait by default (see [`Timeouts`]), so they see the specific error
+/// rather than their own generic timeout.
+pub const DEFAULT_DONE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Liveness of a worker's connection, as seen by [`Worker::connection_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Connected, and the last heartbeat (if any) was answered.
+    Connected,
+    /// The socket is open but a heartbeat probe went unanswered for a full
+    /// interval: the server is busy, paused or unreachable.
+    Degraded,
+    /// Not connected yet, or the connection has closed.
+    Disconnected,
+}
+
+/// Caller-held handle for abandoning in-flight requests without dropping the
+/// connection.
+///
+/// Link a request to a token with [`Worker::cancel_on`]; [`cancel`](Self::cancel)
+/// then retires every linked request the way a timeout does: the caller gets
+/// [`NReplError::Cancelled`] and the request's late responses are discarded,
+/// so the connection stays usable. Cancelling is client-side only; send an
+/// `interrupt` as well if the server should stop work too. One token can cover
+/// any number of requests, on any number of workers.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<CancelInner>,
+}
+
+#[derive(Default)]
+struct CancelInner {
+    cancelled: AtomicBool,
+    /// Linked requests still in flight, with the worker each belongs to,
+    /// keyed by the link that removes them once they finish.
+    targets: Mutex<HashMap<usize, (UnboundedSender<WorkerCommand>, RequestId)>>,
+    next_link: AtomicUsize,
+}
+
+/// A request's place in a [`CancellationToken`]. The worker holds it while
+/// the request is in flight; dropping it unlinks the request, so a token
+/// reused for many requests only keeps the unfinished ones.
+pub struct CancelLink {
+    inner: Weak<CancelInner>,
+    key: usize,
+    target: RequestId,
+}
+
+impl Drop for CancelLink {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.upgrade() {
+            inner
+                .targets
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&self.key);
+        }
+    }
+}
+
+impl CancellationToken {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every linked request. Idempotent.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let mut targets = self
+            .inner
+            .targets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (_, (command_tx, target)) in targets.drain() {
+            let _ = command_tx.send(WorkerCommand::Cancel { target });
+        }
+    }
+
+    /// Whether [`cancel`](Self::cancel) has been called.
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Link `target` to this token; an already-cancelled token cancels it now.
+    /// The worker is handed the [`CancelLink`] to drop once `target` is done.
+    fn link(&self, command_tx: UnboundedSender<WorkerCommand>, target: RequestId) {
+        // Checked under the lock so a concurrent `cancel` either drains this
+        // entry or is seen here - never neither.
+        let mut targets = self
+            .inner
+            .targets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if self.is_cancelled() {
+            let _ = command_tx.send(WorkerCommand::Cancel { target });
+            return;
+        }
+        let key = self.inner.next_link.fetch_add(1, Ordering::Relaxed);
+        targets.insert(key, (command_tx.clone(), target));
+        drop(targets);
+        let link = CancelLink {
+            inner: Arc::downgrade(&self.inner),
+            key,
+            target,
+        };
+        // A worker already gone drops the link with the command.
+        let _ = command_tx.send(WorkerCommand::Watch(link));
+    }
+
+    /// How many linked requests are still in flight.
+    #[must_use]
+    pub fn linked(&self) -> usize {
+        self.inner
+            .targets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+}
+
+impl std::fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish_non_exhaustive()
+    }
+}
+
 /// Error type for submission operations (eval/load-file)
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SubmitError {
     /// Worker thread has died or disconnected
     WorkerDisconnected,
+    /// A namespace given to [`Worker::eval_in_ns`] that is not a plain symbol
+    InvalidNamespace(String),
     /// Request ID overflow (billions of requests processed)
     RequestIdOverflow,
 }
@@ -87,6 +752,7 @@ impl std::fmt::Display for SubmitError {
             SubmitError::WorkerDisconnected => {
                 write!(f, "Worker thread has died or disconnected")
             }
+            SubmitError::InvalidNamespace(ns) => write!(f, "Not a namespace name: {ns}"),
             SubmitError::RequestIdOverflow => {
                 write!(
                     f,
@@ -103,23 +769,88 @@ impl std::error::Error for SubmitError {}
 pub struct EvalRequest {
     pub request_id: RequestId,
     pub session: Session,
-    pub code: String,
+    pub code: Code,
     pub timeout: Option<Duration>,
     pub file: Option<String>,
     pub line: Option<i64>,
     pub column: Option<i64>,
+    /// Namespace to evaluate in, sent as the request's `ns`.
+    pub ns: Option<String>,
+    /// Extra request fields; see [`EvalOptions::tags`].
+    pub tags: BTreeMap<String, String>,
 }
 
 /// Request to load a file
 pub struct LoadFileRequest {
     pub request_id: RequestId,
     pub session: Session,
-    pub file_contents: String,
+    pub file_contents: Code,
     pub file_path: Option<String>,
     pub file_name: Option<String>,
 }
 
+/// How [`Worker::eval_batch`] runs its forms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchOptions {
+    /// Time allowed for the whole batch. Forms still running or waiting
+    /// when it runs out fail with [`NReplError::Timeout`]; the running one is
+    /// interrupted.
+    pub timeout: Duration,
+    /// Stop at the first form that fails, throws or is interrupted. Forms
+    /// are then sent one at a time, and those never sent come back as
+    /// [`NReplError::OperationFailed`]. Otherwise every form is sent at
+    /// once and they run back to back.
+    pub fail_fast: bool,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            timeout: DEFAULT_EVAL_TIMEOUT,
+            fail_fast: false,
+        }
+    }
+}
+
+/// Everything about an eval besides its session and code, for
+/// [`Worker::submit_eval_with`]. The default is what
+/// [`submit_eval`](Worker::submit_eval) sends with every argument `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvalOptions {
+    /// Time allowed for the eval; `None` for the server profile's default.
+    pub timeout: Option<Duration>,
+    /// Source location reported for the code, as in
+    /// [`submit_eval`](Worker::submit_eval).
+    pub file: Option<String>,
+    pub line: Option<i64>,
+    pub column: Option<i64>,
+    /// Namespace to evaluate in, as in [`eval_in_ns`](Worker::eval_in_ns).
+    pub ns: Option<String>,
+    /// Extra fields to send on the request, such as a trace id or an editor
+    /// buffer name. nREPL ignores fields it does not know, and servers or
+    /// middleware that echo them back on their responses have them
+    /// collected in [`EvalResult::tags`]. A tag never replaces a field the
+    /// eval sets itself (`op`, `id`, `code` and so on).
+    pub tags: BTreeMap<String, String>,
+}
+
+/// How long [`Worker::add_libs`] waits: libraries not yet in the local
+/// Maven repository are downloaded first.
+const ADD_LIBS_TIMEOUT: Duration = Duration::from_mins(5);
+
+/// How often [`Worker::eval_batch`] polls for results.
+const BATCH_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How long [`Worker::eval_batch`] waits for the worker to confirm a
+/// cancellation, so the cancelled result does not linger in the buffer.
+const BATCH_CANCEL_GRACE: Duration = Duration::from_secs(1);
+
 /// Outcome of an eval/load-file delivered to the polling main thread.
+///
+/// `Done` is the common variant and `NeedInput` a rare one; boxing the
+/// result would add an allocation to every eval and change what callers
+/// match on, so we accept the size difference.
+#[allow(clippy::large_enum_variant)]
 pub enum EvalOutcome {
     /// The evaluation finished (successfully or with an error/timeout).
     Done(Result<EvalResult, NReplError>),
@@ -140,9 +871,34 @@ pub struct EvalResponse {
     pub outcome: EvalOutcome,
 }
 
+/// Where the worker thread sends [`EvalResponse`]s: the channel
+/// [`Worker::try_recv_response`] reads, then the
+/// [`on_response`](WorkerConfig::on_response) hook, so the hook never
+/// announces a response that is not there yet.
+#[derive(Clone)]
+struct ResponseSender {
+    tx: Sender<EvalResponse>,
+    hook: Option<ResponseHook>,
+}
+
+impl ResponseSender {
+    /// Deliver `response`. Once the [`Worker`] is dropped nobody is left to
+    /// want it, so it is discarded.
+    fn send(&self, response: EvalResponse) {
+        let request_id = response.request_id;
+        if self.tx.send(response).is_ok()
+            && let Some(hook) = &self.hook
+        {
+            hook(request_id);
+        }
+    }
+}
+
 /// Commands that can be sent to the worker thread
 pub enum WorkerCommand {
     Connect(String, Sender<Result<(), NReplError>>),
+    /// Connect over a subprocess's pipes, already wrapped in a client.
+    ConnectStdio(NReplClient, Sender<Result<(), NReplError>>),
     Eval(EvalRequest),
     LoadFile(LoadFileRequest),
     /// Interrupt the eval whose request id is `target`. `op_id` is this
@@ -157,6 +913,14 @@ pub enum WorkerCommand {
         op_id: RequestId,
         reply: Sender<Result<Session, NReplError>>,
     },
+    /// Clone `session` rather than a fresh one, so the new session starts
+    /// with its dynamic bindings (`*ns*`, `*warn-on-reflection*`, a
+    /// ClojureScript REPL).
+    CloneSessionFrom {
+        op_id: RequestId,
+        session: Session,
+        reply: Sender<Result<Session, NReplError>>,
+    },
     CloseSession {
         op_id: RequestId,
         session: Session,
@@ -178,6 +942,22 @@ pub enum WorkerCommand {
         complete_fn: Option<String>,
         reply: Sender<Result<Vec<CompletionCandidate>, NReplError>>,
     },
+    /// Completions for a possibly alias-qualified prefix such as `str/sta`.
+    ///
+    /// The alias is resolved in `ns`, else the session's current namespace,
+    /// from the aliases a [`NsAliases`](WorkerCommand::NsAliases) query
+    /// returns (and caches), then completions are requested in the aliased
+    /// namespace under the same `op_id` and returned with the alias put back
+    /// (`str/starts-with?`). A prefix with no alias, or one that does not
+    /// resolve, is completed as given.
+    AliasedCompletions {
+        op_id: RequestId,
+        session: Session,
+        prefix: String,
+        ns: Option<String>,
+        complete_fn: Option<String>,
+        reply: Sender<Result<Vec<CompletionCandidate>, NReplError>>,
+    },
     Lookup {
         op_id: RequestId,
         session: Session,
@@ -198,66 +978,474 @@ pub enum WorkerCommand {
         op_id: RequestId,
         reply: Sender<Result<Vec<String>, NReplError>>,
     },
-    Shutdown(Sender<Result<(), NReplError>>),
-}
-
-/// A queued eval/load-file awaiting its turn behind the active eval.
-struct QueuedEval {
-    request_id: RequestId,
-    /// Pre-built request (already carries its wire id).
-    request: crate::message::Request,
-    timeout: Duration,
-}
-
-/// In-flight eval state tracked in the demux loop.
-struct EvalState {
-    request_id: RequestId,
-    acc: EvalAccumulator,
-    timeout: Duration,
-    deadline: Instant,
-    /// True while parked on `need-input` (deadline suspended).
-    parked: bool,
-}
-
-/// A control op awaiting its response, keyed in the pending map by wire id.
-///
-/// `Eval` is the large, common variant; boxing it to shrink the rarely-used
-/// control variants would add an allocation on the hot eval path, so we accept
-/// the size difference.
-#[allow(clippy::large_enum_variant)]
-enum Pending {
-    Eval(EvalState),
-    CloneSession {
-        reply: Sender<Result<Session, NReplError>>,
-        new_session: Option<String>,
+    /// Add `middleware` (fully qualified var names such as
+    /// `cider.nrepl/wrap-info`) to the server's running handler with
+    /// `add-middleware`, requiring `extra_namespaces` first. The server
+    /// rebuilds its stack in place, so the new ops show up in the next
+    /// `describe`. Names the server cannot resolve are an
+    /// [`NReplError::OperationFailed`] listing them.
+    ///
+    /// Exempt from the [`WorkerConfig::done_timeout`] watchdog: loading a
+    /// library such as cider-nrepl or refactor-nrepl on the fly can take
+    /// longer than the limit.
+    AddMiddleware {
+        op_id: RequestId,
+        session: Session,
+        middleware: Vec<String>,
+        extra_namespaces: Vec<String>,
+        reply: Sender<Result<(), NReplError>>,
     },
-    CloseSession {
+    /// List the server's middleware stack with `ls-middleware`, innermost
+    /// layer first. With `verbose`, servers that support it send each
+    /// layer's descriptor too. Global op - no session required.
+    LsMiddleware {
+        op_id: RequestId,
+        verbose: bool,
+        reply: Sender<Result<MiddlewareStack, NReplError>>,
+    },
+    /// Replace the server's middleware stack with `middleware` (fully
+    /// qualified var names, innermost first) using `swap-middleware`,
+    /// requiring `extra_namespaces` first. A stack that leaves out a layer
+    /// the client needs, such as session middleware, cannot be undone from
+    /// here: check it with
+    /// [`plan_swap_middleware`](Worker::plan_swap_middleware) first. Names
+    /// the server cannot resolve are an [`NReplError::OperationFailed`]
+    /// listing them. Exempt from the watchdog, like
+    /// [`AddMiddleware`](Self::AddMiddleware).
+    SwapMiddleware {
+        op_id: RequestId,
+        session: Session,
+        middleware: Vec<String>,
+        extra_namespaces: Vec<String>,
         reply: Sender<Result<(), NReplError>>,
     },
-    Interrupt {
+    /// Turn `session` into a ClojureScript REPL with piggieback or
+    /// shadow-cljs (see [`CljsRepl`]). Its evals then run in JavaScript and
+    /// its completions go to cider-nrepl's `complete`, until it evaluates
+    /// `:cljs/quit`. The upgrade is an eval in `session`, so it waits for the
+    /// session's running eval.
+    ///
+    /// Exempt from the [`WorkerConfig::done_timeout`] watchdog, since
+    /// starting the ClojureScript REPL may take a while.
+    UpgradeCljs {
+        op_id: RequestId,
+        session: Session,
+        repl: CljsRepl,
         reply: Sender<Result<(), NReplError>>,
     },
-    Completions {
-        reply: Sender<Result<Vec<CompletionCandidate>, NReplError>>,
-        candidates: Vec<CompletionCandidate>,
+    /// Fetch `ns`'s aliases and referred vars. Aliases belong to the
+    /// namespace rather than a session, so this runs as a session-less eval
+    /// and never waits behind the caller's evals.
+    ///
+    /// Answers are cached per session and namespace. An eval run in `ns`
+    /// drops its entries in every session, since they share the namespace;
+    /// any load-file drops them all, since the file may (re)define any
+    /// namespace. Closing a session drops that session's entries.
+    NsAliases {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        reply: Sender<Result<NsAliases, NReplError>>,
     },
-    Lookup {
-        reply: Sender<Result<Response, NReplError>>,
-        last: Option<Response>,
+    /// Analyze the session's last exception with cider-nrepl's
+    /// `analyze-last-stacktrace`. Replies `None` if the session has not
+    /// raised; the cause chain hangs off [`StackTrace::cause`]. Servers without
+    /// cider-nrepl answer with an unknown-op error.
+    AnalyzeStacktrace {
+        op_id: RequestId,
+        session: Session,
+        reply: Sender<Result<Option<StackTrace>, NReplError>>,
     },
-    Describe {
-        reply: Sender<Result<Response, NReplError>>,
-        last: Option<Response>,
+    /// Look `sym` up with cider-nrepl's `info`, which unlike
+    /// [`Lookup`](Self::Lookup) also resolves Java interop members and
+    /// protocol methods. Replies `None` if nothing was found.
+    Info {
+        op_id: RequestId,
+        session: Session,
+        sym: String,
+        ns: Option<String>,
+        reply: Sender<Result<Option<SymbolInfo>, NReplError>>,
     },
-    LsSessions {
-        reply: Sender<Result<Vec<String>, NReplError>>,
-        sessions: Vec<String>,
+    /// Fetch the ClojureDocs entry for `sym` in `ns` (its namespace, such as
+    /// `clojure.core`) with cider-nrepl's `clojuredocs-lookup`: community
+    /// examples, see-alsos and notes. Replies `None` if ClojureDocs has no
+    /// entry for it.
+    ClojureDocs {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        sym: String,
+        reply: Sender<Result<Option<ClojureDocs>, NReplError>>,
     },
-}
-
-/// Handle to a background worker thread.
-///
-/// Request ids are minted from a per-connection atomic counter.
+    /// Fetch `sym`'s signature with cider-nrepl's `eldoc`, for showing the
+    /// arglists of the call at point. Replies `None` if nothing was found.
+    Eldoc {
+        op_id: RequestId,
+        session: Session,
+        sym: String,
+        ns: Option<String>,
+        reply: Sender<Result<Option<Eldoc>, NReplError>>,
+    },
+    /// Search loaded vars with cider-nrepl's `apropos`: names matching the
+    /// regex `query` (and docstrings too with `docs`), across every namespace
+    /// or only `search_ns`, public vars only unless `privates`. An invalid
+    /// regex is an [`NReplError::OperationFailed`].
+    Apropos {
+        op_id: RequestId,
+        session: Session,
+        query: String,
+        search_ns: Option<String>,
+        docs: bool,
+        privates: bool,
+        reply: Sender<Result<Vec<AproposMatch>, NReplError>>,
+    },
+    /// The loaded functions that call `sym` in `ns`, with cider-nrepl's
+    /// `fn-refs`, for "find references". A symbol that does not resolve is
+    /// an [`NReplError::OperationFailed`].
+    FnRefs {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        sym: String,
+        reply: Sender<Result<Vec<XrefVar>, NReplError>>,
+    },
+    /// The functions `sym` in `ns` calls, with cider-nrepl's `fn-deps`.
+    /// Fails like [`FnRefs`](Self::FnRefs).
+    FnDeps {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        sym: String,
+        reply: Sender<Result<Vec<XrefVar>, NReplError>>,
+    },
+    /// List every loaded namespace with cider-nrepl's `ns-list`, sorted.
+    NsList {
+        op_id: RequestId,
+        session: Session,
+        reply: Sender<Result<Vec<String>, NReplError>>,
+    },
+    /// List the public vars of `ns`, with their metadata, with cider-nrepl's
+    /// `ns-vars-with-meta`. Sorted by name.
+    NsVars {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        reply: Sender<Result<Vec<NsVar>, NReplError>>,
+    },
+    /// Resolve `ns` to its source file with cider-nrepl's `ns-path`: a path,
+    /// or a `jar:` URL for a library. Replies `None` if it has none.
+    NsPath {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        reply: Sender<Result<Option<String>, NReplError>>,
+    },
+    /// The server's classpath, one entry per jar or directory, with
+    /// cider-nrepl's `classpath`. `resolve_source` uses it to find library
+    /// source given as a bare relative path.
+    Classpath {
+        op_id: RequestId,
+        session: Session,
+        reply: Sender<Result<Vec<String>, NReplError>>,
+    },
+    /// List the registered specs with cider-nrepl's `spec-list`, sorted:
+    /// keywords such as `:user/id` and the symbols of `fdef`ed functions.
+    /// With `filter_regex`, only names matching it.
+    SpecList {
+        op_id: RequestId,
+        session: Session,
+        filter_regex: Option<String>,
+        reply: Sender<Result<Vec<String>, NReplError>>,
+    },
+    /// The form `spec_name` was defined with, via cider-nrepl's `spec-form`.
+    /// Replies `None` for a name with no spec.
+    SpecForm {
+        op_id: RequestId,
+        session: Session,
+        spec_name: String,
+        reply: Sender<Result<Option<SpecForm>, NReplError>>,
+    },
+    /// A value generated to conform to `spec_name`, printed, via
+    /// cider-nrepl's `spec-example`. A spec that cannot be generated from
+    /// (test.check missing, or a predicate with no generator) is an
+    /// [`NReplError::OperationFailed`] with the server's explanation.
+    SpecExample {
+        op_id: RequestId,
+        session: Session,
+        spec_name: String,
+        reply: Sender<Result<String, NReplError>>,
+    },
+    /// Remove `sym`, as resolved in `ns`, with cider-nrepl's `undef`: a var
+    /// interned there is unmapped, an alias or refer dropped. Lets a renamed
+    /// definition go without restarting the REPL.
+    Undef {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        sym: String,
+        reply: Sender<Result<(), NReplError>>,
+    },
+    /// Remove every var, alias and refer in `ns` with cider-nrepl's
+    /// `undef-all`, leaving the namespace empty for a clean reload.
+    UndefAll {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        reply: Sender<Result<(), NReplError>>,
+    },
+    /// Trace or untrace `sym`, as resolved in `ns`, with cider-nrepl's
+    /// `toggle-trace-var`. Calls to a traced function print their arguments
+    /// and result as output of the eval that made them. A symbol that is not
+    /// a function is an [`NReplError::OperationFailed`].
+    ToggleTraceVar {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        sym: String,
+        reply: Sender<Result<VarTrace, NReplError>>,
+    },
+    /// Trace or untrace every function in `ns` with cider-nrepl's
+    /// `toggle-trace-ns`. An unknown namespace is an
+    /// [`NReplError::OperationFailed`].
+    ToggleTraceNs {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        reply: Sender<Result<TraceState, NReplError>>,
+    },
+    /// Format Clojure source with cljfmt via cider-nrepl's `format-code`,
+    /// replying with the formatted text. Code that does not read is an
+    /// [`NReplError::OperationFailed`] carrying the server's message.
+    FormatCode {
+        op_id: RequestId,
+        session: Session,
+        code: String,
+        reply: Sender<Result<String, NReplError>>,
+    },
+    /// Pretty-print EDN via cider-nrepl's `format-edn`, within
+    /// `right_margin` columns when given. Errors as for
+    /// [`FormatCode`](Self::FormatCode).
+    FormatEdn {
+        op_id: RequestId,
+        session: Session,
+        edn: String,
+        right_margin: Option<u32>,
+        reply: Sender<Result<String, NReplError>>,
+    },
+    /// Run tests with cider-nrepl's test runner (`test`, `test-all` or
+    /// `retest`, per [`TestSelection`]). A missing namespace is an
+    /// [`NReplError::OperationFailed`].
+    ///
+    /// Test runs are exempt from the [`WorkerConfig::done_timeout`]
+    /// watchdog, since a suite may run for minutes; use a
+    /// [`CancellationToken`] to give up on one.
+    RunTests {
+        op_id: RequestId,
+        session: Session,
+        selection: TestSelection,
+        reply: Sender<Result<TestResults, NReplError>>,
+    },
+    /// Fetch the stack trace of an erroring assertion from the session's last
+    /// test run, identified by its namespace, var and
+    /// [`index`](crate::TestAssertion::index). Replies `None` if there is
+    /// no exception at that position.
+    TestStacktrace {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        var: String,
+        index: u32,
+        reply: Sender<Result<Option<StackTrace>, NReplError>>,
+    },
+    /// Reload the namespaces changed since the last refresh and their
+    /// dependents (`refresh`), or every namespace with `all`
+    /// (`refresh-all`). A namespace that fails to load is reported in the
+    /// [`RefreshReport`], not as an error.
+    ///
+    /// Exempt from the [`WorkerConfig::done_timeout`] watchdog, since
+    /// reloading a large project, and its `before`/`after` hooks, may take a
+    /// while.
+    Refresh {
+        op_id: RequestId,
+        session: Session,
+        all: bool,
+        options: RefreshOptions,
+        reply: Sender<Result<RefreshReport, NReplError>>,
+    },
+    /// Reset the refresh tracker after a failed reload, so the next
+    /// [`Refresh`](Self::Refresh) starts from scratch (`refresh-clear`).
+    RefreshClear {
+        op_id: RequestId,
+        session: Session,
+        reply: Sender<Result<(), NReplError>>,
+    },
+    /// Evaluate `code` (in `ns` when given) and open cider-nrepl's inspector
+    /// on the result, replying with its first view. An exception is an
+    /// [`NReplError::OperationFailed`] carrying the server's message.
+    ///
+    /// Like [`RunTests`](Self::RunTests), this is exempt from the
+    /// [`WorkerConfig::done_timeout`] watchdog, since `code` may take any
+    /// time to run.
+    Inspect {
+        op_id: RequestId,
+        session: Session,
+        code: String,
+        ns: Option<String>,
+        reply: Sender<Result<InspectorPage, NReplError>>,
+    },
+    /// Move the inspector `session` has open (see [`InspectorAction`]),
+    /// replying with the new view.
+    Inspector {
+        op_id: RequestId,
+        session: Session,
+        action: InspectorAction,
+        reply: Sender<Result<InspectorPage, NReplError>>,
+    },
+    /// Subscribe to cider-nrepl's debugger for `session`: every breakpoint an
+    /// eval in it stops at arrives on `breaks` as a [`DebugBreak`], to be
+    /// answered with [`DebugInput`](Self::DebugInput). The stopped eval's
+    /// timeout is suspended until then.
+    ///
+    /// The subscription never finishes on its own and is exempt from the
+    /// [`WorkerConfig::done_timeout`] watchdog. A write failure, a server
+    /// without the debugger, or the connection closing ends it with an
+    /// error on `breaks`; dropping the receiver, or cancelling `op_id` with a
+    /// [`CancellationToken`], ends it quietly.
+    InitDebugger {
+        op_id: RequestId,
+        session: Session,
+        breaks: Sender<Result<DebugBreak, NReplError>>,
+    },
+    /// Subscribe to values sent with `tap>` on the server: each arrives on
+    /// `taps`, printed readably, apart from any eval's result.
+    ///
+    /// The subscription is an eval that waits on a tap queue and never
+    /// returns, so it ties up `session`: pass one cloned for the purpose.
+    /// Like [`InitDebugger`](Self::InitDebugger) it is exempt from the
+    /// watchdog. Cancelling `op_id` with a [`CancellationToken`], or dropping
+    /// the receiver, interrupts it and removes the tap; a failed eval (a
+    /// server older than Clojure 1.10 has no `add-tap`) ends it with an error
+    /// on `taps`.
+    SubscribeTap {
+        op_id: RequestId,
+        session: Session,
+        taps: Sender<Result<String, NReplError>>,
+    },
+    /// Receive output the server prints outside any eval (a background
+    /// thread's logging, a `future` that prints) with cider-nrepl's
+    /// `out-subscribe`. Without it, that output goes only to the server
+    /// process's own stdout. Each chunk arrives on `output`.
+    ///
+    /// The server acknowledges with `done` and then keeps forwarding under
+    /// this request's id, so the subscription is exempt from the watchdog.
+    /// It ends with [`OutUnsubscribe`](Self::OutUnsubscribe), or by
+    /// cancelling `op_id` or dropping the receiver (either of which also
+    /// unsubscribes server-side); a server without the op ends it with an
+    /// error on `output`.
+    OutSubscribe {
+        op_id: RequestId,
+        session: Session,
+        output: Sender<Result<ServerOutput, NReplError>>,
+    },
+    /// Stop forwarding server output to `session` with `out-unsubscribe`.
+    /// Its subscriptions' streams close.
+    OutUnsubscribe {
+        op_id: RequestId,
+        session: Session,
+        reply: Sender<Result<(), NReplError>>,
+    },
+    /// Start nREPL's sideloader for `session`: classes and resources the
+    /// server cannot find are looked up through `provider`, on the worker
+    /// thread, and the answer sent back. Each lookup served is reported on
+    /// `lookups`, best effort.
+    ///
+    /// The server never finishes `sideloader-start`, so the subscription is
+    /// exempt from the watchdog and lasts until the connection closes or
+    /// `op_id` is cancelled with a [`CancellationToken`]. A server without
+    /// the op ends it with an error on `lookups`.
+    StartSideloader {
+        op_id: RequestId,
+        session: Session,
+        provider: SideloadProvider,
+        lookups: Sender<Result<SideloadLookup, NReplError>>,
+    },
+    /// Answer the breakpoint stop `key` (see [`DebugBreak::key`]). Replies
+    /// once the server has taken the command, not when the eval next stops.
+    DebugInput {
+        op_id: RequestId,
+        session: Session,
+        key: String,
+        command: DebugCommand,
+        reply: Sender<Result<(), NReplError>>,
+    },
+    /// Recover a wedged connection: fail everything in flight, discard
+    /// buffered and unread socket bytes, then check the server still answers
+    /// `describe`. See [`ResyncReport`].
+    Resync {
+        op_id: RequestId,
+        reply: Sender<Result<ResyncReport, NReplError>>,
+    },
+    /// Abandon the request `target` client-side (see [`CancellationToken`]).
+    /// Unknown or finished ids are ignored.
+    Cancel {
+        target: RequestId,
+    },
+    /// Hold a [`CancellationToken`]'s link to a request until the request
+    /// finishes, then drop it. Sent by [`Worker::cancel_on`].
+    Watch(CancelLink),
+    Shutdown(Sender<Result<(), NReplError>>),
+}
+
+/// Which tests a [`WorkerCommand::RunTests`] runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TestSelection {
+    /// The named vars in `ns`, or all of its tests when `vars` is empty
+    /// (`test`).
+    Namespace { ns: String, vars: Vec<String> },
+    /// Every loaded test namespace; with `load_all`, every project namespace
+    /// is loaded first (`test-all`).
+    All { load_all: bool },
+    /// The tests that failed or errored in the session's last run
+    /// (`retest`).
+    Failed,
+}
+
+/// How a [`WorkerCommand::Inspector`] moves the inspector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InspectorAction {
+    /// Re-render the current value, picking up any change to it
+    /// (`inspect-refresh`).
+    Refresh,
+    /// Back to the value this one was reached from (`inspect-pop`).
+    Pop,
+    /// Into the value rendered with this
+    /// [`index`](crate::InspectorChunk::Value) (`inspect-push`).
+    Push(u32),
+    /// `inspect-next-page`.
+    NextPage,
+    /// `inspect-prev-page`.
+    PrevPage,
+    /// Show this many items per page (`inspect-set-page-size`).
+    SetPageSize(u32),
+}
+
+/// What a [`WorkerCommand::Resync`] did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResyncReport {
+    /// Bytes dropped from the decode buffer and the socket.
+    pub bytes_discarded: usize,
+    /// In-flight and queued requests failed (their callers get an error).
+    pub requests_failed: usize,
+    /// Unclaimed responses dropped from the worker's buffer
+    /// (see [`Worker::clear_pending_responses`]).
+    pub responses_dropped: usize,
+    /// Whether the server answered `describe` after the drain.
+    pub server_alive: bool,
+}
+
+/// Handle to a background worker thread.
+///
+/// Request ids are minted from a per-connection atomic counter.
 /// This is the single id source for the connection (evals and control ops
 /// alike), so wire ids never collide and the demux loop can route responses
 /// unambiguously.
@@ -269,6 +1457,18 @@ pub struct Worker {
     id_source: Arc<AtomicUsize>,
     // Buffer for responses - allows concurrent evals without losing responses
     pending_responses: HashMap<RequestId, EvalResponse>,
+    state: watch::Receiver<ConnectionState>,
+    metrics: Option<ClientMetrics>,
+    events: EventLog,
+    server: ServerInfo,
+    sessions: SessionTable,
+    cache: Option<ResponseCache>,
+    in_ns_fallback: bool,
+    retry: RetryPolicy,
+    timeouts: Timeouts,
+    /// Set by [`abort`](Self::abort); the worker thread drops the socket as
+    /// soon as it sees it.
+    abort: watch::Sender<bool>,
 }
 
 impl Worker {
@@ -280,9 +1480,39 @@ impl Worker {
     #[allow(clippy::new_without_default)]
     #[must_use]
     pub fn new() -> Self {
+        Self::with_config(WorkerConfig::default())
+    }
+
+    /// Create a new worker thread with non-default behaviour.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the worker thread's Tokio runtime cannot be built.
+    #[must_use]
+    pub fn with_config(mut config: WorkerConfig) -> Self {
         let (command_tx, command_rx) = unbounded_channel::<WorkerCommand>();
         let (response_tx, response_rx) = channel::<EvalResponse>();
+        let response_tx = ResponseSender {
+            tx: response_tx,
+            hook: config.response_hook.0.clone(),
+        };
+        let (state_tx, state) = watch::channel(ConnectionState::Disconnected);
         let id_source = Arc::new(AtomicUsize::new(1));
+        let worker_ids = Arc::clone(&id_source);
+        let metrics = config.metrics.clone();
+        // A fresh log per worker, even when one config builds several.
+        config.events = EventLog::new(config.events.capacity());
+        let events = config.events.clone();
+        config.server = ServerInfo::default();
+        let server = config.server.clone();
+        config.sessions = SessionTable::default();
+        let sessions = config.sessions.clone();
+        config.cache = config.cache.as_ref().map(|c| ResponseCache::new(c.ttl()));
+        let cache = config.cache.clone();
+        let in_ns_fallback = config.in_ns_fallback;
+        let retry = config.retry;
+        let timeouts = config.timeouts;
+        let (abort, abort_rx) = watch::channel(false);
 
         // Spawn worker thread - it will run until shutdown command or channel closes
         let _worker_thread = thread::spawn(move || {
@@ -292,7 +1522,14 @@ impl Worker {
                 .build()
                 .expect("Failed to create Tokio runtime for worker");
 
-            rt.block_on(worker_main(command_rx, response_tx));
+            rt.block_on(worker_main(
+                command_rx,
+                response_tx,
+                state_tx,
+                config,
+                worker_ids,
+                abort_rx,
+            ));
         });
 
         Self {
@@ -300,6 +1537,348 @@ impl Worker {
             response_rx,
             id_source,
             pending_responses: HashMap::new(),
+            state,
+            metrics,
+            events,
+            server,
+            sessions,
+            cache,
+            in_ns_fallback,
+            retry,
+            timeouts,
+            abort,
+        }
+    }
+
+    /// Recent significant events on this connection (connects, disconnects,
+    /// timeouts, limit hits), oldest first. Recorded whether or not
+    /// `NREPL_DEBUG` is set, and free of code and output, so it can go
+    /// straight into a bug report.
+    #[must_use]
+    pub fn debug_events(&self) -> Vec<DebugEvent> {
+        self.events.snapshot()
+    }
+
+    /// The connection's traffic metrics, if the worker was built with
+    /// [`WorkerConfig::collect_metrics`]. Take a
+    /// [`snapshot`](ClientMetrics::snapshot) to read them or
+    /// [`reset`](ClientMetrics::reset) to start a new measuring window.
+    #[must_use]
+    pub fn metrics(&self) -> Option<&ClientMetrics> {
+        self.metrics.as_ref()
+    }
+
+    /// The connection's `completions` and `lookup` cache, if the worker was
+    /// built with [`WorkerConfig::cache_responses`]. Read its
+    /// [`stats`](ResponseCache::stats), or
+    /// [`invalidate`](ResponseCache::invalidate) a session whose state changed
+    /// behind the worker's back.
+    #[must_use]
+    pub fn response_cache(&self) -> Option<&ResponseCache> {
+        self.cache.as_ref()
+    }
+
+    /// The server's flavor and ops, once a `describe` has been answered (see
+    /// [`WorkerConfig::detect_server`]).
+    #[must_use]
+    pub fn server_profile(&self) -> Option<ServerProfile> {
+        self.server.profile()
+    }
+
+    /// The namespace `session` is in, as of its latest eval reply, or where
+    /// the server starts a session if it has not evaluated anything yet and
+    /// the server's flavor is known. Never asks the server.
+    #[must_use]
+    pub fn session_ns(&self, session: &Session) -> Option<String> {
+        self.server.fallback_ns(session.id())
+    }
+
+    /// The sessions this worker has cloned or sent requests in, oldest
+    /// first, with their labels and request counts. A closed session drops
+    /// out; one that expired on the server stays until closed here.
+    #[must_use]
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        self.sessions.snapshot()
+    }
+
+    /// Name `session`'s role (such as "user REPL" or "tooling") for
+    /// [`sessions`](Self::sessions) to report, or clear it with `None`.
+    pub fn label_session(&self, session: &Session, label: Option<String>) {
+        self.sessions.set_label(session.id(), label);
+    }
+
+    /// Clone `session` on the server and return the copy, which starts
+    /// with `session`'s dynamic bindings (its `*ns*`, `*warn-on-reflection*`
+    /// and the like) instead of the defaults a fresh clone gets. Blocks for
+    /// up to the `clone` [`timeouts`](Self::timeouts).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the worker thread has gone away, the server
+    /// rejects the clone, or it does not answer in time.
+    pub fn clone_session_from(&self, session: &Session) -> Result<Session, NReplError> {
+        let (reply, replies) = channel();
+        self.command_tx
+            .send(WorkerCommand::CloneSessionFrom {
+                op_id: self.next_id(),
+                session: session.clone(),
+                reply,
+            })
+            .map_err(|_| {
+                NReplError::Connection(std::io::Error::other("Worker thread disconnected"))
+            })?;
+        let timeout = self.timeouts.clone;
+        replies
+            .recv_timeout(timeout)
+            .map_err(|_| NReplError::timeout("clone", timeout))?
+    }
+
+    /// Ask the server which sessions it has (`ls-sessions`) and bring
+    /// [`sessions`](Self::sessions) in line: tracked sessions it no longer
+    /// knows, as after a server restart, are dropped and recorded as
+    /// [`SessionInvalidated`](DebugEventKind::SessionInvalidated) events;
+    /// with `adopt_orphans`, sessions only the server knows are tracked from
+    /// now on. Blocks like [`ls_sessions`](Self::ls_sessions).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the worker thread has gone away, the server does
+    /// not implement `ls-sessions`, or it does not answer in time.
+    pub fn reconcile_sessions(
+        &self,
+        adopt_orphans: bool,
+    ) -> Result<SessionReconciliation, NReplError> {
+        let live = self.ls_sessions()?;
+        Ok(self.reconcile_sessions_with(&live, adopt_orphans))
+    }
+
+    /// The policy the blocking idempotent helpers retry by (see
+    /// [`WorkerConfig::retry_policy`]).
+    #[must_use]
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// How long the blocking helpers wait for each kind of op (see
+    /// [`WorkerConfig::timeouts`]).
+    #[must_use]
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+
+    /// The server's capabilities (`describe`). Retries by the
+    /// [`retry_policy`](Self::retry_policy), blocking for no longer than
+    /// the `control` [`timeouts`](Self::timeouts) in all.
+    ///
+    /// # Errors
+    ///
+    /// Returns the last attempt's error: the worker thread has gone away,
+    /// the server failed the request, or it did not answer in time.
+    pub fn describe(&self, verbose: bool) -> Result<Response, NReplError> {
+        self.retrying("describe", |op_id, reply| WorkerCommand::Describe {
+            op_id,
+            verbose,
+            reply,
+        })
+    }
+
+    /// The server's session ids (`ls-sessions`). Blocks and retries like
+    /// [`describe`](Self::describe).
+    ///
+    /// # Errors
+    ///
+    /// As for [`describe`](Self::describe); a server without `ls-sessions`
+    /// fails at once with an unknown-op error.
+    pub fn ls_sessions(&self) -> Result<Vec<String>, NReplError> {
+        self.retrying("ls-sessions", |op_id, reply| WorkerCommand::LsSessions {
+            op_id,
+            reply,
+        })
+    }
+
+    /// The server's middleware stack (`ls-middleware`), innermost layer
+    /// first. Blocks and retries like [`describe`](Self::describe).
+    ///
+    /// # Errors
+    ///
+    /// As for [`ls_sessions`](Self::ls_sessions).
+    pub fn ls_middleware(&self, verbose: bool) -> Result<MiddlewareStack, NReplError> {
+        self.retrying("ls-middleware", |op_id, reply| {
+            WorkerCommand::LsMiddleware {
+                op_id,
+                verbose,
+                reply,
+            }
+        })
+    }
+
+    /// What replacing the server's middleware with `new_stack` (var names,
+    /// innermost first) would do, without doing it: the current stack
+    /// (listed verbosely) and `describe` ops are fetched, and the plan
+    /// names the ops that nothing in `new_stack` would answer. See
+    /// [`MiddlewareStack::plan_swap`].
+    ///
+    /// # Errors
+    ///
+    /// As for [`ls_middleware`](Self::ls_middleware) and
+    /// [`describe`](Self::describe).
+    pub fn plan_swap_middleware(&self, new_stack: &[String]) -> Result<SwapPlan, NReplError> {
+        let stack = self.ls_middleware(true)?;
+        let ops: Vec<String> = self
+            .describe(false)?
+            .ops
+            .map(|ops| ops.into_keys().collect())
+            .unwrap_or_default();
+        Ok(stack.plan_swap(new_stack, &ops))
+    }
+
+    /// Completions for `prefix` in `ns` (the session's namespace if `None`).
+    /// Blocks and retries like [`describe`](Self::describe).
+    ///
+    /// # Errors
+    ///
+    /// As for [`describe`](Self::describe).
+    pub fn completions(
+        &self,
+        session: &Session,
+        prefix: &str,
+        ns: Option<&str>,
+    ) -> Result<Vec<CompletionCandidate>, NReplError> {
+        self.retrying("completions", |op_id, reply| WorkerCommand::Completions {
+            op_id,
+            session: session.clone(),
+            prefix: prefix.to_string(),
+            ns: ns.map(str::to_string),
+            complete_fn: None,
+            reply,
+        })
+    }
+
+    /// Information about `sym` as seen from `ns` (`lookup`). Blocks and
+    /// retries like [`describe`](Self::describe).
+    ///
+    /// # Errors
+    ///
+    /// As for [`describe`](Self::describe).
+    pub fn lookup(
+        &self,
+        session: &Session,
+        sym: &str,
+        ns: Option<&str>,
+    ) -> Result<Response, NReplError> {
+        self.retrying("lookup", |op_id, reply| WorkerCommand::Lookup {
+            op_id,
+            session: session.clone(),
+            sym: sym.to_string(),
+            ns: ns.map(str::to_string),
+            lookup_fn: None,
+            reply,
+        })
+    }
+
+    /// Send the command `build` makes and wait for its reply, again under a
+    /// fresh id for each retry the policy allows, all within what the
+    /// [`timeouts`](Self::timeouts) give `operation`. A late reply to an
+    /// abandoned attempt goes nowhere.
+    fn retrying<T>(
+        &self,
+        operation: &str,
+        mut build: impl FnMut(RequestId, Sender<Result<T, NReplError>>) -> WorkerCommand,
+    ) -> Result<T, NReplError> {
+        let timeout = self.timeouts.for_op(operation);
+        self.retry.run_within(timeout, |wait| {
+            let (reply, replies) = channel();
+            self.command_tx
+                .send(build(self.next_id(), reply))
+                .map_err(|_| {
+                    NReplError::Connection(std::io::Error::other("Worker thread disconnected"))
+                })?;
+            replies
+                .recv_timeout(wait)
+                .map_err(|_| NReplError::timeout(operation, timeout))?
+        })
+    }
+
+    /// Descriptors of the tracked sessions, oldest first, for an editor to
+    /// save and hand to [`import_sessions`](Self::import_sessions) after a
+    /// restart.
+    #[must_use]
+    pub fn export_sessions(&self) -> Vec<SessionDescriptor> {
+        self.sessions
+            .snapshot()
+            .iter()
+            .map(SessionDescriptor::from)
+            .collect()
+    }
+
+    /// Track the sessions in `saved` again, with their labels and creation
+    /// times, returning those restored. Each id is checked against the
+    /// server's `ls-sessions` first: one the server does not list (it
+    /// restarted, or the file was edited) is skipped, so only ids the server
+    /// vouches for become [`Session`]s. Blocks like
+    /// [`ls_sessions`](Self::ls_sessions).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the worker thread has gone away, the server does
+    /// not implement `ls-sessions`, or it does not answer in time.
+    pub fn import_sessions(&self, saved: &[SessionDescriptor]) -> Result<Vec<Session>, NReplError> {
+        let live = self.ls_sessions()?;
+        Ok(saved
+            .iter()
+            .filter_map(|descriptor| {
+                let id = live.iter().find(|id| **id == descriptor.id)?;
+                self.sessions.restore(id, descriptor);
+                Some(Session::from_server_id(id.as_str()))
+            })
+            .collect())
+    }
+
+    /// [`reconcile_sessions`](Self::reconcile_sessions) against a session
+    /// list the caller already fetched with
+    /// [`LsSessions`](WorkerCommand::LsSessions). Never blocks.
+    pub fn reconcile_sessions_with(
+        &self,
+        live: &[String],
+        adopt_orphans: bool,
+    ) -> SessionReconciliation {
+        let changes = self.sessions.reconcile(live, adopt_orphans);
+        for info in &changes.invalidated {
+            self.server.forget_session(info.session.id());
+            self.events.record(
+                DebugEventKind::SessionInvalidated,
+                format!("session {} is gone from the server", info.session.id()),
+            );
+        }
+        changes
+    }
+
+    /// Current liveness of the connection. `Disconnected` until
+    /// [`connect_blocking`](Self::connect_blocking) succeeds.
+    #[must_use]
+    pub fn connection_state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    /// A receiver notified on every [`ConnectionState`] change, for callers
+    /// that want to show status as it moves.
+    #[must_use]
+    pub fn watch_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Resolves once the connection is `Disconnected`: the server closed it,
+    /// the socket failed, or the worker shut down. Call after connecting, or
+    /// it resolves immediately. The future does not borrow the worker, so it
+    /// can be spawned onto any runtime.
+    pub fn on_disconnect(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut state = self.state.clone();
+        async move {
+            // A closed channel means the worker thread is gone, which is a
+            // disconnect too.
+            let _ = state
+                .wait_for(|s| *s == ConnectionState::Disconnected)
+                .await;
         }
     }
 
@@ -316,13 +1895,18 @@ impl Worker {
         RequestId::new(self.id_source.fetch_add(1, Ordering::Relaxed))
     }
 
-    /// Connect to an nREPL server (blocking call with 30s timeout)
+    /// Connect to an nREPL server, blocking for up to the `connect`
+    /// [`timeouts`](Self::timeouts)
+    ///
+    /// With the `drawbridge` feature, an `http://` URL connects to a
+    /// drawbridge endpoint instead of a TCP address; with `websocket`, a
+    /// `ws://` URL connects through a WebSocket bridge.
     ///
     /// # Errors
     ///
     /// Returns [`NReplError::Connection`] if the worker thread has gone away or
     /// the TCP connection fails, and [`NReplError::Timeout`] if the server does
-    /// not accept the connection within 30 seconds.
+    /// not accept the connection in time.
     pub fn connect_blocking(&self, address: String) -> Result<(), NReplError> {
         let (response_tx, response_rx) = channel();
 
@@ -332,15 +1916,96 @@ impl Worker {
                 NReplError::Connection(std::io::Error::other("Worker thread disconnected"))
             })?;
 
+        let timeout = self.timeouts.connect;
+        response_rx
+            .recv_timeout(timeout)
+            .map_err(|_| NReplError::timeout("connect", timeout))?
+    }
+
+    /// Connect to a server running as a subprocess in stdio mode, over its
+    /// `stdin` and `stdout` pipes, so no port is opened:
+    ///
+    /// ```no_run
+    /// use std::process::{Command, Stdio};
+    /// use nrepl_rs::worker::Worker;
+    ///
+    /// let mut child = Command::new("bb")
+    ///     .args(["nrepl-server", "--stdio"])
+    ///     .stdin(Stdio::piped())
+    ///     .stdout(Stdio::piped())
+    ///     .spawn()?;
+    /// let worker = Worker::new();
+    /// worker.connect_stdio(child.stdin.take().unwrap(), child.stdout.take().unwrap())?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    ///
+    /// The process is the caller's to wait on and kill; when it exits the
+    /// worker sees the connection close.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NReplError::Connection`] if the worker thread has gone away.
+    pub fn connect_stdio<W, R>(&self, stdin: W, stdout: R) -> Result<(), NReplError>
+    where
+        W: std::io::Write + Send + 'static,
+        R: std::io::Read + Send + 'static,
+    {
+        let (response_tx, response_rx) = channel();
+        let client = NReplClient::connect_stdio(stdin, stdout);
+        self.command_tx
+            .send(WorkerCommand::ConnectStdio(client, response_tx))
+            .map_err(|_| {
+                NReplError::Connection(std::io::Error::other("Worker thread disconnected"))
+            })?;
+
+        let timeout = self.timeouts.connect;
         response_rx
-            .recv_timeout(Duration::from_secs(30))
-            .map_err(|_| NReplError::Timeout {
-                operation: "connect".to_string(),
-                duration: Duration::from_secs(30),
-            })?
+            .recv_timeout(timeout)
+            .map_err(|_| NReplError::timeout("connect", timeout))?
+    }
+
+    /// Connect to a drawbridge endpoint at `url` (`http://host[:port]/path`),
+    /// for servers that expose nREPL only over HTTP; see
+    /// [`crate::drawbridge`]. Blocks as [`connect_blocking`](Self::connect_blocking).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NReplError::Connection`] if `url` is not an `http://` URL or
+    /// the endpoint cannot be reached, and [`NReplError::Timeout`] as
+    /// [`connect_blocking`](Self::connect_blocking).
+    #[cfg(feature = "drawbridge")]
+    pub fn connect_http(&self, url: &str) -> Result<(), NReplError> {
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(NReplError::Connection(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("not an http:// drawbridge URL: {url}"),
+            )));
+        }
+        self.connect_blocking(url.to_string())
+    }
+
+    /// Connect through a WebSocket bridge at `url` (`ws://host[:port]/path`);
+    /// see [`crate::websocket`]. Blocks as
+    /// [`connect_blocking`](Self::connect_blocking).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NReplError::Connection`] if `url` is not a `ws://` URL or
+    /// the bridge cannot be reached, and [`NReplError::Timeout`] as
+    /// [`connect_blocking`](Self::connect_blocking).
+    #[cfg(feature = "websocket")]
+    pub fn connect_ws(&self, url: &str) -> Result<(), NReplError> {
+        if !(url.starts_with("ws://") || url.starts_with("wss://")) {
+            return Err(NReplError::Connection(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("not a ws:// WebSocket URL: {url}"),
+            )));
+        }
+        self.connect_blocking(url.to_string())
     }
 
     /// Submit an eval request and return the request ID (non-blocking).
+    /// `code` may be a `String` or a shared `Arc<str>`; see [`Code`].
     ///
     /// # Errors
     ///
@@ -348,22 +2013,63 @@ impl Worker {
     pub fn submit_eval(
         &mut self,
         session: Session,
-        code: String,
+        code: impl Into<Code>,
         timeout: Option<Duration>,
         file: Option<String>,
         line: Option<i64>,
         column: Option<i64>,
     ) -> Result<RequestId, SubmitError> {
+        self.submit_eval_with(
+            session,
+            code,
+            EvalOptions {
+                timeout,
+                file,
+                line,
+                column,
+                ..EvalOptions::default()
+            },
+        )
+    }
+
+    /// Submit an eval of `code` set up by `options` (non-blocking), like
+    /// [`submit_eval`](Self::submit_eval) and
+    /// [`eval_in_ns`](Self::eval_in_ns) together, plus
+    /// [`tags`](EvalOptions::tags).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SubmitError::InvalidNamespace`] if `options.ns` is not a
+    /// plain symbol, or [`SubmitError::WorkerDisconnected`] if the worker
+    /// thread has gone away.
+    pub fn submit_eval_with(
+        &mut self,
+        session: Session,
+        code: impl Into<Code>,
+        options: EvalOptions,
+    ) -> Result<RequestId, SubmitError> {
+        let mut code = code.into();
+        if let Some(ns) = &options.ns {
+            if !is_plain_symbol(ns) {
+                return Err(SubmitError::InvalidNamespace(ns.clone()));
+            }
+            if self.in_ns_fallback {
+                // Same line, so line numbers in errors still match the caller's.
+                code = Code::from(format!("(clojure.core/in-ns '{ns}) {code}"));
+            }
+        }
         let request_id = self.next_id();
 
         let request = EvalRequest {
             request_id,
             session,
             code,
-            timeout,
-            file,
-            line,
-            column,
+            timeout: options.timeout,
+            file: options.file,
+            line: options.line,
+            column: options.column,
+            ns: options.ns,
+            tags: options.tags,
         };
 
         self.command_tx
@@ -373,7 +2079,205 @@ impl Worker {
         Ok(request_id)
     }
 
+    /// Submit an eval of `code` in namespace `ns` (non-blocking), like
+    /// [`submit_eval`](Self::submit_eval) otherwise. The server runs the
+    /// eval with `*ns*` bound to `ns`, which must already be loaded, and the
+    /// session stays there afterwards. See
+    /// [`WorkerConfig::in_ns_fallback`] for servers that ignore `ns`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SubmitError::InvalidNamespace`] if `ns` is not a plain
+    /// symbol, or [`SubmitError::WorkerDisconnected`] if the worker thread
+    /// has gone away.
+    pub fn eval_in_ns(
+        &mut self,
+        session: &Session,
+        ns: &str,
+        code: impl Into<Code>,
+        timeout: Option<Duration>,
+    ) -> Result<RequestId, SubmitError> {
+        self.submit_eval_with(
+            session.clone(),
+            code,
+            EvalOptions {
+                timeout,
+                ns: Some(ns.to_string()),
+                ..EvalOptions::default()
+            },
+        )
+    }
+
+    /// Evaluate `forms` in order in `session`, blocking until all are done,
+    /// and return their results aligned with `forms`. An eval that throws
+    /// is an `Ok` carrying [`EvalResult::ex`]; `Err` is for evals that did
+    /// not finish (timeouts, disconnects) or, with
+    /// [`BatchOptions::fail_fast`], were never sent. A form asking for stdin
+    /// is interrupted, since nobody is there to answer it.
+    pub fn eval_batch(
+        &mut self,
+        session: &Session,
+        forms: Vec<String>,
+        options: BatchOptions,
+    ) -> Vec<Result<EvalResult, NReplError>> {
+        let deadline = std::time::Instant::now() + options.timeout;
+        let mut timed_out = false;
+        let mut results = Vec::with_capacity(forms.len());
+        if options.fail_fast {
+            let mut failed = false;
+            for code in forms {
+                if failed {
+                    results.push(Err(NReplError::OperationFailed(
+                        "not evaluated: an earlier form failed".to_string(),
+                    )));
+                    continue;
+                }
+                let result = self
+                    .submit_batch_form(session, code, deadline)
+                    .and_then(|id| {
+                        self.await_batch_form(
+                            session,
+                            id,
+                            deadline,
+                            options.timeout,
+                            &mut timed_out,
+                        )
+                    });
+                failed = result
+                    .as_ref()
+                    .map_or(true, |r| r.ex.is_some() || r.interrupted);
+                results.push(result);
+            }
+        } else {
+            let ids: Vec<_> = forms
+                .into_iter()
+                .map(|code| self.submit_batch_form(session, code, deadline))
+                .collect();
+            for id in ids {
+                results.push(id.and_then(|id| {
+                    self.await_batch_form(session, id, deadline, options.timeout, &mut timed_out)
+                }));
+            }
+        }
+        results
+    }
+
+    /// Load `coords` (lib, Maven version), such as
+    /// `("criterium/criterium", "0.4.6")`, into the running REPL with
+    /// Clojure 1.12's `add-libs`, or tools.deps' where that is missing (see
+    /// [`AddLibsReport`]). Runs as an eval in `session` and blocks until the
+    /// libraries are resolved, downloading them if need be, for up to five
+    /// minutes.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NReplError::OperationFailed`] for a lib name that is not a
+    /// symbol, if neither `add-libs` is available, or with the server's
+    /// explanation if resolving fails; a timeout or lost connection as for
+    /// [`eval_batch`](Self::eval_batch).
+    pub fn add_libs(
+        &mut self,
+        session: &Session,
+        coords: &[(&str, &str)],
+    ) -> Result<AddLibsReport, NReplError> {
+        if let Some((lib, _)) = coords.iter().find(|(lib, _)| !is_lib_name(lib)) {
+            return Err(NReplError::OperationFailed(format!(
+                "not a library name: {lib}"
+            )));
+        }
+        let options = BatchOptions {
+            timeout: ADD_LIBS_TIMEOUT,
+            ..BatchOptions::default()
+        };
+        let result = self
+            .eval_batch(session, vec![add_libs_form(coords)], options)
+            .pop()
+            .unwrap_or_else(|| Err(NReplError::OperationFailed("add-libs not sent".to_string())))?;
+        let libs: Vec<&str> = coords.iter().map(|(lib, _)| *lib).collect();
+        match result
+            .value
+            .as_deref()
+            .and_then(|v| AddLibsReport::parse(v, &libs))
+        {
+            Some(report) if result.ex.is_none() => Ok(report),
+            _ => Err(NReplError::OperationFailed(
+                match (result.error.concat().trim_end(), result.ex) {
+                    ("", Some(ex)) => format!("add-libs failed: {ex}"),
+                    ("", None) => "add-libs failed".to_string(),
+                    (err, _) => err.to_string(),
+                },
+            )),
+        }
+    }
+
+    fn submit_batch_form(
+        &mut self,
+        session: &Session,
+        code: String,
+        deadline: std::time::Instant,
+    ) -> Result<RequestId, NReplError> {
+        let budget = deadline.saturating_duration_since(std::time::Instant::now());
+        self.submit_eval(session.clone(), code, Some(budget), None, None, None)
+            .map_err(|e| NReplError::OperationFailed(e.to_string()))
+    }
+
+    /// Wait for one batch form. At the deadline the form is abandoned, and
+    /// the first form to time out (the one running) is interrupted.
+    fn await_batch_form(
+        &mut self,
+        session: &Session,
+        id: RequestId,
+        deadline: std::time::Instant,
+        budget: Duration,
+        timed_out: &mut bool,
+    ) -> Result<EvalResult, NReplError> {
+        match self.await_response(id, deadline, session) {
+            Some(result) => result,
+            None => {
+                if !*timed_out {
+                    *timed_out = true;
+                    self.interrupt_quietly(session, id);
+                }
+                let _ = self.command_tx.send(WorkerCommand::Cancel { target: id });
+                let grace = std::time::Instant::now() + BATCH_CANCEL_GRACE;
+                let _ = self.await_response(id, grace, session);
+                Err(NReplError::timeout("evaluating a batch", budget))
+            }
+        }
+    }
+
+    /// Poll for `id`'s result until `deadline`, interrupting it if it asks
+    /// for stdin. `None` if the deadline passed first.
+    fn await_response(
+        &mut self,
+        id: RequestId,
+        deadline: std::time::Instant,
+        session: &Session,
+    ) -> Option<Result<EvalResult, NReplError>> {
+        loop {
+            match self.try_recv_response(id).map(|r| r.outcome) {
+                Some(EvalOutcome::Done(result)) => return Some(result),
+                Some(EvalOutcome::NeedInput { .. }) => self.interrupt_quietly(session, id),
+                None if std::time::Instant::now() >= deadline => return None,
+                None => thread::sleep(BATCH_POLL_INTERVAL),
+            }
+        }
+    }
+
+    /// Interrupt `target` without waiting to hear how it went.
+    fn interrupt_quietly(&self, session: &Session, target: RequestId) {
+        let (reply, _) = channel();
+        let _ = self.command_tx.send(WorkerCommand::Interrupt {
+            op_id: self.next_id(),
+            session: session.clone(),
+            target,
+            reply,
+        });
+    }
+
     /// Submit a load-file request and return the request ID (non-blocking).
+    /// `file_contents` may be a `String` or a shared `Arc<str>`; see
+    /// [`Code`].
     ///
     /// # Errors
     ///
@@ -381,7 +2285,7 @@ impl Worker {
     pub fn submit_load_file(
         &mut self,
         session: Session,
-        file_contents: String,
+        file_contents: impl Into<Code>,
         file_path: Option<String>,
         file_name: Option<String>,
     ) -> Result<RequestId, SubmitError> {
@@ -390,7 +2294,7 @@ impl Worker {
         let request = LoadFileRequest {
             request_id,
             session,
-            file_contents,
+            file_contents: file_contents.into(),
             file_path,
             file_name,
         };
@@ -412,7 +2316,25 @@ impl Worker {
         if let Some(response) = self.pending_responses.remove(&request_id) {
             return Some(response);
         }
+        self.buffer_arrivals();
+        self.pending_responses.remove(&request_id)
+    }
+
+    /// Take the oldest completed eval response for any request, without
+    /// naming one (non-blocking). Lets a caller with many evals in flight
+    /// collect them with one call per tick; it shares the buffer with
+    /// [`try_recv_response`](Self::try_recv_response), so each response is
+    /// returned once, by whichever is called first.
+    pub fn try_recv_any_response(&mut self) -> Option<EvalResponse> {
+        self.buffer_arrivals();
+        // Request ids are minted monotonically: the smallest is the oldest.
+        let oldest = self.pending_responses.keys().min().copied()?;
+        self.pending_responses.remove(&oldest)
+    }
 
+    /// Move every response that has arrived into the buffer, evicting the
+    /// oldest past `MAX_PENDING_RESPONSES`.
+    fn buffer_arrivals(&mut self) {
         while let Ok(response) = self.response_rx.try_recv() {
             self.pending_responses.insert(response.request_id, response);
             // Request ids are minted monotonically, so the smallest key is the
@@ -420,745 +2342,93 @@ impl Worker {
             while self.pending_responses.len() > MAX_PENDING_RESPONSES {
                 if let Some(oldest) = self.pending_responses.keys().min().copied() {
                     self.pending_responses.remove(&oldest);
+                    self.events.record(
+                        DebugEventKind::ResponsesEvicted,
+                        format!(
+                            "dropped unclaimed result of {} (over {MAX_PENDING_RESPONSES} buffered)",
+                            oldest.wire()
+                        ),
+                    );
                 }
             }
         }
-
-        self.pending_responses.remove(&request_id)
-    }
-
-    /// Shutdown the worker thread (non-blocking).
-    pub fn shutdown(&mut self) {
-        let _ = self.command_tx.send(WorkerCommand::Shutdown(channel().0));
-    }
-}
-
-impl Drop for Worker {
-    fn drop(&mut self) {
-        self.shutdown();
-    }
-}
-
-/// Worker thread entry: wait for the initial Connect, then run the demux loop.
-async fn worker_main(
-    mut command_rx: UnboundedReceiver<WorkerCommand>,
-    response_tx: Sender<EvalResponse>,
-) {
-    // Phase 1: wait for a Connect command before we have a stream to demux.
-    loop {
-        match command_rx.recv().await {
-            Some(WorkerCommand::Connect(address, reply)) => {
-                match NReplClient::connect(&address).await {
-                    Ok(client) => {
-                        let (writer, reader) = client.into_split();
-                        let _ = reply.send(Ok(()));
-                        // Phase 2: run the demux event loop until shutdown/disconnect.
-                        event_loop(writer, reader, &mut command_rx, &response_tx).await;
-                        return;
-                    }
-                    Err(e) => {
-                        // Connection failed; let the caller retry with a new worker.
-                        let _ = reply.send(Err(e));
-                    }
-                }
-            }
-            Some(WorkerCommand::Shutdown(reply)) => {
-                let _ = reply.send(Ok(()));
-                return;
-            }
-            Some(other) => {
-                // Not connected yet - reply to any waiting one-shot with an error.
-                reply_not_connected(other);
-            }
-            None => return,
-        }
-    }
-}
-
-/// Reply to a command's one-shot channel with a "Not connected" error.
-fn reply_not_connected(cmd: WorkerCommand) {
-    let err = || NReplError::protocol("Not connected");
-    match cmd {
-        WorkerCommand::Eval(req) => {
-            // No response channel here; main thread polls try_recv_response and
-            // would get nothing. This path shouldn't happen in practice because
-            // connect happens before any eval, but be safe and drop it.
-            let _ = req;
-        }
-        WorkerCommand::LoadFile(req) => {
-            let _ = req;
-        }
-        WorkerCommand::Interrupt { reply, .. }
-        | WorkerCommand::CloseSession { reply, .. }
-        | WorkerCommand::Stdin { reply, .. }
-        | WorkerCommand::Connect(_, reply) => {
-            let _ = reply.send(Err(err()));
-        }
-        WorkerCommand::CloneSession { reply, .. } => {
-            let _ = reply.send(Err(err()));
-        }
-        WorkerCommand::Completions { reply, .. } => {
-            let _ = reply.send(Err(err()));
-        }
-        WorkerCommand::Lookup { reply, .. } | WorkerCommand::Describe { reply, .. } => {
-            let _ = reply.send(Err(err()));
-        }
-        WorkerCommand::LsSessions { reply, .. } => {
-            let _ = reply.send(Err(err()));
-        }
-        WorkerCommand::Shutdown(reply) => {
-            let _ = reply.send(Ok(()));
-        }
     }
-}
 
-/// The demux event loop. Owns the writer/reader and all in-flight state.
-async fn event_loop(
-    mut writer: NReplWriter,
-    mut reader: NReplReader,
-    command_rx: &mut UnboundedReceiver<WorkerCommand>,
-    response_tx: &Sender<EvalResponse>,
-) {
-    let mut pending: HashMap<String, Pending> = HashMap::new();
-    let mut eval_queue: VecDeque<QueuedEval> = VecDeque::new();
-    // Wire id of the currently running eval, if any.
-    let mut active_eval: Option<String> = None;
-
-    loop {
-        // Deadline arm: only the active, non-parked eval has a live deadline.
-        let deadline = active_eval
-            .as_ref()
-            .and_then(|id| pending.get(id))
-            .and_then(|p| match p {
-                Pending::Eval(s) if !s.parked => Some(s.deadline),
-                _ => None,
-            })
-            .unwrap_or_else(|| Instant::now() + Duration::from_hours(1));
-
-        tokio::select! {
-            cmd = command_rx.recv() => {
-                match cmd {
-                    Some(WorkerCommand::Shutdown(reply)) => {
-                        // Best-effort: fail any pending ops, then exit.
-                        fail_all_pending(&mut pending, &mut eval_queue, response_tx,
-                            || NReplError::protocol("Worker shutting down"));
-                        let _ = reply.send(Ok(()));
-                        return;
-                    }
-                    Some(cmd) => {
-                        dispatch_command(
-                            cmd, &mut writer, &mut pending, &mut eval_queue,
-                            &mut active_eval, response_tx,
-                        ).await;
-                    }
-                    None => {
-                        // All command senders dropped - shut down.
-                        return;
-                    }
-                }
-            }
-            resp = reader.next_response() => {
-                match resp {
-                    Ok(r) => {
-                        route_response(
-                            r, &mut writer, &mut pending, &mut eval_queue,
-                            &mut active_eval, response_tx,
-                        ).await;
-                    }
-                    Err(e) => {
-                        // Reader EOF / connection error: fail everything and stop.
-                        fail_all_pending(&mut pending, &mut eval_queue, response_tx,
-                            || NReplError::Connection(std::io::Error::new(
-                                std::io::ErrorKind::UnexpectedEof,
-                                format!("connection closed: {e}"),
-                            )));
-                        return;
-                    }
-                }
-            }
-            () = tokio::time::sleep_until(deadline) => {
-                // Active eval deadline expired.
-                if let Some(id) = active_eval.clone() {
-                    if let Some(Pending::Eval(state)) = pending.remove(&id) {
-                        let _ = response_tx.send(EvalResponse {
-                            request_id: state.request_id,
-                            outcome: EvalOutcome::Done(Err(NReplError::Timeout {
-                                operation: "eval".to_string(),
-                                duration: state.timeout,
-                            })),
-                        });
-                    }
-                    active_eval = None;
-                    start_next_eval(
-                        &mut writer, &mut pending, &mut eval_queue, &mut active_eval, response_tx,
-                    ).await;
-                }
-            }
-        }
+    /// Cancel `request_id` (an eval, load-file or control op id from this
+    /// worker) when `token` is cancelled.
+    pub fn cancel_on(&self, token: &CancellationToken, request_id: RequestId) {
+        token.link(self.command_tx.clone(), request_id);
     }
-}
-
-/// Dispatch a command: queue evals/load-files; write control ops immediately.
-// One arm per nREPL op; each is irreducible protocol handling, so the match is
-// long but flat.
-/// True when this response terminates its op.
-///
-/// nREPL ends an op with `done`, but a server that rejects the op answers with
-/// `error` or `unknown-op` and never sends `done`, so all three have to retire
-/// the pending entry or the caller waits forever.
-fn op_finished(flags: StatusFlags) -> bool {
-    flags.done || flags.error || flags.unknown_op
-}
-
-/// The error returned when the server does not implement `op`.
-fn unknown_op_err(op: &str) -> NReplError {
-    NReplError::OperationFailed(format!("server does not support {op}"))
-}
-
-/// Write a control request, then park `$entry` under its wire id so the
-/// response can be routed back. On a write failure there is nothing to park,
-/// so the caller is answered with the error immediately.
-macro_rules! send_control {
-    ($writer:expr, $pending:expr, $op_id:expr, $reply:expr, $request:expr, $entry:expr) => {
-        match $writer.send(&$request).await {
-            Ok(()) => {
-                $pending.insert($op_id.wire(), $entry);
-            }
-            Err(e) => {
-                let _ = $reply.send(Err(e));
-            }
-        }
-    };
-}
 
-async fn dispatch_command(
-    cmd: WorkerCommand,
-    writer: &mut NReplWriter,
-    pending: &mut HashMap<String, Pending>,
-    eval_queue: &mut VecDeque<QueuedEval>,
-    active_eval: &mut Option<String>,
-    response_tx: &Sender<EvalResponse>,
-) {
-    match cmd {
-        WorkerCommand::Eval(req) => {
-            let timeout = req.timeout.unwrap_or(DEFAULT_EVAL_TIMEOUT);
-            let request = ops::eval_request_with_location(
-                req.request_id.wire(),
-                req.session.id(),
-                req.code,
-                req.file,
-                req.line,
-                req.column,
-            );
-            enqueue_eval(
-                QueuedEval {
-                    request_id: req.request_id,
-                    request,
-                    timeout,
-                },
-                writer,
-                pending,
-                eval_queue,
-                active_eval,
-                response_tx,
-            )
-            .await;
-        }
-        WorkerCommand::LoadFile(req) => {
-            let request = ops::load_file_request(
-                req.request_id.wire(),
-                req.session.id(),
-                req.file_contents,
-                req.file_path,
-                req.file_name,
-            );
-            enqueue_eval(
-                QueuedEval {
-                    request_id: req.request_id,
-                    request,
-                    timeout: DEFAULT_EVAL_TIMEOUT,
-                },
-                writer,
-                pending,
-                eval_queue,
-                active_eval,
-                response_tx,
-            )
-            .await;
-        }
-        WorkerCommand::Connect(_, reply) => {
-            // Already connected.
-            let _ = reply.send(Err(NReplError::protocol("Already connected")));
-        }
-        WorkerCommand::Shutdown(reply) => {
-            // Handled in the select loop; reply here defensively.
-            let _ = reply.send(Ok(()));
+    /// Drop every buffered response nobody has claimed yet, returning how many
+    /// went. Part of a resync: anything still polling for one of them is
+    /// polling for a result from before the connection was reset.
+    pub fn clear_pending_responses(&mut self) -> usize {
+        while let Ok(response) = self.response_rx.try_recv() {
+            self.pending_responses.insert(response.request_id, response);
         }
-        // Control ops bypass the eval queue.
-        other => dispatch_control(other, writer, pending, eval_queue, response_tx).await,
+        let dropped = self.pending_responses.len();
+        self.pending_responses.clear();
+        dropped
     }
-}
 
-/// Write a control op straight to the socket, parking it in `pending` for its
-/// reply. Bypassing the eval queue is what lets an interrupt or a stdin line
-/// reach the server while an eval is still in flight.
-///
-/// Long by line count because it is a flat dispatch table: seven ops, each
-/// destructured from its own enum variant. Splitting it further would invent a
-/// boundary that does not exist in the protocol.
-#[allow(clippy::too_many_lines)]
-async fn dispatch_control(
-    cmd: WorkerCommand,
-    writer: &mut NReplWriter,
-    pending: &mut HashMap<String, Pending>,
-    eval_queue: &mut VecDeque<QueuedEval>,
-    response_tx: &Sender<EvalResponse>,
-) {
-    match cmd {
-        WorkerCommand::Interrupt {
-            op_id,
-            session,
-            target,
-            reply,
-        } => {
-            let target_wire = target.wire();
-            // If the target eval is still queued (not yet sent), cancel it locally.
-            if let Some(pos) = eval_queue.iter().position(|q| q.request_id == target) {
-                let cancelled = eval_queue.remove(pos).expect("position valid");
-                let _ = response_tx.send(EvalResponse {
-                    request_id: cancelled.request_id,
-                    outcome: EvalOutcome::Done(Ok(interrupted_result())),
-                });
-                let _ = reply.send(Ok(()));
-                return;
-            }
-            // If the target isn't active/pending, the eval already finished:
-            // harmless no-op.
-            if !pending.contains_key(&target_wire) {
-                let _ = reply.send(Ok(()));
-                return;
-            }
-            let request = ops::interrupt_request(op_id.wire(), session.id(), target_wire);
-            send_control!(
-                writer,
-                pending,
-                op_id,
-                reply,
-                request,
-                Pending::Interrupt { reply }
-            );
-        }
-        WorkerCommand::CloneSession { op_id, reply } => {
-            let request = ops::clone_request(op_id.wire());
-            send_control!(
-                writer,
-                pending,
-                op_id,
-                reply,
-                request,
-                Pending::CloneSession {
-                    reply,
-                    new_session: None,
-                }
-            );
-        }
-        WorkerCommand::CloseSession {
-            op_id,
-            session,
-            reply,
-        } => {
-            let request = ops::close_request(op_id.wire(), session.id());
-            send_control!(
-                writer,
-                pending,
-                op_id,
-                reply,
-                request,
-                Pending::CloseSession { reply }
-            );
-        }
-        WorkerCommand::Stdin {
-            op_id,
-            session,
-            data,
-            reply,
-        } => {
-            // Fire-and-forget: nREPL does not ack stdin.
-            let request = ops::stdin_request(op_id.wire(), session.id(), data);
-            let _ = reply.send(writer.send(&request).await);
-        }
-        WorkerCommand::Completions {
-            op_id,
-            session,
-            prefix,
-            ns,
-            complete_fn,
-            reply,
-        } => {
-            let request =
-                ops::completions_request(op_id.wire(), session.id(), prefix, ns, complete_fn);
-            send_control!(
-                writer,
-                pending,
-                op_id,
-                reply,
-                request,
-                Pending::Completions {
-                    reply,
-                    candidates: Vec::new(),
-                }
-            );
-        }
-        WorkerCommand::Lookup {
-            op_id,
-            session,
-            sym,
-            ns,
-            lookup_fn,
-            reply,
-        } => {
-            let request = ops::lookup_request(op_id.wire(), session.id(), sym, ns, lookup_fn);
-            send_control!(
-                writer,
-                pending,
-                op_id,
-                reply,
-                request,
-                Pending::Lookup { reply, last: None }
-            );
-        }
-        WorkerCommand::Describe {
-            op_id,
-            verbose,
-            reply,
-        } => {
-            let request = ops::describe_request(op_id.wire(), Some(verbose));
-            send_control!(
-                writer,
-                pending,
-                op_id,
-                reply,
-                request,
-                Pending::Describe { reply, last: None }
-            );
-        }
-        WorkerCommand::LsSessions { op_id, reply } => {
-            let request = ops::ls_sessions_request(op_id.wire());
-            send_control!(
-                writer,
-                pending,
-                op_id,
+    /// Close every tracked session on the server (see
+    /// [`sessions`](Self::sessions)), best-effort: all the closes are sent at
+    /// once and a failure does not stop the rest. Returns the sessions that
+    /// could not be closed, with why; empty when all were. Blocks for up to
+    /// the `close` [`timeouts`](Self::timeouts) in all, however many sessions
+    /// there are.
+    pub fn close_all_sessions(&self) -> Vec<(Session, NReplError)> {
+        let timeout = self.timeouts.close;
+        let mut failed = Vec::new();
+        let mut closing = Vec::new();
+        for info in self.sessions.snapshot() {
+            let (reply, replies) = channel();
+            let sent = self.command_tx.send(WorkerCommand::CloseSession {
+                op_id: self.next_id(),
+                session: info.session.clone(),
                 reply,
-                request,
-                Pending::LsSessions {
-                    reply,
-                    sessions: Vec::new(),
-                }
-            );
-        }
-        WorkerCommand::Eval(_)
-        | WorkerCommand::LoadFile(_)
-        | WorkerCommand::Connect(..)
-        | WorkerCommand::Shutdown(_) => {
-            unreachable!("dispatch_command handles these before delegating")
-        }
-    }
-}
-
-/// Queue an eval; if nothing is running, send it now and make it active.
-async fn enqueue_eval(
-    queued: QueuedEval,
-    writer: &mut NReplWriter,
-    pending: &mut HashMap<String, Pending>,
-    eval_queue: &mut VecDeque<QueuedEval>,
-    active_eval: &mut Option<String>,
-    response_tx: &Sender<EvalResponse>,
-) {
-    eval_queue.push_back(queued);
-    if active_eval.is_none() {
-        start_next_eval(writer, pending, eval_queue, active_eval, response_tx).await;
-    }
-}
-
-/// Pop and start the next queued eval (if any), reporting an immediate write
-/// failure via the response channel.
-async fn start_next_eval(
-    writer: &mut NReplWriter,
-    pending: &mut HashMap<String, Pending>,
-    eval_queue: &mut VecDeque<QueuedEval>,
-    active_eval: &mut Option<String>,
-    response_tx: &Sender<EvalResponse>,
-) {
-    while let Some(queued) = eval_queue.pop_front() {
-        let wire = queued.request_id.wire();
-        match writer.send(&queued.request).await {
-            Ok(()) => {
-                pending.insert(
-                    wire.clone(),
-                    Pending::Eval(EvalState {
-                        request_id: queued.request_id,
-                        acc: EvalAccumulator::new(),
-                        timeout: queued.timeout,
-                        deadline: Instant::now() + queued.timeout,
-                        parked: false,
-                    }),
-                );
-                *active_eval = Some(wire);
-                return;
-            }
-            Err(e) => {
-                // Failed to send; report and try the next queued eval.
-                let _ = response_tx.send(EvalResponse {
-                    request_id: queued.request_id,
-                    outcome: EvalOutcome::Done(Err(e)),
-                });
-            }
-        }
-    }
-}
-
-/// Route one decoded response to its pending op by request id.
-// One branch per pending op kind; each is irreducible protocol handling, so the
-// match is long but flat.
-#[allow(clippy::too_many_lines)]
-async fn route_response(
-    response: Response,
-    writer: &mut NReplWriter,
-    pending: &mut HashMap<String, Pending>,
-    eval_queue: &mut VecDeque<QueuedEval>,
-    active_eval: &mut Option<String>,
-    response_tx: &Sender<EvalResponse>,
-) {
-    let id = response.id.clone();
-    let Some(entry) = pending.get_mut(&id) else {
-        // Unknown / timed-out id - discard.
-        return;
-    };
-
-    let flags = classify(&response.status);
-
-    match entry {
-        Pending::Eval(state) => {
-            // Unknown-op on an eval shouldn't happen, but treat as an error.
-            if flags.unknown_op {
-                let request_id = state.request_id;
-                pending.remove(&id);
-                let _ = response_tx.send(EvalResponse {
-                    request_id,
-                    outcome: EvalOutcome::Done(Err(unknown_op_err("eval"))),
-                });
-                if active_eval.as_deref() == Some(id.as_str()) {
-                    *active_eval = None;
-                    start_next_eval(writer, pending, eval_queue, active_eval, response_tx).await;
-                }
-                return;
-            }
-
-            // A response means the server is making progress: if we were parked
-            // on need-input, resume (reset the deadline).
-            if state.parked {
-                state.parked = false;
-                state.deadline = Instant::now() + state.timeout;
-            }
-
-            let request_id = state.request_id;
-            let need_input = flags.need_input;
-            let done = flags.done;
-
-            if let Err(e) = state.acc.push(response) {
-                // Backpressure limit exceeded - fail the eval.
-                pending.remove(&id);
-                let _ = response_tx.send(EvalResponse {
-                    request_id,
-                    outcome: EvalOutcome::Done(Err(e)),
-                });
-                if active_eval.as_deref() == Some(id.as_str()) {
-                    *active_eval = None;
-                    start_next_eval(writer, pending, eval_queue, active_eval, response_tx).await;
-                }
-                return;
-            }
-
-            if need_input && !done {
-                // Park the eval; keep it active and do not advance the queue.
-                // Drain the output captured so far so the client can render it
-                // (e.g. a prompt string) before opening its stdin box; draining
-                // prevents it being re-rendered at `done`.
-                let (output, error) = if let Some(Pending::Eval(state)) = pending.get_mut(&id) {
-                    state.parked = true;
-                    state.acc.drain_output()
-                } else {
-                    (Vec::new(), Vec::new())
-                };
-                let _ = response_tx.send(EvalResponse {
-                    request_id,
-                    outcome: EvalOutcome::NeedInput { output, error },
-                });
-                return;
-            }
-
-            if done {
-                if let Some(Pending::Eval(state)) = pending.remove(&id) {
-                    let _ = response_tx.send(EvalResponse {
-                        request_id,
-                        outcome: EvalOutcome::Done(Ok(state.acc.finish())),
-                    });
-                }
-                if active_eval.as_deref() == Some(id.as_str()) {
-                    *active_eval = None;
-                    start_next_eval(writer, pending, eval_queue, active_eval, response_tx).await;
-                }
-            }
-        }
-        Pending::CloneSession { new_session, .. } => {
-            if let Some(s) = response.new_session.clone() {
-                *new_session = Some(s);
-            }
-            if op_finished(flags)
-                && let Some(Pending::CloneSession { reply, new_session }) = pending.remove(&id)
-            {
-                let result = match new_session {
-                    Some(s) => Ok(Session::from_server_id(s)),
-                    None => Err(NReplError::protocol(
-                        "Missing new-session in clone response",
-                    )),
-                };
-                let _ = reply.send(result);
-            }
-        }
-        Pending::CloseSession { .. } => {
-            if op_finished(flags)
-                && let Some(Pending::CloseSession { reply }) = pending.remove(&id)
-            {
-                let _ = reply.send(op_unit_result(&response, flags, "close"));
-            }
-        }
-        Pending::Interrupt { .. } => {
-            if op_finished(flags)
-                && let Some(Pending::Interrupt { reply }) = pending.remove(&id)
-            {
-                let _ = reply.send(op_unit_result(&response, flags, "interrupt"));
-            }
-        }
-        Pending::Completions { candidates, .. } => {
-            if let Some(c) = response.completions.clone() {
-                candidates.extend(c);
-            }
-            if op_finished(flags)
-                && let Some(Pending::Completions { reply, candidates }) = pending.remove(&id)
-            {
-                let result = if flags.unknown_op {
-                    Err(unknown_op_err("completions"))
-                } else {
-                    Ok(candidates)
-                };
-                let _ = reply.send(result);
-            }
-        }
-        Pending::Lookup { last, .. } => {
-            *last = Some(response.clone());
-            if op_finished(flags)
-                && let Some(Pending::Lookup { reply, last }) = pending.remove(&id)
-            {
-                let result = if flags.unknown_op {
-                    Err(unknown_op_err("lookup"))
-                } else {
-                    last.ok_or_else(|| NReplError::protocol("No lookup response"))
-                };
-                let _ = reply.send(result);
-            }
-        }
-        Pending::Describe { last, .. } => {
-            *last = Some(response.clone());
-            if op_finished(flags)
-                && let Some(Pending::Describe { reply, last }) = pending.remove(&id)
-            {
-                let result = if flags.unknown_op {
-                    Err(unknown_op_err("describe"))
-                } else {
-                    last.ok_or_else(|| NReplError::protocol("No describe response"))
-                };
-                let _ = reply.send(result);
+            });
+            match sent {
+                Ok(()) => closing.push((info.session, replies)),
+                Err(_) => failed.push((
+                    info.session,
+                    NReplError::Connection(std::io::Error::other("Worker thread disconnected")),
+                )),
             }
         }
-        Pending::LsSessions { sessions, .. } => {
-            if let Some(s) = response.sessions.clone() {
-                sessions.extend(s);
-            }
-            if op_finished(flags)
-                && let Some(Pending::LsSessions { reply, sessions }) = pending.remove(&id)
-            {
-                let result = if flags.unknown_op {
-                    Err(unknown_op_err("ls-sessions"))
-                } else {
-                    Ok(sessions)
-                };
-                let _ = reply.send(result);
+        let give_up = Instant::now() + timeout;
+        for (session, replies) in closing {
+            let wait = give_up.saturating_duration_since(Instant::now());
+            match replies.recv_timeout(wait) {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => failed.push((session, e)),
+                Err(_) => failed.push((session, NReplError::timeout("close", timeout))),
             }
         }
+        failed
     }
-}
 
-/// Build the unit result for a control op that completed, honouring `err`,
-/// `unknown-op` and `error` status (conformance #3).
-fn op_unit_result(response: &Response, flags: StatusFlags, op: &str) -> Result<(), NReplError> {
-    if flags.unknown_op {
-        return Err(unknown_op_err(op));
-    }
-    if let Some(err) = &response.err {
-        return Err(NReplError::OperationFailed(format!("{op} failed: {err}")));
+    /// Drop the connection now: the socket is closed without closing any
+    /// session, everything in flight fails with a connection error and the
+    /// worker thread exits. For a server that has stopped answering, where
+    /// closing sessions one by one would only wait out each timeout.
+    /// Non-blocking, and a no-op once the worker has stopped.
+    pub fn abort(&self) {
+        self.abort.send_replace(true);
     }
-    if flags.error {
-        return Err(NReplError::OperationFailed(format!("{op} failed")));
-    }
-    Ok(())
-}
 
-/// Result delivered when a queued eval is cancelled by an interrupt.
-fn interrupted_result() -> EvalResult {
-    let mut r = EvalResult::new();
-    r.interrupted = true;
-    r
+    /// Shutdown the worker thread (non-blocking). Sessions stay open on the
+    /// server; close them first with
+    /// [`close_all_sessions`](Self::close_all_sessions).
+    pub fn shutdown(&mut self) {
+        let _ = self.command_tx.send(WorkerCommand::Shutdown(channel().0));
+    }
 }
 
-/// Fail every pending op and queued eval with the given error (connection lost
-/// / shutdown).
-fn fail_all_pending(
-    pending: &mut HashMap<String, Pending>,
-    eval_queue: &mut VecDeque<QueuedEval>,
-    response_tx: &Sender<EvalResponse>,
-    make_err: impl Fn() -> NReplError,
-) {
-    for (_id, p) in pending.drain() {
-        match p {
-            Pending::Eval(state) => {
-                let _ = response_tx.send(EvalResponse {
-                    request_id: state.request_id,
-                    outcome: EvalOutcome::Done(Err(make_err())),
-                });
-            }
-            Pending::CloneSession { reply, .. } => {
-                let _ = reply.send(Err(make_err()));
-            }
-            Pending::CloseSession { reply } | Pending::Interrupt { reply } => {
-                let _ = reply.send(Err(make_err()));
-            }
-            Pending::Completions { reply, .. } => {
-                let _ = reply.send(Err(make_err()));
-            }
-            Pending::Lookup { reply, .. } | Pending::Describe { reply, .. } => {
-                let _ = reply.send(Err(make_err()));
-            }
-            Pending::LsSessions { reply, .. } => {
-                let _ = reply.send(Err(make_err()));
-            }
-        }
-    }
-    for queued in eval_queue.drain(..) {
-        let _ = response_tx.send(EvalResponse {
-            request_id: queued.request_id,
-            outcome: EvalOutcome::Done(Err(make_err())),
-        });
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.shutdown();
     }
 }
 
@@ -1191,6 +2461,69 @@ mod tests {
         assert_eq!(RequestId::new(7).wire(), "req-7");
     }
 
+    #[test]
+    fn test_worker_config_defaults_to_no_interrupt() {
+        assert!(!WorkerConfig::default().interrupt_on_timeout);
+        assert!(
+            WorkerConfig::default()
+                .interrupt_on_timeout(true)
+                .interrupt_on_timeout
+        );
+    }
+
+    #[test]
+    fn test_unconnected_worker_is_disconnected() {
+        let worker = Worker::new();
+        assert_eq!(worker.connection_state(), ConnectionState::Disconnected);
+        // Resolves straight away rather than waiting for a connection.
+        tokio_test::block_on(worker.on_disconnect());
+    }
+
+    #[test]
+    fn test_cancellation_token_sends_cancel_once() {
+        let (command_tx, mut command_rx) = unbounded_channel();
+        let token = CancellationToken::new();
+        token.link(command_tx.clone(), RequestId::new(1));
+        let link = command_rx.try_recv();
+        assert!(matches!(&link, Ok(WorkerCommand::Watch(l)) if l.target == RequestId::new(1)));
+
+        token.cancel();
+        token.cancel();
+        assert!(token.is_cancelled());
+        assert!(matches!(
+            command_rx.try_recv(),
+            Ok(WorkerCommand::Cancel { target }) if target == RequestId::new(1)
+        ));
+        drop(link);
+        assert!(command_rx.try_recv().is_err(), "cancel is idempotent");
+
+        // Linking to an already-cancelled token cancels immediately.
+        token.link(command_tx, RequestId::new(2));
+        assert!(matches!(
+            command_rx.try_recv(),
+            Ok(WorkerCommand::Cancel { target }) if target == RequestId::new(2)
+        ));
+    }
+
+    #[test]
+    fn test_cancellation_token_forgets_finished_requests() {
+        let (command_tx, mut command_rx) = unbounded_channel();
+        let token = CancellationToken::new();
+        for id in 0..1000 {
+            token.link(command_tx.clone(), RequestId::new(id));
+            // The worker drops the link once the request is done.
+            drop(command_rx.try_recv());
+        }
+        assert_eq!(token.linked(), 0);
+
+        token.link(command_tx, RequestId::new(1000));
+        let in_flight = command_rx.try_recv();
+        assert_eq!(token.linked(), 1);
+        token.cancel();
+        assert_eq!(token.linked(), 0);
+        drop(in_flight);
+    }
+
     #[test]
     fn test_max_pending_responses_constant() {
         assert_eq!(
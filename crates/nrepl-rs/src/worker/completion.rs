@@ -0,0 +1,542 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Completion and symbol lookup, including completing `alias/name` by first
+//! resolving the alias.
+
+use super::RequestId;
+use super::pending::{CompletionOp, Pending, op_finished, send_control, unknown_op_err};
+use super::sessions::CljsSessions;
+use crate::cache::{CacheKey, CacheSlot, CachedReply, ResponseCache};
+use crate::connection::NReplWriter;
+use crate::error::NReplError;
+use crate::flavor::ServerInfo;
+use crate::message::{CompletionCandidate, NsAliases, Request, Response, StatusFlags};
+use crate::ops;
+use crate::session::Session;
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
+/// Answered [`CompletionCommand::NsAliases`] queries, keyed by session wire id
+/// and namespace.
+pub(super) type NsCache = HashMap<(String, String), NsAliases>;
+
+/// A [`CompletionCommand::AliasedCompletions`] waiting on the aliases of the
+/// namespace it completes in.
+pub(super) struct AliasResolve {
+    session: Session,
+    alias: String,
+    /// The part of the prefix after `alias/`.
+    rest: String,
+    ns: Option<String>,
+    /// The namespace whose aliases are fetched: `ns`, else the session's.
+    in_ns: String,
+    complete_fn: Option<String>,
+    pub(super) reply: Sender<Result<Vec<CompletionCandidate>, NReplError>>,
+    value: Option<String>,
+    cache: Option<CacheSlot>,
+}
+
+/// Whether `session`'s completions go to cider-nrepl's `complete` rather
+/// than nREPL's `completions`: it is a ClojureScript session, or the server
+/// only has `complete` (babashka, nbb).
+fn uses_complete(session: &str, cljs_sessions: &CljsSessions, server: &ServerInfo) -> bool {
+    cljs_sessions.contains(session) || (server.lacks("completions") && !server.lacks("complete"))
+}
+
+/// Split `alias/rest` when the alias part is a plain symbol. Anything else is
+/// left for the server to complete as given (and is never spliced into code).
+fn split_alias(prefix: &str) -> Option<(&str, &str)> {
+    let (alias, rest) = prefix.split_once('/')?;
+    is_plain_symbol(alias).then_some((alias, rest))
+}
+
+/// True when `s` is a symbol safe to splice into generated code.
+pub(super) fn is_plain_symbol(s: &str) -> bool {
+    let symbol_char = |c: char| c.is_alphanumeric() || "-_.*+!?<>=$&%".contains(c);
+    !s.is_empty() && s.chars().all(symbol_char)
+}
+
+/// Clojure form printing `ns`'s aliases and non-`clojure.core` refers in the
+/// format [`NsAliases::parse`] reads. Throws if `ns` is not loaded.
+fn ns_aliases_form(ns: &str) -> String {
+    format!(
+        "(let [n (the-ns '{ns}) \
+               pairs (fn [m] (apply str (interpose \" \" (mapcat identity m))))] \
+           (str (pairs (map (fn [[a t]] [a (ns-name t)]) (ns-aliases n))) \
+                \";\" \
+                (pairs (keep (fn [[s v]] \
+                               (let [o (some-> v meta :ns ns-name)] \
+                                 (when (and o (not= o 'clojure.core)) [s o]))) \
+                             (ns-refers n)))))"
+    )
+}
+
+/// A completions request for `alias/rest`, in the namespace `target` the alias
+/// names if it resolved, else for the prefix as typed. Also returns the
+/// `(alias, target)` the candidates are qualified back with.
+fn alias_completions_request(
+    id: String,
+    session: &Session,
+    alias: String,
+    rest: String,
+    target: Option<String>,
+    ns: Option<String>,
+    complete_fn: Option<String>,
+) -> (Request, Option<(String, String)>) {
+    let (prefix, ns, alias) = match target {
+        Some(target) => (rest, Some(target.clone()), Some((alias, target))),
+        None => (format!("{alias}/{rest}"), ns, None),
+    };
+    let request = ops::completions_request(id, session.id(), prefix, ns, complete_fn);
+    (request, alias)
+}
+
+/// The completion and lookup variants of
+/// [`WorkerCommand`](super::WorkerCommand), which [`dispatch`] writes.
+pub(super) enum CompletionCommand {
+    Completions {
+        op_id: RequestId,
+        session: Session,
+        prefix: String,
+        ns: Option<String>,
+        complete_fn: Option<String>,
+        reply: Sender<Result<Vec<CompletionCandidate>, NReplError>>,
+    },
+    AliasedCompletions {
+        op_id: RequestId,
+        session: Session,
+        prefix: String,
+        ns: Option<String>,
+        complete_fn: Option<String>,
+        reply: Sender<Result<Vec<CompletionCandidate>, NReplError>>,
+    },
+    Lookup {
+        op_id: RequestId,
+        session: Session,
+        sym: String,
+        ns: Option<String>,
+        lookup_fn: Option<String>,
+        reply: Sender<Result<Response, NReplError>>,
+    },
+    NsAliases {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        reply: Sender<Result<NsAliases, NReplError>>,
+    },
+}
+
+/// Write a completion, lookup or namespace-aliases query, answering it from
+/// the cache instead when the cache has it.
+pub(super) async fn dispatch(
+    cmd: CompletionCommand,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    ns_cache: &NsCache,
+    cljs_sessions: &CljsSessions,
+    server: &ServerInfo,
+    cache: Option<&ResponseCache>,
+) {
+    match cmd {
+        CompletionCommand::Completions {
+            op_id,
+            session,
+            prefix,
+            ns,
+            complete_fn,
+            reply,
+        } => {
+            let key = || CacheKey::Completions {
+                prefix: prefix.clone(),
+                ns: ns.clone(),
+                complete_fn: complete_fn.clone(),
+                aliased: false,
+            };
+            let cache = match cache.map(|c| c.completions(session.id(), key())) {
+                Some(Ok(candidates)) => {
+                    let _ = reply.send(Ok(candidates));
+                    return;
+                }
+                Some(Err(slot)) => Some(slot),
+                None => None,
+            };
+            let request = if uses_complete(session.id(), cljs_sessions, server) {
+                ops::complete_request(op_id.wire(), session.id(), prefix, ns)
+            } else {
+                ops::completions_request(op_id.wire(), session.id(), prefix, ns, complete_fn)
+            };
+            send_control!(
+                writer,
+                pending,
+                op_id,
+                reply,
+                request,
+                Pending::Completion(CompletionOp::Completions {
+                    reply,
+                    candidates: Vec::new(),
+                    alias: None,
+                    cache,
+                })
+            );
+        }
+        CompletionCommand::AliasedCompletions {
+            op_id,
+            session,
+            prefix,
+            ns,
+            complete_fn,
+            reply,
+        } => {
+            let key = || CacheKey::Completions {
+                prefix: prefix.clone(),
+                ns: ns.clone(),
+                complete_fn: complete_fn.clone(),
+                aliased: true,
+            };
+            let cache = match cache.map(|c| c.completions(session.id(), key())) {
+                Some(Ok(candidates)) => {
+                    let _ = reply.send(Ok(candidates));
+                    return;
+                }
+                Some(Err(slot)) => Some(slot),
+                None => None,
+            };
+            if uses_complete(session.id(), cljs_sessions, server) {
+                // `complete` resolves aliases itself, and in a ClojureScript
+                // session the alias lookup (a Clojure eval) would be wrong.
+                let request = ops::complete_request(op_id.wire(), session.id(), prefix, ns);
+                send_control!(
+                    writer,
+                    pending,
+                    op_id,
+                    reply,
+                    request,
+                    Pending::Completion(CompletionOp::Completions {
+                        reply,
+                        candidates: Vec::new(),
+                        alias: None,
+                        cache,
+                    })
+                );
+            } else if let Some((alias, rest)) = split_alias(&prefix)
+                && let Some(in_ns) = ns
+                    .clone()
+                    .or_else(|| server.fallback_ns(session.id()))
+                    .filter(|n| is_plain_symbol(n))
+            {
+                if let Some(aliases) = ns_cache.get(&(session.id().to_string(), in_ns.clone())) {
+                    let (request, alias) = alias_completions_request(
+                        op_id.wire(),
+                        &session,
+                        alias.to_string(),
+                        rest.to_string(),
+                        aliases.aliases.get(alias).cloned(),
+                        ns,
+                        complete_fn,
+                    );
+                    send_control!(
+                        writer,
+                        pending,
+                        op_id,
+                        reply,
+                        request,
+                        Pending::Completion(CompletionOp::Completions {
+                            reply,
+                            candidates: Vec::new(),
+                            alias,
+                            cache,
+                        })
+                    );
+                } else {
+                    // Fetch the aliases as a `ns-aliases` query would, so
+                    // the answer is cached for both.
+                    let request =
+                        ops::sessionless_eval_request(op_id.wire(), ns_aliases_form(&in_ns), None);
+                    let entry =
+                        Pending::Completion(CompletionOp::AliasResolve(Box::new(AliasResolve {
+                            session,
+                            alias: alias.to_string(),
+                            rest: rest.to_string(),
+                            ns,
+                            in_ns,
+                            complete_fn,
+                            reply: reply.clone(),
+                            value: None,
+                            cache,
+                        })));
+                    send_control!(writer, pending, op_id, reply, request, entry);
+                }
+            } else {
+                let request =
+                    ops::completions_request(op_id.wire(), session.id(), prefix, ns, complete_fn);
+                send_control!(
+                    writer,
+                    pending,
+                    op_id,
+                    reply,
+                    request,
+                    Pending::Completion(CompletionOp::Completions {
+                        reply,
+                        candidates: Vec::new(),
+                        alias: None,
+                        cache,
+                    })
+                );
+            }
+        }
+        CompletionCommand::Lookup {
+            op_id,
+            session,
+            sym,
+            ns,
+            lookup_fn,
+            reply,
+        } => {
+            let key = CacheKey::Lookup {
+                sym: sym.clone(),
+                ns: ns.clone(),
+                lookup_fn: lookup_fn.clone(),
+            };
+            let cache = match cache.map(|c| c.lookup(session.id(), key)) {
+                Some(Ok(response)) => {
+                    let _ = reply.send(Ok(response));
+                    return;
+                }
+                Some(Err(slot)) => Some(slot),
+                None => None,
+            };
+            let request = ops::lookup_request(op_id.wire(), session.id(), sym, ns, lookup_fn);
+            send_control!(
+                writer,
+                pending,
+                op_id,
+                reply,
+                request,
+                Pending::Completion(CompletionOp::Lookup {
+                    reply,
+                    last: None,
+                    cache,
+                })
+            );
+        }
+        CompletionCommand::NsAliases {
+            op_id,
+            session,
+            ns,
+            reply,
+        } => {
+            let session = session.id().to_string();
+            if let Some(cached) = ns_cache.get(&(session.clone(), ns.clone())) {
+                let _ = reply.send(Ok(cached.clone()));
+            } else if !is_plain_symbol(&ns) {
+                let _ = reply.send(Err(NReplError::OperationFailed(format!(
+                    "not a namespace name: {ns}"
+                ))));
+            } else {
+                let request =
+                    ops::sessionless_eval_request(op_id.wire(), ns_aliases_form(&ns), None);
+                send_control!(
+                    writer,
+                    pending,
+                    op_id,
+                    reply,
+                    request,
+                    Pending::Completion(CompletionOp::NsAliases {
+                        session,
+                        ns,
+                        reply,
+                        value: None,
+                        err: String::new(),
+                    })
+                );
+            }
+        }
+    }
+}
+
+/// Route a response to a completion, lookup or namespace-aliases query.
+// One arm per kind of query; each is irreducible protocol handling, so the
+// match is long but flat.
+#[allow(clippy::too_many_lines)]
+pub(super) async fn route(
+    response: Response,
+    flags: StatusFlags,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    ns_cache: &mut NsCache,
+) {
+    let id = response.id.clone();
+    let Some(Pending::Completion(entry)) = pending.get_mut(&id) else {
+        return;
+    };
+    match entry {
+        CompletionOp::Completions { candidates, .. } => {
+            if let Some(c) = response.completions.clone() {
+                candidates.extend(c);
+            }
+            if op_finished(flags)
+                && let Some(Pending::Completion(CompletionOp::Completions {
+                    reply,
+                    candidates,
+                    alias,
+                    cache,
+                })) = pending.remove(&id)
+            {
+                let result = if flags.unknown_op {
+                    Err(unknown_op_err("completions"))
+                } else if let Some((alias, target)) = alias {
+                    // Completing in the target ns also offers everything it
+                    // refers (clojure.core, ...); keep only its own vars.
+                    Ok(candidates
+                        .into_iter()
+                        .filter(|c| c.ns.as_deref().is_none_or(|n| n == target))
+                        .map(|c| CompletionCandidate {
+                            candidate: format!("{alias}/{}", c.candidate),
+                            ..c
+                        })
+                        .collect())
+                } else {
+                    Ok(candidates)
+                };
+                if let (Ok(candidates), Some(slot)) = (&result, cache) {
+                    slot.fill(CachedReply::Completions(candidates.clone()));
+                }
+                let _ = reply.send(result);
+            }
+        }
+        CompletionOp::AliasResolve(state) => {
+            if let Some(value) = &response.value {
+                state.value = Some(value.clone());
+            }
+            if op_finished(flags)
+                && let Some(Pending::Completion(CompletionOp::AliasResolve(state))) =
+                    pending.remove(&id)
+            {
+                let AliasResolve {
+                    session,
+                    alias,
+                    rest,
+                    ns,
+                    in_ns,
+                    complete_fn,
+                    reply,
+                    value,
+                    cache,
+                } = *state;
+                // An error (namespace not loaded, not Clojure) just means
+                // there is nothing to resolve: complete the prefix as typed.
+                let aliases = value
+                    .filter(|_| !flags.error && !flags.unknown_op)
+                    .as_deref()
+                    .and_then(NsAliases::parse);
+                let target = aliases
+                    .as_ref()
+                    .and_then(|a| a.aliases.get(&alias).cloned());
+                if let Some(aliases) = aliases {
+                    ns_cache.insert((session.id().to_string(), in_ns), aliases);
+                }
+                // The completions go out under the caller's id, now that the
+                // alias query under it is done.
+                let (request, alias) = alias_completions_request(
+                    id.clone(),
+                    &session,
+                    alias,
+                    rest,
+                    target,
+                    ns,
+                    complete_fn,
+                );
+                match writer.send(&request).await {
+                    Ok(()) => {
+                        pending.insert(
+                            id,
+                            Pending::Completion(CompletionOp::Completions {
+                                reply,
+                                candidates: Vec::new(),
+                                alias,
+                                cache,
+                            }),
+                        );
+                    }
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                    }
+                }
+            }
+        }
+        CompletionOp::Lookup { last, .. } => {
+            *last = Some(response.clone());
+            if op_finished(flags)
+                && let Some(Pending::Completion(CompletionOp::Lookup { reply, last, cache })) =
+                    pending.remove(&id)
+            {
+                let result = if flags.unknown_op {
+                    Err(unknown_op_err("lookup"))
+                } else {
+                    last.ok_or_else(|| NReplError::protocol("No lookup response"))
+                };
+                if let (Ok(response), Some(slot)) = (&result, cache) {
+                    slot.fill(CachedReply::Lookup(Box::new(response.clone())));
+                }
+                let _ = reply.send(result);
+            }
+        }
+        CompletionOp::NsAliases { value, err, .. } => {
+            if let Some(v) = &response.value {
+                *value = Some(v.clone());
+            }
+            if let Some(e) = &response.err {
+                err.push_str(e);
+            }
+            if op_finished(flags)
+                && let Some(Pending::Completion(CompletionOp::NsAliases {
+                    session,
+                    ns,
+                    reply,
+                    value,
+                    err,
+                })) = pending.remove(&id)
+            {
+                let result = match value.as_deref().and_then(NsAliases::parse) {
+                    Some(aliases) if !flags.error => {
+                        ns_cache.insert((session, ns), aliases.clone());
+                        Ok(aliases)
+                    }
+                    // `the-ns` throws for a namespace that is not loaded; pass
+                    // the server's message on.
+                    _ => Err(NReplError::OperationFailed(format!(
+                        "could not read aliases of {ns}: {}",
+                        err.trim()
+                    ))),
+                };
+                let _ = reply.send(result);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_alias() {
+        assert_eq!(split_alias("str/sta"), Some(("str", "sta")));
+        assert_eq!(split_alias("str/"), Some(("str", "")));
+        assert_eq!(split_alias("map"), None);
+        assert_eq!(split_alias("/foo"), None);
+        // Never splice anything but a plain symbol into the lookup form.
+        assert_eq!(split_alias("a)(b/x"), None);
+        assert_eq!(split_alias("a b/x"), None);
+    }
+}
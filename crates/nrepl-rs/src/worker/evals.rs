@@ -0,0 +1,505 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Evals and load-files: queued behind one another, sent one at a time,
+//! and retired by their `done`, a timeout, an interrupt or a cancel.
+
+use super::completion::NsCache;
+use super::pending::{
+    EvalState, Pending, QueuedEval, StreamOp, fail_all_pending, fail_pending, op_finished,
+    op_unit_result, send_control, unknown_op_err,
+};
+use super::sessions::CljsSessions;
+use super::streams::{stop_tap, unsubscribe_out};
+use super::{
+    DEFAULT_EVAL_TIMEOUT, EvalOutcome, EvalRequest, EvalResponse, LoadFileRequest, OutputOptions,
+    RequestId, ResponseSender, ResyncReport,
+};
+use crate::cache::ResponseCache;
+use crate::cljs::CLJS_QUIT;
+use crate::connection::{NReplReader, NReplWriter};
+use crate::error::NReplError;
+use crate::events::{DebugEventKind, EventLog};
+use crate::flavor::ServerInfo;
+use crate::message::{EvalResult, Response, StatusFlags, classify};
+use crate::ops;
+use crate::session::Session;
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Longest a resync drains the socket for before treating it as quiet.
+const RESYNC_DRAIN_IDLE: Duration = Duration::from_millis(100);
+
+/// Most a resync will discard from the socket (matches `MAX_RESPONSE_SIZE`).
+const RESYNC_DRAIN_LIMIT: usize = 10 * 1024 * 1024;
+
+/// How long a resync waits for the liveness `describe`.
+const RESYNC_DESCRIBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Queue an eval behind any running one. A `:cljs/quit` ends the session's
+/// ClojureScript REPL, and whatever the eval redefines goes stale in the
+/// cache.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn queue_eval(
+    req: EvalRequest,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    eval_queue: &mut VecDeque<QueuedEval>,
+    active_eval: &mut Option<String>,
+    response_tx: &ResponseSender,
+    cljs_sessions: &mut CljsSessions,
+    server: &ServerInfo,
+    rich_content: bool,
+    output_options: &OutputOptions,
+    cache: Option<&ResponseCache>,
+) {
+    if let Some(cache) = cache {
+        cache.invalidate(&req.session);
+    }
+    if req.code.trim() == CLJS_QUIT {
+        cljs_sessions.remove(req.session.id());
+    }
+    let timeout = req.timeout.unwrap_or_else(|| {
+        server
+            .profile()
+            .map_or(DEFAULT_EVAL_TIMEOUT, |p| p.flavor.default_eval_timeout())
+    });
+    let mut request = ops::eval_request_with_location(
+        req.request_id.wire(),
+        req.session.id(),
+        req.code,
+        req.file,
+        req.line,
+        req.column,
+    );
+    request.ns = req.ns;
+    request.print_quota = output_options.print_quota;
+    if rich_content {
+        request.content_type = Some(true);
+    }
+    let mut acc = output_options.accumulator(req.request_id);
+    acc.expect_tags(req.tags.keys().cloned());
+    request.tags = req.tags;
+    enqueue_eval(
+        QueuedEval {
+            request_id: req.request_id,
+            request,
+            session: req.session.id().to_string(),
+            timeout,
+            acc,
+        },
+        writer,
+        pending,
+        eval_queue,
+        active_eval,
+        response_tx,
+    )
+    .await;
+}
+
+/// Queue a load-file like an eval. Loading may change any namespace's
+/// aliases, so the alias cache is dropped whole.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn queue_load_file(
+    req: LoadFileRequest,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    eval_queue: &mut VecDeque<QueuedEval>,
+    active_eval: &mut Option<String>,
+    response_tx: &ResponseSender,
+    ns_cache: &mut NsCache,
+    output_options: &OutputOptions,
+    cache: Option<&ResponseCache>,
+) {
+    ns_cache.clear();
+    if let Some(cache) = cache {
+        cache.invalidate(&req.session);
+    }
+    let request = ops::load_file_request(
+        req.request_id.wire(),
+        req.session.id(),
+        req.file_contents,
+        req.file_path,
+        req.file_name,
+    );
+    enqueue_eval(
+        QueuedEval {
+            request_id: req.request_id,
+            request,
+            session: req.session.id().to_string(),
+            timeout: DEFAULT_EVAL_TIMEOUT,
+            acc: output_options.accumulator(req.request_id),
+        },
+        writer,
+        pending,
+        eval_queue,
+        active_eval,
+        response_tx,
+    )
+    .await;
+}
+
+/// Queue an eval; if nothing is running, send it now and make it active.
+pub(super) async fn enqueue_eval(
+    queued: QueuedEval,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    eval_queue: &mut VecDeque<QueuedEval>,
+    active_eval: &mut Option<String>,
+    response_tx: &ResponseSender,
+) {
+    eval_queue.push_back(queued);
+    if active_eval.is_none() {
+        start_next_eval(writer, pending, eval_queue, active_eval, response_tx).await;
+    }
+}
+
+/// Pop and start the next queued eval (if any), reporting an immediate write
+/// failure via the response channel.
+pub(super) async fn start_next_eval(
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    eval_queue: &mut VecDeque<QueuedEval>,
+    active_eval: &mut Option<String>,
+    response_tx: &ResponseSender,
+) {
+    while let Some(mut queued) = eval_queue.pop_front() {
+        let wire = queued.request_id.wire();
+        match writer.send(&queued.request).await {
+            Ok(()) => {
+                queued.acc.mark_sent();
+                pending.insert(
+                    wire.clone(),
+                    Pending::Eval(EvalState {
+                        request_id: queued.request_id,
+                        acc: queued.acc,
+                        session: queued.session,
+                        ns: queued.request.ns,
+                        timeout: queued.timeout,
+                        deadline: Instant::now() + queued.timeout,
+                        parked: false,
+                    }),
+                );
+                *active_eval = Some(wire);
+                return;
+            }
+            Err(e) => {
+                // Failed to send; report and try the next queued eval.
+                response_tx.send(EvalResponse {
+                    request_id: queued.request_id,
+                    outcome: EvalOutcome::Done(Err(e)),
+                });
+            }
+        }
+    }
+}
+
+/// Retire `target` with [`NReplError::Cancelled`], whether it is still queued,
+/// the active eval, or a control op. Like a timeout, dropping it from `pending`
+/// is what makes its late responses fall on the floor.
+pub(super) async fn cancel_request(
+    target: RequestId,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    eval_queue: &mut VecDeque<QueuedEval>,
+    active_eval: &mut Option<String>,
+    response_tx: &ResponseSender,
+) {
+    let wire = target.wire();
+    let cancelled = || NReplError::Cancelled(target.wire());
+
+    if let Some(pos) = eval_queue.iter().position(|q| q.request_id == target) {
+        let queued = eval_queue.remove(pos).expect("position valid");
+        response_tx.send(EvalResponse {
+            request_id: queued.request_id,
+            outcome: EvalOutcome::Done(Err(cancelled())),
+        });
+        return;
+    }
+
+    if let Some(entry) = pending.remove(&wire) {
+        match &entry {
+            Pending::Stream(StreamOp::Tap { session, .. }) => {
+                stop_tap(writer, session, &wire).await
+            }
+            Pending::Stream(StreamOp::OutSubscription { session, .. }) => {
+                unsubscribe_out(writer, session, &wire).await;
+            }
+            _ => {}
+        }
+        fail_pending(entry, response_tx, cancelled());
+        if active_eval.as_deref() == Some(wire.as_str()) {
+            *active_eval = None;
+            start_next_eval(writer, pending, eval_queue, active_eval, response_tx).await;
+        }
+    }
+}
+
+/// Reset the connection to a clean state (see
+/// [`WorkerCommand::Resync`](super::WorkerCommand::Resync)).
+///
+/// The liveness check runs inline, so commands queue behind it for at most
+/// `RESYNC_DESCRIBE_TIMEOUT`.
+pub(super) async fn resync(
+    op_id: RequestId,
+    writer: &mut NReplWriter,
+    reader: &mut NReplReader,
+    pending: &mut HashMap<String, Pending>,
+    eval_queue: &mut VecDeque<QueuedEval>,
+    active_eval: &mut Option<String>,
+    response_tx: &ResponseSender,
+) -> Result<ResyncReport, NReplError> {
+    // Held requests go out first, so the drain swallows their replies too.
+    writer.flush().await?;
+    let requests_failed = pending.len() + eval_queue.len();
+    fail_all_pending(pending, eval_queue, response_tx, || {
+        NReplError::OperationFailed("connection was resynchronized".to_string())
+    });
+    *active_eval = None;
+
+    let bytes_discarded = reader.drain(RESYNC_DRAIN_IDLE, RESYNC_DRAIN_LIMIT).await?;
+
+    // Nothing is pending any more, so the only response that can arrive with
+    // this id is the describe reply; anything else is a straggler to skip.
+    let wire = op_id.wire();
+    writer
+        .send(&ops::describe_request(wire.clone(), None))
+        .await?;
+    writer.flush().await?;
+    let server_alive = tokio::time::timeout(RESYNC_DESCRIBE_TIMEOUT, async {
+        loop {
+            let response = reader.next_frame().await?;
+            if response.id() == wire && op_finished(classify(response.status())) {
+                return Ok::<_, NReplError>(());
+            }
+        }
+    })
+    .await
+    .is_ok_and(|r| r.is_ok());
+
+    Ok(ResyncReport {
+        bytes_discarded,
+        requests_failed,
+        responses_dropped: 0,
+        server_alive,
+    })
+}
+
+/// The variants of [`WorkerCommand`](super::WorkerCommand) that reach an
+/// eval while it runs: [`dispatch`] writes them past the eval queue.
+pub(super) enum EvalControl {
+    Interrupt {
+        op_id: RequestId,
+        session: Session,
+        target: RequestId,
+        reply: Sender<Result<(), NReplError>>,
+    },
+    Stdin {
+        op_id: RequestId,
+        session: Session,
+        data: String,
+        reply: Sender<Result<(), NReplError>>,
+    },
+}
+
+/// Write an `interrupt` or a line of stdin for an eval. Both bypass the
+/// eval queue, which is what lets them reach an eval still running.
+pub(super) async fn dispatch(
+    cmd: EvalControl,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    eval_queue: &mut VecDeque<QueuedEval>,
+    response_tx: &ResponseSender,
+) {
+    match cmd {
+        EvalControl::Interrupt {
+            op_id,
+            session,
+            target,
+            reply,
+        } => {
+            let target_wire = target.wire();
+            // If the target eval is still queued (not yet sent), cancel it locally.
+            if let Some(pos) = eval_queue.iter().position(|q| q.request_id == target) {
+                let cancelled = eval_queue.remove(pos).expect("position valid");
+                response_tx.send(EvalResponse {
+                    request_id: cancelled.request_id,
+                    outcome: EvalOutcome::Done(Ok(interrupted_result())),
+                });
+                let _ = reply.send(Ok(()));
+                return;
+            }
+            // If the target isn't active/pending, the eval already finished:
+            // harmless no-op.
+            if !pending.contains_key(&target_wire) {
+                let _ = reply.send(Ok(()));
+                return;
+            }
+            let request = ops::interrupt_request(op_id.wire(), session.id(), target_wire);
+            send_control!(
+                writer,
+                pending,
+                op_id,
+                reply,
+                request,
+                Pending::Interrupt { reply }
+            );
+        }
+        EvalControl::Stdin {
+            op_id,
+            session,
+            data,
+            reply,
+        } => {
+            // Fire-and-forget: nREPL does not ack stdin.
+            let request = ops::stdin_request(op_id.wire(), session.id(), data);
+            let _ = reply.send(writer.send(&request).await);
+        }
+    }
+}
+
+/// Route a response to an eval.
+// Long because each way an eval can end (an unknown op, a limit, parking on
+// need-input, done) retires it differently.
+#[allow(clippy::too_many_arguments, clippy::too_many_lines)]
+pub(super) async fn route(
+    response: Response,
+    flags: StatusFlags,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    eval_queue: &mut VecDeque<QueuedEval>,
+    active_eval: &mut Option<String>,
+    response_tx: &ResponseSender,
+    ns_cache: &mut NsCache,
+    server: &ServerInfo,
+    events: &EventLog,
+    cache: Option<&ResponseCache>,
+) {
+    let id = response.id.clone();
+    let Some(Pending::Eval(state)) = pending.get_mut(&id) else {
+        return;
+    };
+    // The eval may have changed what the namespace it ran in requires. That
+    // is not the response's `ns` when the eval switched namespace.
+    if state.ns.is_none() {
+        state.ns = server.fallback_ns(&state.session);
+    }
+    if let Some(ns) = &state.ns {
+        ns_cache.retain(|(_, cached), _| cached != ns);
+    }
+    if let Some(ns) = &response.ns {
+        server.note_ns(&state.session, ns);
+    }
+    // Each step of the eval may have (re)defined vars, so a reply
+    // requested before it is no longer fresh.
+    if let Some(cache) = cache {
+        cache.invalidate_id(&state.session);
+    }
+    // Unknown-op on an eval shouldn't happen, but treat as an error.
+    if flags.unknown_op {
+        let request_id = state.request_id;
+        pending.remove(&id);
+        response_tx.send(EvalResponse {
+            request_id,
+            outcome: EvalOutcome::Done(Err(unknown_op_err("eval"))),
+        });
+        if active_eval.as_deref() == Some(id.as_str()) {
+            *active_eval = None;
+            start_next_eval(writer, pending, eval_queue, active_eval, response_tx).await;
+        }
+        return;
+    }
+
+    // A response means the server is making progress: if we were parked
+    // on need-input, resume (reset the deadline).
+    if state.parked {
+        state.parked = false;
+        state.deadline = Instant::now() + state.timeout;
+    }
+
+    let request_id = state.request_id;
+    let need_input = flags.need_input;
+    let done = flags.done;
+
+    if let Err(e) = state.acc.push(response) {
+        // Backpressure limit exceeded - fail the eval.
+        events.record(DebugEventKind::LimitExceeded, format!("eval {id}: {e}"));
+        pending.remove(&id);
+        response_tx.send(EvalResponse {
+            request_id,
+            outcome: EvalOutcome::Done(Err(e)),
+        });
+        if active_eval.as_deref() == Some(id.as_str()) {
+            *active_eval = None;
+            start_next_eval(writer, pending, eval_queue, active_eval, response_tx).await;
+        }
+        return;
+    }
+
+    if need_input && !done {
+        // Park the eval; keep it active and do not advance the queue.
+        // Drain the output captured so far so the client can render it
+        // (e.g. a prompt string) before opening its stdin box; draining
+        // prevents it being re-rendered at `done`.
+        let (output, error) = if let Some(Pending::Eval(state)) = pending.get_mut(&id) {
+            state.parked = true;
+            state.acc.drain_output()
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        response_tx.send(EvalResponse {
+            request_id,
+            outcome: EvalOutcome::NeedInput { output, error },
+        });
+        return;
+    }
+
+    if done {
+        if let Some(Pending::Eval(state)) = pending.remove(&id) {
+            let mut result = state.acc.finish();
+            // Not every server sends `ns` on eval replies.
+            if result.ns.is_none() {
+                result.ns = server.fallback_ns(&state.session);
+            }
+            response_tx.send(EvalResponse {
+                request_id,
+                outcome: EvalOutcome::Done(Ok(result)),
+            });
+        }
+        if active_eval.as_deref() == Some(id.as_str()) {
+            *active_eval = None;
+            start_next_eval(writer, pending, eval_queue, active_eval, response_tx).await;
+        }
+    }
+}
+
+/// Route a response to an interrupt.
+pub(super) fn route_interrupt(
+    response: &Response,
+    flags: StatusFlags,
+    pending: &mut HashMap<String, Pending>,
+) {
+    if op_finished(flags)
+        && let Some(Pending::Interrupt { reply }) = pending.remove(&response.id)
+    {
+        let _ = reply.send(op_unit_result(response, flags, "interrupt"));
+    }
+}
+
+/// Result delivered when a queued eval is cancelled by an interrupt.
+pub(super) fn interrupted_result() -> EvalResult {
+    let mut r = EvalResult::new();
+    r.interrupted = true;
+    r
+}
@@ -0,0 +1,1564 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! The connection's side of the worker: connecting, then the event loop
+//! that writes commands and routes responses back by request id.
+
+use super::completion::{CompletionCommand, NsCache};
+use super::evals::{EvalControl, resync, start_next_eval};
+use super::pending::{
+    Pending, QueuedEval, SessionOp, fail_all_pending, fail_pending, op_finished, send_collect,
+};
+use super::sessions::{CljsSessions, SessionCommand, expire_idle_sessions};
+use super::streams::{StreamCommand, held_output_deadline, send_held_output};
+use super::tooling::ToolingCommand;
+use super::{
+    CancelLink, ConnectionState, DEFAULT_DONE_TIMEOUT, EvalOutcome, EvalRequest, EvalResponse,
+    LoadFileRequest, OutputOptions, RequestId, ResponseSender, ResyncReport, WorkerCommand,
+    WorkerConfig, completion, evals, sessions, streams, tooling,
+};
+use crate::cache::ResponseCache;
+use crate::capture::FrameCapture;
+use crate::codec::LazyResponse;
+use crate::connection::{NReplClient, NReplReader, NReplWriter};
+use crate::error::NReplError;
+use crate::events::{DebugEventKind, EventLog};
+use crate::flavor::ServerInfo;
+use crate::message::{Response, classify};
+use crate::ops;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Sender, channel};
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// Bounds on how often idle sessions are looked for: a quarter of the TTL,
+/// within these limits.
+const MIN_SESSION_SWEEP: Duration = Duration::from_millis(10);
+const MAX_SESSION_SWEEP: Duration = Duration::from_mins(1);
+
+/// Bounds on the wait between attempts to remake a lost connection: the
+/// first waits the minimum, and each failure doubles it up to the maximum.
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+/// How long an aborted event loop gets to fail its requests before it is
+/// dropped regardless, mid-write or not.
+const ABORT_GRACE: Duration = Duration::from_millis(100);
+
+/// Keepalive bookkeeping for [`WorkerConfig::heartbeat`].
+struct Heartbeat {
+    interval: Duration,
+    next_probe: Instant,
+    /// Wire id of the unanswered probe, if one is out.
+    outstanding: Option<String>,
+    /// When the outstanding probe was sent.
+    sent_at: Instant,
+}
+
+impl Heartbeat {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next_probe: Instant::now() + interval,
+            outstanding: None,
+            sent_at: Instant::now(),
+        }
+    }
+
+    /// When the outstanding probe, if any, will have gone `limit` unanswered.
+    fn lost_at(&self, limit: Duration) -> Option<Instant> {
+        self.outstanding.as_ref().map(|_| self.sent_at + limit)
+    }
+
+    /// True (consuming the response) when `response` answers our probe.
+    fn answers(&mut self, response: &LazyResponse) -> bool {
+        if self.outstanding.as_deref() != Some(response.id()) {
+            return false;
+        }
+        if op_finished(classify(response.status())) {
+            self.outstanding = None;
+        }
+        true
+    }
+}
+
+/// Publish `state`, notifying watchers only when it actually changes.
+/// Returns whether it changed.
+fn set_state(state_tx: &watch::Sender<ConnectionState>, state: ConnectionState) -> bool {
+    state_tx.send_if_modified(|current| std::mem::replace(current, state) != state)
+}
+
+/// Resolves once [`Worker::abort`](super::Worker::abort) is called. Never
+/// resolves if the worker was dropped without aborting: that is a shutdown,
+/// handled elsewhere.
+async fn aborted(abort: &mut watch::Receiver<bool>) {
+    if abort.wait_for(|aborted| *aborted).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Open a connection to `address`: an `http://` URL through drawbridge, a
+/// `ws://` URL through a WebSocket bridge, anything else over TCP, through
+/// the configured proxy if there is one.
+async fn connect_to(address: &str, config: &WorkerConfig) -> Result<NReplClient, NReplError> {
+    #[cfg(feature = "drawbridge")]
+    if address.starts_with("http://") || address.starts_with("https://") {
+        return NReplClient::connect_http(address).await;
+    }
+    #[cfg(feature = "websocket")]
+    if address.starts_with("ws://") || address.starts_with("wss://") {
+        return NReplClient::connect_ws(address).await;
+    }
+    #[cfg(feature = "proxy")]
+    if let Some(proxy) = &config.proxy {
+        return NReplClient::connect_via(address, proxy).await;
+    }
+    let _ = config;
+    NReplClient::connect(address).await
+}
+
+/// Worker thread entry: wait for the initial Connect, then run the demux loop.
+pub(super) async fn worker_main(
+    mut command_rx: UnboundedReceiver<WorkerCommand>,
+    response_tx: ResponseSender,
+    state_tx: watch::Sender<ConnectionState>,
+    config: WorkerConfig,
+    id_source: Arc<AtomicUsize>,
+    mut abort: watch::Receiver<bool>,
+) {
+    // Phase 1: wait for a Connect command before we have a stream to demux.
+    loop {
+        let (address, stdio, reply) = match command_rx.recv().await {
+            Some(WorkerCommand::Connect(address, reply)) => (address, None, reply),
+            Some(WorkerCommand::ConnectStdio(client, reply)) => {
+                ("stdio".to_string(), Some(client), reply)
+            }
+            Some(WorkerCommand::Shutdown(reply)) => {
+                let _ = reply.send(Ok(()));
+                return;
+            }
+            Some(other) => {
+                // Not connected yet - reply to any waiting one-shot with an error.
+                reply_not_connected(other);
+                continue;
+            }
+            None => return,
+        };
+        let capture = match &config.capture_path {
+            Some(path) => match FrameCapture::open(path, config.capture_rotate_at) {
+                Ok(capture) => Some(capture),
+                Err(e) => {
+                    let e = std::io::Error::new(
+                        e.kind(),
+                        format!("cannot open frame capture {}: {e}", path.display()),
+                    );
+                    config
+                        .events
+                        .record(DebugEventKind::ConnectFailed, format!("{address}: {e}"));
+                    let _ = reply.send(Err(e.into()));
+                    continue;
+                }
+            },
+            None => None,
+        };
+        // A subprocess's pipes cannot be opened again.
+        let reconnect = config.reconnect && stdio.is_none();
+        let connected = match stdio {
+            Some(client) => Ok(client),
+            None => connect_to(&address, &config).await,
+        };
+        match connected {
+            Ok(mut client) => {
+                set_state(&state_tx, ConnectionState::Connected);
+                config
+                    .events
+                    .record(DebugEventKind::Connected, format!("connected to {address}"));
+                let _ = reply.send(Ok(()));
+                loop {
+                    let (writer, reader) = split_client(client, capture.clone(), &config);
+                    // Phase 2: run the demux event loop until shutdown/disconnect.
+                    let mut loop_abort = abort.clone();
+                    let exit = tokio::select! {
+                        exit = event_loop(
+                            writer,
+                            reader,
+                            &mut command_rx,
+                            &response_tx,
+                            &state_tx,
+                            &config,
+                            &id_source,
+                            &mut loop_abort,
+                        ) => exit,
+                        // The loop answers an abort itself; this only
+                        // matters when it is stuck writing to a server
+                        // that stopped reading. Dropping it mid-write
+                        // drops the socket with it.
+                        () = async {
+                            aborted(&mut abort).await;
+                            tokio::time::sleep(ABORT_GRACE).await;
+                        } => {
+                            config.events.record(DebugEventKind::Disconnected, "connection aborted");
+                            LoopExit::Closed
+                        }
+                    };
+                    set_state(&state_tx, ConnectionState::Disconnected);
+                    if exit == LoopExit::Closed || !reconnect {
+                        return;
+                    }
+                    match reconnect_to(&address, &mut command_rx, &response_tx, &config, &mut abort)
+                        .await
+                    {
+                        Some(again) => client = again,
+                        None => return,
+                    }
+                    set_state(&state_tx, ConnectionState::Connected);
+                    config.events.record(
+                        DebugEventKind::Reconnected,
+                        format!("reconnected to {address}"),
+                    );
+                }
+            }
+            Err(e) => {
+                // Connection failed; let the caller retry with a new worker.
+                config
+                    .events
+                    .record(DebugEventKind::ConnectFailed, format!("{address}: {e}"));
+                let _ = reply.send(Err(e));
+            }
+        }
+    }
+}
+
+/// Split a new connection into the writer and reader the event loop runs,
+/// set up as `config` asks.
+fn split_client(
+    client: NReplClient,
+    capture: Option<FrameCapture>,
+    config: &WorkerConfig,
+) -> (NReplWriter, NReplReader) {
+    let (mut writer, mut reader) = client.into_split();
+    if let Some(capture) = capture {
+        writer.set_capture(capture.clone());
+        reader.set_capture(capture);
+    }
+    reader.set_large_field_telemetry(config.large_fields.clone());
+    if let Some(bytes) = config.read_chunk {
+        reader.set_read_chunk(bytes);
+    }
+    if let Some(limit) = config.read_timeout {
+        reader.set_read_timeout(limit);
+    }
+    if let Some(limit) = config.write_timeout {
+        writer.set_write_timeout(limit);
+    }
+    writer.set_sessions(config.sessions.clone());
+    writer.set_flush_policy(config.flush_policy);
+    if let Some(auth) = &config.auth {
+        writer.set_auth(auth.clone());
+    }
+    if let Some(metrics) = &config.metrics {
+        writer.set_metrics(metrics.clone());
+        reader.set_metrics(metrics.clone());
+    }
+    (writer, reader)
+}
+
+/// Connect to `address` again after the connection to it was lost, backing
+/// off between failed attempts. Commands that arrive in the meantime fail
+/// as not connected. `None` if the worker is shut down, dropped or aborted
+/// first.
+async fn reconnect_to(
+    address: &str,
+    command_rx: &mut UnboundedReceiver<WorkerCommand>,
+    response_tx: &ResponseSender,
+    config: &WorkerConfig,
+    abort: &mut watch::Receiver<bool>,
+) -> Option<NReplClient> {
+    let mut backoff = RECONNECT_MIN;
+    let mut attempt_at = Instant::now() + backoff;
+    loop {
+        tokio::select! {
+            () = aborted(abort) => return None,
+            cmd = command_rx.recv() => match cmd {
+                Some(WorkerCommand::Shutdown(reply)) => {
+                    let _ = reply.send(Ok(()));
+                    return None;
+                }
+                // Unlike before the first connect, an eval can arrive now,
+                // and its caller is polling for the result.
+                Some(
+                    WorkerCommand::Eval(EvalRequest { request_id, .. })
+                    | WorkerCommand::LoadFile(LoadFileRequest { request_id, .. }),
+                ) => {
+                    response_tx.send(EvalResponse {
+                        request_id,
+                        outcome: EvalOutcome::Done(Err(NReplError::NotConnected)),
+                    });
+                }
+                Some(other) => reply_not_connected(other),
+                None => return None,
+            },
+            () = tokio::time::sleep_until(attempt_at) => {
+                match connect_to(address, config).await {
+                    Ok(client) => return Some(client),
+                    Err(e) => {
+                        backoff = (backoff * 2).min(RECONNECT_MAX);
+                        attempt_at = Instant::now() + backoff;
+                        config.events.record(
+                            DebugEventKind::ConnectFailed,
+                            format!("{address}: {e}, next attempt in {backoff:?}"),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reply to a command's one-shot channel with [`NReplError::NotConnected`].
+fn reply_not_connected(cmd: WorkerCommand) {
+    let err = || NReplError::NotConnected;
+    match cmd {
+        WorkerCommand::Eval(req) => {
+            // No response channel here; main thread polls try_recv_response and
+            // would get nothing. This path shouldn't happen in practice because
+            // connect happens before any eval, but be safe and drop it.
+            let _ = req;
+        }
+        WorkerCommand::LoadFile(req) => {
+            let _ = req;
+        }
+        WorkerCommand::Interrupt { reply, .. }
+        | WorkerCommand::CloseSession { reply, .. }
+        | WorkerCommand::Stdin { reply, .. }
+        | WorkerCommand::Connect(_, reply)
+        | WorkerCommand::ConnectStdio(_, reply) => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::CloneSession { reply, .. }
+        | WorkerCommand::CloneSessionFrom { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::Completions { reply, .. }
+        | WorkerCommand::AliasedCompletions { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::Lookup { reply, .. } | WorkerCommand::Describe { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::LsSessions { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::NsAliases { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::AnalyzeStacktrace { reply, .. }
+        | WorkerCommand::TestStacktrace { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::Info { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::Eldoc { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::ClojureDocs { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::Apropos { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::FnRefs { reply, .. } | WorkerCommand::FnDeps { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::NsList { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::NsVars { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::NsPath { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::Classpath { reply, .. } | WorkerCommand::SpecList { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::SpecForm { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::SpecExample { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::LsMiddleware { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::AddMiddleware { reply, .. }
+        | WorkerCommand::SwapMiddleware { reply, .. }
+        | WorkerCommand::UpgradeCljs { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::Undef { reply, .. } | WorkerCommand::UndefAll { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::ToggleTraceVar { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::ToggleTraceNs { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::FormatCode { reply, .. } | WorkerCommand::FormatEdn { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::RunTests { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::Refresh { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::RefreshClear { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::Inspect { reply, .. } | WorkerCommand::Inspector { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::InitDebugger { breaks, .. } => {
+            let _ = breaks.send(Err(err()));
+        }
+        WorkerCommand::SubscribeTap { taps, .. } => {
+            let _ = taps.send(Err(err()));
+        }
+        WorkerCommand::OutSubscribe { output, .. } => {
+            let _ = output.send(Err(err()));
+        }
+        WorkerCommand::OutUnsubscribe { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::StartSideloader { lookups, .. } => {
+            let _ = lookups.send(Err(err()));
+        }
+        WorkerCommand::DebugInput { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::Resync { reply, .. } => {
+            let _ = reply.send(Err(err()));
+        }
+        WorkerCommand::Cancel { .. } | WorkerCommand::Watch(_) => {}
+        WorkerCommand::Shutdown(reply) => {
+            let _ = reply.send(Ok(()));
+        }
+    }
+}
+
+/// Why [`event_loop`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LoopExit {
+    /// The worker shut down, was dropped or was aborted.
+    Closed,
+    /// The connection failed under it: closed by the server, a read or
+    /// write error, or found half-open.
+    Lost,
+}
+
+/// Whether `e` is a stall rather than a close: the socket's own timeout
+/// (TCP keepalive or retransmission giving up) or the configured read and
+/// write timeouts.
+fn is_stall(e: &NReplError) -> bool {
+    matches!(e, NReplError::Connection(io) if io.kind() == std::io::ErrorKind::TimedOut)
+}
+
+/// The demux event loop. Owns the writer/reader and all in-flight state.
+#[allow(clippy::too_many_arguments)]
+async fn event_loop(
+    mut writer: NReplWriter,
+    mut reader: NReplReader,
+    command_rx: &mut UnboundedReceiver<WorkerCommand>,
+    response_tx: &ResponseSender,
+    state_tx: &watch::Sender<ConnectionState>,
+    config: &WorkerConfig,
+    id_source: &AtomicUsize,
+    abort: &mut watch::Receiver<bool>,
+) -> LoopExit {
+    let mut pending: HashMap<String, Pending> = HashMap::new();
+    let mut eval_queue: VecDeque<QueuedEval> = VecDeque::new();
+    // Wire id of the currently running eval, if any.
+    let mut active_eval: Option<String> = None;
+    let mut heartbeat = config
+        .heartbeat
+        .or_else(|| config.half_open_after.map(|limit| limit / 3))
+        .map(Heartbeat::new);
+    let mut ns_cache = NsCache::new();
+    let mut cljs_sessions = CljsSessions::new();
+    let done_timeout = config.done_timeout.unwrap_or(DEFAULT_DONE_TIMEOUT);
+    // When each watched control op was sent or last answered: the done
+    // watchdog counts from there.
+    let mut last_heard: HashMap<String, Instant> = HashMap::new();
+    // Cancellation links of requests in flight, dropped as they finish.
+    let mut links: Vec<CancelLink> = Vec::new();
+    let sweep_every = config
+        .session_expiry
+        .ttl
+        .map(|ttl| (ttl / 4).clamp(MIN_SESSION_SWEEP, MAX_SESSION_SWEEP));
+    let mut next_sweep = sweep_every.map(|every| Instant::now() + every);
+
+    if config.detect_server {
+        // Nobody waits on the reply: routing it records the profile.
+        let op_id = RequestId::new(id_source.fetch_add(1, Ordering::Relaxed));
+        let (reply, _) = channel();
+        let request = ops::describe_request(op_id.wire(), None);
+        if writer.send(&request).await.is_ok() {
+            pending.insert(
+                op_id.wire(),
+                Pending::Session(SessionOp::Describe { reply, last: None }),
+            );
+        }
+    }
+
+    loop {
+        // Requests sent while handling a run of commands go out together,
+        // once no command is left waiting.
+        if writer.has_queued()
+            && command_rx.is_empty()
+            && let Err(e) = writer.flush().await
+        {
+            if is_stall(&e) {
+                config.events.record(
+                    DebugEventKind::ConnectionLost,
+                    format!("write stalled: {e}"),
+                );
+                fail_all_pending(&mut pending, &mut eval_queue, response_tx, || {
+                    NReplError::ConnectionLost(format!("write stalled: {e}"))
+                });
+            } else {
+                config
+                    .events
+                    .record(DebugEventKind::Disconnected, format!("write failed: {e}"));
+                fail_all_pending(&mut pending, &mut eval_queue, response_tx, || {
+                    NReplError::Connection(std::io::Error::new(
+                        std::io::ErrorKind::BrokenPipe,
+                        format!("connection closed: {e}"),
+                    ))
+                });
+            }
+            return LoopExit::Lost;
+        }
+
+        // Deadline arm: only the active, non-parked eval has a live deadline.
+        let deadline = active_eval
+            .as_ref()
+            .and_then(|id| pending.get(id))
+            .and_then(|p| match p {
+                Pending::Eval(s) if !s.parked => Some(s.deadline),
+                _ => None,
+            })
+            .unwrap_or_else(|| Instant::now() + Duration::from_hours(1));
+        let probe_at = heartbeat.as_ref().map_or_else(
+            || Instant::now() + Duration::from_hours(1),
+            |h| h.next_probe,
+        );
+        let lost_at = config
+            .half_open_after
+            .zip(heartbeat.as_ref())
+            .and_then(|(limit, h)| h.lost_at(limit));
+        // Reconcile rather than hook every insert: the map is a handful of
+        // entries and control ops are parked from several places.
+        last_heard.retain(|id, _| pending.contains_key(id));
+        links.retain(|link| {
+            pending.contains_key(&link.target.wire())
+                || eval_queue.iter().any(|q| q.request_id == link.target)
+        });
+        for (id, p) in &pending {
+            if p.watched() && !last_heard.contains_key(id) {
+                last_heard.insert(id.clone(), Instant::now());
+            }
+        }
+        let done_at = last_heard.values().min().map_or_else(
+            || Instant::now() + Duration::from_hours(1),
+            |since| *since + done_timeout,
+        );
+        let sweep_at = next_sweep.unwrap_or_else(|| Instant::now() + Duration::from_hours(1));
+        let held_until = held_output_deadline(&pending);
+
+        tokio::select! {
+            () = aborted(abort) => {
+                fail_aborted(&mut pending, &mut eval_queue, command_rx, response_tx);
+                config.events.record(DebugEventKind::Disconnected, "connection aborted");
+                return LoopExit::Closed;
+            }
+            cmd = command_rx.recv() => {
+                match cmd.map(Command::from) {
+                    Some(Command::Shutdown(reply)) => {
+                        // Best-effort: send what is held (a close_all_sessions
+                        // just before, say), fail any pending ops, then exit.
+                        let _ = writer.flush().await;
+                        fail_all_pending(&mut pending, &mut eval_queue, response_tx,
+                            || NReplError::protocol("Worker shutting down"));
+                        config.events.record(DebugEventKind::Disconnected, "worker shut down");
+                        let _ = reply.send(Ok(()));
+                        return LoopExit::Closed;
+                    }
+                    Some(Command::Resync { op_id, reply }) => {
+                        let report = resync(
+                            op_id, &mut writer, &mut reader, &mut pending,
+                            &mut eval_queue, &mut active_eval, response_tx,
+                        ).await;
+                        // Resync discards everything in flight, our probe too.
+                        if let Some(h) = heartbeat.as_mut() {
+                            h.outstanding = None;
+                        }
+                        config.events.record(DebugEventKind::Resync, match &report {
+                            Ok(r) => format!(
+                                "failed {} requests, discarded {} bytes, server alive: {}",
+                                r.requests_failed, r.bytes_discarded, r.server_alive
+                            ),
+                            Err(e) => format!("resync failed: {e}"),
+                        });
+                        let _ = reply.send(report);
+                    }
+                    Some(Command::Watch(link)) => links.push(link),
+                    Some(Command::Dispatch(cmd)) => {
+                        dispatch_command(
+                            cmd, &mut writer, &mut pending, &mut eval_queue,
+                            &mut active_eval, response_tx, &mut ns_cache,
+                            &mut cljs_sessions, &config.server, config.rich_content,
+                            &config.output, config.cache.as_ref(),
+                        ).await;
+                    }
+                    None => {
+                        // All command senders dropped - shut down.
+                        let _ = writer.flush().await;
+                        config.events.record(DebugEventKind::Disconnected, "worker dropped");
+                        return LoopExit::Closed;
+                    }
+                }
+            }
+            resp = reader.next_frame() => {
+                match resp {
+                    Ok(r) if heartbeat.as_mut().is_some_and(|h| h.answers(&r)) => {
+                        if set_state(state_tx, ConnectionState::Connected) {
+                            config.events.record(DebugEventKind::Recovered, "heartbeat answered");
+                        }
+                    }
+                    // Unknown / timed-out id: discard it without decoding
+                    // the rest.
+                    Ok(r) if !pending.contains_key(r.id()) => {}
+                    // A frame that routes but will not decode is skipped;
+                    // its op times out.
+                    Ok(r) => {
+                        if let Some(Pending::Eval(state)) = pending.get_mut(r.id()) {
+                            state.acc.take_tags(&r);
+                        }
+                        if let Some(heard) = last_heard.get_mut(r.id()) {
+                            *heard = Instant::now();
+                        }
+                        if let Ok(r) = r.decode() {
+                            route_response(
+                                r, &mut writer, &mut pending, &mut eval_queue,
+                                &mut active_eval, response_tx, &mut ns_cache,
+                                &mut cljs_sessions, &config.server, &config.events,
+                                config.cache.as_ref(),
+                            ).await;
+                        }
+                    }
+                    Err(e) if is_stall(&e) => {
+                        config.events.record(DebugEventKind::ConnectionLost, format!("read stalled: {e}"));
+                        fail_all_pending(&mut pending, &mut eval_queue, response_tx,
+                            || NReplError::ConnectionLost(format!("read stalled: {e}")));
+                        return LoopExit::Lost;
+                    }
+                    Err(e) => {
+                        config.events.record(DebugEventKind::Disconnected, format!("read failed: {e}"));
+                        // Reader EOF / connection error: fail everything and stop.
+                        fail_all_pending(&mut pending, &mut eval_queue, response_tx,
+                            || NReplError::Connection(std::io::Error::new(
+                                std::io::ErrorKind::UnexpectedEof,
+                                format!("connection closed: {e}"),
+                            )));
+                        return LoopExit::Lost;
+                    }
+                }
+            }
+            () = tokio::time::sleep_until(deadline) => {
+                // Active eval deadline expired. Hand back whatever the eval
+                // had accumulated so the caller can still show its output.
+                if let Some(id) = active_eval.clone() {
+                    if let Some(Pending::Eval(state)) = pending.remove(&id) {
+                        let interrupt = config.interrupt_on_timeout
+                            && !config.server.lacks("interrupt");
+                        if interrupt {
+                            // Stop the server burning CPU on the abandoned form.
+                            // Best-effort: the reply (and the eval's own trailing
+                            // `interrupted`/`done`) hit no pending entry and are
+                            // discarded by route_response.
+                            let op_id = RequestId::new(id_source.fetch_add(1, Ordering::Relaxed));
+                            let request = ops::interrupt_request(op_id.wire(), &state.session, &id);
+                            let _ = writer.send(&request).await;
+                        }
+                        config.events.record(DebugEventKind::Timeout, format!(
+                            "eval {id} timed out after {:?}{}",
+                            state.timeout,
+                            if interrupt { ", interrupt sent" } else { "" }
+                        ));
+                        response_tx.send(EvalResponse {
+                            request_id: state.request_id,
+                            outcome: EvalOutcome::Done(Err(NReplError::Timeout {
+                                operation: "eval".to_string(),
+                                duration: state.timeout,
+                                partial: Some(Box::new(state.acc.finish())),
+                            })),
+                        });
+                    }
+                    active_eval = None;
+                    start_next_eval(
+                        &mut writer, &mut pending, &mut eval_queue, &mut active_eval, response_tx,
+                    ).await;
+                }
+            }
+            () = tokio::time::sleep_until(done_at), if !last_heard.is_empty() => {
+                let now = Instant::now();
+                let expired: Vec<String> = last_heard
+                    .iter()
+                    .filter(|(_, since)| now >= **since + done_timeout)
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                for id in expired {
+                    last_heard.remove(&id);
+                    if let Some(p) = pending.remove(&id) {
+                        // Any late responses hit no pending entry and are
+                        // discarded by route_response.
+                        let op = p.control_op().unwrap_or("eval").to_string();
+                        config.events.record(DebugEventKind::ProtocolViolation, format!(
+                            "{op} {id} went {done_timeout:?} without a response or done"
+                        ));
+                        fail_pending(p, response_tx, NReplError::ProtocolViolation {
+                            op,
+                            id,
+                            waited: done_timeout,
+                        });
+                    }
+                }
+            }
+            () = tokio::time::sleep_until(held_until.unwrap_or(sweep_at)), if held_until.is_some() => {
+                send_held_output(&mut writer, &mut pending).await;
+            }
+            () = tokio::time::sleep_until(sweep_at), if next_sweep.is_some() => {
+                next_sweep = sweep_every.map(|every| Instant::now() + every);
+                expire_idle_sessions(
+                    &mut writer, &pending, &eval_queue, &mut cljs_sessions, &mut ns_cache, config,
+                    id_source,
+                ).await;
+            }
+            () = tokio::time::sleep_until(lost_at.unwrap_or(probe_at)), if lost_at.is_some() => {
+                // Writes still go out, or the probe would have failed to
+                // send, but nothing has come back: a half-open socket.
+                let limit = config.half_open_after.unwrap_or_default();
+                let reason = format!("heartbeat unanswered for {limit:?}");
+                config.events.record(DebugEventKind::ConnectionLost, reason.clone());
+                fail_all_pending(&mut pending, &mut eval_queue, response_tx,
+                    || NReplError::ConnectionLost(reason.clone()));
+                return LoopExit::Lost;
+            }
+            () = tokio::time::sleep_until(probe_at), if heartbeat.is_some() => {
+                if let Some(h) = heartbeat.as_mut() {
+                    h.next_probe = Instant::now() + h.interval;
+                    if h.outstanding.is_some() {
+                        // A whole interval without an answer. Keep waiting on
+                        // the same probe rather than piling up more.
+                        if set_state(state_tx, ConnectionState::Degraded) {
+                            config.events.record(DebugEventKind::Degraded, format!(
+                                "heartbeat unanswered for {:?}", h.interval
+                            ));
+                        }
+                    } else {
+                        let op_id = RequestId::new(id_source.fetch_add(1, Ordering::Relaxed));
+                        let request = ops::describe_request(op_id.wire(), None);
+                        match writer.send(&request).await {
+                            Ok(()) => {
+                                h.outstanding = Some(op_id.wire());
+                                h.sent_at = Instant::now();
+                            }
+                            Err(_) => {
+                                set_state(state_tx, ConnectionState::Degraded);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A [`WorkerCommand`] sorted by what handles it, so that each handler is
+/// given only the commands it knows.
+enum Command {
+    Shutdown(Sender<Result<(), NReplError>>),
+    /// Handled by the loop itself: it is the one command that needs the
+    /// reader.
+    Resync {
+        op_id: RequestId,
+        reply: Sender<Result<ResyncReport, NReplError>>,
+    },
+    Watch(CancelLink),
+    Dispatch(Dispatch),
+}
+
+/// The commands [`dispatch_command`] takes.
+enum Dispatch {
+    /// A connect while already connected.
+    Connect(Sender<Result<(), NReplError>>),
+    Eval(EvalRequest),
+    LoadFile(LoadFileRequest),
+    Cancel(RequestId),
+    Input(EvalControl),
+    Session(SessionCommand),
+    Completion(CompletionCommand),
+    Stream(StreamCommand),
+    Tooling(ToolingCommand),
+}
+
+impl From<WorkerCommand> for Command {
+    // One arm per command, each only moving its fields across, so the match
+    // is long but flat.
+    #[allow(clippy::too_many_lines)]
+    fn from(cmd: WorkerCommand) -> Self {
+        let dispatch = match cmd {
+            WorkerCommand::Shutdown(reply) => return Command::Shutdown(reply),
+            WorkerCommand::Resync { op_id, reply } => return Command::Resync { op_id, reply },
+            WorkerCommand::Watch(link) => return Command::Watch(link),
+            WorkerCommand::Connect(_, reply) | WorkerCommand::ConnectStdio(_, reply) => {
+                Dispatch::Connect(reply)
+            }
+            WorkerCommand::Eval(req) => Dispatch::Eval(req),
+            WorkerCommand::LoadFile(req) => Dispatch::LoadFile(req),
+            WorkerCommand::Cancel { target } => Dispatch::Cancel(target),
+            WorkerCommand::Interrupt {
+                op_id,
+                session,
+                target,
+                reply,
+            } => Dispatch::Input(EvalControl::Interrupt {
+                op_id,
+                session,
+                target,
+                reply,
+            }),
+            WorkerCommand::CloneSession { op_id, reply } => {
+                Dispatch::Session(SessionCommand::CloneSession { op_id, reply })
+            }
+            WorkerCommand::CloneSessionFrom {
+                op_id,
+                session,
+                reply,
+            } => Dispatch::Session(SessionCommand::CloneSessionFrom {
+                op_id,
+                session,
+                reply,
+            }),
+            WorkerCommand::CloseSession {
+                op_id,
+                session,
+                reply,
+            } => Dispatch::Session(SessionCommand::CloseSession {
+                op_id,
+                session,
+                reply,
+            }),
+            WorkerCommand::Stdin {
+                op_id,
+                session,
+                data,
+                reply,
+            } => Dispatch::Input(EvalControl::Stdin {
+                op_id,
+                session,
+                data,
+                reply,
+            }),
+            WorkerCommand::Completions {
+                op_id,
+                session,
+                prefix,
+                ns,
+                complete_fn,
+                reply,
+            } => Dispatch::Completion(CompletionCommand::Completions {
+                op_id,
+                session,
+                prefix,
+                ns,
+                complete_fn,
+                reply,
+            }),
+            WorkerCommand::AliasedCompletions {
+                op_id,
+                session,
+                prefix,
+                ns,
+                complete_fn,
+                reply,
+            } => Dispatch::Completion(CompletionCommand::AliasedCompletions {
+                op_id,
+                session,
+                prefix,
+                ns,
+                complete_fn,
+                reply,
+            }),
+            WorkerCommand::Lookup {
+                op_id,
+                session,
+                sym,
+                ns,
+                lookup_fn,
+                reply,
+            } => Dispatch::Completion(CompletionCommand::Lookup {
+                op_id,
+                session,
+                sym,
+                ns,
+                lookup_fn,
+                reply,
+            }),
+            WorkerCommand::Describe {
+                op_id,
+                verbose,
+                reply,
+            } => Dispatch::Session(SessionCommand::Describe {
+                op_id,
+                verbose,
+                reply,
+            }),
+            WorkerCommand::LsSessions { op_id, reply } => {
+                Dispatch::Session(SessionCommand::LsSessions { op_id, reply })
+            }
+            WorkerCommand::AddMiddleware {
+                op_id,
+                session,
+                middleware,
+                extra_namespaces,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::AddMiddleware {
+                op_id,
+                session,
+                middleware,
+                extra_namespaces,
+                reply,
+            }),
+            WorkerCommand::LsMiddleware {
+                op_id,
+                verbose,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::LsMiddleware {
+                op_id,
+                verbose,
+                reply,
+            }),
+            WorkerCommand::SwapMiddleware {
+                op_id,
+                session,
+                middleware,
+                extra_namespaces,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::SwapMiddleware {
+                op_id,
+                session,
+                middleware,
+                extra_namespaces,
+                reply,
+            }),
+            WorkerCommand::UpgradeCljs {
+                op_id,
+                session,
+                repl,
+                reply,
+            } => Dispatch::Session(SessionCommand::UpgradeCljs {
+                op_id,
+                session,
+                repl,
+                reply,
+            }),
+            WorkerCommand::NsAliases {
+                op_id,
+                session,
+                ns,
+                reply,
+            } => Dispatch::Completion(CompletionCommand::NsAliases {
+                op_id,
+                session,
+                ns,
+                reply,
+            }),
+            WorkerCommand::AnalyzeStacktrace {
+                op_id,
+                session,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::AnalyzeStacktrace {
+                op_id,
+                session,
+                reply,
+            }),
+            WorkerCommand::Info {
+                op_id,
+                session,
+                sym,
+                ns,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::Info {
+                op_id,
+                session,
+                sym,
+                ns,
+                reply,
+            }),
+            WorkerCommand::ClojureDocs {
+                op_id,
+                session,
+                ns,
+                sym,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::ClojureDocs {
+                op_id,
+                session,
+                ns,
+                sym,
+                reply,
+            }),
+            WorkerCommand::Eldoc {
+                op_id,
+                session,
+                sym,
+                ns,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::Eldoc {
+                op_id,
+                session,
+                sym,
+                ns,
+                reply,
+            }),
+            WorkerCommand::Apropos {
+                op_id,
+                session,
+                query,
+                search_ns,
+                docs,
+                privates,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::Apropos {
+                op_id,
+                session,
+                query,
+                search_ns,
+                docs,
+                privates,
+                reply,
+            }),
+            WorkerCommand::FnRefs {
+                op_id,
+                session,
+                ns,
+                sym,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::FnRefs {
+                op_id,
+                session,
+                ns,
+                sym,
+                reply,
+            }),
+            WorkerCommand::FnDeps {
+                op_id,
+                session,
+                ns,
+                sym,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::FnDeps {
+                op_id,
+                session,
+                ns,
+                sym,
+                reply,
+            }),
+            WorkerCommand::NsList {
+                op_id,
+                session,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::NsList {
+                op_id,
+                session,
+                reply,
+            }),
+            WorkerCommand::NsVars {
+                op_id,
+                session,
+                ns,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::NsVars {
+                op_id,
+                session,
+                ns,
+                reply,
+            }),
+            WorkerCommand::NsPath {
+                op_id,
+                session,
+                ns,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::NsPath {
+                op_id,
+                session,
+                ns,
+                reply,
+            }),
+            WorkerCommand::Classpath {
+                op_id,
+                session,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::Classpath {
+                op_id,
+                session,
+                reply,
+            }),
+            WorkerCommand::SpecList {
+                op_id,
+                session,
+                filter_regex,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::SpecList {
+                op_id,
+                session,
+                filter_regex,
+                reply,
+            }),
+            WorkerCommand::SpecForm {
+                op_id,
+                session,
+                spec_name,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::SpecForm {
+                op_id,
+                session,
+                spec_name,
+                reply,
+            }),
+            WorkerCommand::SpecExample {
+                op_id,
+                session,
+                spec_name,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::SpecExample {
+                op_id,
+                session,
+                spec_name,
+                reply,
+            }),
+            WorkerCommand::Undef {
+                op_id,
+                session,
+                ns,
+                sym,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::Undef {
+                op_id,
+                session,
+                ns,
+                sym,
+                reply,
+            }),
+            WorkerCommand::UndefAll {
+                op_id,
+                session,
+                ns,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::UndefAll {
+                op_id,
+                session,
+                ns,
+                reply,
+            }),
+            WorkerCommand::ToggleTraceVar {
+                op_id,
+                session,
+                ns,
+                sym,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::ToggleTraceVar {
+                op_id,
+                session,
+                ns,
+                sym,
+                reply,
+            }),
+            WorkerCommand::ToggleTraceNs {
+                op_id,
+                session,
+                ns,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::ToggleTraceNs {
+                op_id,
+                session,
+                ns,
+                reply,
+            }),
+            WorkerCommand::FormatCode {
+                op_id,
+                session,
+                code,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::FormatCode {
+                op_id,
+                session,
+                code,
+                reply,
+            }),
+            WorkerCommand::FormatEdn {
+                op_id,
+                session,
+                edn,
+                right_margin,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::FormatEdn {
+                op_id,
+                session,
+                edn,
+                right_margin,
+                reply,
+            }),
+            WorkerCommand::RunTests {
+                op_id,
+                session,
+                selection,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::RunTests {
+                op_id,
+                session,
+                selection,
+                reply,
+            }),
+            WorkerCommand::TestStacktrace {
+                op_id,
+                session,
+                ns,
+                var,
+                index,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::TestStacktrace {
+                op_id,
+                session,
+                ns,
+                var,
+                index,
+                reply,
+            }),
+            WorkerCommand::Refresh {
+                op_id,
+                session,
+                all,
+                options,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::Refresh {
+                op_id,
+                session,
+                all,
+                options,
+                reply,
+            }),
+            WorkerCommand::RefreshClear {
+                op_id,
+                session,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::RefreshClear {
+                op_id,
+                session,
+                reply,
+            }),
+            WorkerCommand::Inspect {
+                op_id,
+                session,
+                code,
+                ns,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::Inspect {
+                op_id,
+                session,
+                code,
+                ns,
+                reply,
+            }),
+            WorkerCommand::Inspector {
+                op_id,
+                session,
+                action,
+                reply,
+            } => Dispatch::Tooling(ToolingCommand::Inspector {
+                op_id,
+                session,
+                action,
+                reply,
+            }),
+            WorkerCommand::InitDebugger {
+                op_id,
+                session,
+                breaks,
+            } => Dispatch::Stream(StreamCommand::InitDebugger {
+                op_id,
+                session,
+                breaks,
+            }),
+            WorkerCommand::SubscribeTap {
+                op_id,
+                session,
+                taps,
+            } => Dispatch::Stream(StreamCommand::SubscribeTap {
+                op_id,
+                session,
+                taps,
+            }),
+            WorkerCommand::OutSubscribe {
+                op_id,
+                session,
+                output,
+            } => Dispatch::Stream(StreamCommand::OutSubscribe {
+                op_id,
+                session,
+                output,
+            }),
+            WorkerCommand::OutUnsubscribe {
+                op_id,
+                session,
+                reply,
+            } => Dispatch::Stream(StreamCommand::OutUnsubscribe {
+                op_id,
+                session,
+                reply,
+            }),
+            WorkerCommand::StartSideloader {
+                op_id,
+                session,
+                provider,
+                lookups,
+            } => Dispatch::Stream(StreamCommand::StartSideloader {
+                op_id,
+                session,
+                provider,
+                lookups,
+            }),
+            WorkerCommand::DebugInput {
+                op_id,
+                session,
+                key,
+                command,
+                reply,
+            } => Dispatch::Stream(StreamCommand::DebugInput {
+                op_id,
+                session,
+                key,
+                command,
+                reply,
+            }),
+        };
+        Command::Dispatch(dispatch)
+    }
+}
+
+/// Dispatch a command: queue evals/load-files; write control ops immediately.
+///
+/// Control ops go straight to the socket, parked in `pending` for their
+/// reply. Bypassing the eval queue is what lets an interrupt or a stdin line
+/// reach the server while an eval is still in flight.
+#[allow(clippy::too_many_arguments)]
+async fn dispatch_command(
+    cmd: Dispatch,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    eval_queue: &mut VecDeque<QueuedEval>,
+    active_eval: &mut Option<String>,
+    response_tx: &ResponseSender,
+    ns_cache: &mut NsCache,
+    cljs_sessions: &mut CljsSessions,
+    server: &ServerInfo,
+    rich_content: bool,
+    output_options: &OutputOptions,
+    cache: Option<&ResponseCache>,
+) {
+    match cmd {
+        Dispatch::Eval(req) => {
+            evals::queue_eval(
+                req,
+                writer,
+                pending,
+                eval_queue,
+                active_eval,
+                response_tx,
+                cljs_sessions,
+                server,
+                rich_content,
+                output_options,
+                cache,
+            )
+            .await;
+        }
+        Dispatch::LoadFile(req) => {
+            evals::queue_load_file(
+                req,
+                writer,
+                pending,
+                eval_queue,
+                active_eval,
+                response_tx,
+                ns_cache,
+                output_options,
+                cache,
+            )
+            .await;
+        }
+        Dispatch::Connect(reply) => {
+            let _ = reply.send(Err(NReplError::protocol("Already connected")));
+        }
+        Dispatch::Cancel(target) => {
+            evals::cancel_request(
+                target,
+                writer,
+                pending,
+                eval_queue,
+                active_eval,
+                response_tx,
+            )
+            .await;
+        }
+        Dispatch::Input(cmd) => {
+            evals::dispatch(cmd, writer, pending, eval_queue, response_tx).await;
+        }
+        Dispatch::Session(cmd) => {
+            sessions::dispatch(cmd, writer, pending, cljs_sessions, ns_cache, server, cache).await;
+        }
+        Dispatch::Completion(cmd) => {
+            completion::dispatch(cmd, writer, pending, ns_cache, cljs_sessions, server, cache)
+                .await;
+        }
+        Dispatch::Stream(cmd) => {
+            streams::dispatch(cmd, writer, pending, output_options).await;
+        }
+        Dispatch::Tooling(cmd) => {
+            send_collect(writer, pending, tooling::collect_op(cmd)).await;
+        }
+    }
+}
+
+/// Route one decoded response to its pending op by request id.
+#[allow(clippy::too_many_arguments)]
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        level = "debug",
+        name = "nrepl.response",
+        skip_all,
+        fields(id = %response.id, status = ?response.status)
+    )
+)]
+async fn route_response(
+    response: Response,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    eval_queue: &mut VecDeque<QueuedEval>,
+    active_eval: &mut Option<String>,
+    response_tx: &ResponseSender,
+    ns_cache: &mut NsCache,
+    cljs_sessions: &mut CljsSessions,
+    server: &ServerInfo,
+    events: &EventLog,
+    cache: Option<&ResponseCache>,
+) {
+    let Some(entry) = pending.get(&response.id) else {
+        // Unknown / timed-out id - discard.
+        return;
+    };
+    let flags = classify(&response.status);
+
+    match entry {
+        Pending::Eval(_) => {
+            evals::route(
+                response,
+                flags,
+                writer,
+                pending,
+                eval_queue,
+                active_eval,
+                response_tx,
+                ns_cache,
+                server,
+                events,
+                cache,
+            )
+            .await;
+        }
+        Pending::Interrupt { .. } => evals::route_interrupt(&response, flags, pending),
+        Pending::Session(_) => {
+            sessions::route(response, flags, writer, pending, cljs_sessions, server).await;
+        }
+        Pending::Completion(_) => {
+            completion::route(response, flags, writer, pending, ns_cache).await;
+        }
+        Pending::Collect { .. } => tooling::route(response, flags, pending),
+        Pending::Stream(_) => {
+            streams::route(response, flags, writer, pending).await;
+        }
+    }
+}
+
+/// Fail everything in flight after [`Worker::abort`](super::Worker::abort),
+/// including commands sent before the abort that the loop has not read yet:
+/// an eval among them would otherwise never be answered.
+fn fail_aborted(
+    pending: &mut HashMap<String, Pending>,
+    eval_queue: &mut VecDeque<QueuedEval>,
+    command_rx: &mut UnboundedReceiver<WorkerCommand>,
+    response_tx: &ResponseSender,
+) {
+    let err = || {
+        NReplError::Connection(std::io::Error::new(
+            std::io::ErrorKind::ConnectionAborted,
+            "connection aborted",
+        ))
+    };
+    fail_all_pending(pending, eval_queue, response_tx, err);
+    while let Ok(cmd) = command_rx.try_recv() {
+        match cmd {
+            WorkerCommand::Eval(EvalRequest { request_id, .. })
+            | WorkerCommand::LoadFile(LoadFileRequest { request_id, .. }) => {
+                response_tx.send(EvalResponse {
+                    request_id,
+                    outcome: EvalOutcome::Done(Err(err())),
+                });
+            }
+            other => reply_not_connected(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_state_notifies_only_on_change() {
+        let (state_tx, mut state_rx) = watch::channel(ConnectionState::Connected);
+        set_state(&state_tx, ConnectionState::Connected);
+        assert!(!state_rx.has_changed().unwrap());
+        set_state(&state_tx, ConnectionState::Degraded);
+        assert!(state_rx.has_changed().unwrap());
+        assert_eq!(*state_rx.borrow_and_update(), ConnectionState::Degraded);
+    }
+}
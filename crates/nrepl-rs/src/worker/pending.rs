@@ -0,0 +1,476 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! What the event loop is waiting on: the queued evals, and one [`Pending`]
+//! entry per request in flight, keyed by wire id.
+
+use super::completion::AliasResolve;
+use super::streams::OutputStream;
+use super::{EvalOutcome, EvalResponse, RequestId, ResponseSender};
+use crate::cache::CacheSlot;
+use crate::cljs::CljsRepl;
+use crate::connection::{EvalAccumulator, NReplWriter};
+use crate::debugger::DebugBreak;
+use crate::error::NReplError;
+use crate::message::{
+    CompletionCandidate, NsAliases, Request, Response, ServerOutput, StatusFlags,
+};
+use crate::session::Session;
+use crate::sideloader::{SideloadLookup, SideloadProvider};
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A queued eval/load-file awaiting its turn behind the active eval.
+pub(super) struct QueuedEval {
+    pub(super) request_id: RequestId,
+    /// Pre-built request (already carries its wire id).
+    pub(super) request: crate::message::Request,
+    /// Wire id of the session the eval runs in (target of a timeout interrupt).
+    pub(super) session: String,
+    pub(super) timeout: Duration,
+    /// Collects the eval's responses once it runs.
+    pub(super) acc: EvalAccumulator,
+}
+
+/// In-flight eval state tracked in the demux loop.
+pub(super) struct EvalState {
+    pub(super) request_id: RequestId,
+    pub(super) acc: EvalAccumulator,
+    pub(super) session: String,
+    /// The namespace the eval runs in, once known: the one the request named,
+    /// else where the session was when the first response came back.
+    pub(super) ns: Option<String>,
+    pub(super) timeout: Duration,
+    pub(super) deadline: Instant,
+    /// True while parked on `need-input` (deadline suspended).
+    pub(super) parked: bool,
+}
+
+/// A control op awaiting its response, keyed in the pending map by wire id.
+///
+/// `Eval` is the large, common variant; boxing it to shrink the rarely-used
+/// control variants would add an allocation on the hot eval path, so we accept
+/// the size difference.
+#[allow(clippy::large_enum_variant)]
+pub(super) enum Pending {
+    Eval(EvalState),
+    Interrupt {
+        reply: Sender<Result<(), NReplError>>,
+    },
+    /// A session op; see [`sessions`](super::sessions).
+    Session(SessionOp),
+    /// A completion or lookup; see [`completion`](super::completion).
+    Completion(CompletionOp),
+    /// An op whose responses are gathered until `done`, then handed to
+    /// `finish` to parse and reply. New ops use this rather than a bespoke
+    /// variant.
+    Collect {
+        op: &'static str,
+        responses: Vec<Response>,
+        finish: CollectFinish,
+        /// Whether the done watchdog applies. Off for ops, such as test
+        /// runs, that may legitimately take longer.
+        watched: bool,
+    },
+    /// A request answered past its first `done`; see
+    /// [`streams`](super::streams).
+    Stream(StreamOp),
+}
+
+/// A [`Pending`] session op.
+///
+/// Held inside [`Pending`], whose `Eval` variant is larger still, so boxing
+/// the big variant here would save nothing.
+#[allow(clippy::large_enum_variant)]
+pub(super) enum SessionOp {
+    CloneSession {
+        reply: Sender<Result<Session, NReplError>>,
+        new_session: Option<String>,
+        /// Whether the clone inherits a ClojureScript REPL from its source.
+        cljs: bool,
+    },
+    CloseSession {
+        reply: Sender<Result<(), NReplError>>,
+    },
+    Describe {
+        reply: Sender<Result<Response, NReplError>>,
+        last: Option<Response>,
+    },
+    LsSessions {
+        reply: Sender<Result<Vec<String>, NReplError>>,
+        sessions: Vec<String>,
+    },
+    /// The eval upgrading a session to ClojureScript.
+    CljsUpgrade {
+        session: String,
+        repl: CljsRepl,
+        reply: Sender<Result<(), NReplError>>,
+        value: Option<String>,
+        err: String,
+        ex: bool,
+    },
+}
+
+/// A [`Pending`] completion, lookup or alias query. Sized like
+/// [`SessionOp`], for the same reason.
+#[allow(clippy::large_enum_variant)]
+pub(super) enum CompletionOp {
+    Completions {
+        reply: Sender<Result<Vec<CompletionCandidate>, NReplError>>,
+        candidates: Vec<CompletionCandidate>,
+        /// `(alias, namespace)` to re-apply to candidates, for completions
+        /// issued on behalf of
+        /// [`WorkerCommand::AliasedCompletions`](super::WorkerCommand::AliasedCompletions).
+        alias: Option<(String, String)>,
+        /// Where the reply goes in the
+        /// [`ResponseCache`](crate::cache::ResponseCache), if one is kept.
+        cache: Option<CacheSlot>,
+    },
+    /// First step of an aliased completion: resolving the alias.
+    AliasResolve(Box<AliasResolve>),
+    Lookup {
+        reply: Sender<Result<Response, NReplError>>,
+        last: Option<Response>,
+        cache: Option<CacheSlot>,
+    },
+    NsAliases {
+        session: String,
+        ns: String,
+        reply: Sender<Result<NsAliases, NReplError>>,
+        value: Option<String>,
+        err: String,
+    },
+}
+
+/// A [`Pending`] subscription: its responses keep coming after `done`.
+pub(super) enum StreamOp {
+    /// An `init-debugger` subscription, forwarding each stop to `breaks`.
+    Debugger {
+        session: String,
+        breaks: Sender<Result<DebugBreak, NReplError>>,
+    },
+    /// An `out-subscribe` subscription, forwarding server output to `output`
+    /// past the request's `done`.
+    OutSubscription {
+        session: String,
+        output: Sender<Result<ServerOutput, NReplError>>,
+        stream: OutputStream,
+    },
+    /// A `sideloader-start` request, answering each lookup from `provider`.
+    Sideloader {
+        session: String,
+        provider: SideloadProvider,
+        lookups: Sender<Result<SideloadLookup, NReplError>>,
+        /// Lookups answered so far, numbering the provide requests.
+        answered: usize,
+    },
+    /// A tap subscription's eval, forwarding each printed value to `taps`.
+    Tap {
+        session: String,
+        taps: Sender<Result<String, NReplError>>,
+        /// Output not yet ended by a newline.
+        line: String,
+        err: String,
+        failed: bool,
+        /// Whether someone interrupted the eval, which ends the subscription
+        /// quietly even though the interrupted wait throws.
+        interrupted: bool,
+    },
+}
+
+/// Completion for a [`Pending::Collect`] op: receives every response, or the
+/// error that ended the op, and replies to the caller.
+pub(super) type CollectFinish = Box<dyn FnOnce(Result<Vec<Response>, NReplError>) + Send>;
+
+/// A [`CollectFinish`] that parses the responses with `parse` and sends the
+/// outcome on `reply`.
+pub(super) fn collect_into<T: Send + 'static>(
+    reply: Sender<Result<T, NReplError>>,
+    parse: impl FnOnce(Vec<Response>) -> Result<T, NReplError> + Send + 'static,
+) -> CollectFinish {
+    Box::new(move |responses| {
+        let _ = reply.send(responses.and_then(parse));
+    })
+}
+
+impl Pending {
+    /// The wire op a control entry is waiting on, or `None` for an eval
+    /// (which has its own deadline).
+    pub(super) fn control_op(&self) -> Option<&'static str> {
+        match self {
+            Pending::Eval(_) => None,
+            Pending::Interrupt { .. } => Some("interrupt"),
+            Pending::Session(op) => Some(op.wire_op()),
+            Pending::Completion(op) => Some(op.wire_op()),
+            Pending::Collect { op, .. } => Some(op),
+            Pending::Stream(sub) => Some(sub.wire_op()),
+        }
+    }
+
+    /// The session an eval or subscription is holding, which keeps it from
+    /// counting as idle.
+    pub(super) fn session(&self) -> Option<&str> {
+        match self {
+            Pending::Eval(state) => Some(&state.session),
+            Pending::Session(SessionOp::CljsUpgrade { session, .. }) => Some(session),
+            Pending::Stream(sub) => Some(sub.session()),
+            _ => None,
+        }
+    }
+
+    /// Whether the done watchdog times this entry out.
+    pub(super) fn watched(&self) -> bool {
+        match self {
+            Pending::Collect { watched, .. } => *watched,
+            // Starting a ClojureScript REPL (a shadow-cljs build, say) often
+            // outlasts the limit.
+            Pending::Stream(_) | Pending::Session(SessionOp::CljsUpgrade { .. }) => false,
+            other => other.control_op().is_some(),
+        }
+    }
+}
+
+impl SessionOp {
+    /// The wire op the entry is waiting on.
+    fn wire_op(&self) -> &'static str {
+        match self {
+            SessionOp::CloneSession { .. } => "clone",
+            SessionOp::CloseSession { .. } => "close",
+            SessionOp::Describe { .. } => "describe",
+            SessionOp::LsSessions { .. } => "ls-sessions",
+            SessionOp::CljsUpgrade { .. } => "eval",
+        }
+    }
+
+    /// Answer the caller with `err`.
+    fn fail(self, err: NReplError) {
+        match self {
+            SessionOp::CloneSession { reply, .. } => {
+                let _ = reply.send(Err(err));
+            }
+            SessionOp::CloseSession { reply } | SessionOp::CljsUpgrade { reply, .. } => {
+                let _ = reply.send(Err(err));
+            }
+            SessionOp::Describe { reply, .. } => {
+                let _ = reply.send(Err(err));
+            }
+            SessionOp::LsSessions { reply, .. } => {
+                let _ = reply.send(Err(err));
+            }
+        }
+    }
+}
+
+impl CompletionOp {
+    /// The wire op the entry is waiting on.
+    fn wire_op(&self) -> &'static str {
+        match self {
+            CompletionOp::Completions { .. } => "completions",
+            CompletionOp::AliasResolve(_) | CompletionOp::NsAliases { .. } => "eval",
+            CompletionOp::Lookup { .. } => "lookup",
+        }
+    }
+
+    /// Answer the caller with `err`.
+    fn fail(self, err: NReplError) {
+        match self {
+            CompletionOp::Completions { reply, .. } => {
+                let _ = reply.send(Err(err));
+            }
+            CompletionOp::AliasResolve(state) => {
+                let _ = state.reply.send(Err(err));
+            }
+            CompletionOp::Lookup { reply, .. } => {
+                let _ = reply.send(Err(err));
+            }
+            CompletionOp::NsAliases { reply, .. } => {
+                let _ = reply.send(Err(err));
+            }
+        }
+    }
+}
+
+impl StreamOp {
+    /// The wire op the entry is waiting on.
+    fn wire_op(&self) -> &'static str {
+        match self {
+            StreamOp::Debugger { .. } => "init-debugger",
+            StreamOp::OutSubscription { .. } => "out-subscribe",
+            StreamOp::Sideloader { .. } => "sideloader-start",
+            StreamOp::Tap { .. } => "eval",
+        }
+    }
+
+    /// The session the subscription holds.
+    fn session(&self) -> &str {
+        match self {
+            StreamOp::Debugger { session, .. }
+            | StreamOp::OutSubscription { session, .. }
+            | StreamOp::Sideloader { session, .. }
+            | StreamOp::Tap { session, .. } => session,
+        }
+    }
+
+    /// Answer the caller with `err`.
+    fn fail(self, err: NReplError) {
+        match self {
+            StreamOp::Debugger { breaks, .. } => {
+                let _ = breaks.send(Err(err));
+            }
+            StreamOp::OutSubscription {
+                output, mut stream, ..
+            } => {
+                if let Some(held) = stream.take_held(None) {
+                    let _ = output.send(Ok(held));
+                }
+                let _ = output.send(Err(err));
+            }
+            StreamOp::Sideloader { lookups, .. } => {
+                let _ = lookups.send(Err(err));
+            }
+            StreamOp::Tap { taps, .. } => {
+                let _ = taps.send(Err(err));
+            }
+        }
+    }
+}
+
+/// True when this response terminates its op.
+///
+/// nREPL ends an op with `done`, but a server that rejects the op answers with
+/// `error` or `unknown-op` and never sends `done`, so all three have to retire
+/// the pending entry or the caller waits forever.
+pub(super) fn op_finished(flags: StatusFlags) -> bool {
+    flags.done || flags.error || flags.unknown_op
+}
+
+/// The error returned when the server does not implement `op`.
+pub(super) fn unknown_op_err(op: &str) -> NReplError {
+    NReplError::OperationFailed(format!("server does not support {op}"))
+}
+
+/// Write a control request, then park `$entry` under its wire id so the
+/// response can be routed back. On a write failure there is nothing to park,
+/// so the caller is answered with the error immediately.
+macro_rules! send_control {
+    ($writer:expr, $pending:expr, $op_id:expr, $reply:expr, $request:expr, $entry:expr) => {
+        match $writer.send(&$request).await {
+            Ok(()) => {
+                $pending.insert($op_id.wire(), $entry);
+            }
+            Err(e) => {
+                let _ = $reply.send(Err(e));
+            }
+        }
+    };
+}
+
+pub(super) use send_control;
+
+/// A [`Pending::Collect`] op, ready to write.
+pub(super) struct CollectOp {
+    pub(super) op_id: RequestId,
+    pub(super) request: Request,
+    /// The wire op, for errors and the done watchdog.
+    pub(super) op: &'static str,
+    pub(super) finish: CollectFinish,
+    /// Whether the done watchdog applies.
+    pub(super) watched: bool,
+}
+
+/// Write a [`Pending::Collect`] op, or finish it with the write error.
+pub(super) async fn send_collect(
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    op: CollectOp,
+) {
+    let CollectOp {
+        op_id,
+        request,
+        op,
+        finish,
+        watched,
+    } = op;
+    match writer.send(&request).await {
+        Ok(()) => {
+            pending.insert(
+                op_id.wire(),
+                Pending::Collect {
+                    op,
+                    responses: Vec::new(),
+                    finish,
+                    watched,
+                },
+            );
+        }
+        Err(e) => finish(Err(e)),
+    }
+}
+
+/// Build the unit result for a control op that completed, honouring `err`,
+/// `unknown-op` and `error` status (conformance #3).
+pub(super) fn op_unit_result(
+    response: &Response,
+    flags: StatusFlags,
+    op: &str,
+) -> Result<(), NReplError> {
+    if flags.unknown_op {
+        return Err(unknown_op_err(op));
+    }
+    if let Some(err) = &response.err {
+        return Err(NReplError::OperationFailed(format!("{op} failed: {err}")));
+    }
+    if flags.error {
+        return Err(NReplError::OperationFailed(format!("{op} failed")));
+    }
+    Ok(())
+}
+
+/// Fail every pending op and queued eval with the given error (connection lost
+/// / shutdown).
+pub(super) fn fail_all_pending(
+    pending: &mut HashMap<String, Pending>,
+    eval_queue: &mut VecDeque<QueuedEval>,
+    response_tx: &ResponseSender,
+    make_err: impl Fn() -> NReplError,
+) {
+    for (_id, p) in pending.drain() {
+        fail_pending(p, response_tx, make_err());
+    }
+    for queued in eval_queue.drain(..) {
+        response_tx.send(EvalResponse {
+            request_id: queued.request_id,
+            outcome: EvalOutcome::Done(Err(make_err())),
+        });
+    }
+}
+
+/// Answer one pending op's caller with `err`.
+pub(super) fn fail_pending(p: Pending, response_tx: &ResponseSender, err: NReplError) {
+    match p {
+        Pending::Eval(state) => {
+            response_tx.send(EvalResponse {
+                request_id: state.request_id,
+                outcome: EvalOutcome::Done(Err(err)),
+            });
+        }
+        Pending::Interrupt { reply } => {
+            let _ = reply.send(Err(err));
+        }
+        Pending::Session(op) => op.fail(err),
+        Pending::Completion(op) => op.fail(err),
+        Pending::Collect { finish, .. } => finish(Err(err)),
+        Pending::Stream(sub) => sub.fail(err),
+    }
+}
@@ -0,0 +1,361 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Session ops (clone, close, describe, `ls-sessions`, the ClojureScript
+//! upgrade) and closing the sessions left idle.
+
+use super::completion::NsCache;
+use super::pending::{
+    Pending, QueuedEval, SessionOp, op_finished, op_unit_result, send_control, unknown_op_err,
+};
+use super::{RequestId, WorkerConfig};
+use crate::cache::ResponseCache;
+use crate::cljs::CljsRepl;
+use crate::connection::NReplWriter;
+use crate::error::NReplError;
+use crate::events::DebugEventKind;
+use crate::flavor::{ServerInfo, ServerProfile};
+use crate::message::{Response, StatusFlags};
+use crate::ops;
+use crate::session::Session;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+
+/// Wire ids of the sessions upgraded with [`SessionCommand::UpgradeCljs`].
+pub(super) type CljsSessions = HashSet<String>;
+
+/// The session variants of [`WorkerCommand`](super::WorkerCommand), for
+/// [`dispatch`].
+pub(super) enum SessionCommand {
+    CloneSession {
+        op_id: RequestId,
+        reply: Sender<Result<Session, NReplError>>,
+    },
+    CloneSessionFrom {
+        op_id: RequestId,
+        session: Session,
+        reply: Sender<Result<Session, NReplError>>,
+    },
+    CloseSession {
+        op_id: RequestId,
+        session: Session,
+        reply: Sender<Result<(), NReplError>>,
+    },
+    Describe {
+        op_id: RequestId,
+        verbose: bool,
+        reply: Sender<Result<Response, NReplError>>,
+    },
+    LsSessions {
+        op_id: RequestId,
+        reply: Sender<Result<Vec<String>, NReplError>>,
+    },
+    UpgradeCljs {
+        op_id: RequestId,
+        session: Session,
+        repl: CljsRepl,
+        reply: Sender<Result<(), NReplError>>,
+    },
+}
+
+/// Write a session op: clone, close, describe, list, or the ClojureScript
+/// upgrade.
+pub(super) async fn dispatch(
+    cmd: SessionCommand,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    cljs_sessions: &mut CljsSessions,
+    ns_cache: &mut NsCache,
+    server: &ServerInfo,
+    cache: Option<&ResponseCache>,
+) {
+    match cmd {
+        SessionCommand::CloneSession { op_id, reply } => {
+            let request = ops::clone_request(op_id.wire());
+            send_control!(
+                writer,
+                pending,
+                op_id,
+                reply,
+                request,
+                Pending::Session(SessionOp::CloneSession {
+                    reply,
+                    new_session: None,
+                    cljs: false,
+                })
+            );
+        }
+        SessionCommand::CloneSessionFrom {
+            op_id,
+            session,
+            reply,
+        } => {
+            let request = ops::clone_from_request(op_id.wire(), session.id());
+            send_control!(
+                writer,
+                pending,
+                op_id,
+                reply,
+                request,
+                Pending::Session(SessionOp::CloneSession {
+                    reply,
+                    new_session: None,
+                    cljs: cljs_sessions.contains(session.id()),
+                })
+            );
+        }
+        SessionCommand::CloseSession {
+            op_id,
+            session,
+            reply,
+        } => {
+            cljs_sessions.remove(session.id());
+            ns_cache.retain(|(cached, _), _| cached != session.id());
+            server.forget_session(session.id());
+            if let Some(cache) = cache {
+                cache.forget(session.id());
+            }
+            let request = ops::close_request(op_id.wire(), session.id());
+            send_control!(
+                writer,
+                pending,
+                op_id,
+                reply,
+                request,
+                Pending::Session(SessionOp::CloseSession { reply })
+            );
+            writer.sessions().forget(session.id());
+        }
+        SessionCommand::Describe {
+            op_id,
+            verbose,
+            reply,
+        } => {
+            let request = ops::describe_request(op_id.wire(), Some(verbose));
+            send_control!(
+                writer,
+                pending,
+                op_id,
+                reply,
+                request,
+                Pending::Session(SessionOp::Describe { reply, last: None })
+            );
+        }
+        SessionCommand::LsSessions { op_id, reply } => {
+            let request = ops::ls_sessions_request(op_id.wire());
+            send_control!(
+                writer,
+                pending,
+                op_id,
+                reply,
+                request,
+                Pending::Session(SessionOp::LsSessions {
+                    reply,
+                    sessions: Vec::new(),
+                })
+            );
+        }
+        SessionCommand::UpgradeCljs {
+            op_id,
+            session,
+            repl,
+            reply,
+        } => match repl.upgrade_form() {
+            Ok(form) => {
+                let request = ops::eval_request_with_location(
+                    op_id.wire(),
+                    session.id(),
+                    form,
+                    None,
+                    None,
+                    None,
+                );
+                send_control!(
+                    writer,
+                    pending,
+                    op_id,
+                    reply,
+                    request,
+                    Pending::Session(SessionOp::CljsUpgrade {
+                        session: session.id().to_string(),
+                        repl,
+                        reply,
+                        value: None,
+                        err: String::new(),
+                        ex: false,
+                    })
+                );
+            }
+            Err(e) => {
+                let _ = reply.send(Err(e));
+            }
+        },
+    }
+}
+
+/// Route a response to a session op.
+pub(super) async fn route(
+    response: Response,
+    flags: StatusFlags,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    cljs_sessions: &mut CljsSessions,
+    server: &ServerInfo,
+) {
+    let id = response.id.clone();
+    let Some(Pending::Session(entry)) = pending.get_mut(&id) else {
+        return;
+    };
+    match entry {
+        SessionOp::CloneSession { new_session, .. } => {
+            if let Some(s) = response.new_session.clone() {
+                *new_session = Some(s);
+            }
+            if op_finished(flags)
+                && let Some(Pending::Session(SessionOp::CloneSession {
+                    reply,
+                    new_session,
+                    cljs,
+                })) = pending.remove(&id)
+            {
+                let result = match new_session {
+                    Some(s) => {
+                        writer.sessions().opened(&s);
+                        if cljs {
+                            cljs_sessions.insert(s.clone());
+                        }
+                        Ok(Session::from_server_id(s))
+                    }
+                    None => Err(NReplError::protocol(
+                        "Missing new-session in clone response",
+                    )),
+                };
+                let _ = reply.send(result);
+            }
+        }
+        SessionOp::CloseSession { .. } => {
+            if op_finished(flags)
+                && let Some(Pending::Session(SessionOp::CloseSession { reply })) =
+                    pending.remove(&id)
+            {
+                let _ = reply.send(op_unit_result(&response, flags, "close"));
+            }
+        }
+        SessionOp::Describe { last, .. } => {
+            *last = Some(response.clone());
+            if op_finished(flags)
+                && let Some(Pending::Session(SessionOp::Describe { reply, last })) =
+                    pending.remove(&id)
+            {
+                let result = if flags.unknown_op {
+                    Err(unknown_op_err("describe"))
+                } else {
+                    last.ok_or_else(|| NReplError::protocol("No describe response"))
+                };
+                if let Ok(described) = &result {
+                    server.set_profile(ServerProfile::from_describe(described));
+                }
+                let _ = reply.send(result);
+            }
+        }
+        SessionOp::LsSessions { sessions, .. } => {
+            if let Some(s) = response.sessions.clone() {
+                sessions.extend(s);
+            }
+            if op_finished(flags)
+                && let Some(Pending::Session(SessionOp::LsSessions { reply, sessions })) =
+                    pending.remove(&id)
+            {
+                let result = if flags.unknown_op {
+                    Err(unknown_op_err("ls-sessions"))
+                } else {
+                    Ok(sessions)
+                };
+                let _ = reply.send(result);
+            }
+        }
+        SessionOp::CljsUpgrade { value, err, ex, .. } => {
+            if let Some(v) = &response.value {
+                *value = Some(v.clone());
+            }
+            if let Some(e) = &response.err {
+                err.push_str(e);
+            }
+            *ex |= response.ex.is_some();
+            if op_finished(flags)
+                && let Some(Pending::Session(SessionOp::CljsUpgrade {
+                    session,
+                    repl,
+                    reply,
+                    value,
+                    err,
+                    ex,
+                })) = pending.remove(&id)
+            {
+                let result = if ex || flags.error {
+                    Err(NReplError::OperationFailed(format!(
+                        "ClojureScript REPL did not start: {}",
+                        err.trim()
+                    )))
+                } else {
+                    repl.check_upgrade(value.as_deref())
+                };
+                if result.is_ok() {
+                    cljs_sessions.insert(session);
+                }
+                let _ = reply.send(result);
+            }
+        }
+    }
+}
+
+/// Close the sessions idle for longer than the configured TTL. Nobody waits
+/// on the `close` replies: they hit no pending entry and are discarded.
+pub(super) async fn expire_idle_sessions(
+    writer: &mut NReplWriter,
+    pending: &HashMap<String, Pending>,
+    eval_queue: &VecDeque<QueuedEval>,
+    cljs_sessions: &mut CljsSessions,
+    ns_cache: &mut NsCache,
+    config: &WorkerConfig,
+    id_source: &AtomicUsize,
+) {
+    let Some(ttl) = config.session_expiry.ttl else {
+        return;
+    };
+    let busy: HashSet<&str> = pending
+        .values()
+        .filter_map(Pending::session)
+        .chain(eval_queue.iter().map(|q| q.session.as_str()))
+        .collect();
+    for info in writer.sessions().idle(ttl, &busy) {
+        let id = info.session.id();
+        let op_id = RequestId::new(id_source.fetch_add(1, Ordering::Relaxed));
+        let _ = writer.send(&ops::close_request(op_id.wire(), id)).await;
+        // After the send, which would otherwise count it as used again.
+        writer.sessions().forget(id);
+        cljs_sessions.remove(id);
+        ns_cache.retain(|(cached, _), _| cached != id);
+        config.server.forget_session(id);
+        if let Some(cache) = &config.cache {
+            cache.forget(id);
+        }
+        config.events.record(
+            DebugEventKind::SessionExpired,
+            format!("closed session {id} after {ttl:?} idle"),
+        );
+        if let Some(hook) = &config.session_expiry.hook {
+            hook(&info);
+        }
+    }
+}
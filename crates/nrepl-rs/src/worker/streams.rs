@@ -0,0 +1,525 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Subscriptions: requests that go on answering past their first `done`
+//! (the debugger, taps, forwarded output and the sideloader).
+
+use super::pending::{
+    CollectOp, Pending, StreamOp, collect_into, op_unit_result, send_collect, send_control,
+    unknown_op_err,
+};
+use super::{OutputOptions, RequestId};
+use crate::ansi::AnsiFilter;
+use crate::base64;
+use crate::connection::NReplWriter;
+use crate::debugger::{DebugBreak, DebugCommand};
+use crate::error::NReplError;
+use crate::message::{OutputCoalescer, Response, ServerOutput, StatusFlags};
+use crate::ops;
+use crate::session::Session;
+use crate::sideloader::{SideloadKind, SideloadLookup, SideloadProvider};
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use tokio::time::Instant;
+
+/// Clojure form behind [`StreamCommand::SubscribeTap`]: registers a tap and
+/// prints each value it receives on a line of its own, until interrupted.
+/// Values are boxed because the queue cannot hold `nil`.
+const TAP_LOOP_FORM: &str = "\
+(let [q (java.util.concurrent.LinkedBlockingQueue.) \
+      f (fn [v] (.put q [v]))] \
+  (add-tap f) \
+  (try \
+    (loop [] \
+      (let [[v] (.take q)] \
+        (binding [*print-length* nil *print-level* nil] (prn v)) \
+        (flush)) \
+      (recur)) \
+    (finally (remove-tap f))))";
+
+/// Interrupt the tap subscription eval `wire` in `session`, which removes its
+/// tap server-side. The interrupt's own reply is not waited for.
+pub(super) async fn stop_tap(writer: &mut NReplWriter, session: &str, wire: &str) {
+    let request = ops::interrupt_request(format!("{wire}-interrupt"), session, wire);
+    let _ = writer.send(&request).await;
+}
+
+/// Ask the server to stop forwarding output to `session`, for the
+/// subscription `wire`. The reply is not waited for.
+pub(super) async fn unsubscribe_out(writer: &mut NReplWriter, session: &str, wire: &str) {
+    let request = ops::out_unsubscribe_request(format!("{wire}-unsubscribe"), session);
+    let _ = writer.send(&request).await;
+}
+
+/// Turns an `out-subscribe` subscription's responses into [`ServerOutput`]
+/// by the worker's [`OutputOptions`].
+pub(super) struct OutputStream {
+    /// Holds unfinished lines under
+    /// [`WorkerConfig::coalesce_output`](super::WorkerConfig::coalesce_output).
+    coalescer: Option<OutputCoalescer>,
+    ansi: AnsiFilter,
+}
+
+impl OutputStream {
+    fn new(options: &OutputOptions) -> Self {
+        Self {
+            coalescer: options.coalesce.map(OutputCoalescer::new),
+            ansi: AnsiFilter::new(options.ansi),
+        }
+    }
+
+    /// The output `response` carries that is ready to forward.
+    fn push(&mut self, response: &Response) -> Vec<ServerOutput> {
+        let out = response.out.iter().map(|text| (false, text));
+        let err = response.err.iter().map(|text| (true, text));
+        let pieces: Vec<(bool, String)> = match &mut self.coalescer {
+            Some(coalescer) => {
+                let now = Instant::now().into_std();
+                out.chain(err)
+                    .flat_map(|(stderr, text)| coalescer.push(stderr, text, now))
+                    .collect()
+            }
+            None => out.chain(err).map(|(s, text)| (s, text.clone())).collect(),
+        };
+        pieces
+            .into_iter()
+            .map(|(stderr, text)| ServerOutput::new(stderr, text, &mut self.ansi))
+            .collect()
+    }
+
+    /// When held-back output is due out.
+    fn deadline(&self) -> Option<Instant> {
+        self.coalescer
+            .as_ref()
+            .and_then(OutputCoalescer::deadline)
+            .map(Instant::from_std)
+    }
+
+    /// Held-back output that is due out by `now`, or all of it if `now` is
+    /// `None`.
+    pub(super) fn take_held(&mut self, now: Option<Instant>) -> Option<ServerOutput> {
+        let coalescer = self.coalescer.as_mut()?;
+        let (stderr, text) = match now {
+            Some(now) => coalescer.poll(now.into_std()),
+            None => coalescer.flush(),
+        }?;
+        Some(ServerOutput::new(stderr, text, &mut self.ansi))
+    }
+}
+
+/// When the earliest output an `out-subscribe` subscription holds back is
+/// due out.
+pub(super) fn held_output_deadline(pending: &HashMap<String, Pending>) -> Option<Instant> {
+    pending
+        .values()
+        .filter_map(|p| match p {
+            Pending::Stream(StreamOp::OutSubscription { stream, .. }) => stream.deadline(),
+            _ => None,
+        })
+        .min()
+}
+
+/// Send the held-back subscription output that has waited long enough,
+/// unsubscribing any subscription nobody listens to any more.
+pub(super) async fn send_held_output(
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+) {
+    let now = Instant::now();
+    let mut gone = Vec::new();
+    for (id, p) in pending.iter_mut() {
+        if let Pending::Stream(StreamOp::OutSubscription {
+            session,
+            output,
+            stream,
+        }) = p
+            && let Some(held) = stream.take_held(Some(now))
+            && output.send(Ok(held)).is_err()
+        {
+            gone.push((id.clone(), session.clone()));
+        }
+    }
+    for (id, session) in gone {
+        pending.remove(&id);
+        unsubscribe_out(writer, &session, &id).await;
+    }
+}
+
+/// The variants of [`WorkerCommand`](super::WorkerCommand) that start or
+/// stop a subscription, for [`dispatch`].
+pub(super) enum StreamCommand {
+    InitDebugger {
+        op_id: RequestId,
+        session: Session,
+        breaks: Sender<Result<DebugBreak, NReplError>>,
+    },
+    SubscribeTap {
+        op_id: RequestId,
+        session: Session,
+        taps: Sender<Result<String, NReplError>>,
+    },
+    OutSubscribe {
+        op_id: RequestId,
+        session: Session,
+        output: Sender<Result<ServerOutput, NReplError>>,
+    },
+    OutUnsubscribe {
+        op_id: RequestId,
+        session: Session,
+        reply: Sender<Result<(), NReplError>>,
+    },
+    StartSideloader {
+        op_id: RequestId,
+        session: Session,
+        provider: SideloadProvider,
+        lookups: Sender<Result<SideloadLookup, NReplError>>,
+    },
+    DebugInput {
+        op_id: RequestId,
+        session: Session,
+        key: String,
+        command: DebugCommand,
+        reply: Sender<Result<(), NReplError>>,
+    },
+}
+
+/// Start or stop a subscription: the debugger, taps, forwarded output or
+/// the sideloader, which all keep answering after their first `done`.
+pub(super) async fn dispatch(
+    cmd: StreamCommand,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+    output_options: &OutputOptions,
+) {
+    match cmd {
+        StreamCommand::InitDebugger {
+            op_id,
+            session,
+            breaks,
+        } => {
+            let request = ops::init_debugger_request(op_id.wire(), session.id());
+            send_control!(
+                writer,
+                pending,
+                op_id,
+                breaks,
+                request,
+                Pending::Stream(StreamOp::Debugger {
+                    session: session.id().to_string(),
+                    breaks,
+                })
+            );
+        }
+        StreamCommand::SubscribeTap {
+            op_id,
+            session,
+            taps,
+        } => {
+            let request = ops::eval_request_with_location(
+                op_id.wire(),
+                session.id(),
+                TAP_LOOP_FORM,
+                None,
+                None,
+                None,
+            );
+            send_control!(
+                writer,
+                pending,
+                op_id,
+                taps,
+                request,
+                Pending::Stream(StreamOp::Tap {
+                    session: session.id().to_string(),
+                    taps,
+                    line: String::new(),
+                    err: String::new(),
+                    failed: false,
+                    interrupted: false,
+                })
+            );
+        }
+        StreamCommand::OutSubscribe {
+            op_id,
+            session,
+            output,
+        } => {
+            let request = ops::out_subscribe_request(op_id.wire(), session.id());
+            send_control!(
+                writer,
+                pending,
+                op_id,
+                output,
+                request,
+                Pending::Stream(StreamOp::OutSubscription {
+                    session: session.id().to_string(),
+                    output,
+                    stream: OutputStream::new(output_options),
+                })
+            );
+        }
+        StreamCommand::OutUnsubscribe {
+            op_id,
+            session,
+            reply,
+        } => {
+            // Anything forwarded after this point has nowhere to go; what
+            // was held back is sent as it stands.
+            pending.retain(|_, p| match p {
+                Pending::Stream(StreamOp::OutSubscription {
+                    session: s,
+                    output,
+                    stream,
+                }) if s == session.id() => {
+                    if let Some(held) = stream.take_held(None) {
+                        let _ = output.send(Ok(held));
+                    }
+                    false
+                }
+                _ => true,
+            });
+            let request = ops::out_unsubscribe_request(op_id.wire(), session.id());
+            let finish = collect_into(reply, |_| Ok(()));
+            send_collect(
+                writer,
+                pending,
+                CollectOp {
+                    op_id,
+                    request,
+                    op: "out-unsubscribe",
+                    finish,
+                    watched: true,
+                },
+            )
+            .await;
+        }
+        StreamCommand::StartSideloader {
+            op_id,
+            session,
+            provider,
+            lookups,
+        } => {
+            let request = ops::sideloader_start_request(op_id.wire(), session.id());
+            send_control!(
+                writer,
+                pending,
+                op_id,
+                lookups,
+                request,
+                Pending::Stream(StreamOp::Sideloader {
+                    session: session.id().to_string(),
+                    provider,
+                    lookups,
+                    answered: 0,
+                })
+            );
+        }
+        StreamCommand::DebugInput {
+            op_id,
+            session,
+            key,
+            command,
+            reply,
+        } => {
+            // The stopped eval is about to run again: give it its timeout
+            // back. A later stop parks it anew.
+            for p in pending.values_mut() {
+                if let Pending::Eval(state) = p
+                    && state.parked
+                    && state.session == session.id()
+                {
+                    state.parked = false;
+                    state.deadline = Instant::now() + state.timeout;
+                }
+            }
+            let request =
+                ops::debug_input_request(op_id.wire(), session.id(), key, command.input());
+            let finish = collect_into(reply, |_| Ok(()));
+            send_collect(
+                writer,
+                pending,
+                CollectOp {
+                    op_id,
+                    request,
+                    op: "debug-input",
+                    finish,
+                    watched: true,
+                },
+            )
+            .await;
+        }
+    }
+}
+
+/// Route a response to a subscription.
+// One arm per subscription, each speaking its own protocol, so the match is
+// long but flat.
+#[allow(clippy::too_many_lines)]
+pub(super) async fn route(
+    response: Response,
+    flags: StatusFlags,
+    writer: &mut NReplWriter,
+    pending: &mut HashMap<String, Pending>,
+) {
+    let id = response.id.clone();
+    let Some(Pending::Stream(entry)) = pending.get_mut(&id) else {
+        return;
+    };
+    match entry {
+        StreamOp::Debugger { breaks, .. } => {
+            if flags.unknown_op || flags.error {
+                if let Err(e) = op_unit_result(&response, flags, "init-debugger") {
+                    let _ = breaks.send(Err(e));
+                }
+                pending.remove(&id);
+                return;
+            }
+            if flags.done {
+                // The server let go of the subscription (its session closed).
+                pending.remove(&id);
+                return;
+            }
+            let Some(stop) = DebugBreak::from_response(&response) else {
+                return;
+            };
+            let breaks = breaks.clone();
+            // The eval that hit the breakpoint waits on the user, not the
+            // server: suspend its deadline as for need-input.
+            if let Some(Pending::Eval(state)) =
+                stop.original_id.as_ref().and_then(|o| pending.get_mut(o))
+            {
+                state.parked = true;
+            }
+            if breaks.send(Ok(stop)).is_err() {
+                // Nobody is listening any more.
+                pending.remove(&id);
+            }
+        }
+        StreamOp::OutSubscription {
+            session,
+            output,
+            stream,
+        } => {
+            if flags.unknown_op || flags.error {
+                if let Err(e) = op_unit_result(&response, flags, "out-subscribe") {
+                    let _ = output.send(Err(e));
+                }
+                pending.remove(&id);
+                return;
+            }
+            // `done` only acknowledges the subscription; output follows it.
+            for chunk in stream.push(&response) {
+                if output.send(Ok(chunk)).is_err() {
+                    // Nobody is listening any more.
+                    let session = session.clone();
+                    pending.remove(&id);
+                    unsubscribe_out(writer, &session, &id).await;
+                    return;
+                }
+            }
+        }
+        StreamOp::Sideloader {
+            session,
+            provider,
+            lookups,
+            answered,
+        } => {
+            if flags.unknown_op || flags.error {
+                if let Err(e) = op_unit_result(&response, flags, "sideloader-start") {
+                    let _ = lookups.send(Err(e));
+                }
+                pending.remove(&id);
+                return;
+            }
+            if !response.status.iter().any(|s| s == "sideloader-lookup") {
+                return;
+            }
+            let (Some(kind), Some(name)) = (
+                response
+                    .symbol_type
+                    .as_deref()
+                    .and_then(SideloadKind::parse),
+                response.name.as_deref(),
+            ) else {
+                return;
+            };
+            // The server blocks until it hears back, so always answer, with
+            // empty content for "not found".
+            let bytes = provider(kind, name);
+            let content = bytes.as_deref().map(base64::encode).unwrap_or_default();
+            *answered += 1;
+            let request = ops::sideloader_provide_request(
+                format!("{id}-provide-{answered}"),
+                session,
+                kind.as_str(),
+                name,
+                content,
+            );
+            let _ = lookups.send(Ok(SideloadLookup {
+                kind,
+                name: name.to_string(),
+                found: bytes.is_some(),
+            }));
+            if let Err(e) = writer.send(&request).await {
+                let _ = lookups.send(Err(e));
+            }
+        }
+        StreamOp::Tap {
+            session,
+            taps,
+            line,
+            err,
+            failed,
+            interrupted,
+        } => {
+            if let Some(out) = &response.out {
+                line.push_str(out);
+                while let Some(end) = line.find('\n') {
+                    let value: String = line.drain(..=end).collect();
+                    if taps.send(Ok(value.trim_end().to_string())).is_err() {
+                        // Nobody is listening any more.
+                        let session = session.clone();
+                        pending.remove(&id);
+                        stop_tap(writer, &session, &id).await;
+                        return;
+                    }
+                }
+            }
+            if let Some(e) = &response.err {
+                err.push_str(e);
+            }
+            *failed |= flags.error;
+            *interrupted |= flags.interrupted;
+            // Wait for `done` rather than stopping at `eval-error`: the
+            // exception is printed to `err` after it.
+            if (flags.done || flags.unknown_op)
+                && let Some(Pending::Stream(StreamOp::Tap {
+                    taps,
+                    err,
+                    failed,
+                    interrupted,
+                    ..
+                })) = pending.remove(&id)
+            {
+                if flags.unknown_op {
+                    let _ = taps.send(Err(unknown_op_err("eval")));
+                } else if failed && !interrupted {
+                    let _ = taps.send(Err(NReplError::OperationFailed(if err.is_empty() {
+                        "tap subscription failed".to_string()
+                    } else {
+                        err.trim_end().to_string()
+                    })));
+                }
+            }
+        }
+    }
+}
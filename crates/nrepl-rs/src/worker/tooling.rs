@@ -0,0 +1,1009 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Tooling ops (cider-nrepl's and nREPL's own): each gathers its responses
+//! until `done`, then parses them for the caller. See [`Pending::Collect`].
+
+use super::pending::{CollectOp, Pending, collect_into, op_finished, unknown_op_err};
+use super::{InspectorAction, RequestId, TestSelection};
+use crate::error::NReplError;
+use crate::info::{AproposMatch, ClojureDocs, Eldoc, NsVar, SymbolInfo, XrefVar};
+use crate::inspector::InspectorPage;
+use crate::message::{Response, StatusFlags};
+use crate::middleware::MiddlewareStack;
+use crate::ops;
+use crate::refresh::{RefreshOptions, RefreshReport};
+use crate::session::Session;
+use crate::spec::SpecForm;
+use crate::stacktrace::StackTrace;
+use crate::test_report::TestResults;
+use crate::toggle_trace::{TraceState, VarTrace, ns_trace_state};
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
+/// The variants of [`WorkerCommand`](super::WorkerCommand) answered by
+/// collecting replies; [`collect_op`] turns each into its request.
+pub(super) enum ToolingCommand {
+    AddMiddleware {
+        op_id: RequestId,
+        session: Session,
+        middleware: Vec<String>,
+        extra_namespaces: Vec<String>,
+        reply: Sender<Result<(), NReplError>>,
+    },
+    LsMiddleware {
+        op_id: RequestId,
+        verbose: bool,
+        reply: Sender<Result<MiddlewareStack, NReplError>>,
+    },
+    SwapMiddleware {
+        op_id: RequestId,
+        session: Session,
+        middleware: Vec<String>,
+        extra_namespaces: Vec<String>,
+        reply: Sender<Result<(), NReplError>>,
+    },
+    AnalyzeStacktrace {
+        op_id: RequestId,
+        session: Session,
+        reply: Sender<Result<Option<StackTrace>, NReplError>>,
+    },
+    Info {
+        op_id: RequestId,
+        session: Session,
+        sym: String,
+        ns: Option<String>,
+        reply: Sender<Result<Option<SymbolInfo>, NReplError>>,
+    },
+    ClojureDocs {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        sym: String,
+        reply: Sender<Result<Option<ClojureDocs>, NReplError>>,
+    },
+    Eldoc {
+        op_id: RequestId,
+        session: Session,
+        sym: String,
+        ns: Option<String>,
+        reply: Sender<Result<Option<Eldoc>, NReplError>>,
+    },
+    Apropos {
+        op_id: RequestId,
+        session: Session,
+        query: String,
+        search_ns: Option<String>,
+        docs: bool,
+        privates: bool,
+        reply: Sender<Result<Vec<AproposMatch>, NReplError>>,
+    },
+    FnRefs {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        sym: String,
+        reply: Sender<Result<Vec<XrefVar>, NReplError>>,
+    },
+    FnDeps {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        sym: String,
+        reply: Sender<Result<Vec<XrefVar>, NReplError>>,
+    },
+    NsList {
+        op_id: RequestId,
+        session: Session,
+        reply: Sender<Result<Vec<String>, NReplError>>,
+    },
+    NsVars {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        reply: Sender<Result<Vec<NsVar>, NReplError>>,
+    },
+    NsPath {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        reply: Sender<Result<Option<String>, NReplError>>,
+    },
+    Classpath {
+        op_id: RequestId,
+        session: Session,
+        reply: Sender<Result<Vec<String>, NReplError>>,
+    },
+    SpecList {
+        op_id: RequestId,
+        session: Session,
+        filter_regex: Option<String>,
+        reply: Sender<Result<Vec<String>, NReplError>>,
+    },
+    SpecForm {
+        op_id: RequestId,
+        session: Session,
+        spec_name: String,
+        reply: Sender<Result<Option<SpecForm>, NReplError>>,
+    },
+    SpecExample {
+        op_id: RequestId,
+        session: Session,
+        spec_name: String,
+        reply: Sender<Result<String, NReplError>>,
+    },
+    Undef {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        sym: String,
+        reply: Sender<Result<(), NReplError>>,
+    },
+    UndefAll {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        reply: Sender<Result<(), NReplError>>,
+    },
+    ToggleTraceVar {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        sym: String,
+        reply: Sender<Result<VarTrace, NReplError>>,
+    },
+    ToggleTraceNs {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        reply: Sender<Result<TraceState, NReplError>>,
+    },
+    FormatCode {
+        op_id: RequestId,
+        session: Session,
+        code: String,
+        reply: Sender<Result<String, NReplError>>,
+    },
+    FormatEdn {
+        op_id: RequestId,
+        session: Session,
+        edn: String,
+        right_margin: Option<u32>,
+        reply: Sender<Result<String, NReplError>>,
+    },
+    RunTests {
+        op_id: RequestId,
+        session: Session,
+        selection: TestSelection,
+        reply: Sender<Result<TestResults, NReplError>>,
+    },
+    TestStacktrace {
+        op_id: RequestId,
+        session: Session,
+        ns: String,
+        var: String,
+        index: u32,
+        reply: Sender<Result<Option<StackTrace>, NReplError>>,
+    },
+    Refresh {
+        op_id: RequestId,
+        session: Session,
+        all: bool,
+        options: RefreshOptions,
+        reply: Sender<Result<RefreshReport, NReplError>>,
+    },
+    RefreshClear {
+        op_id: RequestId,
+        session: Session,
+        reply: Sender<Result<(), NReplError>>,
+    },
+    Inspect {
+        op_id: RequestId,
+        session: Session,
+        code: String,
+        ns: Option<String>,
+        reply: Sender<Result<InspectorPage, NReplError>>,
+    },
+    Inspector {
+        op_id: RequestId,
+        session: Session,
+        action: InspectorAction,
+        reply: Sender<Result<InspectorPage, NReplError>>,
+    },
+}
+
+/// The [`Pending::Collect`] op for `cmd`: the request, and how to read its
+/// replies.
+// One arm per op, each only a request and its parser, so the match is long
+// but flat.
+#[allow(clippy::too_many_lines)]
+pub(super) fn collect_op(cmd: ToolingCommand) -> CollectOp {
+    match cmd {
+        ToolingCommand::AnalyzeStacktrace {
+            op_id,
+            session,
+            reply,
+        } => {
+            let request = ops::analyze_last_stacktrace_request(op_id.wire(), session.id());
+            let finish = collect_into(reply, |responses| Ok(StackTrace::from_cider(&responses)));
+            CollectOp {
+                op_id,
+                request,
+                op: "analyze-last-stacktrace",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::Info {
+            op_id,
+            session,
+            sym,
+            ns,
+            reply,
+        } => {
+            let request = ops::info_request(op_id.wire(), session.id(), sym, ns);
+            let finish = collect_into(reply, |responses| Ok(SymbolInfo::from_cider(&responses)));
+            CollectOp {
+                op_id,
+                request,
+                op: "info",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::ClojureDocs {
+            op_id,
+            session,
+            ns,
+            sym,
+            reply,
+        } => {
+            let request = ops::clojuredocs_lookup_request(op_id.wire(), session.id(), ns, sym);
+            let finish = collect_into(reply, |responses| {
+                Ok(responses.into_iter().find_map(|r| r.clojuredocs))
+            });
+            CollectOp {
+                op_id,
+                request,
+                op: "clojuredocs-lookup",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::Eldoc {
+            op_id,
+            session,
+            sym,
+            ns,
+            reply,
+        } => {
+            let request = ops::eldoc_request(op_id.wire(), session.id(), sym, ns);
+            let finish = collect_into(reply, |responses| Ok(Eldoc::from_cider(&responses)));
+            CollectOp {
+                op_id,
+                request,
+                op: "eldoc",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::Apropos {
+            op_id,
+            session,
+            query,
+            search_ns,
+            docs,
+            privates,
+            reply,
+        } => {
+            let request =
+                ops::apropos_request(op_id.wire(), session.id(), query, search_ns, docs, privates);
+            let finish = collect_into(reply, apropos_matches);
+            CollectOp {
+                op_id,
+                request,
+                op: "apropos",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::FnRefs {
+            op_id,
+            session,
+            ns,
+            sym,
+            reply,
+        } => {
+            let request = ops::fn_refs_request(op_id.wire(), session.id(), ns, sym);
+            let finish = collect_into(reply, |responses| xref_vars(responses, |r| r.fn_refs));
+            CollectOp {
+                op_id,
+                request,
+                op: "fn-refs",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::FnDeps {
+            op_id,
+            session,
+            ns,
+            sym,
+            reply,
+        } => {
+            let request = ops::fn_deps_request(op_id.wire(), session.id(), ns, sym);
+            let finish = collect_into(reply, |responses| xref_vars(responses, |r| r.fn_deps));
+            CollectOp {
+                op_id,
+                request,
+                op: "fn-deps",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::NsList {
+            op_id,
+            session,
+            reply,
+        } => {
+            let request = ops::ns_list_request(op_id.wire(), session.id());
+            let finish = collect_into(reply, |responses| {
+                let mut namespaces: Vec<String> = responses
+                    .into_iter()
+                    .filter_map(|r| r.ns_list)
+                    .flatten()
+                    .collect();
+                namespaces.sort();
+                Ok(namespaces)
+            });
+            CollectOp {
+                op_id,
+                request,
+                op: "ns-list",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::NsVars {
+            op_id,
+            session,
+            ns,
+            reply,
+        } => {
+            let request = ops::ns_vars_request(op_id.wire(), session.id(), &ns);
+            let finish = collect_into(reply, |responses| {
+                Ok(responses
+                    .into_iter()
+                    .filter_map(|r| r.ns_vars_with_meta)
+                    .flatten()
+                    .collect())
+            });
+            CollectOp {
+                op_id,
+                request,
+                op: "ns-vars-with-meta",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::NsPath {
+            op_id,
+            session,
+            ns,
+            reply,
+        } => {
+            let request = ops::ns_path_request(op_id.wire(), session.id(), &ns);
+            // An unknown namespace comes back as an empty path.
+            let finish = collect_into(reply, |responses| {
+                Ok(responses
+                    .into_iter()
+                    .filter_map(|r| r.path)
+                    .find(|p| !p.is_empty()))
+            });
+            CollectOp {
+                op_id,
+                request,
+                op: "ns-path",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::Classpath {
+            op_id,
+            session,
+            reply,
+        } => {
+            let request = ops::classpath_request(op_id.wire(), session.id());
+            let finish = collect_into(reply, |responses| {
+                Ok(responses
+                    .into_iter()
+                    .filter_map(|r| r.classpath)
+                    .flatten()
+                    .collect())
+            });
+            CollectOp {
+                op_id,
+                request,
+                op: "classpath",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::SpecList {
+            op_id,
+            session,
+            filter_regex,
+            reply,
+        } => {
+            let request = ops::spec_list_request(op_id.wire(), session.id(), filter_regex);
+            let finish = collect_into(reply, |responses| {
+                Ok(responses
+                    .into_iter()
+                    .filter_map(|r| r.spec_list)
+                    .flatten()
+                    .collect())
+            });
+            CollectOp {
+                op_id,
+                request,
+                op: "spec-list",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::SpecForm {
+            op_id,
+            session,
+            spec_name,
+            reply,
+        } => {
+            let request = ops::spec_form_request(op_id.wire(), session.id(), spec_name);
+            let finish = collect_into(reply, |responses| {
+                Ok(responses.into_iter().find_map(|r| r.spec_form))
+            });
+            CollectOp {
+                op_id,
+                request,
+                op: "spec-form",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::SpecExample {
+            op_id,
+            session,
+            spec_name,
+            reply,
+        } => {
+            let request = ops::spec_example_request(op_id.wire(), session.id(), spec_name);
+            let finish = collect_into(reply, |responses| {
+                formatted(responses, "spec-example", |r| r.spec_example)
+            });
+            CollectOp {
+                op_id,
+                request,
+                op: "spec-example",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::LsMiddleware {
+            op_id,
+            verbose,
+            reply,
+        } => {
+            let request = ops::ls_middleware_request(op_id.wire(), verbose);
+            let finish = collect_into(reply, |responses| middleware_stack(&responses));
+            CollectOp {
+                op_id,
+                request,
+                op: "ls-middleware",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::AddMiddleware {
+            op_id,
+            session,
+            middleware,
+            extra_namespaces,
+            reply,
+        } => {
+            let request = ops::add_middleware_request(
+                op_id.wire(),
+                session.id(),
+                &middleware,
+                &extra_namespaces,
+            );
+            let finish = collect_into(reply, |responses| {
+                middleware_added(&responses, "add-middleware")
+            });
+            CollectOp {
+                op_id,
+                request,
+                op: "add-middleware",
+                finish,
+                watched: false,
+            }
+        }
+        ToolingCommand::SwapMiddleware {
+            op_id,
+            session,
+            middleware,
+            extra_namespaces,
+            reply,
+        } => {
+            let request = ops::swap_middleware_request(
+                op_id.wire(),
+                session.id(),
+                &middleware,
+                &extra_namespaces,
+            );
+            let finish = collect_into(reply, |responses| {
+                middleware_added(&responses, "swap-middleware")
+            });
+            CollectOp {
+                op_id,
+                request,
+                op: "swap-middleware",
+                finish,
+                watched: false,
+            }
+        }
+        ToolingCommand::Undef {
+            op_id,
+            session,
+            ns,
+            sym,
+            reply,
+        } => {
+            let request = ops::undef_request(op_id.wire(), session.id(), &ns, &sym);
+            let finish = collect_into(reply, |responses| undefined(&responses, "undef"));
+            CollectOp {
+                op_id,
+                request,
+                op: "undef",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::UndefAll {
+            op_id,
+            session,
+            ns,
+            reply,
+        } => {
+            let request = ops::undef_all_request(op_id.wire(), session.id(), &ns);
+            let finish = collect_into(reply, |responses| undefined(&responses, "undef-all"));
+            CollectOp {
+                op_id,
+                request,
+                op: "undef-all",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::ToggleTraceVar {
+            op_id,
+            session,
+            ns,
+            sym,
+            reply,
+        } => {
+            let request = ops::toggle_trace_var_request(op_id.wire(), session.id(), &ns, &sym);
+            let finish = collect_into(reply, move |responses| {
+                VarTrace::from_cider(&responses, &sym)
+            });
+            CollectOp {
+                op_id,
+                request,
+                op: "toggle-trace-var",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::ToggleTraceNs {
+            op_id,
+            session,
+            ns,
+            reply,
+        } => {
+            let request = ops::toggle_trace_ns_request(op_id.wire(), session.id(), &ns);
+            let finish = collect_into(reply, move |responses| ns_trace_state(&responses, &ns));
+            CollectOp {
+                op_id,
+                request,
+                op: "toggle-trace-ns",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::FormatCode {
+            op_id,
+            session,
+            code,
+            reply,
+        } => {
+            let request = ops::format_code_request(op_id.wire(), session.id(), code);
+            let finish = collect_into(reply, |responses| {
+                formatted(responses, "format-code", |r| r.formatted_code)
+            });
+            CollectOp {
+                op_id,
+                request,
+                op: "format-code",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::FormatEdn {
+            op_id,
+            session,
+            edn,
+            right_margin,
+            reply,
+        } => {
+            let request = ops::format_edn_request(op_id.wire(), session.id(), edn, right_margin);
+            let finish = collect_into(reply, |responses| {
+                formatted(responses, "format-edn", |r| r.formatted_edn)
+            });
+            CollectOp {
+                op_id,
+                request,
+                op: "format-edn",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::RunTests {
+            op_id,
+            session,
+            selection,
+            reply,
+        } => {
+            let (request, op) = match selection {
+                TestSelection::Namespace { ns, vars } => (
+                    ops::test_request(op_id.wire(), session.id(), &ns, vars),
+                    "test",
+                ),
+                TestSelection::All { load_all } => (
+                    ops::test_all_request(op_id.wire(), session.id(), load_all),
+                    "test-all",
+                ),
+                TestSelection::Failed => {
+                    (ops::retest_request(op_id.wire(), session.id()), "retest")
+                }
+            };
+            let finish = collect_into(reply, |responses| TestResults::from_cider(&responses));
+            CollectOp {
+                op_id,
+                request,
+                op,
+                finish,
+                watched: false,
+            }
+        }
+        ToolingCommand::TestStacktrace {
+            op_id,
+            session,
+            ns,
+            var,
+            index,
+            reply,
+        } => {
+            let request =
+                ops::test_stacktrace_request(op_id.wire(), session.id(), &ns, &var, index);
+            let finish = collect_into(reply, |responses| Ok(StackTrace::from_cider(&responses)));
+            CollectOp {
+                op_id,
+                request,
+                op: "test-stacktrace",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::Refresh {
+            op_id,
+            session,
+            all,
+            options,
+            reply,
+        } => {
+            let request = ops::refresh_request(op_id.wire(), session.id(), all, options);
+            let op = if all { "refresh-all" } else { "refresh" };
+            let finish = collect_into(reply, |responses| Ok(RefreshReport::from_cider(&responses)));
+            CollectOp {
+                op_id,
+                request,
+                op,
+                finish,
+                watched: false,
+            }
+        }
+        ToolingCommand::RefreshClear {
+            op_id,
+            session,
+            reply,
+        } => {
+            let request = ops::refresh_clear_request(op_id.wire(), session.id());
+            let finish = collect_into(reply, |_| Ok(()));
+            CollectOp {
+                op_id,
+                request,
+                op: "refresh-clear",
+                finish,
+                watched: true,
+            }
+        }
+        ToolingCommand::Inspect {
+            op_id,
+            session,
+            code,
+            ns,
+            reply,
+        } => {
+            let request = ops::inspect_eval_request(op_id.wire(), session.id(), code, ns);
+            let finish = collect_into(reply, |responses| InspectorPage::from_cider(&responses));
+            CollectOp {
+                op_id,
+                request,
+                op: "eval",
+                finish,
+                watched: false,
+            }
+        }
+        ToolingCommand::Inspector {
+            op_id,
+            session,
+            action,
+            reply,
+        } => {
+            let (wire, session) = (op_id.wire(), session.id());
+            let (request, op) = match action {
+                InspectorAction::Refresh => (
+                    ops::inspect_request(wire, session, "inspect-refresh"),
+                    "inspect-refresh",
+                ),
+                InspectorAction::Pop => (
+                    ops::inspect_request(wire, session, "inspect-pop"),
+                    "inspect-pop",
+                ),
+                InspectorAction::Push(idx) => (
+                    ops::inspect_push_request(wire, session, idx),
+                    "inspect-push",
+                ),
+                InspectorAction::NextPage => (
+                    ops::inspect_request(wire, session, "inspect-next-page"),
+                    "inspect-next-page",
+                ),
+                InspectorAction::PrevPage => (
+                    ops::inspect_request(wire, session, "inspect-prev-page"),
+                    "inspect-prev-page",
+                ),
+                InspectorAction::SetPageSize(size) => (
+                    ops::inspect_set_page_size_request(wire, session, size),
+                    "inspect-set-page-size",
+                ),
+            };
+            let finish = collect_into(reply, |responses| InspectorPage::from_cider(&responses));
+            CollectOp {
+                op_id,
+                request,
+                op,
+                finish,
+                watched: true,
+            }
+        }
+    }
+}
+
+/// Route a response to a [`Pending::Collect`] op.
+pub(super) fn route(
+    response: Response,
+    flags: StatusFlags,
+    pending: &mut HashMap<String, Pending>,
+) {
+    let id = response.id.clone();
+    let Some(Pending::Collect { responses, .. }) = pending.get_mut(&id) else {
+        return;
+    };
+    responses.push(response);
+    if op_finished(flags)
+        && let Some(Pending::Collect {
+            op,
+            responses,
+            finish,
+            ..
+        }) = pending.remove(&id)
+    {
+        finish(if flags.unknown_op {
+            Err(unknown_op_err(op))
+        } else {
+            Ok(responses)
+        });
+    }
+}
+
+/// Gather an `apropos` exchange's matches. A reply with no match list but
+/// error text means the search itself failed (a bad regex, say).
+fn apropos_matches(responses: Vec<Response>) -> Result<Vec<AproposMatch>, NReplError> {
+    let mut matches = None::<Vec<AproposMatch>>;
+    let mut err = String::new();
+    for response in responses {
+        if let Some(found) = response.apropos_matches {
+            matches.get_or_insert_default().extend(found);
+        }
+        if let Some(e) = response.err {
+            err.push_str(&e);
+        }
+    }
+    match matches {
+        Some(matches) => Ok(matches),
+        None if !err.is_empty() => Err(NReplError::OperationFailed(err.trim_end().to_string())),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Gather an `fn-refs`/`fn-deps` exchange's vars. A reply with no list but
+/// error text means the symbol did not resolve.
+fn xref_vars(
+    responses: Vec<Response>,
+    take: impl Fn(Response) -> Option<Vec<XrefVar>>,
+) -> Result<Vec<XrefVar>, NReplError> {
+    let mut vars = None::<Vec<XrefVar>>;
+    let mut err = String::new();
+    for mut response in responses {
+        if let Some(e) = response.err.take() {
+            err.push_str(&e);
+        }
+        if let Some(found) = take(response) {
+            vars.get_or_insert_default().extend(found);
+        }
+    }
+    match vars {
+        Some(vars) => Ok(vars),
+        None if !err.is_empty() => Err(NReplError::OperationFailed(err.trim_end().to_string())),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// The outcome of an `undef`/`undef-all` exchange: the server flags a failure
+/// (a namespace that is not loaded, say) with an `<op>-error` status and
+/// explains it in `err`.
+fn undefined(responses: &[Response], op: &str) -> Result<(), NReplError> {
+    let error_status = format!("{op}-error");
+    if !responses.iter().any(|r| r.status.contains(&error_status)) {
+        return Ok(());
+    }
+    let err: String = responses.iter().filter_map(|r| r.err.as_deref()).collect();
+    Err(NReplError::OperationFailed(if err.is_empty() {
+        format!("{op} failed")
+    } else {
+        err.trim_end().to_string()
+    }))
+}
+
+/// Check an `add-middleware` or `swap-middleware` exchange: the server lists
+/// the names it could not resolve, and uses the rest regardless.
+fn middleware_added(responses: &[Response], op: &str) -> Result<(), NReplError> {
+    let unresolved: Vec<&str> = responses
+        .iter()
+        .filter_map(|r| r.unresolved_middleware.as_deref())
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    if !unresolved.is_empty() {
+        return Err(NReplError::OperationFailed(format!(
+            "unresolved middleware: {}",
+            unresolved.join(", ")
+        )));
+    }
+    match responses.iter().find_map(|r| r.err.as_deref()) {
+        Some(err) => Err(NReplError::OperationFailed(format!(
+            "{op} failed: {}",
+            err.trim_end()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// The stack an `ls-middleware` exchange lists.
+fn middleware_stack(responses: &[Response]) -> Result<MiddlewareStack, NReplError> {
+    match responses.iter().find_map(|r| r.err.as_deref()) {
+        Some(err) => Err(NReplError::OperationFailed(format!(
+            "ls-middleware failed: {}",
+            err.trim_end()
+        ))),
+        None => Ok(MiddlewareStack::from_responses(responses)),
+    }
+}
+
+/// The text from a `format-code`/`format-edn`/`spec-example` exchange. The server flags a
+/// failure with an `<op>-error` status and explains it in `err`.
+fn formatted(
+    responses: Vec<Response>,
+    op: &str,
+    text: impl Fn(Response) -> Option<String>,
+) -> Result<String, NReplError> {
+    let error_status = format!("{op}-error");
+    let failed = responses.iter().any(|r| r.status.contains(&error_status));
+    let err: String = responses.iter().filter_map(|r| r.err.as_deref()).collect();
+    let found = responses.into_iter().find_map(text);
+    match found {
+        Some(text) if !failed => Ok(text),
+        _ if err.is_empty() => Err(NReplError::OperationFailed(format!(
+            "{op} returned no text"
+        ))),
+        _ => Err(NReplError::OperationFailed(err.trim_end().to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formatted_reads_text_or_error() {
+        let decode = |frame: &str| crate::codec::decode_response(frame.as_bytes()).unwrap().0;
+
+        let ok = vec![decode(
+            "d14:formatted-code9:(ns foo)\n2:id1:16:statusl4:doneee",
+        )];
+        assert_eq!(
+            formatted(ok, "format-code", |r| r.formatted_code).unwrap(),
+            "(ns foo)\n"
+        );
+
+        let failed = vec![decode(
+            "d3:err23:Unmatched delimiter: )\n2:id1:16:statusl17:format-code-error4:doneee",
+        )];
+        match formatted(failed, "format-code", |r| r.formatted_code) {
+            Err(NReplError::OperationFailed(msg)) => assert_eq!(msg, "Unmatched delimiter: )"),
+            other => panic!("expected OperationFailed, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_undefined_reports_error_status() {
+        let decode = |frame: &str| crate::codec::decode_response(frame.as_bytes()).unwrap().0;
+
+        let ok = [decode("d2:id5:req-16:statusl4:doneee")];
+        assert!(undefined(&ok, "undef").is_ok());
+
+        let failed = [
+            decode("d3:err22:No namespace: missing\n2:id5:req-1e"),
+            decode("d2:id5:req-16:statusl4:done11:undef-erroree"),
+        ];
+        match undefined(&failed, "undef") {
+            Err(NReplError::OperationFailed(msg)) => assert_eq!(msg, "No namespace: missing"),
+            other => panic!("expected OperationFailed, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_middleware_added_lists_unresolved() {
+        let decode = |frame: &str| crate::codec::decode_response(frame.as_bytes()).unwrap().0;
+
+        let ok = [decode("d2:id5:req-16:statusl4:doneee")];
+        assert!(middleware_added(&ok, "add-middleware").is_ok());
+
+        let partial = [decode(
+            "d2:id5:req-16:statusl4:donee21:unresolved-middlewarel12:no.such/wrapee",
+        )];
+        match middleware_added(&partial, "add-middleware") {
+            Err(NReplError::OperationFailed(msg)) => {
+                assert_eq!(msg, "unresolved middleware: no.such/wrap");
+            }
+            other => panic!("expected OperationFailed, got {other:?}"),
+        }
+    }
+}
@@ -20,7 +20,7 @@
 #![allow(dead_code)] // each test file uses a different subset of the helpers
 
 use nrepl_rs::worker::{EvalOutcome, Worker, WorkerCommand};
-use nrepl_rs::{CompletionCandidate, EvalResult, NReplError, Response, Session};
+use nrepl_rs::{CompletionCandidate, EvalResult, NReplError, NsAliases, Response, Session};
 use std::sync::mpsc::channel;
 use std::time::{Duration, Instant};
 
@@ -97,12 +97,30 @@ pub fn describe(worker: &Worker, verbose: bool) -> Result<Response, NReplError>
     })
 }
 
+pub fn resync(worker: &Worker) -> Result<nrepl_rs::worker::ResyncReport, NReplError> {
+    send_and_wait(worker, "resync", |op_id, reply| WorkerCommand::Resync {
+        op_id,
+        reply,
+    })
+}
+
 pub fn ls_sessions(worker: &Worker) -> Result<Vec<String>, NReplError> {
     send_and_wait(worker, "ls-sessions", |op_id, reply| {
         WorkerCommand::LsSessions { op_id, reply }
     })
 }
 
+pub fn ns_aliases(worker: &Worker, session: &Session, ns: &str) -> Result<NsAliases, NReplError> {
+    send_and_wait(worker, "ns-aliases", |op_id, reply| {
+        WorkerCommand::NsAliases {
+            op_id,
+            session: session.clone(),
+            ns: ns.to_string(),
+            reply,
+        }
+    })
+}
+
 pub fn completions(
     worker: &Worker,
     session: &Session,
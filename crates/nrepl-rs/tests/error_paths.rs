@@ -17,10 +17,26 @@
 
 mod common;
 
-use nrepl_rs::NReplError;
 use nrepl_rs::worker::Worker;
+use nrepl_rs::{DebugEventKind, NReplError};
 use std::time::Duration;
 
+/// The ids of the request frames in `chunk`, in order, for echoing back.
+fn request_ids(chunk: &str) -> Vec<String> {
+    chunk
+        .match_indices("2:id")
+        .filter_map(|(at, _)| {
+            let (len, rest) = chunk[at + 4..].split_once(':')?;
+            Some(rest.get(..len.parse::<usize>().ok()?)?.to_string())
+        })
+        .collect()
+}
+
+/// A response frame for `id` with the bencoded entries `body`.
+fn frame(id: &str, body: &str) -> Vec<u8> {
+    format!("d2:id{}:{id}{body}e", id.len()).into_bytes()
+}
+
 #[test]
 fn test_connection_refused() {
     // Try to connect to a port that's not listening
@@ -63,6 +79,468 @@ fn test_invalid_host() {
     }
 }
 
+#[test]
+fn test_control_op_without_done_is_protocol_violation() {
+    use nrepl_rs::worker::{WorkerCommand, WorkerConfig};
+    use std::io::{Read, Write};
+
+    // A server whose describe answer never carries `done`.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 1024];
+        let _ = stream.read(&mut buf).expect("read request");
+        stream
+            .write_all(b"d2:id5:req-12:ns4:usere")
+            .expect("write response");
+        // Hold the socket open until the client is done with it.
+        let _ = stream.read(&mut buf);
+    });
+
+    let worker =
+        Worker::with_config(WorkerConfig::default().done_timeout(Duration::from_millis(200)));
+    worker.connect_blocking(addr).expect("connect");
+    let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+    worker
+        .command_sender()
+        .send(WorkerCommand::Describe {
+            op_id: worker.next_id(),
+            verbose: false,
+            reply: reply_tx,
+        })
+        .expect("worker thread gone");
+
+    match reply_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("no reply")
+    {
+        Err(NReplError::ProtocolViolation { op, id, .. }) => {
+            assert_eq!(op, "describe");
+            assert_eq!(id, "req-1");
+        }
+        other => panic!("Expected ProtocolViolation, got: {other:?}"),
+    }
+    let kinds: Vec<DebugEventKind> = worker.debug_events().iter().map(|e| e.kind).collect();
+    assert_eq!(
+        kinds,
+        [DebugEventKind::Connected, DebugEventKind::ProtocolViolation]
+    );
+    drop(worker);
+    server.join().expect("server thread");
+}
+
+#[test]
+fn test_test_run_outlives_done_timeout() {
+    use nrepl_rs::Session;
+    use nrepl_rs::worker::{TestSelection, WorkerCommand, WorkerConfig};
+    use std::io::{Read, Write};
+
+    // A test runner slower than the done watchdog.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 1024];
+        let _ = stream.read(&mut buf).expect("read request");
+        std::thread::sleep(Duration::from_millis(500));
+        stream
+            .write_all(b"d2:id5:req-17:resultsde7:summaryd4:passi2e4:testi1eee")
+            .expect("write results");
+        stream
+            .write_all(b"d2:id5:req-16:statusl4:doneee")
+            .expect("write done");
+        let _ = stream.read(&mut buf);
+    });
+
+    let worker =
+        Worker::with_config(WorkerConfig::default().done_timeout(Duration::from_millis(100)));
+    worker.connect_blocking(addr).expect("connect");
+    let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+    worker
+        .command_sender()
+        .send(WorkerCommand::RunTests {
+            op_id: worker.next_id(),
+            session: Session::from_server_id("s1"),
+            selection: TestSelection::All { load_all: false },
+            reply: reply_tx,
+        })
+        .expect("worker thread gone");
+
+    let results = reply_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("no reply")
+        .expect("test run failed");
+    assert_eq!(results.summary.pass, 2);
+    assert!(results.passed());
+    drop(worker);
+    server.join().expect("server thread");
+}
+
+#[test]
+fn test_add_middleware_outlives_done_timeout() {
+    use nrepl_rs::Session;
+    use nrepl_rs::worker::{WorkerCommand, WorkerConfig};
+    use std::io::{Read, Write};
+
+    // A server that takes longer to load the middleware than the watchdog
+    // allows, and says nothing meanwhile.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 1024];
+        let _ = stream.read(&mut buf).expect("read request");
+        std::thread::sleep(Duration::from_millis(500));
+        stream
+            .write_all(b"d2:id5:req-16:statusl4:doneee")
+            .expect("write done");
+        let _ = stream.read(&mut buf);
+    });
+
+    let worker =
+        Worker::with_config(WorkerConfig::default().done_timeout(Duration::from_millis(100)));
+    worker.connect_blocking(addr).expect("connect");
+    let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+    worker
+        .command_sender()
+        .send(WorkerCommand::AddMiddleware {
+            op_id: worker.next_id(),
+            session: Session::from_server_id("s1"),
+            middleware: vec!["cider.nrepl/wrap-info".to_string()],
+            extra_namespaces: Vec::new(),
+            reply: reply_tx,
+        })
+        .expect("worker thread gone");
+
+    reply_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("no reply")
+        .expect("add-middleware failed");
+    drop(worker);
+    server.join().expect("server thread");
+}
+
+#[test]
+fn test_cljs_upgrade_outlives_done_timeout() {
+    use nrepl_rs::worker::{WorkerCommand, WorkerConfig};
+    use nrepl_rs::{CljsRepl, Session};
+    use std::io::{Read, Write};
+
+    // A shadow-cljs build that takes longer to start than the watchdog
+    // allows.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 1024];
+        let _ = stream.read(&mut buf).expect("read upgrade eval");
+        std::thread::sleep(Duration::from_millis(500));
+        stream
+            .write_all(b"d2:id5:req-12:ns9:cljs.user5:value16:[:selected :app]e")
+            .expect("write value");
+        stream
+            .write_all(b"d2:id5:req-16:statusl4:doneee")
+            .expect("write done");
+        let _ = stream.read(&mut buf);
+    });
+
+    let worker =
+        Worker::with_config(WorkerConfig::default().done_timeout(Duration::from_millis(100)));
+    worker.connect_blocking(addr).expect("connect");
+    let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+    worker
+        .command_sender()
+        .send(WorkerCommand::UpgradeCljs {
+            op_id: worker.next_id(),
+            session: Session::from_server_id("s1"),
+            repl: CljsRepl::Shadow {
+                build: "app".to_string(),
+            },
+            reply: reply_tx,
+        })
+        .expect("worker thread gone");
+
+    reply_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("no reply")
+        .expect("upgrade failed");
+    drop(worker);
+    server.join().expect("server thread");
+}
+
+#[test]
+fn test_streaming_op_outlives_done_timeout() {
+    use nrepl_rs::worker::{WorkerCommand, WorkerConfig};
+    use std::io::{Read, Write};
+
+    // ls-sessions answered in pieces, each inside the watchdog's limit, with
+    // `done` arriving long after the limit has passed since the request.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 1024];
+        let _ = stream.read(&mut buf).expect("read request");
+        for i in 0..8 {
+            std::thread::sleep(Duration::from_millis(60));
+            stream
+                .write_all(format!("d2:id5:req-18:sessionsl2:s{i}ee").as_bytes())
+                .expect("write sessions");
+        }
+        stream
+            .write_all(b"d2:id5:req-16:statusl4:doneee")
+            .expect("write done");
+        let _ = stream.read(&mut buf);
+    });
+
+    let worker =
+        Worker::with_config(WorkerConfig::default().done_timeout(Duration::from_millis(150)));
+    worker.connect_blocking(addr).expect("connect");
+    let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+    worker
+        .command_sender()
+        .send(WorkerCommand::LsSessions {
+            op_id: worker.next_id(),
+            reply: reply_tx,
+        })
+        .expect("worker thread gone");
+
+    let sessions = reply_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("no reply")
+        .expect("ls-sessions failed");
+    assert_eq!(sessions.len(), 8);
+    assert_eq!(sessions[7], "s7");
+    drop(worker);
+    server.join().expect("server thread");
+}
+
+#[test]
+fn test_debugger_subscription_outlives_done_timeout() {
+    use nrepl_rs::worker::{WorkerCommand, WorkerConfig};
+    use nrepl_rs::{DebugCommand, Session};
+    use std::io::{Read, Write};
+
+    // init-debugger never finishes; its first stop comes after the watchdog
+    // would have fired.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 1024];
+        let _ = stream.read(&mut buf).expect("read init-debugger");
+        std::thread::sleep(Duration::from_millis(500));
+        stream
+            .write_all(b"d11:debug-value1:32:id5:req-13:key3:k-16:statusl16:need-debug-inputee")
+            .expect("write break");
+        let n = stream.read(&mut buf).expect("read debug-input");
+        let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+        stream
+            .write_all(b"d2:id5:req-26:statusl4:doneee")
+            .expect("write done");
+        let _ = stream.read(&mut buf);
+        request
+    });
+
+    let worker =
+        Worker::with_config(WorkerConfig::default().done_timeout(Duration::from_millis(100)));
+    worker.connect_blocking(addr).expect("connect");
+    let session = Session::from_server_id("s1");
+    let (breaks_tx, breaks_rx) = std::sync::mpsc::channel();
+    worker
+        .command_sender()
+        .send(WorkerCommand::InitDebugger {
+            op_id: worker.next_id(),
+            session: session.clone(),
+            breaks: breaks_tx,
+        })
+        .expect("worker thread gone");
+
+    let stop = breaks_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("no break")
+        .expect("debugger failed");
+    assert_eq!(stop.key, "k-1");
+    assert_eq!(stop.value.as_deref(), Some("3"));
+
+    let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+    worker
+        .command_sender()
+        .send(WorkerCommand::DebugInput {
+            op_id: worker.next_id(),
+            session,
+            key: stop.key,
+            command: DebugCommand::Next,
+            reply: reply_tx,
+        })
+        .expect("worker thread gone");
+    reply_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("no reply")
+        .expect("debug-input failed");
+    drop(worker);
+    let request = server.join().expect("server thread");
+    assert!(request.contains("5:input5::next"), "sent: {request}");
+    assert!(request.contains("3:key3:k-1"), "sent: {request}");
+}
+
+#[test]
+fn test_tap_subscription_streams_values_until_dropped() {
+    use nrepl_rs::Session;
+    use nrepl_rs::worker::WorkerCommand;
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let (dropped_tx, dropped_rx) = std::sync::mpsc::channel::<()>();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 2048];
+        let _ = stream.read(&mut buf).expect("read tap eval");
+        // One value split across two writes, then a second whole.
+        stream
+            .write_all(b"d2:id5:req-13:out6:1\n{:a e")
+            .expect("write out");
+        stream
+            .write_all(b"d2:id5:req-13:out4::b}\ne")
+            .expect("write out");
+        dropped_rx.recv().expect("test gone");
+        stream
+            .write_all(b"d2:id5:req-13:out2:2\ne")
+            .expect("write out");
+        let n = stream.read(&mut buf).expect("read interrupt");
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    });
+
+    let worker = Worker::new();
+    worker.connect_blocking(addr).expect("connect");
+    let (taps_tx, taps_rx) = std::sync::mpsc::channel();
+    worker
+        .command_sender()
+        .send(WorkerCommand::SubscribeTap {
+            op_id: worker.next_id(),
+            session: Session::from_server_id("s1"),
+            taps: taps_tx,
+        })
+        .expect("worker thread gone");
+
+    let next = || {
+        taps_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("no tap")
+            .expect("tap failed")
+    };
+    assert_eq!(next(), "1");
+    assert_eq!(next(), "{:a :b}");
+    drop(taps_rx);
+    dropped_tx.send(()).expect("server gone");
+
+    // The next value finds nobody listening, so the worker interrupts the
+    // subscription eval.
+    let request = server.join().expect("server thread");
+    assert!(request.contains("2:op9:interrupt"), "sent: {request}");
+    assert!(
+        request.contains("12:interrupt-id5:req-1"),
+        "sent: {request}"
+    );
+    drop(worker);
+}
+
+#[test]
+fn test_out_subscription_outlives_its_done() {
+    use nrepl_rs::worker::{WorkerCommand, WorkerConfig};
+    use nrepl_rs::{ChunkKind, Session};
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 1024];
+        let _ = stream.read(&mut buf).expect("read out-subscribe");
+        stream
+            .write_all(b"d2:id5:req-16:statusl4:doneee")
+            .expect("write done");
+        // Forwarded output turns up whenever the server prints, long after
+        // the watchdog would have fired.
+        std::thread::sleep(Duration::from_millis(300));
+        stream
+            .write_all(b"d2:id5:req-13:out8:tick 42\ne")
+            .expect("write out");
+        stream
+            .write_all(b"d3:err15:WARNING: slow!\n2:id5:req-1e")
+            .expect("write err");
+        let n = stream.read(&mut buf).expect("read out-unsubscribe");
+        let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+        stream
+            .write_all(b"d2:id5:req-26:statusl4:doneee")
+            .expect("write done");
+        let _ = stream.read(&mut buf);
+        request
+    });
+
+    let worker =
+        Worker::with_config(WorkerConfig::default().done_timeout(Duration::from_millis(100)));
+    worker.connect_blocking(addr).expect("connect");
+    let session = Session::from_server_id("s1");
+    let (output_tx, output_rx) = std::sync::mpsc::channel();
+    worker
+        .command_sender()
+        .send(WorkerCommand::OutSubscribe {
+            op_id: worker.next_id(),
+            session: session.clone(),
+            output: output_tx,
+        })
+        .expect("worker thread gone");
+
+    let next = || {
+        output_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("no output")
+            .expect("subscription failed")
+    };
+    let out = next();
+    assert_eq!(out.kind, ChunkKind::Stdout);
+    assert_eq!(out.text, "tick 42\n");
+    assert_eq!(next().kind, ChunkKind::Warning);
+
+    let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+    worker
+        .command_sender()
+        .send(WorkerCommand::OutUnsubscribe {
+            op_id: worker.next_id(),
+            session,
+            reply: reply_tx,
+        })
+        .expect("worker thread gone");
+    reply_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("no reply")
+        .expect("out-unsubscribe failed");
+    // Unsubscribing closed the stream.
+    assert!(output_rx.recv_timeout(Duration::from_secs(5)).is_err());
+    drop(worker);
+    let request = server.join().expect("server thread");
+    assert!(request.contains("15:out-unsubscribe"), "sent: {request}");
+}
+
+#[test]
+fn test_unopenable_frame_capture_fails_connect() {
+    use nrepl_rs::worker::WorkerConfig;
+
+    // The capture file is opened before dialling, so no server is needed.
+    let worker = Worker::with_config(
+        WorkerConfig::default().capture_frames("/nonexistent-dir/nrepl-frames.log"),
+    );
+    match worker.connect_blocking("127.0.0.1:1".to_string()) {
+        Err(NReplError::Connection(e)) => {
+            assert!(e.to_string().contains("frame capture"), "got: {e}");
+        }
+        other => panic!("Expected Connection error, got: {other:?}"),
+    }
+}
+
 #[test]
 fn test_codec_error_incomplete_bencode() {
     use nrepl_rs::codec::decode_response;
@@ -245,6 +723,7 @@ fn test_error_display_timeout() {
     let err = NReplError::Timeout {
         operation: "eval".to_string(),
         duration: Duration::from_secs(5),
+        partial: None,
     };
     let display = format!("{err}");
     assert!(display.contains("Timeout"));
@@ -252,6 +731,43 @@ fn test_error_display_timeout() {
     assert!(display.contains("5s"));
 }
 
+#[test]
+fn test_error_display_protocol_violation() {
+    let err = NReplError::ProtocolViolation {
+        op: "completions".to_string(),
+        id: "req-7".to_string(),
+        waited: Duration::from_secs(20),
+    };
+    let display = format!("{err}");
+    assert!(display.contains("completions"));
+    assert!(display.contains("req-7"));
+    assert!(display.contains("20s"));
+}
+
+#[test]
+fn test_timeout_partial_result() {
+    let mut partial = nrepl_rs::EvalResult::new();
+    partial.output.push("before\n".to_string());
+
+    let err = NReplError::Timeout {
+        operation: "eval".to_string(),
+        duration: Duration::from_secs(1),
+        partial: Some(Box::new(partial)),
+    };
+    let carried = err
+        .partial_result()
+        .expect("eval timeout carries a partial result");
+    assert_eq!(carried.output, vec!["before\n".to_string()]);
+
+    // Non-eval timeouts and other errors carry nothing.
+    assert!(
+        NReplError::timeout("connect", Duration::from_secs(30))
+            .partial_result()
+            .is_none()
+    );
+    assert!(NReplError::protocol("x").partial_result().is_none());
+}
+
 #[test]
 fn test_error_source_connection() {
     use std::error::Error;
@@ -359,6 +875,7 @@ fn test_close_session_completes() {
         Err(NReplError::Timeout {
             operation,
             duration,
+            ..
         }) => {
             assert_eq!(operation, "close-session");
             assert_eq!(duration, Duration::from_secs(10));
@@ -368,3 +885,1713 @@ fn test_close_session_completes() {
         }
     }
 }
+
+/// Sideloader lookups arrive unprompted on the start request's id; the worker
+/// answers each from the provider without the caller's involvement.
+#[test]
+fn test_sideloader_answers_lookups_from_provider() {
+    use nrepl_rs::Session;
+    use nrepl_rs::worker::WorkerCommand;
+    use nrepl_rs::{SideloadKind, SideloadProvider};
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 1024];
+        let _ = stream.read(&mut buf).expect("read sideloader-start");
+        stream
+            .write_all(b"d2:id5:req-14:name7:a/b.txt7:session2:s16:statusl17:sideloader-lookupe4:type8:resourcee")
+            .expect("write lookup");
+        let n = stream.read(&mut buf).expect("read sideloader-provide");
+        let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+        stream
+            .write_all(b"d2:id4:nope4:name7:X.class7:session2:s16:statusl17:sideloader-lookupe4:type5:classe")
+            .expect("write stray lookup");
+        let _ = stream.read(&mut buf);
+        request
+    });
+
+    let worker = Worker::new();
+    worker.connect_blocking(addr).expect("connect");
+    let provider: SideloadProvider = Arc::new(|kind, name| {
+        (kind == SideloadKind::Resource && name == "a/b.txt").then(|| b"hi".to_vec())
+    });
+    let (lookups_tx, lookups_rx) = std::sync::mpsc::channel();
+    worker
+        .command_sender()
+        .send(WorkerCommand::StartSideloader {
+            op_id: worker.next_id(),
+            session: Session::from_server_id("s1"),
+            provider,
+            lookups: lookups_tx,
+        })
+        .expect("worker thread gone");
+
+    let lookup = lookups_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("no lookup")
+        .expect("sideloader failed");
+    assert_eq!(lookup.kind, SideloadKind::Resource);
+    assert_eq!(lookup.name, "a/b.txt");
+    assert!(lookup.found);
+
+    drop(worker);
+    let request = server.join().expect("server panicked");
+    assert!(request.contains("2:op18:sideloader-provide"), "{request}");
+    assert!(request.contains("7:content4:aGk="), "{request}");
+    assert!(request.contains("4:type8:resource"), "{request}");
+}
+
+/// Once a session is upgraded to ClojureScript its completions go to
+/// cider-nrepl's `complete`; other sessions keep nREPL's `completions`.
+#[test]
+fn test_cljs_session_completes_with_cider() {
+    use nrepl_rs::worker::WorkerCommand;
+    use nrepl_rs::{CljsRepl, Session};
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 1024];
+        let mut requests = Vec::new();
+        let n = stream.read(&mut buf).expect("read upgrade eval");
+        requests.push(String::from_utf8_lossy(&buf[..n]).into_owned());
+        stream
+            .write_all(b"d2:id5:req-12:ns9:cljs.user5:value16:[:selected :app]e")
+            .expect("write value");
+        stream
+            .write_all(b"d2:id5:req-16:statusl4:doneee")
+            .expect("write done");
+        for id in ["req-2", "req-3"] {
+            let n = stream.read(&mut buf).expect("read completions");
+            requests.push(String::from_utf8_lossy(&buf[..n]).into_owned());
+            stream
+                .write_all(format!("d11:completionsle2:id5:{id}6:statusl4:doneee").as_bytes())
+                .expect("write completions");
+        }
+        let _ = stream.read(&mut buf);
+        requests
+    });
+
+    let worker = Worker::new();
+    worker.connect_blocking(addr).expect("connect");
+    let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+    worker
+        .command_sender()
+        .send(WorkerCommand::UpgradeCljs {
+            op_id: worker.next_id(),
+            session: Session::from_server_id("s1"),
+            repl: CljsRepl::Shadow {
+                build: "app".to_string(),
+            },
+            reply: reply_tx,
+        })
+        .expect("worker thread gone");
+    reply_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("no reply")
+        .expect("upgrade failed");
+
+    for session in ["s1", "s2"] {
+        let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+        worker
+            .command_sender()
+            .send(WorkerCommand::Completions {
+                op_id: worker.next_id(),
+                session: Session::from_server_id(session),
+                prefix: "ma".to_string(),
+                ns: None,
+                complete_fn: None,
+                reply: reply_tx,
+            })
+            .expect("worker thread gone");
+        reply_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("no reply")
+            .expect("completions failed");
+    }
+
+    drop(worker);
+    let requests = server.join().expect("server panicked");
+    assert!(
+        requests[0].contains("nrepl-select) :app)"),
+        "{}",
+        requests[0]
+    );
+    assert!(requests[1].contains("2:op8:complete"), "{}", requests[1]);
+    assert!(
+        requests[2].contains("2:op11:completions"),
+        "{}",
+        requests[2]
+    );
+}
+
+/// With server detection on, a babashka server's missing `completions` op
+/// sends completions to `complete`, and eval results without `ns` get the
+/// session's namespace.
+#[test]
+fn test_detected_babashka_profile_adapts_requests() {
+    use nrepl_rs::ServerFlavor;
+    use nrepl_rs::Session;
+    use nrepl_rs::worker::{EvalOutcome, WorkerCommand, WorkerConfig};
+    use std::io::{Read, Write};
+
+    let request_id = |chunk: &str| request_ids(chunk).remove(0);
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 1024];
+        let mut read = |stream: &mut std::net::TcpStream| {
+            let n = stream.read(&mut buf).expect("read");
+            String::from_utf8_lossy(&buf[..n]).into_owned()
+        };
+        let describe = read(&mut stream);
+        stream
+            .write_all(&frame(
+                &request_id(&describe),
+                concat!(
+                    "3:opsd8:completede4:evaldee6:statusl4:donee",
+                    "8:versionsd8:babashkad14:version-string6:1.12.0ee"
+                ),
+            ))
+            .expect("write describe");
+        let complete = read(&mut stream);
+        stream
+            .write_all(&frame(
+                &request_id(&complete),
+                "11:completionsle6:statusl4:donee",
+            ))
+            .expect("write completions");
+        let eval = read(&mut stream);
+        let id = request_id(&eval);
+        stream
+            .write_all(&frame(&id, "5:value1:3"))
+            .expect("write value");
+        stream
+            .write_all(&frame(&id, "6:statusl4:donee"))
+            .expect("write done");
+        let _ = read(&mut stream);
+        (describe, complete)
+    });
+
+    let mut worker = Worker::with_config(WorkerConfig::default().detect_server(true));
+    worker.connect_blocking(addr).expect("connect");
+    let started = std::time::Instant::now();
+    let profile = loop {
+        if let Some(profile) = worker.server_profile() {
+            break profile;
+        }
+        assert!(started.elapsed() < Duration::from_secs(5), "no profile");
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert_eq!(profile.flavor, ServerFlavor::Babashka);
+
+    let session = Session::from_server_id("s1");
+    let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+    worker
+        .command_sender()
+        .send(WorkerCommand::Completions {
+            op_id: worker.next_id(),
+            session: session.clone(),
+            prefix: "ma".to_string(),
+            ns: None,
+            complete_fn: None,
+            reply: reply_tx,
+        })
+        .expect("worker thread gone");
+    reply_rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("no reply")
+        .expect("completions failed");
+
+    let id = worker
+        .submit_eval(session, "(+ 1 2)".to_string(), None, None, None, None)
+        .expect("submit");
+    let result = loop {
+        if let Some(response) = worker.try_recv_response(id) {
+            match response.outcome {
+                EvalOutcome::Done(result) => break result.expect("eval failed"),
+                EvalOutcome::NeedInput { .. } => panic!("unexpected need-input"),
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert_eq!(result.ns.as_deref(), Some("user"));
+
+    drop(worker);
+    let (describe, complete) = server.join().expect("server panicked");
+    assert!(describe.contains("2:op8:describe"), "{describe}");
+    assert!(complete.contains("2:op8:complete"), "{complete}");
+}
+
+#[test]
+fn test_eval_in_ns_sends_ns_and_tracks_session_ns() {
+    use nrepl_rs::Session;
+    use nrepl_rs::worker::{EvalOutcome, SubmitError, WorkerConfig};
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 1024];
+        let n = stream.read(&mut buf).expect("read eval");
+        stream
+            .write_all(b"d2:id5:req-12:ns8:app.core5:value1:3e")
+            .expect("write value");
+        stream
+            .write_all(b"d2:id5:req-16:statusl4:doneee")
+            .expect("write done");
+        let _ = stream.read(&mut buf);
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    });
+
+    let mut worker = Worker::with_config(WorkerConfig::default().in_ns_fallback(true));
+    worker.connect_blocking(addr).expect("connect");
+    let session = Session::from_server_id("s1");
+    assert_eq!(worker.session_ns(&session), None);
+    assert_eq!(
+        worker.eval_in_ns(&session, "app core", "1".to_string(), None),
+        Err(SubmitError::InvalidNamespace("app core".to_string()))
+    );
+
+    let id = worker
+        .eval_in_ns(&session, "app.core", "(+ 1 2)".to_string(), None)
+        .expect("submit");
+    loop {
+        if let Some(response) = worker.try_recv_response(id) {
+            match response.outcome {
+                EvalOutcome::Done(result) => {
+                    assert_eq!(result.expect("eval failed").value.as_deref(), Some("3"));
+                    break;
+                }
+                EvalOutcome::NeedInput { .. } => panic!("unexpected need-input"),
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(worker.session_ns(&session).as_deref(), Some("app.core"));
+
+    drop(worker);
+    let request = server.join().expect("server thread");
+    assert!(request.contains("2:ns8:app.core"), "sent: {request}");
+    assert!(
+        request.contains("4:code38:(clojure.core/in-ns 'app.core) (+ 1 2)"),
+        "sent: {request}"
+    );
+}
+
+/// An eval that switches namespace drops the aliases cached for the one it
+/// ran in, not the one its responses report.
+#[test]
+fn test_ns_aliases_dropped_for_the_ns_an_eval_ran_in() {
+    use nrepl_rs::Session;
+    use nrepl_rs::worker::EvalOutcome;
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut alias_queries = 0;
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut buf).unwrap_or(0);
+            if n == 0 {
+                return alias_queries;
+            }
+            let chunk = String::from_utf8_lossy(&buf[..n]).into_owned();
+            for id in request_ids(&chunk) {
+                let reply = if chunk.contains("the-ns 'app.core") {
+                    alias_queries += 1;
+                    let value = "\"str clojure.string;\"";
+                    format!("5:value{}:{value}6:statusl4:donee", value.len())
+                } else {
+                    "2:ns5:other5:value5:other6:statusl4:donee".to_string()
+                };
+                stream.write_all(&frame(&id, &reply)).expect("write reply");
+            }
+        }
+    });
+
+    let mut worker = Worker::new();
+    worker.connect_blocking(addr).expect("connect");
+    let session = Session::from_server_id("s1");
+    for _ in 0..2 {
+        let found = common::ns_aliases(&worker, &session, "app.core").expect("ns-aliases");
+        assert_eq!(
+            found.aliases.get("str").map(String::as_str),
+            Some("clojure.string")
+        );
+    }
+
+    let id = worker
+        .eval_in_ns(&session, "app.core", "(in-ns 'other)".to_string(), None)
+        .expect("submit");
+    loop {
+        if let Some(response) = worker.try_recv_response(id) {
+            match response.outcome {
+                EvalOutcome::Done(result) => {
+                    result.expect("eval failed");
+                    break;
+                }
+                EvalOutcome::NeedInput { .. } => panic!("unexpected need-input"),
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    common::ns_aliases(&worker, &session, "app.core").expect("ns-aliases after eval");
+
+    drop(worker);
+    assert_eq!(server.join().expect("server thread"), 2);
+}
+
+/// Aliased completions resolve the alias from the namespace-aliases cache,
+/// fetching the aliases only on a miss, and every request goes out under the
+/// caller's id.
+#[test]
+fn test_aliased_completions_share_the_ns_aliases_cache() {
+    use nrepl_rs::Session;
+    use nrepl_rs::worker::WorkerCommand;
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut requests = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut buf).unwrap_or(0);
+            if n == 0 {
+                return requests;
+            }
+            let chunk = String::from_utf8_lossy(&buf[..n]).into_owned();
+            for id in request_ids(&chunk) {
+                let reply = if chunk.contains("the-ns 'app.core") {
+                    let value = "\"str clojure.string;\"";
+                    format!("5:value{}:{value}6:statusl4:donee", value.len())
+                } else {
+                    "11:completionsld9:candidate12:starts-with?2:ns14:clojure.stringe\
+                     d9:candidate3:map2:ns12:clojure.coreee6:statusl4:donee"
+                        .to_string()
+                };
+                stream.write_all(&frame(&id, &reply)).expect("write reply");
+                requests.push((id, chunk.clone()));
+            }
+        }
+    });
+
+    let worker = Worker::new();
+    worker.connect_blocking(addr).expect("connect");
+    let mut op_ids = Vec::new();
+    for prefix in ["str/sta", "str/st"] {
+        let op_id = worker.next_id();
+        op_ids.push(format!("req-{}", op_id.as_usize()));
+        let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+        worker
+            .command_sender()
+            .send(WorkerCommand::AliasedCompletions {
+                op_id,
+                session: Session::from_server_id("s1"),
+                prefix: prefix.to_string(),
+                ns: Some("app.core".to_string()),
+                complete_fn: None,
+                reply: reply_tx,
+            })
+            .expect("worker thread gone");
+        let candidates = reply_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("no reply")
+            .expect("completions failed");
+        let names: Vec<&str> = candidates.iter().map(|c| c.candidate.as_str()).collect();
+        assert_eq!(names, ["str/starts-with?"]);
+    }
+
+    drop(worker);
+    let requests = server.join().expect("server thread");
+    let ids: Vec<&str> = requests.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(ids, [&op_ids[0], &op_ids[0], &op_ids[1]]);
+    assert!(requests[0].1.contains("2:op4:eval"), "{}", requests[0].1);
+    for (_, request) in &requests[1..] {
+        assert!(request.contains("2:op11:completions"), "{request}");
+        assert!(request.contains("2:ns14:clojure.string"), "{request}");
+    }
+}
+
+/// Serve evals whose code is `"1"`, `"(/ 1 0)"` or `"3"`, answering the
+/// second with an exception, until the client goes away. Returns everything
+/// the client sent.
+fn serve_batch(listener: std::net::TcpListener) -> String {
+    use std::io::{Read, Write};
+    let (mut stream, _) = listener.accept().expect("accept");
+    let mut sent = String::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut buf).unwrap_or(0);
+        if n == 0 {
+            return sent;
+        }
+        let chunk = String::from_utf8_lossy(&buf[..n]).into_owned();
+        for (id, request) in request_ids(&chunk)
+            .iter()
+            .zip(chunk.split("d4:code").skip(1))
+        {
+            let reply = if request.starts_with("7:(/ 1 0)") {
+                "2:ex35:class java.lang.ArithmeticException6:statusl10:eval-error4:donee"
+                    .to_string()
+            } else {
+                let value = &request[2..3];
+                format!("2:ns4:user5:value1:{value}6:statusl4:donee")
+            };
+            let _ = stream.write_all(&frame(id, &reply));
+        }
+        sent.push_str(&chunk);
+    }
+}
+
+#[test]
+fn test_eval_batch_results_align_with_forms() {
+    use nrepl_rs::Session;
+    use nrepl_rs::worker::BatchOptions;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || serve_batch(listener));
+
+    let mut worker = Worker::new();
+    worker.connect_blocking(addr).expect("connect");
+    let session = Session::from_server_id("s1");
+    let forms = || vec!["1".to_string(), "(/ 1 0)".to_string(), "3".to_string()];
+
+    let results = worker.eval_batch(&session, forms(), BatchOptions::default());
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap().value.as_deref(), Some("1"));
+    assert!(results[1].as_ref().unwrap().ex.is_some());
+    assert_eq!(results[2].as_ref().unwrap().value.as_deref(), Some("3"));
+
+    let fail_fast = BatchOptions {
+        fail_fast: true,
+        ..BatchOptions::default()
+    };
+    let results = worker.eval_batch(&session, forms(), fail_fast);
+    assert!(results[1].as_ref().unwrap().ex.is_some());
+    assert!(matches!(results[2], Err(NReplError::OperationFailed(_))));
+
+    worker.label_session(&session, Some("batch".to_string()));
+    let sessions = worker.sessions();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].session, session);
+    assert_eq!(sessions[0].label.as_deref(), Some("batch"));
+    assert_eq!(sessions[0].ops.get("eval"), Some(&5));
+
+    drop(worker);
+    let sent = server.join().expect("server thread");
+    // Three forms pipelined, then two before stopping.
+    assert_eq!(sent.matches("4:code1:3").count(), 1, "sent: {sent}");
+}
+
+/// Answer each `load-file` with its file name as the value.
+#[cfg(feature = "watch")]
+fn serve_load_files(listener: std::net::TcpListener) -> String {
+    use std::io::{Read, Write};
+    let (mut stream, _) = listener.accept().expect("accept");
+    let mut sent = String::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut buf).unwrap_or(0);
+        if n == 0 {
+            return sent;
+        }
+        let chunk = String::from_utf8_lossy(&buf[..n]).into_owned();
+        for id in request_ids(&chunk) {
+            let _ = stream.write_all(&frame(&id, "5:value8:core.clj6:statusl4:donee"));
+        }
+        sent.push_str(&chunk);
+    }
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+fn test_blocking_client_evals_in_its_own_session() {
+    use nrepl_rs::blocking::NReplClient;
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 4096];
+        let mut sent = String::new();
+        loop {
+            let n = stream.read(&mut buf).unwrap_or(0);
+            if n == 0 {
+                return sent;
+            }
+            let chunk = String::from_utf8_lossy(&buf[..n]).into_owned();
+            for id in request_ids(&chunk) {
+                let body = if chunk.contains("2:op5:clone") {
+                    "11:new-session2:s1"
+                } else if chunk.contains("2:op4:eval") {
+                    "2:ns4:user5:value1:3"
+                } else {
+                    ""
+                };
+                let _ = stream.write_all(&frame(&id, body));
+                let _ = stream.write_all(&frame(&id, "6:statusl4:donee"));
+            }
+            sent.push_str(&chunk);
+        }
+    });
+
+    let mut client = NReplClient::connect(addr).expect("connect");
+    assert_eq!(client.session().id(), "s1");
+    let result = client.eval("(+ 1 2)").expect("eval");
+    assert_eq!(result.value.as_deref(), Some("3"));
+    let at = nrepl_rs::Position { line: 3, column: 5 };
+    let result = client
+        .eval_with_location("(+ 1 2)", Some("src/app.clj"), at)
+        .expect("eval with location");
+    assert_eq!(result.value.as_deref(), Some("3"));
+    client.close().expect("close");
+
+    let sent = server.join().expect("server thread");
+    assert!(sent.contains("7:session2:s1"), "sent: {sent}");
+    assert!(sent.contains("6:columni5e"), "sent: {sent}");
+    assert!(sent.contains("4:file11:src/app.clj"), "sent: {sent}");
+    assert!(sent.contains("4:linei3e"), "sent: {sent}");
+    assert!(sent.contains("2:op5:close"), "sent: {sent}");
+}
+
+#[cfg(all(feature = "blocking", feature = "test-utils"))]
+#[test]
+fn test_conformance_check_reports_each_op() {
+    use nrepl_rs::blocking::NReplClient;
+    use nrepl_rs::testing::{Frame, MockNReplServer, MockRequest, Reply, standard_replies};
+    use nrepl_rs::{CheckOutcome, ServerFlavor};
+
+    // A babashka-like server without completions, whose interrupt stops
+    // the sleeping eval.
+    let mut sleeping: Option<MockRequest> = None;
+    let server = MockNReplServer::start(move |request| {
+        let id = request.id();
+        match (request.op(), request.get("code")) {
+            ("describe", _) => vec![Reply::Raw(
+                format!(
+                    "d2:id{}:{id}3:opsd5:clonede5:closede8:describede4:evalde\
+                     9:interruptde6:lookupdee6:statusl4:donee8:versionsd\
+                     8:babashkad14:version-string5:1.3.0eee",
+                    id.len()
+                )
+                .into_bytes(),
+            )],
+            ("eval", Some("(Thread/sleep 3000)")) => {
+                sleeping = Some(request.clone());
+                Vec::new()
+            }
+            ("eval", Some("(print \"ok\")")) => vec![
+                Reply::Frame(Frame::reply_to(request).str("out", "ok")),
+                Reply::Frame(Frame::reply_to(request).str("value", "nil").done()),
+            ],
+            ("eval", _) => vec![Reply::Frame(
+                Frame::reply_to(request)
+                    .str("value", "3")
+                    .str("ns", "user")
+                    .done(),
+            )],
+            ("interrupt", _) => {
+                let mut replies = standard_replies(request);
+                if let Some(eval) = sleeping.take() {
+                    replies.push(Reply::Frame(
+                        Frame::reply_to(&eval).list("status", &["interrupted", "done"]),
+                    ));
+                }
+                replies
+            }
+            ("lookup", _) => vec![Reply::Raw(
+                format!(
+                    "d2:id{}:{id}4:infod2:ns12:clojure.coree6:statusl4:doneee",
+                    id.len()
+                )
+                .into_bytes(),
+            )],
+            _ => standard_replies(request),
+        }
+    })
+    .expect("start mock server");
+
+    let mut client = NReplClient::connect(server.address()).expect("connect");
+    let report = client.run_conformance_check();
+    assert_eq!(report.flavor, ServerFlavor::Babashka);
+    assert_eq!(report.versions["babashka"], "1.3.0");
+    let outcomes: Vec<(&str, &CheckOutcome)> =
+        report.checks.iter().map(|c| (c.name, &c.outcome)).collect();
+    assert_eq!(
+        outcomes,
+        [
+            ("describe", &CheckOutcome::Passed),
+            ("clone", &CheckOutcome::Passed),
+            ("eval", &CheckOutcome::Passed),
+            ("eval-output", &CheckOutcome::Passed),
+            ("interrupt", &CheckOutcome::Passed),
+            ("lookup", &CheckOutcome::Passed),
+            ("completions", &CheckOutcome::Unsupported),
+        ]
+    );
+    assert!(report.passed());
+    assert!(
+        report.check("interrupt").unwrap().latency < Duration::from_secs(3),
+        "{report}"
+    );
+    assert!(
+        report
+            .to_string()
+            .starts_with("server: babashka (babashka 1.3.0)\n")
+    );
+    client.close().expect("close");
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_plan_swap_middleware_names_lost_ops() {
+    use nrepl_rs::testing::{MockNReplServer, Reply, standard_replies};
+
+    let server = MockNReplServer::start(|request| {
+        let id = request.id();
+        let body = match request.op() {
+            "ls-middleware" => {
+                "10:middlewarel56:#'nrepl.middleware.interruptible-eval/interruptible-eval\
+                 34:#'nrepl.middleware.session/sessione"
+            }
+            "describe" => "3:opsd5:clonede4:evalde9:interruptdee",
+            _ => return standard_replies(request),
+        };
+        let frame = format!("d2:id{}:{id}{body}6:statusl4:doneee", id.len());
+        vec![Reply::Raw(frame.into_bytes())]
+    })
+    .expect("start mock server");
+
+    let worker = Worker::new();
+    worker
+        .connect_blocking(server.address().to_string())
+        .expect("connect");
+    let stack = worker.ls_middleware(false).expect("ls-middleware");
+    assert_eq!(stack.position("nrepl.middleware.session/session"), Some(1));
+
+    let plan = worker
+        .plan_swap_middleware(&["nrepl.middleware.interruptible-eval/interruptible-eval".into()])
+        .expect("plan");
+    assert_eq!(plan.removed, ["nrepl.middleware.session/session"]);
+    let lost: Vec<&str> = plan.lost_ops.iter().map(|l| l.op.as_str()).collect();
+    assert_eq!(lost, ["clone", "interrupt"]);
+    let verbose: Vec<_> = server
+        .received()
+        .iter()
+        .filter(|r| r.op() == "ls-middleware")
+        .map(|r| r.int("verbose?"))
+        .collect();
+    assert_eq!(verbose, [None, Some(1)]);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_mock_server_split_and_out_of_order_replies() {
+    use nrepl_rs::Session;
+    use nrepl_rs::testing::{Frame, MockNReplServer, MockRequest, Reply, standard_replies};
+    use nrepl_rs::worker::EvalOutcome;
+
+    // Hold the eval's answer back until a lookup arrives, then answer the
+    // lookup first, split over several writes, and the eval after it.
+    let mut held: Option<MockRequest> = None;
+    let server = MockNReplServer::start(move |request| match request.op() {
+        "eval" => {
+            held = Some(request.clone());
+            Vec::new()
+        }
+        "lookup" => {
+            let lookup = Frame::reply_to(request).str("ns", "clojure.core").done();
+            let mut replies = vec![Reply::Split {
+                bytes: lookup.encode(),
+                piece: 3,
+                pause: Duration::from_millis(1),
+            }];
+            if let Some(eval) = held.take() {
+                replies.push(Reply::Frame(
+                    Frame::reply_to(&eval).str("value", "3").done(),
+                ));
+            }
+            replies
+        }
+        _ => standard_replies(request),
+    })
+    .expect("start mock server");
+
+    let mut worker = Worker::new();
+    worker
+        .connect_blocking(server.address().to_string())
+        .expect("connect");
+    let session = Session::from_server_id("s1");
+    let id = worker
+        .submit_eval(session.clone(), "(+ 1 2)", None, None, None, None)
+        .expect("submit");
+    let info = worker.lookup(&session, "map", None).expect("lookup");
+    assert_eq!(info.ns.as_deref(), Some("clojure.core"));
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let result = loop {
+        if let Some(response) = worker.try_recv_response(id) {
+            let EvalOutcome::Done(result) = response.outcome else {
+                panic!("unexpected need-input");
+            };
+            break result.expect("eval");
+        }
+        assert!(std::time::Instant::now() < deadline, "no eval reply");
+        std::thread::sleep(Duration::from_millis(5));
+    };
+    assert_eq!(result.value.as_deref(), Some("3"));
+    let ops: Vec<_> = server
+        .received()
+        .iter()
+        .map(|r| r.op().to_string())
+        .collect();
+    assert_eq!(ops, ["eval", "lookup"]);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_captured_session_replays_the_same_answers() {
+    use nrepl_rs::testing::{MockNReplServer, Recording};
+    use nrepl_rs::worker::WorkerConfig;
+
+    fn run(address: &str, config: WorkerConfig) -> (String, Option<String>) {
+        let mut worker = Worker::with_config(config);
+        worker
+            .connect_blocking(address.to_string())
+            .expect("connect");
+        let session = common::clone_session(&worker).expect("clone");
+        let result = common::eval(&mut worker, &session, "(+ 1 2)").expect("eval");
+        (session.id().to_string(), result.value)
+    }
+
+    let path = std::env::temp_dir().join(format!("nrepl-replay-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let live = MockNReplServer::standard().expect("start mock server");
+    let recorded = run(
+        live.address(),
+        WorkerConfig::default().capture_frames(&path),
+    );
+
+    let recording = Recording::load(&path).expect("load capture");
+    assert_eq!(recording.len(), 2);
+    let replay = MockNReplServer::replay(recording).expect("start replay");
+    assert_eq!(run(replay.address(), WorkerConfig::default()), recorded);
+    std::fs::remove_file(&path).expect("remove capture");
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_connect_stdio_drives_a_worker_over_pipes() {
+    use nrepl_rs::testing::MockNReplServer;
+
+    // Any byte stream serves as a subprocess's pipes; a socket to the mock
+    // server is the simplest one that answers.
+    let server = MockNReplServer::standard().expect("start mock server");
+    let stream = std::net::TcpStream::connect(server.address()).expect("connect");
+    let stdin = stream.try_clone().expect("clone stream");
+
+    let mut worker = Worker::new();
+    worker.connect_stdio(stdin, stream).expect("connect_stdio");
+    let session = common::clone_session(&worker).expect("clone");
+    let result = common::eval(&mut worker, &session, "(+ 1 2)").expect("eval");
+    assert_eq!(result.value.as_deref(), Some("nil"));
+    assert!(
+        worker
+            .connect_stdio(std::io::empty(), std::io::empty())
+            .is_err()
+    );
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_half_open_connection_fails_requests_as_lost() {
+    use nrepl_rs::ErrorCode;
+    use nrepl_rs::testing::{MockNReplServer, standard_replies};
+    use nrepl_rs::worker::{ConnectionState, WorkerConfig};
+
+    // Takes every request but only ever answers the clone, like a peer
+    // whose replies no longer get through.
+    let server = MockNReplServer::start(|request| match request.op() {
+        "clone" => standard_replies(request),
+        _ => Vec::new(),
+    })
+    .expect("start mock server");
+    let config = WorkerConfig::default().half_open_after(Duration::from_millis(300));
+    let mut worker = Worker::with_config(config);
+    worker
+        .connect_blocking(server.address().to_string())
+        .expect("connect");
+    let session = common::clone_session(&worker).expect("clone");
+
+    let started = std::time::Instant::now();
+    let err = common::eval_with_timeout(&mut worker, &session, "(+ 1 2)", Duration::from_mins(1))
+        .expect_err("half-open");
+    assert!(matches!(err, NReplError::ConnectionLost(_)), "{err:?}");
+    assert_eq!(err.code(), ErrorCode::ConnectionLost);
+    assert!(!err.is_retryable());
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(worker.connection_state(), ConnectionState::Disconnected);
+    assert!(
+        worker
+            .debug_events()
+            .iter()
+            .any(|e| e.kind == DebugEventKind::ConnectionLost)
+    );
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_reconnect_on_loss_connects_again() {
+    use nrepl_rs::testing::{MockNReplServer, standard_replies};
+    use nrepl_rs::worker::WorkerConfig;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // The first heartbeat goes unanswered; the server is fine afterwards.
+    let swallowed = AtomicBool::new(false);
+    let server = MockNReplServer::start(move |request| {
+        if request.op() == "describe" && !swallowed.swap(true, Ordering::SeqCst) {
+            return Vec::new();
+        }
+        standard_replies(request)
+    })
+    .expect("start mock server");
+    let config = WorkerConfig::default()
+        .half_open_after(Duration::from_millis(300))
+        .reconnect_on_loss(true);
+    let mut worker = Worker::with_config(config);
+    worker
+        .connect_blocking(server.address().to_string())
+        .expect("connect");
+
+    let reconnected = || {
+        worker
+            .debug_events()
+            .iter()
+            .any(|e| e.kind == DebugEventKind::Reconnected)
+    };
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    while !reconnected() && std::time::Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert!(reconnected(), "events: {:?}", worker.debug_events());
+    assert_eq!(
+        worker.connection_state(),
+        nrepl_rs::worker::ConnectionState::Connected
+    );
+
+    let session = common::clone_session(&worker).expect("clone");
+    let result = common::eval(&mut worker, &session, "(+ 1 2)").expect("eval");
+    assert_eq!(result.value.as_deref(), Some("nil"));
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_eval_tags_are_sent_and_echoes_collected() {
+    use nrepl_rs::testing::{Frame, MockNReplServer, Reply, standard_replies};
+    use nrepl_rs::worker::{EvalOptions, EvalOutcome};
+
+    // Echoes `trace-id` back, as tracing middleware would; `buffer` is
+    // dropped like any field a server does not know.
+    let server = MockNReplServer::start(|request| match request.op() {
+        "eval" => vec![Reply::Frame(
+            Frame::reply_to(request)
+                .str("value", "3")
+                .str("trace-id", request.get("trace-id").unwrap_or_default())
+                .done(),
+        )],
+        _ => standard_replies(request),
+    })
+    .expect("start mock server");
+    let mut worker = Worker::new();
+    worker
+        .connect_blocking(server.address().to_string())
+        .expect("connect");
+    let session = common::clone_session(&worker).expect("clone");
+
+    let options = EvalOptions {
+        tags: [
+            ("trace-id", "t-42"),
+            ("buffer", "core.clj"),
+            ("code", "(evil)"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect(),
+        ..EvalOptions::default()
+    };
+    let id = worker
+        .submit_eval_with(session, "(+ 1 2)", options)
+        .expect("submit");
+    let result = loop {
+        if let Some(response) = worker.try_recv_response(id) {
+            match response.outcome {
+                EvalOutcome::Done(result) => break result.expect("eval failed"),
+                EvalOutcome::NeedInput { .. } => panic!("unexpected need-input"),
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert_eq!(result.value.as_deref(), Some("3"));
+    assert_eq!(
+        result
+            .tags
+            .expect("tags echoed")
+            .into_iter()
+            .collect::<Vec<_>>(),
+        [("trace-id".to_string(), "t-42".to_string())]
+    );
+
+    let eval = server
+        .received()
+        .into_iter()
+        .find(|r| r.op() == "eval")
+        .expect("eval sent");
+    assert_eq!(eval.get("buffer"), Some("core.clj"));
+    // A tag never displaces the eval's own fields.
+    assert_eq!(eval.get("code"), Some("(+ 1 2)"));
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_eval_output_is_reported_as_it_arrives() {
+    use nrepl_rs::testing::{Frame, MockNReplServer, Reply, standard_replies};
+    use nrepl_rs::worker::WorkerConfig;
+    use nrepl_rs::{ChunkKind, ServerOutput};
+    use std::sync::{Arc, Mutex};
+
+    let server = MockNReplServer::start(|request| match request.op() {
+        "eval" => vec![
+            Reply::Frame(Frame::reply_to(request).str("out", "working\n")),
+            Reply::Frame(Frame::reply_to(request).str("err", "WARNING: slow\n")),
+            Reply::Frame(Frame::reply_to(request).str("value", "nil").done()),
+        ],
+        _ => standard_replies(request),
+    })
+    .expect("start mock server");
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let config = WorkerConfig::default().on_eval_output({
+        let seen = Arc::clone(&seen);
+        move |id, chunk: &ServerOutput| {
+            seen.lock()
+                .unwrap()
+                .push((id, chunk.kind, chunk.text.clone()))
+        }
+    });
+    let mut worker = Worker::with_config(config);
+    worker
+        .connect_blocking(server.address().to_string())
+        .expect("connect");
+    let session = common::clone_session(&worker).expect("clone");
+    let id = worker
+        .submit_eval(session.clone(), "(run)", None, None, None, None)
+        .expect("submit");
+    let result = common::eval(&mut worker, &session, "(run)").expect("eval");
+    assert_eq!(result.output, ["working\n"]);
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 4, "{seen:?}");
+    assert_eq!(seen[0], (id, ChunkKind::Stdout, "working\n".to_string()));
+    assert_eq!(
+        seen[1],
+        (id, ChunkKind::Warning, "WARNING: slow\n".to_string())
+    );
+    assert_ne!(seen[2].0, id);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_response_hook_announces_results_that_are_ready() {
+    use nrepl_rs::testing::MockNReplServer;
+    use nrepl_rs::worker::{EvalOutcome, WorkerConfig};
+    use std::sync::mpsc::channel;
+
+    let server = MockNReplServer::standard().expect("start mock server");
+    let (ready_tx, ready) = channel();
+    let config = WorkerConfig::default().on_response(move |id| {
+        let _ = ready_tx.send(id);
+    });
+    let mut worker = Worker::with_config(config);
+    worker
+        .connect_blocking(server.address().to_string())
+        .expect("connect");
+    let session = common::clone_session(&worker).expect("clone");
+    let id = worker
+        .submit_eval(session, "(+ 1 2)", None, None, None, None)
+        .expect("submit");
+
+    let announced = ready
+        .recv_timeout(Duration::from_secs(5))
+        .expect("hook called");
+    assert_eq!(announced, id);
+    // Announced only once the response can be taken, so no polling needed.
+    let response = worker.try_recv_response(id).expect("response is ready");
+    assert!(matches!(response.outcome, EvalOutcome::Done(Ok(_))));
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_any_response_collects_every_request_once() {
+    use nrepl_rs::testing::MockNReplServer;
+
+    let server = MockNReplServer::standard().expect("start mock server");
+    let mut worker = Worker::new();
+    worker
+        .connect_blocking(server.address().to_string())
+        .expect("connect");
+    let session = common::clone_session(&worker).expect("clone");
+    let first = worker
+        .submit_eval(session.clone(), "(+ 1 2)", None, None, None, None)
+        .expect("submit");
+    let second = worker
+        .submit_eval(session, "(+ 3 4)", None, None, None, None)
+        .expect("submit");
+
+    let mut seen = Vec::new();
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while seen.len() < 2 && std::time::Instant::now() < deadline {
+        match worker.try_recv_any_response() {
+            Some(response) => seen.push(response.request_id),
+            None => std::thread::sleep(Duration::from_millis(10)),
+        }
+    }
+    assert_eq!(seen, [first, second]);
+    assert!(worker.try_recv_any_response().is_none());
+    assert!(worker.try_recv_response(first).is_none());
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_reused_cancellation_token_forgets_finished_evals() {
+    use nrepl_rs::testing::MockNReplServer;
+    use nrepl_rs::worker::CancellationToken;
+
+    let server = MockNReplServer::standard().expect("start mock server");
+    let mut worker = Worker::new();
+    worker
+        .connect_blocking(server.address().to_string())
+        .expect("connect");
+    let session = common::clone_session(&worker).expect("clone");
+    let token = CancellationToken::new();
+
+    for _ in 0..50 {
+        let id = worker
+            .submit_eval(session.clone(), "(+ 1 2)", None, None, None, None)
+            .expect("submit");
+        worker.cancel_on(&token, id);
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while worker.try_recv_response(id).is_none() {
+            assert!(std::time::Instant::now() < deadline, "eval never answered");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    // Each link goes once its eval is done, however many the token saw.
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while token.linked() > 0 {
+        assert!(std::time::Instant::now() < deadline, "links never dropped");
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert!(!token.is_cancelled());
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_auth_token_is_sent_but_not_captured() {
+    use nrepl_rs::testing::MockNReplServer;
+    use nrepl_rs::worker::WorkerConfig;
+
+    let path = std::env::temp_dir().join(format!("nrepl-auth-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let server = MockNReplServer::standard().expect("start mock server");
+    let config = WorkerConfig::default()
+        .auth_token("auth", "s3cret")
+        .expect("auth is not a request field")
+        .capture_frames(&path);
+    assert!(!format!("{config:?}").contains("s3cret"));
+
+    let mut worker = Worker::with_config(config);
+    worker
+        .connect_blocking(server.address().to_string())
+        .expect("connect");
+    let session = common::clone_session(&worker).expect("clone");
+    common::eval(&mut worker, &session, "(+ 1 2)").expect("eval");
+    drop(worker);
+
+    let tokens: Vec<_> = server
+        .received()
+        .iter()
+        .map(|r| r.get("auth").map(str::to_string))
+        .collect();
+    assert_eq!(
+        tokens,
+        [Some("s3cret".to_string()), Some("s3cret".to_string())]
+    );
+    let captured = std::fs::read_to_string(&path).expect("read capture");
+    assert!(captured.contains("<redacted>"), "{captured}");
+    assert!(!captured.contains("s3cret"));
+    std::fs::remove_file(&path).expect("remove capture");
+}
+
+#[test]
+fn test_auth_token_cannot_replace_request_fields() {
+    use nrepl_rs::worker::WorkerConfig;
+
+    for key in ["op", "id", "session", "code", "file-path"] {
+        assert!(
+            matches!(
+                WorkerConfig::default().auth_token(key, "s3cret"),
+                Err(NReplError::Protocol { .. })
+            ),
+            "{key} accepted"
+        );
+    }
+    assert!(WorkerConfig::default().auth_token("auth", "s3cret").is_ok());
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_chaos_transport_duplicates_and_hangs_up() {
+    use nrepl_rs::testing::{ChaosSchedule, ChaosTransport, Fault, MockNReplServer};
+    use nrepl_rs::worker::ConnectionState;
+
+    // Frame 0 answers the clone, 1 the first eval, 2 the second.
+    let server = MockNReplServer::standard().expect("start mock server");
+    let chaos = ChaosTransport::start(
+        server.address(),
+        ChaosSchedule::seeded(7)
+            .at(1, Fault::Duplicate)
+            .at(2, Fault::Hangup),
+    )
+    .expect("start chaos transport");
+
+    let mut worker = Worker::new();
+    worker
+        .connect_blocking(chaos.address().to_string())
+        .expect("connect");
+    let session = common::clone_session(&worker).expect("clone");
+    let result = common::eval(&mut worker, &session, "1").expect("eval");
+    assert_eq!(result.value.as_deref(), Some("nil"));
+
+    // Half a frame, then nothing: the eval fails rather than hanging.
+    assert!(common::eval(&mut worker, &session, "2").is_err());
+    assert_eq!(worker.connection_state(), ConnectionState::Disconnected);
+    assert_eq!(
+        chaos.injected(),
+        [(1, Fault::Duplicate), (2, Fault::Hangup)]
+    );
+
+    // A new connection through the same transport gets through.
+    let mut worker = Worker::new();
+    worker
+        .connect_blocking(chaos.address().to_string())
+        .expect("reconnect");
+    let session = common::clone_session(&worker).expect("clone again");
+    let result = common::eval(&mut worker, &session, "3").expect("eval again");
+    assert_eq!(result.value.as_deref(), Some("nil"));
+}
+
+#[cfg(feature = "watch")]
+#[test]
+fn test_reload_watcher_loads_saved_source_files() {
+    use nrepl_rs::Session;
+    use nrepl_rs::watch::{ReloadEvent, ReloadWatcher, WatchOptions};
+    use std::time::Instant;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || serve_load_files(listener));
+    let mut worker = Worker::new();
+    worker.connect_blocking(addr).expect("connect");
+
+    let dir = std::env::temp_dir().join(format!("nrepl-watch-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("create dir");
+    let dir = dir.canonicalize().expect("canonical dir");
+    let options = WatchOptions {
+        debounce: Duration::from_millis(50),
+        ..WatchOptions::default()
+    };
+    let mut watcher =
+        ReloadWatcher::new([&dir], Session::from_server_id("s1"), options).expect("watch");
+
+    // Two quick writes reload once; the text file not at all.
+    let source = dir.join("core.clj");
+    std::fs::write(&source, "(ns core)").expect("write");
+    std::fs::write(&source, "(ns core) (def x 1)").expect("write");
+    std::fs::write(dir.join("notes.txt"), "not source").expect("write");
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut events = Vec::new();
+    while Instant::now() < deadline {
+        events.extend(watcher.poll(&mut worker));
+        if !events.is_empty() && !watcher.is_busy() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert!(
+        matches!(
+            events.as_slice(),
+            [ReloadEvent::Reloading(paths), ReloadEvent::Loaded { path, result }]
+                if *paths == vec![source.clone()]
+                    && *path == source
+                    && result.value.as_deref() == Some("core.clj")
+        ),
+        "{events:?}"
+    );
+
+    worker.shutdown();
+    let sent = server.join().expect("server thread");
+    assert_eq!(sent.matches("9:load-file").count(), 1, "{sent}");
+    assert!(sent.contains("(def x 1)"), "{sent}");
+}
+
+#[test]
+fn test_fleet_broadcast_collects_each_servers_result() {
+    use nrepl_rs::{NReplFleet, Session};
+
+    let mut fleet = NReplFleet::new();
+    let mut servers = Vec::new();
+    for key in ["backend", "bb"] {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("local addr").to_string();
+        servers.push(std::thread::spawn(move || serve_batch(listener)));
+        let worker = Worker::new();
+        worker.connect_blocking(addr).expect("connect");
+        fleet.add(key, worker, Session::from_server_id("s1"));
+    }
+    fleet.add("offline", Worker::new(), Session::from_server_id("s1"));
+
+    let results = fleet.broadcast("7", Duration::from_secs(5));
+    let values: Vec<(&str, Option<&str>)> = results
+        .iter()
+        .map(|(key, r)| {
+            (
+                key.as_str(),
+                r.as_ref().ok().and_then(|r| r.value.as_deref()),
+            )
+        })
+        .collect();
+    assert_eq!(
+        values,
+        [("backend", Some("7")), ("bb", Some("7")), ("offline", None)]
+    );
+    assert!(matches!(
+        fleet.eval("bb", "(/ 1 0)", Duration::from_secs(5)),
+        Ok(r) if r.ex.is_some()
+    ));
+
+    drop(fleet);
+    for server in servers {
+        server.join().expect("server thread");
+    }
+}
+
+#[test]
+fn test_idle_session_is_closed_and_reported() {
+    use nrepl_rs::Session;
+    use nrepl_rs::worker::WorkerConfig;
+    use std::sync::{Arc, Mutex};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || serve_batch(listener));
+
+    let expired = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&expired);
+    let config = WorkerConfig::default()
+        .session_idle_ttl(Duration::from_millis(100))
+        .on_session_expired(move |info| {
+            seen.lock().unwrap().push(info.session.id().to_string());
+        });
+    let mut worker = Worker::with_config(config);
+    worker.connect_blocking(addr).expect("connect");
+    let session = Session::from_server_id("s1");
+    let results = worker.eval_batch(&session, vec!["1".to_string()], Default::default());
+    assert!(results[0].is_ok());
+    assert_eq!(worker.sessions().len(), 1);
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while expired.lock().unwrap().is_empty() && std::time::Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(*expired.lock().unwrap(), ["s1"]);
+    assert!(worker.sessions().is_empty());
+    assert!(
+        worker
+            .debug_events()
+            .iter()
+            .any(|e| e.kind == DebugEventKind::SessionExpired)
+    );
+
+    drop(worker);
+    let sent = server.join().expect("server thread");
+    assert!(sent.contains("2:op5:close7:session2:s1"), "sent: {sent}");
+}
+
+#[test]
+fn test_reconcile_drops_sessions_the_server_lost() {
+    use nrepl_rs::Session;
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut buf).unwrap_or(0);
+            if n == 0 {
+                return;
+            }
+            let chunk = String::from_utf8_lossy(&buf[..n]).into_owned();
+            for id in request_ids(&chunk) {
+                let reply = "8:sessionsl2:s22:s3e6:statusl4:donee";
+                let _ = stream.write_all(&frame(&id, reply));
+            }
+        }
+    });
+
+    let worker = Worker::new();
+    worker.connect_blocking(addr).expect("connect");
+    // Tracked before the "restart": s1 is gone, s2 survived.
+    worker.label_session(&Session::from_server_id("s1"), Some("user".to_string()));
+    worker.label_session(&Session::from_server_id("s2"), Some("tooling".to_string()));
+
+    let changes = worker.reconcile_sessions(false).expect("reconcile");
+    assert_eq!(changes.invalidated.len(), 1);
+    assert_eq!(changes.invalidated[0].session.id(), "s1");
+    assert!(changes.adopted.is_empty());
+    let tracked: Vec<_> = worker.sessions().into_iter().map(|i| i.session).collect();
+    assert_eq!(tracked, [Session::from_server_id("s2")]);
+    assert!(
+        worker
+            .debug_events()
+            .iter()
+            .any(|e| e.kind == DebugEventKind::SessionInvalidated)
+    );
+
+    let changes = worker.reconcile_sessions(true).expect("reconcile");
+    assert_eq!(changes.adopted, [Session::from_server_id("s3")]);
+    assert_eq!(worker.sessions().len(), 2);
+
+    // A saved id the server does not list is not restored.
+    let mut saved = worker.export_sessions();
+    assert_eq!(saved[0].label.as_deref(), Some("tooling"));
+    saved.push(nrepl_rs::SessionDescriptor {
+        id: "s9".to_string(),
+        ..saved[0].clone()
+    });
+    let restored = worker.import_sessions(&saved).expect("import");
+    assert_eq!(
+        restored,
+        [Session::from_server_id("s2"), Session::from_server_id("s3")]
+    );
+
+    drop(worker);
+    server.join().expect("server thread");
+}
+
+#[test]
+fn test_cached_lookup_is_served_until_an_eval_in_its_session() {
+    use nrepl_rs::Session;
+    use nrepl_rs::worker::{BatchOptions, WorkerConfig};
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 4096];
+        let mut sent = String::new();
+        loop {
+            let n = stream.read(&mut buf).unwrap_or(0);
+            if n == 0 {
+                return sent;
+            }
+            let chunk = String::from_utf8_lossy(&buf[..n]).into_owned();
+            for id in request_ids(&chunk) {
+                let _ = stream.write_all(&frame(&id, "6:statusl4:donee"));
+            }
+            sent.push_str(&chunk);
+        }
+    });
+
+    let mut worker =
+        Worker::with_config(WorkerConfig::default().cache_responses(Duration::from_secs(60)));
+    worker.connect_blocking(addr).expect("connect");
+    let session = Session::from_server_id("s1");
+    worker.lookup(&session, "map", None).expect("lookup");
+    worker.lookup(&session, "map", None).expect("cached lookup");
+    let cache = worker.response_cache().expect("cache configured").clone();
+    assert_eq!((cache.stats().hits, cache.stats().misses), (1, 1));
+
+    // The eval may have redefined `map`: ask the server again.
+    let results = worker.eval_batch(&session, vec!["1".to_string()], BatchOptions::default());
+    assert!(results[0].is_ok());
+    worker
+        .lookup(&session, "map", None)
+        .expect("lookup after eval");
+    cache.invalidate(&session);
+    worker
+        .lookup(&session, "map", None)
+        .expect("lookup after invalidate");
+    assert_eq!((cache.stats().hits, cache.stats().misses), (1, 3));
+
+    drop(worker);
+    let sent = server.join().expect("server thread");
+    assert_eq!(sent.matches("2:op6:lookup").count(), 3, "sent: {sent}");
+}
+
+#[test]
+fn test_close_all_sessions_reports_the_ones_that_failed() {
+    use nrepl_rs::Session;
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut buf).unwrap_or(0);
+            if n == 0 {
+                return;
+            }
+            let chunk = String::from_utf8_lossy(&buf[..n]).into_owned();
+            let requests = chunk.split("d2:id").skip(1);
+            for (id, request) in request_ids(&chunk).into_iter().zip(requests) {
+                let reply = if request.contains("7:session2:s2") {
+                    "6:statusl5:error4:donee"
+                } else {
+                    "6:statusl4:donee"
+                };
+                let _ = stream.write_all(&frame(&id, reply));
+            }
+        }
+    });
+
+    let worker = Worker::new();
+    worker.connect_blocking(addr).expect("connect");
+    for id in ["s1", "s2", "s3"] {
+        worker.label_session(&Session::from_server_id(id), None);
+    }
+
+    let failed = worker.close_all_sessions();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].0, Session::from_server_id("s2"));
+    assert!(matches!(failed[0].1, NReplError::OperationFailed(_)));
+    assert!(worker.sessions().is_empty());
+
+    drop(worker);
+    server.join().expect("server thread");
+}
+
+#[test]
+fn test_abort_drops_a_hung_connection() {
+    use nrepl_rs::Session;
+    use nrepl_rs::worker::{ConnectionState, EvalOutcome};
+    use std::io::Read;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    // Reads everything, answers nothing, until the client hangs up.
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 4096];
+        while stream.read(&mut buf).unwrap_or(0) > 0 {}
+    });
+
+    let mut worker = Worker::new();
+    worker.connect_blocking(addr).expect("connect");
+    let id = worker
+        .submit_eval(
+            Session::from_server_id("s1"),
+            "(Thread/sleep 60000)".to_string(),
+            Some(Duration::from_secs(60)),
+            None,
+            None,
+            None,
+        )
+        .expect("submit");
+
+    let started = std::time::Instant::now();
+    worker.abort();
+    let response = loop {
+        if let Some(response) = worker.try_recv_response(id) {
+            break response;
+        }
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "eval never failed"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert!(matches!(
+        response.outcome,
+        EvalOutcome::Done(Err(NReplError::Connection(_)))
+    ));
+    // The server sees the socket close.
+    server.join().expect("server thread");
+    assert_eq!(worker.connection_state(), ConnectionState::Disconnected);
+}
+
+#[test]
+fn test_idempotent_op_is_retried_after_a_lost_done() {
+    use nrepl_rs::RetryPolicy;
+    use nrepl_rs::worker::WorkerConfig;
+    use std::io::{Read, Write};
+
+    // Forgets `done` on the first request, answers properly after that.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 4096];
+        let mut requests = 0;
+        loop {
+            let n = stream.read(&mut buf).unwrap_or(0);
+            if n == 0 {
+                return requests;
+            }
+            let chunk = String::from_utf8_lossy(&buf[..n]).into_owned();
+            for id in request_ids(&chunk) {
+                requests += 1;
+                let reply = if requests == 1 {
+                    "8:sessionsle"
+                } else {
+                    "8:sessionsl2:s1e6:statusl4:donee"
+                };
+                let _ = stream.write_all(&frame(&id, reply));
+            }
+        }
+    });
+
+    let policy = RetryPolicy {
+        initial_backoff: Duration::from_millis(1),
+        ..RetryPolicy::default()
+    };
+    let config = WorkerConfig::default()
+        .done_timeout(Duration::from_millis(100))
+        .retry_policy(policy);
+    let worker = Worker::with_config(config);
+    worker.connect_blocking(addr).expect("connect");
+    assert_eq!(worker.ls_sessions().expect("ls-sessions"), ["s1"]);
+
+    drop(worker);
+    assert_eq!(server.join().expect("server thread"), 2);
+}
+
+#[test]
+fn test_retries_stay_within_the_op_timeout() {
+    use nrepl_rs::Timeouts;
+    use nrepl_rs::worker::WorkerConfig;
+    use std::io::Read;
+
+    // Reads every request and answers none.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("local addr").to_string();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut buf = [0u8; 4096];
+        while stream.read(&mut buf).is_ok_and(|n| n > 0) {}
+    });
+
+    let timeout = Duration::from_millis(300);
+    let worker = Worker::with_config(WorkerConfig::default().timeouts(Timeouts::uniform(timeout)));
+    worker.connect_blocking(addr).expect("connect");
+    let started = std::time::Instant::now();
+    let result = worker.describe(false);
+    let elapsed = started.elapsed();
+    assert!(
+        matches!(result, Err(NReplError::Timeout { .. })),
+        "{result:?}"
+    );
+    // The default policy allows three attempts; together they get one
+    // timeout's worth of waiting, not three.
+    assert!(
+        elapsed < timeout + Duration::from_millis(150),
+        "waited {elapsed:?}"
+    );
+
+    drop(worker);
+    server.join().expect("server thread");
+}
+
+#[test]
+fn test_error_codes_classify_each_variant() {
+    use nrepl_rs::ErrorCode;
+    use std::io::ErrorKind;
+
+    let reset = NReplError::Connection(ErrorKind::ConnectionReset.into());
+    assert_eq!(reset.code(), ErrorCode::ConnectionLost);
+    assert!(reset.is_connection_dead());
+    assert!(!reset.is_retryable());
+
+    let slow = NReplError::Connection(ErrorKind::TimedOut.into());
+    assert_eq!(slow.code(), ErrorCode::Timeout);
+    assert!(!slow.is_connection_dead());
+    assert!(slow.is_retryable());
+
+    assert!(NReplError::NotConnected.is_connection_dead());
+    assert!(!NReplError::NotConnected.is_retryable());
+    // The variant decides, never the wording.
+    assert_eq!(
+        NReplError::protocol("Not connected").code(),
+        ErrorCode::Protocol
+    );
+
+    let timeout = NReplError::timeout("eval", Duration::from_secs(1));
+    assert_eq!(timeout.code(), ErrorCode::Timeout);
+    assert!(timeout.is_retryable());
+
+    let violation = NReplError::ProtocolViolation {
+        op: "describe".to_string(),
+        id: "req-1".to_string(),
+        waited: Duration::from_secs(1),
+    };
+    assert_eq!(violation.code(), ErrorCode::Protocol);
+    assert!(violation.is_retryable());
+
+    let cases = [
+        (NReplError::codec("bad", 0), ErrorCode::Decode, "decode"),
+        (
+            NReplError::SessionNotFound("s1".to_string()),
+            ErrorCode::SessionInvalid,
+            "session-invalid",
+        ),
+        (
+            NReplError::OperationFailed("server does not support xref".to_string()),
+            ErrorCode::ServerRejected,
+            "server-rejected",
+        ),
+        (
+            NReplError::Cancelled("req-2".to_string()),
+            ErrorCode::Cancelled,
+            "cancelled",
+        ),
+    ];
+    for (err, code, name) in cases {
+        assert_eq!(err.code(), code);
+        assert_eq!(err.code().to_string(), name);
+        assert!(!err.is_retryable());
+        assert!(!err.is_connection_dead());
+    }
+}
@@ -34,7 +34,7 @@ mod common;
 #[cfg(test)]
 mod real_server_tests {
     use crate::common;
-    use nrepl_rs::NReplError;
+    use nrepl_rs::{NReplError, SessionManager};
     use std::time::{Duration, Instant};
 
     #[test]
@@ -55,6 +55,18 @@ mod real_server_tests {
         assert!(!session.id().is_empty(), "Session ID should not be empty");
     }
 
+    #[test]
+    #[ignore = "requires a running nREPL server"]
+    fn test_clone_session_from_inherits_ns() {
+        let (mut worker, session) = common::connect();
+        common::eval(&mut worker, &session, "(ns clone.source)").expect("switch ns");
+
+        let copy = worker.clone_session_from(&session).expect("clone from");
+        assert_ne!(copy.id(), session.id());
+        let result = common::eval(&mut worker, &copy, "(str *ns*)").expect("eval in copy");
+        assert_eq!(result.value.as_deref(), Some("\"clone.source\""));
+    }
+
     #[test]
     #[ignore = "requires a running nREPL server"]
     fn test_eval_simple_expression() {
@@ -191,6 +203,7 @@ mod real_server_tests {
             NReplError::Timeout {
                 operation,
                 duration,
+                ..
             } => {
                 assert_eq!(operation, "eval", "Error should be for eval operation");
                 assert_eq!(
@@ -203,6 +216,100 @@ mod real_server_tests {
         }
     }
 
+    /// Output printed before the deadline fires is handed back on the error
+    #[test]
+    #[ignore = "requires a running nREPL server"]
+    fn test_eval_timeout_keeps_partial_output() {
+        let (mut worker, session) = common::connect();
+
+        let result = common::eval_with_timeout(
+            &mut worker,
+            &session,
+            "(do (println \"before\") (flush) (Thread/sleep 5000))",
+            Duration::from_secs(1),
+        );
+
+        let err = result.expect_err("Long-running eval should timeout");
+        let partial = err
+            .partial_result()
+            .expect("eval timeout should carry a partial result");
+        assert!(
+            partial.output.iter().any(|s| s.contains("before")),
+            "Partial result should keep output printed before the timeout, got: {:?}",
+            partial.output
+        );
+    }
+
+    /// With `interrupt_on_timeout`, a timed-out eval is stopped on the server,
+    /// so the session is free again well before the abandoned form would end.
+    #[test]
+    #[ignore = "requires a running nREPL server"]
+    fn test_eval_timeout_interrupts_server() {
+        use nrepl_rs::worker::{Worker, WorkerConfig};
+
+        let mut worker = Worker::with_config(WorkerConfig::default().interrupt_on_timeout(true));
+        worker
+            .connect_blocking(common::test_server_addr())
+            .expect("Failed to connect");
+        let session = common::clone_session(&worker).expect("Failed to clone session");
+
+        let result = common::eval_with_timeout(
+            &mut worker,
+            &session,
+            "(Thread/sleep 10000)",
+            Duration::from_secs(1),
+        );
+        assert!(result.is_err(), "Long-running eval should timeout");
+
+        // Without the interrupt this would queue behind the 10s sleep.
+        let result =
+            common::eval_with_timeout(&mut worker, &session, "(+ 1 2)", Duration::from_secs(3))
+                .expect("Session should be free after the timeout interrupt");
+        assert_eq!(result.value, Some("3".to_string()));
+    }
+
+    /// Cancelling an eval answers its caller at once and leaves the
+    /// connection usable for the next request.
+    #[test]
+    #[ignore = "requires a running nREPL server"]
+    fn test_cancelled_eval_keeps_connection_usable() {
+        use nrepl_rs::worker::{CancellationToken, EvalOutcome};
+
+        let (mut worker, session) = common::connect();
+        let token = CancellationToken::new();
+
+        let id = worker
+            .submit_eval(
+                session.clone(),
+                "(Thread/sleep 2000)".to_string(),
+                None,
+                None,
+                None,
+                None,
+            )
+            .expect("submit failed");
+        worker.cancel_on(&token, id);
+        token.cancel();
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let outcome = loop {
+            if let Some(response) = worker.try_recv_response(id) {
+                break response.outcome;
+            }
+            assert!(Instant::now() < deadline, "cancel should answer promptly");
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert!(matches!(
+            outcome,
+            EvalOutcome::Done(Err(NReplError::Cancelled(_)))
+        ));
+
+        let result =
+            common::eval_with_timeout(&mut worker, &session, "(+ 1 2)", Duration::from_secs(5))
+                .expect("connection should survive a cancel");
+        assert_eq!(result.value, Some("3".to_string()));
+    }
+
     #[test]
     #[ignore = "requires a running nREPL server"]
     fn test_eval_timeout_boundary() {
@@ -496,14 +603,9 @@ mod real_server_tests {
 
     /// Test oversized-response `DoS` protection
     ///
-    /// An 11MB response is refused rather than buffered without limit.
-    ///
-    /// Which guard fires is worth stating precisely, because it is not the one
-    /// the old name suggested: the reader tops up its buffer 4KB at a time, and
-    /// each top-up that leaves the bencode message incomplete increments a
-    /// counter capped at `MAX_INCOMPLETE_READS` (1000). That cap is reached
-    /// after about 4MB, so `MAX_RESPONSE_SIZE` (10MB) is never the guard that
-    /// trips for a single streamed response.
+    /// An 11MB response is refused rather than buffered without limit, by
+    /// `MAX_RESPONSE_SIZE` (10MB), which counts the bytes buffered however
+    /// many reads brought them.
     ///
     /// A reader error is terminal for the connection, so the worker fails every
     /// pending op with a connection error carrying the underlying message,
@@ -531,7 +633,7 @@ mod real_server_tests {
             NReplError::Connection(ref io_err) => {
                 let message = io_err.to_string();
                 assert!(
-                    message.contains("incomplete reads") || message.contains("maximum size"),
+                    message.contains("maximum size"),
                     "Error should name the read guard that tripped, got: {message}"
                 );
             }
@@ -863,6 +965,41 @@ mod real_server_tests {
     }
 
     /// Test that `ls-sessions` lists the sessions we cloned
+    /// Resync fails the in-flight eval, confirms the server is alive, and
+    /// leaves the connection ready for new work.
+    #[test]
+    #[ignore = "requires a running nREPL server"]
+    fn test_resync_fails_in_flight_and_recovers() {
+        use nrepl_rs::worker::EvalOutcome;
+
+        let (mut worker, session) = common::connect();
+        let id = worker
+            .submit_eval(
+                session.clone(),
+                "(Thread/sleep 3000)".to_string(),
+                None,
+                None,
+                None,
+                None,
+            )
+            .expect("submit failed");
+
+        let report = common::resync(&worker).expect("resync failed");
+        assert_eq!(report.requests_failed, 1);
+        assert!(report.server_alive, "server should answer describe");
+
+        let response = worker
+            .try_recv_response(id)
+            .expect("in-flight eval should be failed by the resync");
+        assert!(matches!(response.outcome, EvalOutcome::Done(Err(_))));
+
+        // The sleep is still running server-side; give it room to finish.
+        let result =
+            common::eval_with_timeout(&mut worker, &session, "(+ 1 2)", Duration::from_secs(10))
+                .expect("connection should work after resync");
+        assert_eq!(result.value, Some("3".to_string()));
+    }
+
     #[test]
     #[ignore = "requires a running nREPL server"]
     fn test_ls_sessions() {
@@ -876,6 +1013,21 @@ mod real_server_tests {
         );
     }
 
+    #[test]
+    #[ignore = "requires a running nREPL server"]
+    fn test_ls_middleware() {
+        let worker = common::connect_worker();
+
+        let stack = worker.ls_middleware(false).expect("ls-middleware failed");
+        let session = stack
+            .position("nrepl.middleware.session/session")
+            .expect("the stack should include session middleware");
+        let eval = stack
+            .position("nrepl.middleware.interruptible-eval/interruptible-eval")
+            .expect("the stack should include eval middleware");
+        assert!(eval < session, "eval should sit inside session: {stack:?}");
+    }
+
     /// Test basic completions functionality
     ///
     /// Verifies that the completions operation returns results for a simple prefix.
@@ -1078,4 +1230,62 @@ mod real_server_tests {
             std::thread::sleep(Duration::from_millis(10));
         }
     }
+
+    #[test]
+    #[ignore = "requires a running nREPL server"]
+    fn test_session_manager_resets_ns_on_checkin() {
+        let worker = common::connect_worker();
+        let mut pool = SessionManager::new(worker, 1)
+            .expect("Failed to warm pool")
+            .reset_ns("user");
+        assert_eq!(pool.idle(), 1);
+
+        let session = pool.checkout().expect("checkout failed");
+        assert_eq!(pool.idle(), 0);
+        common::eval(pool.worker(), &session, "(in-ns 'pool.scratch)").expect("in-ns failed");
+        pool.checkin(session).expect("checkin failed");
+        assert_eq!(pool.idle(), 1);
+
+        let session = pool.checkout().expect("second checkout failed");
+        let result = common::eval(pool.worker(), &session, "(str *ns*)").expect("eval failed");
+        assert_eq!(result.value, Some("\"user\"".to_string()));
+        pool.checkin(session).expect("checkin failed");
+    }
+
+    #[test]
+    #[ignore = "requires a running nREPL server"]
+    fn test_ns_aliases_cached_until_ns_is_evaluated() {
+        let (mut worker, session) = common::connect();
+        common::eval(
+            &mut worker,
+            &session,
+            "(ns alias.test (:require [clojure.string :as str] [clojure.set :refer [union]]))",
+        )
+        .expect("ns form failed");
+
+        let found = common::ns_aliases(&worker, &session, "alias.test").expect("ns-aliases failed");
+        assert_eq!(
+            found.aliases.get("str").map(String::as_str),
+            Some("clojure.string")
+        );
+        assert_eq!(
+            found.refers.get("union").map(String::as_str),
+            Some("clojure.set")
+        );
+        assert!(
+            !found.refers.contains_key("map"),
+            "clojure.core refers are left out"
+        );
+
+        // Re-evaluating the ns drops the cached entry, so a new alias shows up.
+        common::eval(&mut worker, &session, "(require '[clojure.walk :as walk])")
+            .expect("require failed");
+        let found = common::ns_aliases(&worker, &session, "alias.test").expect("ns-aliases failed");
+        assert_eq!(
+            found.aliases.get("walk").map(String::as_str),
+            Some("clojure.walk")
+        );
+
+        assert!(common::ns_aliases(&worker, &session, "no.such.ns").is_err());
+    }
 }
@@ -0,0 +1,145 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Loading cider-nrepl into a server started without it
+//!
+//! Completions, info, test running and the rest come from cider-nrepl. A
+//! plain `clojure -M -m nrepl.cmdline` server lacks them, but nREPL's
+//! dynamic loader can add middleware to a running server. Injection fetches
+//! cider-nrepl onto the server's classpath (Clojure 1.12's `add-lib`, or
+//! pomegranate when that is loaded), asks it for its middleware list, adds
+//! that with `add-middleware`, then checks `describe` for the new ops.
+
+use crate::registry::{self, ConnectionId};
+use nrepl_rs::{NReplError, Session};
+use std::time::Duration;
+
+/// Resolving and downloading cider-nrepl and its dependencies on a cold
+/// Maven cache takes a while.
+const LOAD_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// An op only cider-nrepl provides; its presence means the middleware is in.
+const CIDER_OP: &str = "info";
+
+/// Make sure the server behind `session` has cider-nrepl's middleware,
+/// loading cider-nrepl `version` if it is not on the classpath already.
+///
+/// Returns `false` if the server had it all along, `true` once injected.
+/// Fails on servers that cannot fetch libraries (no `add-lib` or
+/// pomegranate) or whose handler has no dynamic loader.
+pub fn ensure_cider_middleware(
+    conn_id: ConnectionId,
+    session: &Session,
+    version: &str,
+) -> Result<bool, NReplError> {
+    // The version is spliced into code, so only a version-like string goes.
+    if version.is_empty()
+        || !version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+    {
+        return Err(NReplError::OperationFailed(format!(
+            "not a cider-nrepl version: {version}"
+        )));
+    }
+    if has_cider_ops(conn_id)? {
+        return Ok(false);
+    }
+    let result =
+        registry::eval_blocking(conn_id, session.clone(), load_form(version), LOAD_TIMEOUT)?;
+    if let Some(ex) = result.ex {
+        let detail = result.error.concat();
+        return Err(NReplError::OperationFailed(format!(
+            "loading cider-nrepl {version} failed: {ex}: {}",
+            detail.trim_end()
+        )));
+    }
+    let middleware = middleware_names(result.value.as_deref().unwrap_or_default());
+    if middleware.is_empty() {
+        return Err(NReplError::OperationFailed(
+            "cider-nrepl listed no middleware".to_string(),
+        ));
+    }
+    registry::add_middleware_blocking(conn_id, session.clone(), middleware)?;
+    if !has_cider_ops(conn_id)? {
+        return Err(NReplError::OperationFailed(format!(
+            "cider-nrepl middleware was added but the server does not offer {CIDER_OP}"
+        )));
+    }
+    Ok(true)
+}
+
+fn has_cider_ops(conn_id: ConnectionId) -> Result<bool, NReplError> {
+    let described = registry::describe_blocking(conn_id, false)?;
+    Ok(described.ops.is_some_and(|ops| ops.contains_key(CIDER_OP)))
+}
+
+/// A form that puts cider-nrepl `version` on the classpath unless it is
+/// already there, and evaluates to its middleware names, space separated.
+fn load_form(version: &str) -> String {
+    format!(
+        r#"(do
+  (when-not (try (require 'cider.nrepl) true (catch Exception _ false))
+    (if-let [add-lib (try (requiring-resolve 'clojure.repl.deps/add-lib) (catch Exception _ nil))]
+      (with-bindings {{(resolve 'clojure.core/*repl*) true}}
+        (add-lib 'cider/cider-nrepl {{:mvn/version "{version}"}}))
+      (if-let [add-deps (try (requiring-resolve 'cemerick.pomegranate/add-dependencies) (catch Exception _ nil))]
+        (add-deps :coordinates '[[cider/cider-nrepl "{version}"]]
+                  :repositories {{"central" "https://repo1.maven.org/maven2/"
+                                  "clojars" "https://repo.clojars.org/"}})
+        (throw (ex-info "cannot fetch cider-nrepl: the server has neither Clojure 1.12's add-lib nor pomegranate" {{}}))))
+    (require 'cider.nrepl))
+  (clojure.string/join " " @(resolve 'cider.nrepl/cider-middleware)))"#
+    )
+}
+
+/// The names from [`load_form`]'s printed value.
+fn middleware_names(printed: &str) -> Vec<String> {
+    printed
+        .trim()
+        .trim_matches('"')
+        .split_whitespace()
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_form_names_version_for_both_loaders() {
+        let form = load_form("0.62.1");
+        assert!(form.contains("(add-lib 'cider/cider-nrepl {:mvn/version \"0.62.1\"})"));
+        assert!(form.contains("'[[cider/cider-nrepl \"0.62.1\"]]"));
+    }
+
+    #[test]
+    fn test_unsafe_version_refused() {
+        // Refused before anything is sent, so no connection is needed.
+        let err = ensure_cider_middleware(
+            ConnectionId::new(999),
+            &Session::from_server_id("s1"),
+            "1\" (System/exit 1) \"",
+        );
+        assert!(matches!(err, Err(NReplError::OperationFailed(_))));
+    }
+
+    #[test]
+    fn test_middleware_names_from_printed_string() {
+        assert_eq!(
+            middleware_names("\"cider.nrepl/wrap-apropos cider.nrepl/wrap-info\""),
+            ["cider.nrepl/wrap-apropos", "cider.nrepl/wrap-info"]
+        );
+        assert!(middleware_names("\"\"").is_empty());
+    }
+}
@@ -12,11 +12,22 @@
 
 //! Connection management for Steel FFI
 
-use crate::error::{SteelNReplResult, nrepl_error_to_steel, steel_error};
+use crate::cider_injection;
+use crate::error::{SteelNReplResult, nrepl_error_message, nrepl_error_to_steel, steel_error};
+use crate::presets::{self, Preset};
 use crate::registry::{self, ConnectionId, SessionId};
-use nrepl_rs::worker::{EvalOutcome, RequestId};
-use nrepl_rs::{CompletionCandidate, EvalResult, Session};
-use std::borrow::Cow;
+use crate::value::Value;
+use nrepl_rs::worker::{
+    ConnectionState, EvalOutcome, EvalResponse, InspectorAction, RequestId, TestSelection,
+};
+use nrepl_rs::{
+    AproposMatch, CljsRepl, CompletionCandidate, Dialect, EvalResult, FormOptions, FormSpan,
+    InspectorChunk, InspectorPage, MetricsSnapshot, NReplError, NsVar, RefreshReport, ServerFlavor,
+    ServerProfile, Session, StackTrace, TestOutcome, TestResults, TraceState, forms_at_with,
+    parse_ns,
+};
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::time::Duration;
 use steel::SteelErr;
 use steel::rvals::Custom;
@@ -33,167 +44,229 @@ use steel::rvals::Custom;
 /// - Small enough to prevent memory exhaustion
 const MAX_CODE_SIZE: usize = 10 * 1024 * 1024; // 10MB
 
-/// Escape a string for Steel/Scheme syntax
-/// Handles: ", \, newlines, tabs, and other common escapes
-///
-/// Uses Cow<str> to avoid allocations when no escaping is needed.
-/// Returns a borrowed reference if the string contains no special characters,
-/// otherwise returns an owned escaped string.
-fn escape_steel_string(s: &str) -> Cow<'_, str> {
-    // Check if escaping is needed
-    let needs_escape = s
-        .chars()
-        .any(|c| matches!(c, '"' | '\\' | '\n' | '\r' | '\t'));
-
-    if needs_escape {
-        // Escaping needed - build escaped string
-        let escaped: String = s
-            .chars()
-            .flat_map(|c| match c {
-                '"' => vec!['\\', '"'],
-                '\\' => vec!['\\', '\\'],
-                '\n' => vec!['\\', 'n'],
-                '\r' => vec!['\\', 'r'],
-                '\t' => vec!['\\', 't'],
-                c => vec![c],
-            })
-            .collect();
-        Cow::Owned(escaped)
-    } else {
-        // No escaping needed - return borrowed reference (zero allocation)
-        Cow::Borrowed(s)
-    }
-}
-
-/// Render a list of output strings as a Steel `(list "..." ...)` expression,
-/// escaping each string. Shared by the `Done` and `need-input` paths so both
-/// produce identically-escaped lists the Scheme reader can parse.
-fn output_list_to_steel(output: &[String]) -> String {
-    let items: Vec<String> = output
+/// Convert an `EvalResult` to a Steel hashmap with the keys `"value"`,
+/// `"output"`, `"error"`, `"ns"`, `"ex"`, `"interrupted"`, `"elapsed-ms"`,
+/// `"first-response-ms"`, `"chunks"` and `"stacktrace"`.
+/// Uses #f for false/null values (Steel is R5RS Scheme, no nil)
+fn eval_result_to_steel_hashmap(result: &EvalResult) -> Value {
+    // "error" - multiple errors joined with newlines, or #f if none
+    let error = (!result.error.is_empty()).then(|| result.error.join("\n"));
+
+    // "elapsed-ms" and "first-response-ms" - from sending the eval to its done
+    // and to its first response, or #f when not timed.
+    let timing = result.timing.as_ref();
+    let ms = |d: Option<Duration>| Value::from(d.map(|d| d.as_millis()));
+
+    // "chunks" - the same content as (kind text) pairs tagged with a stable
+    // kind name, so the plugin can map each kind to a theme scope.
+    let chunks = result
+        .chunks()
         .iter()
-        .map(|s| format!("\"{}\"", escape_steel_string(s)))
-        .collect();
-    format!("(list {})", items.join(" "))
+        .map(|c| Value::list([c.kind.as_str(), c.text]))
+        .collect::<Vec<_>>();
+
+    Value::hash([
+        ("value", result.value.as_deref().into()),
+        ("output", Value::list(&result.output)),
+        ("error", error.into()),
+        ("ns", result.ns.as_deref().into()),
+        // "ex" - the explicit exception from `ex`/`root-ex` (conformance #1).
+        // Distinct from "error" (stderr text): set only on a genuine eval
+        // error, so adapters can key off it instead of string-matching stderr.
+        ("ex", result.ex.as_deref().into()),
+        // "interrupted" - #t if the eval was interrupted (conformance #4).
+        ("interrupted", result.interrupted.into()),
+        ("elapsed-ms", ms(timing.map(|t| t.duration))),
+        (
+            "first-response-ms",
+            ms(timing.and_then(|t| t.first_response_after)),
+        ),
+        ("chunks", chunks.into()),
+        // "stacktrace" - the exception parsed out of stderr, so the plugin can
+        // offer jump-to-frame. #f when the eval didn't raise.
+        (
+            "stacktrace",
+            result.stacktrace().as_ref().map(stacktrace_to_steel).into(),
+        ),
+    ])
 }
 
-/// Convert an `EvalResult` to a Steel-readable hashmap string
-/// Returns a hash construction call: (hash 'value "..." 'output [...] 'error "..." 'ns "...")
-/// Uses #f for false/null values (Steel is R5RS Scheme, no nil)
-fn eval_result_to_steel_hashmap(result: &EvalResult) -> String {
-    let mut parts = Vec::new();
-
-    // Add 'value
-    let value_str = match &result.value {
-        Some(v) => format!("\"{}\"", escape_steel_string(v)),
-        None => "#f".to_string(),
-    };
-    parts.push(format!("'value {value_str}"));
-
-    // Add 'output as a list of strings
-    parts.push(format!("'output {}", output_list_to_steel(&result.output)));
-
-    // Add 'error - join multiple errors with newlines, or #f if none
-    let error_str = if result.error.is_empty() {
-        "#f".to_string()
-    } else {
-        format!("\"{}\"", escape_steel_string(&result.error.join("\n")))
-    };
-    parts.push(format!("'error {error_str}"));
+/// Render a [`StackTrace`] as a hash of `"class"`, `"message"`, `"frames"`
+/// (a list of hashes of `"name"`, `"file"`, `"line"`, `"ns"` and `"fn"`)
+/// and `"cause"`, with `#f` for missing frame fields and for the end of the
+/// cause chain.
+fn stacktrace_to_steel(trace: &StackTrace) -> Value {
+    let frames = trace.frames.iter().map(|f| {
+        Value::hash([
+            ("name", f.name.as_str().into()),
+            ("file", f.file.as_deref().into()),
+            ("line", f.line.into()),
+            ("ns", f.ns.as_deref().into()),
+            ("fn", f.function.as_deref().into()),
+        ])
+    });
+    Value::hash([
+        ("class", trace.class.as_str().into()),
+        ("message", trace.message.as_str().into()),
+        ("frames", Value::list(frames)),
+        (
+            "cause",
+            trace.cause.as_deref().map(stacktrace_to_steel).into(),
+        ),
+    ])
+}
 
-    // Add 'ns
-    let ns_str = match &result.ns {
-        Some(n) => format!("\"{}\"", escape_steel_string(n)),
-        None => "#f".to_string(),
-    };
-    parts.push(format!("'ns {ns_str}"));
-
-    // Add 'ex - the explicit exception from `ex`/`root-ex` (conformance #1).
-    // Distinct from 'error (stderr text): set only on a genuine eval error, so
-    // adapters can key off it instead of string-matching stderr.
-    let ex_str = match &result.ex {
-        Some(e) => format!("\"{}\"", escape_steel_string(e)),
-        None => "#f".to_string(),
-    };
-    parts.push(format!("'ex {ex_str}"));
+/// Render a test run as a hash of `"passed"`, `"summary"` (`"namespaces"`,
+/// `"vars"`, `"tests"`, `"pass"`, `"fail"`, `"error"`) and `"results"`, with
+/// one hash of `"ns"`, `"var"`, `"type"`, `"index"`, `"message"`,
+/// `"expected"`, `"actual"`, `"diffs"`, `"file"`, `"line"`, `"context"` and
+/// `"error"` per assertion, flattened in namespace and var order. `"type"` is
+/// `"pass"`, `"fail"` or `"error"`; missing fields are `#f`.
+fn format_test_results(results: &TestResults) -> Value {
+    let assertions = results.assertions().map(|a| {
+        let kind = match a.outcome {
+            TestOutcome::Pass => "pass",
+            TestOutcome::Fail => "fail",
+            TestOutcome::Error => "error",
+        };
+        let diffs = a.diffs.iter().map(|d| {
+            Value::hash([
+                ("actual", d.actual.as_str().into()),
+                ("removed", d.removed.as_str().into()),
+                ("added", d.added.as_str().into()),
+            ])
+        });
+        Value::hash([
+            ("ns", a.ns.as_str().into()),
+            ("var", a.var.as_str().into()),
+            ("type", kind.into()),
+            ("index", a.index.into()),
+            ("message", a.message.as_deref().into()),
+            ("expected", a.expected.as_deref().into()),
+            ("actual", a.actual.as_deref().into()),
+            ("diffs", Value::list(diffs)),
+            ("file", a.file.as_deref().into()),
+            ("line", a.line.into()),
+            ("context", a.context.as_deref().into()),
+            ("error", a.error.as_deref().into()),
+        ])
+    });
+    let summary = &results.summary;
+    Value::hash([
+        ("passed", results.passed().into()),
+        (
+            "summary",
+            Value::hash([
+                ("namespaces", summary.namespaces.into()),
+                ("vars", summary.vars.into()),
+                ("tests", summary.tests.into()),
+                ("pass", summary.pass.into()),
+                ("fail", summary.fail.into()),
+                ("error", summary.error.into()),
+            ]),
+        ),
+        ("results", Value::list(assertions)),
+    ])
+}
 
-    // Add 'interrupted - #t if the eval was interrupted (conformance #4).
-    parts.push(format!(
-        "'interrupted {}",
-        if result.interrupted { "#t" } else { "#f" }
-    ));
+/// Format apropos matches as a list of hashes of `"ns"`, `"name"`, `"type"`
+/// and `"doc"`, with `#f` for a missing namespace or docstring.
+fn format_apropos(matches: &[AproposMatch]) -> Value {
+    Value::list(matches.iter().map(|m| {
+        Value::hash([
+            ("ns", m.ns.as_deref().into()),
+            ("name", m.name.as_str().into()),
+            ("type", m.kind.as_str().into()),
+            ("doc", m.doc.as_deref().into()),
+        ])
+    }))
+}
 
-    format!("(hash {})", parts.join(" "))
+/// Format a namespace's vars as a list of hashes of `"name"`, `"arglists"`,
+/// `"doc"` and `"macro"`, with `#f` for missing arglists or docstring.
+fn format_ns_vars(vars: &[NsVar]) -> Value {
+    Value::list(vars.iter().map(|v| {
+        Value::hash([
+            ("name", v.name.as_str().into()),
+            ("arglists", v.arglists().into()),
+            ("doc", v.doc().as_deref().into()),
+            ("macro", v.is_macro().into()),
+        ])
+    }))
 }
 
-/// Format completion candidates as a Steel list of hashmaps:
-/// `(list (hash '#:candidate "map" '#:ns "clojure.core" '#:type "function") ...)`
-/// Missing fields are `#f`. Shared by the blocking and submit/poll paths so
-/// both emit the same FFI grammar.
-fn format_completions(completions: &[CompletionCandidate]) -> String {
-    let completion_items: Vec<String> = completions
-        .iter()
-        .map(|c| {
-            let mut parts = Vec::new();
-
-            // Always include candidate
-            parts.push(format!(
-                "'#:candidate \"{}\"",
-                escape_steel_string(&c.candidate)
-            ));
-
-            // Include namespace if present
-            if let Some(ns) = &c.ns {
-                parts.push(format!("'#:ns \"{}\"", escape_steel_string(ns)));
-            } else {
-                parts.push("'#:ns #f".to_string());
-            }
+/// Render a reload as a hash of `"reloading"` (a list of namespaces) and
+/// `"error"`, which is `#f` on success, else a hash of `"ns"`, `"trace"` and
+/// `"err"` naming the namespace that failed (or `#f`) and its stack trace (or
+/// `#f`).
+fn format_refresh_report(report: &RefreshReport) -> Value {
+    let error = report.error.as_ref().map(|e| {
+        Value::hash([
+            ("ns", e.ns.as_deref().into()),
+            ("trace", e.trace.as_ref().map(stacktrace_to_steel).into()),
+            ("err", e.err.as_str().into()),
+        ])
+    });
+    Value::hash([
+        ("reloading", Value::list(&report.reloading)),
+        ("error", error.into()),
+    ])
+}
 
-            // Include type if present
-            if let Some(ctype) = &c.candidate_type {
-                parts.push(format!("'#:type \"{}\"", escape_steel_string(ctype)));
-            } else {
-                parts.push("'#:type #f".to_string());
+/// Format an inspector view as a hash of `"lines"`, `"path"` and `"page"`
+/// (`"current"`, `"total"`, `"size"`). A line is a list of strings and value
+/// references, hashes of `"value"` and `"index"`; `"path"` and `"page"` are
+/// `#f` when absent.
+fn format_inspector_page(page: &InspectorPage) -> Value {
+    let lines = page.lines.iter().map(|line| {
+        Value::list(line.iter().map(|chunk| match chunk {
+            InspectorChunk::Text(text) => text.into(),
+            InspectorChunk::Value { text, index } => {
+                Value::hash([("value", text.into()), ("index", (*index).into())])
             }
-
-            format!("(hash {})", parts.join(" "))
-        })
-        .collect();
-
-    format!("(list {})", completion_items.join(" "))
+        }))
+    });
+    let paging = page.page.map(|p| {
+        Value::hash([
+            ("current", p.current.into()),
+            ("total", p.total.into()),
+            ("size", p.size.into()),
+        ])
+    });
+    Value::hash([
+        ("lines", Value::list(lines)),
+        ("path", page.path.as_deref().into()),
+        ("page", paging.into()),
+    ])
 }
 
-/// A lookup info key is emitted as a Steel keyword (`'#:key`), so it must be
-/// a single reader token. Restrict to characters that cannot terminate or
-/// corrupt the token; entries with other keys are skipped (the Scheme side
-/// could never parse them).
-fn is_steel_keyword_safe(key: &str) -> bool {
-    !key.is_empty()
-        && key.chars().all(|c| {
-            c.is_ascii_alphanumeric()
-                || matches!(
-                    c,
-                    '-' | '_' | '+' | '*' | '/' | '.' | '<' | '>' | '=' | '!' | '?'
-                )
-        })
+/// Format completion candidates as a list of hashes of `"candidate"`, `"ns"`
+/// and `"type"`. Missing fields are `#f`. Shared by the blocking and
+/// submit/poll paths so both return the same shape.
+fn format_completions(completions: &[CompletionCandidate]) -> Value {
+    Value::list(completions.iter().map(|c| {
+        Value::hash([
+            ("candidate", c.candidate.as_str().into()),
+            ("ns", c.ns.as_deref().into()),
+            ("type", c.candidate_type.as_deref().into()),
+        ])
+    }))
 }
 
-/// Format a lookup response's info map as a Steel hash of keyword keys:
-/// `(hash '#:doc "..." '#:ns "..." ...)`, or `(hash )` when the server sent
-/// no info. Shared by the blocking and submit/poll paths.
-fn format_lookup_info(info: Option<&std::collections::BTreeMap<String, String>>) -> String {
-    let mut parts = Vec::new();
-
-    if let Some(info) = info {
-        for (key, value) in info {
-            if !is_steel_keyword_safe(key) {
-                continue;
-            }
-            let value_escaped = escape_steel_string(value);
-            parts.push(format!("'#:{key} \"{value_escaped}\""));
-        }
-    }
+/// Format a lookup response's info map as a hash from each key the server
+/// sent (`"doc"`, `"ns"`, `"arglists"` ...) to its value, empty when the
+/// server sent no info. Shared by the blocking and submit/poll paths.
+fn format_lookup_info(info: Option<&BTreeMap<String, String>>) -> Value {
+    Value::hash(
+        info.into_iter()
+            .flatten()
+            .map(|(key, value)| (key.as_str(), value.into())),
+    )
+}
 
-    format!("(hash {})", parts.join(" "))
+/// A hash from string to string, as for namespace aliases.
+fn string_hash<'a>(entries: impl IntoIterator<Item = (&'a String, &'a String)>) -> Value {
+    Value::hash(entries.into_iter().map(|(k, v)| (k.as_str(), v.into())))
 }
 
 /// A handle to an nREPL session that can be used from Steel
@@ -303,6 +376,58 @@ impl NReplSession {
         )
     }
 
+    /// Submit an eval in namespace `ns` (non-blocking, returns request ID
+    /// immediately). The session stays in `ns` afterwards. Poll with
+    /// `try-get-result` as for `eval-with-timeout`.
+    ///
+    /// Usage: (define req-id (nrepl-eval-in-ns session "app.core" "(run)" 5000))
+    pub fn eval_in_ns(
+        &mut self,
+        ns: &str,
+        code: &str,
+        timeout_ms: usize,
+    ) -> SteelNReplResult<usize> {
+        check_payload(
+            code,
+            "Cannot evaluate empty code. Provide non-empty code to evaluate.",
+            "Code",
+        )?;
+        let session = self.session()?;
+        let request_id = registry::submit_eval_in_ns(
+            self.conn_id,
+            &session,
+            ns,
+            code.to_string(),
+            Some(Duration::from_millis(timeout_ms as u64)),
+        )
+        .ok_or_else(|| connection_not_found(self.conn_id))?
+        .map_err(|e| steel_error(e.to_string()))?;
+        Ok(request_id.as_usize())
+    }
+
+    /// The namespace this session is in, as of its latest eval reply, or
+    /// `#f` when not yet known. Never blocks on the server.
+    ///
+    /// Usage: (nrepl-session-ns session)
+    pub fn current_ns(&self) -> SteelNReplResult<Option<String>> {
+        let session = self.session()?;
+        registry::session_ns(self.conn_id, &session)
+            .ok_or_else(|| connection_not_found(self.conn_id))
+    }
+
+    /// Name this session's role ("user REPL", "test runner") for
+    /// `nrepl-sessions` to show, or clear it with `#f`.
+    ///
+    /// Usage: (nrepl-label-session session "tooling")
+    pub fn set_label(&self, label: Option<String>) -> SteelNReplResult<()> {
+        let session = self.session()?;
+        if registry::label_session(self.conn_id, &session, label) {
+            Ok(())
+        } else {
+            Err(connection_not_found(self.conn_id))
+        }
+    }
+
     /// Submit a load-file request (non-blocking, returns request ID immediately)
     ///
     /// Loads file contents with optional file path and name for better error messages.
@@ -361,14 +486,38 @@ impl NReplSession {
         Ok(request_id.as_usize())
     }
 
+    /// Submit a completions request for a prefix that may be alias-qualified
+    /// (non-blocking). For `"str/sta"` the alias is resolved in `ns` first and
+    /// candidates come back re-qualified (`"str/starts-with?"`). Polled with
+    /// `try-get-completions` and single-flight with `submit-completions`.
+    ///
+    /// Usage: (define req-id (session.submit-aliased-completions "str/sta" "user" #f))
+    pub fn submit_aliased_completions(
+        &self,
+        prefix: &str,
+        ns: Option<String>,
+        complete_fn: Option<String>,
+    ) -> SteelNReplResult<usize> {
+        let session = self.session()?;
+        let request_id = registry::submit_aliased_completions(
+            self.conn_id,
+            session,
+            prefix.to_string(),
+            ns,
+            complete_fn,
+        )
+        .map_err(nrepl_error_to_steel)?;
+        Ok(request_id.as_usize())
+    }
+
     /// Try to get a submitted completions result (non-blocking).
     ///
-    /// Returns #f while pending; the formatted candidate list (same shape as
-    /// `completions`) when ready. Errors once the request was superseded or
-    /// the connection closed, so poll loops terminate.
+    /// Returns #f while pending; the list of candidate hashes (see
+    /// [`format_completions`]) when ready. Errors once the request was
+    /// superseded or the connection closed, so poll loops terminate.
     ///
     /// Usage: (session.try-get-completions req-id)
-    pub fn try_get_completions(&self, request_id: usize) -> SteelNReplResult<Option<String>> {
+    pub fn try_get_completions(&self, request_id: usize) -> SteelNReplResult<Option<Value>> {
         let candidates = registry::try_get_completions(self.conn_id, RequestId::new(request_id))
             .map_err(nrepl_error_to_steel)?;
         Ok(candidates.map(|c| format_completions(&c)))
@@ -394,17 +543,353 @@ impl NReplSession {
 
     /// Try to get a submitted lookup result (non-blocking).
     ///
-    /// Returns #f while pending; the formatted info hash (same shape as
-    /// `lookup`) when ready. Errors once the request was superseded or the
-    /// connection closed.
+    /// Returns #f while pending; the info hash (see [`format_lookup_info`])
+    /// when ready. Errors once the request was superseded or the connection
+    /// closed.
     ///
     /// Usage: (session.try-get-lookup req-id)
-    pub fn try_get_lookup(&self, request_id: usize) -> SteelNReplResult<Option<String>> {
+    pub fn try_get_lookup(&self, request_id: usize) -> SteelNReplResult<Option<Value>> {
         let response = registry::try_get_lookup(self.conn_id, RequestId::new(request_id))
             .map_err(nrepl_error_to_steel)?;
         Ok(response.map(|r| format_lookup_info(r.info.as_ref())))
     }
 
+    /// Search loaded vars whose name matches the regex `query` (cider-nrepl),
+    /// across all namespaces or only `search-ns`. `docs` also matches
+    /// docstrings; `privates` includes private vars. Returns a list of hashes
+    /// of `"ns"`, `"name"`, `"type"` and `"doc"`.
+    ///
+    /// Usage: (session.apropos "map" #f #f #f)
+    pub fn apropos(
+        &self,
+        query: &str,
+        search_ns: Option<String>,
+        docs: bool,
+        privates: bool,
+    ) -> SteelNReplResult<Value> {
+        let session = self.session()?;
+        let matches = registry::apropos_blocking(
+            self.conn_id,
+            session,
+            query.to_string(),
+            search_ns,
+            docs,
+            privates,
+        )
+        .map_err(nrepl_error_to_steel)?;
+        Ok(format_apropos(&matches))
+    }
+
+    /// Every loaded namespace, sorted, as a list of strings (cider-nrepl).
+    ///
+    /// Usage: (session.ns-list)
+    pub fn ns_list(&self) -> SteelNReplResult<Value> {
+        let session = self.session()?;
+        let namespaces =
+            registry::ns_list_blocking(self.conn_id, session).map_err(nrepl_error_to_steel)?;
+        Ok(namespaces.into())
+    }
+
+    /// The public vars of `ns`, sorted, as a list of hashes of `"name"`,
+    /// `"arglists"`, `"doc"` and `"macro"` (cider-nrepl).
+    ///
+    /// Usage: (session.ns-vars "clojure.string")
+    pub fn ns_vars(&self, ns: &str) -> SteelNReplResult<Value> {
+        let session = self.session()?;
+        let vars = registry::ns_vars_blocking(self.conn_id, session, ns.to_string())
+            .map_err(nrepl_error_to_steel)?;
+        Ok(format_ns_vars(&vars))
+    }
+
+    /// The source file of `ns` (a path, or a `jar:` URL), or #f if it has
+    /// none (cider-nrepl).
+    ///
+    /// Usage: (session.ns-path "my.app")
+    pub fn ns_path(&self, ns: &str) -> SteelNReplResult<Option<String>> {
+        let session = self.session()?;
+        registry::ns_path_blocking(self.conn_id, session, ns.to_string())
+            .map_err(nrepl_error_to_steel)
+    }
+
+    /// The server's classpath, as a list of strings (cider-nrepl).
+    ///
+    /// Usage: (session.classpath)
+    pub fn classpath(&self) -> SteelNReplResult<Value> {
+        let session = self.session()?;
+        let entries =
+            registry::classpath_blocking(self.conn_id, session).map_err(nrepl_error_to_steel)?;
+        Ok(entries.into())
+    }
+
+    /// A path the editor can open for `file` as `info` or `ns-path` report
+    /// it. Library source inside a jar is extracted to a temp file, found
+    /// along the server's classpath when `file` is a bare relative path.
+    ///
+    /// Usage: (session.source-file "clojure/core.clj")
+    pub fn source_file(&self, file: &str) -> SteelNReplResult<String> {
+        let session = self.session()?;
+        let classpath =
+            registry::classpath_blocking(self.conn_id, session).map_err(nrepl_error_to_steel)?;
+        let path = nrepl_rs::resolve_source(file, &classpath).map_err(nrepl_error_to_steel)?;
+        Ok(path.display().to_string())
+    }
+
+    /// Remove `sym`, as resolved in `ns`: a var defined there, or an alias or
+    /// refer (cider-nrepl).
+    ///
+    /// Usage: (session.undef "old-name" "my.app")
+    pub fn undef(&self, sym: &str, ns: &str) -> SteelNReplResult<()> {
+        let session = self.session()?;
+        registry::undef_blocking(self.conn_id, session, ns.to_string(), sym.to_string())
+            .map_err(nrepl_error_to_steel)
+    }
+
+    /// Remove every public var in `ns`, listing them with `ns-vars` and
+    /// undefining each, which works on servers without `undef-all`. Returns
+    /// the removed names as a list of strings.
+    ///
+    /// Usage: (session.undef-all "my.app")
+    pub fn undef_all(&self, ns: &str) -> SteelNReplResult<Value> {
+        let session = self.session()?;
+        let vars = registry::ns_vars_blocking(self.conn_id, session.clone(), ns.to_string())
+            .map_err(nrepl_error_to_steel)?;
+        let mut removed = Vec::with_capacity(vars.len());
+        for var in vars {
+            registry::undef_blocking(
+                self.conn_id,
+                session.clone(),
+                ns.to_string(),
+                var.name.clone(),
+            )
+            .map_err(nrepl_error_to_steel)?;
+            removed.push(var.name);
+        }
+        Ok(removed.into())
+    }
+
+    /// Trace or untrace `sym`, as resolved in `ns`, returning #t if it is now
+    /// traced. Calls to it then print their arguments and result as output
+    /// of whichever eval makes them (cider-nrepl).
+    ///
+    /// Usage: (session.toggle-trace-var "handler" "my.app")
+    pub fn toggle_trace_var(&self, sym: &str, ns: &str) -> SteelNReplResult<bool> {
+        let session = self.session()?;
+        let trace = registry::toggle_trace_var_blocking(
+            self.conn_id,
+            session,
+            ns.to_string(),
+            sym.to_string(),
+        )
+        .map_err(nrepl_error_to_steel)?;
+        Ok(trace.state == TraceState::Traced)
+    }
+
+    /// Trace or untrace every function in `ns`, returning #t if it is now
+    /// traced (cider-nrepl).
+    ///
+    /// Usage: (session.toggle-trace-ns "my.app")
+    pub fn toggle_trace_ns(&self, ns: &str) -> SteelNReplResult<bool> {
+        let session = self.session()?;
+        let state = registry::toggle_trace_ns_blocking(self.conn_id, session, ns.to_string())
+            .map_err(nrepl_error_to_steel)?;
+        Ok(state == TraceState::Traced)
+    }
+
+    /// Format Clojure source with cljfmt on the server (cider-nrepl),
+    /// returning the formatted text. Code that does not read is an error
+    /// carrying the server's message.
+    ///
+    /// Usage: (session.format-code "(defn f [x]\n(inc x))")
+    pub fn format_code(&self, code: &str) -> SteelNReplResult<String> {
+        check_payload(code, "Cannot format empty code", "Code")?;
+        let session = self.session()?;
+        registry::format_code_blocking(self.conn_id, session, code.to_string())
+            .map_err(nrepl_error_to_steel)
+    }
+
+    /// Pretty-print EDN on the server (cider-nrepl), within `right-margin`
+    /// columns, or the server's default when #f.
+    ///
+    /// Usage: (session.format-edn "{:a 1 :b [1 2 3]}" 40)
+    pub fn format_edn(&self, edn: &str, right_margin: Option<usize>) -> SteelNReplResult<String> {
+        check_payload(edn, "Cannot format empty EDN", "EDN")?;
+        let session = self.session()?;
+        let right_margin = right_margin
+            .map(|m| {
+                u32::try_from(m)
+                    .map_err(|_| steel_error(format!("Right margin {m} is out of range")))
+            })
+            .transpose()?;
+        registry::format_edn_blocking(self.conn_id, session, edn.to_string(), right_margin)
+            .map_err(nrepl_error_to_steel)
+    }
+
+    /// Reload the namespaces changed since the last refresh, and those that
+    /// depend on them, or every namespace with `all` (cider-nrepl). Returns a
+    /// hash of `"reloading"` and `"error"`; `"error"` names the namespace that
+    /// stopped the reload.
+    ///
+    /// Usage: (session.refresh #f)
+    pub fn refresh(&self, all: bool) -> SteelNReplResult<Value> {
+        let session = self.session()?;
+        let report =
+            registry::refresh_blocking(self.conn_id, session, all).map_err(nrepl_error_to_steel)?;
+        Ok(format_refresh_report(&report))
+    }
+
+    /// Forget the refresh tracker's state, after a reload that failed.
+    ///
+    /// Usage: (session.refresh-clear)
+    pub fn refresh_clear(&self) -> SteelNReplResult<()> {
+        let session = self.session()?;
+        registry::refresh_clear_blocking(self.conn_id, session).map_err(nrepl_error_to_steel)
+    }
+
+    /// Evaluate `code` (in `ns`, or the session's namespace when #f) and open
+    /// the inspector on the result (cider-nrepl). Returns the view as a hash
+    /// of `"lines"`, `"path"` and `"page"`; the other `inspect-*` functions
+    /// move it and return the new view.
+    ///
+    /// Usage: (session.inspect "(range 100)" #f)
+    pub fn inspect(&self, code: &str, ns: Option<String>) -> SteelNReplResult<Value> {
+        check_payload(code, "Cannot inspect empty code", "Code")?;
+        let session = self.session()?;
+        let page = registry::inspect_blocking(self.conn_id, session, code.to_string(), ns)
+            .map_err(nrepl_error_to_steel)?;
+        Ok(format_inspector_page(&page))
+    }
+
+    /// Inspect the value rendered with `index` in the current view.
+    ///
+    /// Usage: (session.inspect-push 2)
+    pub fn inspect_push(&self, index: usize) -> SteelNReplResult<Value> {
+        let index = u32::try_from(index)
+            .map_err(|_| steel_error(format!("Inspector index {index} is out of range")))?;
+        self.inspector(InspectorAction::Push(index))
+    }
+
+    /// Return to the value the current one was reached from.
+    ///
+    /// Usage: (session.inspect-pop)
+    pub fn inspect_pop(&self) -> SteelNReplResult<Value> {
+        self.inspector(InspectorAction::Pop)
+    }
+
+    /// Re-render the current value.
+    ///
+    /// Usage: (session.inspect-refresh)
+    pub fn inspect_refresh(&self) -> SteelNReplResult<Value> {
+        self.inspector(InspectorAction::Refresh)
+    }
+
+    /// Show the next page of a large collection.
+    ///
+    /// Usage: (session.inspect-next-page)
+    pub fn inspect_next_page(&self) -> SteelNReplResult<Value> {
+        self.inspector(InspectorAction::NextPage)
+    }
+
+    /// Show the previous page of a large collection.
+    ///
+    /// Usage: (session.inspect-prev-page)
+    pub fn inspect_prev_page(&self) -> SteelNReplResult<Value> {
+        self.inspector(InspectorAction::PrevPage)
+    }
+
+    /// Show `size` items per page.
+    ///
+    /// Usage: (session.inspect-set-page-size 50)
+    pub fn inspect_set_page_size(&self, size: usize) -> SteelNReplResult<Value> {
+        let size = u32::try_from(size)
+            .ok()
+            .filter(|&s| s > 0)
+            .ok_or_else(|| steel_error(format!("Page size {size} is out of range")))?;
+        self.inspector(InspectorAction::SetPageSize(size))
+    }
+
+    fn inspector(&self, action: InspectorAction) -> SteelNReplResult<Value> {
+        let session = self.session()?;
+        let page = registry::inspector_blocking(self.conn_id, session, action)
+            .map_err(nrepl_error_to_steel)?;
+        Ok(format_inspector_page(&page))
+    }
+
+    /// Submit a run of the tests in `ns` (non-blocking, returns request ID
+    /// immediately). `vars` names the tests to run, separated by whitespace,
+    /// or is empty to run the whole namespace. Poll with `try-get-tests`.
+    /// Single-flight per connection, shared with `submit-test-all` and
+    /// `submit-retest`. Needs cider-nrepl.
+    ///
+    /// Usage: (define req-id (session.submit-tests "my.app-test" "test-add"))
+    pub fn submit_tests(&self, ns: &str, vars: &str) -> SteelNReplResult<usize> {
+        self.submit_test_selection(TestSelection::Namespace {
+            ns: ns.to_string(),
+            vars: vars.split_whitespace().map(str::to_string).collect(),
+        })
+    }
+
+    /// Submit a run of every loaded test namespace, loading all project
+    /// namespaces first when `load-all` is true. Polled with `try-get-tests`.
+    ///
+    /// Usage: (define req-id (session.submit-test-all #t))
+    pub fn submit_test_all(&self, load_all: bool) -> SteelNReplResult<usize> {
+        self.submit_test_selection(TestSelection::All { load_all })
+    }
+
+    /// Submit a re-run of the tests that failed or errored in this session's
+    /// last run. Polled with `try-get-tests`.
+    ///
+    /// Usage: (define req-id (session.submit-retest))
+    pub fn submit_retest(&self) -> SteelNReplResult<usize> {
+        self.submit_test_selection(TestSelection::Failed)
+    }
+
+    fn submit_test_selection(&self, selection: TestSelection) -> SteelNReplResult<usize> {
+        let session = self.session()?;
+        let request_id = registry::submit_tests(self.conn_id, session, selection)
+            .map_err(nrepl_error_to_steel)?;
+        Ok(request_id.as_usize())
+    }
+
+    /// Try to get a submitted test run's results (non-blocking).
+    ///
+    /// Returns #f while pending; when ready, a hash of `"passed"`,
+    /// `"summary"` and `"results"` (see [`format_test_results`]). Errors once
+    /// the request was superseded or the connection closed, or if the run
+    /// failed (an unknown namespace, a namespace that would not load).
+    ///
+    /// Usage: (session.try-get-tests req-id)
+    pub fn try_get_tests(&self, request_id: usize) -> SteelNReplResult<Option<Value>> {
+        let results = registry::try_get_tests(self.conn_id, RequestId::new(request_id))
+            .map_err(nrepl_error_to_steel)?;
+        Ok(results.as_ref().map(format_test_results))
+    }
+
+    /// The stack trace of the erroring assertion at `index` in `ns`/`var`
+    /// from this session's last test run, as a hash of `"class"`, `"message"`,
+    /// `"frames"` and `"cause"`, or #f if there is none.
+    ///
+    /// Usage: (session.test-stacktrace "my.app-test" "test-add" 0)
+    pub fn test_stacktrace(
+        &self,
+        ns: &str,
+        var: &str,
+        index: usize,
+    ) -> SteelNReplResult<Option<Value>> {
+        let session = self.session()?;
+        let index = u32::try_from(index)
+            .map_err(|_| steel_error(format!("Test index {index} is out of range")))?;
+        let trace = registry::test_stacktrace_blocking(
+            self.conn_id,
+            session,
+            ns.to_string(),
+            var.to_string(),
+            index,
+        )
+        .map_err(nrepl_error_to_steel)?;
+        Ok(trace.as_ref().map(stacktrace_to_steel))
+    }
+
     /// Interrupt the in-flight eval with the given steel request id.
     ///
     /// Method form taking the session handle (the shape Steel uses, like
@@ -428,6 +913,83 @@ impl NReplSession {
         nrepl_stdin(self.conn_id.as_usize(), self.session_id.as_usize(), data)
     }
 
+    /// Apply a named settings preset (`"beginner"`, `"data"`, `"raw"` or
+    /// `"safe"`) to this session, verifying each setting took.
+    ///
+    /// **Blocking:** evaluates a handful of `set!` forms in sequence, each
+    /// bounded by a 10 second timeout.
+    ///
+    /// Usage: (session.apply-preset "beginner")
+    pub fn apply_preset(&self, name: &str) -> SteelNReplResult<()> {
+        let preset = Preset::from_name(name).ok_or_else(|| {
+            let names: Vec<&str> = Preset::ALL.iter().map(|p| p.name()).collect();
+            steel_error(format!(
+                "Unknown preset '{name}'. Available presets: {}",
+                names.join(", ")
+            ))
+        })?;
+        let session = self.session()?;
+        presets::apply_preset(self.conn_id, &session, preset).map_err(nrepl_error_to_steel)
+    }
+
+    /// Turn this session into a ClojureScript REPL. `tool` is `"shadow"`,
+    /// with `arg` the shadow-cljs build id (`"app"`), or `"piggieback"`, with
+    /// `arg` a form building the REPL environment
+    /// (`"(cljs.repl.node/repl-env)"`). Evaluate `:cljs/quit` to go back.
+    ///
+    /// Usage: (session.upgrade-cljs "shadow" "app")
+    pub fn upgrade_cljs(&self, tool: &str, arg: &str) -> SteelNReplResult<()> {
+        let repl = match tool {
+            "shadow" => CljsRepl::Shadow {
+                build: arg.to_string(),
+            },
+            "piggieback" => CljsRepl::Piggieback {
+                repl_env: arg.to_string(),
+            },
+            _ => {
+                return Err(steel_error(format!(
+                    "Unknown ClojureScript tool '{tool}'. Available tools: shadow, piggieback"
+                )));
+            }
+        };
+        let session = self.session()?;
+        registry::upgrade_cljs_blocking(self.conn_id, session, repl).map_err(nrepl_error_to_steel)
+    }
+
+    /// Make sure the server has cider-nrepl's middleware, fetching cider-nrepl
+    /// `version` and adding it to the running server when it is missing.
+    /// Returns #t if it was injected, #f if the server already had it.
+    ///
+    /// **Blocking:** fetching cider-nrepl can take minutes on a cold Maven
+    /// cache. Needs Clojure 1.12 (`add-lib`) or pomegranate on the server.
+    ///
+    /// Usage: (session.ensure-cider-middleware "0.62.1")
+    pub fn ensure_cider_middleware(&self, version: &str) -> SteelNReplResult<bool> {
+        let session = self.session()?;
+        cider_injection::ensure_cider_middleware(self.conn_id, &session, version)
+            .map_err(nrepl_error_to_steel)
+    }
+
+    /// Get `ns`'s aliases and referred vars, for resolving qualified symbols.
+    ///
+    /// **Blocking:** answered from the worker's cache when `ns` has not been
+    /// evaluated since the last query, otherwise one round trip (up to 30s).
+    ///
+    /// Returns a hash of `"aliases"` (alias to namespace, `"str"` to
+    /// `"clojure.string"`) and `"refers"` (var to namespace, `"union"` to
+    /// `"clojure.set"`).
+    ///
+    /// Usage: (session.ns-aliases "my.app.core")
+    pub fn ns_aliases(&self, ns: &str) -> SteelNReplResult<Value> {
+        let session = self.session()?;
+        let found = registry::ns_aliases_blocking(self.conn_id, session, ns.to_string())
+            .map_err(nrepl_error_to_steel)?;
+        Ok(Value::hash([
+            ("aliases", string_hash(&found.aliases)),
+            ("refers", string_hash(&found.refers)),
+        ]))
+    }
+
     /// Return this session's on-the-wire session id (the UUID string the
     /// server minted in the clone response). This is the id `ls-sessions`
     /// reports, so the client can match its own session in that list.
@@ -445,7 +1007,8 @@ impl NReplSession {
 /// Try to get a completed eval result (non-blocking)
 ///
 /// Returns #f if no result is ready yet.
-/// Returns the result string if ready: (hash 'value "..." 'output (list) 'error #f 'ns "user")
+/// Returns the result hash if ready, with `"value"`, `"output"`, `"error"`,
+/// `"ns"` and the rest (see [`eval_result_to_steel_hashmap`]).
 ///
 /// Usage in polling loop:
 /// ```scheme
@@ -458,7 +1021,7 @@ impl NReplSession {
 ///       ;; Got result! Process it
 ///       (process-result result))))
 /// ```
-pub fn nrepl_try_get_result(conn_id: usize, request_id: usize) -> SteelNReplResult<Option<String>> {
+pub fn nrepl_try_get_result(conn_id: usize, request_id: usize) -> SteelNReplResult<Option<Value>> {
     // Try to get the response for this specific request ID
     // The worker buffers responses to support concurrent evals
     //
@@ -467,39 +1030,141 @@ pub fn nrepl_try_get_result(conn_id: usize, request_id: usize) -> SteelNReplResu
     let response =
         registry::try_recv_response(ConnectionId::new(conn_id), RequestId::new(request_id))
             .map_err(nrepl_error_to_steel)?;
-    match response {
-        Some(response) => match response.outcome {
-            EvalOutcome::Done(result) => {
-                let result = result.map_err(nrepl_error_to_steel)?;
-                Ok(Some(eval_result_to_steel_hashmap(&result)))
-            }
-            EvalOutcome::NeedInput { output, error } => {
-                // The evaluation is blocked on (read-line) etc. Surface a marker
-                // hash so the Steel side can prompt and send `nrepl-stdin`
-                // targeting this request id, then keep polling for the result.
-                // Carry any output produced before the pause (e.g. a prompt
-                // string) so the client can render it before opening its stdin
-                // box. Escape identically to the `Done` path.
-                let error_str = if error.is_empty() {
-                    "#f".to_string()
-                } else {
-                    format!("\"{}\"", escape_steel_string(&error.join("\n")))
-                };
-                Ok(Some(format!(
-                    "(hash 'need-input #t 'request-id {} 'output {} 'error {})",
-                    request_id,
-                    output_list_to_steel(&output),
-                    error_str
-                )))
-            }
-        },
-        None => {
-            // Response not ready yet
-            Ok(None)
+    // None: response not ready yet
+    response
+        .map(|response| eval_response_to_steel(response).map_err(nrepl_error_to_steel))
+        .transpose()
+}
+
+/// Take the output an in-flight eval or load-file has printed since the last
+/// call (non-blocking)
+///
+/// Returns a list of `(kind text)` pairs, oldest first, tagged like the
+/// result's `"chunks"` (`"stdout"`, `"stderr"`, `"warning"`, `"trace"`);
+/// empty when nothing new was printed. Lets a long evaluation show its
+/// progress. The finished result still carries all of its output, and once
+/// it has been taken there is nothing left here.
+///
+/// Usage:
+/// ```scheme
+/// (for-each (lambda (chunk) (show (car chunk) (cadr chunk)))
+///           (nrepl-try-get-output conn-id req-id))
+/// ```
+pub fn nrepl_try_get_output(conn_id: usize, request_id: usize) -> SteelNReplResult<Value> {
+    let chunks = registry::take_output(ConnectionId::new(conn_id), RequestId::new(request_id))
+        .map_err(nrepl_error_to_steel)?;
+    Ok(Value::list(chunks.into_iter().map(|(kind, text)| {
+        Value::list([Value::from(kind.as_str()), text.into()])
+    })))
+}
+
+/// The result hash for a finished eval or load-file, or the need-input
+/// marker for one paused on stdin. An eval that failed outright (timed out,
+/// lost its connection) is an error.
+fn eval_response_to_steel(response: EvalResponse) -> Result<Value, NReplError> {
+    match response.outcome {
+        EvalOutcome::Done(result) => Ok(eval_result_to_steel_hashmap(&result?)),
+        EvalOutcome::NeedInput { output, error } => {
+            // The evaluation is blocked on (read-line) etc. Surface a marker
+            // hash so the Steel side can prompt and send `nrepl-stdin`
+            // targeting this request id, then keep polling for the result.
+            // Carry any output produced before the pause (e.g. a prompt
+            // string) so the client can render it before opening its stdin
+            // box. Shaped like the `Done` path's fields of the same names.
+            let error = (!error.is_empty()).then(|| error.join("\n"));
+            Ok(Value::hash([
+                ("need-input", true.into()),
+                ("request-id", response.request_id.as_usize().into()),
+                ("output", output.into()),
+                ("error", error.into()),
+            ]))
         }
     }
 }
 
+/// Take every eval and load-file result that arrived since the last call,
+/// on any connection (non-blocking)
+///
+/// The worker announces each response as it lands, so one call per tick
+/// services every outstanding request instead of a `try-get-result` poll
+/// for each. Returns a list, empty when nothing is new, of hashes of
+/// `"conn-id"`, `"request-id"`, `"result"` (as `try-get-result` returns it,
+/// need-input marker included) and `"failed"`, the error message when the
+/// eval failed outright (timeout, lost connection), else `#f`. A result
+/// already taken by `try-get-result` is not repeated.
+///
+/// Usage:
+/// ```scheme
+/// (for-each (lambda (r) (dispatch (hash-get r "request-id") r))
+///           (nrepl-drain-results))
+/// ```
+#[must_use]
+pub fn nrepl_drain_results() -> Value {
+    Value::list(
+        registry::drain_ready()
+            .into_iter()
+            .map(|(conn_id, response)| response_entry(conn_id, response)),
+    )
+}
+
+/// Try to get the next completed eval result on a connection, whichever
+/// request it answers (non-blocking)
+///
+/// Returns #f if none is ready, otherwise a hash of `"conn-id"`,
+/// `"request-id"`, `"result"` and `"failed"`, as `drain-results` lists them.
+/// Results come oldest first, and each once: one taken here is not returned
+/// by `try-get-result`, and the other way round.
+///
+/// Usage: (nrepl-try-get-any-result conn-id)
+pub fn nrepl_try_get_any_result(conn_id: usize) -> SteelNReplResult<Option<Value>> {
+    let conn_id = ConnectionId::new(conn_id);
+    let response = registry::try_recv_any_response(conn_id).map_err(nrepl_error_to_steel)?;
+    Ok(response.map(|response| response_entry(conn_id, response)))
+}
+
+/// Try to get the results of several requests on a connection at once
+/// (non-blocking)
+///
+/// Returns a list, in the order of `request-ids`, with an entry like
+/// `try-get-any-result`'s for each request that has finished; those still
+/// running are left out, to be asked for again.
+///
+/// Usage: (nrepl-try-get-results conn-id (list 3 4 7))
+pub fn nrepl_try_get_results(conn_id: usize, request_ids: Vec<isize>) -> SteelNReplResult<Value> {
+    let conn_id = ConnectionId::new(conn_id);
+    let request_ids = request_ids
+        .into_iter()
+        .map(|id| {
+            usize::try_from(id)
+                .map(RequestId::new)
+                .map_err(|_| steel_error(format!("Invalid request ID: {id}")))
+        })
+        .collect::<SteelNReplResult<Vec<_>>>()?;
+    let responses =
+        registry::try_recv_responses(conn_id, &request_ids).map_err(nrepl_error_to_steel)?;
+    Ok(Value::list(
+        responses
+            .into_iter()
+            .map(|response| response_entry(conn_id, response)),
+    ))
+}
+
+/// A finished request as the polls that span requests report it: which it
+/// was, and its result or why it has none.
+fn response_entry(conn_id: ConnectionId, response: EvalResponse) -> Value {
+    let request_id = response.request_id.as_usize();
+    let (result, failed) = match eval_response_to_steel(response) {
+        Ok(result) => (Some(result), None),
+        Err(e) => (None, Some(nrepl_error_message(e))),
+    };
+    Value::hash([
+        ("conn-id", conn_id.as_usize().into()),
+        ("request-id", request_id.into()),
+        ("result", result.into()),
+        ("failed", failed.into()),
+    ])
+}
+
 /// Connect to an nREPL server
 /// Returns a connection ID
 ///
@@ -583,18 +1248,40 @@ pub fn nrepl_interrupt(
 
 /// List the sessions active on the server (the `ls-sessions` op).
 ///
-/// Returns a Steel `(list "session-id" ...)` source string of wire session
-/// ids, for the Scheme side to parse with `parse-eval-result`.
+/// Returns a list of wire session id strings.
 ///
 /// **Blocking:** This operation blocks the calling thread for up to 30 seconds.
 /// If the server doesn't respond within this timeout, a timeout error is returned.
 /// Servers that don't implement `ls-sessions` produce an "unknown op" error.
 ///
 /// Usage: (nrepl-ls-sessions conn-id)
-pub fn nrepl_ls_sessions(conn_id: usize) -> SteelNReplResult<String> {
+pub fn nrepl_ls_sessions(conn_id: usize) -> SteelNReplResult<Value> {
     let conn_id = ConnectionId::new(conn_id);
     let sessions = registry::ls_sessions_blocking(conn_id).map_err(nrepl_error_to_steel)?;
-    Ok(output_list_to_steel(&sessions))
+    Ok(sessions.into())
+}
+
+/// Check this client's sessions against the server's (`ls-sessions`)
+///
+/// Sessions the server no longer has, as after a restart, are dropped along
+/// with every handle to them; with `adopt-orphans`, server sessions this
+/// client was not tracking are tracked from now on (attach one with
+/// `nrepl-attach-session` to use it). Returns a hash of `"invalidated"` and
+/// `"adopted"`, each a list of wire session ids.
+///
+/// **Blocking:** This operation blocks the calling thread for up to 30 seconds.
+///
+/// Usage: (nrepl-reconcile-sessions conn-id #t)
+pub fn nrepl_reconcile_sessions(conn_id: usize, adopt_orphans: bool) -> SteelNReplResult<Value> {
+    let conn_id = ConnectionId::new(conn_id);
+    let changes =
+        registry::reconcile_sessions(conn_id, adopt_orphans).map_err(nrepl_error_to_steel)?;
+    let invalidated = changes.invalidated.iter().map(|info| info.session.id());
+    let adopted = changes.adopted.iter().map(Session::id);
+    Ok(Value::hash([
+        ("invalidated", Value::list(invalidated)),
+        ("adopted", Value::list(adopted)),
+    ]))
 }
 
 /// Attach to an existing server session by its wire session id.
@@ -630,6 +1317,29 @@ pub fn nrepl_attach_session(conn_id: usize, wire_id: String) -> SteelNReplResult
     })
 }
 
+/// Reattach to a server session by a wire id this client did not just read
+/// from the server, such as one an earlier editor instance saved.
+///
+/// Asks the server for its sessions (`ls-sessions`) and attaches as
+/// `nrepl-attach-session` does only if `wire-id` is among them, so a stale or
+/// made-up id is refused rather than turned into a handle.
+///
+/// **Blocking:** This operation blocks the calling thread for up to the
+/// connection's control timeout (30 seconds by default). A `ls-sessions` that
+/// fails transiently is retried, but within that same time.
+///
+/// Usage: (nrepl-adopt-session conn-id "31f2c0a2-...")
+pub fn nrepl_adopt_session(conn_id: usize, wire_id: String) -> SteelNReplResult<NReplSession> {
+    let sessions =
+        registry::ls_sessions_blocking(ConnectionId::new(conn_id)).map_err(nrepl_error_to_steel)?;
+    if !sessions.contains(&wire_id) {
+        return Err(steel_error(format!(
+            "Session {wire_id} not found on connection {conn_id}'s server. It may have been closed."
+        )));
+    }
+    nrepl_attach_session(conn_id, wire_id)
+}
+
 /// Close a server session identified by its wire session id.
 ///
 /// Unlike `nrepl-close-session`, this does not need a client-side handle: it
@@ -674,42 +1384,181 @@ pub fn nrepl_stdin(conn_id: usize, session_id: usize, data: &str) -> SteelNReplR
     Ok(())
 }
 
-/// Get registry statistics for observability
+/// Find the forms at a cursor, for "eval form at point"
 ///
-/// Returns a hashmap with connection and session counts, useful for monitoring.
+/// `offset` is a character offset into `text`, as the editor counts them.
+/// Returns `#f` when the cursor is between top-level forms or the buffer is
+/// unbalanced before it, otherwise a hash of `"top-level"`, the top-level
+/// form, and `"innermost"`, the innermost form under the cursor. Each is a
+/// hash of `"start"`, `"end"`, `"line"`, `"column"`, `"end-line"` and
+/// `"end-column"`: `start` and `end` are character offsets (end exclusive);
+/// lines and columns are 1-based, ready for `eval-with-timeout`. With `comment-blocks`
+/// set, a form inside a `(comment ...)` block is its own top-level form.
+///
+/// Usage: (nrepl-form-at text cursor #t)
+#[must_use]
+pub fn nrepl_form_at(text: &str, offset: usize, comment_blocks: bool) -> Option<Value> {
+    let byte_offset = text
+        .char_indices()
+        .nth(offset)
+        .map_or(text.len(), |(i, _)| i);
+    let forms = forms_at_with(text, byte_offset, FormOptions { comment_blocks })?;
+    let chars = |byte: usize| text[..byte].chars().count();
+    let span = |span: &FormSpan| {
+        Value::hash([
+            ("start", chars(span.range.start).into()),
+            ("end", chars(span.range.end).into()),
+            ("line", span.start.line.into()),
+            ("column", span.start.column.into()),
+            ("end-line", span.end.line.into()),
+            ("end-column", span.end.column.into()),
+        ])
+    };
+    Some(Value::hash([
+        ("top-level", span(&forms.top_level)),
+        ("innermost", span(&forms.innermost)),
+    ]))
+}
+
+/// Read the `ns` form of a buffer, without a server
+///
+/// `path` picks the reader-conditional branch: `.cljs` files read the `:cljs`
+/// one, anything else (`#f` included) the `:clj` one. Returns `#f` if the
+/// buffer has no `ns` form, otherwise a hash of `"name"` (`"app.core"`) and
+/// `"aliases"`, from alias to namespace (`"str"` to `"clojure.string"`).
+///
+/// Usage: (nrepl-source-ns contents "/path/to/core.clj")
+#[must_use]
+pub fn nrepl_source_ns(contents: &str, path: Option<String>) -> Option<Value> {
+    let dialect = path.map_or(Dialect::Clojure, |p| Dialect::from_path(Path::new(&p)));
+    let ns = parse_ns(contents, dialect)?;
+    let aliases = ns
+        .requires
+        .iter()
+        .filter_map(|r| Some((r.alias.as_ref()?, &r.ns)));
+    Some(Value::hash([
+        ("name", ns.name.as_str().into()),
+        ("aliases", string_hash(aliases)),
+    ]))
+}
+
+/// Get registry statistics for observability
 ///
-/// Returns: Steel hashmap string with stats like:
-/// `(hash 'total-connections 2 'total-sessions 5 'max-connections 100)`
+/// Returns a hashmap with connection and session counts, useful for monitoring:
+/// `"total-connections"`, `"total-sessions"`, `"max-connections"`,
+/// `"next-conn-id"` and `"connections"`, a list of per-connection hashes.
 ///
 /// Usage: (nrepl-stats)
 #[must_use]
-pub fn nrepl_stats() -> String {
+pub fn nrepl_stats() -> Value {
     let stats = registry::get_stats();
 
-    // Format as Steel hashmap with connection details
-    let mut parts = vec![
-        format!("'total-connections {}", stats.total_connections),
-        format!("'total-sessions {}", stats.total_sessions),
-        format!("'max-connections {}", stats.max_connections),
-        format!("'next-conn-id {}", stats.next_conn_id),
-    ];
-
-    // Add connection details as list
-    let conn_details: Vec<String> = stats
-        .connections
-        .iter()
-        .map(|c| {
-            format!(
-                "(hash 'id {} 'sessions {})",
-                c.connection_id.as_usize(),
-                c.session_count
-            )
-        })
-        .collect();
+    let connections = stats.connections.iter().map(|c| {
+        let t = &c.timeouts;
+        let mut details = vec![
+            ("id", c.connection_id.as_usize().into()),
+            ("sessions", c.session_count.into()),
+            (
+                "timeouts",
+                Value::hash([
+                    ("connect-ms", t.connect.as_millis().into()),
+                    ("clone-ms", t.clone.as_millis().into()),
+                    ("close-ms", t.close.as_millis().into()),
+                    ("interrupt-ms", t.interrupt.as_millis().into()),
+                    ("control-ms", t.control.as_millis().into()),
+                ]),
+            ),
+        ];
+        if let Some(metrics) = &c.metrics {
+            details.extend(metrics_to_steel(metrics));
+        }
+        Value::hash(details)
+    });
+
+    Value::hash([
+        ("total-connections", stats.total_connections.into()),
+        ("total-sessions", stats.total_sessions.into()),
+        ("max-connections", stats.max_connections.into()),
+        ("next-conn-id", stats.next_conn_id.into()),
+        ("connections", Value::list(connections)),
+    ])
+}
 
-    parts.push(format!("'connections (list {})", conn_details.join(" ")));
+/// A connection's traffic counters, as extra `stats` hash entries.
+/// Latencies are whole milliseconds.
+fn metrics_to_steel(m: &MetricsSnapshot) -> [(&'static str, Value); 5] {
+    let millis = |d: Option<Duration>| d.map_or(0, |d| d.as_millis());
+    let ops = m.ops.iter().map(|(op, o)| {
+        let stats = Value::hash([
+            ("sent", o.sent.into()),
+            ("responses", o.responses.into()),
+            ("completed", o.latency.count().into()),
+            ("mean-ms", millis(o.latency.mean()).into()),
+            ("p50-ms", millis(o.latency.percentile(0.5)).into()),
+            ("p99-ms", millis(o.latency.percentile(0.99)).into()),
+            ("max-ms", o.latency.max().as_millis().into()),
+        ]);
+        (op.as_str(), stats)
+    });
+    [
+        ("bytes-sent", m.bytes_sent.into()),
+        ("bytes-received", m.bytes_received.into()),
+        ("requests", m.requests_sent.into()),
+        ("responses", m.responses_received.into()),
+        ("ops", Value::hash(ops)),
+    ]
+}
 
-    format!("(hash {})", parts.join(" "))
+/// Get a connection's recent significant events
+///
+/// Connects, disconnects, timeouts, limit hits and the like, oldest first,
+/// recorded whether or not `NREPL_DEBUG` was set. Events carry ids, sizes and
+/// durations but never code or output, so the dump is safe to paste into a bug
+/// report.
+///
+/// Returns a list of hashes of `"at"`, seconds since the Unix epoch,
+/// `"kind"` (`"connected"`, `"timeout"` ...) and `"message"`
+/// (`"eval req-4 timed out after 60s"`).
+///
+/// # Errors
+/// Returns an error if the connection ID is not found.
+///
+/// Usage: (nrepl-debug-events conn-id)
+pub fn nrepl_debug_events(conn_id: usize) -> SteelNReplResult<Value> {
+    let conn_id = ConnectionId::new(conn_id);
+    let events = registry::debug_events(conn_id).ok_or_else(|| connection_not_found(conn_id))?;
+    Ok(Value::list(events.iter().map(|e| {
+        Value::hash([
+            ("at", epoch_secs(e.at).into()),
+            ("kind", e.kind.as_str().into()),
+            ("message", e.message.as_str().into()),
+        ])
+    })))
+}
+
+/// Seconds since the Unix epoch.
+fn epoch_secs(t: std::time::SystemTime) -> f64 {
+    t.duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Reset a connection's traffic metrics
+///
+/// Zeroes the counters `stats` reports for this connection, starting a fresh
+/// measuring window.
+///
+/// # Errors
+/// Returns an error if the connection ID is not found.
+///
+/// Usage: (nrepl-reset-metrics conn-id)
+pub fn nrepl_reset_metrics(conn_id: usize) -> SteelNReplResult<()> {
+    let conn_id = ConnectionId::new(conn_id);
+    if registry::reset_metrics(conn_id) {
+        Ok(())
+    } else {
+        Err(connection_not_found(conn_id))
+    }
 }
 
 /// Describe the server's capabilities (the nREPL `describe` operation)
@@ -718,7 +1567,9 @@ pub fn nrepl_stats() -> String {
 /// and auxiliary metadata. This is the spec's capability-discovery mechanism;
 /// the plugin uses it to gate optional operations and to surface server info.
 ///
-/// **Blocking:** This operation blocks the calling thread for up to 30 seconds.
+/// **Blocking:** This operation blocks the calling thread for up to the
+/// connection's control timeout (30 seconds by default). A `describe` that
+/// fails transiently is retried, but within that same time.
 ///
 /// # Arguments
 /// * `conn_id` - The connection ID (no session required - `describe` is global)
@@ -726,83 +1577,129 @@ pub fn nrepl_stats() -> String {
 ///
 /// # Returns
 ///
-/// An S-expression string holding a hashmap:
-/// ```scheme
-/// (hash 'ops (list "eval" "describe" "lookup" ...)
-///       'versions (hash "nrepl" (hash "version-string" "1.3.0" ...) ...)
-///       'aux (hash "current-ns" "user" ...))
-/// ```
-/// - `'ops`: list of supported operation names (the keys of the server's ops map)
-/// - `'versions`: nested hash of implementation -> (sub-key -> value)
-/// - `'aux`: flat hash of auxiliary metadata
+/// A hash of:
+/// - `"ops"`: list of supported operation names (the keys of the server's ops map)
+/// - `"versions"`: hash of implementation to a hash of its details
+///   (`"nrepl"` to `"version-string"` to `"1.3.0"`)
+/// - `"aux"`: flat hash of auxiliary metadata (`"current-ns"` to `"user"`)
+/// - `"flavor"`: the kind of server this reply names, as `server-flavor`
+///   reports it (`"clojure"`, `"babashka"`, ... or `"unknown"`)
 ///
-/// Missing sections come back as empty `(list )` / `(hash )`.
+/// Missing sections come back empty.
 ///
 /// Usage: (nrepl-describe conn-id #f)
-pub fn nrepl_describe(conn_id: usize, verbose: bool) -> SteelNReplResult<String> {
+pub fn nrepl_describe(conn_id: usize, verbose: bool) -> SteelNReplResult<Value> {
     let conn_id = ConnectionId::new(conn_id);
 
     let response = registry::describe_blocking(conn_id, verbose).map_err(nrepl_error_to_steel)?;
 
-    // ops -> (list "name" ...) - the op names are all the gating layer needs.
-    let ops = match &response.ops {
-        Some(ops) => {
-            let names: Vec<String> = ops
-                .keys()
-                .map(|k| format!("\"{}\"", escape_steel_string(k)))
-                .collect();
-            format!("(list {})", names.join(" "))
-        }
-        None => "(list )".to_string(),
-    };
+    let flavor = ServerProfile::from_describe(&response).flavor;
+    // The op names are all the gating layer needs.
+    let ops = response.ops.iter().flat_map(|ops| ops.keys());
+    let versions = response
+        .versions
+        .iter()
+        .flatten()
+        .map(|(implementation, details)| (implementation.as_str(), string_hash(details)));
+    Ok(Value::hash([
+        ("ops", Value::list(ops)),
+        ("versions", Value::hash(versions)),
+        ("aux", string_hash(response.aux.iter().flatten())),
+        ("flavor", flavor.as_str().into()),
+    ]))
+}
 
-    // versions -> (hash "impl" (hash "k" "v" ...) ...)
-    let versions = match &response.versions {
-        Some(versions) => {
-            let entries: Vec<String> = versions
-                .iter()
-                .map(|(impl_name, sub)| {
-                    let sub_parts: Vec<String> = sub
-                        .iter()
-                        .map(|(k, v)| {
-                            format!(
-                                "\"{}\" \"{}\"",
-                                escape_steel_string(k),
-                                escape_steel_string(v)
-                            )
-                        })
-                        .collect();
-                    format!(
-                        "\"{}\" (hash {})",
-                        escape_steel_string(impl_name),
-                        sub_parts.join(" ")
-                    )
-                })
-                .collect();
-            format!("(hash {})", entries.join(" "))
-        }
-        None => "(hash )".to_string(),
-    };
+/// Flush and resynchronize a wedged connection
+///
+/// The "unstick my REPL" button. Fails every in-flight and queued request on
+/// the connection, drops unclaimed results, discards buffered and unread
+/// socket bytes (bounded), then checks the server still answers `describe`.
+///
+/// **Blocking:** waits up to 30 seconds for the worker to finish.
+///
+/// # Returns
+///
+/// A hash of what was done: `"bytes-discarded"`, `"requests-failed"`,
+/// `"responses-dropped"` and `"server-alive"`.
+///
+/// Usage: (nrepl-resync conn-id)
+pub fn nrepl_resync(conn_id: usize) -> SteelNReplResult<Value> {
+    let report =
+        registry::resync_blocking(ConnectionId::new(conn_id)).map_err(nrepl_error_to_steel)?;
+    Ok(Value::hash([
+        ("bytes-discarded", report.bytes_discarded.into()),
+        ("requests-failed", report.requests_failed.into()),
+        ("responses-dropped", report.responses_dropped.into()),
+        ("server-alive", report.server_alive.into()),
+    ]))
+}
 
-    // aux -> (hash "k" "v" ...)
-    let aux = match &response.aux {
-        Some(aux) => {
-            let parts: Vec<String> = aux
-                .iter()
-                .map(|(k, v)| {
-                    format!(
-                        "\"{}\" \"{}\"",
-                        escape_steel_string(k),
-                        escape_steel_string(v)
-                    )
-                })
-                .collect();
-            format!("(hash {})", parts.join(" "))
-        }
-        None => "(hash )".to_string(),
-    };
+/// Get the liveness of a connection
+///
+/// Returns `"connected"`, `"degraded"` (the socket is open but the server has
+/// not answered a heartbeat for a while) or `"disconnected"` (the server or
+/// socket closed the connection). Reads state the worker already tracks, so it
+/// never blocks on the server.
+///
+/// # Errors
+/// Returns an error if the connection ID is not found.
+///
+/// Usage: (nrepl-connection-state conn-id)
+pub fn nrepl_connection_state(conn_id: usize) -> SteelNReplResult<String> {
+    let conn_id = ConnectionId::new(conn_id);
+    let state = registry::connection_state(conn_id).ok_or_else(|| connection_not_found(conn_id))?;
+    Ok(match state {
+        ConnectionState::Connected => "connected",
+        ConnectionState::Degraded => "degraded",
+        ConnectionState::Disconnected => "disconnected",
+    }
+    .to_string())
+}
 
-    Ok(format!("(hash 'ops {ops} 'versions {versions} 'aux {aux})"))
+/// The sessions a connection has cloned or used, oldest first
+///
+/// Unlike `ls-sessions` this does not ask the server: it reports what this
+/// client sent. Returns a list of hashes of `"id"`, the wire session id,
+/// `"label"` (#f when unset), `"created"` and `"last-used"`, in seconds since
+/// the Unix epoch, `"requests"` and `"ops"`, a hash from op name to count.
+///
+/// # Errors
+/// Returns an error if the connection ID is not found.
+///
+/// Usage: (nrepl-sessions conn-id)
+pub fn nrepl_sessions(conn_id: usize) -> SteelNReplResult<Value> {
+    let conn_id = ConnectionId::new(conn_id);
+    let sessions = registry::sessions(conn_id).ok_or_else(|| connection_not_found(conn_id))?;
+    Ok(Value::list(sessions.iter().map(|info| {
+        let ops = info.ops.iter().map(|(op, n)| (op.as_str(), (*n).into()));
+        Value::hash([
+            ("id", info.session.id().into()),
+            ("label", info.label.as_deref().into()),
+            ("created", epoch_secs(info.created).into()),
+            ("last-used", epoch_secs(info.last_used).into()),
+            ("requests", info.requests().into()),
+            ("ops", Value::hash(ops)),
+        ])
+    })))
+}
+
+/// Get the kind of server a connection is talking to
+///
+/// Returns `"clojure"`, `"babashka"`, `"nbb"`, `"python"` or `"unknown"`, as
+/// read from the server's `describe` reply on connect. Until that reply
+/// arrives the flavor is `"unknown"`. Never blocks on the server.
+///
+/// # Errors
+/// Returns an error if the connection ID is not found.
+///
+/// Usage: (nrepl-server-flavor conn-id)
+pub fn nrepl_server_flavor(conn_id: usize) -> SteelNReplResult<String> {
+    let conn_id = ConnectionId::new(conn_id);
+    let profile = registry::server_profile(conn_id).ok_or_else(|| connection_not_found(conn_id))?;
+    Ok(profile
+        .map_or(ServerFlavor::Unknown, |p| p.flavor)
+        .as_str()
+        .to_string())
 }
 
 /// Close an nREPL connection
@@ -838,353 +1735,464 @@ pub fn nrepl_close(conn_id: usize) -> SteelNReplResult<()> {
     Ok(())
 }
 
+/// Drop an nREPL connection without closing its sessions
+///
+/// Like `nrepl-close`, but the socket is closed immediately and evals still
+/// running fail with a connection error. For a server that has stopped
+/// answering; its sessions stay open on the server.
+///
+/// # Errors
+/// Returns an error if the connection ID is not found (already closed or never existed).
+///
+/// Usage: (nrepl-abort conn-id)
+pub fn nrepl_abort(conn_id: usize) -> SteelNReplResult<()> {
+    let conn_id = ConnectionId::new(conn_id);
+    if !registry::abort_connection(conn_id) {
+        return Err(steel_error(format!(
+            "Connection {} not found. It may have already been closed.",
+            conn_id.as_usize()
+        )));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_escape_steel_string_quotes() {
-        assert_eq!(escape_steel_string("\"hello\""), r#"\"hello\""#);
-    }
-
-    #[test]
-    fn test_escape_steel_string_backslashes() {
-        assert_eq!(escape_steel_string(r"path\to\file"), r"path\\to\\file");
-    }
-
-    #[test]
-    fn test_escape_steel_string_newlines() {
-        assert_eq!(escape_steel_string("line1\nline2"), r"line1\nline2");
-    }
-
-    #[test]
-    fn test_escape_steel_string_tabs() {
-        assert_eq!(escape_steel_string("col1\tcol2"), r"col1\tcol2");
+    fn text(v: &Value, key: &str) -> Option<String> {
+        v.get(key).and_then(Value::as_str).map(str::to_string)
     }
 
-    #[test]
-    fn test_escape_steel_string_carriage_return() {
-        assert_eq!(escape_steel_string("line1\rline2"), r"line1\rline2");
-    }
-
-    #[test]
-    fn test_escape_steel_string_combined() {
-        let input = "He said \"Hello\"\nNext line\tTab here\\backslash";
-        let expected = r#"He said \"Hello\"\nNext line\tTab here\\backslash"#;
-        assert_eq!(escape_steel_string(input), expected);
-    }
-
-    #[test]
-    fn test_escape_steel_string_empty() {
-        assert_eq!(escape_steel_string(""), "");
-    }
-
-    #[test]
-    fn test_escape_steel_string_no_escapes_needed() {
-        assert_eq!(escape_steel_string("simple text"), "simple text");
+    fn strings(items: &[&str]) -> Value {
+        Value::list(items.iter().copied())
     }
 
     #[test]
     fn test_eval_result_to_steel_hashmap_simple_value() {
         let result = EvalResult {
             value: Some("42".to_string()),
-            output: vec![],
-            error: vec![],
             ns: Some("user".to_string()),
-            ex: None,
-            interrupted: false,
+            ..EvalResult::new()
         };
 
         let hashmap = eval_result_to_steel_hashmap(&result);
 
-        // Verify it's a valid S-expression hash
-        assert!(hashmap.starts_with("(hash "), "Should start with '(hash '");
-        assert!(hashmap.ends_with(')'), "Should end with ')'");
-
-        // Verify it contains expected keys
-        assert!(hashmap.contains("'value \"42\""), "Should contain value");
-        assert!(
-            hashmap.contains("'output (list"),
-            "Should contain output list"
-        );
-        assert!(hashmap.contains("'error #f"), "Should contain no error");
-        assert!(hashmap.contains("'ns \"user\""), "Should contain namespace");
+        assert_eq!(text(&hashmap, "value").as_deref(), Some("42"));
+        assert_eq!(hashmap.get("output"), Some(&Value::List(vec![])));
+        assert_eq!(hashmap.get("error"), Some(&Value::Bool(false)));
+        assert_eq!(text(&hashmap, "ns").as_deref(), Some("user"));
+        assert_eq!(hashmap.get("ex"), Some(&Value::Bool(false)));
+        assert_eq!(hashmap.get("interrupted"), Some(&Value::Bool(false)));
+        assert_eq!(hashmap.get("elapsed-ms"), Some(&Value::Bool(false)));
     }
 
     #[test]
     fn test_eval_result_to_steel_hashmap_with_output() {
         let result = EvalResult {
             value: Some("3".to_string()),
-            output: vec!["hello\n".to_string(), "world\n".to_string()],
-            error: vec![],
-            ns: Some("user".to_string()),
-            ex: None,
-            interrupted: false,
+            output: vec!["hello\n".to_string(), String::new(), "world\n".to_string()],
+            ..EvalResult::new()
         };
 
         let hashmap = eval_result_to_steel_hashmap(&result);
 
-        // Verify output list contains both strings
-        assert!(
-            hashmap.contains("'output (list"),
-            "Should contain output list"
-        );
-        assert!(
-            hashmap.contains(r"hello\n"),
-            "Should contain first output with escaped newline"
-        );
-        assert!(
-            hashmap.contains(r"world\n"),
-            "Should contain second output with escaped newline"
+        assert_eq!(
+            hashmap.get("output"),
+            Some(&strings(&["hello\n", "", "world\n"]))
         );
     }
 
     #[test]
     fn test_eval_result_to_steel_hashmap_with_error() {
         let result = EvalResult {
-            value: None,
-            output: vec![],
             error: vec!["Syntax error".to_string(), "Line 42".to_string()],
             ns: Some("user".to_string()),
-            ex: None,
-            interrupted: false,
+            ..EvalResult::new()
         };
 
         let hashmap = eval_result_to_steel_hashmap(&result);
 
-        // Verify error is joined with newlines
-        assert!(
-            hashmap.contains("'error \"Syntax error\\nLine 42\""),
-            "Should contain joined errors"
+        assert_eq!(
+            text(&hashmap, "error").as_deref(),
+            Some("Syntax error\nLine 42")
         );
-        assert!(hashmap.contains("'value #f"), "Should contain no value");
+        assert_eq!(hashmap.get("value"), Some(&Value::Bool(false)));
     }
 
     #[test]
-    fn test_eval_result_to_steel_hashmap_no_namespace() {
+    fn test_eval_result_to_steel_hashmap_stacktrace() {
         let result = EvalResult {
-            value: Some("result".to_string()),
-            output: vec![],
-            error: vec![],
-            ns: None,
-            ex: None,
-            interrupted: false,
-        };
-
-        let hashmap = eval_result_to_steel_hashmap(&result);
-
-        assert!(hashmap.contains("'ns #f"), "Should contain no namespace");
-    }
-
-    #[test]
-    fn test_eval_result_to_steel_hashmap_special_chars_in_value() {
-        let result = EvalResult {
-            value: Some("\"quoted\"\n\ttabbed".to_string()),
-            output: vec![],
-            error: vec![],
-            ns: Some("user".to_string()),
-            ex: None,
-            interrupted: false,
+            error: vec![
+                "Execution error (ArithmeticException) at user/eval2 (REPL:1).\nDivide by zero\n"
+                    .to_string(),
+            ],
+            ex: Some("class java.lang.ArithmeticException".to_string()),
+            ..EvalResult::new()
         };
 
         let hashmap = eval_result_to_steel_hashmap(&result);
 
-        // Verify special characters are escaped
-        assert!(hashmap.contains(r#"\"quoted\""#), "Should escape quotes");
-        assert!(hashmap.contains(r"\n"), "Should escape newline");
-        assert!(hashmap.contains(r"\t"), "Should escape tab");
+        let frame = Value::hash([
+            ("name", "user/eval2".into()),
+            ("file", "REPL".into()),
+            ("line", 1.into()),
+            ("ns", "user".into()),
+            ("fn", "eval2".into()),
+        ]);
+        assert_eq!(
+            hashmap.get("stacktrace"),
+            Some(&Value::hash([
+                ("class", "ArithmeticException".into()),
+                ("message", "Divide by zero".into()),
+                ("frames", Value::list([frame])),
+                ("cause", false.into()),
+            ]))
+        );
     }
 
     #[test]
-    fn test_eval_result_to_steel_hashmap_empty_error_list() {
+    fn test_eval_result_to_steel_hashmap_chunks() {
         let result = EvalResult {
-            value: Some("ok".to_string()),
-            output: vec![],
-            error: vec![], // Empty error list should become #f
+            value: Some("nil".to_string()),
+            output: vec!["hi\n".to_string()],
+            error: vec!["WARNING: abs already refers to #'clojure.core/abs".to_string()],
             ns: Some("user".to_string()),
-            ex: None,
-            interrupted: false,
+            ..EvalResult::new()
         };
 
         let hashmap = eval_result_to_steel_hashmap(&result);
 
-        assert!(
-            hashmap.contains("'error #f"),
-            "Empty error list should be #f"
+        assert_eq!(
+            hashmap.get("chunks"),
+            Some(&Value::list([
+                strings(&["stdout", "hi\n"]),
+                strings(&[
+                    "warning",
+                    "WARNING: abs already refers to #'clojure.core/abs"
+                ]),
+                strings(&["value", "nil"]),
+            ])),
+            "Should contain classified chunks in display order"
         );
     }
 
     #[test]
-    fn test_eval_result_to_steel_hashmap_multiple_output_entries() {
+    fn test_eval_result_to_steel_hashmap_keeps_strings_verbatim() {
+        // Quotes, escapes and source-like text are data, not syntax.
+        let value = "\"quoted\"\n\ttabbed\\ (hash 'value \"pwned\")";
         let result = EvalResult {
-            value: Some("done".to_string()),
-            output: vec![
-                "line 1".to_string(),
-                "line 2".to_string(),
-                "line 3".to_string(),
-            ],
-            error: vec![],
-            ns: Some("test.ns".to_string()),
-            ex: None,
-            interrupted: false,
+            value: Some(value.to_string()),
+            ..EvalResult::new()
         };
 
         let hashmap = eval_result_to_steel_hashmap(&result);
 
-        // Verify all output entries are present
-        assert!(hashmap.contains("\"line 1\""), "Should contain first line");
-        assert!(hashmap.contains("\"line 2\""), "Should contain second line");
-        assert!(hashmap.contains("\"line 3\""), "Should contain third line");
+        assert_eq!(text(&hashmap, "value").as_deref(), Some(value));
     }
 
     #[test]
-    fn test_format_completions_empty() {
-        assert_eq!(format_completions(&[]), "(list )");
+    fn test_format_apropos() {
+        let matches = vec![
+            AproposMatch {
+                ns: Some("clojure.core".to_string()),
+                name: "map".to_string(),
+                kind: "function".to_string(),
+                doc: Some("Returns a \"lazy\" seq".to_string()),
+            },
+            AproposMatch {
+                ns: None,
+                name: "if".to_string(),
+                kind: "special-form".to_string(),
+                doc: None,
+            },
+        ];
+        assert_eq!(
+            format_apropos(&matches),
+            Value::list([
+                Value::hash([
+                    ("ns", "clojure.core".into()),
+                    ("name", "map".into()),
+                    ("type", "function".into()),
+                    ("doc", "Returns a \"lazy\" seq".into()),
+                ]),
+                Value::hash([
+                    ("ns", false.into()),
+                    ("name", "if".into()),
+                    ("type", "special-form".into()),
+                    ("doc", false.into()),
+                ]),
+            ])
+        );
     }
 
     #[test]
-    fn test_format_completions_full_candidate() {
-        let candidates = vec![CompletionCandidate {
-            candidate: "map".to_string(),
-            ns: Some("clojure.core".to_string()),
-            candidate_type: Some("function".to_string()),
-        }];
+    fn test_format_ns_vars() {
+        let mut add = NsVar {
+            name: "add".to_string(),
+            ..NsVar::default()
+        };
+        add.meta
+            .insert("arglists".to_string(), "([x y])".to_string());
+        add.meta
+            .insert("doc".to_string(), "\"Adds \\\"two\\\".\"".to_string());
+        let mut when = NsVar {
+            name: "when".to_string(),
+            ..NsVar::default()
+        };
+        when.meta.insert("macro".to_string(), "true".to_string());
 
         assert_eq!(
-            format_completions(&candidates),
-            "(list (hash '#:candidate \"map\" '#:ns \"clojure.core\" '#:type \"function\"))"
+            format_ns_vars(&[add, when]),
+            Value::list([
+                Value::hash([
+                    ("name", "add".into()),
+                    ("arglists", "([x y])".into()),
+                    ("doc", "Adds \"two\".".into()),
+                    ("macro", false.into()),
+                ]),
+                Value::hash([
+                    ("name", "when".into()),
+                    ("arglists", false.into()),
+                    ("doc", false.into()),
+                    ("macro", true.into()),
+                ]),
+            ])
         );
     }
 
     #[test]
-    fn test_format_completions_missing_fields_are_false() {
-        // babashka sends ns but no type; minimal servers may send neither
-        let candidates = vec![CompletionCandidate {
-            candidate: "mapv".to_string(),
-            ns: Some("clojure.core".to_string()),
-            candidate_type: None,
-        }];
+    fn test_format_refresh_report() {
+        use nrepl_rs::RefreshError;
 
+        let ok = RefreshReport {
+            reloading: vec!["app.core".to_string()],
+            error: None,
+        };
         assert_eq!(
-            format_completions(&candidates),
-            "(list (hash '#:candidate \"mapv\" '#:ns \"clojure.core\" '#:type #f))"
+            format_refresh_report(&ok),
+            Value::hash([
+                ("reloading", strings(&["app.core"])),
+                ("error", false.into()),
+            ])
+        );
+
+        let failed = RefreshReport {
+            reloading: vec![],
+            error: Some(RefreshError {
+                ns: Some("app.core".to_string()),
+                trace: None,
+                err: "Syntax error\n".to_string(),
+            }),
+        };
+        assert_eq!(
+            format_refresh_report(&failed).get("error"),
+            Some(&Value::hash([
+                ("ns", "app.core".into()),
+                ("trace", false.into()),
+                ("err", "Syntax error\n".into()),
+            ]))
         );
     }
 
     #[test]
-    fn test_format_completions_escapes_candidate() {
-        let candidates = vec![CompletionCandidate {
-            candidate: "weird\"name".to_string(),
-            ns: None,
-            candidate_type: None,
-        }];
-
+    fn test_format_inspector_page() {
+        use nrepl_rs::InspectorPaging;
+
+        let page = InspectorPage {
+            lines: vec![
+                vec![
+                    InspectorChunk::Text("Class: ".to_string()),
+                    InspectorChunk::Value {
+                        text: "clojure.lang.LongRange".to_string(),
+                        index: 0,
+                    },
+                ],
+                vec![],
+            ],
+            path: None,
+            page: Some(InspectorPaging {
+                current: 1,
+                total: 4,
+                size: 32,
+            }),
+        };
+        let reference = Value::hash([
+            ("value", "clojure.lang.LongRange".into()),
+            ("index", 0.into()),
+        ]);
         assert_eq!(
-            format_completions(&candidates),
-            "(list (hash '#:candidate \"weird\\\"name\" '#:ns #f '#:type #f))"
+            format_inspector_page(&page),
+            Value::hash([
+                (
+                    "lines",
+                    Value::list([
+                        Value::list(["Class: ".into(), reference]),
+                        Value::List(vec![]),
+                    ]),
+                ),
+                ("path", false.into()),
+                (
+                    "page",
+                    Value::hash([
+                        ("current", 1.into()),
+                        ("total", 4.into()),
+                        ("size", 32.into()),
+                    ]),
+                ),
+            ])
         );
     }
 
     #[test]
-    fn test_format_lookup_info_none_is_empty_hash() {
-        assert_eq!(format_lookup_info(None), "(hash )");
+    fn test_format_test_results() {
+        use nrepl_rs::{TestAssertion, TestDiff, TestSummary};
+
+        let failure = TestAssertion {
+            outcome: TestOutcome::Fail,
+            ns: "my.tests".to_string(),
+            var: "adding".to_string(),
+            index: 1,
+            message: None,
+            expected: Some("4".to_string()),
+            actual: Some("[5]".to_string()),
+            diffs: vec![TestDiff {
+                actual: "5".to_string(),
+                removed: "4".to_string(),
+                added: "5".to_string(),
+            }],
+            file: Some("my/tests.clj".to_string()),
+            line: Some(12),
+            context: None,
+            error: None,
+        };
+        let mut results = TestResults {
+            summary: TestSummary {
+                namespaces: 1,
+                vars: 1,
+                tests: 1,
+                fail: 1,
+                ..TestSummary::default()
+            },
+            ..TestResults::default()
+        };
+        results
+            .results
+            .entry("my.tests".to_string())
+            .or_default()
+            .insert("adding".to_string(), vec![failure]);
+
+        let formatted = format_test_results(&results);
+        assert_eq!(formatted.get("passed"), Some(&Value::Bool(false)));
+        let summary = formatted.get("summary").expect("summary");
+        assert_eq!(summary.get("fail").and_then(Value::as_int), Some(1));
+        assert_eq!(summary.get("pass").and_then(Value::as_int), Some(0));
+        let diff = Value::hash([
+            ("actual", "5".into()),
+            ("removed", "4".into()),
+            ("added", "5".into()),
+        ]);
+        assert_eq!(
+            formatted.get("results"),
+            Some(&Value::list([Value::hash([
+                ("ns", "my.tests".into()),
+                ("var", "adding".into()),
+                ("type", "fail".into()),
+                ("index", 1.into()),
+                ("message", false.into()),
+                ("expected", "4".into()),
+                ("actual", "[5]".into()),
+                ("diffs", Value::list([diff])),
+                ("file", "my/tests.clj".into()),
+                ("line", 12.into()),
+                ("context", false.into()),
+                ("error", false.into()),
+            ])]))
+        );
     }
 
     #[test]
-    fn test_format_lookup_info_fields_and_escaping() {
-        let mut info = std::collections::BTreeMap::new();
-        info.insert("doc".to_string(), "Line one\nline two".to_string());
-        info.insert("ns".to_string(), "clojure.core".to_string());
+    fn test_format_completions() {
+        assert_eq!(format_completions(&[]), Value::List(vec![]));
 
+        // babashka sends ns but no type; minimal servers may send neither
+        let candidates = vec![
+            CompletionCandidate {
+                candidate: "map".to_string(),
+                ns: Some("clojure.core".to_string()),
+                candidate_type: Some("function".to_string()),
+            },
+            CompletionCandidate {
+                candidate: "weird\"name".to_string(),
+                ns: None,
+                candidate_type: None,
+            },
+        ];
         assert_eq!(
-            format_lookup_info(Some(&info)),
-            "(hash '#:doc \"Line one\\nline two\" '#:ns \"clojure.core\")"
+            format_completions(&candidates),
+            Value::list([
+                Value::hash([
+                    ("candidate", "map".into()),
+                    ("ns", "clojure.core".into()),
+                    ("type", "function".into()),
+                ]),
+                Value::hash([
+                    ("candidate", "weird\"name".into()),
+                    ("ns", false.into()),
+                    ("type", false.into()),
+                ]),
+            ])
         );
     }
 
     #[test]
-    fn test_format_lookup_info_skips_unsafe_keys() {
-        let mut info = std::collections::BTreeMap::new();
-        info.insert("doc".to_string(), "adds numbers".to_string());
+    fn test_format_lookup_info() {
+        assert_eq!(format_lookup_info(None), Value::Hash(BTreeMap::new()));
+
+        // Any key the server sends comes through, whatever its characters.
+        let mut info = BTreeMap::new();
+        info.insert("doc".to_string(), "Line one\nline two".to_string());
         info.insert("see also".to_string(), "x".to_string());
-        info.insert("weird\"key".to_string(), "y".to_string());
-        info.insert("arglists-str".to_string(), "[x y]".to_string());
         assert_eq!(
             format_lookup_info(Some(&info)),
-            "(hash '#:arglists-str \"[x y]\" '#:doc \"adds numbers\")"
+            Value::hash([
+                ("doc", "Line one\nline two".into()),
+                ("see also", "x".into()),
+            ])
         );
     }
 
     #[test]
-    fn test_eval_result_to_steel_hashmap_empty_string_output() {
-        // Test edge case where output contains empty strings
-        let result = EvalResult {
-            value: Some("result".to_string()),
-            output: vec![String::new(), "non-empty".to_string(), String::new()],
-            error: vec![],
-            ns: Some("user".to_string()),
-            ex: None,
-            interrupted: false,
-        };
-
-        let hashmap = eval_result_to_steel_hashmap(&result);
-
-        // Verify output list is present
-        assert!(
-            hashmap.contains("'output (list"),
-            "Should contain output list"
-        );
-
-        // Empty strings should appear as ""
-        // The output should have three entries: two empty strings and one non-empty
-        assert!(hashmap.contains("\"\""), "Should contain empty strings");
-        assert!(
-            hashmap.contains("\"non-empty\""),
-            "Should contain non-empty string"
-        );
-
-        // Verify structure is valid
-        assert!(hashmap.starts_with("(hash "), "Should start with '(hash '");
-        assert!(hashmap.ends_with(')'), "Should end with ')'");
+    fn test_form_at_reports_both_forms() {
+        let text = "(ns a)\n\n(defn f [x] (inc x))";
+        let cursor = text.find("inc").expect("cursor");
+        let forms = nrepl_form_at(text, cursor, false).expect("inside a form");
+
+        let top = forms.get("top-level").expect("top-level");
+        assert_eq!(top.get("start").and_then(Value::as_int), Some(8));
+        assert_eq!(top.get("line").and_then(Value::as_int), Some(3));
+        // The innermost form is the symbol under the cursor.
+        let inner = forms.get("innermost").expect("innermost");
+        assert_eq!(inner.get("start").and_then(Value::as_int), Some(21));
+        assert_eq!(nrepl_form_at(text, 7, false), None);
     }
 
     #[test]
-    fn test_escape_steel_string_unicode_and_emoji() {
-        // Test that Unicode and emoji characters are preserved as-is
-        // Steel/Scheme strings support UTF-8, so we don't need to escape these
-
-        // Unicode characters from various languages
-        let unicode_text = "Hello 世界 مرحبا мир"; // Chinese, Arabic, Cyrillic
-        assert_eq!(
-            escape_steel_string(unicode_text),
-            unicode_text,
-            "Unicode text should be preserved"
-        );
-
-        // Emoji
-        let emoji_text = "🎉 🚀 ❤️ 👍";
-        assert_eq!(
-            escape_steel_string(emoji_text),
-            emoji_text,
-            "Emoji should be preserved"
-        );
-
-        // Mixed content with special chars that DO need escaping
-        let mixed = "Hello 🌍\nNext line\t\"quoted\"";
-        let expected = "Hello 🌍\\nNext line\\t\\\"quoted\\\""; // Only ASCII special chars escaped
-        assert_eq!(
-            escape_steel_string(mixed),
-            expected,
-            "Should preserve Unicode while escaping ASCII special chars"
-        );
-
-        // Edge case: Unicode with backslash
-        let unicode_with_backslash = "Path\\to\\日本語\\file";
-        let expected_unicode_backslash = "Path\\\\to\\\\日本語\\\\file"; // Backslashes escaped, Unicode preserved
+    fn test_source_ns_lists_aliases() {
+        let ns = nrepl_source_ns(
+            "(ns app.core (:require [clojure.string :as str] [clojure.set]))",
+            None,
+        )
+        .expect("has an ns form");
         assert_eq!(
-            escape_steel_string(unicode_with_backslash),
-            expected_unicode_backslash,
-            "Should escape backslashes but preserve Unicode"
+            ns,
+            Value::hash([
+                ("name", "app.core".into()),
+                (
+                    "aliases",
+                    Value::hash([("str", Value::from("clojure.string"))]),
+                ),
+            ])
         );
     }
 
@@ -1237,140 +2245,22 @@ mod tests {
     use proptest::prelude::*;
 
     proptest! {
-        /// Property: Escaped string should never be shorter than the original
-        ///
-        /// Since escaping only adds characters (never removes), the escaped
-        /// string must be >= original length
-        #[test]
-        fn prop_escaped_length_never_decreases(s in ".*") {
-            let escaped = escape_steel_string(&s);
-            prop_assert!(escaped.len() >= s.len(),
-                "Escaped string ({} bytes) shorter than original ({} bytes): {:?} -> {:?}",
-                escaped.len(), s.len(), s, escaped);
-        }
-
-        /// Property: No unescaped quotes in output
-        ///
-        /// After escaping, any quote character (") must be preceded by a backslash.
-        /// This ensures the string can be safely embedded in Steel/Scheme syntax.
-        #[test]
-        fn prop_no_unescaped_quotes(s in ".*") {
-            let escaped = escape_steel_string(&s);
-
-            // Check each quote is preceded by backslash
-            let chars: Vec<char> = escaped.chars().collect();
-            for (i, &c) in chars.iter().enumerate() {
-                if c == '"' {
-                    prop_assert!(i > 0 && chars[i-1] == '\\',
-                        "Found unescaped quote at position {} in: {:?}", i, escaped);
-                }
-            }
-        }
-
-        /// Property: No bare newlines, tabs, or carriage returns
-        ///
-        /// These characters must be escaped as \n, \t, \r respectively.
-        /// The literal characters should not appear in the output.
-        #[test]
-        fn prop_no_bare_control_chars(s in ".*") {
-            let escaped = escape_steel_string(&s);
-
-            prop_assert!(!escaped.contains('\n'),
-                "Found bare newline in escaped string: {:?}", escaped);
-            prop_assert!(!escaped.contains('\t'),
-                "Found bare tab in escaped string: {:?}", escaped);
-            prop_assert!(!escaped.contains('\r'),
-                "Found bare carriage return in escaped string: {:?}", escaped);
-        }
-
-        /// Property: All backslashes are doubled or part of valid escape sequences
-        ///
-        /// After escaping, every backslash should either be:
-        /// - Followed by another backslash (escaped backslash: \\)
-        /// - Followed by a valid ASCII escape character (", n, t, r)
-        /// Note: Non-ASCII characters after backslash are fine (they pass through unchanged)
-        #[test]
-        fn prop_valid_escape_sequences(s in ".*") {
-            let escaped = escape_steel_string(&s);
-            let chars: Vec<char> = escaped.chars().collect();
-
-            let mut i = 0;
-            while i < chars.len() {
-                if chars[i] == '\\' {
-                    prop_assert!(i + 1 < chars.len(),
-                        "Backslash at end of string (position {}): {:?}", i, escaped);
-
-                    let next = chars[i + 1];
-                    // After a backslash, we expect either:
-                    // - Another backslash (escaped \)
-                    // - An ASCII escape char (", n, t, r)
-                    // - Or a non-ASCII char (which is fine, just data)
-                    if next.is_ascii() {
-                        prop_assert!(
-                            next == '\\' || next == '"' || next == 'n' || next == 't' || next == 'r',
-                            "Invalid ASCII escape sequence \\{} at position {} in: {:?}",
-                            next, i, escaped
-                        );
-                    }
-                    // If we have \\, skip the next backslash
-                    if next == '\\' {
-                        i += 2;
-                        continue;
-                    }
-                }
-                i += 1;
-            }
-        }
-
-        /// Property: Unicode and emoji are preserved
-        ///
-        /// Non-ASCII characters should pass through unchanged, only ASCII
-        /// special characters should be escaped.
-        #[test]
-        fn prop_unicode_preserved(s in "[\\u{80}-\\u{10FFFF}]+") {
-            // Generate strings with only non-ASCII characters
-            let escaped = escape_steel_string(&s);
-
-            // Since the input contains no ASCII special chars, output should equal input
-            prop_assert_eq!(&escaped, &s,
-                "Unicode-only string was modified: {:?} -> {:?}", s, escaped);
-        }
-
-        /// Property: Escaping is consistent
-        ///
-        /// Calling escape_steel_string twice should produce the same result as
-        /// calling it once (idempotence for already-escaped strings).
-        /// Note: This is NOT true in general because escaping adds backslashes
-        /// which then get escaped again. This property tests that re-escaping
-        /// is well-defined.
-        #[test]
-        fn prop_double_escape_is_well_defined(s in ".*") {
-            let escaped_once = escape_steel_string(&s);
-            let escaped_twice = escape_steel_string(&escaped_once);
-
-            // Verify second escape is valid (doesn't panic or produce invalid output)
-            prop_assert!(escaped_twice.len() >= escaped_once.len(),
-                "Second escape produced shorter string: {:?} -> {:?}",
-                escaped_once, escaped_twice);
-        }
-
-        /// Property: Empty string remains empty
-        #[test]
-        fn prop_empty_string_unchanged(_s in prop::strategy::Just(())) {
-            let escaped = escape_steel_string("");
-            prop_assert_eq!(&escaped, "",
-                "Empty string was modified: {:?}", escaped);
-        }
-
-        /// Property: Strings without special characters are unchanged
+        /// Property: whatever the server sends comes back unchanged
         ///
-        /// If a string contains only alphanumeric, spaces, and common punctuation
-        /// (no quotes, backslashes, or control chars), it should pass through as-is.
+        /// Results are data, not source, so no string needs escaping and
+        /// none can change the shape of the result around it.
         #[test]
-        fn prop_safe_strings_unchanged(s in "[a-zA-Z0-9 .,;:!?()\\[\\]{}]+") {
-            let escaped = escape_steel_string(&s);
-            prop_assert_eq!(&escaped, &s,
-                "Safe string was modified: {:?} -> {:?}", s, escaped);
+        fn prop_strings_pass_through_verbatim(value in ".*", out in ".*", err in ".+") {
+            let result = EvalResult {
+                value: Some(value.clone()),
+                output: vec![out.clone()],
+                error: vec![err.clone()],
+                ..EvalResult::new()
+            };
+            let hashmap = eval_result_to_steel_hashmap(&result);
+            prop_assert_eq!(text(&hashmap, "value"), Some(value));
+            prop_assert_eq!(hashmap.get("output"), Some(&Value::list([out])));
+            prop_assert_eq!(text(&hashmap, "error"), Some(err));
         }
     }
 }
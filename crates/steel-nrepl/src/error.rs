@@ -29,12 +29,20 @@ pub type SteelNReplResult<T> = Result<T, SteelErr>;
 /// text differs for Timeout, Codec and Protocol.
 #[must_use]
 pub fn nrepl_error_to_steel(err: nrepl_rs::NReplError) -> SteelErr {
+    steel_error(nrepl_error_message(err))
+}
+
+/// The message [`nrepl_error_to_steel`] raises, for results that carry an
+/// error as data rather than raising it.
+#[must_use]
+pub fn nrepl_error_message(err: nrepl_rs::NReplError) -> String {
     use nrepl_rs::NReplError;
 
-    let message = match err {
+    match err {
         NReplError::Timeout {
             operation,
             duration,
+            ..
         } => format!("Operation '{operation}' timed out after {duration:?}"),
         NReplError::SessionNotFound(id) => {
             format!("Session not found: {id}. It may have been closed or never existed.")
@@ -42,6 +50,13 @@ pub fn nrepl_error_to_steel(err: nrepl_rs::NReplError) -> SteelErr {
         NReplError::Connection(e) => {
             format!("Connection error: {e}. Check if nREPL server is running and accessible.")
         }
+        NReplError::ConnectionLost(reason) => {
+            format!("Connection lost: {reason}. The server or the network stopped responding.")
+        }
+        NReplError::NotConnected => {
+            "Not connected. The connection has not been made yet, or is being re-established."
+                .to_string()
+        }
         NReplError::Codec {
             message, position, ..
         } => format!(
@@ -51,9 +66,11 @@ pub fn nrepl_error_to_steel(err: nrepl_rs::NReplError) -> SteelErr {
             format!("Protocol error: {message}. The server response was unexpected.")
         }
         NReplError::OperationFailed(msg) => format!("Operation failed: {msg}"),
-    };
-
-    steel_error(message)
+        NReplError::Cancelled(id) => format!("Request {id} was cancelled"),
+        NReplError::ProtocolViolation { op, id, waited } => format!(
+            "Protocol violation: the server never finished {op} request {id} (nothing for {waited:?} after its last response, and no done status). A middleware may be misbehaving."
+        ),
+    }
 }
 
 /// Create a generic Steel error
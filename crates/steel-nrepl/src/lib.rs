@@ -33,12 +33,16 @@
 //! - **Async isolation**: Prevents blocking the main Steel thread during long evaluations
 //! - **Non-blocking submission**: `submit_eval()` returns immediately with a request ID
 //! - **Polling pattern**: Steel code polls for results with `try_recv_response()`
+//! - **Ready queue**: The worker announces each finished request, so `drain-results` collects
+//!   every new result in one call
+//! - **Live output**: The worker also hands over each chunk an eval prints, held per request
+//!   for `try-get-output` until the result is claimed
 //! - **Response buffering**: Supports multiple concurrent evaluations without losing responses
 //!
 //! ## 3. FFI Layer ([`connection`] module)
 //!
 //! - **Steel-compatible functions**: Export Rust functions that Steel can call
-//! - **S-expression formatting**: Converts results to Steel data structures
+//! - **Structured results**: Returns native Steel hashes and lists ([`value`] module)
 //! - **Error conversion**: Maps `NReplError` to Steel-friendly error strings
 //!
 //! # Usage Pattern
@@ -61,8 +65,8 @@
 //! ; Poll for result (returns false if not ready)
 //! (define result (ffi.try-get-result conn-id request-id))
 //!
-//! ; Result is an S-expression string that evaluates to a hashmap:
-//! ; (hash 'value "3" 'output (list) 'error #f 'ns "user")
+//! ; Result is a hashmap keyed by strings:
+//! ; (hash "value" "3" "output" (list) "error" #f "ns" "user" ...)
 //!
 //! ; IMPORTANT: Always close connections to prevent resource leaks
 //! (ffi.close conn-id)
@@ -86,21 +90,67 @@
 //! - `connect(address: String) -> Int` - Connect to nREPL server, returns connection ID
 //! - `clone-session(conn-id: Int) -> Session` - Clone a new session for evaluations
 //! - `eval-with-timeout(session: Session, code: String, timeout-ms: Int, ...) -> Int` - Submit eval, returns request ID
+//! - `eval-in-ns(session: Session, ns: String, code: String, timeout-ms: Int) -> Int` - Submit eval in a namespace, returns request ID
+//! - `session-ns(session: Session) -> String|False` - The namespace the session was last seen in
+//! - `form-at(text: String, offset: Int, comment-blocks: Bool) -> Hash|False` - The top-level and innermost forms at a cursor, with their ranges, as a hash; with `comment-blocks`, forms in a `(comment ...)` block count as top-level
+//! - `source-ns(contents: String, path: String|False) -> Hash|False` - A buffer's `ns` name and aliases, read locally, as a hash
 //! - `load-file(session: Session, contents: String, path: String, name: String) -> Int` - Load file
-//! - `try-get-result(conn-id: Int, request-id: Int) -> String|False` - Poll for result (non-blocking)
+//! - `try-get-result(conn-id: Int, request-id: Int) -> Hash|False` - Poll for result (non-blocking)
+//! - `try-get-output(conn-id: Int, request-id: Int) -> List` - Output an in-flight eval has printed since the last call, as `(kind text)` pairs
+//! - `try-get-any-result(conn-id: Int) -> Hash|False` - Poll for whichever result on the connection finished first, as a hash like `drain-results` gives
+//! - `try-get-results(conn-id: Int, request-ids: List) -> List` - Poll for several results at once, returning those that are ready
+//! - `drain-results() -> List` - Every eval result that arrived since the last call, on any connection, as hashes of `"conn-id"`, `"request-id"`, `"result"` and `"failed"`
 //! - `interrupt(session: Session, request-id: Int) -> Result` - Interrupt evaluation
-//! - `ls-sessions(conn-id: Int) -> String` - List server sessions as a `(list ...)` source string
+//! - `ls-sessions(conn-id: Int) -> List` - List server sessions' wire ids
+//! - `sessions(conn-id: Int) -> List` - Sessions this client has cloned or used, with labels, times and request counts, as a list of hashes
+//! - `label-session(session: Session, label: String|False) -> Result` - Name a session's role for `sessions`
+//! - `reconcile-sessions(conn-id: Int, adopt-orphans: Bool) -> Hash` - Drop sessions the server no longer has, optionally tracking ones it has that this client does not
 //! - `attach-session(conn-id: Int, wire-id: String) -> Session` - Adopt an existing server session
+//! - `adopt-session(conn-id: Int, wire-id: String) -> Session` - Attach to a saved session id after checking the server still lists it
 //! - `session-id(session: Session) -> String` - The session's on-the-wire id
 //! - `close-session-by-id(conn-id: Int, wire-id: String) -> Result` - Close a session by wire id
 //! - `stdin(session: Session, data: String) -> Result` - Send stdin to evaluation
+//! - `apply-preset(session: Session, name: String) -> Result` - Apply a printer and reader settings preset
+//! - `upgrade-cljs(session: Session, tool: String, arg: String) -> Result` - Turn a session into a ClojureScript REPL with shadow-cljs (build id) or piggieback (REPL env form)
+//! - `ensure-cider-middleware(session: Session, version: String) -> bool` - Load cider-nrepl into a server started without it, #t when it had to be injected
+//! - `ns-aliases(session: Session, ns: String) -> Hash` - A namespace's aliases and refers
 //! - `submit-completions(session: Session, prefix: String, ...) -> Int` - Submit completions, returns request ID
-//! - `try-get-completions(session: Session, request-id: Int) -> String|False` - Poll for completions
+//! - `submit-aliased-completions(session: Session, prefix: String, ...) -> Int` - Like `submit-completions`, resolving an `alias/` prefix first
+//! - `try-get-completions(session: Session, request-id: Int) -> List|False` - Poll for completions
 //! - `submit-lookup(session: Session, symbol: String, ...) -> Int` - Submit lookup, returns request ID
-//! - `try-get-lookup(session: Session, request-id: Int) -> String|False` - Poll for lookup info
-//! - `describe(conn-id: Int, verbose: Bool) -> String` - Server capabilities as a `(hash ...)` source string
+//! - `try-get-lookup(session: Session, request-id: Int) -> Hash|False` - Poll for lookup info
+//! - `apropos(session: Session, query: String, search-ns: String|False, docs: Bool, privates: Bool) -> List` - Search loaded vars by name, as hashes of `"ns"`, `"name"`, `"type"` and `"doc"` (cider-nrepl)
+//! - `ns-list(session: Session) -> List` - Loaded namespaces (cider-nrepl)
+//! - `ns-vars(session: Session, ns: String) -> List` - A namespace's public vars, as hashes of `"name"`, `"arglists"`, `"doc"` and `"macro"` (cider-nrepl)
+//! - `ns-path(session: Session, ns: String) -> String|False` - A namespace's source file (cider-nrepl)
+//! - `classpath(session: Session) -> List` - The server's classpath entries (cider-nrepl)
+//! - `source-file(session: Session, file: String) -> String` - A path to open for a `file` from `info` or `ns-path`, extracting library source from its jar (cider-nrepl)
+//! - `undef(session: Session, sym: String, ns: String) -> Result` - Remove a var, alias or refer (cider-nrepl)
+//! - `undef-all(session: Session, ns: String) -> List` - Remove a namespace's public vars, returning their names
+//! - `toggle-trace-var(session: Session, sym: String, ns: String) -> bool` - Trace or untrace a function, #t when now traced; trace lines arrive as eval output (cider-nrepl)
+//! - `toggle-trace-ns(session: Session, ns: String) -> bool` - Trace or untrace every function in a namespace (cider-nrepl)
+//! - `format-code(session: Session, code: String) -> String` - Format Clojure source with cljfmt (cider-nrepl)
+//! - `format-edn(session: Session, edn: String, right-margin: Int|False) -> String` - Pretty-print EDN (cider-nrepl)
+//! - `refresh(session: Session, all: Bool) -> Hash` - Reload changed namespaces (or all), as a hash of `"reloading"` and `"error"` (cider-nrepl)
+//! - `refresh-clear(session: Session) -> Result` - Reset the refresh tracker after a failed reload
+//! - `inspect(session: Session, code: String, ns: String|False) -> Hash` - Open the inspector on a value, as a hash of `"lines"`, `"path"` and `"page"` (cider-nrepl)
+//! - `inspect-push(session: Session, index: Int) -> Hash`, `inspect-pop(session: Session) -> Hash` - Descend into a nested value, or back out
+//! - `inspect-next-page(session: Session) -> Hash`, `inspect-prev-page(session: Session) -> Hash`, `inspect-set-page-size(session: Session, size: Int) -> Hash` - Page through a large collection
+//! - `inspect-refresh(session: Session) -> Hash` - Re-render the inspected value
+//! - `submit-tests(session: Session, ns: String, vars: String) -> Int` - Run a namespace's tests, or just the named ones (cider-nrepl)
+//! - `submit-test-all(session: Session, load-all: Bool) -> Int` - Run every loaded test namespace (cider-nrepl)
+//! - `submit-retest(session: Session) -> Int` - Re-run the last run's failures (cider-nrepl)
+//! - `try-get-tests(session: Session, request-id: Int) -> Hash|False` - Poll for test results
+//! - `test-stacktrace(session: Session, ns: String, var: String, index: Int) -> Hash|False` - Stack trace of an erroring test
+//! - `describe(conn-id: Int, verbose: Bool) -> Hash` - Server capabilities: ops, versions, aux and detected flavor
+//! - `resync(conn-id: Int) -> Hash` - Flush and resynchronize a wedged connection, reporting what was done
+//! - `connection-state(conn-id: Int) -> String` - Connection liveness: "connected", "degraded" or "disconnected"
+//! - `server-flavor(conn-id: Int) -> String` - The server implementation: "clojure", "babashka", "nbb", "python" or "unknown"
 //! - `stats(conn-id: Int) -> Hashmap` - Get connection statistics
+//! - `reset-metrics(conn-id: Int) -> Result` - Zero a connection's traffic metrics
+//! - `debug-events(conn-id: Int) -> List` - Recent connection events (connects, timeouts, limit hits)
 //! - `close(conn-id: Int) -> Bool` - Close connection and shutdown worker
+//! - `abort(conn-id: Int) -> Result` - Drop a hung connection immediately, leaving its sessions open
 //!
 //! # Thread Safety
 //!
@@ -119,37 +169,40 @@
 //!
 //! FFI functions return errors as:
 //! - **Option**: `None` for invalid connection/session IDs
-//! - **Result in S-expression**: `(hash ... 'error "error message" ...)`
+//! - **Error in the result**: `(hash ... "error" "error message" ...)`
 //! - **String errors**: Returned directly for submission failures
 //!
-//! # S-Expression Result Formats
+//! # Result Formats
 //!
-//! Several FFI functions return S-expression strings that Steel code must parse and evaluate.
-//! These strings are valid Steel/Scheme code that construct data structures when evaluated.
+//! Functions that return more than a scalar return native Steel data, built
+//! by [`value::Value`]: hashes keyed by strings, lists, strings, numbers and
+//! booleans, with `#f` for anything missing. Nothing needs to be read or
+//! evaluated, and a string from the server is never parsed as code.
 //!
 //! ## Eval Results (from `try-get-result`)
 //!
-//! Returns a string containing a hash construction call:
-//!
 //! ```scheme
-//! (hash 'value "3"              ; Evaluation result (string or #f if none)
-//!       'output (list "line1\n" "line2\n")  ; Stdout/stderr output (list of strings)
-//!       'error #f               ; Error message (string or #f if no error)
-//!       'ns "user")             ; Current namespace (string or #f)
+//! (hash "value" "3"                          ; Evaluation result (string or #f if none)
+//!       "output" (list "line1\n" "line2\n")  ; Stdout/stderr output (list of strings)
+//!       "error" #f                           ; Error message (string or #f if no error)
+//!       "ns" "user"                          ; Current namespace (string or #f)
+//!       ...)
 //! ```
 //!
 //! **Fields**:
-//! - `'value`: The result value as a string, or `#f` if evaluation produced no value
-//! - `'output`: List of output strings (stdout/stderr), may be empty `(list)`
-//! - `'error`: Error message string if evaluation failed, or `#f` for success
-//! - `'ns`: Namespace after evaluation (e.g., "user", "clojure.core"), or `#f`
+//! - `"value"`: The result value as a string, or `#f` if evaluation produced no value
+//! - `"output"`: List of output strings (stdout/stderr), may be empty
+//! - `"error"`: Error message string if evaluation failed, or `#f` for success
+//! - `"ns"`: Namespace after evaluation (e.g., "user", "clojure.core"), or `#f`
+//! - `"chunks"`: The same content as `(list kind text)` pairs in display order,
+//!   where `kind` is one of `"stdout"`, `"stderr"`, `"warning"`, `"trace"`,
+//!   `"exception"` or `"value"`, for mapping onto theme scopes
 //!
 //! **Usage**:
 //! ```scheme
-//! (define result-str (ffi.try-get-result conn-id req-id))
-//! (when result-str  ; Returns #f if not ready yet
-//!   (define result (eval (read (open-input-string result-str))))
-//!   (hash-get result 'value))   ; Get the value
+//! (define result (ffi.try-get-result conn-id req-id))
+//! (when result  ; Returns #f if not ready yet
+//!   (hash-get result "value"))
 //! ```
 //!
 //! ## Completions (from `try-get-completions`)
@@ -157,14 +210,14 @@
 //! Returns a list of per-candidate hashes:
 //!
 //! ```scheme
-//! (list (hash '#:candidate "map" '#:ns "clojure.core" '#:type "function")
-//!       (hash '#:candidate "mapv" '#:ns "clojure.core" '#:type "function"))
+//! (list (hash "candidate" "map" "ns" "clojure.core" "type" "function")
+//!       (hash "candidate" "mapv" "ns" "clojure.core" "type" "function"))
 //! ```
 //!
 //! **Usage**:
 //! ```scheme
 //! (define req-id (ffi.submit-completions session "ma" #f #f))
-//! (define completions-str (ffi.try-get-completions session req-id))  ; #f until ready
+//! (define completions (ffi.try-get-completions session req-id))  ; #f until ready
 //! ```
 //!
 //! ## Lookup (from `try-get-lookup`)
@@ -172,43 +225,65 @@
 //! Returns a hash with symbol metadata:
 //!
 //! ```scheme
-//! (hash '#:arglists "([f] [f coll] [f c1 c2] [f c1 c2 c3] [f c1 c2 c3 & colls])"
-//!       '#:doc "Returns a lazy sequence consisting of the result of applying f..."
-//!       '#:file "clojure/core.clj"
-//!       '#:line "2776"
-//!       '#:name "map"
-//!       '#:ns "clojure.core")
+//! (hash "arglists" "([f] [f coll] [f c1 c2] [f c1 c2 c3] [f c1 c2 c3 & colls])"
+//!       "doc" "Returns a lazy sequence consisting of the result of applying f..."
+//!       "file" "clojure/core.clj"
+//!       "line" "2776"
+//!       "name" "map"
+//!       "ns" "clojure.core")
 //! ```
 //!
 //! **Common fields** (server-dependent):
-//! - `'#:arglists`: Function argument lists
-//! - `'#:doc`: Documentation string
-//! - `'#:file`: Source file path
-//! - `'#:line`: Line number in source
-//! - `'#:name`: Symbol name
-//! - `'#:ns`: Defining namespace
+//! - `"arglists"`: Function argument lists
+//! - `"doc"`: Documentation string
+//! - `"file"`: Source file path
+//! - `"line"`: Line number in source
+//! - `"name"`: Symbol name
+//! - `"ns"`: Defining namespace
 //!
 //! Note: Available fields depend on nREPL server implementation and middleware.
 //!
+//! ## Test Results (from `try-get-tests`)
+//!
+//! Returns the summary and every assertion, flattened:
+//!
+//! ```scheme
+//! (hash "passed" #f
+//!       "summary" (hash "namespaces" 1 "vars" 2 "tests" 2 "pass" 5 "fail" 1 "error" 0)
+//!       "results" (list (hash "ns" "my.app-test" "var" "test-add" "type" "fail" "index" 1
+//!                             "message" #f "expected" "4" "actual" "5"
+//!                             "diffs" (list (hash "actual" "5" "removed" "4" "added" "5"))
+//!                             "file" "my/app_test.clj" "line" 12 "context" #f "error" #f)
+//!                       ...))
+//! ```
+//!
+//! `"type"` is `"pass"`, `"fail"` or `"error"`. Pass an erroring assertion's
+//! `"ns"`, `"var"` and `"index"` to `test-stacktrace` for its exception.
+//!
 //! ## Stats (from `stats`)
 //!
 //! Returns registry statistics:
 //!
 //! ```scheme
-//! (hash 'total-connections 2
-//!       'total-sessions 5
-//!       'max-connections 100
-//!       'next-conn-id 3
-//!       'connections (list (hash 'id 1 'sessions 2)
-//!                         (hash 'id 2 'sessions 3)))
+//! (hash "total-connections" 2
+//!       "total-sessions" 5
+//!       "max-connections" 100
+//!       "next-conn-id" 3
+//!       "connections" (list (hash "id" 1 "sessions" 2 "timeouts" (hash ...) "bytes-sent" 812 ...)
+//!                           (hash "id" 2 "sessions" 3 "timeouts" (hash ...) "bytes-sent" 96 ...)))
 //! ```
 //!
 //! **Fields**:
-//! - `'total-connections`: Current number of open connections
-//! - `'total-sessions`: Total sessions across all connections
-//! - `'max-connections`: Maximum allowed connections (100)
-//! - `'next-conn-id`: Next connection ID that will be assigned
-//! - `'connections`: List of per-connection stats with `'id` and `'sessions` count
+//! - `"total-connections"`: Current number of open connections
+//! - `"total-sessions"`: Total sessions across all connections
+//! - `"max-connections"`: Maximum allowed connections (100)
+//! - `"next-conn-id"`: Next connection ID that will be assigned
+//! - `"connections"`: List of per-connection stats with `"id"` and `"sessions"` count,
+//!   the `"timeouts"` its blocking ops wait by (a hash of `"connect-ms"`, `"clone-ms"`,
+//!   `"close-ms"`, `"interrupt-ms"` and `"control-ms"`), plus traffic metrics: `"bytes-sent"`,
+//!   `"bytes-received"`, `"requests"`, `"responses"` and `"ops"`, a hash from op name to
+//!   a hash of `"sent"`, `"responses"`, `"completed"`, `"mean-ms"`, `"p50-ms"`, `"p99-ms"`
+//!   and `"max-ms"`. Percentiles are bucket upper bounds, not exact.
 //!
 //! # Module Structure
 //!
@@ -216,6 +291,8 @@
 //! lib.rs           ← You are here (module declaration and FFI registration)
 //! ├── registry.rs  ← Global connection/session registry
 //! ├── connection.rs ← FFI function implementations and result formatting
+//! ├── presets.rs   ← Per-session printer and reader settings presets
+//! ├── value.rs     ← Structured results as Steel sees them
 //! └── error.rs     ← Error type conversions
 //! ```
 //!
@@ -227,9 +304,12 @@
 // repeated on every function.
 #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
 
+pub mod cider_injection;
 pub mod connection;
 pub mod error;
+pub mod presets;
 pub mod registry;
+pub mod value;
 
 use steel::{
     declare_module,
@@ -249,30 +329,97 @@ fn create_module() -> FFIModule {
             "eval-with-timeout",
             connection::NReplSession::eval_with_timeout,
         )
+        .register_fn("eval-in-ns", connection::NReplSession::eval_in_ns)
+        .register_fn("session-ns", connection::NReplSession::current_ns)
+        .register_fn("form-at", connection::nrepl_form_at)
+        .register_fn("source-ns", connection::nrepl_source_ns)
         .register_fn("load-file", connection::NReplSession::load_file)
         .register_fn("try-get-result", connection::nrepl_try_get_result)
+        .register_fn("try-get-output", connection::nrepl_try_get_output)
+        .register_fn("try-get-any-result", connection::nrepl_try_get_any_result)
+        .register_fn("try-get-results", connection::nrepl_try_get_results)
+        .register_fn("drain-results", connection::nrepl_drain_results)
         .register_fn("interrupt", connection::NReplSession::interrupt)
         .register_fn("ls-sessions", connection::nrepl_ls_sessions)
+        .register_fn("sessions", connection::nrepl_sessions)
+        .register_fn("label-session", connection::NReplSession::set_label)
+        .register_fn("reconcile-sessions", connection::nrepl_reconcile_sessions)
         .register_fn("attach-session", connection::nrepl_attach_session)
+        .register_fn("adopt-session", connection::nrepl_adopt_session)
         .register_fn("session-id", connection::NReplSession::wire_session_id)
         .register_fn(
             "close-session-by-id",
             connection::nrepl_close_session_by_wire_id,
         )
         .register_fn("stdin", connection::NReplSession::stdin)
+        .register_fn("apply-preset", connection::NReplSession::apply_preset)
+        .register_fn("upgrade-cljs", connection::NReplSession::upgrade_cljs)
+        .register_fn(
+            "ensure-cider-middleware",
+            connection::NReplSession::ensure_cider_middleware,
+        )
+        .register_fn("ns-aliases", connection::NReplSession::ns_aliases)
         .register_fn(
             "submit-completions",
             connection::NReplSession::submit_completions,
         )
+        .register_fn(
+            "submit-aliased-completions",
+            connection::NReplSession::submit_aliased_completions,
+        )
         .register_fn(
             "try-get-completions",
             connection::NReplSession::try_get_completions,
         )
         .register_fn("submit-lookup", connection::NReplSession::submit_lookup)
         .register_fn("try-get-lookup", connection::NReplSession::try_get_lookup)
+        .register_fn("apropos", connection::NReplSession::apropos)
+        .register_fn("ns-list", connection::NReplSession::ns_list)
+        .register_fn("ns-vars", connection::NReplSession::ns_vars)
+        .register_fn("ns-path", connection::NReplSession::ns_path)
+        .register_fn("classpath", connection::NReplSession::classpath)
+        .register_fn("source-file", connection::NReplSession::source_file)
+        .register_fn("undef", connection::NReplSession::undef)
+        .register_fn("undef-all", connection::NReplSession::undef_all)
+        .register_fn(
+            "toggle-trace-var",
+            connection::NReplSession::toggle_trace_var,
+        )
+        .register_fn("toggle-trace-ns", connection::NReplSession::toggle_trace_ns)
+        .register_fn("format-code", connection::NReplSession::format_code)
+        .register_fn("format-edn", connection::NReplSession::format_edn)
+        .register_fn("refresh", connection::NReplSession::refresh)
+        .register_fn("refresh-clear", connection::NReplSession::refresh_clear)
+        .register_fn("inspect", connection::NReplSession::inspect)
+        .register_fn("inspect-push", connection::NReplSession::inspect_push)
+        .register_fn("inspect-pop", connection::NReplSession::inspect_pop)
+        .register_fn("inspect-refresh", connection::NReplSession::inspect_refresh)
+        .register_fn(
+            "inspect-next-page",
+            connection::NReplSession::inspect_next_page,
+        )
+        .register_fn(
+            "inspect-prev-page",
+            connection::NReplSession::inspect_prev_page,
+        )
+        .register_fn(
+            "inspect-set-page-size",
+            connection::NReplSession::inspect_set_page_size,
+        )
+        .register_fn("submit-tests", connection::NReplSession::submit_tests)
+        .register_fn("submit-test-all", connection::NReplSession::submit_test_all)
+        .register_fn("submit-retest", connection::NReplSession::submit_retest)
+        .register_fn("try-get-tests", connection::NReplSession::try_get_tests)
+        .register_fn("test-stacktrace", connection::NReplSession::test_stacktrace)
         .register_fn("stats", connection::nrepl_stats)
+        .register_fn("reset-metrics", connection::nrepl_reset_metrics)
+        .register_fn("debug-events", connection::nrepl_debug_events)
         .register_fn("describe", connection::nrepl_describe)
-        .register_fn("close", connection::nrepl_close);
+        .register_fn("resync", connection::nrepl_resync)
+        .register_fn("connection-state", connection::nrepl_connection_state)
+        .register_fn("server-flavor", connection::nrepl_server_flavor)
+        .register_fn("close", connection::nrepl_close)
+        .register_fn("abort", connection::nrepl_abort);
 
     module
 }
@@ -0,0 +1,174 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Per-session REPL setting presets
+//!
+//! A preset is a curated bundle of Clojure printer and reader settings applied
+//! to a single session by evaluating a short list of `set!` forms. nREPL thread-binds these
+//! vars per session, so a preset only affects the session it is applied to.
+//!
+//! Each form is paired with the printed value a successful `set!` returns. The
+//! result of every eval is checked against it, so a preset that fails part-way
+//! (a non-Clojure server, a var the server does not bind) is reported as an
+//! error naming the offending form rather than leaving the session silently
+//! half-configured.
+
+use crate::registry::{self, ConnectionId};
+use nrepl_rs::{NReplError, Session};
+use std::time::Duration;
+
+/// Per-form eval timeout. A `set!` returns instantly; anything slower means
+/// the session is busy or the server is not answering.
+const PRESET_FORM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A named bundle of session settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Short, readable output: plain keyword maps and bounded collections.
+    Beginner,
+    /// Full data with namespaced map syntax, no truncation.
+    Data,
+    /// Everything printed verbatim, metadata included.
+    Raw,
+    /// For exploring untrusted data: bounded output, and the reader's `#=`
+    /// read-time eval turned off.
+    Safe,
+}
+
+impl Preset {
+    /// Every preset, in the order they are listed to users.
+    pub const ALL: [Preset; 4] = [Preset::Beginner, Preset::Data, Preset::Raw, Preset::Safe];
+
+    /// Resolve a preset from its user-facing name.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "beginner" => Some(Preset::Beginner),
+            "data" => Some(Preset::Data),
+            "raw" => Some(Preset::Raw),
+            "safe" => Some(Preset::Safe),
+            _ => None,
+        }
+    }
+
+    /// The user-facing name (the inverse of [`from_name`](Self::from_name)).
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Beginner => "beginner",
+            Preset::Data => "data",
+            Preset::Raw => "raw",
+            Preset::Safe => "safe",
+        }
+    }
+
+    /// The `(form, expected printed value)` pairs this preset evaluates.
+    #[must_use]
+    pub fn forms(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Preset::Beginner => &[
+                ("(set! *print-namespace-maps* false)", "false"),
+                ("(set! *print-length* 100)", "100"),
+                ("(set! *print-level* 10)", "10"),
+                ("(set! *print-meta* false)", "false"),
+            ],
+            Preset::Data => &[
+                ("(set! *print-namespace-maps* true)", "true"),
+                ("(set! *print-length* nil)", "nil"),
+                ("(set! *print-level* nil)", "nil"),
+                ("(set! *print-meta* false)", "false"),
+            ],
+            Preset::Raw => &[
+                ("(set! *print-namespace-maps* false)", "false"),
+                ("(set! *print-length* nil)", "nil"),
+                ("(set! *print-level* nil)", "nil"),
+                ("(set! *print-meta* true)", "true"),
+            ],
+            Preset::Safe => &[
+                ("(set! *print-namespace-maps* false)", "false"),
+                ("(set! *print-length* 100)", "100"),
+                ("(set! *print-level* 10)", "10"),
+                ("(set! *print-meta* false)", "false"),
+                ("(set! *read-eval* false)", "false"),
+            ],
+        }
+    }
+}
+
+/// Apply `preset` to `session`, one form at a time, verifying each result.
+///
+/// Stops at the first form that raises or prints something other than its
+/// expected value; forms before it stay applied.
+pub fn apply_preset(
+    conn_id: ConnectionId,
+    session: &Session,
+    preset: Preset,
+) -> Result<(), NReplError> {
+    for (form, expected) in preset.forms() {
+        let result = registry::eval_blocking(
+            conn_id,
+            session.clone(),
+            (*form).to_string(),
+            PRESET_FORM_TIMEOUT,
+        )?;
+        if let Some(ex) = result.ex {
+            return Err(NReplError::OperationFailed(format!(
+                "preset '{}' failed at {form}: {ex}",
+                preset.name()
+            )));
+        }
+        if result.value.as_deref() != Some(*expected) {
+            return Err(NReplError::OperationFailed(format!(
+                "preset '{}' failed at {form}: expected {expected}, got {}",
+                preset.name(),
+                result.value.as_deref().unwrap_or("no value")
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preset_names_round_trip() {
+        for preset in Preset::ALL {
+            assert_eq!(Preset::from_name(preset.name()), Some(preset));
+        }
+        assert_eq!(Preset::from_name("fancy"), None);
+    }
+
+    #[test]
+    fn test_every_preset_sets_namespace_maps() {
+        for preset in Preset::ALL {
+            assert!(
+                preset
+                    .forms()
+                    .iter()
+                    .any(|(form, _)| form.contains("*print-namespace-maps*")),
+                "preset {} should set *print-namespace-maps*",
+                preset.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_safe_preset_turns_off_read_eval() {
+        assert!(
+            Preset::Safe
+                .forms()
+                .contains(&("(set! *read-eval* false)", "false"))
+        );
+    }
+}
@@ -33,9 +33,17 @@
 //! there's a bug in the registry implementation itself (array bounds, unwrap on None, etc.).
 //! In such cases, failing fast with a panic is preferable to silent data corruption.
 
-use nrepl_rs::worker::{EvalResponse, RequestId, SubmitError, Worker, WorkerCommand};
-use nrepl_rs::{CompletionCandidate, NReplError, Response, Session};
-use std::collections::HashMap;
+use nrepl_rs::worker::{
+    ConnectionState, EvalOutcome, EvalResponse, InspectorAction, RequestId, ResyncReport,
+    SubmitError, TestSelection, Worker, WorkerCommand, WorkerConfig,
+};
+use nrepl_rs::{
+    AproposMatch, ChunkKind, CljsRepl, CompletionCandidate, DebugEvent, EvalResult, InspectorPage,
+    MetricsSnapshot, NReplError, NsAliases, NsVar, RefreshOptions, RefreshReport, Response,
+    RetryPolicy, ServerProfile, Session, SessionInfo, SessionReconciliation, StackTrace,
+    TestResults, Timeouts, TraceState, VarTrace,
+};
+use std::collections::{HashMap, VecDeque};
 use std::sync::mpsc::{Receiver, Sender, TryRecvError, channel};
 use std::sync::{Arc, LazyLock, Mutex};
 use std::time::Duration;
@@ -80,11 +88,48 @@ impl SessionId {
 /// Maximum number of concurrent connections to prevent resource exhaustion
 const MAX_CONNECTIONS: usize = 100;
 
+/// How often each connection probes its server to keep its state current.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Most finished requests a connection remembers for [`drain_ready`]. Past
+/// this the oldest are forgotten; their results can still be polled for.
+const MAX_READY: usize = 1000;
+
+/// Ids of a connection's finished requests, pushed by its worker thread as
+/// each response lands and taken by [`drain_ready`].
+type ReadyQueue = Arc<Mutex<VecDeque<RequestId>>>;
+
+/// Most chunks of one request's output held for [`take_output`]. Past this
+/// the oldest are dropped; the finished result still carries every one.
+const MAX_LIVE_CHUNKS: usize = 1000;
+
+/// Most requests a connection holds output for at once. Past this the
+/// oldest request's output is dropped.
+const MAX_LIVE_REQUESTS: usize = 100;
+
+/// Output of a connection's evals while they run, by request, pushed by its
+/// worker thread as each chunk lands and taken by [`take_output`]. A
+/// request's entry goes once its result is claimed.
+type LiveOutput = Arc<Mutex<HashMap<RequestId, VecDeque<(ChunkKind, String)>>>>;
+
 /// Connection entry storing worker thread and its sessions
 struct ConnectionEntry {
     worker: Worker,
     sessions: HashMap<SessionId, Session>,
     next_session_id: usize,
+    ready: ReadyQueue,
+    output: LiveOutput,
+}
+
+impl ConnectionEntry {
+    /// Pass `response` on, forgetting any output still held for its request:
+    /// the result carries it all.
+    fn claimed(&self, response: Option<EvalResponse>) -> Option<EvalResponse> {
+        if let Some(response) = &response {
+            self.output.lock().unwrap().remove(&response.request_id);
+        }
+        response
+    }
 }
 
 /// Global registry of nREPL connections
@@ -111,7 +156,12 @@ impl Registry {
     /// Re-checks the limit authoritatively (the pre-check happens before the
     /// blocking connect, so the count could have grown meanwhile). Returns the
     /// worker back on rejection so the caller can drop it cleanly.
-    fn insert_connected_worker(&mut self, worker: Worker) -> Result<ConnectionId, Worker> {
+    fn insert_connected_worker(
+        &mut self,
+        worker: Worker,
+        ready: ReadyQueue,
+        output: LiveOutput,
+    ) -> Result<ConnectionId, Worker> {
         if self.at_capacity() {
             return Err(worker);
         }
@@ -127,6 +177,8 @@ impl Registry {
                 worker,
                 sessions: HashMap::new(),
                 next_session_id: 1,
+                ready,
+                output,
             },
         );
         Ok(id)
@@ -148,6 +200,24 @@ impl Registry {
         Ok((entry.worker.command_sender(), entry.worker.next_id()))
     }
 
+    /// The policy a connection's idempotent ops retry by, or `None` if the
+    /// id is unknown.
+    #[must_use]
+    pub fn retry_policy(&self, conn_id: ConnectionId) -> Option<RetryPolicy> {
+        self.connections
+            .get(&conn_id)
+            .map(|entry| *entry.worker.retry_policy())
+    }
+
+    /// How long a connection's blocking ops wait for their replies, or
+    /// `None` if the id is unknown.
+    #[must_use]
+    pub fn timeouts(&self, conn_id: ConnectionId) -> Option<Timeouts> {
+        self.connections
+            .get(&conn_id)
+            .map(|entry| *entry.worker.timeouts())
+    }
+
     /// Submit an eval request to the worker thread (non-blocking)
     ///
     /// Note: This function has many parameters to pass file location metadata for better
@@ -172,6 +242,19 @@ impl Registry {
         )
     }
 
+    /// Submit an eval in namespace `ns` to the worker thread (non-blocking)
+    pub fn submit_eval_in_ns(
+        &mut self,
+        conn_id: ConnectionId,
+        session: &Session,
+        ns: &str,
+        code: String,
+        timeout: Option<Duration>,
+    ) -> Option<Result<RequestId, SubmitError>> {
+        let entry = self.connections.get_mut(&conn_id)?;
+        Some(entry.worker.eval_in_ns(session, ns, code, timeout))
+    }
+
     /// Submit a load-file request to the worker thread (non-blocking)
     pub fn submit_load_file(
         &mut self,
@@ -206,7 +289,80 @@ impl Registry {
                 conn_id.as_usize()
             ))
         })?;
-        Ok(entry.worker.try_recv_response(request_id))
+        let response = entry.worker.try_recv_response(request_id);
+        Ok(entry.claimed(response))
+    }
+
+    /// Try to receive the oldest completed eval response for any request on
+    /// a connection (non-blocking). Errors like
+    /// [`try_recv_response`](Self::try_recv_response) when the connection
+    /// is gone.
+    pub fn try_recv_any_response(
+        &mut self,
+        conn_id: ConnectionId,
+    ) -> Result<Option<EvalResponse>, NReplError> {
+        let entry = self.connections.get_mut(&conn_id).ok_or_else(|| {
+            NReplError::protocol(format!(
+                "Connection {} not found. It may have been closed.",
+                conn_id.as_usize()
+            ))
+        })?;
+        let response = entry.worker.try_recv_any_response();
+        Ok(entry.claimed(response))
+    }
+
+    /// Take the output a request has printed since the last call, oldest
+    /// first. Empty for a request that has printed nothing new, or whose
+    /// result was already claimed. Errors like
+    /// [`try_recv_response`](Self::try_recv_response) when the connection
+    /// is gone.
+    pub fn take_output(
+        &self,
+        conn_id: ConnectionId,
+        request_id: RequestId,
+    ) -> Result<Vec<(ChunkKind, String)>, NReplError> {
+        let entry = self.connections.get(&conn_id).ok_or_else(|| {
+            NReplError::protocol(format!(
+                "Connection {} not found. It may have been closed.",
+                conn_id.as_usize()
+            ))
+        })?;
+        let mut output = entry.output.lock().unwrap();
+        Ok(output
+            .get_mut(&request_id)
+            .map(|chunks| chunks.drain(..).collect())
+            .unwrap_or_default())
+    }
+
+    /// Take the responses of every request that finished since the last
+    /// call, on every connection, by connection id. A response already
+    /// claimed through [`try_recv_response`](Self::try_recv_response) is
+    /// skipped, so pollers and drainers can share a connection.
+    pub fn drain_ready(&mut self) -> Vec<(ConnectionId, EvalResponse)> {
+        let mut conn_ids: Vec<_> = self.connections.keys().copied().collect();
+        conn_ids.sort();
+        let mut drained = Vec::new();
+        for conn_id in conn_ids {
+            let Some(entry) = self.connections.get_mut(&conn_id) else {
+                continue;
+            };
+            let ids: Vec<_> = entry.ready.lock().unwrap().drain(..).collect();
+            drained.extend(
+                ids.into_iter()
+                    .filter_map(|id| {
+                        let response = entry.worker.try_recv_response(id);
+                        entry.claimed(response)
+                    })
+                    .map(|response| (conn_id, response)),
+            );
+        }
+        drained
+    }
+
+    /// Drop a connection's unclaimed eval responses, returning how many went.
+    pub fn clear_pending_responses(&mut self, conn_id: ConnectionId) -> Option<usize> {
+        let entry = self.connections.get_mut(&conn_id)?;
+        Some(entry.worker.clear_pending_responses())
     }
 
     /// Add a session to a connection, returns session ID
@@ -267,11 +423,108 @@ impl Registry {
             .remove(&session_id)
     }
 
+    /// Liveness of a connection's socket, or `None` if the id is unknown.
+    #[must_use]
+    pub fn connection_state(&self, conn_id: ConnectionId) -> Option<ConnectionState> {
+        self.connections
+            .get(&conn_id)
+            .map(|entry| entry.worker.connection_state())
+    }
+
+    /// The namespace `session` was last seen in. `None` if the id is unknown.
+    #[must_use]
+    pub fn session_ns(&self, conn_id: ConnectionId, session: &Session) -> Option<Option<String>> {
+        self.connections
+            .get(&conn_id)
+            .map(|entry| entry.worker.session_ns(session))
+    }
+
+    /// The sessions a connection has cloned or used, with their labels and
+    /// request counts. `None` if the id is unknown.
+    #[must_use]
+    pub fn sessions(&self, conn_id: ConnectionId) -> Option<Vec<SessionInfo>> {
+        self.connections
+            .get(&conn_id)
+            .map(|entry| entry.worker.sessions())
+    }
+
+    /// Reconcile a connection's tracked sessions with `live`, the server's
+    /// `ls-sessions` answer, and drop every handle to a session the server
+    /// no longer has. `None` if the id is unknown.
+    pub fn reconcile_sessions(
+        &mut self,
+        conn_id: ConnectionId,
+        live: &[String],
+        adopt_orphans: bool,
+    ) -> Option<SessionReconciliation> {
+        let entry = self.connections.get_mut(&conn_id)?;
+        let changes = entry.worker.reconcile_sessions_with(live, adopt_orphans);
+        entry
+            .sessions
+            .retain(|_, session| live.iter().any(|id| id == session.id()));
+        Some(changes)
+    }
+
+    /// Label `session` for [`sessions`](Self::sessions). Returns false if the
+    /// connection id is unknown.
+    pub fn label_session(
+        &self,
+        conn_id: ConnectionId,
+        session: &Session,
+        label: Option<String>,
+    ) -> bool {
+        let Some(entry) = self.connections.get(&conn_id) else {
+            return false;
+        };
+        entry.worker.label_session(session, label);
+        true
+    }
+
+    /// The server's flavor and ops as read from `describe` on connect.
+    /// `None` if the id is unknown; an unanswered `describe` reads as an
+    /// unknown flavor.
+    #[must_use]
+    pub fn server_profile(&self, conn_id: ConnectionId) -> Option<Option<ServerProfile>> {
+        self.connections
+            .get(&conn_id)
+            .map(|entry| entry.worker.server_profile())
+    }
+
+    /// A connection's recent significant events, or `None` if the id is
+    /// unknown.
+    #[must_use]
+    pub fn debug_events(&self, conn_id: ConnectionId) -> Option<Vec<DebugEvent>> {
+        self.connections
+            .get(&conn_id)
+            .map(|entry| entry.worker.debug_events())
+    }
+
+    /// Zero a connection's traffic metrics. Returns false if the id is unknown.
+    pub fn reset_metrics(&self, conn_id: ConnectionId) -> bool {
+        let Some(entry) = self.connections.get(&conn_id) else {
+            return false;
+        };
+        if let Some(metrics) = entry.worker.metrics() {
+            metrics.reset();
+        }
+        true
+    }
+
     /// Remove a connection and all its sessions
     pub fn remove_connection(&mut self, conn_id: ConnectionId) -> bool {
         self.connections.remove(&conn_id).is_some()
     }
 
+    /// Remove a connection, dropping its socket at once rather than waiting
+    /// on a server that may be hung.
+    pub fn abort_connection(&mut self, conn_id: ConnectionId) -> bool {
+        let Some(entry) = self.connections.remove(&conn_id) else {
+            return false;
+        };
+        entry.worker.abort();
+        true
+    }
+
     /// Get registry statistics for observability
     ///
     /// Returns statistics about connections and sessions in the registry.
@@ -290,6 +543,11 @@ impl Registry {
             .map(|(conn_id, entry)| ConnectionStats {
                 connection_id: *conn_id,
                 session_count: entry.sessions.len(),
+                metrics: entry
+                    .worker
+                    .metrics()
+                    .map(nrepl_rs::ClientMetrics::snapshot),
+                timeouts: *entry.worker.timeouts(),
             })
             .collect();
 
@@ -308,6 +566,10 @@ impl Registry {
 pub struct ConnectionStats {
     pub connection_id: ConnectionId,
     pub session_count: usize,
+    /// Traffic counters, when the connection collects them.
+    pub metrics: Option<MetricsSnapshot>,
+    /// How long its blocking ops wait for replies.
+    pub timeouts: Timeouts,
 }
 
 /// Registry statistics for observability
@@ -348,11 +610,53 @@ pub fn create_and_connect(address: String) -> Result<ConnectionId, NReplError> {
 
     // Create the worker and connect WITHOUT holding the registry lock - the
     // connect blocks up to 30s and must not stall other connections' ops.
-    let worker = Worker::new();
+    // An editor user who hits a timeout has given up on the form, so stop it
+    // on the server too rather than leave the session busy. The heartbeat lets
+    // the editor show a stalled server before an eval times out on it.
+    let ready = ReadyQueue::default();
+    let output = LiveOutput::default();
+    let worker = Worker::with_config(
+        WorkerConfig::default()
+            .interrupt_on_timeout(true)
+            .heartbeat(HEARTBEAT_INTERVAL)
+            .collect_metrics(true)
+            .detect_server(true)
+            .on_response({
+                let ready = Arc::clone(&ready);
+                move |id| {
+                    let mut ready = ready.lock().unwrap();
+                    if ready.len() >= MAX_READY {
+                        ready.pop_front();
+                    }
+                    ready.push_back(id);
+                }
+            })
+            .on_eval_output({
+                let output = Arc::clone(&output);
+                move |id, chunk| {
+                    let mut output = output.lock().unwrap();
+                    if !output.contains_key(&id) && output.len() >= MAX_LIVE_REQUESTS {
+                        let oldest = output.keys().min().copied();
+                        if let Some(oldest) = oldest {
+                            output.remove(&oldest);
+                        }
+                    }
+                    let chunks = output.entry(id).or_default();
+                    if chunks.len() >= MAX_LIVE_CHUNKS {
+                        chunks.pop_front();
+                    }
+                    chunks.push_back((chunk.kind, chunk.text.clone()));
+                }
+            }),
+    );
     worker.connect_blocking(address)?;
 
     // Register the connected worker under a brief lock.
-    match REGISTRY.lock().unwrap().insert_connected_worker(worker) {
+    match REGISTRY
+        .lock()
+        .unwrap()
+        .insert_connected_worker(worker, ready, output)
+    {
         Ok(id) => Ok(id),
         Err(_worker) => Err(NReplError::protocol(format!(
             "Maximum connections ({MAX_CONNECTIONS}) exceeded. Close unused connections before creating new ones."
@@ -368,21 +672,20 @@ fn channel_for(
     REGISTRY.lock().unwrap().channel_for(conn_id)
 }
 
-/// Send a command and wait up to 30s for its one-shot reply, holding no lock.
+/// Send a command and wait up to `timeout` for its one-shot reply, holding
+/// no lock.
 fn send_and_wait<T>(
     tx: &UnboundedSender<WorkerCommand>,
     cmd: WorkerCommand,
     reply_rx: &std::sync::mpsc::Receiver<Result<T, NReplError>>,
     operation: &str,
+    timeout: Duration,
 ) -> Result<T, NReplError> {
     tx.send(cmd)
         .map_err(|_| NReplError::Connection(std::io::Error::other("Worker thread disconnected")))?;
     reply_rx
-        .recv_timeout(Duration::from_secs(30))
-        .map_err(|_| NReplError::Timeout {
-            operation: operation.to_string(),
-            duration: Duration::from_secs(30),
-        })?
+        .recv_timeout(timeout)
+        .map_err(|_| NReplError::timeout(operation, timeout))?
 }
 
 #[must_use]
@@ -401,6 +704,20 @@ pub fn submit_eval(
         .submit_eval(conn_id, session, code, timeout, file, line, column)
 }
 
+#[must_use]
+pub fn submit_eval_in_ns(
+    conn_id: ConnectionId,
+    session: &Session,
+    ns: &str,
+    code: String,
+    timeout: Option<Duration>,
+) -> Option<Result<RequestId, SubmitError>> {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .submit_eval_in_ns(conn_id, session, ns, code, timeout)
+}
+
 #[must_use]
 pub fn submit_load_file(
     conn_id: ConnectionId,
@@ -425,17 +742,133 @@ pub fn try_recv_response(
         .try_recv_response(conn_id, request_id)
 }
 
+pub fn try_recv_any_response(conn_id: ConnectionId) -> Result<Option<EvalResponse>, NReplError> {
+    REGISTRY.lock().unwrap().try_recv_any_response(conn_id)
+}
+
+pub fn take_output(
+    conn_id: ConnectionId,
+    request_id: RequestId,
+) -> Result<Vec<(ChunkKind, String)>, NReplError> {
+    REGISTRY.lock().unwrap().take_output(conn_id, request_id)
+}
+
+/// The completed responses among `request_ids`, under one lock.
+pub fn try_recv_responses(
+    conn_id: ConnectionId,
+    request_ids: &[RequestId],
+) -> Result<Vec<EvalResponse>, NReplError> {
+    let mut registry = REGISTRY.lock().unwrap();
+    let mut responses = Vec::new();
+    for &request_id in request_ids {
+        responses.extend(registry.try_recv_response(conn_id, request_id)?);
+    }
+    Ok(responses)
+}
+
+/// Take every finished eval and load-file response since the last call,
+/// across all connections.
+#[must_use]
+pub fn drain_ready() -> Vec<(ConnectionId, EvalResponse)> {
+    REGISTRY.lock().unwrap().drain_ready()
+}
+
+/// Submit an eval and block until its result arrives.
+///
+/// Polls like the Steel poll loop does, taking the registry lock only for each
+/// brief `try_recv_response`. The worker's eval deadline bounds the wait. An
+/// eval that stops on `need-input` is sent end of input (an empty `stdin`), so
+/// it reads EOF and finishes instead of staying parked on the server.
+pub fn eval_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    code: String,
+    timeout: Duration,
+) -> Result<EvalResult, NReplError> {
+    let request_id = submit_eval(
+        conn_id,
+        session.clone(),
+        code,
+        Some(timeout),
+        None,
+        None,
+        None,
+    )
+    .ok_or_else(|| {
+        NReplError::protocol(format!(
+            "Connection {} not found. Create a connection with nrepl-connect first.",
+            conn_id.as_usize()
+        ))
+    })?
+    .map_err(|e| NReplError::Connection(std::io::Error::other(e.to_string())))?;
+
+    loop {
+        match try_recv_response(conn_id, request_id)? {
+            Some(response) => match response.outcome {
+                EvalOutcome::Done(result) => return result,
+                EvalOutcome::NeedInput { .. } => {
+                    stdin_blocking(conn_id, session.clone(), String::new())?;
+                }
+            },
+            None => std::thread::sleep(Duration::from_millis(10)),
+        }
+    }
+}
+
 /// Shared shell for the blocking control ops: mint an op id and command sender
-/// under a brief registry lock, then send and await the one-shot reply holding
-/// no lock (a 30s wait under the global lock would stall every connection).
+/// and look up the connection's timeout for `operation` under a brief registry
+/// lock, then send and await the one-shot reply holding no lock (a 30s wait
+/// under the global lock would stall every connection).
 fn blocking_op<T>(
     conn_id: ConnectionId,
     operation: &str,
     build: impl FnOnce(RequestId, Sender<Result<T, NReplError>>) -> WorkerCommand,
 ) -> Result<T, NReplError> {
-    let (tx, op_id) = channel_for(conn_id)?;
+    let timeout = op_timeout(&REGISTRY.lock().unwrap(), conn_id, operation);
+    blocking_op_within(conn_id, operation, timeout, build)
+}
+
+/// How long the connection's [`Timeouts`] give `operation`.
+fn op_timeout(registry: &Registry, conn_id: ConnectionId, operation: &str) -> Duration {
+    // Ops here are named `clone_session` and the like.
+    registry
+        .timeouts(conn_id)
+        .unwrap_or_default()
+        .for_op(&operation.replace('_', "-"))
+}
+
+/// [`blocking_op`], waiting `timeout` for the reply.
+fn blocking_op_within<T>(
+    conn_id: ConnectionId,
+    operation: &str,
+    timeout: Duration,
+    build: impl FnOnce(RequestId, Sender<Result<T, NReplError>>) -> WorkerCommand,
+) -> Result<T, NReplError> {
+    let (tx, op_id) = REGISTRY.lock().unwrap().channel_for(conn_id)?;
     let (reply_tx, reply_rx) = channel();
-    send_and_wait(&tx, build(op_id, reply_tx), &reply_rx, operation)
+    send_and_wait(&tx, build(op_id, reply_tx), &reply_rx, operation, timeout)
+}
+
+/// [`blocking_op`] for ops that only read server state, retried by the
+/// connection's [`RetryPolicy`] when they fail transiently. The op's timeout
+/// bounds all the attempts together, so the caller blocks no longer than it
+/// would without retries. Each attempt takes the lock afresh, so a
+/// connection closed between attempts stops the retries.
+fn idempotent_op<T>(
+    conn_id: ConnectionId,
+    operation: &str,
+    mut build: impl FnMut(RequestId, Sender<Result<T, NReplError>>) -> WorkerCommand,
+) -> Result<T, NReplError> {
+    let (policy, timeout) = {
+        let registry = REGISTRY.lock().unwrap();
+        let policy = registry
+            .retry_policy(conn_id)
+            .unwrap_or_else(RetryPolicy::never);
+        (policy, op_timeout(&registry, conn_id, operation))
+    };
+    policy.run_within(timeout, |wait| {
+        blocking_op_within(conn_id, operation, wait, &mut build)
+    })
 }
 
 pub fn clone_session_blocking(conn_id: ConnectionId) -> Result<Session, NReplError> {
@@ -503,6 +936,11 @@ static PENDING_COMPLETIONS: LazyLock<
 static PENDING_LOOKUPS: LazyLock<Mutex<HashMap<ConnectionId, PendingOp<Response>>>> =
     LazyLock::new(|| Mutex::new(HashMap::new()));
 
+/// Pending test runs, single-flight per connection (see
+/// [`PENDING_COMPLETIONS`]).
+static PENDING_TESTS: LazyLock<Mutex<HashMap<ConnectionId, PendingOp<TestResults>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
 /// Poll a pending op map (non-blocking).
 ///
 /// Returns `Ok(None)` while the reply is pending. A missing or superseded
@@ -552,17 +990,44 @@ pub fn submit_completions(
     ns: Option<String>,
     complete_fn: Option<String>,
 ) -> Result<RequestId, NReplError> {
-    let (tx, op_id) = channel_for(conn_id)?;
-    let (reply_tx, reply_rx) = channel();
-    tx.send(WorkerCommand::Completions {
+    submit_pending_completions(conn_id, |op_id, reply| WorkerCommand::Completions {
         op_id,
         session,
         prefix,
         ns,
         complete_fn,
-        reply: reply_tx,
+        reply,
     })
-    .map_err(|_| NReplError::Connection(std::io::Error::other("Worker thread disconnected")))?;
+}
+
+/// Submit completions for a possibly alias-qualified prefix (`str/sta`,
+/// see [`WorkerCommand::AliasedCompletions`]). Shares the single-flight slot
+/// and poller ([`try_get_completions`]) with [`submit_completions`].
+pub fn submit_aliased_completions(
+    conn_id: ConnectionId,
+    session: Session,
+    prefix: String,
+    ns: Option<String>,
+    complete_fn: Option<String>,
+) -> Result<RequestId, NReplError> {
+    submit_pending_completions(conn_id, |op_id, reply| WorkerCommand::AliasedCompletions {
+        op_id,
+        session,
+        prefix,
+        ns,
+        complete_fn,
+        reply,
+    })
+}
+
+fn submit_pending_completions(
+    conn_id: ConnectionId,
+    build: impl FnOnce(RequestId, Sender<Result<Vec<CompletionCandidate>, NReplError>>) -> WorkerCommand,
+) -> Result<RequestId, NReplError> {
+    let (tx, op_id) = channel_for(conn_id)?;
+    let (reply_tx, reply_rx) = channel();
+    tx.send(build(op_id, reply_tx))
+        .map_err(|_| NReplError::Connection(std::io::Error::other("Worker thread disconnected")))?;
     PENDING_COMPLETIONS.lock().unwrap().insert(
         conn_id,
         PendingOp {
@@ -621,8 +1086,265 @@ pub fn try_get_lookup(
     try_get_pending(&PENDING_LOOKUPS, conn_id, request_id, "lookup")
 }
 
+/// Submit a test run (non-blocking). Returns the request id to poll with
+/// [`try_get_tests`]. Single-flight per connection. Test runs can take far
+/// longer than the blocking-op timeout, hence submit/poll.
+pub fn submit_tests(
+    conn_id: ConnectionId,
+    session: Session,
+    selection: TestSelection,
+) -> Result<RequestId, NReplError> {
+    let (tx, op_id) = channel_for(conn_id)?;
+    let (reply_tx, reply_rx) = channel();
+    tx.send(WorkerCommand::RunTests {
+        op_id,
+        session,
+        selection,
+        reply: reply_tx,
+    })
+    .map_err(|_| NReplError::Connection(std::io::Error::other("Worker thread disconnected")))?;
+    PENDING_TESTS.lock().unwrap().insert(
+        conn_id,
+        PendingOp {
+            request_id: op_id,
+            receiver: reply_rx,
+        },
+    );
+    Ok(op_id)
+}
+
+/// Poll for a submitted test run (non-blocking). `Ok(None)` while pending;
+/// an error once the request is superseded or the connection closed.
+pub fn try_get_tests(
+    conn_id: ConnectionId,
+    request_id: RequestId,
+) -> Result<Option<TestResults>, NReplError> {
+    try_get_pending(&PENDING_TESTS, conn_id, request_id, "test")
+}
+
+pub fn test_stacktrace_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    ns: String,
+    var: String,
+    index: u32,
+) -> Result<Option<StackTrace>, NReplError> {
+    blocking_op(conn_id, "test_stacktrace", |op_id, reply| {
+        WorkerCommand::TestStacktrace {
+            op_id,
+            session,
+            ns,
+            var,
+            index,
+            reply,
+        }
+    })
+}
+
+pub fn apropos_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    query: String,
+    search_ns: Option<String>,
+    docs: bool,
+    privates: bool,
+) -> Result<Vec<AproposMatch>, NReplError> {
+    blocking_op(conn_id, "apropos", |op_id, reply| WorkerCommand::Apropos {
+        op_id,
+        session,
+        query,
+        search_ns,
+        docs,
+        privates,
+        reply,
+    })
+}
+
+pub fn ns_list_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+) -> Result<Vec<String>, NReplError> {
+    blocking_op(conn_id, "ns_list", |op_id, reply| WorkerCommand::NsList {
+        op_id,
+        session,
+        reply,
+    })
+}
+
+pub fn ns_vars_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    ns: String,
+) -> Result<Vec<NsVar>, NReplError> {
+    blocking_op(conn_id, "ns_vars", |op_id, reply| WorkerCommand::NsVars {
+        op_id,
+        session,
+        ns,
+        reply,
+    })
+}
+
+pub fn ns_path_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    ns: String,
+) -> Result<Option<String>, NReplError> {
+    blocking_op(conn_id, "ns_path", |op_id, reply| WorkerCommand::NsPath {
+        op_id,
+        session,
+        ns,
+        reply,
+    })
+}
+
+pub fn classpath_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+) -> Result<Vec<String>, NReplError> {
+    blocking_op(conn_id, "classpath", |op_id, reply| {
+        WorkerCommand::Classpath {
+            op_id,
+            session,
+            reply,
+        }
+    })
+}
+
+pub fn undef_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    ns: String,
+    sym: String,
+) -> Result<(), NReplError> {
+    blocking_op(conn_id, "undef", |op_id, reply| WorkerCommand::Undef {
+        op_id,
+        session,
+        ns,
+        sym,
+        reply,
+    })
+}
+
+pub fn toggle_trace_var_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    ns: String,
+    sym: String,
+) -> Result<VarTrace, NReplError> {
+    blocking_op(conn_id, "toggle_trace_var", |op_id, reply| {
+        WorkerCommand::ToggleTraceVar {
+            op_id,
+            session,
+            ns,
+            sym,
+            reply,
+        }
+    })
+}
+
+pub fn toggle_trace_ns_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    ns: String,
+) -> Result<TraceState, NReplError> {
+    blocking_op(conn_id, "toggle_trace_ns", |op_id, reply| {
+        WorkerCommand::ToggleTraceNs {
+            op_id,
+            session,
+            ns,
+            reply,
+        }
+    })
+}
+
+pub fn format_code_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    code: String,
+) -> Result<String, NReplError> {
+    blocking_op(conn_id, "format_code", |op_id, reply| {
+        WorkerCommand::FormatCode {
+            op_id,
+            session,
+            code,
+            reply,
+        }
+    })
+}
+
+pub fn format_edn_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    edn: String,
+    right_margin: Option<u32>,
+) -> Result<String, NReplError> {
+    blocking_op(conn_id, "format_edn", |op_id, reply| {
+        WorkerCommand::FormatEdn {
+            op_id,
+            session,
+            edn,
+            right_margin,
+            reply,
+        }
+    })
+}
+
+pub fn refresh_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    all: bool,
+) -> Result<RefreshReport, NReplError> {
+    blocking_op(conn_id, "refresh", |op_id, reply| WorkerCommand::Refresh {
+        op_id,
+        session,
+        all,
+        options: RefreshOptions::default(),
+        reply,
+    })
+}
+
+pub fn refresh_clear_blocking(conn_id: ConnectionId, session: Session) -> Result<(), NReplError> {
+    blocking_op(conn_id, "refresh_clear", |op_id, reply| {
+        WorkerCommand::RefreshClear {
+            op_id,
+            session,
+            reply,
+        }
+    })
+}
+
+pub fn inspect_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    code: String,
+    ns: Option<String>,
+) -> Result<InspectorPage, NReplError> {
+    blocking_op(conn_id, "inspect", |op_id, reply| WorkerCommand::Inspect {
+        op_id,
+        session,
+        code,
+        ns,
+        reply,
+    })
+}
+
+pub fn inspector_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    action: InspectorAction,
+) -> Result<InspectorPage, NReplError> {
+    blocking_op(conn_id, "inspector", |op_id, reply| {
+        WorkerCommand::Inspector {
+            op_id,
+            session,
+            action,
+            reply,
+        }
+    })
+}
+
 pub fn describe_blocking(conn_id: ConnectionId, verbose: bool) -> Result<Response, NReplError> {
-    blocking_op(conn_id, "describe", |op_id, reply| {
+    idempotent_op(conn_id, "describe", |op_id, reply| {
         WorkerCommand::Describe {
             op_id,
             verbose,
@@ -631,12 +1353,80 @@ pub fn describe_blocking(conn_id: ConnectionId, verbose: bool) -> Result<Respons
     })
 }
 
+/// Resynchronize a connection (see [`WorkerCommand::Resync`]), also dropping
+/// the unclaimed responses buffered on this side of the worker.
+///
+/// The buffer is cleared first, under a brief lock, so the failures the worker
+/// reports for in-flight requests arrive afterwards and still reach whoever is
+/// polling for them.
+pub fn resync_blocking(conn_id: ConnectionId) -> Result<ResyncReport, NReplError> {
+    let responses_dropped = REGISTRY
+        .lock()
+        .unwrap()
+        .clear_pending_responses(conn_id)
+        .unwrap_or(0);
+    let report = blocking_op(conn_id, "resync", |op_id, reply| WorkerCommand::Resync {
+        op_id,
+        reply,
+    })?;
+    Ok(ResyncReport {
+        responses_dropped,
+        ..report
+    })
+}
+
+pub fn ns_aliases_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    ns: String,
+) -> Result<NsAliases, NReplError> {
+    blocking_op(conn_id, "ns_aliases", |op_id, reply| {
+        WorkerCommand::NsAliases {
+            op_id,
+            session,
+            ns,
+            reply,
+        }
+    })
+}
+
 pub fn ls_sessions_blocking(conn_id: ConnectionId) -> Result<Vec<String>, NReplError> {
-    blocking_op(conn_id, "ls_sessions", |op_id, reply| {
+    idempotent_op(conn_id, "ls_sessions", |op_id, reply| {
         WorkerCommand::LsSessions { op_id, reply }
     })
 }
 
+pub fn add_middleware_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    middleware: Vec<String>,
+) -> Result<(), NReplError> {
+    blocking_op(conn_id, "add_middleware", |op_id, reply| {
+        WorkerCommand::AddMiddleware {
+            op_id,
+            session,
+            middleware,
+            extra_namespaces: Vec::new(),
+            reply,
+        }
+    })
+}
+
+pub fn upgrade_cljs_blocking(
+    conn_id: ConnectionId,
+    session: Session,
+    repl: CljsRepl,
+) -> Result<(), NReplError> {
+    blocking_op(conn_id, "upgrade_cljs", |op_id, reply| {
+        WorkerCommand::UpgradeCljs {
+            op_id,
+            session,
+            repl,
+            reply,
+        }
+    })
+}
+
 #[must_use]
 pub fn add_session(conn_id: ConnectionId, session: Session) -> Option<SessionId> {
     REGISTRY.lock().unwrap().add_session(conn_id, session)
@@ -677,9 +1467,74 @@ pub fn remove_connection(conn_id: ConnectionId) -> bool {
     // of waiting on a connection that no longer exists.
     PENDING_COMPLETIONS.lock().unwrap().remove(&conn_id);
     PENDING_LOOKUPS.lock().unwrap().remove(&conn_id);
+    PENDING_TESTS.lock().unwrap().remove(&conn_id);
     REGISTRY.lock().unwrap().remove_connection(conn_id)
 }
 
+#[must_use]
+pub fn abort_connection(conn_id: ConnectionId) -> bool {
+    PENDING_COMPLETIONS.lock().unwrap().remove(&conn_id);
+    PENDING_LOOKUPS.lock().unwrap().remove(&conn_id);
+    PENDING_TESTS.lock().unwrap().remove(&conn_id);
+    REGISTRY.lock().unwrap().abort_connection(conn_id)
+}
+
+#[must_use]
+pub fn connection_state(conn_id: ConnectionId) -> Option<ConnectionState> {
+    REGISTRY.lock().unwrap().connection_state(conn_id)
+}
+
+#[must_use]
+pub fn session_ns(conn_id: ConnectionId, session: &Session) -> Option<Option<String>> {
+    REGISTRY.lock().unwrap().session_ns(conn_id, session)
+}
+
+#[must_use]
+pub fn sessions(conn_id: ConnectionId) -> Option<Vec<SessionInfo>> {
+    REGISTRY.lock().unwrap().sessions(conn_id)
+}
+
+/// Fetch the server's sessions holding no lock, then reconcile under it.
+pub fn reconcile_sessions(
+    conn_id: ConnectionId,
+    adopt_orphans: bool,
+) -> Result<SessionReconciliation, NReplError> {
+    let live = ls_sessions_blocking(conn_id)?;
+    REGISTRY
+        .lock()
+        .unwrap()
+        .reconcile_sessions(conn_id, &live, adopt_orphans)
+        .ok_or_else(|| {
+            NReplError::protocol(format!(
+                "Connection {} not found. Create a connection with nrepl-connect first.",
+                conn_id.as_usize()
+            ))
+        })
+}
+
+#[must_use]
+pub fn label_session(conn_id: ConnectionId, session: &Session, label: Option<String>) -> bool {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .label_session(conn_id, session, label)
+}
+
+#[must_use]
+pub fn server_profile(conn_id: ConnectionId) -> Option<Option<ServerProfile>> {
+    REGISTRY.lock().unwrap().server_profile(conn_id)
+}
+
+#[must_use]
+pub fn debug_events(conn_id: ConnectionId) -> Option<Vec<DebugEvent>> {
+    REGISTRY.lock().unwrap().debug_events(conn_id)
+}
+
+#[must_use]
+pub fn reset_metrics(conn_id: ConnectionId) -> bool {
+    REGISTRY.lock().unwrap().reset_metrics(conn_id)
+}
+
 #[must_use]
 pub fn get_stats() -> RegistryStats {
     REGISTRY.lock().unwrap().get_stats()
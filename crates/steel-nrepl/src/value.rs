@@ -0,0 +1,197 @@
+// Copyright (C) 2025 Tom Waddington
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+//! Structured results for Steel
+//!
+//! FFI functions that return more than a scalar build a [`Value`], which
+//! reaches Steel as native data: hashes become hashmaps keyed by strings,
+//! lists become lists, and a missing value is `#f`. Nothing is printed as
+//! source and read back, so a string from the server is only ever a string.
+//!
+//! ```scheme
+//! (define result (ffi.try-get-result conn-id req-id))
+//! (when result
+//!   (hash-get result "value"))
+//! ```
+
+use abi_stable::std_types::{RBoxError, RResult};
+use std::collections::BTreeMap;
+use steel::steel_vm::ffi::{FFIValue, IntoFFIVal};
+
+/// A result as Steel will see it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    List(Vec<Value>),
+    Hash(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// A hash of `entries`, for records with fixed keys.
+    pub fn hash<K: Into<String>>(entries: impl IntoIterator<Item = (K, Value)>) -> Self {
+        Value::Hash(entries.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    /// A list of `items`.
+    pub fn list<T: Into<Value>>(items: impl IntoIterator<Item = T>) -> Self {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+
+    /// The entry under `key`, if this is a hash holding one.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Hash(entries) => entries.get(key),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn into_ffi(self) -> FFIValue {
+        match self {
+            Value::Bool(b) => FFIValue::BoolV(b),
+            Value::Int(n) => FFIValue::IntV(isize::try_from(n).unwrap_or(isize::MAX)),
+            Value::Float(f) => FFIValue::NumV(f),
+            Value::String(s) => FFIValue::StringV(s.into()),
+            Value::List(items) => {
+                FFIValue::Vector(items.into_iter().map(Value::into_ffi).collect())
+            }
+            Value::Hash(entries) => FFIValue::HashMap(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (FFIValue::StringV(k.into()), v.into_ffi()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl IntoFFIVal for Value {
+    fn into_ffi_val(self) -> RResult<FFIValue, RBoxError> {
+        RResult::ROk(self.into_ffi())
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Value::Float(f)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&String> for Value {
+    fn from(s: &String) -> Self {
+        Value::String(s.clone())
+    }
+}
+
+/// Counts and sizes past `i64::MAX` saturate; none get near it.
+macro_rules! int_value {
+    ($($t:ty),*) => {$(
+        impl From<$t> for Value {
+            fn from(n: $t) -> Self {
+                Value::Int(i64::try_from(n).unwrap_or(i64::MAX))
+            }
+        }
+    )*};
+}
+
+int_value!(i32, i64, isize, u32, u64, usize, u128);
+
+/// `None` is `#f`, as everywhere in the plugin.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Value::Bool(false), Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        Value::list(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_values_are_false() {
+        let v = Value::hash([
+            ("name", Some("map").into()),
+            ("doc", None::<String>.into()),
+            ("line", Some(12_u32).into()),
+        ]);
+        assert_eq!(v.get("name").and_then(Value::as_str), Some("map"));
+        assert_eq!(v.get("doc"), Some(&Value::Bool(false)));
+        assert_eq!(v.get("line").and_then(Value::as_int), Some(12));
+        assert_eq!(v.get("absent"), None);
+    }
+
+    #[test]
+    fn test_strings_are_kept_verbatim() {
+        // What used to need escaping is now just data.
+        let s = "He said \"hi\"\n\t\\ (hash 'x 1) 世界";
+        assert_eq!(Value::from(s).as_str(), Some(s));
+    }
+}
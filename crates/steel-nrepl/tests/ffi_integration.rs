@@ -13,7 +13,7 @@
 //! FFI Integration tests for steel-nrepl
 //!
 //! These tests verify the entire FFI stack from connection through evaluation,
-//! including S-expression format generation and error propagation.
+//! including the shape of the structured results and error propagation.
 //!
 //! **Note:** Registry statistics tests have been moved to `registry_stats.rs`
 //! to allow them to run in isolation (required for predictable connection counts).
@@ -29,9 +29,12 @@
 
 use std::{thread, time::Duration};
 use steel_nrepl::connection::{
-    nrepl_attach_session, nrepl_clone_session, nrepl_close, nrepl_close_session_by_wire_id,
-    nrepl_connect, nrepl_ls_sessions, nrepl_stdin, nrepl_try_get_result,
+    nrepl_adopt_session, nrepl_attach_session, nrepl_clone_session, nrepl_close,
+    nrepl_close_session_by_wire_id, nrepl_connect, nrepl_describe, nrepl_drain_results,
+    nrepl_ls_sessions, nrepl_server_flavor, nrepl_stdin, nrepl_try_get_output,
+    nrepl_try_get_result,
 };
+use steel_nrepl::value::Value;
 
 /// Helper to connect to test server and return connection ID
 ///
@@ -43,7 +46,7 @@ fn connect_test_server() -> usize {
 }
 
 /// Helper to poll for result with timeout
-/// Returns Result<Option<String>, Error> where:
+/// Returns Result<Option<Value>, Error> where:
 /// - Ok(Some(result)) = Got result
 /// - Ok(None) = Timeout waiting for result
 /// - Err(e) = Error occurred (e.g., nREPL timeout, connection error)
@@ -51,7 +54,7 @@ fn poll_for_result(
     conn_id: usize,
     request_id: usize,
     timeout_ms: u64,
-) -> Result<Option<String>, String> {
+) -> Result<Option<Value>, String> {
     let start = std::time::Instant::now();
     let timeout = Duration::from_millis(timeout_ms);
 
@@ -72,76 +75,26 @@ fn poll_for_result(
     Ok(None) // Polling timeout (result never arrived)
 }
 
-/// Parse S-expression hash to verify format (simple validation)
+/// Pull the fields the tests check out of an eval result hash
 /// Returns (value, `output_count`, `has_error`, namespace)
-fn parse_sexpr_hash(sexpr: &str) -> (Option<String>, usize, bool, Option<String>) {
-    // This is a simple parser to verify the basic format
-    // A proper parser would use a full S-expression library
-
-    // Should start with "(hash "
-    assert!(
-        sexpr.starts_with("(hash "),
-        "S-expr should start with '(hash ', got: {sexpr}"
-    );
-    assert!(sexpr.ends_with(')'), "S-expr should end with ')'");
-
-    // Extract value
-    let value = if sexpr.contains("'value \"") {
-        let start = sexpr
-            .find("'value \"")
-            .expect("Failed to find 'value \" start position in S-expr")
-            + 8;
-        let rest = &sexpr[start..];
-        let end = rest
-            .find('"')
-            .expect("Failed to find closing quote for 'value field in S-expr");
-        Some(rest[..end].to_string())
-    } else if sexpr.contains("'value #f") {
-        None
-    } else {
-        panic!("Could not find 'value in S-expr: {sexpr}");
-    };
-
-    // Count output list items
-    let output_count = if sexpr.contains("'output (list") {
-        let start = sexpr
-            .find("'output (list")
-            .expect("Failed to find 'output (list start position in S-expr")
-            + 14;
-        let rest = &sexpr[start..];
-        let end = rest
-            .find(')')
-            .expect("Failed to find closing paren for 'output list in S-expr");
-        let list_contents = &rest[..end];
-        if list_contents.trim().is_empty() {
-            0
-        } else {
-            // Count quoted strings
-            list_contents.matches('"').count() / 2
-        }
-    } else {
-        0
+fn result_fields(result: &Value) -> (Option<String>, usize, bool, Option<String>) {
+    let text = |key: &str| {
+        result
+            .get(key)
+            .unwrap_or_else(|| panic!("result has no {key:?}: {result:?}"))
+            .as_str()
+            .map(str::to_string)
     };
-
-    // Check for error
-    let has_error = !sexpr.contains("'error #f");
-
-    // Extract namespace
-    let ns = if sexpr.contains("'ns \"") {
-        let start = sexpr
-            .find("'ns \"")
-            .expect("Failed to find 'ns \" start position in S-expr")
-            + 5;
-        let rest = &sexpr[start..];
-        let end = rest
-            .find('"')
-            .expect("Failed to find closing quote for 'ns field in S-expr");
-        Some(rest[..end].to_string())
-    } else {
-        None
-    };
-
-    (value, output_count, has_error, ns)
+    let output_count = result
+        .get("output")
+        .and_then(Value::as_list)
+        .map_or(0, <[Value]>::len);
+    (
+        text("value"),
+        output_count,
+        text("error").is_some(),
+        text("ns"),
+    )
 }
 
 #[test]
@@ -190,8 +143,7 @@ fn test_ffi_eval_simple_expression() {
         .expect("Failed to poll for result")
         .expect("Timeout waiting for eval result");
 
-    // Parse S-expression
-    let (value, output_count, has_error, ns) = parse_sexpr_hash(&result);
+    let (value, output_count, has_error, ns) = result_fields(&result);
 
     assert_eq!(value, Some("3".to_string()), "Value should be 3");
     assert_eq!(output_count, 0, "Should have no output");
@@ -218,8 +170,7 @@ fn test_ffi_eval_simple_expression2() {
         .expect("Failed to poll for result")
         .expect("Timeout waiting for eval result");
 
-    // Parse S-expression
-    let (value, output_count, has_error, ns) = parse_sexpr_hash(&result);
+    let (value, output_count, has_error, ns) = result_fields(&result);
 
     assert_eq!(value, Some("-1".to_string()), "Value should be -1");
     assert_eq!(output_count, 0, "Should have no output");
@@ -251,13 +202,135 @@ fn test_ffi_eval_with_output() {
         .expect("Failed to poll for result")
         .expect("Timeout waiting for eval result");
 
-    // Parse S-expression
-    let (value, output_count, has_error, _ns) = parse_sexpr_hash(&result);
+    let (value, output_count, has_error, _ns) = result_fields(&result);
 
     assert_eq!(value, Some("3".to_string()), "Value should be 3");
     assert!(output_count > 0, "Should have output");
     assert!(!has_error, "Should have no error");
-    assert!(result.contains("hello"), "Output should contain 'hello'");
+    let output = result
+        .get("output")
+        .and_then(Value::as_list)
+        .unwrap_or_default();
+    assert!(
+        output
+            .iter()
+            .any(|line| line.as_str().is_some_and(|l| l.contains("hello"))),
+        "Output should contain 'hello'"
+    );
+
+    nrepl_close(conn_id).expect("Failed to close connection");
+}
+
+/// The stdout text of a `try-get-output` batch, in order.
+fn stdout_text(chunks: &Value) -> String {
+    chunks
+        .as_list()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|chunk| match chunk.as_list()? {
+            [kind, text] if kind.as_str() == Some("stdout") => text.as_str(),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+#[ignore = "requires a running nREPL server"]
+fn test_ffi_try_get_output_streams_in_pieces() {
+    let conn_id = connect_test_server();
+    let mut session = nrepl_clone_session(conn_id).expect("Failed to clone session");
+
+    let request_id = session
+        .eval_with_timeout(
+            "(dotimes [i 20] (println i) (Thread/sleep 50))",
+            60_000,
+            None,
+            None,
+            None,
+        )
+        .expect("Failed to submit eval");
+
+    // Take the output as it is printed, until the result is in.
+    let mut streamed = String::new();
+    let mut pieces = 0;
+    let start = std::time::Instant::now();
+    let result = loop {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "Timeout waiting for eval result"
+        );
+        let taken = nrepl_try_get_output(conn_id, request_id).expect("try-get-output failed");
+        let text = stdout_text(&taken);
+        if !text.is_empty() {
+            pieces += 1;
+            streamed.push_str(&text);
+        }
+        match nrepl_try_get_result(conn_id, request_id).expect("try-get-result failed") {
+            Some(result) => break result,
+            None => thread::sleep(Duration::from_millis(100)),
+        }
+    };
+
+    let full: String = (0..20).map(|i| format!("{i}\n")).collect();
+    assert!(
+        pieces > 1,
+        "Output should arrive in several pieces, got {pieces}"
+    );
+    // Output printed after the last take is only in the result, so what
+    // streamed is a prefix of it: each line once, in order.
+    assert!(
+        full.starts_with(&streamed),
+        "Streamed output should never repeat a line, got: {streamed:?}"
+    );
+    let output: String = result
+        .get("output")
+        .and_then(Value::as_list)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(Value::as_str)
+        .collect();
+    assert_eq!(output, full, "The result should carry all of the output");
+    let left = nrepl_try_get_output(conn_id, request_id).expect("try-get-output failed");
+    assert!(
+        left.as_list().is_some_and(<[Value]>::is_empty),
+        "Nothing should be left once the result is taken, got: {left:?}"
+    );
+
+    nrepl_close(conn_id).expect("Failed to close connection");
+}
+
+#[test]
+#[ignore = "requires a running nREPL server"]
+fn test_ffi_try_get_output_keeps_newest_chunks() {
+    let conn_id = connect_test_server();
+    let mut session = nrepl_clone_session(conn_id).expect("Failed to clone session");
+
+    // More lines than the 1000 chunks a request holds, none taken meanwhile.
+    let request_id = session
+        .eval_with_timeout(
+            "(do (dotimes [i 1500] (println i)) (Thread/sleep 2000))",
+            60_000,
+            None,
+            None,
+            None,
+        )
+        .expect("Failed to submit eval");
+    thread::sleep(Duration::from_secs(1));
+
+    let taken = nrepl_try_get_output(conn_id, request_id).expect("try-get-output failed");
+    let held = taken.as_list().unwrap_or_default().len();
+    assert!(
+        (1..=1000).contains(&held),
+        "At most 1000 chunks should be held, got {held}"
+    );
+    assert!(
+        stdout_text(&taken).ends_with("1499\n"),
+        "The newest output should be kept, got: {taken:?}"
+    );
+
+    poll_for_result(conn_id, request_id, 10_000)
+        .expect("Error waiting for result")
+        .expect("Timeout waiting for result");
 
     nrepl_close(conn_id).expect("Failed to close connection");
 }
@@ -278,8 +351,7 @@ fn test_ffi_eval_with_error() {
         .expect("Failed to poll for result")
         .expect("Timeout waiting for eval result");
 
-    // Parse S-expression
-    let (value, _output_count, has_error, _ns) = parse_sexpr_hash(&result);
+    let (value, _output_count, has_error, _ns) = result_fields(&result);
 
     // Either no value or has error
     assert!(
@@ -306,8 +378,7 @@ fn test_ffi_eval_with_timeout() {
         .expect("Failed to poll for result")
         .expect("Timeout waiting for eval result");
 
-    // Parse S-expression
-    let (value, _output_count, has_error, _ns) = parse_sexpr_hash(&result);
+    let (value, _output_count, has_error, _ns) = result_fields(&result);
 
     assert_eq!(value, Some("30".to_string()), "Value should be 30");
     assert!(!has_error, "Should have no error");
@@ -347,7 +418,7 @@ fn test_ffi_eval_timeout_fires() {
         .expect("Failed to poll for result")
         .expect("Connection should remain usable after timeout");
 
-    let (value, _, _, _) = parse_sexpr_hash(&result2);
+    let (value, _, _, _) = result_fields(&result2);
     assert_eq!(
         value,
         Some("3".to_string()),
@@ -408,9 +479,9 @@ fn test_ffi_concurrent_evals() {
         .expect("Timeout on eval 3");
 
     // Parse results
-    let (value1, _, _, _) = parse_sexpr_hash(&result1);
-    let (value2, _, _, _) = parse_sexpr_hash(&result2);
-    let (value3, _, _, _) = parse_sexpr_hash(&result3);
+    let (value1, _, _, _) = result_fields(&result1);
+    let (value2, _, _, _) = result_fields(&result2);
+    let (value3, _, _, _) = result_fields(&result3);
 
     assert_eq!(value1, Some("3".to_string()), "First eval should return 3");
     assert_eq!(
@@ -448,7 +519,7 @@ fn test_ffi_multiple_sessions() {
     let result1 = poll_for_result(conn_id, req1, 5000)
         .expect("Failed to poll")
         .expect("Timeout on session 1 eval");
-    let (value1, _, _, _) = parse_sexpr_hash(&result1);
+    let (value1, _, _, _) = result_fields(&result1);
     assert_eq!(value1, Some("30".to_string()), "Session 1 should return 30");
 
     // Eval in session 2
@@ -458,7 +529,7 @@ fn test_ffi_multiple_sessions() {
     let result2 = poll_for_result(conn_id, req2, 5000)
         .expect("Failed to poll")
         .expect("Timeout on session 2 eval");
-    let (value2, _, _, _) = parse_sexpr_hash(&result2);
+    let (value2, _, _, _) = result_fields(&result2);
     assert_eq!(value2, Some("30".to_string()), "Session 2 should return 30");
 
     // Check *1 in session 1 (should be 30 from + 10 20)
@@ -468,7 +539,7 @@ fn test_ffi_multiple_sessions() {
     let result3 = poll_for_result(conn_id, req3, 5000)
         .expect("Failed to poll")
         .expect("Timeout on *1 eval");
-    let (value3, _, _, _) = parse_sexpr_hash(&result3);
+    let (value3, _, _, _) = result_fields(&result3);
     assert_eq!(
         value3,
         Some("30".to_string()),
@@ -482,7 +553,7 @@ fn test_ffi_multiple_sessions() {
     let result4 = poll_for_result(conn_id, req4, 5000)
         .expect("Failed to poll")
         .expect("Timeout on *1 eval");
-    let (value4, _, _, _) = parse_sexpr_hash(&result4);
+    let (value4, _, _, _) = result_fields(&result4);
     assert_eq!(
         value4,
         Some("30".to_string()),
@@ -513,8 +584,7 @@ fn test_ffi_load_file() {
         .expect("Failed to poll for result")
         .expect("Timeout waiting for load-file result");
 
-    // Parse S-expression
-    let (_value, _, has_error, _) = parse_sexpr_hash(&result);
+    let (_value, _, has_error, _) = result_fields(&result);
     assert!(!has_error, "Load-file should not have error");
 
     // Verify the function was defined
@@ -524,7 +594,7 @@ fn test_ffi_load_file() {
     let result2 = poll_for_result(conn_id, req2, 5000)
         .expect("Failed to poll")
         .expect("Timeout on test-fn eval");
-    let (value2, _, _, _) = parse_sexpr_hash(&result2);
+    let (value2, _, _, _) = result_fields(&result2);
     assert_eq!(value2, Some("42".to_string()), "test-fn should return 42");
 
     nrepl_close(conn_id).expect("Failed to close connection");
@@ -532,7 +602,7 @@ fn test_ffi_load_file() {
 
 #[test]
 #[ignore = "requires a running nREPL server"]
-fn test_ffi_s_expression_escaping() {
+fn test_ffi_special_characters_pass_through() {
     let conn_id = connect_test_server();
     let mut session = nrepl_clone_session(conn_id).expect("Failed to clone session");
 
@@ -546,15 +616,14 @@ fn test_ffi_s_expression_escaping() {
         .expect("Failed to poll for result")
         .expect("Timeout waiting for eval result");
 
-    // Verify the S-expression has properly escaped strings
-    assert!(result.contains(r"\n"), "Should escape newlines");
-    assert!(result.contains(r"\t"), "Should escape tabs");
-    assert!(result.contains(r#"\""#), "Should escape quotes");
-
-    // Parse to verify format
-    let (value, _, has_error, _) = parse_sexpr_hash(&result);
+    // The printed string arrives exactly as the server sent it.
+    let (value, _, has_error, _) = result_fields(&result);
     assert!(!has_error, "Should have no error");
-    assert!(value.is_some(), "Should have a value");
+    assert_eq!(
+        value.as_deref(),
+        Some(r#""line1\nline2\ttab\"quoted\"""#),
+        "Value should be the printed string, untouched"
+    );
 
     nrepl_close(conn_id).expect("Failed to close connection");
 }
@@ -592,7 +661,7 @@ fn test_ffi_error_propagation() {
     let result1 = poll_for_result(conn_id, req1, 5000)
         .expect("Failed to poll")
         .expect("Timeout on eval");
-    let (_, _, has_error1, _) = parse_sexpr_hash(&result1);
+    let (_, _, has_error1, _) = result_fields(&result1);
     assert!(has_error1, "Syntax error should be reported");
 
     // 2. Undefined variable
@@ -602,7 +671,7 @@ fn test_ffi_error_propagation() {
     let result2 = poll_for_result(conn_id, req2, 5000)
         .expect("Failed to poll")
         .expect("Timeout on eval");
-    let (_, _, has_error2, _) = parse_sexpr_hash(&result2);
+    let (_, _, has_error2, _) = result_fields(&result2);
     assert!(has_error2, "Undefined variable should be reported");
 
     nrepl_close(conn_id).expect("Failed to close connection");
@@ -624,8 +693,7 @@ fn test_ffi_namespace_tracking() {
         .expect("Failed to poll for result")
         .expect("Timeout waiting for eval result");
 
-    // Parse S-expression
-    let (_, _, has_error, ns) = parse_sexpr_hash(&result);
+    let (_, _, has_error, ns) = result_fields(&result);
     assert!(!has_error, "Should not have error");
     assert_eq!(
         ns,
@@ -673,12 +741,14 @@ fn test_ffi_ls_sessions_attach_and_kill() {
     // ls-sessions must report A.
     let listed = nrepl_ls_sessions(conn_id).expect("ls-sessions failed");
     assert!(
-        listed.starts_with("(list ") && listed.ends_with(')'),
-        "ls-sessions should return a (list ...) source string, got: {listed}"
+        listed.as_list().is_some(),
+        "ls-sessions should return a list, got: {listed:?}"
     );
     assert!(
-        listed.contains(&wire_a),
-        "ls-sessions should contain session A ({wire_a}), got: {listed}"
+        listed
+            .as_list()
+            .is_some_and(|ids| ids.contains(&Value::from(&wire_a))),
+        "ls-sessions should contain session A ({wire_a}), got: {listed:?}"
     );
 
     // Define state in A, then attach by wire id and read it back: proves the
@@ -703,7 +773,7 @@ fn test_ffi_ls_sessions_attach_and_kill() {
     let result = poll_for_result(conn_id, req, 5000)
         .expect("Error waiting for probe result")
         .expect("Timeout waiting for probe result");
-    let (value, _, _, _) = parse_sexpr_hash(&result);
+    let (value, _, _, _) = result_fields(&result);
     assert_eq!(
         value,
         Some("42".to_string()),
@@ -717,13 +787,81 @@ fn test_ffi_ls_sessions_attach_and_kill() {
         .expect("Failed to read session B wire id");
     nrepl_close_session_by_wire_id(conn_id, &wire_b).expect("close-session-by-id failed");
     let listed = nrepl_ls_sessions(conn_id).expect("ls-sessions after kill failed");
+    let ids = listed.as_list().unwrap_or_default();
     assert!(
-        !listed.contains(&wire_b),
-        "killed session B ({wire_b}) should not be listed, got: {listed}"
+        !ids.contains(&Value::from(&wire_b)),
+        "killed session B ({wire_b}) should not be listed, got: {listed:?}"
     );
     assert!(
-        listed.contains(&wire_a),
-        "session A ({wire_a}) should survive B's kill, got: {listed}"
+        ids.contains(&Value::from(&wire_a)),
+        "session A ({wire_a}) should survive B's kill, got: {listed:?}"
+    );
+
+    nrepl_close(conn_id).expect("Failed to close connection");
+}
+
+#[test]
+#[ignore = "requires a running nREPL server"]
+fn test_ffi_adopt_session_accepts_listed_id() {
+    // A session another client made, as an earlier editor instance would
+    // have left behind.
+    let other_conn = connect_test_server();
+    let mut other = nrepl_clone_session(other_conn).expect("Failed to clone session");
+    let wire_id = other
+        .wire_session_id()
+        .expect("Failed to read session wire id");
+    let req = other
+        .eval_with_timeout("(def adopted-probe 7)", 60_000, None, None, None)
+        .expect("Failed to submit def");
+    poll_for_result(other_conn, req, 5000)
+        .expect("Error waiting for def result")
+        .expect("Timeout waiting for def result");
+
+    let conn_id = connect_test_server();
+    let mut adopted = nrepl_adopt_session(conn_id, wire_id.clone()).expect("adopt-session failed");
+    assert_eq!(
+        adopted.wire_session_id().expect("Failed to read wire id"),
+        wire_id,
+        "adopted handle should target the listed session"
+    );
+    let req = adopted
+        .eval_with_timeout("adopted-probe", 60_000, None, None, None)
+        .expect("Failed to submit probe eval");
+    let result = poll_for_result(conn_id, req, 5000)
+        .expect("Error waiting for probe result")
+        .expect("Timeout waiting for probe result");
+    let (value, _, _, _) = result_fields(&result);
+    assert_eq!(
+        value,
+        Some("7".to_string()),
+        "adopted session should see state defined before adoption"
+    );
+
+    nrepl_close(conn_id).expect("Failed to close connection");
+    nrepl_close(other_conn).expect("Failed to close other connection");
+}
+
+#[test]
+#[ignore = "requires a running nREPL server"]
+fn test_ffi_adopt_session_refuses_stale_id() {
+    let conn_id = connect_test_server();
+
+    // A session that existed once but has since been closed.
+    let session = nrepl_clone_session(conn_id).expect("Failed to clone session");
+    let stale = session
+        .wire_session_id()
+        .expect("Failed to read session wire id");
+    nrepl_close_session_by_wire_id(conn_id, &stale).expect("close-session-by-id failed");
+
+    let result = nrepl_adopt_session(conn_id, stale.clone());
+    assert!(
+        result.is_err(),
+        "adopting closed session {stale} should fail, got a handle"
+    );
+    let result = nrepl_adopt_session(conn_id, "no-such-session".to_string());
+    assert!(
+        result.is_err(),
+        "adopting a made-up session id should fail, got a handle"
     );
 
     nrepl_close(conn_id).expect("Failed to close connection");
@@ -734,7 +872,7 @@ fn poll_for_completions(
     session: &steel_nrepl::connection::NReplSession,
     request_id: usize,
     timeout_ms: u64,
-) -> Result<Option<String>, String> {
+) -> Result<Option<Value>, String> {
     let start = std::time::Instant::now();
     let timeout = Duration::from_millis(timeout_ms);
 
@@ -749,6 +887,15 @@ fn poll_for_completions(
     Ok(None)
 }
 
+/// Whether a completions list offers `candidate`.
+fn offers(completions: &Value, candidate: &str) -> bool {
+    completions.as_list().is_some_and(|items| {
+        items
+            .iter()
+            .any(|c| c.get("candidate").and_then(Value::as_str) == Some(candidate))
+    })
+}
+
 #[test]
 #[ignore = "requires a running nREPL server"]
 fn test_ffi_submit_completions_and_poll() {
@@ -765,12 +912,12 @@ fn test_ffi_submit_completions_and_poll() {
         .expect("Timeout waiting for completions result");
 
     assert!(
-        result.starts_with("(list"),
-        "Completions should be a list, got: {result}"
+        result.as_list().is_some(),
+        "Completions should be a list, got: {result:?}"
     );
     assert!(
-        result.contains("'#:candidate \"map\""),
-        "Completions for \"map\" should include map itself, got: {result}"
+        offers(&result, "map"),
+        "Completions for \"map\" should include map itself, got: {result:?}"
     );
 
     nrepl_close(conn_id).expect("Failed to close connection");
@@ -802,8 +949,8 @@ fn test_ffi_submit_completions_supersede() {
         .expect("Error while polling for second completions")
         .expect("Timeout waiting for second completions result");
     assert!(
-        result.contains("'#:candidate \"map\""),
-        "Second request should return candidates, got: {result}"
+        offers(&result, "map"),
+        "Second request should return candidates, got: {result:?}"
     );
 
     nrepl_close(conn_id).expect("Failed to close connection");
@@ -834,13 +981,95 @@ fn test_ffi_submit_lookup_and_poll() {
     }
     let result = result.expect("Timeout waiting for lookup result");
 
-    assert!(
-        result.starts_with("(hash"),
-        "Lookup should be a hash, got: {result}"
+    assert_eq!(
+        result.get("name").and_then(Value::as_str),
+        Some("map"),
+        "Lookup for map should include its name, got: {result:?}"
     );
+
+    nrepl_close(conn_id).expect("Failed to close connection");
+}
+
+/// Submit an eval of each of `codes`, then one more that is waited for with
+/// `try-get-result`, so all have finished (evals run one at a time) and the
+/// last is claimed. Returns the values `drain-results` then lists for
+/// `conn_id`, in its order.
+fn drain_after_evals(
+    conn_id: usize,
+    session: &mut steel_nrepl::connection::NReplSession,
+    codes: impl IntoIterator<Item = String>,
+) -> Vec<String> {
+    for code in codes {
+        session
+            .eval_with_timeout(&code, 60_000, None, None, None)
+            .expect("Failed to submit eval");
+    }
+    let last = session
+        .eval_with_timeout(":last", 60_000, None, None, None)
+        .expect("Failed to submit last eval");
+    poll_for_result(conn_id, last, 60_000)
+        .expect("Error waiting for last result")
+        .expect("Timeout waiting for last result");
+
+    let drained = nrepl_drain_results();
+    drained
+        .as_list()
+        .unwrap_or_default()
+        .iter()
+        .filter(|entry| entry.get("conn-id").and_then(Value::as_int) == i64::try_from(conn_id).ok())
+        .map(|entry| {
+            entry
+                .get("result")
+                .and_then(|result| result.get("value"))
+                .and_then(Value::as_str)
+                .unwrap_or_else(|| panic!("drained entry has no value: {entry:?}"))
+                .to_string()
+        })
+        .collect()
+}
+
+#[test]
+#[ignore = "requires a running nREPL server"]
+fn test_ffi_drain_results_in_order_and_bounded() {
+    let conn_id = connect_test_server();
+    let mut session = nrepl_clone_session(conn_id).expect("Failed to clone session");
+
+    // Oldest first, and the claimed last eval is left out.
+    let values = drain_after_evals(conn_id, &mut session, (1..=3).map(|i| i.to_string()));
+    assert_eq!(values, ["1", "2", "3"], "Results should drain oldest first");
     assert!(
-        result.contains("'#:name \"map\""),
-        "Lookup for map should include its name, got: {result}"
+        drain_after_evals(conn_id, &mut session, []).is_empty(),
+        "A drained result should not be listed again"
+    );
+
+    // Past 1000 finished requests the oldest are forgotten. Of 1006 results
+    // the newest 1000 are kept, the last of them claimed by its poll.
+    let values = drain_after_evals(conn_id, &mut session, (0..1005).map(|i| i.to_string()));
+    let expected: Vec<String> = (6..1005).map(|i| i.to_string()).collect();
+    assert_eq!(
+        values, expected,
+        "The newest 1000 results should drain, oldest first"
+    );
+
+    nrepl_close(conn_id).expect("Failed to close connection");
+}
+
+#[test]
+#[ignore = "requires a running nREPL server"]
+fn test_ffi_describe_reports_flavor() {
+    let conn_id = connect_test_server();
+
+    let described = nrepl_describe(conn_id, false).expect("describe failed");
+    let flavor = described
+        .get("flavor")
+        .and_then(Value::as_str)
+        .unwrap_or_else(|| panic!("describe should report a flavor, got: {described:?}"));
+    // The describe reply also updates what the connection knows, so the two
+    // agree once it is back.
+    assert_eq!(
+        flavor,
+        nrepl_server_flavor(conn_id).expect("server-flavor failed"),
+        "describe's flavor should match server-flavor"
     );
 
     nrepl_close(conn_id).expect("Failed to close connection");
@@ -28,12 +28,21 @@
 
 use std::sync::Mutex;
 use steel_nrepl::connection::{nrepl_clone_session, nrepl_close, nrepl_connect, nrepl_stats};
+use steel_nrepl::value::Value;
 
 /// Global mutex to serialize tests that check registry stats
 /// This ensures only one test accesses registry stats at a time,
 /// preventing flakiness from concurrent connection creation/closure
 static REGISTRY_STATS_LOCK: Mutex<()> = Mutex::new(());
 
+/// A count from the stats hash
+fn count(stats: &Value, key: &str) -> i64 {
+    stats
+        .get(key)
+        .and_then(Value::as_int)
+        .unwrap_or_else(|| panic!("Stats should contain {key} as a number: {stats:?}"))
+}
+
 /// Helper to connect to test server and return connection ID
 fn connect_test_server() -> usize {
     nrepl_connect("localhost:7888".to_string()).expect("Failed to connect to test server")
@@ -69,49 +78,11 @@ fn test_ffi_registry_stats_accuracy() {
     // Get stats after creating connections and sessions
     let stats = nrepl_stats();
 
-    // Parse the stats S-expression
-    // Expected format: (hash 'total-connections N 'total-sessions M 'max-connections 100
-    //                        'next-conn-id X 'connections (list ...))
-    assert!(
-        stats.starts_with("(hash "),
-        "Stats should be a hash S-expression"
-    );
-
-    // Extract total-connections
-    let total_connections_str = stats
-        .split("'total-connections ")
-        .nth(1)
-        .expect("Stats should contain 'total-connections")
-        .split_whitespace()
-        .next()
-        .expect("Should have value after 'total-connections");
-    let total_connections: usize = total_connections_str
-        .parse()
-        .expect("total-connections should be a number");
-
-    // Extract total-sessions
-    let total_sessions_str = stats
-        .split("'total-sessions ")
-        .nth(1)
-        .expect("Stats should contain 'total-sessions")
-        .split_whitespace()
-        .next()
-        .expect("Should have value after 'total-sessions");
-    let total_sessions: usize = total_sessions_str
-        .parse()
-        .expect("total-sessions should be a number");
-
-    // Extract max-connections
-    let max_connections_str = stats
-        .split("'max-connections ")
-        .nth(1)
-        .expect("Stats should contain 'max-connections")
-        .split_whitespace()
-        .next()
-        .expect("Should have value after 'max-connections");
-    let max_connections: usize = max_connections_str
-        .parse()
-        .expect("max-connections should be a number");
+    // Expected shape: (hash "total-connections" N "total-sessions" M "max-connections" 100
+    //                       "next-conn-id" X "connections" (list ...))
+    let total_connections = count(&stats, "total-connections");
+    let total_sessions = count(&stats, "total-sessions");
+    let max_connections = count(&stats, "max-connections");
 
     // Verify counts
     // Note: We can't assert exact numbers because other tests might be running concurrently
@@ -122,19 +93,19 @@ fn test_ffi_registry_stats_accuracy() {
 
     assert!(
         total_connections >= 3,
-        "Should have at least 3 connections, got {total_connections}. Initial stats: {initial_stats}"
+        "Should have at least 3 connections, got {total_connections}. Initial stats: {initial_stats:?}"
     );
 
     assert!(
         total_sessions >= 6,
-        "Should have at least 6 sessions (2+3+1), got {total_sessions}. Stats: {stats}"
+        "Should have at least 6 sessions (2+3+1), got {total_sessions}. Stats: {stats:?}"
     );
 
     assert_eq!(max_connections, 100, "Max connections should be 100");
 
     // Verify connection details list exists
     assert!(
-        stats.contains("'connections (list"),
+        stats.get("connections").and_then(Value::as_list).is_some(),
         "Stats should contain connections list"
     );
 
@@ -146,17 +117,7 @@ fn test_ffi_registry_stats_accuracy() {
     // Get stats after cleanup
     let final_stats = nrepl_stats();
 
-    // Parse final stats
-    let final_total_connections_str = final_stats
-        .split("'total-connections ")
-        .nth(1)
-        .expect("Final stats should contain 'total-connections")
-        .split_whitespace()
-        .next()
-        .expect("Should have value after 'total-connections");
-    let final_total_connections: usize = final_total_connections_str
-        .parse()
-        .expect("total-connections should be a number");
+    let final_total_connections = count(&final_stats, "total-connections");
 
     // After closing our 3 connections, count should decrease by at least 3
     // (Could decrease by more if other tests closed connections concurrently)
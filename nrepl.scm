@@ -18,6 +18,8 @@
 ;;;   :nrepl-set-orientation [vsplit|hsplit] - Set/view buffer split orientation (default: vsplit)
 ;;;   :nrepl-stats                           - Display connection/session statistics
 ;;;   :nrepl-describe                        - Display server capabilities (ops/versions)
+;;;   :nrepl-resync                          - Flush and resynchronize a stuck connection
+;;;   :nrepl-debug-events                    - Log recent connection events for bug reports
 ;;;   :nrepl-sessions                        - Pick a server session to attach to (Ctrl-k kills)
 ;;;   :nrepl-eval-prompt                     - Prompt for code and evaluate
 ;;;   :nrepl-eval-selection                  - Evaluate current selection (primary)
@@ -42,9 +44,6 @@
 ;; Load language-agnostic core client
 (require "cogs/nrepl/core.scm")
 
-;; FFI result parsing (data-walked, never eval'd)
-(require (only-in "cogs/nrepl/string-utils.scm" parse-ffi-sexp))
-
 ;; Load adapter interface for accessors
 (require "cogs/nrepl/adapter-interface.scm")
 
@@ -114,6 +113,8 @@
   nrepl-stdin
   nrepl-lookup
   nrepl-describe
+  nrepl-resync
+  nrepl-debug-events
   nrepl-sessions
   nrepl-copy-jack-in-command
   nrepl-shadow-select
@@ -192,8 +193,8 @@
 ;; parsed describe hash (or #f when unknown).
 (define (capabilities-guile? capabilities)
   (and capabilities
-    (hash-contains? capabilities 'ops)
-    (let ([ops (hash-get capabilities 'ops)])
+    (hash-contains? capabilities "ops")
+    (let ([ops (hash-get capabilities "ops")])
       (and (list? ops)
         (not (null? (filter (lambda (op)
                              (and (string? op)
@@ -211,8 +212,8 @@
 ;; to a running server pick the right adapter from any buffer.
 (define (capabilities-has-version? capabilities impl)
   (and capabilities
-    (hash-contains? capabilities 'versions)
-    (let ([versions (hash-get capabilities 'versions)])
+    (hash-contains? capabilities "versions")
+    (let ([versions (hash-get capabilities "versions")])
       (and (hash? versions) (hash-contains? versions impl)))))
 
 (define (capabilities-steel? capabilities)
@@ -523,15 +524,14 @@
 ;;@doc
 ;; Display registry statistics for debugging
 (define (nrepl-stats)
-  (let* ([stats-str (nrepl:stats)]
-         [stats (parse-ffi-sexp stats-str)])
+  (let ([stats (nrepl:stats)])
     (helix.echo (string-append "nREPL Stats - "
                  "Total Connections: "
-                 (number->string (hash-get stats 'total-connections))
+                 (number->string (hash-get stats "total-connections"))
                  ", Total Sessions: "
-                 (number->string (hash-get stats 'total-sessions))
+                 (number->string (hash-get stats "total-sessions"))
                  ", Max Connections: "
-                 (number->string (hash-get stats 'max-connections))))))
+                 (number->string (hash-get stats "max-connections"))))))
 
 ;;@doc
 ;; Return a human version string for `impl` from a describe `versions` hash,
@@ -584,9 +584,9 @@
            [caps (with-handler (lambda (err) #f) (nrepl:describe conn-id #f))])
       (if (not caps)
         (helix.echo "nREPL: Server did not respond to describe")
-        (let* ([ops (hash-get caps 'ops)]
-               [versions (hash-get caps 'versions)]
-               [aux (hash-get caps 'aux)]
+        (let* ([ops (hash-get caps "ops")]
+               [versions (hash-get caps "versions")]
+               [aux (hash-get caps "aux")]
                [block (describe-format-block comment-prefix ops versions aux)]
                [new-state (nrepl:append-to-buffer state block ctx)]
                [nrepl-ver (describe-impl-version versions "nrepl")])
@@ -597,6 +597,55 @@
                        (number->string (length ops))
                        " ops (see *nrepl* buffer)")))))))
 
+;;@doc
+;; Unstick the REPL: fail whatever is in flight, flush the connection and
+;; check the server still answers. Echoes what was done.
+(define (nrepl-resync)
+  (if (not (connected?))
+    (helix.echo "nREPL: Not connected. Use :nrepl-connect first")
+    (let* ([state (get-state)]
+           [report (with-handler
+                    (lambda (err) #f)
+                    (nrepl:resync (nrepl-state-conn-id state)))])
+      (if (not report)
+        (helix.echo "nREPL: Resync failed - try :nrepl-disconnect and reconnect")
+        (begin
+          (set-state! (nrepl-state-with state 'current-eval-request-id #f))
+          (helix.echo
+            (string-append "nREPL: Resynced - "
+              (number->string (hash-get report "requests-failed"))
+              " request(s) failed, "
+              (number->string (hash-get report "responses-dropped"))
+              " result(s) dropped, "
+              (number->string (hash-get report "bytes-discarded"))
+              " byte(s) discarded; server "
+              (if (hash-get report "server-alive") "responding" "NOT responding"))))))))
+
+;;@doc
+;; Log the connection's recent events (connects, timeouts, limit hits) to the
+;; *nrepl* buffer as comments, ready to copy into a bug report
+(define (nrepl-debug-events)
+  (if (not (connected?))
+    (helix.echo "nREPL: Not connected. Use :nrepl-connect first")
+    (let* ([state (get-state)]
+           [ctx (make-helix-context)]
+           [prefix (adapter-comment-prefix (nrepl-state-adapter state))]
+           [events (nrepl:debug-events (nrepl-state-conn-id state))]
+           [lines (map (lambda (e)
+                         (string-append prefix " "
+                           (number->string (hash-get e "at")) " "
+                           (hash-get e "kind") ": "
+                           (hash-get e "message") "\n"))
+                       events)])
+      (set-state!
+        (nrepl:append-to-buffer
+          state
+          (string-append prefix " nREPL: " (number->string (length events))
+            " recent event(s)\n"
+            (apply string-append lines)
+            "\n")
+          ctx)))))
+
 ;; Log a session change to the *nrepl* buffer, e.g.
 ;;   ;; nREPL: session 8f2c... (new, was 1a2b...)
 (define (log-session-banner new-id prev-id new?)
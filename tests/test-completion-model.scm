@@ -11,29 +11,27 @@
 
 (require "steel-test/test.scm")
 (require "../cogs/nrepl/completion-model.scm")
-(require (only-in "../cogs/nrepl/string-utils.scm" parse-ffi-sexp))
 
 ;; cider-shaped candidates: all fields present.
 (deftest cider-shape
-  (let* ([parsed (parse-ffi-sexp
-                  "(list (hash '#:candidate \"map\" '#:ns \"clojure.core\" '#:type \"function\") (hash '#:candidate \"mapv\" '#:ns \"clojure.core\" '#:type \"function\"))")]
-         [result (candidates->symbols+metadata parsed)])
+  (let ([result (candidates->symbols+metadata
+                 (list (hash "candidate" "map" "ns" "clojure.core" "type" "function")
+                   (hash "candidate" "mapv" "ns" "clojure.core" "type" "function")))])
     (is (= '("map" "mapv") (car result)))
-    (is (= "clojure.core" (hash-ref (hash-ref (cdr result) "map") '#:ns)))
-    (is (= "function" (hash-ref (hash-ref (cdr result) "map") '#:type)))))
+    (is (= "clojure.core" (hash-ref (hash-ref (cdr result) "map") "ns")))
+    (is (= "function" (hash-ref (hash-ref (cdr result) "map") "type")))))
 
 ;; babashka-shaped candidates: ns present, type #f.
 (deftest babashka-shape
-  (let* ([parsed (parse-ffi-sexp
-                  "(list (hash '#:candidate \"map\" '#:ns \"clojure.core\" '#:type #f))")]
-         [result (candidates->symbols+metadata parsed)])
+  (let ([result (candidates->symbols+metadata
+                 (list (hash "candidate" "map" "ns" "clojure.core" "type" #f)))])
     (is (= '("map") (car result)))
-    (is (= "clojure.core" (hash-ref (hash-ref (cdr result) "map") '#:ns)))
-    (is (not (hash-ref (hash-ref (cdr result) "map") '#:type)))))
+    (is (= "clojure.core" (hash-ref (hash-ref (cdr result) "map") "ns")))
+    (is (not (hash-ref (hash-ref (cdr result) "map") "type")))))
 
 ;; Empty result (babashka on an empty prefix).
 (deftest empty-result
-  (let ([result (candidates->symbols+metadata (parse-ffi-sexp "(list )"))])
+  (let ([result (candidates->symbols+metadata (list))])
     (is (= '() (car result)))
     (is (= 0 (hash-length (cdr result))))))
 
@@ -45,13 +43,12 @@
 
 ;; Mixed shapes: hashes and strings interleaved.
 (deftest mixed-shapes
-  (let* ([parsed (parse-ffi-sexp
-                  "(list \"plain\" (hash '#:candidate \"rich\" '#:ns \"user\" '#:type \"var\"))")]
-         [result (candidates->symbols+metadata parsed)])
+  (let ([result (candidates->symbols+metadata
+                 (list "plain" (hash "candidate" "rich" "ns" "user" "type" "var")))])
     (is (= '("plain" "rich") (car result)))
     (is (= 1 (hash-length (cdr result))))))
 
-;; Non-list input (parse failure upstream returns #f).
+;; Non-list input (no result).
 (deftest non-list-input
   (let ([result (candidates->symbols+metadata #f)])
     (is (= '() (car result)))